@@ -18,25 +18,32 @@ use crate::dns_cf::CfDnsTask;
 use crate::dns_huawei::HuaweiDnsTask;
 use crate::dns_manual::ManualDnsTask;
 use crate::dns_tencent::TencentDnsTask;
+use crate::renewal_queue::{
+    RenewalEntry, enqueue_renewal, get_retry_backoff, load_renewal_queue,
+    update_renewal_queue,
+};
+use crate::storage::AcmeStorage;
 use async_trait::async_trait;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
 use hickory_resolver::Resolver;
 use hickory_resolver::config::ResolverConfig;
 use hickory_resolver::name_server::TokioConnectionProvider;
 use hickory_resolver::proto::rr::RecordType;
 use instant_acme::{
-    Account, ChallengeType, Identifier, LetsEncrypt, NewAccount, NewOrder,
-    OrderStatus, RetryPolicy,
+    Account, ChallengeType, ExternalAccountKey, Identifier, LetsEncrypt,
+    NewAccount, NewOrder, OrderStatus, RetryPolicy,
 };
 use pingap_certificate::CertificateProvider;
+use pingap_certificate::rcgen;
 use pingap_certificate::{
     Certificate, parse_certificates, parse_leaf_chain_certificates,
+    select_preferred_chain, sort_certificate_chain_pem,
 };
-use pingap_config::{
-    Category, CertificateConf, ConfigManager, PingapConfig, StorageConf,
-};
+use pingap_config::{CertificateConf, ConfigManager, PingapConfig};
 use pingap_core::BackgroundTask;
 use pingap_core::Error as ServiceError;
 use pingap_core::HttpResponse;
+use pingap_core::now_sec;
 use pingap_core::{
     Ctx, NotificationData, NotificationLevel, NotificationSender,
 };
@@ -44,6 +51,7 @@ use pingora::http::StatusCode;
 use pingora::proxy::Session;
 use scopeguard::defer;
 use std::collections::HashMap;
+use std::future::Future;
 use std::sync::Arc;
 use std::sync::Once;
 use std::sync::atomic::{AtomicBool, Ordering};
@@ -51,7 +59,23 @@ use std::time::Duration;
 use substring::Substring;
 use tracing::{error, info};
 
-static WELL_KNOWN_PATH_PREFIX: &str = "/.well-known/acme-challenge/";
+/// Standard path prefix of the http-01 challenge
+pub static WELL_KNOWN_PATH_PREFIX: &str = "/.well-known/acme-challenge/";
+
+/// Normalizes the path prefix of the http-01 challenge, the standard
+/// prefix is used if it's empty, e.g. `/acme` -> `/acme/`.
+pub fn normalize_challenge_prefix(prefix: &str) -> String {
+    let prefix = prefix.trim().trim_matches('/');
+    if prefix.is_empty() {
+        return WELL_KNOWN_PATH_PREFIX.to_string();
+    }
+    format!("/{prefix}/")
+}
+
+/// Returns the challenge token of the request path if it's under the prefix.
+fn get_challenge_token<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    path.strip_prefix(prefix).filter(|token| !token.is_empty())
+}
 
 // Initialize crypto provider once
 static INIT: Once = Once::new();
@@ -62,42 +86,107 @@ fn ensure_crypto_provider() {
     });
 }
 
+/// ACME provider(certificate authority) with optional external account binding
+#[derive(Debug, Clone, PartialEq)]
+struct AcmeProvider {
+    directory_url: String,
+    // (key id, hmac key) of the external account binding
+    eab: Option<(String, String)>,
+}
+
+/// Parses the ACME providers in `directory_url` or
+/// `directory_url eab_kid eab_hmac_key` format,
+/// Let's Encrypt is used if there is no valid provider.
+fn parse_acme_providers(values: &[String]) -> Vec<AcmeProvider> {
+    let mut providers = vec![];
+    for value in values.iter() {
+        let arr: Vec<&str> = value.split_whitespace().collect();
+        match arr.as_slice() {
+            [directory_url] => providers.push(AcmeProvider {
+                directory_url: directory_url.to_string(),
+                eab: None,
+            }),
+            [directory_url, kid, hmac_key] => providers.push(AcmeProvider {
+                directory_url: directory_url.to_string(),
+                eab: Some((kid.to_string(), get_value_from_env(hmac_key))),
+            }),
+            _ => {
+                error!(
+                    target: LOG_TARGET,
+                    provider = value,
+                    "acme provider is invalid"
+                );
+            },
+        }
+    }
+    if providers.is_empty() {
+        providers.push(AcmeProvider {
+            directory_url: LetsEncrypt::Production.url().to_string(),
+            eab: None,
+        });
+    }
+    providers
+}
+
+/// Issues the certificate from the providers in order, the next provider
+/// is tried only if the previous one fails with a retryable error.
+/// Returns the result and the directory url of the provider which issued it.
+async fn issue_with_fallback<T, F, Fut>(
+    providers: &[AcmeProvider],
+    issue: F,
+) -> Result<(T, String)>
+where
+    F: Fn(AcmeProvider) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let mut last_error = None;
+    for provider in providers.iter() {
+        match issue(provider.clone()).await {
+            Ok(value) => return Ok((value, provider.directory_url.clone())),
+            Err(e) => {
+                let retryable = e.is_retryable();
+                error!(
+                    target: LOG_TARGET,
+                    error = %e,
+                    kind = ?e.kind(),
+                    directory_url = provider.directory_url,
+                    "issue certificate fail"
+                );
+                if !retryable {
+                    return Err(e);
+                }
+                last_error = Some(e);
+            },
+        }
+    }
+    Err(last_error.unwrap_or_else(|| Error::Fail {
+        category: "acme_provider".to_string(),
+        message: "acme provider is empty".to_string(),
+    }))
+}
+
 /// Updates the certificate for the given name and domains using Let's Encrypt.
 /// This function will:
-/// 1. Generate a new certificate from Let's Encrypt
-/// 2. Update the configuration with the new certificate
-/// 3. Save the updated configuration
+/// 1. Generate a new certificate from the ACME providers in order
+/// 2. Save the new certificate and its issuer to the acme storage
 async fn update_certificate_lets_encrypt(
-    config_manager: Arc<ConfigManager>,
+    storage: AcmeStorage,
     params: UpdateCertificateParams,
 ) -> Result<()> {
-    // get new certificate from lets encrypt
-    let (pem, key) =
-        new_lets_encrypt(config_manager.clone(), true, params.clone()).await?;
+    // get new certificate from the acme providers
+    let ((pem, key), issuer) =
+        issue_with_fallback(&params.providers, |provider| {
+            new_lets_encrypt(storage.clone(), provider, params.clone())
+        })
+        .await?;
 
-    let cert: Option<CertificateConf> = config_manager
-        .get(Category::Certificate, &params.name)
+    storage
+        .save_certificate(&params.name, pem, key, issuer)
         .await
-        .map_err(|e| Error::Fail {
-            category: "load_config".to_string(),
-            message: e.to_string(),
-        })?;
-    if let Some(mut cert) = cert {
-        cert.tls_cert = Some(pem);
-        cert.tls_key = Some(key);
-        config_manager
-            .update(Category::Certificate, &params.name, &cert)
-            .await
-            .map_err(|e| Error::Fail {
-                category: "save_config".to_string(),
-                message: e.to_string(),
-            })?;
-    }
-    Ok(())
 }
 
 /// File cache parameters
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 struct UpdateCertificateParams {
     name: String,
     domains: Vec<String>,
@@ -105,9 +194,14 @@ struct UpdateCertificateParams {
     dns_challenge: bool,
     dns_provider: String,
     dns_service_url: String,
+    preferred_chain: String,
+    providers: Vec<AcmeProvider>,
+    common_name: bool,
+    keep_domain_order: bool,
 }
 
-/// Periodically checks and updates certificates that need renewal.
+/// Periodically checks the certificates and adds the ones that need
+/// renewal to the renewal queue, which is drained by the renewal worker.
 /// A certificate needs renewal if:
 /// - It is invalid or expired
 /// - The configured domains have changed
@@ -116,10 +210,8 @@ struct UpdateCertificateParams {
 /// The check runs every UPDATE_INTERVAL iterations to avoid excessive checks.
 async fn do_update_certificates(
     count: u32,
-    config_manager: Arc<ConfigManager>,
+    storage: &AcmeStorage,
     params: &[UpdateCertificateParams],
-    provider: Arc<dyn CertificateProvider>,
-    sender: Option<Arc<NotificationSender>>,
 ) -> Result<bool, ServiceError> {
     if params.is_empty() {
         return Ok(false);
@@ -128,7 +220,7 @@ async fn do_update_certificates(
     if count % UPDATE_INTERVAL != 0 {
         return Ok(false);
     }
-    let config = config_manager.get_current_config();
+    let config = storage.config_manager().get_current_config();
     for item in params.iter() {
         let name = &item.name;
         let domains = &item.domains;
@@ -152,7 +244,14 @@ async fn do_update_certificates(
                 };
                 needs_renewal || domains_changed
             },
-            Ok(None) => true,
+            Ok(None) => {
+                info!(
+                    target: LOG_TARGET,
+                    name,
+                    "certificate is not yet issued"
+                );
+                true
+            },
             Err(e) => {
                 error!(
                     target: LOG_TARGET,
@@ -174,41 +273,103 @@ async fn do_update_certificates(
             continue;
         }
 
-        if let Err(e) = renew_certificate(
-            config_manager.clone(),
-            item.clone(),
-            provider.clone(),
-            sender.clone(),
-        )
-        .await
-        {
-            error!(
+        match enqueue_renewal(storage.state_manager(), name, now_sec()).await {
+            Ok(true) => info!(
                 target: LOG_TARGET,
-                error = %e,
                 domains = domains.join(","),
                 name,
-                "certificate renewal failed, will retry later"
-            );
+                "certificate is queued for renewal"
+            ),
+            Ok(false) => {},
+            Err(e) => error!(
+                target: LOG_TARGET,
+                error = %e,
+                kind = ?e.kind(),
+                name,
+                "queue certificate renewal failed"
+            ),
         }
     }
     Ok(true)
 }
 
-async fn renew_certificate(
+/// Drains the due entries of the renewal queue. The entry is removed only
+/// after the renewal succeeds, so the in-progress renewal is resumed after
+/// restart, and the failed one is retried with backoff.
+/// The entry of removed certificate is dropped.
+async fn do_renewal_queue<F, Fut>(
     config_manager: Arc<ConfigManager>,
+    params: &[UpdateCertificateParams],
+    now: u64,
+    renew: F,
+) -> Result<bool>
+where
+    F: Fn(UpdateCertificateParams) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let queue = load_renewal_queue(&config_manager).await?;
+    let due: Vec<RenewalEntry> = queue
+        .into_iter()
+        .filter(|entry| entry.next_retry_at <= now)
+        .collect();
+    if due.is_empty() {
+        return Ok(false);
+    }
+    for entry in due.iter() {
+        let name = entry.name.as_str();
+        let Some(item) = params.iter().find(|item| item.name == name) else {
+            info!(
+                target: LOG_TARGET,
+                name,
+                "certificate is removed, drop it from renewal queue"
+            );
+            update_renewal_queue(&config_manager, |queue| {
+                queue.retain(|item| item.name != name);
+                true
+            })
+            .await?;
+            continue;
+        };
+
+        let result = renew(item.clone()).await;
+        update_renewal_queue(&config_manager, |queue| {
+            if let Err(e) = &result {
+                let Some(entry) =
+                    queue.iter_mut().find(|item| item.name == name)
+                else {
+                    return false;
+                };
+                entry.attempts += 1;
+                let backoff = get_retry_backoff(entry.attempts);
+                entry.next_retry_at = now + backoff.as_secs();
+                error!(
+                    target: LOG_TARGET,
+                    error = %e,
+                    kind = ?e.kind(),
+                    domains = item.domains.join(","),
+                    name,
+                    attempts = entry.attempts,
+                    "certificate renewal failed, will retry after {backoff:?}"
+                );
+            } else {
+                queue.retain(|item| item.name != name);
+            }
+            true
+        })
+        .await?;
+    }
+    Ok(true)
+}
+
+async fn renew_certificate(
+    storage: AcmeStorage,
     params: UpdateCertificateParams,
     provider: Arc<dyn CertificateProvider>,
     sender: Option<Arc<NotificationSender>>,
 ) -> Result<()> {
-    update_certificate_lets_encrypt(config_manager.clone(), params.clone())
+    update_certificate_lets_encrypt(storage.clone(), params.clone()).await?;
+    handle_successful_renewal(&params.domains, storage, provider, sender)
         .await?;
-    handle_successful_renewal(
-        &params.domains,
-        config_manager,
-        provider,
-        sender,
-    )
-    .await?;
     Ok(())
 }
 
@@ -239,7 +400,7 @@ fn try_update_certificates(
 
 async fn handle_successful_renewal(
     domains: &[String],
-    config_manager: Arc<ConfigManager>,
+    storage: AcmeStorage,
     provider: Arc<dyn CertificateProvider>,
     sender: Option<Arc<NotificationSender>>,
 ) -> Result<()> {
@@ -248,18 +409,20 @@ async fn handle_successful_renewal(
         domains = domains.join(","),
         "renew certificate success"
     );
+    let config_manager = storage.config_manager();
     let toml_config =
         config_manager.load_all().await.map_err(|e| Error::Fail {
             category: "load_config".to_string(),
             message: e.to_string(),
         })?;
-    let config =
+    let mut config =
         toml_config
             .to_pingap_config(true)
             .map_err(|e| Error::Fail {
                 category: "convert_config".to_string(),
                 message: e.to_string(),
             })?;
+    storage.merge_certificates(&mut config.certificates).await?;
     if let Some(sender) = &sender {
         sender
             .notify(NotificationData {
@@ -292,10 +455,50 @@ async fn handle_successful_renewal(
     Ok(())
 }
 
+/// Gets the renewal params of the acme certificates.
+fn get_update_certificate_params(
+    config: &PingapConfig,
+) -> Vec<UpdateCertificateParams> {
+    let mut params = vec![];
+    for (name, certificate) in config.certificates.iter() {
+        let acme = certificate.acme.clone().unwrap_or_default();
+        let domains = certificate.domains.clone().unwrap_or_default();
+        if acme.is_empty() || domains.is_empty() {
+            continue;
+        }
+        let dns_service_url = get_value_from_env(
+            &certificate.dns_service_url.clone().unwrap_or_default(),
+        );
+
+        params.push(UpdateCertificateParams {
+            name: name.to_string(),
+            buffer_days: certificate.buffer_days.unwrap_or_default(),
+            domains: domains
+                .split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect(),
+            dns_challenge: certificate.dns_challenge.unwrap_or_default(),
+            dns_provider: certificate.dns_provider.clone().unwrap_or_default(),
+            dns_service_url,
+            preferred_chain: certificate
+                .acme_preferred_chain
+                .clone()
+                .unwrap_or_default(),
+            providers: parse_acme_providers(
+                &certificate.acme_providers.clone().unwrap_or_default(),
+            ),
+            common_name: certificate.acme_common_name.unwrap_or_default(),
+            keep_domain_order: certificate
+                .acme_keep_domain_order
+                .unwrap_or_default(),
+        });
+    }
+    params
+}
+
 struct LetsEncryptTask {
-    config_manager: Arc<ConfigManager>,
-    certificate_provider: Arc<dyn CertificateProvider>,
-    sender: Option<Arc<NotificationSender>>,
+    storage: AcmeStorage,
     running: AtomicBool,
 }
 
@@ -306,56 +509,70 @@ impl BackgroundTask for LetsEncryptTask {
             return Ok(true);
         }
         defer!(self.running.store(false, Ordering::Relaxed););
-        let mut params = vec![];
-        let config = self.config_manager.get_current_config();
-
-        for (name, certificate) in config.certificates.iter() {
-            let acme = certificate.acme.clone().unwrap_or_default();
-            let domains = certificate.domains.clone().unwrap_or_default();
-            if acme.is_empty() || domains.is_empty() {
-                continue;
-            }
-            let dns_service_url = get_value_from_env(
-                &certificate.dns_service_url.clone().unwrap_or_default(),
-            );
+        let config = self.storage.config_manager().get_current_config();
+        let params = get_update_certificate_params(&config);
+        do_update_certificates(count, &self.storage, &params).await?;
+        Ok(true)
+    }
+}
 
-            params.push(UpdateCertificateParams {
-                name: name.to_string(),
-                buffer_days: certificate.buffer_days.unwrap_or_default(),
-                domains: domains
-                    .split(',')
-                    .map(|item| item.trim().to_string())
-                    .filter(|item| !item.is_empty())
-                    .collect(),
-                dns_challenge: certificate.dns_challenge.unwrap_or_default(),
-                dns_provider: certificate
-                    .dns_provider
-                    .clone()
-                    .unwrap_or_default(),
-                dns_service_url,
-            });
+/// Create a Let's Encrypt service to check the certificates,
+/// the invalid or will be expired certificate is queued for renewal.
+pub fn new_lets_encrypt_service(
+    config_manager: Arc<ConfigManager>,
+) -> Box<dyn BackgroundTask> {
+    Box::new(LetsEncryptTask {
+        storage: AcmeStorage::resolve(config_manager),
+        running: AtomicBool::new(false),
+    })
+}
+
+struct LetsEncryptRenewalTask {
+    storage: AcmeStorage,
+    certificate_provider: Arc<dyn CertificateProvider>,
+    sender: Option<Arc<NotificationSender>>,
+    running: AtomicBool,
+}
+
+#[async_trait]
+impl BackgroundTask for LetsEncryptRenewalTask {
+    async fn execute(&self, _count: u32) -> Result<bool, ServiceError> {
+        if self.running.swap(true, Ordering::Relaxed) {
+            return Ok(false);
         }
-        do_update_certificates(
-            count,
-            self.config_manager.clone(),
+        defer!(self.running.store(false, Ordering::Relaxed););
+        let config = self.storage.config_manager().get_current_config();
+        let params = get_update_certificate_params(&config);
+        let done = do_renewal_queue(
+            self.storage.state_manager().clone(),
             &params,
-            self.certificate_provider.clone(),
-            self.sender.clone(),
+            now_sec(),
+            |item| {
+                renew_certificate(
+                    self.storage.clone(),
+                    item,
+                    self.certificate_provider.clone(),
+                    self.sender.clone(),
+                )
+            },
         )
-        .await?;
-        Ok(true)
+        .await
+        .map_err(|e| ServiceError::Invalid {
+            message: e.to_string(),
+        })?;
+        Ok(done)
     }
 }
 
-/// Create a Let's Encrypt service to generate the certificate,
-/// and regenerate if the certificate is invalid or will be expired.
-pub fn new_lets_encrypt_service(
+/// Create the renewal worker of Let's Encrypt, it drains the persistent
+/// renewal queue and generates the certificates.
+pub fn new_lets_encrypt_renewal_service(
     config_manager: Arc<ConfigManager>,
     certificate_provider: Arc<dyn CertificateProvider>,
     sender: Option<Arc<NotificationSender>>,
 ) -> Box<dyn BackgroundTask> {
-    Box::new(LetsEncryptTask {
-        config_manager,
+    Box::new(LetsEncryptRenewalTask {
+        storage: AcmeStorage::resolve(config_manager),
         certificate_provider,
         sender,
         running: AtomicBool::new(false),
@@ -373,42 +590,55 @@ fn get_lets_encrypt_certificate(
         });
     };
 
-    let pem = cert.tls_cert.clone().unwrap_or_default();
-    let key = cert.tls_key.clone().unwrap_or_default();
-    if pem.is_empty() || key.is_empty() {
-        return Ok(None);
-    }
+    let pem = cert.tls_cert.as_deref().unwrap_or_default();
+    let key = cert.tls_key.as_deref().unwrap_or_default();
+    let (pem, key) = match (pem.is_empty(), key.is_empty()) {
+        // not yet issued
+        (true, true) => return Ok(None),
+        (false, true) => {
+            return Err(Error::Fail {
+                category: "partial_certificate".to_string(),
+                message: format!(
+                    "certificate({name}) has tls cert but tls key is missing, set the tls key or remove the tls cert to reissue"
+                ),
+            });
+        },
+        (true, false) => {
+            return Err(Error::Fail {
+                category: "partial_certificate".to_string(),
+                message: format!(
+                    "certificate({name}) has tls key but tls cert is missing, set the tls cert or remove the tls key to reissue"
+                ),
+            });
+        },
+        (false, false) => (pem, key),
+    };
 
-    let (cert, _) = parse_leaf_chain_certificates(
-        cert.tls_cert.clone().unwrap_or_default().as_str(),
-        cert.tls_key.clone().unwrap_or_default().as_str(),
-    )
-    .map_err(|e| Error::Fail {
-        category: "new_certificate".to_string(),
-        message: e.to_string(),
-    })?;
+    let (cert, _) =
+        parse_leaf_chain_certificates(pem, key).map_err(|e| Error::Fail {
+            category: "new_certificate".to_string(),
+            message: e.to_string(),
+        })?;
     Ok(Some(cert))
 }
 
 /// Handles the HTTP-01 challenge verification for Let's Encrypt.
 /// This function:
-/// 1. Intercepts requests to /.well-known/acme-challenge/
+/// 1. Intercepts requests to the challenge prefix(default: /.well-known/acme-challenge/)
 /// 2. Extracts the challenge token from the URL path
-/// 3. Loads the pre-stored token response from storage
+/// 3. Loads the pre-stored token response from acme storage
 /// 4. Returns the token response to validate domain ownership
 pub async fn handle_lets_encrypt(
     config_manager: Arc<ConfigManager>,
     session: &mut Session,
     _ctx: &mut Ctx,
+    challenge_prefix: &str,
 ) -> pingora::Result<bool> {
     let path = session.req_header().uri.path();
     // lets encrypt acme challenge path
-    if path.starts_with(WELL_KNOWN_PATH_PREFIX) {
-        // token auth
-        let token = path.substring(WELL_KNOWN_PATH_PREFIX.len(), path.len());
-
-        let value: Option<StorageConf> = config_manager
-            .get(Category::Storage, token)
+    if let Some(token) = get_challenge_token(path, challenge_prefix) {
+        let value = AcmeStorage::resolve(config_manager)
+            .get_challenge(token)
             .await
             .map_err(|e| {
                 error!(
@@ -424,11 +654,7 @@ pub async fn handle_lets_encrypt(
                 )
             })?;
         info!(target: LOG_TARGET, token, "let't encrypt http-01 success");
-        let body = if let Some(value) = value {
-            value.value
-        } else {
-            "".to_string()
-        };
+        let body = value.unwrap_or_default();
         HttpResponse {
             status: StatusCode::OK,
             body: body.into(),
@@ -441,9 +667,84 @@ pub async fn handle_lets_encrypt(
     Ok(false)
 }
 
+/// Restores the account of the provider from acme storage,
+/// a new account is created and saved if it can't be restored.
+async fn new_account(
+    storage: &AcmeStorage,
+    provider: &AcmeProvider,
+) -> Result<Account> {
+    let new_error = |e: instant_acme::Error| Error::Instant {
+        category: "create_account".to_string(),
+        source: e,
+    };
+    let directory_url = provider.directory_url.as_str();
+    match storage.load_account_credentials(directory_url).await {
+        Ok(Some(credentials)) => {
+            match Account::builder()
+                .map_err(new_error)?
+                .from_credentials(credentials)
+                .await
+            {
+                Ok(account) => return Ok(account),
+                Err(e) => error!(
+                    target: LOG_TARGET,
+                    error = %e,
+                    directory_url,
+                    "restore acme account fail"
+                ),
+            }
+        },
+        Ok(None) => {},
+        Err(e) => error!(
+            target: LOG_TARGET,
+            error = %e,
+            directory_url,
+            "load acme account fail"
+        ),
+    }
+
+    let external_account = if let Some((kid, hmac_key)) = &provider.eab {
+        let key = URL_SAFE_NO_PAD
+            .decode(hmac_key.trim_end_matches('='))
+            .map_err(|e| Error::Fail {
+                category: "eab_hmac_key".to_string(),
+                message: e.to_string(),
+            })?;
+        Some(ExternalAccountKey::new(kid.clone(), &key))
+    } else {
+        None
+    };
+    let (account, credentials) = Account::builder()
+        .map_err(new_error)?
+        .create(
+            &NewAccount {
+                contact: &[],
+                terms_of_service_agreed: true,
+                only_return_existing: false,
+            },
+            directory_url.to_string(),
+            external_account.as_ref(),
+        )
+        .await
+        .map_err(new_error)?;
+    // the account is still usable for this order if saving fails
+    if let Err(e) = storage
+        .save_account_credentials(directory_url, &credentials)
+        .await
+    {
+        error!(
+            target: LOG_TARGET,
+            error = %e,
+            directory_url,
+            "save acme account fail"
+        );
+    }
+    Ok(account)
+}
+
 /// Generates a new certificate from Let's Encrypt for the given domains.
 /// The ACME protocol flow:
-/// 1. Creates/retrieves an ACME account with Let's Encrypt
+/// 1. Restores the ACME account from acme storage or creates a new one
 /// 2. Creates a new order for the domains to be certified
 /// 3. For each domain:
 ///    - Gets the HTTP-01 challenge details
@@ -455,44 +756,23 @@ pub async fn handle_lets_encrypt(
 ///
 /// Returns a tuple of (certificate_chain_pem, private_key_pem)
 async fn new_lets_encrypt(
-    config_manager: Arc<ConfigManager>,
-    production: bool,
+    storage: AcmeStorage,
+    provider: AcmeProvider,
     params: UpdateCertificateParams,
 ) -> Result<(String, String)> {
     let mut domains: Vec<String> = params.domains.to_vec();
     // sort domain for comparing later
-    domains.sort();
+    if !params.keep_domain_order {
+        domains.sort();
+    }
     info!(
         target: LOG_TARGET,
         domains = domains.join(","),
+        directory_url = provider.directory_url,
         "acme from let's encrypt"
     );
-    let url = if production {
-        LetsEncrypt::Production.url()
-    } else {
-        LetsEncrypt::Staging.url()
-    };
     ensure_crypto_provider();
-
-    let (account, _) = Account::builder()
-        .map_err(|e| Error::Instant {
-            category: "create_account".to_string(),
-            source: e,
-        })?
-        .create(
-            &NewAccount {
-                contact: &[],
-                terms_of_service_agreed: true,
-                only_return_existing: false,
-            },
-            url.to_string(),
-            None,
-        )
-        .await
-        .map_err(|e| Error::Instant {
-            category: "create_account".to_string(),
-            source: e,
-        })?;
+    let account = new_account(&storage, &provider).await?;
 
     let mut order = account
         .new_order(&NewOrder::new(
@@ -519,6 +799,7 @@ async fn new_lets_encrypt(
     }
 
     let mut dns_tasks = vec![];
+    let mut http_tokens = vec![];
 
     let result = (async {
         let mut authorizations = order.authorizations();
@@ -565,7 +846,9 @@ async fn new_lets_encrypt(
                     "huawei" => {
                         Box::new(HuaweiDnsTask::new(&params.dns_service_url)?)
                     },
-                    _ => Box::new(ManualDnsTask::new(config_manager.clone())),
+                    _ => Box::new(ManualDnsTask::new(
+                        storage.state_manager().clone(),
+                    )),
                 };
 
                 info!(
@@ -624,24 +907,14 @@ async fn new_lets_encrypt(
                     })?;
 
                 let key_auth = challenge.key_authorization();
-                config_manager
-                    .update(
-                        Category::Storage,
+                storage
+                    .save_value(
                         &challenge.token,
-                        &StorageConf {
-                            value: key_auth.as_str().to_string(),
-                            category: "config".to_string(),
-                            secret: None,
-                            remark: Some(
-                                "let's encrypt http-01 token".to_string(),
-                            ),
-                        },
+                        key_auth.as_str(),
+                        "let's encrypt http-01 token",
                     )
-                    .await
-                    .map_err(|e| Error::Fail {
-                        category: "save_token".to_string(),
-                        message: e.to_string(),
-                    })?;
+                    .await?;
+                http_tokens.push(challenge.token.clone());
                 info!(
                     target: LOG_TARGET,
                     token = challenge.token,
@@ -685,13 +958,29 @@ async fn new_lets_encrypt(
             );
         }
     }
+    for token in http_tokens.iter() {
+        // ignore delete error, the token is useless after validation
+        if let Err(err) = storage.delete_value(token).await {
+            error!(
+                target: LOG_TARGET,
+                error = err.to_string(),
+                token,
+                "remove http-01 token fail"
+            );
+        }
+    }
     result?;
 
-    let private_key_pem =
-        order.finalize().await.map_err(|e| Error::Instant {
+    let (csr, key_pair) =
+        new_certificate_signing_request(&domains, params.common_name)?;
+    order
+        .finalize_csr(csr.der())
+        .await
+        .map_err(|e| Error::Instant {
             category: "finalize".to_string(),
             source: e,
         })?;
+    let private_key_pem = key_pair.serialize_pem();
     let cert_chain_pem = order
         .poll_certificate(
             &RetryPolicy::default().timeout(Duration::from_secs(60)),
@@ -701,6 +990,525 @@ async fn new_lets_encrypt(
             category: "poll_certificate".to_string(),
             source: e,
         })?;
+    // instant-acme only downloads the default chain, the alternate chains
+    // (link rel="alternate") are not exposed, so the default chain is the
+    // only one offered now.
+    let cert_chain_pem =
+        choose_certificate_chain(vec![cert_chain_pem], &params.preferred_chain);
 
     Ok((cert_chain_pem, private_key_pem))
 }
+
+/// Creates the CSR of the domains, the SAN keeps the order of domains
+/// and the common name is set to the first domain if `common_name` is true,
+/// otherwise the distinguished name is empty.
+fn new_certificate_signing_request(
+    domains: &[String],
+    common_name: bool,
+) -> Result<(rcgen::CertificateSigningRequest, rcgen::KeyPair)> {
+    let mut params =
+        rcgen::CertificateParams::new(domains.to_vec()).map_err(|e| {
+            Error::Rcgen {
+                category: "csr_params".to_string(),
+                source: e,
+            }
+        })?;
+    let mut distinguished_name = rcgen::DistinguishedName::new();
+    if common_name {
+        if let Some(domain) = domains.first() {
+            distinguished_name
+                .push(rcgen::DnType::CommonName, domain.to_string());
+        }
+    }
+    params.distinguished_name = distinguished_name;
+    let key_pair = rcgen::KeyPair::generate().map_err(|e| Error::Rcgen {
+        category: "key_pair".to_string(),
+        source: e,
+    })?;
+    let csr =
+        params
+            .serialize_request(&key_pair)
+            .map_err(|e| Error::Rcgen {
+                category: "csr".to_string(),
+                source: e,
+            })?;
+    Ok((csr, key_pair))
+}
+
+/// Chooses the preferred chain from the offered chains(the first one is
+/// the default chain), and sorts the certificates in leaf → intermediate order.
+fn choose_certificate_chain(
+    mut chains: Vec<String>,
+    preferred_chain: &str,
+) -> String {
+    let index = select_preferred_chain(&chains, preferred_chain)
+        .unwrap_or_else(|| {
+            if !preferred_chain.is_empty() {
+                info!(
+                    target: LOG_TARGET,
+                    preferred_chain,
+                    "preferred chain is not offered, use the default chain"
+                );
+            }
+            0
+        });
+    if index >= chains.len() {
+        return String::new();
+    }
+    sort_certificate_chain_pem(&chains.swap_remove(index))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ErrorKind;
+    use crate::renewal_queue::RenewalEntry;
+    use pingap_config::test_util::FlakyStorage;
+    use pingap_config::{Category, ConfigMode, StorageConf};
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::AtomicU32;
+
+    #[test]
+    fn test_parse_acme_providers() {
+        assert_eq!(
+            vec![AcmeProvider {
+                directory_url: LetsEncrypt::Production.url().to_string(),
+                eab: None,
+            }],
+            parse_acme_providers(&[])
+        );
+        assert_eq!(
+            vec![
+                AcmeProvider {
+                    directory_url: "https://acme.zerossl.com/v2/DV90"
+                        .to_string(),
+                    eab: Some(("kid".to_string(), "aG1hYw".to_string())),
+                },
+                AcmeProvider {
+                    directory_url: LetsEncrypt::Production.url().to_string(),
+                    eab: None,
+                },
+            ],
+            parse_acme_providers(&[
+                "https://acme.zerossl.com/v2/DV90 kid aG1hYw".to_string(),
+                "https://acme.zerossl.com/v2/DV90 kid".to_string(),
+                LetsEncrypt::Production.url().to_string(),
+            ])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_issue_with_fallback() {
+        let primary = "https://primary.acme/directory";
+        let secondary = "https://secondary.acme/directory";
+        let providers =
+            parse_acme_providers(&[primary.to_string(), secondary.to_string()]);
+        let rate_limited = || {
+            Error::Instant {
+            category: "new_order".to_string(),
+            source: instant_acme::Error::Api(
+                serde_json::from_str(
+                    r#"{"type":"urn:ietf:params:acme:error:rateLimited","status":429}"#,
+                )
+                .unwrap(),
+            ),
+        }
+        };
+
+        // primary is rate limited, the secondary issues the certificate
+        let (cert, issuer) =
+            issue_with_fallback(&providers, |provider| async move {
+                if provider.directory_url == primary {
+                    return Err(rate_limited());
+                }
+                Ok("cert")
+            })
+            .await
+            .unwrap();
+        assert_eq!("cert", cert);
+        assert_eq!(secondary, issuer);
+
+        // primary succeeds
+        let (_, issuer) =
+            issue_with_fallback(&providers, |_| async { Ok("cert") })
+                .await
+                .unwrap();
+        assert_eq!(primary, issuer);
+
+        // non retryable error doesn't fall back
+        let err = issue_with_fallback(&providers, |provider| async move {
+            if provider.directory_url == primary {
+                return Err(Error::Fail {
+                    category: "save_token".to_string(),
+                    message: "permission denied".to_string(),
+                });
+            }
+            Ok("cert")
+        })
+        .await
+        .err()
+        .unwrap();
+        assert_eq!(ErrorKind::StorageFailure, err.kind());
+
+        // all providers fail
+        let err = issue_with_fallback(&providers, |_| async {
+            Err::<&str, _>(rate_limited())
+        })
+        .await
+        .err()
+        .unwrap();
+        assert_eq!(ErrorKind::RateLimited, err.kind());
+    }
+
+    #[test]
+    fn test_new_certificate_signing_request() {
+        let domains =
+            vec!["b.pingap.io".to_string(), "a.pingap.io".to_string()];
+        let get_san = |params: &rcgen::CertificateParams| {
+            params
+                .subject_alt_names
+                .iter()
+                .map(|san| match san {
+                    rcgen::SanType::DnsName(name) => name.as_str().to_string(),
+                    _ => "".to_string(),
+                })
+                .collect::<Vec<String>>()
+        };
+
+        // common name is the first domain, san keeps the order
+        let (csr, _) = new_certificate_signing_request(&domains, true).unwrap();
+        let csr = rcgen::CertificateSigningRequestParams::from_der(csr.der())
+            .unwrap();
+        assert_eq!(
+            Some(&rcgen::DnValue::Utf8String("b.pingap.io".to_string())),
+            csr.params
+                .distinguished_name
+                .get(&rcgen::DnType::CommonName)
+        );
+        assert_eq!(domains, get_san(&csr.params));
+
+        // empty distinguished name by default
+        let (csr, _) =
+            new_certificate_signing_request(&domains, false).unwrap();
+        let csr = rcgen::CertificateSigningRequestParams::from_der(csr.der())
+            .unwrap();
+        assert_eq!(0, csr.params.distinguished_name.iter().count());
+    }
+
+    #[test]
+    fn test_normalize_challenge_prefix() {
+        assert_eq!(WELL_KNOWN_PATH_PREFIX, normalize_challenge_prefix(""));
+        assert_eq!(WELL_KNOWN_PATH_PREFIX, normalize_challenge_prefix("/"));
+        assert_eq!("/acme/", normalize_challenge_prefix("/acme"));
+        assert_eq!("/edge/acme/", normalize_challenge_prefix("edge/acme/"));
+    }
+
+    #[test]
+    fn test_get_challenge_token() {
+        // standard prefix by default
+        let prefix = normalize_challenge_prefix("");
+        assert_eq!(
+            Some("token"),
+            get_challenge_token("/.well-known/acme-challenge/token", &prefix)
+        );
+        assert_eq!(None, get_challenge_token("/acme/token", &prefix));
+        assert_eq!(
+            None,
+            get_challenge_token("/.well-known/acme-challenge/", &prefix)
+        );
+
+        // custom prefix
+        let prefix = normalize_challenge_prefix("/acme");
+        assert_eq!(Some("token"), get_challenge_token("/acme/token", &prefix));
+        assert_eq!(None, get_challenge_token("/acme-token", &prefix));
+        assert_eq!(
+            None,
+            get_challenge_token("/.well-known/acme-challenge/token", &prefix)
+        );
+    }
+
+    #[test]
+    fn test_get_lets_encrypt_certificate() {
+        let mut config = PingapConfig::default();
+        let new_conf =
+            |tls_cert: Option<&str>, tls_key: Option<&str>| CertificateConf {
+                tls_cert: tls_cert.map(|v| v.to_string()),
+                tls_key: tls_key.map(|v| v.to_string()),
+                ..Default::default()
+            };
+        config
+            .certificates
+            .insert("empty".to_string(), new_conf(None, Some("")));
+        config
+            .certificates
+            .insert("no_key".to_string(), new_conf(Some("cert"), None));
+        config
+            .certificates
+            .insert("no_cert".to_string(), new_conf(None, Some("key")));
+
+        // not yet issued
+        assert_eq!(
+            true,
+            get_lets_encrypt_certificate(&config, "empty")
+                .unwrap()
+                .is_none()
+        );
+
+        let err = get_lets_encrypt_certificate(&config, "no_key")
+            .err()
+            .unwrap();
+        assert_eq!(ErrorKind::Certificate, err.kind());
+        assert_eq!(
+            "Let's Encrypt operation failed: certificate(no_key) has tls cert but tls key is missing, set the tls key or remove the tls cert to reissue, category: partial_certificate",
+            err.to_string()
+        );
+
+        let err = get_lets_encrypt_certificate(&config, "no_cert")
+            .err()
+            .unwrap();
+        assert_eq!(ErrorKind::Certificate, err.kind());
+        assert_eq!(
+            "Let's Encrypt operation failed: certificate(no_cert) has tls key but tls cert is missing, set the tls cert or remove the tls key to reissue, category: partial_certificate",
+            err.to_string()
+        );
+
+        let err = get_lets_encrypt_certificate(&config, "unknown")
+            .err()
+            .unwrap();
+        assert_eq!(ErrorKind::NotFound, err.kind());
+    }
+
+    /// Requests the challenge token, returns the result of handling and
+    /// the response received by client.
+    async fn request_challenge(
+        config_manager: Arc<ConfigManager>,
+        token: &str,
+    ) -> (pingora::Result<bool>, String) {
+        use pingora::protocols::l4::stream::Stream;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::{TcpListener, TcpStream};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let path = format!("{WELL_KNOWN_PATH_PREFIX}{token}");
+        let client = tokio::spawn(async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream
+                .write_all(
+                    format!("GET {path} HTTP/1.1\r\nHost: pingap.io\r\n\r\n")
+                        .as_bytes(),
+                )
+                .await
+                .unwrap();
+            let mut buf = vec![];
+            stream.read_to_end(&mut buf).await.unwrap();
+            String::from_utf8_lossy(&buf).to_string()
+        });
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut session = Session::new_h1(Box::new(Stream::from(stream)));
+        session.read_request().await.unwrap();
+        let result = handle_lets_encrypt(
+            config_manager,
+            &mut session,
+            &mut Ctx::default(),
+            WELL_KNOWN_PATH_PREFIX,
+        )
+        .await;
+        drop(session);
+        (result, client.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_handle_lets_encrypt_storage_outage() {
+        let storage = Arc::new(FlakyStorage::default());
+        let config_manager = Arc::new(
+            ConfigManager::new(storage.clone(), ConfigMode::MultiByItem)
+                .with_stale_ttl(Duration::from_secs(60)),
+        );
+        config_manager
+            .update(
+                Category::Storage,
+                "token1",
+                &StorageConf {
+                    category: "config".to_string(),
+                    value: "token1.thumbprint".to_string(),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let (result, resp) =
+            request_challenge(config_manager.clone(), "token1").await;
+        assert_eq!(true, result.unwrap());
+        assert_eq!(true, resp.ends_with("token1.thumbprint"));
+
+        // the token is served from cache when storage throws
+        storage.set_unavailable(true);
+        let (result, resp) =
+            request_challenge(config_manager.clone(), "token1").await;
+        assert_eq!(true, result.unwrap());
+        assert_eq!(true, resp.starts_with("HTTP/1.1 200 OK"));
+        assert_eq!(true, resp.ends_with("token1.thumbprint"));
+
+        // the unknown token fails
+        let (result, _) = request_challenge(config_manager, "token2").await;
+        assert_eq!(
+            true,
+            result
+                .unwrap_err()
+                .to_string()
+                .contains("storage is unavailable")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_renewal_queue_restart() {
+        let storage = Arc::new(FlakyStorage::default());
+        let new_config_manager = || {
+            Arc::new(ConfigManager::new(
+                storage.clone(),
+                ConfigMode::MultiByItem,
+            ))
+        };
+        let params = vec![UpdateCertificateParams {
+            name: "pingap".to_string(),
+            domains: vec!["pingap.io".to_string()],
+            ..Default::default()
+        }];
+        let renewed = AtomicU32::new(0);
+        let renew = |_: UpdateCertificateParams| {
+            renewed.fetch_add(1, Ordering::Relaxed);
+            async { Ok(()) }
+        };
+
+        let config_manager = new_config_manager();
+        assert_eq!(
+            true,
+            enqueue_renewal(&config_manager, "pingap", 100)
+                .await
+                .unwrap()
+        );
+        // the queued certificate is not added again
+        assert_eq!(
+            false,
+            enqueue_renewal(&config_manager, "pingap", 100)
+                .await
+                .unwrap()
+        );
+
+        // the failed renewal is kept in queue with backoff
+        let done =
+            do_renewal_queue(config_manager.clone(), &params, 100, |_| async {
+                Err(Error::Fail {
+                    category: "new_order".to_string(),
+                    message: "service unavailable".to_string(),
+                })
+            })
+            .await
+            .unwrap();
+        assert_eq!(true, done);
+
+        // the queue persists across restart
+        let config_manager = new_config_manager();
+        assert_eq!(
+            vec![RenewalEntry {
+                name: "pingap".to_string(),
+                attempts: 1,
+                next_retry_at: 160,
+            }],
+            load_renewal_queue(&config_manager).await.unwrap()
+        );
+
+        // not retried before the backoff
+        let done =
+            do_renewal_queue(config_manager.clone(), &params, 120, renew)
+                .await
+                .unwrap();
+        assert_eq!(false, done);
+        assert_eq!(0, renewed.load(Ordering::Relaxed));
+
+        // retried after the backoff, and removed after success
+        let done =
+            do_renewal_queue(config_manager.clone(), &params, 160, renew)
+                .await
+                .unwrap();
+        assert_eq!(true, done);
+        assert_eq!(1, renewed.load(Ordering::Relaxed));
+        assert_eq!(
+            true,
+            load_renewal_queue(&config_manager)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+
+        // the entry of removed certificate is dropped
+        enqueue_renewal(&config_manager, "removed", 200)
+            .await
+            .unwrap();
+        let done =
+            do_renewal_queue(config_manager.clone(), &params, 200, renew)
+                .await
+                .unwrap();
+        assert_eq!(true, done);
+        assert_eq!(1, renewed.load(Ordering::Relaxed));
+        assert_eq!(
+            true,
+            load_renewal_queue(&new_config_manager())
+                .await
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_update_certificates_dedicated_storage() {
+        let config_storage = Arc::new(FlakyStorage::default());
+        let acme_storage = Arc::new(FlakyStorage::default());
+        let config_manager = Arc::new(ConfigManager::new(
+            config_storage.clone(),
+            ConfigMode::MultiByItem,
+        ));
+        let acme_manager = Arc::new(ConfigManager::new(
+            acme_storage.clone(),
+            ConfigMode::MultiByItem,
+        ));
+        let mut config = PingapConfig::default();
+        config.certificates.insert(
+            "pingap".to_string(),
+            CertificateConf {
+                domains: Some("pingap.io".to_string()),
+                acme: Some("lets_encrypt".to_string()),
+                ..Default::default()
+            },
+        );
+        config_manager.set_current_config(config.clone());
+        let storage =
+            AcmeStorage::new(config_manager.clone(), Some(acme_manager));
+        let params = get_update_certificate_params(&config);
+
+        let done = do_update_certificates(0, &storage, &params).await.unwrap();
+        assert_eq!(true, done);
+
+        // the renewal queue is written to the dedicated storage
+        assert_eq!(
+            vec!["pingap".to_string()],
+            load_renewal_queue(storage.state_manager())
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|item| item.name)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(
+            true,
+            load_renewal_queue(&config_manager)
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        assert_eq!(true, config_storage.is_empty());
+    }
+}
@@ -50,6 +50,78 @@ pub enum Error {
     Fail { category: String, message: String },
 }
 
+/// Stable kind of ACME error, callers and metrics can match on it
+/// instead of the category string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The ACME server rejects the request because of rate limits
+    RateLimited,
+    /// The ACME server can't reach or validate the challenge
+    ChallengeUnreachable,
+    /// The order is not pending or doesn't become ready
+    OrderInvalid,
+    /// Loading or saving the config/token fails
+    StorageFailure,
+    /// The dns provider fails to add or remove the txt record
+    DnsProvider,
+    /// The certificate can't be generated or parsed
+    Certificate,
+    /// The challenge or certificate is not found
+    NotFound,
+    /// Other errors, e.g. network error of the ACME server
+    Other,
+}
+
+impl Error {
+    /// Returns the stable kind of the error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Instant { source, .. } => instant_error_kind(source),
+            Error::Rcgen { .. } => ErrorKind::Certificate,
+            Error::NotFound { .. } => ErrorKind::NotFound,
+            Error::Fail { category, .. } => match category.as_str() {
+                "order_status" | "poll_ready" => ErrorKind::OrderInvalid,
+                "load_config" | "save_config" | "convert_config"
+                | "save_token" => ErrorKind::StorageFailure,
+                "ali" | "cf" | "huawei" | "tencent" => ErrorKind::DnsProvider,
+                "new_certificate" | "partial_certificate" => {
+                    ErrorKind::Certificate
+                },
+                _ => ErrorKind::Other,
+            },
+        }
+    }
+    /// Returns true if the error is caused by the ACME provider,
+    /// e.g. rate limited or unavailable, another provider may succeed.
+    pub fn is_retryable(&self) -> bool {
+        matches!(self.kind(), ErrorKind::RateLimited | ErrorKind::Other)
+    }
+}
+
+/// Gets the error kind from the problem document of the ACME server.
+fn instant_error_kind(err: &instant_acme::Error) -> ErrorKind {
+    let instant_acme::Error::Api(problem) = err else {
+        return ErrorKind::Other;
+    };
+    if problem.status == Some(429) {
+        return ErrorKind::RateLimited;
+    }
+    let problem_type = problem.r#type.as_deref().unwrap_or_default();
+    let name = problem_type
+        .strip_prefix("urn:ietf:params:acme:error:")
+        .unwrap_or(problem_type);
+    match name {
+        "rateLimited" => ErrorKind::RateLimited,
+        "connection" | "dns" | "incorrectResponse" | "unauthorized" | "tls" => {
+            ErrorKind::ChallengeUnreachable
+        },
+        "orderNotReady" | "badCSR" | "rejectedIdentifier" => {
+            ErrorKind::OrderInvalid
+        },
+        _ => ErrorKind::Other,
+    }
+}
+
 /// Convenience type alias for Results with our Error type
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
@@ -81,5 +153,81 @@ mod dns_huawei;
 mod dns_manual;
 mod dns_tencent;
 mod lets_encrypt;
+mod renewal_queue;
+mod storage;
+
+pub use lets_encrypt::{
+    WELL_KNOWN_PATH_PREFIX, handle_lets_encrypt,
+    new_lets_encrypt_renewal_service, new_lets_encrypt_service,
+    normalize_challenge_prefix,
+};
+pub use storage::{
+    AcmeStorage, try_init_acme_storage, try_init_challenge_cache,
+};
 
-pub use lets_encrypt::{handle_lets_encrypt, new_lets_encrypt_service};
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn new_api_error(problem: &str) -> Error {
+        Error::Instant {
+            category: "new_order".to_string(),
+            source: instant_acme::Error::Api(
+                serde_json::from_str(problem).unwrap(),
+            ),
+        }
+    }
+
+    #[test]
+    fn test_error_kind() {
+        let err = new_api_error(
+            r#"{"type":"urn:ietf:params:acme:error:rateLimited","detail":"too many certificates","status":429}"#,
+        );
+        assert_eq!(ErrorKind::RateLimited, err.kind());
+        assert_eq!(true, err.is_retryable());
+        assert_eq!(true, err.to_string().starts_with("ACME instant error:"));
+
+        let err = new_api_error(
+            r#"{"type":"urn:ietf:params:acme:error:connection","detail":"timeout during connect","status":400}"#,
+        );
+        assert_eq!(ErrorKind::ChallengeUnreachable, err.kind());
+
+        let err = new_api_error(
+            r#"{"type":"urn:ietf:params:acme:error:malformed","status":400}"#,
+        );
+        assert_eq!(ErrorKind::Other, err.kind());
+
+        let err = Error::Fail {
+            category: "poll_ready".to_string(),
+            message: "unexpected order status: Invalid".to_string(),
+        };
+        assert_eq!(ErrorKind::OrderInvalid, err.kind());
+        assert_eq!(
+            "Let's Encrypt operation failed: unexpected order status: Invalid, category: poll_ready",
+            err.to_string()
+        );
+
+        let err = Error::Fail {
+            category: "save_token".to_string(),
+            message: "permission denied".to_string(),
+        };
+        assert_eq!(ErrorKind::StorageFailure, err.kind());
+        assert_eq!(false, err.is_retryable());
+
+        let err = Error::Fail {
+            category: "cf".to_string(),
+            message: "invalid token".to_string(),
+        };
+        assert_eq!(ErrorKind::DnsProvider, err.kind());
+
+        let err = Error::NotFound {
+            message: "Http01 challenge not found".to_string(),
+        };
+        assert_eq!(ErrorKind::NotFound, err.kind());
+        assert_eq!(
+            "ACME challenge not found: Http01 challenge not found",
+            err.to_string()
+        );
+    }
+}
@@ -0,0 +1,151 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Error, Result};
+use pingap_config::{Category, ConfigManager, StorageConf};
+use serde::{Deserialize, Serialize};
+use std::sync::LazyLock;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// Storage name of the renewal queue
+static RENEWAL_QUEUE_NAME: &str = "acme_renewal_queue";
+
+const MIN_RETRY_INTERVAL: u64 = 60;
+const MAX_RETRY_INTERVAL: u64 = 3600;
+
+// serializes the read-modify-write of the queue in this process
+static RENEWAL_QUEUE_LOCK: LazyLock<Mutex<()>> =
+    LazyLock::new(|| Mutex::new(()));
+
+/// Certificate waiting for renewal, it's removed from the queue
+/// only after the renewal succeeds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct RenewalEntry {
+    /// The name of certificate
+    pub name: String,
+    /// The failed attempts of renewal
+    pub attempts: u32,
+    /// The unix timestamp(seconds) of next attempt
+    pub next_retry_at: u64,
+}
+
+/// Returns the backoff of the failed attempts, it starts from one minute
+/// and doubles each time, up to one hour.
+pub(crate) fn get_retry_backoff(attempts: u32) -> Duration {
+    let secs = MIN_RETRY_INTERVAL
+        .saturating_mul(1u64 << attempts.saturating_sub(1).min(16))
+        .min(MAX_RETRY_INTERVAL);
+    Duration::from_secs(secs)
+}
+
+/// Loads the renewal queue from config storage.
+pub(crate) async fn load_renewal_queue(
+    config_manager: &ConfigManager,
+) -> Result<Vec<RenewalEntry>> {
+    let value: Option<StorageConf> = config_manager
+        .get(Category::Storage, RENEWAL_QUEUE_NAME)
+        .await
+        .map_err(|e| Error::Fail {
+            category: "load_config".to_string(),
+            message: e.to_string(),
+        })?;
+    let Some(value) = value.filter(|value| !value.value.is_empty()) else {
+        return Ok(vec![]);
+    };
+    serde_json::from_str(&value.value).map_err(|e| Error::Fail {
+        category: "load_config".to_string(),
+        message: e.to_string(),
+    })
+}
+
+async fn save_renewal_queue(
+    config_manager: &ConfigManager,
+    queue: &[RenewalEntry],
+) -> Result<()> {
+    let value = serde_json::to_string(queue).map_err(|e| Error::Fail {
+        category: "save_config".to_string(),
+        message: e.to_string(),
+    })?;
+    config_manager
+        .update(
+            Category::Storage,
+            RENEWAL_QUEUE_NAME,
+            &StorageConf {
+                value,
+                category: "config".to_string(),
+                secret: None,
+                remark: Some("acme renewal queue".to_string()),
+            },
+        )
+        .await
+        .map_err(|e| Error::Fail {
+            category: "save_config".to_string(),
+            message: e.to_string(),
+        })
+}
+
+/// Loads the renewal queue, applies the change and saves it if changed.
+pub(crate) async fn update_renewal_queue<F>(
+    config_manager: &ConfigManager,
+    apply: F,
+) -> Result<bool>
+where
+    F: FnOnce(&mut Vec<RenewalEntry>) -> bool,
+{
+    let _guard = RENEWAL_QUEUE_LOCK.lock().await;
+    let mut queue = load_renewal_queue(config_manager).await?;
+    if !apply(&mut queue) {
+        return Ok(false);
+    }
+    save_renewal_queue(config_manager, &queue).await?;
+    Ok(true)
+}
+
+/// Adds the certificate to the renewal queue, returns false
+/// if it's already queued.
+pub(crate) async fn enqueue_renewal(
+    config_manager: &ConfigManager,
+    name: &str,
+    now: u64,
+) -> Result<bool> {
+    update_renewal_queue(config_manager, |queue| {
+        if queue.iter().any(|entry| entry.name == name) {
+            return false;
+        }
+        queue.push(RenewalEntry {
+            name: name.to_string(),
+            attempts: 0,
+            next_retry_at: now,
+        });
+        true
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_get_retry_backoff() {
+        assert_eq!(Duration::from_secs(60), get_retry_backoff(0));
+        assert_eq!(Duration::from_secs(60), get_retry_backoff(1));
+        assert_eq!(Duration::from_secs(120), get_retry_backoff(2));
+        assert_eq!(Duration::from_secs(1920), get_retry_backoff(6));
+        assert_eq!(Duration::from_secs(3600), get_retry_backoff(7));
+        assert_eq!(Duration::from_secs(3600), get_retry_backoff(100));
+    }
+}
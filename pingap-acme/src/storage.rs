@@ -0,0 +1,578 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Error, Result};
+use instant_acme::AccountCredentials;
+use pingap_config::{
+    Category, CertificateConf, ConfigManager, StorageConf, new_config_manager,
+};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+// the dedicated storage of acme state
+static ACME_CONFIG_MANAGER: OnceLock<Arc<ConfigManager>> = OnceLock::new();
+
+// sweep the expired answers of challenge cache when it's larger than the size
+const CHALLENGE_CACHE_SWEEP_SIZE: usize = 1024;
+
+/// Cache of the http-01 challenge answers, the CA may request the token
+/// several times during validation. The unknown token is also cached for
+/// a short time, and the cached token is invalidated when it's written or
+/// deleted.
+struct ChallengeCache {
+    ttl: Duration,
+    negative_ttl: Duration,
+    answers: Mutex<HashMap<String, (Instant, Option<String>)>>,
+}
+
+impl ChallengeCache {
+    fn new(ttl: Duration, negative_ttl: Duration) -> Self {
+        Self {
+            ttl,
+            negative_ttl,
+            answers: Mutex::new(HashMap::new()),
+        }
+    }
+    /// Gets the cached answer, `Some(None)` means the token is unknown.
+    fn get(&self, token: &str) -> Option<Option<String>> {
+        let answers = self.answers.lock().ok()?;
+        answers
+            .get(token)
+            .filter(|(expired_at, _)| *expired_at > Instant::now())
+            .map(|(_, answer)| answer.clone())
+    }
+    fn set(&self, token: &str, answer: Option<String>) {
+        let ttl = if answer.is_some() {
+            self.ttl
+        } else {
+            self.negative_ttl
+        };
+        if ttl.is_zero() {
+            return;
+        }
+        let Ok(mut answers) = self.answers.lock() else {
+            return;
+        };
+        let now = Instant::now();
+        if answers.len() >= CHALLENGE_CACHE_SWEEP_SIZE {
+            answers.retain(|_, (expired_at, _)| *expired_at > now);
+        }
+        answers.insert(token.to_string(), (now + ttl, answer));
+    }
+    fn invalidate(&self, token: &str) {
+        if let Ok(mut answers) = self.answers.lock() {
+            answers.remove(token);
+        }
+    }
+}
+
+static CHALLENGE_CACHE: OnceLock<ChallengeCache> = OnceLock::new();
+
+fn get_challenge_cache() -> &'static ChallengeCache {
+    CHALLENGE_CACHE.get_or_init(|| {
+        ChallengeCache::new(Duration::from_secs(60), Duration::from_secs(5))
+    })
+}
+
+/// Initializes the ttl of the cached challenge answers and unknown tokens,
+/// e.g. `60s,5s`(default), `0s,0s` disables the cache.
+pub fn try_init_challenge_cache(value: &str) -> Result<()> {
+    let new_error = |message: String| Error::Fail {
+        category: "challenge_cache".to_string(),
+        message,
+    };
+    let (ttl, negative_ttl) = value.split_once(',').unwrap_or((value, "5s"));
+    let parse = |value: &str| {
+        humantime::parse_duration(value.trim())
+            .map_err(|e| new_error(format!("{value} is invalid, {e}")))
+    };
+    let cache = ChallengeCache::new(parse(ttl)?, parse(negative_ttl)?);
+    CHALLENGE_CACHE.set(cache).map_err(|_| {
+        new_error("challenge cache is already initialized".to_string())
+    })
+}
+
+/// Initializes the dedicated storage of acme state(account credentials,
+/// challenge tokens, renewal queue and issued certificates), e.g. the config
+/// is stored in git-backed files and the acme state in etcd.
+/// The config storage is used for acme state if it's not initialized.
+pub fn try_init_acme_storage(value: &str) -> Result<()> {
+    let manager = new_config_manager(value).map_err(|e| Error::Fail {
+        category: "acme_storage".to_string(),
+        message: e.to_string(),
+    })?;
+    ACME_CONFIG_MANAGER
+        .set(Arc::new(manager))
+        .map_err(|_| Error::Fail {
+            category: "acme_storage".to_string(),
+            message: "acme storage is already initialized".to_string(),
+        })
+}
+
+fn new_load_error(e: pingap_config::Error) -> Error {
+    Error::Fail {
+        category: "load_config".to_string(),
+        message: e.to_string(),
+    }
+}
+
+fn new_save_error(e: pingap_config::Error) -> Error {
+    Error::Fail {
+        category: "save_config".to_string(),
+        message: e.to_string(),
+    }
+}
+
+/// Storage of acme state, the certificate settings(domains, providers...)
+/// are always read from the config storage, the acme state is read from
+/// and written to the dedicated storage if it's set.
+#[derive(Clone)]
+pub struct AcmeStorage {
+    config_manager: Arc<ConfigManager>,
+    dedicated: Option<Arc<ConfigManager>>,
+}
+
+impl AcmeStorage {
+    pub fn new(
+        config_manager: Arc<ConfigManager>,
+        dedicated: Option<Arc<ConfigManager>>,
+    ) -> Self {
+        Self {
+            config_manager,
+            dedicated,
+        }
+    }
+    /// Resolves the acme storage, the dedicated storage is used
+    /// if it has been initialized by `try_init_acme_storage`.
+    pub fn resolve(config_manager: Arc<ConfigManager>) -> Self {
+        Self::new(config_manager, ACME_CONFIG_MANAGER.get().cloned())
+    }
+    /// Returns the config storage.
+    pub fn config_manager(&self) -> &Arc<ConfigManager> {
+        &self.config_manager
+    }
+    /// Returns the storage of acme state.
+    pub fn state_manager(&self) -> &Arc<ConfigManager> {
+        self.dedicated.as_ref().unwrap_or(&self.config_manager)
+    }
+    /// Gets the value of acme state, e.g. the http-01 token.
+    pub async fn get_value(&self, name: &str) -> Result<Option<String>> {
+        let value: Option<StorageConf> = self
+            .state_manager()
+            .get(Category::Storage, name)
+            .await
+            .map_err(new_load_error)?;
+        Ok(value.map(|value| value.value))
+    }
+    /// Gets the answer of http-01 challenge token, it's served from
+    /// the challenge cache if it has been looked up recently.
+    pub async fn get_challenge(&self, token: &str) -> Result<Option<String>> {
+        let cache = get_challenge_cache();
+        if let Some(answer) = cache.get(token) {
+            return Ok(answer);
+        }
+        let answer = self.get_value(token).await?;
+        cache.set(token, answer.clone());
+        Ok(answer)
+    }
+    /// Deletes the value of acme state.
+    pub async fn delete_value(&self, name: &str) -> Result<()> {
+        get_challenge_cache().invalidate(name);
+        self.state_manager()
+            .delete(Category::Storage, name)
+            .await
+            .map_err(new_save_error)
+    }
+    /// Saves the value of acme state.
+    pub async fn save_value(
+        &self,
+        name: &str,
+        value: &str,
+        remark: &str,
+    ) -> Result<()> {
+        get_challenge_cache().invalidate(name);
+        self.state_manager()
+            .update(
+                Category::Storage,
+                name,
+                &StorageConf {
+                    value: value.to_string(),
+                    category: "config".to_string(),
+                    secret: None,
+                    remark: Some(remark.to_string()),
+                },
+            )
+            .await
+            .map_err(new_save_error)
+    }
+    /// Loads the account credentials of the acme provider.
+    pub async fn load_account_credentials(
+        &self,
+        directory_url: &str,
+    ) -> Result<Option<AccountCredentials>> {
+        let Some(value) = self
+            .get_value(&get_account_name(directory_url))
+            .await?
+            .filter(|value| !value.is_empty())
+        else {
+            return Ok(None);
+        };
+        let credentials =
+            serde_json::from_str(&value).map_err(|e| Error::Fail {
+                category: "load_account".to_string(),
+                message: e.to_string(),
+            })?;
+        Ok(Some(credentials))
+    }
+    /// Saves the account credentials of the acme provider,
+    /// so the account is reused for the next order.
+    pub async fn save_account_credentials(
+        &self,
+        directory_url: &str,
+        credentials: &AccountCredentials,
+    ) -> Result<()> {
+        let value =
+            serde_json::to_string(credentials).map_err(|e| Error::Fail {
+                category: "save_account".to_string(),
+                message: e.to_string(),
+            })?;
+        self.save_value(
+            &get_account_name(directory_url),
+            &value,
+            &format!("acme account of {directory_url}"),
+        )
+        .await
+    }
+    /// Saves the issued certificate. It's written to the certificate config
+    /// if there is no dedicated storage, otherwise to the dedicated storage
+    /// and the certificate config is untouched.
+    pub async fn save_certificate(
+        &self,
+        name: &str,
+        pem: String,
+        key: String,
+        issuer: String,
+    ) -> Result<()> {
+        if let Some(dedicated) = &self.dedicated {
+            return dedicated
+                .update(
+                    Category::Certificate,
+                    name,
+                    &CertificateConf {
+                        tls_cert: Some(pem),
+                        tls_key: Some(key),
+                        acme_issuer: Some(issuer),
+                        remark: Some("issued by acme".to_string()),
+                        ..Default::default()
+                    },
+                )
+                .await
+                .map_err(new_save_error);
+        }
+        let cert: Option<CertificateConf> = self
+            .config_manager
+            .get(Category::Certificate, name)
+            .await
+            .map_err(new_load_error)?;
+        if let Some(mut cert) = cert {
+            cert.tls_cert = Some(pem);
+            cert.tls_key = Some(key);
+            cert.acme_issuer = Some(issuer);
+            self.config_manager
+                .update(Category::Certificate, name, &cert)
+                .await
+                .map_err(new_save_error)?;
+        }
+        Ok(())
+    }
+    /// Merges the certificates issued to the dedicated storage into the acme
+    /// certificates of config, nothing is changed without dedicated storage.
+    pub async fn merge_certificates(
+        &self,
+        certificates: &mut HashMap<String, CertificateConf>,
+    ) -> Result<()> {
+        let Some(dedicated) = &self.dedicated else {
+            return Ok(());
+        };
+        for (name, certificate) in certificates.iter_mut() {
+            if certificate.acme.as_deref().unwrap_or_default().is_empty() {
+                continue;
+            }
+            let issued: Option<CertificateConf> = dedicated
+                .get(Category::Certificate, name)
+                .await
+                .map_err(new_load_error)?;
+            let Some(issued) = issued else {
+                continue;
+            };
+            if issued.tls_cert.as_deref().unwrap_or_default().is_empty() {
+                continue;
+            }
+            certificate.tls_cert = issued.tls_cert;
+            certificate.tls_key = issued.tls_key;
+            certificate.acme_issuer = issued.acme_issuer;
+        }
+        Ok(())
+    }
+}
+
+/// Returns the storage name of the account, the directory url is hashed
+/// because it's not a valid name of storage.
+fn get_account_name(directory_url: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(directory_url.as_bytes());
+    let hash = hex::encode(hasher.finalize());
+    format!("acme_account_{}", hash.get(..16).unwrap_or(&hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingap_config::new_file_config_manager;
+    use pretty_assertions::assert_eq;
+
+    fn new_certificate_conf() -> CertificateConf {
+        CertificateConf {
+            domains: Some("pingap.io".to_string()),
+            acme: Some("lets_encrypt".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_get_account_name() {
+        assert_eq!(
+            "acme_account_5e76d31517ecd664",
+            get_account_name("https://acme-v02.api.letsencrypt.org/directory")
+        );
+        assert_eq!(
+            true,
+            get_account_name("https://pingap.io")
+                != get_account_name("https://acme.pingap.io")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dedicated_storage() {
+        let config_dir = tempfile::tempdir().unwrap();
+        let acme_dir = tempfile::tempdir().unwrap();
+        let config_manager = Arc::new(
+            new_file_config_manager(config_dir.path().to_str().unwrap())
+                .unwrap(),
+        );
+        let acme_manager = Arc::new(
+            new_file_config_manager(acme_dir.path().to_str().unwrap()).unwrap(),
+        );
+        config_manager
+            .update(Category::Certificate, "pingap", &new_certificate_conf())
+            .await
+            .unwrap();
+
+        let storage = AcmeStorage::new(
+            config_manager.clone(),
+            Some(acme_manager.clone()),
+        );
+        storage
+            .save_value("token1", "token1.thumbprint", "http-01 token")
+            .await
+            .unwrap();
+        storage
+            .save_certificate(
+                "pingap",
+                "pem".to_string(),
+                "key".to_string(),
+                "https://pingap.io/directory".to_string(),
+            )
+            .await
+            .unwrap();
+
+        // acme state is written to the dedicated storage
+        assert_eq!(
+            Some("token1.thumbprint".to_string()),
+            storage.get_value("token1").await.unwrap()
+        );
+        let value: Option<StorageConf> =
+            acme_manager.get(Category::Storage, "token1").await.unwrap();
+        assert_eq!("token1.thumbprint", value.unwrap().value);
+        let issued: Option<CertificateConf> = acme_manager
+            .get(Category::Certificate, "pingap")
+            .await
+            .unwrap();
+        assert_eq!(Some("pem".to_string()), issued.unwrap().tls_cert);
+
+        // config storage is untouched
+        let value: Option<StorageConf> = config_manager
+            .get(Category::Storage, "token1")
+            .await
+            .unwrap();
+        assert_eq!(true, value.is_none());
+        let cert: Option<CertificateConf> = config_manager
+            .get(Category::Certificate, "pingap")
+            .await
+            .unwrap();
+        let cert = cert.unwrap();
+        assert_eq!(None, cert.tls_cert);
+        assert_eq!(Some("pingap.io".to_string()), cert.domains);
+
+        // the issued certificate is merged into config
+        let mut certificates = HashMap::from([
+            ("pingap".to_string(), new_certificate_conf()),
+            (
+                "static".to_string(),
+                CertificateConf {
+                    tls_cert: Some("static pem".to_string()),
+                    ..Default::default()
+                },
+            ),
+        ]);
+        storage.merge_certificates(&mut certificates).await.unwrap();
+        let cert = certificates.get("pingap").unwrap();
+        assert_eq!(Some("pem".to_string()), cert.tls_cert);
+        assert_eq!(Some("key".to_string()), cert.tls_key);
+        assert_eq!(
+            Some("https://pingap.io/directory".to_string()),
+            cert.acme_issuer
+        );
+        assert_eq!(Some("pingap.io".to_string()), cert.domains);
+        assert_eq!(
+            Some("static pem".to_string()),
+            certificates.get("static").unwrap().tls_cert
+        );
+    }
+
+    #[tokio::test]
+    async fn test_challenge_cache() {
+        let config_dir = tempfile::tempdir().unwrap();
+        let config_manager = Arc::new(
+            new_file_config_manager(config_dir.path().to_str().unwrap())
+                .unwrap(),
+        );
+        let storage = AcmeStorage::new(config_manager.clone(), None);
+        let update_token = |value: &str| {
+            let config_manager = config_manager.clone();
+            let value = value.to_string();
+            async move {
+                config_manager
+                    .update(
+                        Category::Storage,
+                        "cache_token",
+                        &StorageConf {
+                            value,
+                            category: "config".to_string(),
+                            ..Default::default()
+                        },
+                    )
+                    .await
+                    .unwrap();
+            }
+        };
+
+        storage
+            .save_value("cache_token", "answer1", "http-01 token")
+            .await
+            .unwrap();
+        assert_eq!(
+            Some("answer1".to_string()),
+            storage.get_challenge("cache_token").await.unwrap()
+        );
+        // the repeated lookup hits the cache, not the storage
+        update_token("answer2").await;
+        assert_eq!(
+            Some("answer1".to_string()),
+            storage.get_challenge("cache_token").await.unwrap()
+        );
+        // the cache is invalidated when the token is written
+        storage
+            .save_value("cache_token", "answer3", "http-01 token")
+            .await
+            .unwrap();
+        assert_eq!(
+            Some("answer3".to_string()),
+            storage.get_challenge("cache_token").await.unwrap()
+        );
+
+        // the deleted token is negative cached
+        storage.delete_value("cache_token").await.unwrap();
+        assert_eq!(None, storage.get_challenge("cache_token").await.unwrap());
+        update_token("answer4").await;
+        assert_eq!(None, storage.get_challenge("cache_token").await.unwrap());
+        assert_eq!(
+            Some("answer4".to_string()),
+            storage.get_value("cache_token").await.unwrap()
+        );
+    }
+
+    #[test]
+    fn test_challenge_cache_ttl() {
+        let cache = ChallengeCache::new(Duration::ZERO, Duration::ZERO);
+        cache.set("token", Some("answer".to_string()));
+        assert_eq!(None, cache.get("token"));
+
+        let cache = ChallengeCache::new(
+            Duration::from_secs(60),
+            Duration::from_secs(5),
+        );
+        cache.set("token", Some("answer".to_string()));
+        cache.set("unknown", None);
+        assert_eq!(Some(Some("answer".to_string())), cache.get("token"));
+        assert_eq!(Some(None), cache.get("unknown"));
+        cache.invalidate("token");
+        assert_eq!(None, cache.get("token"));
+    }
+
+    #[tokio::test]
+    async fn test_default_storage() {
+        let config_dir = tempfile::tempdir().unwrap();
+        let config_manager = Arc::new(
+            new_file_config_manager(config_dir.path().to_str().unwrap())
+                .unwrap(),
+        );
+        config_manager
+            .update(Category::Certificate, "pingap", &new_certificate_conf())
+            .await
+            .unwrap();
+
+        let storage = AcmeStorage::new(config_manager.clone(), None);
+        storage
+            .save_value("token1", "token1.thumbprint", "http-01 token")
+            .await
+            .unwrap();
+        storage
+            .save_certificate(
+                "pingap",
+                "pem".to_string(),
+                "key".to_string(),
+                "https://pingap.io/directory".to_string(),
+            )
+            .await
+            .unwrap();
+
+        // acme state is written to the config storage
+        let value: Option<StorageConf> = config_manager
+            .get(Category::Storage, "token1")
+            .await
+            .unwrap();
+        assert_eq!("token1.thumbprint", value.unwrap().value);
+        let cert: Option<CertificateConf> = config_manager
+            .get(Category::Certificate, "pingap")
+            .await
+            .unwrap();
+        let cert = cert.unwrap();
+        assert_eq!(Some("pem".to_string()), cert.tls_cert);
+        assert_eq!(Some("key".to_string()), cert.tls_key);
+        assert_eq!(Some("pingap.io".to_string()), cert.domains);
+    }
+}
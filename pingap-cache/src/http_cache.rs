@@ -19,6 +19,7 @@ use async_trait::async_trait;
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use pingap_core::BackgroundTask;
 use pingap_core::Error as ServiceError;
+use pingap_core::HTTP_HEADER_NAME_X_GENERATE_ETAG;
 use pingora::cache::key::{CacheHashKey, CompactCacheKey};
 use pingora::cache::storage::MissFinishType;
 use pingora::cache::storage::{HandleHit, HandleMiss};
@@ -26,6 +27,7 @@ use pingora::cache::trace::SpanHandle;
 use pingora::cache::{
     CacheKey, CacheMeta, HitHandler, MissHandler, PurgeType, Storage,
 };
+use sha2::{Digest, Sha256};
 use std::any::Any;
 use std::sync::Arc;
 use std::time::Duration;
@@ -338,10 +340,32 @@ impl HandleHit for CompleteHit {
     }
 }
 
+/// Generates the strong ETag of the body, the format is
+/// `"<length in hex>-<sha256 prefix>"`.
+pub fn generate_etag(body: &[u8]) -> String {
+    let hash: String = hex::encode(Sha256::digest(body));
+    format!(r#""{:x}-{}""#, body.len(), &hash[0..16])
+}
+
+/// Replaces the internal generate etag header of the cached meta
+/// with the ETag of the body.
+fn set_generated_etag(
+    meta: BinaryMeta,
+    body: &[u8],
+) -> pingora::Result<BinaryMeta> {
+    let mut cache_meta = CacheMeta::deserialize(&meta.0, &meta.1)?;
+    let header = cache_meta.response_header_mut();
+    let _ = header.remove_header(&HTTP_HEADER_NAME_X_GENERATE_ETAG);
+    header.insert_header(http::header::ETAG, generate_etag(body))?;
+    cache_meta.serialize()
+}
+
 /// Handles cache misses by collecting and storing new content
 pub struct ObjectMissHandler {
     /// Metadata to store with the cached content
     meta: BinaryMeta,
+    /// Whether to generate the ETag of the body before storing
+    generate_etag: bool,
     /// Buffer for collecting the body content
     body: BytesMut,
     /// Cache key for storing the final object
@@ -375,13 +399,18 @@ impl HandleMiss for ObjectMissHandler {
             size,
             "put data to cache"
         );
+        let meta = if self.generate_etag {
+            set_generated_etag(self.meta, &self.body)?
+        } else {
+            self.meta
+        };
         let _ = self
             .cache
             .put(
                 &self.key,
                 &self.namespace,
                 CacheObject {
-                    meta: self.meta,
+                    meta,
                     body: self.body.into(),
                 },
             )
@@ -435,9 +464,13 @@ impl Storage for HttpCache {
             capacity
         };
         let hash = key.combined();
+        let generate_etag = meta
+            .headers()
+            .contains_key(&HTTP_HEADER_NAME_X_GENERATE_ETAG);
         let meta = meta.serialize()?;
         let miss_handler = ObjectMissHandler {
             meta,
+            generate_etag,
             key: hash,
             primary_key: key.primary_key_str().unwrap_or_default().to_string(),
             namespace: key.namespace().to_vec(),
@@ -532,6 +565,7 @@ mod tests {
         let cache = Arc::new(TinyUfoCache::new(CacheMode::Normal, 10, 10));
         let obj = ObjectMissHandler {
             meta: (b"Hello".to_vec(), b"World".to_vec()),
+            generate_etag: false,
             body: BytesMut::new(),
             key: key.to_string(),
             primary_key: "".to_string(),
@@ -550,6 +584,59 @@ mod tests {
         assert_eq!("Hello World!", std::str::from_utf8(&data.body).unwrap());
     }
 
+    #[tokio::test]
+    async fn test_object_miss_handler_generate_etag() {
+        use pingora::http::{RequestHeader, ResponseHeader};
+        use pingora::protocols::http::conditional_filter::not_modified_filter;
+
+        let key = "key";
+        let cache = Arc::new(TinyUfoCache::new(CacheMode::Normal, 10, 10));
+        let mut header = ResponseHeader::build(200, None).unwrap();
+        header
+            .insert_header(&HTTP_HEADER_NAME_X_GENERATE_ETAG, "1")
+            .unwrap();
+        let now = SystemTime::now();
+        let meta =
+            CacheMeta::new(now + Duration::from_secs(60), now, 0, 0, header);
+        let obj = ObjectMissHandler {
+            meta: meta.serialize().unwrap(),
+            generate_etag: true,
+            body: BytesMut::new(),
+            key: key.to_string(),
+            primary_key: "".to_string(),
+            namespace: b"".to_vec(),
+            cache: cache.clone(),
+        };
+        let mut handle: MissHandler = Box::new(obj);
+        handle
+            .write_body(Bytes::from_static(b"Hello World!"), true)
+            .await
+            .unwrap();
+        handle.finish().await.unwrap();
+
+        let data = cache.get(key, b"").await.unwrap().unwrap();
+        let meta = CacheMeta::deserialize(&data.meta.0, &data.meta.1).unwrap();
+        let etag = generate_etag(b"Hello World!");
+        assert_eq!(r#""c-7f83b1657ff1fc53""#, etag);
+        assert_eq!(etag, meta.headers().get("etag").unwrap().to_str().unwrap());
+        assert_eq!(
+            false,
+            meta.headers()
+                .contains_key(&HTTP_HEADER_NAME_X_GENERATE_ETAG)
+        );
+
+        // matching If-None-Match yields 304
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.insert_header("If-None-Match", &etag).unwrap();
+        assert_eq!(true, not_modified_filter(&req, meta.response_header()));
+
+        // non-matching If-None-Match yields the full body
+        let mut req = RequestHeader::build("GET", b"/", None).unwrap();
+        req.insert_header("If-None-Match", r#""c-0000000000000000""#)
+            .unwrap();
+        assert_eq!(false, not_modified_filter(&req, meta.response_header()));
+    }
+
     #[test]
     fn test_cache_object_get_weight() {
         // data less than one page
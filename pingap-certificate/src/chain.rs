@@ -74,10 +74,230 @@ pub fn get_lets_encrypt_chain_certificate(cn: &str) -> Option<X509> {
     }
 }
 
+/// Subject and issuer of the certificate, (subject, issuer, issuer common name)
+fn parse_subject_issuer(pem: &[u8]) -> Option<(Vec<u8>, Vec<u8>, String)> {
+    let (_, p) = x509_parser::pem::parse_x509_pem(pem).ok()?;
+    let x509 = p.parse_x509().ok()?;
+    let issuer_cn = x509
+        .issuer()
+        .iter_common_name()
+        .next()
+        .and_then(|cn| cn.as_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    Some((
+        x509.subject().as_raw().to_vec(),
+        x509.issuer().as_raw().to_vec(),
+        issuer_cn,
+    ))
+}
+
+/// Sorts the pem certificates of chain in leaf → intermediate order.
+///
+/// The leaf is the certificate which doesn't issue any other certificate
+/// of the chain, then each certificate is followed by its issuer.
+/// The certificates which can't be linked are appended in original order,
+/// and the original order is kept if any certificate can't be parsed.
+pub fn sort_certificate_chain(pem_list: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    if pem_list.len() < 2 {
+        return pem_list;
+    }
+    let Some(names) = pem_list
+        .iter()
+        .map(|pem| parse_subject_issuer(pem))
+        .collect::<Option<Vec<_>>>()
+    else {
+        return pem_list;
+    };
+    let is_self_signed = |index: usize| names[index].0 == names[index].1;
+    let is_leaf = |i: usize| {
+        !names.iter().enumerate().any(|(j, (_, issuer, _))| {
+            i != j && !is_self_signed(j) && issuer == &names[i].0
+        })
+    };
+    // prefer the certificate which is not self signed
+    let leaf = (0..names.len())
+        .find(|&i| !is_self_signed(i) && is_leaf(i))
+        .or_else(|| (0..names.len()).find(|&i| is_leaf(i)))
+        .unwrap_or_default();
+
+    let mut indexes = vec![leaf];
+    let mut current = leaf;
+    while !is_self_signed(current) {
+        let Some(next) = (0..names.len())
+            .find(|j| !indexes.contains(j) && names[*j].0 == names[current].1)
+        else {
+            break;
+        };
+        indexes.push(next);
+        current = next;
+    }
+    for i in 0..names.len() {
+        if !indexes.contains(&i) {
+            indexes.push(i);
+        }
+    }
+    let mut pem_list: Vec<Option<Vec<u8>>> =
+        pem_list.into_iter().map(Some).collect();
+    indexes
+        .into_iter()
+        .filter_map(|i| pem_list[i].take())
+        .collect()
+}
+
+/// Sorts the certificates of pem chain in leaf → intermediate order,
+/// the chain is returned as it is if it's not valid pem data.
+pub fn sort_certificate_chain_pem(chain: &str) -> String {
+    let Some(pem_list) = pingap_util::convert_certificate_bytes(Some(chain))
+    else {
+        return chain.to_string();
+    };
+    sort_certificate_chain(pem_list)
+        .iter()
+        .map(|pem| {
+            String::from_utf8_lossy(pem)
+                .replace("\r\n", "\n")
+                .trim()
+                .to_string()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Selects the preferred chain from the chains offered by the ACME server.
+///
+/// The chain is matched by the issuer common name of its topmost certificate,
+/// e.g. `ISRG Root X1` selects the shorter chain of Let's Encrypt.
+/// Returns the index of the matched chain, or `None` if no chain matches.
+pub fn select_preferred_chain(
+    chains: &[String],
+    preferred_chain: &str,
+) -> Option<usize> {
+    let preferred_chain = preferred_chain.trim();
+    if preferred_chain.is_empty() {
+        return None;
+    }
+    chains.iter().position(|chain| {
+        pingap_util::convert_certificate_bytes(Some(chain))
+            .map(sort_certificate_chain)
+            .and_then(|pem_list| {
+                pem_list.last().and_then(|pem| parse_subject_issuer(pem))
+            })
+            .map(|(_, _, issuer_cn)| issuer_cn == preferred_chain)
+            .unwrap_or_default()
+    })
+}
+
 #[cfg(test)]
 mod tests {
-    use super::get_lets_encrypt_chain_certificate;
+    use super::{
+        get_lets_encrypt_chain_certificate, select_preferred_chain,
+        sort_certificate_chain, sort_certificate_chain_pem,
+    };
     use pretty_assertions::assert_eq;
+    use rcgen::{
+        BasicConstraints, Certificate, CertificateParams, DnType, IsCa, KeyPair,
+    };
+
+    fn new_certificate(
+        cn: &str,
+        issuer: Option<(&Certificate, &KeyPair)>,
+    ) -> (Certificate, KeyPair) {
+        let key = KeyPair::generate().unwrap();
+        let mut params = CertificateParams::new(vec![]).unwrap();
+        params
+            .distinguished_name
+            .push(DnType::CommonName, cn.to_string());
+        if cn != "leaf" {
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        }
+        let cert = if let Some((issuer, issuer_key)) = issuer {
+            params.signed_by(&key, issuer, issuer_key).unwrap()
+        } else {
+            params.self_signed(&key).unwrap()
+        };
+        (cert, key)
+    }
+
+    fn get_common_names(pem_list: &[Vec<u8>]) -> Vec<String> {
+        pem_list
+            .iter()
+            .map(|pem| {
+                let (_, p) = x509_parser::pem::parse_x509_pem(pem).unwrap();
+                let x509 = p.parse_x509().unwrap();
+                x509.subject()
+                    .iter_common_name()
+                    .next()
+                    .unwrap()
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_sort_certificate_chain() {
+        let (root, root_key) = new_certificate("root", None);
+        let (intermediate, intermediate_key) =
+            new_certificate("intermediate", Some((&root, &root_key)));
+        let (leaf, _) =
+            new_certificate("leaf", Some((&intermediate, &intermediate_key)));
+        let (other, _) = new_certificate("other", None);
+
+        let pem_list = vec![
+            root.pem().into_bytes(),
+            intermediate.pem().into_bytes(),
+            leaf.pem().into_bytes(),
+        ];
+        assert_eq!(
+            vec!["leaf", "intermediate", "root"],
+            get_common_names(&sort_certificate_chain(pem_list))
+        );
+
+        // the certificate not in chain is appended
+        let pem_list = vec![
+            other.pem().into_bytes(),
+            intermediate.pem().into_bytes(),
+            leaf.pem().into_bytes(),
+        ];
+        assert_eq!(
+            vec!["leaf", "intermediate", "other"],
+            get_common_names(&sort_certificate_chain(pem_list))
+        );
+
+        let chain = [intermediate.pem(), leaf.pem()].join("\n");
+        assert_eq!(
+            [leaf.pem().trim(), intermediate.pem().trim()].join("\n"),
+            sort_certificate_chain_pem(&chain)
+        );
+
+        // invalid certificate, keep the original order
+        let pem_list = vec![b"abc".to_vec(), leaf.pem().into_bytes()];
+        assert_eq!(pem_list.clone(), sort_certificate_chain(pem_list.clone()));
+    }
+
+    #[test]
+    fn test_select_preferred_chain() {
+        let (root, root_key) = new_certificate("ISRG Root X1", None);
+        let (old_root, old_root_key) = new_certificate("DST Root CA X3", None);
+        let (cross, _) =
+            new_certificate("ISRG Root X1", Some((&old_root, &old_root_key)));
+        let (intermediate, intermediate_key) =
+            new_certificate("R10", Some((&root, &root_key)));
+        let (leaf, _) =
+            new_certificate("leaf", Some((&intermediate, &intermediate_key)));
+
+        let long_chain =
+            [leaf.pem(), intermediate.pem(), cross.pem()].join("\n");
+        let short_chain = [intermediate.pem(), leaf.pem()].join("\n");
+        let chains = vec![long_chain, short_chain];
+
+        assert_eq!(Some(1), select_preferred_chain(&chains, "ISRG Root X1"));
+        assert_eq!(Some(0), select_preferred_chain(&chains, "DST Root CA X3"));
+        assert_eq!(None, select_preferred_chain(&chains, "Unknown"));
+        assert_eq!(None, select_preferred_chain(&chains, ""));
+    }
 
     #[test]
     fn test_get_lets_encrypt_chain_certificate() {
@@ -15,6 +15,7 @@
 use super::CertificateProvider;
 use super::DynamicCertificates;
 use super::{Error, LOG_TARGET, TlsCertificate};
+use super::{SessionResumptionParams, set_session_resumption};
 use ahash::AHashMap;
 use async_trait::async_trait;
 use pingap_config::CertificateConf;
@@ -36,39 +37,51 @@ type Result<T, E = Error> = std::result::Result<T, E>;
 // - No matching certificate is found for the requested domain
 pub static DEFAULT_SERVER_NAME: &str = "*";
 
+/// Normalizes the server name for matching, the dns name is case
+/// insensitive and the trailing dot of fully qualified name is ignored.
+fn normalize_server_name(name: &str) -> Cow<'_, str> {
+    let name = name.trim().trim_end_matches('.');
+    if name.bytes().any(|b| b.is_ascii_uppercase()) {
+        Cow::Owned(name.to_ascii_lowercase())
+    } else {
+        Cow::Borrowed(name)
+    }
+}
+
 // Parses certificate configurations and builds the certificate store
 // Parameters:
 // - certificate_configs: Map of certificate names to their configurations
 // Returns:
 // - DynamicCertificates: Map of domain names to parsed certificates
 // - Vec<(String, String)>: List of (certificate_name, error_message) for failed parsing
+//
+// If several certificates serve the same domain, the one serving fewer
+// domains is more specific and selected, the tie is broken by the name
+// of certificate, so the selection doesn't depend on the map order.
 pub fn parse_certificates(
     certificate_configs: &HashMap<String, CertificateConf>,
 ) -> (DynamicCertificates, Vec<(String, String)>) {
     let mut dynamic_certs = AHashMap::new();
     let mut errors = vec![];
+    // The number of domains served by the selected certificate of domain
+    let mut domain_counts: AHashMap<String, usize> = AHashMap::new();
 
-    // Use a temporary map to avoid cloning the Arc<TlsCertificate> for each domain.
-    let mut cert_cache: AHashMap<String, Arc<TlsCertificate>> = AHashMap::new();
-
-    for (name, conf) in certificate_configs.iter() {
+    let mut names: Vec<&String> = certificate_configs.keys().collect();
+    names.sort();
+    for name in names {
+        let conf = &certificate_configs[name];
         if conf.tls_cert.is_none() || conf.tls_key.is_none() {
             continue;
         }
 
-        let cert_arc = match cert_cache.get(name) {
-            Some(cert) => cert.clone(),
-            None => match TlsCertificate::try_from(conf) {
-                Ok(mut cert) => {
-                    cert.name = Some(name.clone());
-                    let arc_cert = Arc::new(cert);
-                    cert_cache.insert(name.clone(), arc_cert.clone());
-                    arc_cert
-                },
-                Err(e) => {
-                    errors.push((name.clone(), e.to_string()));
-                    continue;
-                },
+        let cert_arc = match TlsCertificate::try_from(conf) {
+            Ok(mut cert) => {
+                cert.name = Some(name.clone());
+                Arc::new(cert)
+            },
+            Err(e) => {
+                errors.push((name.clone(), e.to_string()));
+                continue;
             },
         };
 
@@ -80,7 +93,17 @@ pub fn parse_certificates(
             Cow::Borrowed(&cert_arc.domains)
         };
 
+        let count = domains_to_serve.len();
         for domain in domains_to_serve.iter() {
+            let domain = normalize_server_name(domain);
+            if domain.is_empty()
+                || domain_counts
+                    .get(domain.as_ref())
+                    .is_some_and(|value| *value <= count)
+            {
+                continue;
+            }
+            domain_counts.insert(domain.to_string(), count);
             dynamic_certs.insert(domain.to_string(), cert_arc.clone());
         }
 
@@ -92,11 +115,35 @@ pub fn parse_certificates(
     (dynamic_certs, errors)
 }
 
+/// Finds the certificate for the server name of client hello.
+///
+/// The exact name is more specific than the wildcard name, and the
+/// wildcard name only matches a single label, e.g. `*.example.com` matches
+/// `api.example.com`, but neither `example.com` nor `v1.api.example.com`.
+/// The default certificate is used if no certificate matches.
+pub fn find_certificate(
+    certs: &DynamicCertificates,
+    sni: &str,
+) -> Option<Arc<TlsCertificate>> {
+    let sni = normalize_server_name(sni);
+    certs
+        .get(sni.as_ref())
+        .or_else(|| {
+            sni.split_once('.')
+                .filter(|(label, domain)| {
+                    !label.is_empty() && !domain.is_empty()
+                })
+                .and_then(|(_, domain)| certs.get(&format!("*.{domain}")))
+        })
+        .or_else(|| certs.get(DEFAULT_SERVER_NAME))
+        .cloned()
+}
+
 /// Parameters for configuring TLS settings
 ///
 /// Contains all the necessary configuration options for setting up TLS,
 /// including protocol versions, cipher suites, and HTTP/2 support.
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct TlsSettingParams {
     pub server_name: String,
     pub enabled_h2: bool,            // Enable HTTP/2 support
@@ -104,6 +151,7 @@ pub struct TlsSettingParams {
     pub cipher_suites: Option<String>, // Modern cipher suites
     pub tls_min_version: Option<String>, // Minimum TLS version
     pub tls_max_version: Option<String>, // Maximum TLS version
+    pub session_resumption: SessionResumptionParams, // Session tickets and cache
 }
 
 /// Applies certificate, private key and chain certificate to an SSL context
@@ -211,6 +259,11 @@ impl GlobalCertificate {
         {
             error!(target: LOG_TARGET, error = %e, name, "set tls max proto version fail");
         }
+        set_session_resumption(
+            &mut tls_settings,
+            &name,
+            &params.session_resumption,
+        )?;
 
         if let Some(min_version) = tls_settings.min_proto_version() {
             info!(
@@ -242,7 +295,8 @@ impl pingora::listeners::TlsAccept for GlobalCertificate {
         // 3. Try wildcard domain match (*.example.com)
         // 4. Fall back to default certificate (DEFAULT_SERVER_NAME)
         // 5. Handle special case for CA certificates (self-signed)
-        // 6. Apply certificate, private key, and chain to SSL context
+        // 6. Apply certificate, private key, and chain to SSL context,
+        //    including the alternative certificate of another key type
 
         let sni = ssl
             .servername(NameType::HOST_NAME)
@@ -285,9 +339,20 @@ impl pingora::listeners::TlsAccept for GlobalCertificate {
             return;
         }
 
-        if let Some((cert, key)) = &d.certificate {
-            ssl_certificate(ssl, cert, key, &d.chain_certificates);
-        }
+        apply_tls_certificate(ssl, &d);
+    }
+}
+
+/// Applies the certificate and the alternative certificate of another key
+/// type, openssl selects the one matching the signature algorithms
+/// of the client hello.
+#[inline]
+fn apply_tls_certificate(ssl: &mut SslRef, d: &TlsCertificate) {
+    if let Some((cert, key)) = &d.certificate {
+        ssl_certificate(ssl, cert, key, &d.chain_certificates);
+    }
+    if let Some((cert, key)) = &d.alt_certificate {
+        ssl_certificate(ssl, cert, key, &d.alt_chain_certificates);
     }
 }
 
@@ -295,7 +360,18 @@ impl pingora::listeners::TlsAccept for GlobalCertificate {
 mod tests {
     use super::*;
     use pingap_config::CertificateConf;
+    use pingora::tls::asn1::Asn1Time;
+    use pingora::tls::ec::{EcGroup, EcKey};
+    use pingora::tls::hash::MessageDigest;
+    use pingora::tls::nid::Nid;
+    use pingora::tls::pkey::Id;
+    use pingora::tls::rsa::Rsa;
+    use pingora::tls::ssl::{
+        Ssl, SslAcceptor, SslConnector, SslMethod, SslVerifyMode,
+    };
+    use pingora::tls::x509::{X509Builder, X509NameBuilder};
     use pretty_assertions::assert_eq;
+    use std::os::unix::net::UnixStream;
 
     fn get_tls_pem() -> (String, String) {
         // spellchecker:off
@@ -358,6 +434,85 @@ aqcrKJfS+xaKWxXPiNlpBMG5
         // spellchecker:on
     }
 
+    fn new_self_signed_certificate(key: &PKey<Private>) -> X509 {
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "pingap.io").unwrap();
+        let name = name.build();
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder.sign(key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    /// Handshakes with the client of the signature algorithms,
+    /// returns the key type of the certificate received by the client.
+    fn handshake(tls_certificate: &TlsCertificate, sigalgs: &str) -> Id {
+        let (client_stream, server_stream) = UnixStream::pair().unwrap();
+        let tls_certificate = tls_certificate.clone();
+        let server = std::thread::spawn(move || {
+            let acceptor =
+                SslAcceptor::mozilla_intermediate_v5(SslMethod::tls())
+                    .unwrap()
+                    .build();
+            let mut ssl = Ssl::new(acceptor.context()).unwrap();
+            apply_tls_certificate(&mut ssl, &tls_certificate);
+            ssl.accept(server_stream).unwrap();
+        });
+
+        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+        connector.set_verify(SslVerifyMode::NONE);
+        connector.set_sigalgs_list(sigalgs).unwrap();
+        let stream = connector
+            .build()
+            .connect("pingap.io", client_stream)
+            .unwrap();
+        let id = stream
+            .ssl()
+            .peer_certificate()
+            .unwrap()
+            .public_key()
+            .unwrap()
+            .id();
+        server.join().unwrap();
+        id
+    }
+
+    #[test]
+    fn test_dual_certificates() {
+        let rsa_key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let ec_key =
+            PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let tls_certificate = TlsCertificate {
+            certificate: Some((new_self_signed_certificate(&rsa_key), rsa_key)),
+            alt_certificate: Some((
+                new_self_signed_certificate(&ec_key),
+                ec_key,
+            )),
+            ..Default::default()
+        };
+
+        // client only supports rsa
+        assert_eq!(
+            Id::RSA,
+            handshake(&tls_certificate, "RSA-PSS+SHA256:RSA+SHA256")
+        );
+        // client supports ecdsa
+        assert_eq!(
+            Id::EC,
+            handshake(&tls_certificate, "ECDSA+SHA256:RSA-PSS+SHA256")
+        );
+    }
+
     #[test]
     fn test_convert_tls_version() {
         assert_eq!(
@@ -396,4 +551,101 @@ aqcrKJfS+xaKWxXPiNlpBMG5
         assert_eq!(1791253416, info.not_after);
         assert_eq!(true, dynamic_certificate.certificate.is_some());
     }
+
+    fn new_certificate_conf(domains: &[&str]) -> CertificateConf {
+        let certified_key = rcgen::generate_simple_self_signed(
+            domains.iter().map(|v| v.to_string()).collect::<Vec<_>>(),
+        )
+        .unwrap();
+        CertificateConf {
+            tls_cert: Some(certified_key.cert.pem()),
+            tls_key: Some(certified_key.key_pair.serialize_pem()),
+            ..Default::default()
+        }
+    }
+
+    fn get_certificate_name(certs: &DynamicCertificates, sni: &str) -> String {
+        find_certificate(certs, sni)
+            .and_then(|cert| cert.name.clone())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_wildcard_certificate() {
+        let mut configs = HashMap::new();
+        configs.insert(
+            "wildcard".to_string(),
+            new_certificate_conf(&["*.example.com"]),
+        );
+        configs.insert(
+            "default".to_string(),
+            CertificateConf {
+                is_default: Some(true),
+                ..new_certificate_conf(&["pingap.io"])
+            },
+        );
+        let (certs, errors) = parse_certificates(&configs);
+        assert_eq!(true, errors.is_empty());
+
+        // the wildcard san matches a single label
+        assert_eq!("wildcard", get_certificate_name(&certs, "api.example.com"));
+        assert_eq!("wildcard", get_certificate_name(&certs, "www.example.com"));
+        // the server name is case insensitive
+        assert_eq!(
+            "wildcard",
+            get_certificate_name(&certs, "WWW.Example.COM.")
+        );
+        assert_eq!("default", get_certificate_name(&certs, "example.com"));
+        assert_eq!(
+            "default",
+            get_certificate_name(&certs, "v1.api.example.com")
+        );
+        assert_eq!("default", get_certificate_name(&certs, ".example.com"));
+        assert_eq!("default", get_certificate_name(&certs, "pingap.io"));
+
+        // no default certificate
+        configs.remove("default");
+        let (certs, _) = parse_certificates(&configs);
+        assert_eq!(true, find_certificate(&certs, "example.com").is_none());
+    }
+
+    #[test]
+    fn test_certificate_specificity() {
+        let mut configs = HashMap::new();
+        configs.insert(
+            "wildcard".to_string(),
+            new_certificate_conf(&["*.example.com"]),
+        );
+        configs.insert(
+            "api".to_string(),
+            new_certificate_conf(&["api.example.com"]),
+        );
+        configs.insert(
+            "all".to_string(),
+            new_certificate_conf(&[
+                "example.com",
+                "*.example.com",
+                "api.example.com",
+                "*.api.example.com",
+            ]),
+        );
+        configs.insert(
+            "user".to_string(),
+            CertificateConf {
+                domains: Some("user.example.com".to_string()),
+                ..new_certificate_conf(&["*.example.com"])
+            },
+        );
+        let (certs, errors) = parse_certificates(&configs);
+        assert_eq!(true, errors.is_empty());
+
+        // the exact name is more specific than the wildcard name
+        assert_eq!("api", get_certificate_name(&certs, "api.example.com"));
+        assert_eq!("user", get_certificate_name(&certs, "user.example.com"));
+        // the certificate serving fewer domains is more specific
+        assert_eq!("wildcard", get_certificate_name(&certs, "www.example.com"));
+        assert_eq!("all", get_certificate_name(&certs, "example.com"));
+        // the wildcard name of subdomain is more specific
+        assert_eq!("all", get_certificate_name(&certs, "v1.api.example.com"));
+    }
 }
@@ -23,6 +23,7 @@ use std::sync::LazyLock;
 mod chain;
 mod dynamic_certificate;
 mod self_signed;
+mod session_ticket;
 mod tls_certificate;
 mod validity_checker;
 
@@ -77,25 +78,12 @@ fn parse_ip_addr(data: &[u8]) -> Result<IpAddr> {
     })
 }
 
-// parse leaf certificate and chain certificates from pem and key
-pub fn parse_leaf_chain_certificates(
-    pem: &str,
-    key: &str,
-) -> Result<(Certificate, Vec<X509>)> {
-    let pem_data_list = pingap_util::convert_certificate_bytes(Some(pem))
-        .ok_or_else(|| Error::Invalid {
-            category: "certificate".to_string(),
-            message: "invalid pem data".to_string(),
-        })?;
-    let key_data_list =
-        pingap_util::convert_certificate_bytes(Some(key)).unwrap_or_default();
-    let leaf_pem_data = &pem_data_list[0];
+/// Parses the pem data of a certificate, the key of certificate is empty
+pub fn parse_certificate(pem: &[u8]) -> Result<Certificate> {
     let (_, p) =
-        x509_parser::pem::parse_x509_pem(leaf_pem_data).map_err(|e| {
-            Error::X509 {
-                category: "parse_x509_pem".to_string(),
-                message: e.to_string(),
-            }
+        x509_parser::pem::parse_x509_pem(pem).map_err(|e| Error::X509 {
+            category: "parse_x509_pem".to_string(),
+            message: e.to_string(),
         })?;
 
     let x509 = p.parse_x509().map_err(|e| Error::X509 {
@@ -123,6 +111,32 @@ pub fn parse_leaf_chain_certificates(
     dns_names.sort();
     let validity = x509.validity();
 
+    Ok(Certificate {
+        domains: dns_names,
+        pem: pem.to_vec(),
+        not_after: validity.not_after.timestamp(),
+        not_before: validity.not_before.timestamp(),
+        issuer: x509.issuer.to_string(),
+        ..Default::default()
+    })
+}
+
+// parse leaf certificate and chain certificates from pem and key
+pub fn parse_leaf_chain_certificates(
+    pem: &str,
+    key: &str,
+) -> Result<(Certificate, Vec<X509>)> {
+    let pem_data_list = pingap_util::convert_certificate_bytes(Some(pem))
+        .ok_or_else(|| Error::Invalid {
+            category: "certificate".to_string(),
+            message: "invalid pem data".to_string(),
+        })?;
+    // the leaf certificate should be the first one
+    let pem_data_list = sort_certificate_chain(pem_data_list);
+    let key_data_list =
+        pingap_util::convert_certificate_bytes(Some(key)).unwrap_or_default();
+    let mut leaf_certificate = parse_certificate(&pem_data_list[0])?;
+
     let mut x509_certificates = vec![];
     for pem in pem_data_list.iter() {
         let cert = X509::from_pem(pem).map_err(|e| Error::Invalid {
@@ -131,21 +145,9 @@ pub fn parse_leaf_chain_certificates(
         })?;
         x509_certificates.push(cert);
     }
-    let key = if key_data_list.is_empty() {
-        vec![]
-    } else {
-        key_data_list[0].clone()
-    };
-
-    let leaf_certificate = Certificate {
-        domains: dns_names,
-        pem: leaf_pem_data.clone(),
-        key,
-        not_after: validity.not_after.timestamp(),
-        not_before: validity.not_before.timestamp(),
-        issuer: x509.issuer.to_string(),
-        ..Default::default()
-    };
+    if let Some(key) = key_data_list.first() {
+        leaf_certificate.key = key.clone();
+    }
 
     Ok((leaf_certificate, x509_certificates))
 }
@@ -218,9 +220,15 @@ impl Certificate {
     }
 }
 
+pub use chain::{
+    select_preferred_chain, sort_certificate_chain, sort_certificate_chain_pem,
+};
 pub use dynamic_certificate::*;
 pub use rcgen;
 pub use self_signed::new_self_signed_certificate_validity_service;
+pub use session_ticket::{
+    SessionResumptionParams, TicketKeys, set_session_resumption,
+};
 pub use tls_certificate::TlsCertificate;
 pub use validity_checker::new_certificate_validity_service;
 
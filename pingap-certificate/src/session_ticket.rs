@@ -0,0 +1,463 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Error, LOG_TARGET};
+use pingora::tls::ex_data::Index;
+use pingora::tls::ssl::{
+    SslContext, SslContextBuilder, SslOptions, SslSessionCacheMode,
+};
+use pingora::tls::ssl_sys;
+use rand::RngCore;
+use std::ffi::{c_int, c_uchar, c_void};
+use std::fmt;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+// SSL_CTX_set_tlsext_ticket_key_cb is a macro of openssl
+const SSL_CTRL_SET_TLSEXT_TICKET_KEY_CB: c_int = 72;
+// the max length of session id context
+const MAX_SID_CTX_LENGTH: usize = 32;
+const TICKET_KEY_NAME_SIZE: usize = 16;
+
+static TICKET_KEYS_INDEX: OnceLock<Option<Index<SslContext, Arc<TicketKeys>>>> =
+    OnceLock::new();
+
+fn get_ticket_keys_index() -> Option<Index<SslContext, Arc<TicketKeys>>> {
+    *TICKET_KEYS_INDEX.get_or_init(|| {
+        SslContext::new_ex_index()
+            .map_err(|e| {
+                error!(target: LOG_TARGET, error = %e, "new ex index fail");
+            })
+            .ok()
+    })
+}
+
+#[derive(Clone)]
+struct TicketKey {
+    name: [u8; TICKET_KEY_NAME_SIZE],
+    aes_key: [u8; 32],
+    hmac_key: [u8; 32],
+    created_at: Instant,
+    retired_at: Option<Instant>,
+}
+
+impl TicketKey {
+    fn new(now: Instant) -> Self {
+        let mut rng = rand::rng();
+        let mut key = Self {
+            name: [0; TICKET_KEY_NAME_SIZE],
+            aes_key: [0; 32],
+            hmac_key: [0; 32],
+            created_at: now,
+            retired_at: None,
+        };
+        rng.fill_bytes(&mut key.name);
+        rng.fill_bytes(&mut key.aes_key);
+        rng.fill_bytes(&mut key.hmac_key);
+        key
+    }
+}
+
+/// Session ticket keys of server, the current key encrypts the new tickets
+/// and it's rotated periodically. The retired keys can still decrypt the
+/// tickets in the overlap window, so the clients can resume the session
+/// after rotation.
+pub struct TicketKeys {
+    rotation: Duration,
+    overlap: Duration,
+    // the first one is the current key
+    keys: RwLock<Vec<TicketKey>>,
+}
+
+impl fmt::Debug for TicketKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TicketKeys")
+            .field("rotation", &self.rotation)
+            .field("overlap", &self.overlap)
+            .finish()
+    }
+}
+
+impl TicketKeys {
+    pub fn new(rotation: Duration, overlap: Duration) -> Self {
+        Self {
+            rotation,
+            overlap,
+            keys: RwLock::new(vec![TicketKey::new(Instant::now())]),
+        }
+    }
+    /// Generates a new key as current key, the previous one is retired
+    /// and the keys retired before the overlap window are removed.
+    pub fn rotate(&self, now: Instant) {
+        let Ok(mut keys) = self.keys.write() else {
+            return;
+        };
+        if let Some(current) = keys.first_mut() {
+            current.retired_at = Some(now);
+        }
+        keys.insert(0, TicketKey::new(now));
+        keys.retain(|key| {
+            key.retired_at
+                .is_none_or(|at| now.duration_since(at) < self.overlap)
+        });
+        info!(
+            target: LOG_TARGET,
+            count = keys.len(),
+            "rotate session ticket keys"
+        );
+    }
+    fn get_encrypt_key(&self, now: Instant) -> Option<TicketKey> {
+        let expired = self.keys.read().ok().and_then(|keys| {
+            keys.first()
+                .map(|key| now.duration_since(key.created_at) >= self.rotation)
+        })?;
+        if expired {
+            self.rotate(now);
+        }
+        self.keys.read().ok()?.first().cloned()
+    }
+    /// Gets the key of name for decryption, returns the key and
+    /// whether it's the current key.
+    fn get_decrypt_key(
+        &self,
+        name: &[u8],
+        now: Instant,
+    ) -> Option<(TicketKey, bool)> {
+        let keys = self.keys.read().ok()?;
+        keys.iter().enumerate().find_map(|(index, key)| {
+            if key.name != name {
+                return None;
+            }
+            if key
+                .retired_at
+                .is_some_and(|at| now.duration_since(at) >= self.overlap)
+            {
+                return None;
+            }
+            Some((key.clone(), index == 0))
+        })
+    }
+}
+
+// The callback of session ticket, see `SSL_CTX_set_tlsext_ticket_key_cb`.
+// For encryption it returns 1, for decryption it returns 0 if the key
+// is not found, 1 if it's the current key and 2 if the ticket should
+// be renewed by the current key.
+unsafe extern "C" fn ticket_key_callback(
+    ssl: *mut ssl_sys::SSL,
+    key_name: *mut c_uchar,
+    iv: *mut c_uchar,
+    cipher_ctx: *mut ssl_sys::EVP_CIPHER_CTX,
+    hmac_ctx: *mut ssl_sys::HMAC_CTX,
+    enc: c_int,
+) -> c_int {
+    let Some(index) = get_ticket_keys_index() else {
+        return -1;
+    };
+    // the ticket keys are owned by the ssl context
+    let keys = unsafe {
+        let ctx = ssl_sys::SSL_get_SSL_CTX(ssl);
+        let data = ssl_sys::SSL_CTX_get_ex_data(ctx, index.as_raw());
+        if data.is_null() {
+            return -1;
+        }
+        &*(data as *const Arc<TicketKeys>)
+    };
+    let now = Instant::now();
+    if enc == 1 {
+        let Some(key) = keys.get_encrypt_key(now) else {
+            return -1;
+        };
+        let mut iv_data = [0u8; 16];
+        rand::rng().fill_bytes(&mut iv_data);
+        unsafe {
+            std::ptr::copy_nonoverlapping(
+                key.name.as_ptr(),
+                key_name,
+                TICKET_KEY_NAME_SIZE,
+            );
+            std::ptr::copy_nonoverlapping(iv_data.as_ptr(), iv, iv_data.len());
+            if ssl_sys::EVP_EncryptInit_ex(
+                cipher_ctx,
+                ssl_sys::EVP_aes_256_cbc(),
+                std::ptr::null_mut(),
+                key.aes_key.as_ptr(),
+                iv,
+            ) != 1
+            {
+                return -1;
+            }
+            if ssl_sys::HMAC_Init_ex(
+                hmac_ctx,
+                key.hmac_key.as_ptr() as *const c_void,
+                key.hmac_key.len() as c_int,
+                ssl_sys::EVP_sha256(),
+                std::ptr::null_mut(),
+            ) != 1
+            {
+                return -1;
+            }
+        }
+        return 1;
+    }
+
+    let name =
+        unsafe { std::slice::from_raw_parts(key_name, TICKET_KEY_NAME_SIZE) };
+    let Some((key, is_current)) = keys.get_decrypt_key(name, now) else {
+        return 0;
+    };
+    unsafe {
+        if ssl_sys::HMAC_Init_ex(
+            hmac_ctx,
+            key.hmac_key.as_ptr() as *const c_void,
+            key.hmac_key.len() as c_int,
+            ssl_sys::EVP_sha256(),
+            std::ptr::null_mut(),
+        ) != 1
+        {
+            return -1;
+        }
+        if ssl_sys::EVP_DecryptInit_ex(
+            cipher_ctx,
+            ssl_sys::EVP_aes_256_cbc(),
+            std::ptr::null_mut(),
+            key.aes_key.as_ptr(),
+            iv,
+        ) != 1
+        {
+            return -1;
+        }
+    }
+    if is_current { 1 } else { 2 }
+}
+
+/// Parameters of tls session resumption
+#[derive(Debug, Default, Clone)]
+pub struct SessionResumptionParams {
+    /// Whether to enable session tickets, the default is true
+    pub session_tickets: Option<bool>,
+    /// The size of server side session cache, 0 disables the cache
+    pub session_cache_size: Option<usize>,
+    /// The rotating keys of session ticket, openssl's key is used if not set
+    pub ticket_keys: Option<Arc<TicketKeys>>,
+}
+
+/// Configures the session resumption of tls context
+pub fn set_session_resumption(
+    ctx: &mut SslContextBuilder,
+    server_name: &str,
+    params: &SessionResumptionParams,
+) -> Result<()> {
+    let new_error = |message: String| Error::Invalid {
+        category: "session_resumption".to_string(),
+        message,
+    };
+    if let Some(size) = params.session_cache_size {
+        if size == 0 {
+            ctx.set_session_cache_mode(SslSessionCacheMode::OFF);
+        } else {
+            ctx.set_session_cache_mode(SslSessionCacheMode::SERVER);
+            ctx.set_session_cache_size(size.min(i32::MAX as usize) as i32);
+        }
+    }
+    // the session id context is required for session cache
+    let sid_ctx = server_name.as_bytes();
+    ctx.set_session_id_context(
+        &sid_ctx[..sid_ctx.len().min(MAX_SID_CTX_LENGTH)],
+    )
+    .map_err(|e| new_error(e.to_string()))?;
+
+    if !params.session_tickets.unwrap_or(true) {
+        ctx.set_options(SslOptions::NO_TICKET);
+        return Ok(());
+    }
+    let Some(ticket_keys) = &params.ticket_keys else {
+        return Ok(());
+    };
+    let index = get_ticket_keys_index()
+        .ok_or_else(|| new_error("new ex index fail".to_string()))?;
+    ctx.set_ex_data(index, ticket_keys.clone());
+    let callback = ticket_key_callback
+        as unsafe extern "C" fn(
+            *mut ssl_sys::SSL,
+            *mut c_uchar,
+            *mut c_uchar,
+            *mut ssl_sys::EVP_CIPHER_CTX,
+            *mut ssl_sys::HMAC_CTX,
+            c_int,
+        ) -> c_int;
+    let result = unsafe {
+        ssl_sys::SSL_CTX_callback_ctrl(
+            ctx.as_ptr(),
+            SSL_CTRL_SET_TLSEXT_TICKET_KEY_CB,
+            Some(std::mem::transmute::<
+                unsafe extern "C" fn(
+                    *mut ssl_sys::SSL,
+                    *mut c_uchar,
+                    *mut c_uchar,
+                    *mut ssl_sys::EVP_CIPHER_CTX,
+                    *mut ssl_sys::HMAC_CTX,
+                    c_int,
+                ) -> c_int,
+                extern "C" fn(),
+            >(callback)),
+        )
+    };
+    if result != 1 {
+        return Err(new_error("set ticket key callback fail".to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingora::tls::asn1::Asn1Time;
+    use pingora::tls::ec::{EcGroup, EcKey};
+    use pingora::tls::hash::MessageDigest;
+    use pingora::tls::nid::Nid;
+    use pingora::tls::pkey::{PKey, Private};
+    use pingora::tls::ssl::{
+        SslAcceptor, SslConnector, SslMethod, SslSession, SslVerifyMode,
+        SslVersion,
+    };
+    use pingora::tls::x509::{X509, X509Builder, X509NameBuilder};
+    use pretty_assertions::assert_eq;
+    use std::os::unix::net::UnixStream;
+
+    fn new_certificate() -> (X509, PKey<Private>) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_text("CN", "pingap.io").unwrap();
+        let name = name.build();
+        let mut builder = X509Builder::new().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        (builder.build(), key)
+    }
+
+    /// Handshakes with the session, returns whether the session is reused
+    /// and the new session of client.
+    fn handshake(
+        acceptor: &SslAcceptor,
+        session: Option<&SslSession>,
+    ) -> (bool, SslSession) {
+        let (client_stream, server_stream) = UnixStream::pair().unwrap();
+        let acceptor = acceptor.clone();
+        let server = std::thread::spawn(move || {
+            acceptor.accept(server_stream).unwrap();
+        });
+        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+        connector.set_verify(SslVerifyMode::NONE);
+        let connector = connector.build();
+        let mut ssl = connector
+            .configure()
+            .unwrap()
+            .into_ssl("pingap.io")
+            .unwrap();
+        if let Some(session) = session {
+            unsafe { ssl.set_session(session).unwrap() };
+        }
+        let stream = ssl.connect(client_stream).unwrap();
+        server.join().unwrap();
+        let reused = stream.ssl().session_reused();
+        (reused, stream.ssl().session().unwrap().to_owned())
+    }
+
+    #[test]
+    fn test_session_resumption_rotated_key() {
+        let (cert, key) = new_certificate();
+        let overlap = Duration::from_secs(600);
+        let ticket_keys =
+            Arc::new(TicketKeys::new(Duration::from_secs(3600), overlap));
+        let mut builder =
+            SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        builder.set_certificate(&cert).unwrap();
+        builder.set_private_key(&key).unwrap();
+        // the session is available after handshake for tls 1.2
+        builder
+            .set_max_proto_version(Some(SslVersion::TLS1_2))
+            .unwrap();
+        set_session_resumption(
+            &mut builder,
+            "pingap",
+            &SessionResumptionParams {
+                session_tickets: Some(true),
+                // resume the session only by ticket
+                session_cache_size: Some(0),
+                ticket_keys: Some(ticket_keys.clone()),
+            },
+        )
+        .unwrap();
+        let acceptor = builder.build();
+
+        let (reused, first_session) = handshake(&acceptor, None);
+        assert_eq!(false, reused);
+
+        // the ticket of retired key is accepted in the overlap window
+        // and it's renewed by the current key
+        let now = Instant::now();
+        ticket_keys.rotate(now);
+        let (reused, renewed_session) =
+            handshake(&acceptor, Some(&first_session));
+        assert_eq!(true, reused);
+
+        // the first key is removed after the overlap window
+        ticket_keys.rotate(now + overlap + Duration::from_secs(1));
+        let (reused, _) = handshake(&acceptor, Some(&first_session));
+        assert_eq!(false, reused);
+        let (reused, _) = handshake(&acceptor, Some(&renewed_session));
+        assert_eq!(true, reused);
+    }
+
+    #[test]
+    fn test_session_tickets_disabled() {
+        let (cert, key) = new_certificate();
+        let mut builder =
+            SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+        builder.set_certificate(&cert).unwrap();
+        builder.set_private_key(&key).unwrap();
+        builder
+            .set_max_proto_version(Some(SslVersion::TLS1_2))
+            .unwrap();
+        set_session_resumption(
+            &mut builder,
+            "pingap",
+            &SessionResumptionParams {
+                session_tickets: Some(false),
+                session_cache_size: Some(0),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        let acceptor = builder.build();
+        let (reused, session) = handshake(&acceptor, None);
+        assert_eq!(false, reused);
+        let (reused, _) = handshake(&acceptor, Some(&session));
+        assert_eq!(false, reused);
+    }
+}
@@ -30,6 +30,7 @@ use tracing::info;
 // Constants for categorizing different types of certificates and errors
 const LETS_ENCRYPT: &str = "lets_encrypt";
 const ERROR_CERTIFICATE: &str = "certificate";
+const ERROR_ALT_CERTIFICATE: &str = "alt_certificate";
 const ERROR_X509: &str = "x509_from_pem";
 const ERROR_PRIVATE_KEY: &str = "private_key_from_pem";
 const ERROR_CA: &str = "ca";
@@ -43,6 +44,10 @@ pub struct TlsCertificate {
     pub chain_certificates: Option<Vec<X509>>,
     // Optional tuple containing the certificate and its private key
     pub certificate: Option<(X509, PKey<Private>)>,
+    // Optional chain certificate of the alternative certificate
+    pub alt_chain_certificates: Option<Vec<X509>>,
+    // Optional alternative certificate of another key type (RSA + ECDSA)
+    pub alt_certificate: Option<(X509, PKey<Private>)>,
     // List of domain names this certificate is valid for
     pub domains: Vec<String>,
     // Additional certificate information
@@ -97,11 +102,46 @@ impl TryFrom<&CertificateConf> for TlsCertificate {
                 message: e.to_string(),
             }
         })?;
+        let mut alt_certificate = None;
+        let mut alt_chain_certificates = None;
+        if let (Some(alt_cert), Some(alt_key)) =
+            (&value.tls_alt_cert, &value.tls_alt_key)
+        {
+            let (alt_info, alt_x509_certificates) =
+                parse_leaf_chain_certificates(alt_cert, alt_key).map_err(
+                    |e| Error::Invalid {
+                        message: e.to_string(),
+                        category: ERROR_ALT_CERTIFICATE.to_string(),
+                    },
+                )?;
+            let alt_key = PKey::private_key_from_pem(&alt_info.get_key())
+                .map_err(|e| Error::Invalid {
+                    category: ERROR_PRIVATE_KEY.to_string(),
+                    message: e.to_string(),
+                })?;
+            // openssl keeps one certificate for each key type,
+            // the same key type would replace the primary certificate
+            if alt_key.id() == key.id() {
+                return Err(Error::Invalid {
+                    message: "alt certificate should use a different key type"
+                        .to_string(),
+                    category: ERROR_ALT_CERTIFICATE.to_string(),
+                });
+            }
+            if alt_x509_certificates.len() > 1 {
+                alt_chain_certificates =
+                    Some(alt_x509_certificates[1..].to_vec());
+            }
+            alt_certificate = Some((alt_x509_certificates[0].clone(), alt_key));
+        }
+
         Ok(TlsCertificate {
             hash_key,
             chain_certificates,
             domains: info.domains.clone(),
             certificate: Some((cert, key)),
+            alt_chain_certificates,
+            alt_certificate,
             info: Some(info),
             is_ca: value.is_ca.unwrap_or_default(),
             buffer_days: value.buffer_days.unwrap_or_default(),
@@ -38,6 +38,8 @@ pub struct EtcdStorage {
     options: ConnectOptions,
     // Enable history
     enable_history: bool,
+    // How long the last known config is served if etcd is unavailable
+    stale_ttl: Option<Duration>,
 }
 pub const ETCD_PROTOCOL: &str = "etcd://";
 
@@ -55,6 +57,9 @@ struct EtcdStorageParams {
     connect_timeout: Option<Duration>,
     #[serde(default)]
     enable_history: bool,
+    #[serde(default)]
+    #[serde(with = "humantime_serde")]
+    stale_ttl: Option<Duration>,
 }
 
 impl TryFrom<&str> for EtcdStorageParams {
@@ -69,7 +74,7 @@ impl TryFrom<&str> for EtcdStorageParams {
 
 impl EtcdStorage {
     /// Create a new etcd storage for config.
-    /// Connection url format: etcd://host1:port1,host2:port2/pingap?timeout=10s&connect_timeout=5s&user=**&password=**
+    /// Connection url format: etcd://host1:port1,host2:port2/pingap?timeout=10s&connect_timeout=5s&user=**&password=**&stale_ttl=5m
     pub fn new(value: &str) -> Result<Self> {
         let mut hosts = "".to_string();
         let mut path = "".to_string();
@@ -112,9 +117,15 @@ impl EtcdStorage {
             path,
             history_path,
             enable_history: params.enable_history,
+            stale_ttl: params.stale_ttl,
         })
     }
 
+    /// How long the last known config is served if etcd is unavailable
+    pub fn stale_ttl(&self) -> Option<Duration> {
+        self.stale_ttl
+    }
+
     /// Connect to etcd server.
     async fn connect(&self) -> Result<Client> {
         // TODO
@@ -290,5 +301,9 @@ mod tests {
         assert_eq!(params.connect_timeout, Some(Duration::from_secs(5)));
         assert_eq!(params.user, "abc");
         assert_eq!(params.password, "pwd");
+        assert_eq!(params.stale_ttl, None);
+
+        let params = EtcdStorageParams::try_from("stale_ttl=1m").unwrap();
+        assert_eq!(params.stale_ttl, Some(Duration::from_secs(60)));
     }
 }
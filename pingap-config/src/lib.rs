@@ -183,3 +183,65 @@ pub use common::*;
 pub use etcd_storage::ETCD_PROTOCOL;
 pub use manager::*;
 pub use storage::*;
+
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util {
+    use crate::{Error, Storage};
+    use async_trait::async_trait;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    type Result<T, E = Error> = std::result::Result<T, E>;
+
+    /// In memory storage which can be unavailable to simulate the outage
+    #[derive(Default)]
+    pub struct FlakyStorage {
+        data: Mutex<HashMap<String, String>>,
+        unavailable: AtomicBool,
+    }
+
+    impl FlakyStorage {
+        /// Sets the storage unavailable, all fetches fail
+        pub fn set_unavailable(&self, unavailable: bool) {
+            self.unavailable.store(unavailable, Ordering::Relaxed);
+        }
+        /// Inserts the data without going through the config manager
+        pub fn insert(&self, key: &str, value: &str) {
+            if let Ok(mut data) = self.data.lock() {
+                data.insert(key.to_string(), value.to_string());
+            }
+        }
+        /// Returns true if there is no data in storage
+        pub fn is_empty(&self) -> bool {
+            self.data.lock().map(|data| data.is_empty()).unwrap_or(true)
+        }
+    }
+
+    #[async_trait]
+    impl Storage for FlakyStorage {
+        async fn fetch(&self, key: &str) -> Result<String> {
+            if self.unavailable.load(Ordering::Relaxed) {
+                return Err(Error::Invalid {
+                    message: "storage is unavailable".to_string(),
+                });
+            }
+            Ok(self
+                .data
+                .lock()
+                .ok()
+                .and_then(|data| data.get(key).cloned())
+                .unwrap_or_default())
+        }
+        async fn save(&self, key: &str, value: &str) -> Result<()> {
+            self.insert(key, value);
+            Ok(())
+        }
+        async fn delete(&self, key: &str) -> Result<()> {
+            if let Ok(mut data) = self.data.lock() {
+                data.remove(key);
+            }
+            Ok(())
+        }
+    }
+}
@@ -21,9 +21,12 @@ use crate::{Category, Error, Observer};
 use arc_swap::ArcSwap;
 use pingap_util::resolve_path;
 use serde::{Deserialize, Deserializer, Serialize, de::DeserializeOwned};
+use std::collections::HashMap;
 use std::path::Path;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use toml::{Value, map::Map};
+use tracing::warn;
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
@@ -266,16 +269,24 @@ pub fn new_file_config_manager(path: &str) -> Result<ConfigManager> {
 
 pub fn new_etcd_config_manager(path: &str) -> Result<ConfigManager> {
     let storage = EtcdStorage::new(path)?;
-    Ok(ConfigManager::new(
-        Arc::new(storage),
-        ConfigMode::MultiByItem,
-    ))
+    let stale_ttl = storage.stale_ttl();
+    let manager =
+        ConfigManager::new(Arc::new(storage), ConfigMode::MultiByItem);
+    Ok(if let Some(stale_ttl) = stale_ttl {
+        manager.with_stale_ttl(stale_ttl)
+    } else {
+        manager
+    })
 }
 
 pub struct ConfigManager {
     storage: Arc<dyn Storage>,
     mode: ConfigMode,
     current_config: ArcSwap<PingapConfig>,
+    /// The last known data of storage key and the time it's fetched or saved
+    last_known: Mutex<HashMap<String, (Instant, String)>>,
+    /// How long the last known data can be served if storage is unavailable
+    stale_ttl: Duration,
 }
 
 impl ConfigManager {
@@ -284,8 +295,17 @@ impl ConfigManager {
             storage,
             mode,
             current_config: ArcSwap::from_pointee(PingapConfig::default()),
+            last_known: Mutex::new(HashMap::new()),
+            stale_ttl: Duration::ZERO,
         }
     }
+    /// Sets how long the last known data can be served if the storage is
+    /// unavailable, it's disabled by default and should only be enabled
+    /// for the remote storage(e.g. etcd).
+    pub fn with_stale_ttl(mut self, stale_ttl: Duration) -> Self {
+        self.stale_ttl = stale_ttl;
+        self
+    }
     pub fn support_observer(&self) -> bool {
         self.storage.support_observer()
     }
@@ -317,19 +337,70 @@ impl ConfigManager {
         }
     }
 
+    fn set_last_known(&self, key: &str, data: &str) {
+        if self.stale_ttl.is_zero() {
+            return;
+        }
+        if let Ok(mut last_known) = self.last_known.lock() {
+            // prune the expired data, it can't be served anymore
+            last_known.retain(|_, (updated_at, _)| {
+                updated_at.elapsed() <= self.stale_ttl
+            });
+            last_known
+                .insert(key.to_string(), (Instant::now(), data.to_string()));
+        }
+    }
+    fn remove_last_known(&self, key: &str) {
+        if let Ok(mut last_known) = self.last_known.lock() {
+            last_known.remove(key);
+        }
+    }
+    fn get_last_known(&self, key: &str) -> Option<String> {
+        let last_known = self.last_known.lock().ok()?;
+        let (updated_at, data) = last_known.get(key)?;
+        (updated_at.elapsed() <= self.stale_ttl).then(|| data.clone())
+    }
+    /// Fetches the data from storage, the last known data is served if the
+    /// storage is unavailable(e.g. etcd is down) and it's not stale, so the
+    /// transient outage doesn't break the config reads.
+    async fn fetch(&self, key: &str) -> Result<String> {
+        match self.storage.fetch(key).await {
+            Ok(data) => {
+                self.set_last_known(key, &data);
+                Ok(data)
+            },
+            Err(e) => {
+                let Some(data) = self.get_last_known(key) else {
+                    return Err(e);
+                };
+                warn!(
+                    error = %e,
+                    key,
+                    "storage is unavailable, serve the last known data"
+                );
+                Ok(data)
+            },
+        }
+    }
+    /// Saves the data to storage, and keeps it as the last known data
+    async fn save(&self, key: &str, value: &str) -> Result<()> {
+        self.storage.save(key, value).await?;
+        self.set_last_known(key, value);
+        Ok(())
+    }
+
     pub async fn load_all(&self) -> Result<PingapTomlConfig> {
-        let data = self.storage.fetch("").await?;
+        let data = self.fetch("").await?;
         toml::from_str(&data).map_err(|e| Error::De { source: e })
     }
     pub async fn save_all(&self, config: &PingapTomlConfig) -> Result<()> {
         match self.mode {
             ConfigMode::Single => {
-                self.storage
-                    .save(
-                        &self.get_key(&Category::Basic, ""),
-                        &to_string_pretty(config)?,
-                    )
-                    .await?;
+                self.save(
+                    &self.get_key(&Category::Basic, ""),
+                    &to_string_pretty(config)?,
+                )
+                .await?;
             },
             ConfigMode::MultiByType => {
                 for category in [
@@ -344,15 +415,12 @@ impl ConfigManager {
                 .iter()
                 {
                     let value = config.get_category_toml(category)?;
-                    self.storage
-                        .save(&self.get_key(category, ""), &value)
-                        .await?;
+                    self.save(&self.get_key(category, ""), &value).await?;
                 }
             },
             ConfigMode::MultiByItem => {
                 let basic_config = config.get_toml(&Category::Basic, "")?;
-                self.storage
-                    .save(&self.get_key(&Category::Basic, ""), &basic_config)
+                self.save(&self.get_key(&Category::Basic, ""), &basic_config)
                     .await?;
 
                 for (category, value) in [
@@ -368,8 +436,7 @@ impl ConfigManager {
                     };
                     for name in value.keys() {
                         let value = config.get_toml(&category, name)?;
-                        self.storage
-                            .save(&self.get_key(&category, name), &value)
+                        self.save(&self.get_key(&category, name), &value)
                             .await?;
                     }
                 }
@@ -392,7 +459,7 @@ impl ConfigManager {
             let value: Value =
                 toml::from_str(&value).map_err(|e| Error::De { source: e })?;
             let value = format_item_toml_config(Some(value), &category, name)?;
-            return self.storage.save(&key, &value).await;
+            return self.save(&key, &value).await;
         }
         // load all config
         let mut config = self.load_all().await?;
@@ -406,7 +473,7 @@ impl ConfigManager {
             to_string_pretty(&config)?
         };
 
-        self.storage.save(&key, &value).await?;
+        self.save(&key, &value).await?;
         Ok(())
     }
     pub async fn get<T: DeserializeOwned + Send>(
@@ -415,7 +482,7 @@ impl ConfigManager {
         name: &str,
     ) -> Result<Option<T>> {
         let key = self.get_key(&category, name);
-        let data = self.storage.fetch(&key).await?;
+        let data = self.fetch(&key).await?;
         let config: PingapTomlConfig =
             toml::from_str(&data).map_err(|e| Error::De { source: e })?;
 
@@ -435,7 +502,9 @@ impl ConfigManager {
         current_config.remove(category.to_string().as_str(), name)?;
 
         if self.mode == ConfigMode::MultiByItem {
-            return self.storage.delete(&key).await;
+            self.storage.delete(&key).await?;
+            self.remove_last_known(&key);
+            return Ok(());
         }
         let mut config = self.load_all().await?;
         config.delete(&category, name);
@@ -444,7 +513,7 @@ impl ConfigManager {
         } else {
             to_string_pretty(&config)?
         };
-        self.storage.save(&key, &value).await
+        self.save(&key, &value).await
     }
     pub fn support_history(&self) -> bool {
         self.storage.support_history()
@@ -465,6 +534,8 @@ impl ConfigManager {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::StorageConf;
+    use crate::test_util::FlakyStorage;
     use nanoid::nanoid;
     use pretty_assertions::assert_eq;
 
@@ -938,4 +1009,76 @@ value = "/storage22"
         let manager = new_etcd_config_manager(&url).unwrap();
         test_config_manger(manager, ConfigMode::MultiByItem).await;
     }
+
+    #[tokio::test]
+    async fn test_serve_last_known_data() {
+        let storage = Arc::new(FlakyStorage::default());
+        let manager =
+            ConfigManager::new(storage.clone(), ConfigMode::MultiByItem)
+                .with_stale_ttl(Duration::from_secs(60));
+        let token = StorageConf {
+            category: "config".to_string(),
+            value: "token-value".to_string(),
+            ..Default::default()
+        };
+        manager
+            .update(Category::Storage, "token", &token)
+            .await
+            .unwrap();
+        storage.insert(
+            "storages/other.toml",
+            "[storages.other]\ncategory = \"config\"\nvalue = \"other\"\n",
+        );
+
+        storage.set_unavailable(true);
+        // the saved data is served from the last known data
+        let value: StorageConf = manager
+            .get(Category::Storage, "token")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!("token-value", value.value);
+        // the data never fetched or saved isn't served
+        let result =
+            manager.get::<StorageConf>(Category::Storage, "other").await;
+        assert_eq!(
+            "Invalid error storage is unavailable",
+            result.err().unwrap().to_string()
+        );
+
+        // the last known data is disabled by default
+        let manager =
+            ConfigManager::new(storage.clone(), ConfigMode::MultiByItem);
+        storage.set_unavailable(false);
+        manager
+            .get::<StorageConf>(Category::Storage, "token")
+            .await
+            .unwrap();
+        assert_eq!(true, manager.last_known.lock().unwrap().is_empty());
+        storage.set_unavailable(true);
+        let result =
+            manager.get::<StorageConf>(Category::Storage, "token").await;
+        assert_eq!(true, result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_prune_last_known_data() {
+        let storage = Arc::new(FlakyStorage::default());
+        let manager =
+            ConfigManager::new(storage.clone(), ConfigMode::MultiByItem)
+                .with_stale_ttl(Duration::from_millis(50));
+        storage.insert("storages/a.toml", "");
+        storage.insert("storages/b.toml", "");
+        manager.fetch("storages/a.toml").await.unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+
+        // the expired data isn't served and it's pruned
+        storage.set_unavailable(true);
+        assert_eq!(true, manager.fetch("storages/a.toml").await.is_err());
+        storage.set_unavailable(false);
+        manager.fetch("storages/b.toml").await.unwrap();
+        let last_known = manager.last_known.lock().unwrap();
+        assert_eq!(1, last_known.len());
+        assert_eq!(true, last_known.contains_key("storages/b.toml"));
+    }
 }
@@ -12,9 +12,12 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::{Plugin, real_now_ms};
+use crate::{
+    BandwidthThrottle, FairQueuePermit, MinUploadRate, Plugin,
+    ProxyProtocolVersion, SocketOptions, new_internal_error, real_now_ms,
+};
 use ahash::AHashMap;
-use bytes::BytesMut;
+use bytes::{Bytes, BytesMut};
 use http::StatusCode;
 use http::Uri;
 use http::{HeaderName, HeaderValue};
@@ -31,6 +34,7 @@ use pingora::protocols::TimingDigest;
 use pingora::proxy::Session;
 use pingora_limits::inflight::Guard;
 use std::fmt::Write;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime};
 
@@ -109,6 +113,11 @@ pub trait ModifyResponseBody: Sync + Send {
     fn name(&self) -> String {
         "unknown".to_string()
     }
+    /// Returns true if the modifier buffers the whole body before modifying,
+    /// the buffering is limited by the max response buffer size.
+    fn buffering(&self) -> bool {
+        false
+    }
 }
 
 /// Information about a single client connection.
@@ -180,11 +189,65 @@ impl Default for Timing {
     }
 }
 
+/// Action applied when the certificate of upstream fails verification
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum TlsVerifyFailureAction {
+    /// Fail the request
+    #[default]
+    Fail,
+    /// Reconnect to the upstream without verification
+    Insecure,
+    /// Switch to the fallback upstream
+    Fallback(Arc<str>),
+}
+
 /// Trait for upstream instance, used to handle the upstream instance lifecycle.
 pub trait UpstreamInstance: Send + Sync {
     fn on_transport_failure(&self, address: &str);
     fn on_response(&self, address: &str, status: StatusCode);
     fn completed(&self) -> i32;
+    /// Called when the original request(not a retry) is sent to the upstream
+    fn on_request(&self) {}
+    /// Acquires a retry from the retry budget of the upstream,
+    /// returns false if the retry should be suppressed
+    fn acquire_retry(&self) -> bool {
+        true
+    }
+    /// Whether to reuse the connections to the upstream
+    fn keepalive(&self) -> bool {
+        true
+    }
+    /// Socket options applied to new connections of the upstream
+    fn socket_options(&self) -> Option<&SocketOptions> {
+        None
+    }
+    /// PROXY protocol version sent to new connections of the upstream
+    fn proxy_protocol(&self) -> Option<ProxyProtocolVersion> {
+        None
+    }
+    /// The expiry(unix timestamp) of certificate presented by the backend
+    fn cert_not_after(&self, _address: &str) -> Option<i64> {
+        None
+    }
+    /// The headers(name, value, append) applied to the requests to upstream
+    fn request_headers(&self) -> Option<&Vec<(HeaderName, HeaderValue, bool)>> {
+        None
+    }
+    /// The headers(name, value, append) applied to the responses of upstream
+    fn response_headers(
+        &self,
+    ) -> Option<&Vec<(HeaderName, HeaderValue, bool)>> {
+        None
+    }
+    /// Called when the certificate of backend fails verification,
+    /// returns the action applied to the request
+    fn on_tls_verify_failure(
+        &self,
+        _address: &str,
+        _message: &str,
+    ) -> TlsVerifyFailureAction {
+        TlsVerifyFailureAction::Fail
+    }
 }
 
 /// Trait for location instance
@@ -246,6 +309,40 @@ pub struct UpstreamInfo {
     ///
     /// If set to `None`, there is no time limit for the retry process.
     pub max_retry_window: Option<Duration>,
+    /// Whether to retry the idempotent request when the upstream
+    /// connection is reset before the response is sent to client.
+    pub retry_on_reset: bool,
+    /// Whether to reuse the connections to upstream,
+    /// None means using the keepalive of upstream.
+    pub keepalive: Option<bool>,
+    /// The upstream used when all attempts return 5xx.
+    pub fallback_upstream: Option<Arc<str>>,
+    /// The page served when all attempts return 5xx.
+    pub fallback_page: Option<Bytes>,
+    /// Indicates if the request has been switched to the fallback upstream.
+    pub fallback: bool,
+    /// Indicates if the certificate of upstream isn't verified
+    /// after the verification failure.
+    pub tls_insecure: bool,
+    /// The http version of the request sent to upstream, it's set by
+    /// plugin to translate the version of client request.
+    pub version: Option<http::Version>,
+    /// The number of retries for the retryable upstream response status.
+    pub status_retries: u8,
+    /// The delay before retrying the whole request, it's set by plugin
+    /// when the upstream response status is retryable.
+    pub retry_delay: Option<Duration>,
+    /// The maximum size of response body buffered by plugins,
+    /// zero means unlimited.
+    pub max_response_buffer_size: usize,
+    /// Responds error instead of bypassing the buffering plugins when the
+    /// content-length of response exceeds the max size.
+    pub response_buffer_overflow_error: bool,
+    /// The size of response body received by the buffering plugins.
+    pub response_buffered_size: usize,
+    /// The authorized addresses of CONNECT tunnel, it's set by the
+    /// forward proxy plugin.
+    pub tunnel_addrs: Option<Vec<SocketAddr>>,
 }
 
 /// State related to the current request being processed.
@@ -259,6 +356,9 @@ pub struct RequestState {
     pub payload_size: usize,
     /// A guard for rate limiting, if applicable.
     pub guard: Option<Guard>,
+    /// The permit of server's fair queue, the capacity is released
+    /// when the request is done.
+    pub fair_queue_permit: Option<FairQueuePermit>,
     /// The total number of requests currently being processed by the service.
     pub processing_count: i32,
     /// The total number of requests accepted by the service.
@@ -267,6 +367,9 @@ pub struct RequestState {
     pub location_processing_count: i32,
     /// The total number of requests accepted for this location.
     pub location_accepted_count: u64,
+    /// Whether the `100 Continue` interim response has been sent to the
+    /// client by pingap instead of the upstream.
+    pub continue_sent: bool,
 }
 
 /// All cache-related configuration and statistics for a request.
@@ -280,6 +383,9 @@ pub struct CacheInfo {
     pub check_cache_control: bool,
     /// The maximum time-to-live for cache entries.
     pub max_ttl: Option<Duration>,
+    /// Whether to generate a strong ETag for the cached response
+    /// without validators.
+    pub etag: bool,
     /// The number of cache read operations performed.
     pub reading_count: Option<u32>,
     /// The number of cache write operations performed.
@@ -298,6 +404,12 @@ pub struct Features {
     /// A map of plugin names and their response body handlers.
     pub modify_body_handlers:
         Option<AHashMap<String, Box<dyn ModifyResponseBody>>>,
+    /// Throttle of the response body written to the client.
+    pub bandwidth_throttle: Option<BandwidthThrottle>,
+    /// The minimum upload rate of request body
+    pub min_upload_rate: Option<MinUploadRate>,
+    /// The body sent to upstream instead of the buffered request body.
+    pub request_body: Option<Bytes>,
     /// OpenTelemetry tracer for distributed tracing (available with the "tracing" feature).
     #[cfg(feature = "tracing")]
     pub otel_tracer: Option<OtelTracer>,
@@ -508,6 +620,58 @@ impl Ctx {
         handlers.insert(name.to_string(), handler);
     }
 
+    /// Sets the bandwidth throttle of the response body.
+    #[inline]
+    pub fn set_bandwidth_throttle(&mut self, throttle: BandwidthThrottle) {
+        self.features.get_or_insert_default().bandwidth_throttle =
+            Some(throttle);
+    }
+
+    /// Returns the delay before the next response body chunk,
+    /// the chunk size is recorded by the bandwidth throttle.
+    #[inline]
+    pub fn get_bandwidth_throttle_delay(
+        &mut self,
+        size: usize,
+    ) -> Option<Duration> {
+        self.features
+            .as_mut()
+            .and_then(|f| f.bandwidth_throttle.as_mut())
+            .and_then(|throttle| throttle.on_sent(size, Instant::now()))
+    }
+
+    /// Sets the minimum upload rate of the request body.
+    #[inline]
+    pub fn set_min_upload_rate(&mut self, upload_rate: MinUploadRate) {
+        self.features.get_or_insert_default().min_upload_rate =
+            Some(upload_rate);
+    }
+
+    /// Records the request body chunk, returns true if the client uploads
+    /// the body slower than the minimum rate.
+    #[inline]
+    pub fn is_upload_too_slow(&mut self, size: usize) -> bool {
+        self.features
+            .as_mut()
+            .and_then(|f| f.min_upload_rate.as_mut())
+            .is_some_and(|upload_rate| {
+                upload_rate.on_received(size, Instant::now())
+            })
+    }
+
+    /// Replaces the request body sent to upstream, the request body should
+    /// be read into the retry buffer, which is replaced in request body filter.
+    #[inline]
+    pub fn set_request_body(&mut self, body: Bytes) {
+        self.features.get_or_insert_default().request_body = Some(body);
+    }
+
+    /// Returns the replaced request body.
+    #[inline]
+    pub fn get_request_body(&self) -> Option<&Bytes> {
+        self.features.as_ref()?.request_body.as_ref()
+    }
+
     /// Returns the modify body handler by name.
     #[inline]
     pub fn get_modify_body_handler(
@@ -520,6 +684,63 @@ impl Ctx {
             .and_then(|h| h.get_mut(name))
     }
 
+    /// Checks whether the response can be buffered by plugins, it's called
+    /// by the buffering plugins before modifying the response header.
+    /// If the content-length exceeds the max response buffer size, returns
+    /// 502 error in error mode, otherwise returns false and the plugin
+    /// should leave the response unchanged.
+    pub fn can_buffer_response(
+        &self,
+        headers: &http::HeaderMap,
+    ) -> pingora::Result<bool> {
+        let max = self.upstream.max_response_buffer_size;
+        if max == 0 {
+            return Ok(true);
+        }
+        let content_length = headers
+            .get(http::header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
+        let Some(content_length) = content_length else {
+            return Ok(true);
+        };
+        if content_length <= max {
+            return Ok(true);
+        }
+        if self.upstream.response_buffer_overflow_error {
+            return Err(new_internal_error(
+                502,
+                format!("response body exceeds the max buffer size({max})"),
+            ));
+        }
+        Ok(false)
+    }
+
+    /// Checks the size of response body received by the buffering plugins
+    /// against the max response buffer size. The response without
+    /// content-length is only known to exceed after its header is sent,
+    /// so the error is returned to abort the connection.
+    pub fn limit_response_buffer(&self, size: usize) -> pingora::Result<()> {
+        let max = self.upstream.max_response_buffer_size;
+        if max == 0 || size <= max {
+            return Ok(());
+        }
+        let buffering = self
+            .features
+            .as_ref()
+            .and_then(|f| f.modify_body_handlers.as_ref())
+            .is_some_and(|handlers| {
+                handlers.values().any(|handler| handler.buffering())
+            });
+        if buffering {
+            return Err(new_internal_error(
+                502,
+                format!("response body exceeds the max buffer size({max})"),
+            ));
+        }
+        Ok(())
+    }
+
     // A private helper function to filter out time values that are too large (over an hour),
     // which might indicate an error or uninitialized state.
     #[inline]
@@ -1414,4 +1635,89 @@ mod tests {
         ctx.add_modify_body_handler("test", Box::new(TestHandler {}));
         assert_eq!(true, ctx.get_modify_body_handler("test").is_some());
     }
+
+    #[test]
+    fn test_request_body() {
+        let mut ctx = Ctx::default();
+        assert_eq!(None, ctx.get_request_body());
+        ctx.set_request_body(Bytes::from("name=pingap"));
+        assert_eq!(Some(&Bytes::from("name=pingap")), ctx.get_request_body());
+    }
+
+    #[test]
+    fn test_limit_response_buffer() {
+        struct BufferHandler {}
+        impl ModifyResponseBody for BufferHandler {
+            fn handle(
+                &mut self,
+                _session: &Session,
+                _body: &mut Option<bytes::Bytes>,
+                _end_of_stream: bool,
+            ) -> pingora::Result<()> {
+                Ok(())
+            }
+            fn buffering(&self) -> bool {
+                true
+            }
+        }
+        let new_ctx = |overflow_error: bool| {
+            let mut ctx = Ctx::default();
+            ctx.upstream.max_response_buffer_size = 10;
+            ctx.upstream.response_buffer_overflow_error = overflow_error;
+            ctx
+        };
+        let new_headers = |content_length: &str| {
+            let mut headers = http::HeaderMap::new();
+            headers.insert(
+                http::header::CONTENT_LENGTH,
+                HeaderValue::from_str(content_length).unwrap(),
+            );
+            headers
+        };
+
+        // under the cap or unknown content-length
+        let ctx = new_ctx(true);
+        assert_eq!(true, ctx.can_buffer_response(&new_headers("10")).unwrap());
+        assert_eq!(
+            true,
+            ctx.can_buffer_response(&http::HeaderMap::new()).unwrap()
+        );
+
+        // over the cap in error mode, the header isn't sent yet
+        let err = ctx.can_buffer_response(&new_headers("11")).unwrap_err();
+        assert_eq!(pingora::ErrorType::HTTPStatus(502), err.etype);
+
+        // over the cap bypasses the buffering plugins
+        let ctx = new_ctx(false);
+        assert_eq!(false, ctx.can_buffer_response(&new_headers("11")).unwrap());
+
+        // unlimited
+        let ctx = Ctx::default();
+        assert_eq!(true, ctx.can_buffer_response(&new_headers("11")).unwrap());
+
+        // the response without content-length is aborted in both modes
+        for overflow_error in [true, false] {
+            let mut ctx = new_ctx(overflow_error);
+            ctx.add_modify_body_handler("buffer", Box::new(BufferHandler {}));
+            ctx.limit_response_buffer(10).unwrap();
+            let err = ctx.limit_response_buffer(11).unwrap_err();
+            assert_eq!(pingora::ErrorType::HTTPStatus(502), err.etype);
+        }
+
+        // the non buffering handler is not limited
+        let mut ctx = new_ctx(true);
+        struct TestHandler {}
+        impl ModifyResponseBody for TestHandler {
+            fn handle(
+                &mut self,
+                _session: &Session,
+                _body: &mut Option<bytes::Bytes>,
+                _end_of_stream: bool,
+            ) -> pingora::Result<()> {
+                Ok(())
+            }
+        }
+        ctx.add_modify_body_handler("test", Box::new(TestHandler {}));
+        ctx.limit_response_buffer(100).unwrap();
+    }
 }
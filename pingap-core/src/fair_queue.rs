@@ -0,0 +1,286 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ahash::AHashMap;
+use snafu::Snafu;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+#[derive(Debug, Snafu, PartialEq)]
+pub enum FairQueueError {
+    #[snafu(display("too many queued requests of {key}"))]
+    Full { key: String },
+    #[snafu(display("waiting in the queue timeout"))]
+    Timeout,
+}
+
+#[derive(Default)]
+struct FairQueueState {
+    /// The count of requests holding the permit
+    processing: usize,
+    /// The waiting requests of each key
+    waiting: AHashMap<String, VecDeque<oneshot::Sender<FairQueuePermit>>>,
+    /// The keys with waiting requests, in round robin order
+    keys: VecDeque<String>,
+}
+
+impl FairQueueState {
+    /// Pops the waiting request of the next key in round robin order,
+    /// the key is moved to the back if it has more waiting requests.
+    fn pop_waiting(&mut self) -> Option<oneshot::Sender<FairQueuePermit>> {
+        while let Some(key) = self.keys.pop_front() {
+            let Some(waiting) = self.waiting.get_mut(&key) else {
+                continue;
+            };
+            // the closed one has been timeout
+            let mut found = None;
+            while let Some(tx) = waiting.pop_front() {
+                if !tx.is_closed() {
+                    found = Some(tx);
+                    break;
+                }
+            }
+            if waiting.is_empty() {
+                self.waiting.remove(&key);
+            } else {
+                self.keys.push_back(key);
+            }
+            if found.is_some() {
+                return found;
+            }
+        }
+        None
+    }
+}
+
+/// FairQueue limits the concurrent requests, the exceeding requests
+/// are queued per key(e.g. client ip) and the released capacity is
+/// handed over to the keys in round robin, instead of first come first
+/// served, so a client with many queued requests can't monopolize it.
+pub struct FairQueue {
+    capacity: usize,
+    depth: usize,
+    timeout: Duration,
+    state: Mutex<FairQueueState>,
+}
+
+/// The permit of processing request, the capacity is released or
+/// handed over to the next waiting request when it's dropped.
+pub struct FairQueuePermit {
+    queue: Option<Arc<FairQueue>>,
+}
+
+impl Drop for FairQueuePermit {
+    fn drop(&mut self) {
+        if let Some(queue) = self.queue.take() {
+            queue.release();
+        }
+    }
+}
+
+impl FairQueue {
+    /// Creates a new fair queue.
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - The maximum number of concurrent requests
+    /// * `depth` - The maximum number of waiting requests of each key
+    /// * `timeout` - The maximum time of waiting in the queue
+    pub fn new(capacity: usize, depth: usize, timeout: Duration) -> Arc<Self> {
+        Arc::new(Self {
+            capacity,
+            depth,
+            timeout,
+            state: Mutex::new(FairQueueState::default()),
+        })
+    }
+    /// Returns the count of processing and waiting requests.
+    pub fn stats(&self) -> (usize, usize) {
+        let Ok(state) = self.state.lock() else {
+            return (0, 0);
+        };
+        let waiting = state.waiting.values().map(|item| item.len()).sum();
+        (state.processing, waiting)
+    }
+    /// Acquires the permit of processing, it waits in the queue of key
+    /// if the capacity is exhausted.
+    pub async fn acquire(
+        self: &Arc<Self>,
+        key: &str,
+    ) -> Result<FairQueuePermit, FairQueueError> {
+        let rx = {
+            let Ok(mut guard) = self.state.lock() else {
+                return Ok(FairQueuePermit { queue: None });
+            };
+            let state = &mut *guard;
+            // the released capacity is always handed over to the waiting
+            // requests, so there is no valid waiting request if it's free
+            if state.processing < self.capacity {
+                state.processing += 1;
+                return Ok(FairQueuePermit {
+                    queue: Some(self.clone()),
+                });
+            }
+            let waiting = state.waiting.entry(key.to_string()).or_default();
+            waiting.retain(|tx| !tx.is_closed());
+            if waiting.len() >= self.depth {
+                return Err(FairQueueError::Full {
+                    key: key.to_string(),
+                });
+            }
+            let (tx, rx) = oneshot::channel();
+            waiting.push_back(tx);
+            if !state.keys.iter().any(|item| item == key) {
+                state.keys.push_back(key.to_string());
+            }
+            rx
+        };
+        match tokio::time::timeout(self.timeout, rx).await {
+            Ok(Ok(permit)) => Ok(permit),
+            _ => Err(FairQueueError::Timeout),
+        }
+    }
+    /// Hands over the capacity to the next waiting request,
+    /// or releases it if there is no waiting request.
+    fn release(self: &Arc<Self>) {
+        loop {
+            let tx = {
+                let Ok(mut state) = self.state.lock() else {
+                    return;
+                };
+                let Some(tx) = state.pop_waiting() else {
+                    state.processing = state.processing.saturating_sub(1);
+                    return;
+                };
+                tx
+            };
+            // the permit is dropped if the receiver is dropped after
+            // sending, then the capacity is handed over again
+            match tx.send(FairQueuePermit {
+                queue: Some(self.clone()),
+            }) {
+                Ok(()) => return,
+                Err(mut permit) => {
+                    // the waiting request has been timeout, try next
+                    permit.queue = None;
+                },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[tokio::test]
+    async fn test_fair_queue() {
+        let queue = FairQueue::new(2, 2, Duration::from_secs(1));
+        let first = queue.acquire("a").await.unwrap();
+        let second = queue.acquire("a").await.unwrap();
+        assert_eq!((2, 0), queue.stats());
+
+        // the depth of key is limited
+        let mut waiting = vec![];
+        for _ in 0..2 {
+            let queue = queue.clone();
+            waiting.push(tokio::spawn(async move {
+                queue.acquire("a").await.map(|_| ())
+            }));
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!((2, 2), queue.stats());
+        assert_eq!(
+            FairQueueError::Full {
+                key: "a".to_string()
+            },
+            queue.acquire("a").await.err().unwrap()
+        );
+
+        // the released capacity is handed over to the waiting request
+        drop(first);
+        drop(second);
+        for handle in waiting {
+            assert_eq!(Ok(()), handle.await.unwrap());
+        }
+        assert_eq!((0, 0), queue.stats());
+    }
+
+    #[tokio::test]
+    async fn test_fair_queue_timeout() {
+        let queue = FairQueue::new(1, 10, Duration::from_millis(20));
+        let permit = queue.acquire("a").await.unwrap();
+        assert_eq!(
+            FairQueueError::Timeout,
+            queue.acquire("b").await.err().unwrap()
+        );
+        // the timeout request is skipped
+        drop(permit);
+        assert_eq!((0, 0), queue.stats());
+        let _permit = queue.acquire("b").await.unwrap();
+        assert_eq!((1, 0), queue.stats());
+    }
+
+    #[tokio::test]
+    async fn test_fair_queue_round_robin() {
+        let queue = FairQueue::new(2, 100, Duration::from_secs(5));
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        // the heavy client occupies all capacity and queues more requests
+        let mut permits = vec![
+            queue.acquire("heavy").await.unwrap(),
+            queue.acquire("heavy").await.unwrap(),
+        ];
+        let spawn = |key: &'static str| {
+            let queue = queue.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let permit = queue.acquire(key).await.unwrap();
+                let _ = tx.send((key, permit));
+            });
+        };
+        for _ in 0..10 {
+            spawn("heavy");
+        }
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        spawn("light1");
+        spawn("light2");
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!((2, 12), queue.stats());
+
+        // the capacity is shared by the clients in round robin
+        let mut served = vec![];
+        for _ in 0..6 {
+            permits.remove(0);
+            let (key, permit) = rx.recv().await.unwrap();
+            served.push(key);
+            permits.push(permit);
+        }
+        assert_eq!(
+            vec!["heavy", "light1", "light2", "heavy", "heavy", "heavy"],
+            served
+        );
+        // the heavy client doesn't hold more than its share of capacity
+        // while the light clients are waiting
+        assert_eq!(2, queue.stats().0);
+        drop(permits);
+        for _ in 0..6 {
+            let (_, permit) = rx.recv().await.unwrap();
+            drop(permit);
+        }
+        assert_eq!((0, 0), queue.stats());
+    }
+}
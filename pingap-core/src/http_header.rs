@@ -150,6 +150,10 @@ pub static HTTP_HEADER_TRANSFER_CHUNKED: HttpHeader = (
 );
 pub static HTTP_HEADER_NAME_X_REQUEST_ID: HeaderName =
     HeaderName::from_static("x-request-id");
+/// Internal header of the cached response, it marks the response whose
+/// ETag should be generated from the body when it's fully stored in cache.
+pub static HTTP_HEADER_NAME_X_GENERATE_ETAG: HeaderName =
+    HeaderName::from_static("x-pingap-generate-etag");
 
 /// Processes a `HeaderValue` that may contain a special dynamic variable (e.g., `$host`).
 /// It replaces the variable with its corresponding runtime value.
@@ -299,6 +303,14 @@ pub fn get_client_ip(session: &Session) -> String {
     "".to_string()
 }
 
+/// Returns true if the client waits for the `100 Continue` interim
+/// response before sending the request body (`Expect: 100-continue`).
+pub fn is_expect_continue(req_header: &RequestHeader) -> bool {
+    req_header.headers.get(header::EXPECT).is_some_and(|value| {
+        value.as_bytes().eq_ignore_ascii_case(b"100-continue")
+    })
+}
+
 /// A convenient helper to get a header value as a `&str` from a `RequestHeader`.
 pub fn get_req_header_value<'a>(
     req_header: &'a RequestHeader,
@@ -423,6 +435,17 @@ mod tests {
     use pretty_assertions::assert_eq;
     use tokio_test::io::Builder;
 
+    #[test]
+    fn test_is_expect_continue() {
+        let mut req_header =
+            RequestHeader::build("POST", b"/upload", None).unwrap();
+        assert_eq!(false, is_expect_continue(&req_header));
+        req_header
+            .insert_header(header::EXPECT, "100-Continue")
+            .unwrap();
+        assert_eq!(true, is_expect_continue(&req_header));
+    }
+
     #[test]
     fn test_convert_headers() {
         let headers = convert_headers(&[
@@ -13,10 +13,10 @@
 // limitations under the License.
 
 use super::{
-    HTTP_HEADER_CONTENT_HTML, HTTP_HEADER_CONTENT_JSON,
+    Ctx, HTTP_HEADER_CONTENT_HTML, HTTP_HEADER_CONTENT_JSON,
     HTTP_HEADER_CONTENT_TEXT, HTTP_HEADER_NO_CACHE, HTTP_HEADER_NO_STORE,
     HTTP_HEADER_TRANSFER_CHUNKED, HttpHeader, LOG_TARGET, get_super_ts,
-    new_internal_error,
+    is_expect_continue, new_internal_error,
 };
 use bytes::{Bytes, BytesMut};
 use http::StatusCode;
@@ -317,6 +317,24 @@ impl HttpResponse {
     }
 }
 
+/// Sends the `100 Continue` interim response to the client if it is expected,
+/// it should be called before reading the request body in the request phase,
+/// otherwise the client waits for the response before sending the body.
+pub async fn write_continue_response(
+    session: &mut Session,
+    ctx: &mut Ctx,
+) -> pingora::Result<()> {
+    if ctx.state.continue_sent || !is_expect_continue(session.req_header()) {
+        return Ok(());
+    }
+    let header = ResponseHeader::build(StatusCode::CONTINUE, Some(0))?;
+    session
+        .write_response_header(Box::new(header), false)
+        .await?;
+    ctx.state.continue_sent = true;
+    Ok(())
+}
+
 /// Represents a chunked HTTP response for streaming large bodies of data.
 ///
 /// This is used when the response body is too large to fit in memory or is generated on-the-fly.
@@ -680,4 +698,54 @@ mod tests {
             "gzip"
         );
     }
+
+    #[tokio::test]
+    async fn test_write_continue_response() {
+        use pingora::protocols::l4::stream::Stream;
+        use tokio::io::AsyncWriteExt;
+        use tokio::net::{TcpListener, TcpStream};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = tokio::spawn(async move {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream
+                .write_all(b"POST /upload HTTP/1.1\r\nHost: pingap.io\r\nExpect: 100-continue\r\nContent-Length: 10\r\n\r\n")
+                .await
+                .unwrap();
+            // the body is sent only after the interim response
+            let mut buf = vec![0; 1024];
+            let size = stream.read(&mut buf).await.unwrap();
+            let resp = String::from_utf8_lossy(&buf[..size]).to_string();
+            for chunk in [b"01234".as_slice(), b"56789".as_slice()] {
+                stream.write_all(chunk).await.unwrap();
+                stream.flush().await.unwrap();
+            }
+            resp
+        });
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut session = Session::new_h1(Box::new(Stream::from(stream)));
+        session.read_request().await.unwrap();
+        assert_eq!(true, is_expect_continue(session.req_header()));
+
+        let mut ctx = Ctx::default();
+        write_continue_response(&mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, ctx.state.continue_sent);
+        // the interim response is only sent once
+        write_continue_response(&mut session, &mut ctx)
+            .await
+            .unwrap();
+
+        let mut body = BytesMut::new();
+        while let Some(chunk) = session.read_request_body().await.unwrap() {
+            body.extend_from_slice(&chunk);
+        }
+        assert_eq!(b"0123456789", body.as_ref());
+
+        let resp = client.await.unwrap();
+        assert_eq!(true, resp.starts_with("HTTP/1.1 100 Continue\r\n"));
+    }
 }
@@ -37,22 +37,30 @@ pub fn new_internal_error(
 }
 
 mod ctx;
+mod fair_queue;
 mod http_header;
 mod http_response;
 mod notification;
 mod plugin;
+mod proxy_protocol;
 mod service;
+mod socket;
+mod throttle;
 mod ttl_lru_limit;
 mod util;
 
 pub use ctx::*;
+pub use fair_queue::*;
 pub use http_header::*;
 pub use http_response::*;
 pub use notification::*;
 pub use pingora_limits::inflight::*;
 pub use pingora_limits::rate::*;
 pub use plugin::*;
+pub use proxy_protocol::*;
 pub use service::*;
+pub use socket::*;
+pub use throttle::*;
 pub use tinyufo::TinyUfo;
 pub use ttl_lru_limit::*;
 pub use util::*;
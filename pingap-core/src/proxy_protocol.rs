@@ -0,0 +1,817 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::Error;
+use pingora::protocols::Peek;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Signature of PROXY protocol v2 header
+const V2_SIGNATURE: [u8; 12] = [
+    0x0D, 0x0A, 0x0D, 0x0A, 0x00, 0x0D, 0x0A, 0x51, 0x55, 0x49, 0x54, 0x0A,
+];
+/// Prefix of PROXY protocol v1 header
+const V1_PREFIX: &[u8] = b"PROXY ";
+/// Max length of PROXY protocol v1 header, including the CRLF
+const V1_MAX_LENGTH: usize = 107;
+/// Timeout of writing the header to upstream connection
+#[cfg(unix)]
+const WRITE_HEADER_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Version of PROXY protocol
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProxyProtocolVersion {
+    V1,
+    V2,
+}
+
+impl FromStr for ProxyProtocolVersion {
+    type Err = Error;
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "v1" => Ok(Self::V1),
+            "v2" => Ok(Self::V2),
+            _ => Err(Error::Invalid {
+                message: format!("proxy protocol version({value}) is invalid"),
+            }),
+        }
+    }
+}
+
+/// PROXY protocol header, it's used to preserve the client address
+/// through the L4 load balancers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProxyProtocolHeader {
+    pub version: ProxyProtocolVersion,
+    /// The (source, destination) addresses, `None` for the
+    /// `UNKNOWN`(v1) or `LOCAL`(v2) connection.
+    pub addresses: Option<(SocketAddr, SocketAddr)>,
+}
+
+fn new_invalid_error(message: &str) -> Error {
+    Error::Invalid {
+        message: format!("proxy protocol {message}"),
+    }
+}
+
+fn parse_v1(buf: &[u8]) -> Result<Option<(ProxyProtocolHeader, usize)>> {
+    let Some(end) = buf.windows(2).position(|item| item == b"\r\n") else {
+        if buf.len() >= V1_MAX_LENGTH {
+            return Err(new_invalid_error("v1 header is too long"));
+        }
+        return Ok(None);
+    };
+    let line = std::str::from_utf8(&buf[..end])
+        .map_err(|_| new_invalid_error("v1 header is not utf8"))?;
+    let fields: Vec<&str> = line.split(' ').collect();
+    let addresses = match fields.get(1).copied() {
+        Some("UNKNOWN") => None,
+        Some("TCP4") | Some("TCP6") if fields.len() == 6 => {
+            let parse_addr = |ip: &str, port: &str| -> Result<SocketAddr> {
+                let ip = IpAddr::from_str(ip)
+                    .map_err(|_| new_invalid_error("v1 address is invalid"))?;
+                let port = port
+                    .parse::<u16>()
+                    .map_err(|_| new_invalid_error("v1 port is invalid"))?;
+                Ok(SocketAddr::new(ip, port))
+            };
+            Some((
+                parse_addr(fields[2], fields[4])?,
+                parse_addr(fields[3], fields[5])?,
+            ))
+        },
+        _ => return Err(new_invalid_error("v1 protocol is invalid")),
+    };
+    Ok(Some((
+        ProxyProtocolHeader {
+            version: ProxyProtocolVersion::V1,
+            addresses,
+        },
+        end + 2,
+    )))
+}
+
+fn parse_v2(buf: &[u8]) -> Result<Option<(ProxyProtocolHeader, usize)>> {
+    if buf.len() < 16 {
+        return Ok(None);
+    }
+    let version_command = buf[12];
+    if version_command >> 4 != 2 {
+        return Err(new_invalid_error("v2 version is invalid"));
+    }
+    let length = u16::from_be_bytes([buf[14], buf[15]]) as usize;
+    let total = 16 + length;
+    if buf.len() < total {
+        return Ok(None);
+    }
+    let data = &buf[16..total];
+    // LOCAL command, the connection is established by the proxy itself
+    if version_command & 0x0F == 0 {
+        return Ok(Some((
+            ProxyProtocolHeader {
+                version: ProxyProtocolVersion::V2,
+                addresses: None,
+            },
+            total,
+        )));
+    }
+    let addresses = match buf[13] >> 4 {
+        // AF_INET
+        1 if data.len() >= 12 => {
+            let source = Ipv4Addr::new(data[0], data[1], data[2], data[3]);
+            let destination = Ipv4Addr::new(data[4], data[5], data[6], data[7]);
+            Some((
+                SocketAddr::new(
+                    source.into(),
+                    u16::from_be_bytes([data[8], data[9]]),
+                ),
+                SocketAddr::new(
+                    destination.into(),
+                    u16::from_be_bytes([data[10], data[11]]),
+                ),
+            ))
+        },
+        // AF_INET6
+        2 if data.len() >= 36 => {
+            let mut source = [0; 16];
+            source.copy_from_slice(&data[..16]);
+            let mut destination = [0; 16];
+            destination.copy_from_slice(&data[16..32]);
+            Some((
+                SocketAddr::new(
+                    Ipv6Addr::from(source).into(),
+                    u16::from_be_bytes([data[32], data[33]]),
+                ),
+                SocketAddr::new(
+                    Ipv6Addr::from(destination).into(),
+                    u16::from_be_bytes([data[34], data[35]]),
+                ),
+            ))
+        },
+        // AF_UNSPEC or unix socket, the address is ignored
+        0 | 3 => None,
+        _ => return Err(new_invalid_error("v2 address is invalid")),
+    };
+    Ok(Some((
+        ProxyProtocolHeader {
+            version: ProxyProtocolVersion::V2,
+            addresses,
+        },
+        total,
+    )))
+}
+
+impl ProxyProtocolHeader {
+    /// Creates a PROXY protocol header of the addresses
+    pub fn new(
+        version: ProxyProtocolVersion,
+        source: SocketAddr,
+        destination: SocketAddr,
+    ) -> Self {
+        Self {
+            version,
+            addresses: Some((source, destination)),
+        }
+    }
+
+    /// Parses the PROXY protocol header (v1 or v2) from the beginning
+    /// of the data.
+    ///
+    /// Returns the header and its length, `Ok(None)` if the data is
+    /// not complete, or an error if the data is not a valid header.
+    pub fn parse(buf: &[u8]) -> Result<Option<(Self, usize)>> {
+        if buf.starts_with(&V2_SIGNATURE) {
+            return parse_v2(buf);
+        }
+        if buf.starts_with(V1_PREFIX) {
+            return parse_v1(buf);
+        }
+        let size = buf.len();
+        if (size < V2_SIGNATURE.len() && V2_SIGNATURE.starts_with(buf))
+            || (size < V1_PREFIX.len() && V1_PREFIX.starts_with(buf))
+        {
+            return Ok(None);
+        }
+        Err(new_invalid_error("header is not found"))
+    }
+
+    /// Parses the PROXY protocol header only if the connection is from
+    /// a trusted source(e.g. the L4 load balancer), otherwise the header
+    /// is rejected to avoid the client address being spoofed.
+    pub fn parse_trusted(
+        buf: &[u8],
+        trusted: bool,
+    ) -> Result<Option<(Self, usize)>> {
+        if !trusted {
+            return Err(new_invalid_error("source is not trusted"));
+        }
+        Self::parse(buf)
+    }
+
+    /// Returns the source(client) address of the header
+    pub fn source(&self) -> Option<SocketAddr> {
+        self.addresses.map(|(source, _)| source)
+    }
+
+    /// Encodes the header to bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self.version {
+            ProxyProtocolVersion::V1 => self.to_v1_bytes(),
+            ProxyProtocolVersion::V2 => self.to_v2_bytes(),
+        }
+    }
+
+    fn to_v1_bytes(&self) -> Vec<u8> {
+        let line = match self.addresses {
+            Some((source, destination))
+                if source.is_ipv4() == destination.is_ipv4() =>
+            {
+                let protocol = if source.is_ipv4() { "TCP4" } else { "TCP6" };
+                format!(
+                    "PROXY {protocol} {} {} {} {}\r\n",
+                    source.ip(),
+                    destination.ip(),
+                    source.port(),
+                    destination.port()
+                )
+            },
+            _ => "PROXY UNKNOWN\r\n".to_string(),
+        };
+        line.into_bytes()
+    }
+
+    fn to_v2_bytes(&self) -> Vec<u8> {
+        let mut buf = V2_SIGNATURE.to_vec();
+        let push_ports = |buf: &mut Vec<u8>, source: u16, destination: u16| {
+            buf.extend_from_slice(&source.to_be_bytes());
+            buf.extend_from_slice(&destination.to_be_bytes());
+        };
+        match self.addresses {
+            Some((SocketAddr::V4(source), SocketAddr::V4(destination))) => {
+                // PROXY command, TCP over IPv4
+                buf.extend_from_slice(&[0x21, 0x11]);
+                buf.extend_from_slice(&12u16.to_be_bytes());
+                buf.extend_from_slice(&source.ip().octets());
+                buf.extend_from_slice(&destination.ip().octets());
+                push_ports(&mut buf, source.port(), destination.port());
+            },
+            Some((SocketAddr::V6(source), SocketAddr::V6(destination))) => {
+                // PROXY command, TCP over IPv6
+                buf.extend_from_slice(&[0x21, 0x21]);
+                buf.extend_from_slice(&36u16.to_be_bytes());
+                buf.extend_from_slice(&source.ip().octets());
+                buf.extend_from_slice(&destination.ip().octets());
+                push_ports(&mut buf, source.port(), destination.port());
+            },
+            _ => {
+                // LOCAL command, AF_UNSPEC
+                buf.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+            },
+        }
+        buf
+    }
+
+    /// Writes the header to the socket of the file descriptor, it should be
+    /// called before any data is sent on the connection. The socket is
+    /// non-blocking, so the header is written until it's fully sent.
+    #[cfg(unix)]
+    pub async fn write_to_fd(
+        &self,
+        fd: std::os::unix::io::RawFd,
+    ) -> io::Result<()> {
+        use std::os::unix::io::BorrowedFd;
+        let buf = self.to_bytes();
+        let deadline = tokio::time::Instant::now() + WRITE_HEADER_TIMEOUT;
+        let mut written = 0;
+        while written < buf.len() {
+            // SAFETY: the fd is owned by the connection and outlives the borrow
+            let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+            let result = socket2::SockRef::from(&fd).send(&buf[written..]);
+            match result {
+                Ok(0) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WriteZero,
+                        "write proxy protocol header fail",
+                    ));
+                },
+                Ok(size) => written += size,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {},
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if tokio::time::Instant::now() >= deadline {
+                        return Err(io::Error::new(
+                            io::ErrorKind::TimedOut,
+                            "write proxy protocol header timeout",
+                        ));
+                    }
+                    // the send buffer is full, wait for it to be drained
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                },
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub async fn write_to_fd(&self, _fd: i32) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Requirement of PROXY protocol header on the accepted connections
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ProxyProtocolMode {
+    /// The header is not parsed
+    #[default]
+    Off,
+    /// The header is parsed if it's present
+    Optional,
+    /// The connection without header is rejected, it's used when the
+    /// listener is only reachable through the L4 load balancer, so the
+    /// client address can't be bypassed by direct connection.
+    Required,
+}
+
+impl FromStr for ProxyProtocolMode {
+    type Err = Error;
+    fn from_str(value: &str) -> Result<Self> {
+        match value.to_lowercase().as_str() {
+            "" | "off" => Ok(Self::Off),
+            "optional" => Ok(Self::Optional),
+            "required" => Ok(Self::Required),
+            _ => Err(Error::Invalid {
+                message: format!("proxy protocol mode({value}) is invalid"),
+            }),
+        }
+    }
+}
+
+/// Reads the PROXY protocol header from the beginning of the accepted
+/// connection according to the mode, it should be called before the
+/// application protocol(http) is read.
+///
+/// The beginning of the stream is peeked to detect the header, and only the
+/// bytes of header are consumed, so the data of application protocol is
+/// kept in the stream. An error is returned if the header is required but
+/// missing, or the header is invalid or sent by the untrusted source, the
+/// connection should be closed.
+pub async fn read_proxy_protocol<S>(
+    stream: &mut S,
+    mode: ProxyProtocolMode,
+    trusted: bool,
+    timeout: Duration,
+) -> Result<Option<ProxyProtocolHeader>>
+where
+    S: AsyncRead + Peek + Unpin + Send + ?Sized,
+{
+    if mode == ProxyProtocolMode::Off {
+        return Ok(None);
+    }
+    let deadline = tokio::time::Instant::now() + timeout;
+    // the signature of v2 is the longest prefix to detect the header
+    let mut buf = vec![0; 16];
+    let peeked = match tokio::time::timeout_at(
+        deadline,
+        stream.try_peek(&mut buf),
+    )
+    .await
+    {
+        Ok(result) => result.map_err(|e| {
+            new_invalid_error(&format!("read header fail, {e}"))
+        })?,
+        // nothing is consumed, the connection is accepted without header
+        Err(_) if mode == ProxyProtocolMode::Optional => return Ok(None),
+        Err(_) => return Err(new_invalid_error("read header timeout")),
+    };
+    if !peeked {
+        return Err(new_invalid_error("peek is not supported"));
+    }
+    if !buf.starts_with(&V2_SIGNATURE) && !buf.starts_with(V1_PREFIX) {
+        if mode == ProxyProtocolMode::Required {
+            return Err(new_invalid_error("header is not found"));
+        }
+        return Ok(None);
+    }
+
+    let read_error = |e: io::Error| {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            new_invalid_error("header is not complete")
+        } else {
+            new_invalid_error(&format!("read header fail, {e}"))
+        }
+    };
+    let read = async {
+        if buf.starts_with(&V2_SIGNATURE) {
+            let length = u16::from_be_bytes([buf[14], buf[15]]) as usize;
+            buf.resize(16 + length, 0);
+            stream.read_exact(&mut buf).await.map_err(read_error)?;
+        } else {
+            // the v1 header is read byte by byte until the CRLF,
+            // the data after it belongs to the application protocol
+            buf.clear();
+            let mut byte = [0; 1];
+            while !buf.ends_with(b"\r\n") {
+                if buf.len() >= V1_MAX_LENGTH {
+                    return Err(new_invalid_error("v1 header is too long"));
+                }
+                stream.read_exact(&mut byte).await.map_err(read_error)?;
+                buf.push(byte[0]);
+            }
+        }
+        Ok(())
+    };
+    // the header is partially consumed, so it's rejected in any mode
+    tokio::time::timeout_at(deadline, read)
+        .await
+        .map_err(|_| new_invalid_error("read header timeout"))??;
+    match ProxyProtocolHeader::parse_trusted(&buf, trusted)? {
+        Some((header, _)) => Ok(Some(header)),
+        None => Err(new_invalid_error("header is not complete")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_proxy_protocol_version() {
+        assert_eq!(
+            ProxyProtocolVersion::V1,
+            ProxyProtocolVersion::from_str("v1").unwrap()
+        );
+        assert_eq!(
+            ProxyProtocolVersion::V2,
+            ProxyProtocolVersion::from_str("V2").unwrap()
+        );
+        assert_eq!(
+            "invalid error, proxy protocol version(v3) is invalid",
+            ProxyProtocolVersion::from_str("v3")
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_parse_proxy_protocol_v1() {
+        let data = b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\nGET / HTTP/1.1\r\n";
+        let (header, size) = ProxyProtocolHeader::parse(data).unwrap().unwrap();
+        assert_eq!(48, size);
+        assert_eq!(ProxyProtocolVersion::V1, header.version);
+        assert_eq!(
+            Some((
+                "192.168.0.1:56324".parse().unwrap(),
+                "192.168.0.11:443".parse().unwrap()
+            )),
+            header.addresses
+        );
+        assert_eq!(b"GET / HTTP/1.1\r\n", &data[size..]);
+
+        let data = b"PROXY TCP6 ::1 ::2 1000 80\r\n";
+        let (header, _) = ProxyProtocolHeader::parse(data).unwrap().unwrap();
+        assert_eq!("[::1]:1000", header.source().unwrap().to_string());
+
+        let (header, size) = ProxyProtocolHeader::parse(b"PROXY UNKNOWN\r\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(15, size);
+        assert_eq!(None, header.addresses);
+
+        // incomplete
+        assert_eq!(
+            None,
+            ProxyProtocolHeader::parse(b"PROXY TCP4 192.168").unwrap()
+        );
+        assert_eq!(None, ProxyProtocolHeader::parse(b"PRO").unwrap());
+        // invalid
+        assert_eq!(
+            "invalid error, proxy protocol v1 address is invalid",
+            ProxyProtocolHeader::parse(b"PROXY TCP4 a b 1 2\r\n")
+                .unwrap_err()
+                .to_string()
+        );
+        assert_eq!(
+            "invalid error, proxy protocol header is not found",
+            ProxyProtocolHeader::parse(b"GET / HTTP/1.1\r\n")
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_parse_proxy_protocol_v2() {
+        let mut data = V2_SIGNATURE.to_vec();
+        data.extend_from_slice(&[0x21, 0x11, 0x00, 0x0C]);
+        data.extend_from_slice(&[192, 168, 0, 1, 192, 168, 0, 11]);
+        data.extend_from_slice(&56324u16.to_be_bytes());
+        data.extend_from_slice(&443u16.to_be_bytes());
+        data.extend_from_slice(b"GET /");
+
+        let (header, size) =
+            ProxyProtocolHeader::parse(&data).unwrap().unwrap();
+        assert_eq!(28, size);
+        assert_eq!(ProxyProtocolVersion::V2, header.version);
+        assert_eq!(
+            Some((
+                "192.168.0.1:56324".parse().unwrap(),
+                "192.168.0.11:443".parse().unwrap()
+            )),
+            header.addresses
+        );
+
+        // incomplete
+        assert_eq!(None, ProxyProtocolHeader::parse(&data[..20]).unwrap());
+
+        // local command
+        let mut data = V2_SIGNATURE.to_vec();
+        data.extend_from_slice(&[0x20, 0x00, 0x00, 0x00]);
+        let (header, size) =
+            ProxyProtocolHeader::parse(&data).unwrap().unwrap();
+        assert_eq!(16, size);
+        assert_eq!(None, header.addresses);
+    }
+
+    #[test]
+    fn test_parse_trusted_proxy_protocol() {
+        let data = b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n";
+        assert_eq!(
+            true,
+            ProxyProtocolHeader::parse_trusted(data, true)
+                .unwrap()
+                .is_some()
+        );
+        assert_eq!(
+            "invalid error, proxy protocol source is not trusted",
+            ProxyProtocolHeader::parse_trusted(data, false)
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn test_encode_proxy_protocol() {
+        for version in [ProxyProtocolVersion::V1, ProxyProtocolVersion::V2] {
+            for (source, destination) in [
+                ("192.168.0.1:56324", "192.168.0.11:443"),
+                ("[2001:db8::1]:56324", "[2001:db8::2]:443"),
+            ] {
+                let header = ProxyProtocolHeader::new(
+                    version,
+                    source.parse().unwrap(),
+                    destination.parse().unwrap(),
+                );
+                let buf = header.to_bytes();
+                let (parsed, size) =
+                    ProxyProtocolHeader::parse(&buf).unwrap().unwrap();
+                assert_eq!(buf.len(), size);
+                assert_eq!(header, parsed);
+            }
+        }
+        assert_eq!(
+            b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 443\r\n".to_vec(),
+            ProxyProtocolHeader::new(
+                ProxyProtocolVersion::V1,
+                "192.168.0.1:56324".parse().unwrap(),
+                "192.168.0.11:443".parse().unwrap(),
+            )
+            .to_bytes()
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn test_write_proxy_protocol_to_fd() {
+        use std::io::{Read, Write};
+        use std::net::{TcpListener, TcpStream};
+        use std::os::unix::io::AsRawFd;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut stream =
+            TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut upstream, _) = listener.accept().unwrap();
+
+        let header = ProxyProtocolHeader::new(
+            ProxyProtocolVersion::V2,
+            "192.168.0.1:56324".parse().unwrap(),
+            "192.168.0.11:443".parse().unwrap(),
+        );
+        header.write_to_fd(stream.as_raw_fd()).await.unwrap();
+
+        let mut buf = vec![0; 28];
+        upstream.read_exact(&mut buf).unwrap();
+        let (parsed, _) = ProxyProtocolHeader::parse(&buf).unwrap().unwrap();
+        assert_eq!(header, parsed);
+
+        // the header is written after the full send buffer is drained
+        stream.set_nonblocking(true).unwrap();
+        let chunk = vec![0; 64 * 1024];
+        let mut size = 0;
+        while let Ok(written) = stream.write(&chunk) {
+            size += written;
+        }
+        let reader = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            let mut data = vec![];
+            upstream.read_to_end(&mut data).unwrap();
+            data
+        });
+        header.write_to_fd(stream.as_raw_fd()).await.unwrap();
+        drop(stream);
+        let data = reader.join().unwrap();
+        assert_eq!(size + 28, data.len());
+        assert_eq!(header.to_bytes(), data[size..].to_vec());
+    }
+
+    #[test]
+    fn test_proxy_protocol_mode() {
+        assert_eq!(
+            ProxyProtocolMode::Off,
+            ProxyProtocolMode::from_str("").unwrap()
+        );
+        assert_eq!(
+            ProxyProtocolMode::Required,
+            ProxyProtocolMode::from_str("Required").unwrap()
+        );
+        assert_eq!(
+            "invalid error, proxy protocol mode(always) is invalid",
+            ProxyProtocolMode::from_str("always")
+                .unwrap_err()
+                .to_string()
+        );
+    }
+
+    /// Creates the accepted stream of the connection which has sent the data,
+    /// the client is returned to keep the connection open.
+    async fn new_accepted_stream(
+        data: &[u8],
+    ) -> (
+        pingora::protocols::l4::stream::Stream,
+        tokio::net::TcpStream,
+    ) {
+        use tokio::io::AsyncWriteExt;
+        use tokio::net::{TcpListener, TcpStream};
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        client.write_all(data).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+        (stream.into(), client)
+    }
+
+    async fn read_data(
+        stream: &mut pingora::protocols::l4::stream::Stream,
+        size: usize,
+    ) -> Vec<u8> {
+        let mut buf = vec![0; size];
+        stream.read_exact(&mut buf).await.unwrap();
+        buf
+    }
+
+    #[tokio::test]
+    async fn test_read_proxy_protocol() {
+        let timeout = Duration::from_secs(1);
+        let request = b"GET / HTTP/1.1\r\nHost: pingap.io\r\n\r\n";
+        // the connection with header is accepted, only the header is consumed
+        for version in [ProxyProtocolVersion::V1, ProxyProtocolVersion::V2] {
+            let header = ProxyProtocolHeader::new(
+                version,
+                "192.168.1.1:3000".parse().unwrap(),
+                "10.0.0.1:80".parse().unwrap(),
+            );
+            for mode in
+                [ProxyProtocolMode::Required, ProxyProtocolMode::Optional]
+            {
+                let mut data = header.to_bytes();
+                data.extend_from_slice(request);
+                let (mut stream, _client) = new_accepted_stream(&data).await;
+                let parsed =
+                    read_proxy_protocol(&mut stream, mode, true, timeout)
+                        .await
+                        .unwrap();
+                assert_eq!(Some(header.clone()), parsed);
+                assert_eq!(
+                    request.to_vec(),
+                    read_data(&mut stream, request.len()).await
+                );
+            }
+        }
+
+        // the connection without header is rejected if it's required
+        let (mut stream, _client) = new_accepted_stream(request).await;
+        let result = read_proxy_protocol(
+            &mut stream,
+            ProxyProtocolMode::Required,
+            true,
+            timeout,
+        )
+        .await;
+        assert_eq!(
+            "invalid error, proxy protocol header is not found",
+            result.unwrap_err().to_string()
+        );
+        let (mut stream, _client) = new_accepted_stream(b"").await;
+        let result = read_proxy_protocol(
+            &mut stream,
+            ProxyProtocolMode::Required,
+            true,
+            Duration::from_millis(10),
+        )
+        .await;
+        assert_eq!(
+            "invalid error, proxy protocol read header timeout",
+            result.unwrap_err().to_string()
+        );
+        let (mut stream, client) =
+            new_accepted_stream(b"PROXY TCP4 192.168.0.1").await;
+        drop(client);
+        let result = read_proxy_protocol(
+            &mut stream,
+            ProxyProtocolMode::Optional,
+            true,
+            timeout,
+        )
+        .await;
+        assert_eq!(
+            "invalid error, proxy protocol header is not complete",
+            result.unwrap_err().to_string()
+        );
+
+        // the connection without header is accepted if it's optional,
+        // nothing is consumed
+        let (mut stream, _client) = new_accepted_stream(request).await;
+        let parsed = read_proxy_protocol(
+            &mut stream,
+            ProxyProtocolMode::Optional,
+            true,
+            timeout,
+        )
+        .await
+        .unwrap();
+        assert_eq!(None, parsed);
+        assert_eq!(
+            request.to_vec(),
+            read_data(&mut stream, request.len()).await
+        );
+
+        // the header of untrusted source is rejected, the connection
+        // without header is still accepted if it's optional
+        let mut data = b"PROXY UNKNOWN\r\n".to_vec();
+        data.extend_from_slice(request);
+        let (mut stream, _client) = new_accepted_stream(&data).await;
+        let result = read_proxy_protocol(
+            &mut stream,
+            ProxyProtocolMode::Optional,
+            false,
+            timeout,
+        )
+        .await;
+        assert_eq!(
+            "invalid error, proxy protocol source is not trusted",
+            result.unwrap_err().to_string()
+        );
+        let (mut stream, _client) = new_accepted_stream(request).await;
+        let parsed = read_proxy_protocol(
+            &mut stream,
+            ProxyProtocolMode::Optional,
+            false,
+            timeout,
+        )
+        .await
+        .unwrap();
+        assert_eq!(None, parsed);
+
+        // the header is not read if it's off
+        let mut data = b"PROXY UNKNOWN\r\n".to_vec();
+        data.extend_from_slice(request);
+        let (mut stream, _client) = new_accepted_stream(&data).await;
+        let parsed = read_proxy_protocol(
+            &mut stream,
+            ProxyProtocolMode::Off,
+            true,
+            timeout,
+        )
+        .await
+        .unwrap();
+        assert_eq!(None, parsed);
+        assert_eq!(data, read_data(&mut stream, data.len()).await);
+    }
+}
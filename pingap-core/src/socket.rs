@@ -0,0 +1,134 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::io;
+
+/// Low level socket options applied to established tcp connections.
+///
+/// Pingora enables `TCP_NODELAY` for every connection, so `None` keeps the
+/// current behavior and only explicitly configured options are changed.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SocketOptions {
+    /// Whether to disable Nagle's algorithm (TCP_NODELAY)
+    pub tcp_nodelay: Option<bool>,
+    /// DSCP value(0-63) used to mark the IP packets, written into
+    /// the upper six bits of the TOS(ipv4) or traffic class(ipv6) field
+    pub dscp: Option<u8>,
+}
+
+impl SocketOptions {
+    /// Returns true if no option is configured
+    pub fn is_empty(&self) -> bool {
+        self.tcp_nodelay.is_none() && self.dscp.is_none()
+    }
+
+    /// Applies the configured options to the socket of the file descriptor.
+    #[cfg(unix)]
+    pub fn apply(&self, fd: std::os::unix::io::RawFd) -> io::Result<()> {
+        use std::os::unix::io::BorrowedFd;
+        if self.is_empty() {
+            return Ok(());
+        }
+        // SAFETY: the fd is owned by the connection and outlives the borrow
+        let fd = unsafe { BorrowedFd::borrow_raw(fd) };
+        let socket = socket2::SockRef::from(&fd);
+        if let Some(nodelay) = self.tcp_nodelay {
+            socket.set_tcp_nodelay(nodelay)?;
+        }
+        if let Some(dscp) = self.dscp {
+            // the out of range value is rejected by config validation,
+            // it isn't clamped to another class silently
+            if dscp > 63 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("dscp({dscp}) should be <= 63"),
+                ));
+            }
+            let tos = (dscp as u32) << 2;
+            let is_ipv6 = socket
+                .local_addr()
+                .map(|addr| addr.is_ipv6())
+                .unwrap_or_default();
+            if is_ipv6 {
+                socket.set_tclass_v6(tos)?;
+            } else {
+                socket.set_tos_v4(tos)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    pub fn apply(&self, _fd: i32) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_socket_options_is_empty() {
+        assert_eq!(true, SocketOptions::default().is_empty());
+        assert_eq!(
+            false,
+            SocketOptions {
+                dscp: Some(46),
+                ..Default::default()
+            }
+            .is_empty()
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_socket_options_apply() {
+        use std::net::{TcpListener, TcpStream};
+        use std::os::unix::io::AsRawFd;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream =
+            TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+
+        let opts = SocketOptions {
+            tcp_nodelay: Some(false),
+            dscp: Some(46),
+        };
+        opts.apply(stream.as_raw_fd()).unwrap();
+        let socket = socket2::SockRef::from(&stream);
+        assert_eq!(false, socket.tcp_nodelay().unwrap());
+        assert_eq!(46 << 2, socket.tos_v4().unwrap());
+
+        let opts = SocketOptions {
+            tcp_nodelay: Some(true),
+            dscp: None,
+        };
+        opts.apply(stream.as_raw_fd()).unwrap();
+        assert_eq!(true, socket.tcp_nodelay().unwrap());
+        // dscp is not changed
+        assert_eq!(46 << 2, socket.tos_v4().unwrap());
+
+        let opts = SocketOptions {
+            tcp_nodelay: None,
+            dscp: Some(64),
+        };
+        assert_eq!(
+            "dscp(64) should be <= 63",
+            opts.apply(stream.as_raw_fd()).unwrap_err().to_string()
+        );
+        assert_eq!(46 << 2, socket.tos_v4().unwrap());
+    }
+}
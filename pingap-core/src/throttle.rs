@@ -0,0 +1,190 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::time::{Duration, Instant};
+
+/// Throttles the response body written to the client at a fixed byte rate.
+///
+/// The body is not buffered, after each chunk is sent the delay before
+/// the next chunk is calculated from the bytes sent and the elapsed time.
+#[derive(Debug, Clone)]
+pub struct BandwidthThrottle {
+    /// Bytes per second, zero means unlimited
+    rate: u64,
+    /// The first bytes sent without throttling
+    after: u64,
+    /// Total bytes sent
+    sent: u64,
+    /// The time when throttling started
+    started_at: Option<Instant>,
+}
+
+impl BandwidthThrottle {
+    /// Creates a new throttle with the rate(bytes per second),
+    /// the first `after` bytes are not throttled.
+    pub fn new(rate: u64, after: u64) -> Self {
+        Self {
+            rate,
+            after,
+            sent: 0,
+            started_at: None,
+        }
+    }
+
+    /// Records the chunk sent at `now`, returns the delay before
+    /// the next chunk can be sent.
+    pub fn on_sent(&mut self, size: usize, now: Instant) -> Option<Duration> {
+        self.sent += size as u64;
+        if self.rate == 0 || self.sent <= self.after {
+            return None;
+        }
+        let started_at = *self.started_at.get_or_insert(now);
+        let expected = Duration::from_secs_f64(
+            (self.sent - self.after) as f64 / self.rate as f64,
+        );
+        let delay =
+            expected.saturating_sub(now.saturating_duration_since(started_at));
+        if delay.is_zero() { None } else { Some(delay) }
+    }
+}
+
+/// Requires the minimum throughput of request body uploaded by the client.
+///
+/// The received bytes are counted in each window, the upload is too slow
+/// if the bytes received in the passed window are less than the rate.
+#[derive(Debug, Clone)]
+pub struct MinUploadRate {
+    /// Minimum bytes per second
+    rate: u64,
+    /// The window of checking the throughput
+    window: Duration,
+    /// Bytes received in the current window
+    received: u64,
+    /// The start time of the current window
+    window_started_at: Instant,
+}
+
+impl MinUploadRate {
+    /// Creates a new minimum upload rate(bytes per second) checked
+    /// over the window, the first window starts at `now`.
+    pub fn new(rate: u64, window: Duration, now: Instant) -> Self {
+        Self {
+            rate,
+            window,
+            received: 0,
+            window_started_at: now,
+        }
+    }
+
+    /// Records the chunk received at `now`, returns true if the upload
+    /// is slower than the minimum rate in the passed window.
+    pub fn on_received(&mut self, size: usize, now: Instant) -> bool {
+        self.received += size as u64;
+        let elapsed = now.saturating_duration_since(self.window_started_at);
+        if self.window.is_zero() || elapsed < self.window {
+            return false;
+        }
+        if (self.received as f64) < self.rate as f64 * elapsed.as_secs_f64() {
+            return true;
+        }
+        self.received = 0;
+        self.window_started_at = now;
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_bandwidth_throttle() {
+        let mut throttle = BandwidthThrottle::new(1024, 2048);
+        let now = Instant::now();
+        // not throttled
+        assert_eq!(None, throttle.on_sent(1024, now));
+        assert_eq!(None, throttle.on_sent(1024, now));
+        // throttled after 2048 bytes
+        assert_eq!(
+            Some(Duration::from_millis(500)),
+            throttle.on_sent(512, now)
+        );
+        // the elapsed time is deducted
+        assert_eq!(
+            Some(Duration::from_millis(500)),
+            throttle.on_sent(512, now + Duration::from_millis(500))
+        );
+        assert_eq!(None, throttle.on_sent(512, now + Duration::from_secs(2)));
+
+        let mut throttle = BandwidthThrottle::new(0, 0);
+        assert_eq!(None, throttle.on_sent(1024 * 1024, now));
+    }
+
+    #[test]
+    fn test_min_upload_rate() {
+        let now = Instant::now();
+        let mut upload_rate =
+            MinUploadRate::new(1024, Duration::from_secs(2), now);
+        // the rate is not checked in the first window
+        assert_eq!(false, upload_rate.on_received(10, now));
+        assert_eq!(
+            false,
+            upload_rate.on_received(10, now + Duration::from_secs(1))
+        );
+        // 2048 bytes are received in 2s
+        assert_eq!(
+            false,
+            upload_rate.on_received(2028, now + Duration::from_secs(2))
+        );
+        // the next window is too slow
+        assert_eq!(
+            false,
+            upload_rate.on_received(1024, now + Duration::from_secs(3))
+        );
+        assert_eq!(
+            true,
+            upload_rate.on_received(512, now + Duration::from_secs(4))
+        );
+    }
+
+    #[test]
+    fn test_bandwidth_throttle_throughput() {
+        let rate = 1024 * 1024;
+        let after = 1024 * 1024;
+        let total = 10 * 1024 * 1024;
+        let chunk_size = 16 * 1024;
+        let mut throttle = BandwidthThrottle::new(rate, after);
+        let start = Instant::now();
+        let mut now = start;
+        let mut sent = 0;
+        while sent < total {
+            sent += chunk_size;
+            // simulate the write cost of the chunk
+            now += Duration::from_micros(100);
+            if let Some(delay) = throttle.on_sent(chunk_size as usize, now) {
+                now += delay;
+            }
+        }
+        let elapsed = now.duration_since(start).as_secs_f64();
+        let throughput = (total - after) as f64 / elapsed;
+        // the first bytes are sent without delay, so the throughput
+        // of the throttled part is near the configured rate
+        assert_eq!(true, elapsed >= 9.0 && elapsed < 9.1);
+        assert_eq!(
+            true,
+            (throughput - rate as f64).abs() / (rate as f64) < 0.01
+        );
+    }
+}
@@ -15,6 +15,7 @@
 use coarsetime::{Clock, Updater};
 use ctor::ctor;
 use std::sync::LazyLock;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 static COARSE_CLOCK_UPDATER: LazyLock<Updater> = LazyLock::new(|| {
     let interval = std::env::var("PINGAP_COARSE_CLOCK_INTERVAL")
@@ -79,6 +80,21 @@ pub fn real_now_ms() -> u64 {
     Clock::now_since_epoch().as_millis()
 }
 
+// whether the server is draining for graceful shutdown
+static DRAINING: AtomicBool = AtomicBool::new(false);
+
+/// Marks the server as draining, the keep-alive connections are closed
+/// after the current response, so the graceful shutdown converges.
+pub fn set_draining(draining: bool) {
+    DRAINING.store(draining, Ordering::Relaxed);
+}
+
+/// Returns true if the server is draining for graceful shutdown
+#[inline]
+pub fn is_draining() -> bool {
+    DRAINING.load(Ordering::Relaxed)
+}
+
 #[ctor]
 fn init() {
     init_time_cache();
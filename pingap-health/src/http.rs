@@ -16,17 +16,41 @@ use super::{
     DEFAULT_CHECK_FREQUENCY, DEFAULT_CONNECTION_TIMEOUT,
     DEFAULT_CONSECUTIVE_FAILURE, DEFAULT_CONSECUTIVE_SUCCESS,
     DEFAULT_READ_TIMEOUT, Error, HealthCheckSchema, LOG_TARGET,
-    update_peer_options,
+    new_internal_error, update_peer_options,
 };
+use async_trait::async_trait;
 use humantime::parse_duration;
+use pingora::connectors::L4Connect;
+use pingora::connectors::http::Connector;
 use pingora::http::RequestHeader;
-use pingora::lb::health_check::{HealthObserveCallback, HttpHealthCheck};
+use pingora::lb::Backend;
+use pingora::lb::health_check::{
+    HealthCheck, HealthObserveCallback, HttpHealthCheck,
+};
+use pingora::upstreams::peer::Peer;
+use regex::Regex;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
 use std::time::Duration;
 use tracing::error;
 use url::Url;
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+// the max size of response body for matching expected body
+const MAX_EXPECTED_BODY_SIZE: usize = 64 * 1024;
+
+fn is_expected_status(
+    expected_status: Option<&RangeInclusive<u16>>,
+    status: u16,
+) -> bool {
+    if let Some(expected_status) = expected_status {
+        expected_status.contains(&status)
+    } else {
+        status == 200
+    }
+}
+
 pub(crate) fn new_http_health_check(
     name: &str,
     conf: &HealthCheckConf,
@@ -47,6 +71,18 @@ pub(crate) fn new_http_health_check(
     check.backend_summary_callback = Some(Box::new(move |backend| {
         format!("{upstream_name}: {}", backend.addr)
     }));
+    if let Some(expected_status) = conf.expected_status.clone() {
+        check.validator = Some(Box::new(move |resp| {
+            let status = resp.status.as_u16();
+            if !is_expected_status(Some(&expected_status), status) {
+                return Err(new_internal_error(
+                    500,
+                    format!("status({status}) is not expected"),
+                ));
+            }
+            Ok(())
+        }));
+    }
     // create http get request
     match RequestHeader::build("GET", conf.path.as_bytes(), None) {
         Ok(mut req) => {
@@ -72,6 +108,116 @@ pub(crate) fn new_http_health_check(
     check
 }
 
+/// Expected body of http health check response
+#[derive(Debug, Clone)]
+pub enum ExpectedBody {
+    /// The body contains the value
+    Contains(String),
+    /// The body matches the regex, it's configured with `~` prefix
+    Regex(Regex),
+}
+
+impl ExpectedBody {
+    fn new(value: &str) -> Result<Self> {
+        if let Some(value) = value.strip_prefix('~') {
+            let re =
+                Regex::new(value).map_err(|e| Error::Regex { source: e })?;
+            return Ok(Self::Regex(re));
+        }
+        Ok(Self::Contains(value.to_string()))
+    }
+    fn is_match(&self, body: &[u8]) -> bool {
+        let body = String::from_utf8_lossy(body);
+        match self {
+            Self::Contains(value) => body.contains(value.as_str()),
+            Self::Regex(re) => re.is_match(&body),
+        }
+    }
+}
+
+/// Http health check validating the status and body of response,
+/// the other options are the same as [HttpHealthCheck].
+pub struct HttpBodyHealthCheck {
+    check: HttpHealthCheck,
+    connector: Connector,
+    expected_status: Option<RangeInclusive<u16>>,
+    expected_body: ExpectedBody,
+}
+
+impl HttpBodyHealthCheck {
+    pub(crate) fn new(
+        check: HttpHealthCheck,
+        conf: &HealthCheckConf,
+        expected_body: ExpectedBody,
+    ) -> Self {
+        Self {
+            check,
+            connector: Connector::new(None),
+            expected_status: conf.expected_status.clone(),
+            expected_body,
+        }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for HttpBodyHealthCheck {
+    async fn check(&self, target: &Backend) -> pingora::Result<()> {
+        let mut peer = self.check.peer_template.clone();
+        peer._address = target.addr.clone();
+        let (mut session, _) = self.connector.get_http_session(&peer).await?;
+
+        session
+            .write_request_header(Box::new(self.check.req.clone()))
+            .await?;
+        session.finish_request_body().await?;
+        if let Some(read_timeout) = peer.options.read_timeout {
+            session.set_read_timeout(Some(read_timeout));
+        }
+        session.read_response_header().await?;
+        let status = session
+            .response_header()
+            .map(|resp| resp.status.as_u16())
+            .unwrap_or_default();
+        if !is_expected_status(self.expected_status.as_ref(), status) {
+            return Err(new_internal_error(
+                500,
+                format!("status({status}) is not expected"),
+            ));
+        }
+
+        let mut body = vec![];
+        while let Some(data) = session.read_response_body().await? {
+            // drain the body if it's too large
+            if body.len() < MAX_EXPECTED_BODY_SIZE {
+                body.extend_from_slice(&data);
+            }
+        }
+        if !self.expected_body.is_match(&body) {
+            return Err(new_internal_error(500, "body is not expected"));
+        }
+
+        if self.check.reuse_connection {
+            let idle_timeout = peer.idle_timeout();
+            self.connector
+                .release_http_session(session, &peer, idle_timeout)
+                .await;
+        }
+        Ok(())
+    }
+
+    async fn health_status_change(&self, target: &Backend, healthy: bool) {
+        self.check.health_status_change(target, healthy).await;
+    }
+
+    fn backend_summary(&self, target: &Backend) -> String {
+        self.check.backend_summary(target)
+    }
+
+    fn health_threshold(&self, success: bool) -> usize {
+        self.check.health_threshold(success)
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct HealthCheckConf {
     pub schema: HealthCheckSchema,
@@ -86,6 +232,24 @@ pub struct HealthCheckConf {
     pub service: String,
     pub tls: bool,
     pub parallel_check: bool,
+    /// Share the check result of the same backend between upstreams
+    pub shared: bool,
+    /// Expected status range of http response, e.g. `200-399`
+    pub expected_status: Option<RangeInclusive<u16>>,
+    /// Expected body of http response, substring or regex with `~` prefix
+    pub expected_body: Option<ExpectedBody>,
+    /// Connect to the backends through the proxy of upstream
+    pub proxy: Option<Arc<dyn L4Connect + Send + Sync>>,
+}
+
+fn parse_expected_status(value: &str) -> Option<RangeInclusive<u16>> {
+    let (start, end) = value.split_once('-').unwrap_or((value, value));
+    let start = start.trim().parse::<u16>().ok()?;
+    let end = end.trim().parse::<u16>().ok()?;
+    if start > end {
+        return None;
+    }
+    Some(start..=end)
 }
 
 impl TryFrom<&str> for HealthCheckConf {
@@ -105,7 +269,10 @@ impl TryFrom<&str> for HealthCheckConf {
         let mut reuse_connection = false;
         let mut tls = false;
         let mut parallel_check = false;
+        let mut shared = false;
         let mut service = "".to_string();
+        let mut expected_status = None;
+        let mut expected_body = None;
         // HttpHealthCheck
         for (key, value) in value.query_pairs().into_iter() {
             match key.as_ref() {
@@ -146,6 +313,18 @@ impl TryFrom<&str> for HealthCheckConf {
                 "parallel" => {
                     parallel_check = true;
                 },
+                "shared" => {
+                    shared = true;
+                },
+                "expected_status" => {
+                    expected_status = parse_expected_status(value.as_ref());
+                },
+                "expected_body" => {
+                    if !value.is_empty() {
+                        expected_body =
+                            Some(ExpectedBody::new(value.as_ref())?);
+                    }
+                },
                 _ => {
                     if value.is_empty() {
                         query_list.push(key.to_string());
@@ -182,6 +361,10 @@ impl TryFrom<&str> for HealthCheckConf {
             tls,
             service,
             parallel_check,
+            shared,
+            expected_status,
+            expected_body,
+            proxy: None,
         })
     }
 }
@@ -196,7 +379,7 @@ mod tests {
     fn test_http_health_check_conf() {
         let http_check: HealthCheckConf = "https://upstreamname/ping?connection_timeout=3s&read_timeout=1s&success=2&failure=1&check_frequency=10s&from=nginx&reuse&tls&service=grpc".try_into().unwrap();
         assert_eq!(
-            r###"HealthCheckConf { schema: Https, host: "upstreamname", path: "/ping?from=nginx", connection_timeout: 3s, read_timeout: 1s, check_frequency: 10s, reuse_connection: true, consecutive_success: 2, consecutive_failure: 1, service: "grpc", tls: true, parallel_check: false }"###,
+            r###"HealthCheckConf { schema: Https, host: "upstreamname", path: "/ping?from=nginx", connection_timeout: 3s, read_timeout: 1s, check_frequency: 10s, reuse_connection: true, consecutive_success: 2, consecutive_failure: 1, service: "grpc", tls: true, parallel_check: false, shared: false, expected_status: None, expected_body: None, proxy: None }"###,
             format!("{http_check:?}")
         );
         let http_check = new_http_health_check("", &http_check, None);
@@ -212,4 +395,90 @@ mod tests {
             http_check.peer_template.options.read_timeout.unwrap()
         );
     }
+
+    #[test]
+    fn test_expected_status_body() {
+        let http_check: HealthCheckConf =
+            "http://upstreamname/ping?expected_status=200-399&expected_body=ok"
+                .try_into()
+                .unwrap();
+        assert_eq!(Some(200..=399), http_check.expected_status);
+        let expected_body = http_check.expected_body.unwrap();
+        assert_eq!(true, expected_body.is_match(b"status: ok"));
+        assert_eq!(false, expected_body.is_match(b"status: degraded"));
+
+        let http_check: HealthCheckConf =
+            "http://upstreamname/ping?expected_status=204&expected_body=~%5E%5Cw%2B%3A%20ok%24"
+                .try_into()
+                .unwrap();
+        assert_eq!(Some(204..=204), http_check.expected_status);
+        let expected_body = http_check.expected_body.unwrap();
+        assert_eq!(true, expected_body.is_match(b"status: ok"));
+        assert_eq!(false, expected_body.is_match(b"status: ok, degraded"));
+
+        assert_eq!(None, parse_expected_status("399-200"));
+        assert_eq!(None, parse_expected_status("abc"));
+
+        let result = HealthCheckConf::try_from(
+            "http://upstreamname/ping?expected_body=~(",
+        );
+        assert_eq!(true, result.is_err());
+
+        assert_eq!(true, is_expected_status(None, 200));
+        assert_eq!(false, is_expected_status(None, 204));
+    }
+
+    #[tokio::test]
+    async fn test_http_body_health_check() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        async fn serve(status: u16, body: &'static str) -> String {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap().to_string();
+            tokio::spawn(async move {
+                while let Ok((mut stream, _)) = listener.accept().await {
+                    let mut buf = vec![0; 1024];
+                    let _ = stream.read(&mut buf).await;
+                    let resp = format!(
+                        "HTTP/1.1 {status} OK\r\nContent-Length: {}\r\n\r\n{body}",
+                        body.len()
+                    );
+                    let _ = stream.write_all(resp.as_bytes()).await;
+                }
+            });
+            addr
+        }
+
+        let new_check = |url: &str| {
+            let conf: HealthCheckConf = url.try_into().unwrap();
+            let check = new_http_health_check("upstreamname", &conf, None);
+            let expected_body = conf.expected_body.clone().unwrap();
+            HttpBodyHealthCheck::new(check, &conf, expected_body)
+        };
+
+        // 200 with degraded body is unhealthy
+        let addr = serve(200, r#"{"status":"degraded"}"#).await;
+        let backend = Backend::new(&addr).unwrap();
+        let check =
+            new_check("http://upstreamname/ping?expected_body=%22ok%22");
+        let err = check.check(&backend).await.unwrap_err();
+        assert_eq!(true, err.to_string().contains("body is not expected"));
+
+        let addr = serve(200, r#"{"status":"ok"}"#).await;
+        let backend = Backend::new(&addr).unwrap();
+        assert_eq!(true, check.check(&backend).await.is_ok());
+
+        // the status is not in expected range
+        let addr = serve(503, r#"{"status":"ok"}"#).await;
+        let backend = Backend::new(&addr).unwrap();
+        let check = new_check(
+            "http://upstreamname/ping?expected_status=200-399&expected_body=ok",
+        );
+        let err = check.check(&backend).await.unwrap_err();
+        assert_eq!(
+            true,
+            err.to_string().contains("status(503) is not expected")
+        );
+    }
 }
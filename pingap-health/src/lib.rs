@@ -13,11 +13,13 @@
 // limitations under the License.
 
 use humantime::format_duration;
+use pingora::connectors::L4Connect;
 use pingora::lb::health_check::{
     HealthCheck, HealthObserveCallback, TcpHealthCheck,
 };
 use pingora::upstreams::peer::PeerOptions;
 use snafu::Snafu;
+use std::sync::Arc;
 use std::time::Duration;
 use strum::EnumString;
 use tracing::info;
@@ -25,8 +27,10 @@ static LOG_TARGET: &str = "pingap::health";
 
 mod grpc;
 mod http;
+mod shared;
 pub use grpc::GrpcHealthCheck;
-pub use http::HealthCheckConf;
+pub use http::{ExpectedBody, HealthCheckConf, HttpBodyHealthCheck};
+pub use shared::SharedHealthCheck;
 
 /// Creates a new internal error
 fn new_internal_error(status: u16, message: impl ToString) -> pingora::BError {
@@ -53,6 +57,8 @@ pub enum Error {
     },
     #[snafu(display("Invalid health check schema: {schema}, {message}"))]
     InvalidSchema { schema: String, message: String },
+    #[snafu(display("Regex error {source}"))]
+    Regex { source: regex::Error },
 }
 type Result<T, E = Error> = std::result::Result<T, E>;
 
@@ -70,6 +76,8 @@ fn update_peer_options(
     options.write_timeout = Some(conf.read_timeout);
     // set zero to disable reuse connection
     options.idle_timeout = Some(Duration::from_secs(0));
+    // the health check should traverse the same proxy as the requests
+    options.custom_l4.clone_from(&conf.proxy);
     options
 }
 
@@ -92,6 +100,7 @@ pub fn new_health_check(
     name: &str,
     health_check: &str,
     health_changed_callback: Option<HealthObserveCallback>,
+    proxy: Option<Arc<dyn L4Connect + Send + Sync>>,
 ) -> Result<(
     HealthCheckConf,
     Box<dyn HealthCheck + Send + Sync + 'static>,
@@ -108,6 +117,7 @@ pub fn new_health_check(
         check.health_changed_callback = health_changed_callback;
         check.peer_template.options.connection_timeout =
             Some(Duration::from_secs(3));
+        check.peer_template.options.custom_l4 = proxy;
         info!(
             target: LOG_TARGET,
             name,
@@ -117,6 +127,7 @@ pub fn new_health_check(
         check
     } else {
         health_check_conf = health_check.try_into()?;
+        health_check_conf.proxy = proxy;
         info!(
             target: LOG_TARGET,
             name,
@@ -130,31 +141,51 @@ pub fn new_health_check(
             check_frequency =
                 format_duration(health_check_conf.check_frequency).to_string(),
             reuse_connection = health_check_conf.reuse_connection,
+            shared = health_check_conf.shared,
             consecutive_success = health_check_conf.consecutive_success,
             consecutive_failure = health_check_conf.consecutive_failure,
             "new http/grpc health check"
         );
-        match health_check_conf.schema {
-            HealthCheckSchema::Http | HealthCheckSchema::Https => {
-                Box::new(http::new_http_health_check(
+        let hc: Box<dyn HealthCheck + Send + Sync + 'static> =
+            match health_check_conf.schema {
+                HealthCheckSchema::Http | HealthCheckSchema::Https => {
+                    let check = http::new_http_health_check(
+                        name,
+                        &health_check_conf,
+                        health_changed_callback,
+                    );
+                    if let Some(expected_body) =
+                        &health_check_conf.expected_body
+                    {
+                        Box::new(http::HttpBodyHealthCheck::new(
+                            check,
+                            &health_check_conf,
+                            expected_body.clone(),
+                        ))
+                    } else {
+                        Box::new(check)
+                    }
+                },
+                HealthCheckSchema::Grpc => {
+                    let check = GrpcHealthCheck::new(
+                        name,
+                        &health_check_conf,
+                        health_changed_callback,
+                    )?;
+                    Box::new(check)
+                },
+                _ => Box::new(new_tcp_health_check(
                     name,
                     &health_check_conf,
                     health_changed_callback,
-                ))
-            },
-            HealthCheckSchema::Grpc => {
-                let check = GrpcHealthCheck::new(
-                    name,
-                    &health_check_conf,
-                    health_changed_callback,
-                )?;
-                Box::new(check)
-            },
-            _ => Box::new(new_tcp_health_check(
-                name,
-                &health_check_conf,
-                health_changed_callback,
-            )),
+                )),
+            };
+        // the backend behind a proxy isn't shared, the result may differ
+        // with the proxy of each upstream
+        if health_check_conf.shared && health_check_conf.proxy.is_none() {
+            Box::new(SharedHealthCheck::new(hc, &health_check_conf))
+        } else {
+            hc
         }
     };
     Ok((health_check_conf, hc))
@@ -183,7 +214,7 @@ mod tests {
                 .try_into()
                 .unwrap();
         assert_eq!(
-            r###"HealthCheckConf { schema: Tcp, host: "upstreamname", path: "", connection_timeout: 3s, read_timeout: 3s, check_frequency: 10s, reuse_connection: false, consecutive_success: 2, consecutive_failure: 1, service: "", tls: false, parallel_check: false }"###,
+            r###"HealthCheckConf { schema: Tcp, host: "upstreamname", path: "", connection_timeout: 3s, read_timeout: 3s, check_frequency: 10s, reuse_connection: false, consecutive_success: 2, consecutive_failure: 1, service: "", tls: false, parallel_check: false, shared: false, expected_status: None, expected_body: None, proxy: None }"###,
             format!("{tcp_check:?}")
         );
         let tcp_check = new_tcp_health_check("", &tcp_check, None);
@@ -196,7 +227,7 @@ mod tests {
     }
     #[test]
     fn test_new_health_check() {
-        let (conf, _) = new_health_check("upstreamname", "https://upstreamname/ping?connection_timeout=3s&read_timeout=1s&success=2&failure=1&check_frequency=10s&from=nginx&reuse", None).unwrap();
+        let (conf, _) = new_health_check("upstreamname", "https://upstreamname/ping?connection_timeout=3s&read_timeout=1s&success=2&failure=1&check_frequency=10s&from=nginx&reuse", None, None).unwrap();
         assert_eq!(Duration::from_secs(10), conf.check_frequency);
     }
 
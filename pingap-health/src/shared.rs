@@ -0,0 +1,255 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{HealthCheckConf, HealthCheckSchema, LOG_TARGET};
+use async_trait::async_trait;
+use dashmap::DashMap;
+use pingora::lb::Backend;
+use pingora::lb::health_check::HealthCheck;
+use std::sync::{Arc, LazyLock};
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::debug;
+
+/// The shared result is removed if it isn't refreshed in this duration,
+/// e.g. the backend is removed from all upstreams.
+const SHARED_RESULT_EXPIRED: Duration = Duration::from_secs(10 * 60);
+
+struct SharedResult {
+    checked_at: Instant,
+    // the error message of failed check
+    result: Result<(), String>,
+}
+
+type SharedEntry = Arc<Mutex<Option<SharedResult>>>;
+
+// the latest check result of each backend, the key is the backend address
+// with the check params
+static SHARED_RESULTS: LazyLock<DashMap<String, SharedEntry>> =
+    LazyLock::new(DashMap::new);
+
+fn get_shared_entry(key: &str) -> SharedEntry {
+    if let Some(entry) = SHARED_RESULTS.get(key) {
+        return entry.clone();
+    }
+    // remove the expired results before adding the new backend
+    SHARED_RESULTS.retain(|_, entry| {
+        entry.try_lock().map_or(true, |result| {
+            result.as_ref().is_some_and(|result| {
+                result.checked_at.elapsed() < SHARED_RESULT_EXPIRED
+            })
+        })
+    });
+    SHARED_RESULTS.entry(key.to_string()).or_default().clone()
+}
+
+/// Returns the params which affect the check result, the upstreams
+/// with the same params share the check result of the same backend.
+/// The host is ignored for tcp check as it's only the upstream name.
+fn get_shared_params(conf: &HealthCheckConf) -> String {
+    match conf.schema {
+        HealthCheckSchema::Tcp => {
+            format!("tcp?connection_timeout={:?}", conf.connection_timeout)
+        },
+        _ => format!(
+            "{}://{}{}?connection_timeout={:?}&read_timeout={:?}&tls={}&service={}&expected_status={:?}&expected_body={:?}",
+            conf.schema,
+            conf.host,
+            conf.path,
+            conf.connection_timeout,
+            conf.read_timeout,
+            conf.tls,
+            conf.service,
+            conf.expected_status,
+            conf.expected_body,
+        ),
+    }
+}
+
+/// SharedHealthCheck coalesces the checks of the backend which is
+/// referenced by multiple upstreams, the backend is probed once per check
+/// frequency and the result is shared by all upstreams. The concurrent
+/// checks of the same backend wait for the running one.
+pub struct SharedHealthCheck {
+    check: Box<dyn HealthCheck + Send + Sync + 'static>,
+    params: String,
+    // the result is reused within this duration, it's half of the check
+    // frequency so the backend is probed again in next round
+    ttl: Duration,
+}
+
+impl SharedHealthCheck {
+    pub(crate) fn new(
+        check: Box<dyn HealthCheck + Send + Sync + 'static>,
+        conf: &HealthCheckConf,
+    ) -> Self {
+        Self {
+            check,
+            params: get_shared_params(conf),
+            ttl: conf.check_frequency / 2,
+        }
+    }
+}
+
+#[async_trait]
+impl HealthCheck for SharedHealthCheck {
+    async fn check(&self, target: &Backend) -> pingora::Result<()> {
+        let key = format!("{}|{}", target.addr, self.params);
+        let entry = get_shared_entry(&key);
+        let mut shared = entry.lock().await;
+        let result = match shared.as_ref() {
+            Some(shared) if shared.checked_at.elapsed() < self.ttl => {
+                debug!(target: LOG_TARGET, key, "reuse shared health check");
+                shared.result.clone()
+            },
+            _ => {
+                let result =
+                    self.check.check(target).await.map_err(|e| e.to_string());
+                *shared = Some(SharedResult {
+                    checked_at: Instant::now(),
+                    result: result.clone(),
+                });
+                result
+            },
+        };
+        result.map_err(|message| {
+            pingora::Error::explain(pingora::ErrorType::InternalError, message)
+        })
+    }
+
+    async fn health_status_change(&self, target: &Backend, healthy: bool) {
+        self.check.health_status_change(target, healthy).await;
+    }
+
+    fn backend_summary(&self, target: &Backend) -> String {
+        self.check.backend_summary(target)
+    }
+
+    fn health_threshold(&self, success: bool) -> usize {
+        self.check.health_threshold(success)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::new_health_check;
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::net::TcpListener;
+
+    async fn serve(count: Arc<AtomicUsize>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((_stream, _)) = listener.accept().await {
+                count.fetch_add(1, Ordering::Relaxed);
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_get_shared_params() {
+        let tcp: HealthCheckConf = "tcp://upstream1?shared".try_into().unwrap();
+        let other: HealthCheckConf =
+            "tcp://upstream2?shared&success=2".try_into().unwrap();
+        assert_eq!(true, tcp.shared);
+        assert_eq!("tcp?connection_timeout=3s", get_shared_params(&tcp));
+        assert_eq!(get_shared_params(&tcp), get_shared_params(&other));
+
+        let http: HealthCheckConf =
+            "http://pingap/ping?shared".try_into().unwrap();
+        let other: HealthCheckConf =
+            "http://pingap/health?shared".try_into().unwrap();
+        assert_eq!(
+            "http://pingap/ping?connection_timeout=3s&read_timeout=3s&tls=false&service=&expected_status=None&expected_body=None",
+            get_shared_params(&http)
+        );
+        assert_ne!(get_shared_params(&http), get_shared_params(&other));
+    }
+
+    #[tokio::test]
+    async fn test_shared_health_check() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let addr = serve(count.clone()).await;
+        let backend = Backend::new(&addr).unwrap();
+
+        // two upstreams reference the same backend
+        let (_, check1) = new_health_check(
+            "upstream1",
+            "tcp://upstream1?shared&check_frequency=10s",
+            None,
+            None,
+        )
+        .unwrap();
+        let (_, check2) = new_health_check(
+            "upstream2",
+            "tcp://upstream2?shared&check_frequency=10s",
+            None,
+            None,
+        )
+        .unwrap();
+        let (result1, result2) =
+            tokio::join!(check1.check(&backend), check2.check(&backend));
+        assert_eq!(true, result1.is_ok());
+        assert_eq!(true, result2.is_ok());
+        assert_eq!(true, check1.check(&backend).await.is_ok());
+        // wait until the accepted connections are counted
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        // the backend is probed once in the interval
+        assert_eq!(1, count.load(Ordering::Relaxed));
+
+        // the result isn't shared without the shared param
+        let count = Arc::new(AtomicUsize::new(0));
+        let addr = serve(count.clone()).await;
+        let backend = Backend::new(&addr).unwrap();
+        let (_, check1) =
+            new_health_check("upstream1", "tcp://upstream1", None, None)
+                .unwrap();
+        let (_, check2) =
+            new_health_check("upstream2", "tcp://upstream2", None, None)
+                .unwrap();
+        assert_eq!(true, check1.check(&backend).await.is_ok());
+        assert_eq!(true, check2.check(&backend).await.is_ok());
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(2, count.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn test_shared_health_check_failure() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        let backend = Backend::new(&addr).unwrap();
+
+        let (_, check1) = new_health_check(
+            "upstream1",
+            "tcp://upstream1?shared&connection_timeout=1s",
+            None,
+            None,
+        )
+        .unwrap();
+        let (_, check2) = new_health_check(
+            "upstream2",
+            "tcp://upstream2?shared&connection_timeout=1s",
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(true, check1.check(&backend).await.is_err());
+        // the failure is shared
+        assert_eq!(true, check2.check(&backend).await.is_err());
+    }
+}
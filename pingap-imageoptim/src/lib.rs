@@ -84,6 +84,9 @@ impl ModifyResponseBody for ImageOptimizer {
     fn name(&self) -> String {
         "image_optimization".to_string()
     }
+    fn buffering(&self) -> bool {
+        true
+    }
 }
 
 pub struct ImageOptim {
@@ -214,6 +217,10 @@ impl Plugin for ImageOptim {
         let Ok(accept_str) = accept.to_str() else {
             return Ok(ResponsePluginResult::Unchanged);
         };
+        // the image is too large to be buffered
+        if !ctx.can_buffer_response(&upstream_response.headers)? {
+            return Ok(ResponsePluginResult::Unchanged);
+        }
 
         let image_type = image_type.to_string();
         let mut format_type = image_type.clone();
@@ -14,6 +14,7 @@
 
 use super::regex::RegexCapture;
 use ahash::AHashMap;
+use bytes::Bytes;
 use http::HeaderName;
 use http::HeaderValue;
 use pingap_config::Hashable;
@@ -241,6 +242,28 @@ pub struct Location {
 
     /// Maximum window for retries
     pub max_retry_window: Option<Duration>,
+
+    /// Whether to retry the idempotent request when the upstream
+    /// connection is reset before the response is sent to client
+    pub retry_on_upstream_reset: bool,
+
+    /// Whether to reuse the connections to upstream,
+    /// None means using the keepalive of upstream
+    pub upstream_keepalive: Option<bool>,
+
+    /// Upstream used when all attempts return 5xx
+    pub fallback_upstream: Option<Arc<str>>,
+
+    /// Page served when all attempts return 5xx
+    pub fallback_page: Option<Bytes>,
+
+    /// Maximum size of response body buffered by plugins in bytes
+    /// Zero means unlimited
+    pub max_response_buffer_size: usize,
+
+    /// Whether to respond 502 instead of bypassing the buffering plugins
+    /// when the content-length of response exceeds the max size
+    pub response_buffer_overflow_error: bool,
 }
 
 /// Formats a vector of header strings into internal HttpHeader representation.
@@ -365,6 +388,28 @@ impl Location {
             //     .unwrap_or_default(),
             max_retries: conf.max_retries,
             max_retry_window: conf.max_retry_window,
+            retry_on_upstream_reset: conf
+                .retry_on_upstream_reset
+                .unwrap_or_default(),
+            upstream_keepalive: conf.upstream_keepalive,
+            fallback_upstream: conf
+                .fallback_upstream
+                .as_deref()
+                .filter(|value| !value.is_empty())
+                .map(Arc::from),
+            fallback_page: conf
+                .fallback_page
+                .as_ref()
+                .filter(|value| !value.is_empty())
+                .map(|value| Bytes::from(value.clone())),
+            max_response_buffer_size: conf
+                .max_response_buffer_size
+                .unwrap_or_default()
+                .as_u64() as usize,
+            response_buffer_overflow_error: conf
+                .response_buffer_overflow
+                .as_deref()
+                == Some("error"),
         };
         debug!(
             category = LOG_CATEGORY,
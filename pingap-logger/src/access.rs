@@ -54,6 +54,10 @@ pub enum TagCategory {
     PayloadSize,
     PayloadSizeHuman,
     RequestId,
+    UpstreamAddr,         // Address of upstream peer
+    UpstreamStatus,       // Status code of upstream response
+    UpstreamResponseTime, // Time(ms) of upstream response
+    UpstreamConnectTime,  // Time(ms) of connecting to upstream
 }
 
 // Represents a single tag in the log format
@@ -122,6 +126,13 @@ static COMMON: &str =
     r###"{remote} "{method} {uri} {proto}" {status} {size_human}""###;
 static SHORT: &str = r###"{remote} {method} {uri} {proto} {status} {size_human} - {latency}ms"###;
 static TINY: &str = r###"{method} {uri} {status} {size_human} - {latency}ms"###;
+// The upstream tags which can be written in nginx style, e.g. ${upstream_addr}
+static NGINX_STYLE_TAGS: [&str; 4] = [
+    "{upstream_addr}",
+    "{upstream_status}",
+    "{upstream_response_time}",
+    "{upstream_connect_time}",
+];
 
 impl From<&str> for Parser {
     fn from(value: &str) -> Self {
@@ -147,15 +158,22 @@ impl From<&str> for Parser {
         let mut tags = vec![];
 
         while let Some(result) = reg.find_at(value, current) {
-            if end < result.start() {
+            let key = result.as_str();
+            // the nginx style of upstream tags, e.g. ${upstream_status}
+            let fill_end = if result.start() > end
+                && value.as_bytes()[result.start() - 1] == b'$'
+                && NGINX_STYLE_TAGS.contains(&key)
+            {
+                result.start() - 1
+            } else {
+                result.start()
+            };
+            if end < fill_end {
                 tags.push(Tag {
                     category: TagCategory::Fill,
-                    data: Some(
-                        value.substring(end, result.start()).to_string(),
-                    ),
+                    data: Some(value.substring(end, fill_end).to_string()),
                 });
             }
-            let key = result.as_str();
 
             match key {
                 "{host}" => tags.push(Tag {
@@ -246,6 +264,22 @@ impl From<&str> for Parser {
                     category: TagCategory::RequestId,
                     data: None,
                 }),
+                "{upstream_addr}" => tags.push(Tag {
+                    category: TagCategory::UpstreamAddr,
+                    data: None,
+                }),
+                "{upstream_status}" => tags.push(Tag {
+                    category: TagCategory::UpstreamStatus,
+                    data: None,
+                }),
+                "{upstream_response_time}" => tags.push(Tag {
+                    category: TagCategory::UpstreamResponseTime,
+                    data: None,
+                }),
+                "{upstream_connect_time}" => tags.push(Tag {
+                    category: TagCategory::UpstreamConnectTime,
+                    data: None,
+                }),
                 _ => {
                     if let Some(tag) = format_extra_tag(key) {
                         tags.push(tag);
@@ -552,6 +586,38 @@ impl Parser {
                         buf.extend_from_slice(EMPTY_FIELD);
                     }
                 },
+                TagCategory::UpstreamAddr => {
+                    if ctx.upstream.address.is_empty() {
+                        buf.extend_from_slice(EMPTY_FIELD);
+                    } else {
+                        buf.extend_from_slice(ctx.upstream.address.as_bytes());
+                    }
+                },
+                TagCategory::UpstreamStatus => {
+                    if let Some(status) = &ctx.upstream.status {
+                        buf.extend_from_slice(status.as_str().as_bytes());
+                    } else {
+                        buf.extend_from_slice(EMPTY_FIELD);
+                    }
+                },
+                TagCategory::UpstreamResponseTime => {
+                    if let Some(ms) = ctx.get_upstream_response_time() {
+                        buf.extend_from_slice(
+                            itoa::Buffer::new().format(ms).as_bytes(),
+                        );
+                    } else {
+                        buf.extend_from_slice(EMPTY_FIELD);
+                    }
+                },
+                TagCategory::UpstreamConnectTime => {
+                    if let Some(ms) = ctx.get_upstream_connect_time() {
+                        buf.extend_from_slice(
+                            itoa::Buffer::new().format(ms).as_bytes(),
+                        );
+                    } else {
+                        buf.extend_from_slice(EMPTY_FIELD);
+                    }
+                },
                 TagCategory::Context => {
                     if let Some(key) = &tag.data {
                         ctx.append_log_value(&mut buf, key.as_str());
@@ -624,7 +690,7 @@ mod tests {
         Parser, Tag, TagCategory, format_extra_tag, get_resp_header_value,
         parse_access_log_directive,
     };
-    use http::Method;
+    use http::{Method, StatusCode};
     use pingap_core::{
         ConnectionInfo, Ctx, RequestState, Timing, UpstreamInfo,
     };
@@ -828,6 +894,34 @@ mod tests {
                     data: None,
                 },
             ),
+            (
+                "{upstream_addr}",
+                Tag {
+                    category: TagCategory::UpstreamAddr,
+                    data: None,
+                },
+            ),
+            (
+                "${upstream_status}",
+                Tag {
+                    category: TagCategory::UpstreamStatus,
+                    data: None,
+                },
+            ),
+            (
+                "{upstream_response_time}",
+                Tag {
+                    category: TagCategory::UpstreamResponseTime,
+                    data: None,
+                },
+            ),
+            (
+                "${upstream_connect_time}",
+                Tag {
+                    category: TagCategory::UpstreamConnectTime,
+                    data: None,
+                },
+            ),
         ];
 
         for (value, tag) in tests {
@@ -905,6 +999,57 @@ mod tests {
         assert_eq!(true, log.len() == 13);
     }
 
+    #[tokio::test]
+    async fn test_upstream_logger() {
+        let input_header = "GET /vicanso/pingap HTTP/1.1\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+
+        let ctx = Ctx {
+            upstream: UpstreamInfo {
+                address: "192.186.1.1:6188".to_string(),
+                status: Some(StatusCode::BAD_GATEWAY),
+                ..Default::default()
+            },
+            timing: Timing {
+                upstream_connect: Some(3),
+                upstream_response: Some(25),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        // text format of nginx style variables
+        let p: Parser = "${upstream_addr} ${upstream_status} \
+${upstream_response_time} ${upstream_connect_time}"
+            .into();
+        assert_eq!("192.186.1.1:6188 502 25 3", p.format(&session, &ctx));
+
+        // json format, the values are quoted as they're `-` if the
+        // request is not proxied to upstream
+        let p: Parser = r#"{"upstream_addr":"{upstream_addr}","upstream_status":"{upstream_status}","upstream_response_time":"{upstream_response_time}","upstream_connect_time":"{upstream_connect_time}"}"#
+            .into();
+        assert_eq!(
+            r#"{"upstream_addr":"192.186.1.1:6188","upstream_status":"502","upstream_response_time":"25","upstream_connect_time":"3"}"#,
+            p.format(&session, &ctx)
+        );
+        assert_eq!(
+            r#"{"upstream_addr":"-","upstream_status":"-","upstream_response_time":"-","upstream_connect_time":"-"}"#,
+            p.format(&session, &Ctx::default())
+        );
+
+        // the request is not proxied to upstream
+        let p: Parser = "${upstream_addr} ${upstream_status} \
+${upstream_response_time} ${upstream_connect_time}"
+            .into();
+        assert_eq!("- - - -", p.format(&session, &Ctx::default()));
+
+        // the `$` before other tags is kept
+        let p: Parser = "${method} ${upstream_status}".into();
+        assert_eq!("$GET 502", p.format(&session, &ctx));
+    }
+
     #[test]
     fn test_get_resp_header_value() {
         let mut header =
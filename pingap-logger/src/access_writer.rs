@@ -0,0 +1,280 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::Error;
+use super::file_appender::new_file_writer;
+use chrono::{SecondsFormat, Utc};
+use pingap_core::get_hostname;
+use serde::{Deserialize, Serialize};
+use std::io::{self, BufWriter, Write};
+use std::net::{TcpStream, UdpSocket};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+const STDOUT_PROTOCOL: &str = "stdout://";
+const STDERR_PROTOCOL: &str = "stderr://";
+const SYSLOG_PROTOCOL: &str = "syslog://";
+
+/// Severity of the access log, informational(6)
+const SEVERITY_INFO: u8 = 6;
+
+/// The writer of access log
+pub(crate) struct AccessLogWriter {
+    /// The directory of log file, only for file writer
+    pub dir: Option<String>,
+    pub writer: Box<dyn Write + Send>,
+}
+
+/// Creates the writer of access log by path:
+/// - `stdout://` or `stderr://`
+/// - `syslog://?facility=local0` writes to the local syslog socket
+/// - `syslog://127.0.0.1:514?protocol=udp` writes to the remote syslog
+/// - other values are log files, e.g. `/var/log/access.log?rolling=hourly&max_files=24`
+///   or `/var/log/access.log?max_size=100MB&max_files=5`
+pub(crate) fn new_access_log_writer(path: &str) -> Result<AccessLogWriter> {
+    if path.starts_with(STDOUT_PROTOCOL) {
+        return Ok(AccessLogWriter {
+            dir: None,
+            writer: Box::new(io::stdout()),
+        });
+    }
+    if path.starts_with(STDERR_PROTOCOL) {
+        return Ok(AccessLogWriter {
+            dir: None,
+            writer: Box::new(io::stderr()),
+        });
+    }
+    if path.starts_with(SYSLOG_PROTOCOL) {
+        return Ok(AccessLogWriter {
+            dir: None,
+            writer: Box::new(SyslogAccessWriter::new(path)?),
+        });
+    }
+    let file_writer = new_file_writer(path)?;
+    Ok(AccessLogWriter {
+        dir: Some(file_writer.dir),
+        writer: Box::new(BufWriter::new(file_writer.writer)),
+    })
+}
+
+#[derive(Debug, PartialEq, Deserialize, Serialize, Default)]
+struct SyslogAccessWriterParams {
+    protocol: Option<String>,
+    facility: Option<String>,
+    app_name: Option<String>,
+}
+
+/// Converts the facility name to its code, the default is local0.
+fn parse_facility(name: &str) -> u8 {
+    match name.to_lowercase().trim_start_matches("log_") {
+        "kern" => 0,
+        "user" => 1,
+        "mail" => 2,
+        "daemon" => 3,
+        "auth" => 4,
+        "syslog" => 5,
+        "lpr" => 6,
+        "news" => 7,
+        "uucp" => 8,
+        "cron" => 9,
+        "authpriv" => 10,
+        "ftp" => 11,
+        "local1" => 17,
+        "local2" => 18,
+        "local3" => 19,
+        "local4" => 20,
+        "local5" => 21,
+        "local6" => 22,
+        "local7" => 23,
+        _ => 16,
+    }
+}
+
+enum SyslogTransport {
+    Udp(UdpSocket),
+    /// Octet counting framing of RFC 6587
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixDatagram),
+}
+
+/// Writes each line of access log as a RFC 5424 syslog message.
+struct SyslogAccessWriter {
+    transport: SyslogTransport,
+    facility: u8,
+    hostname: String,
+    app_name: String,
+    pid: u32,
+}
+
+impl SyslogAccessWriter {
+    fn new(value: &str) -> Result<Self> {
+        let value = value.trim_start_matches(SYSLOG_PROTOCOL);
+        let (addr, query) = value.split_once('?').unwrap_or((value, ""));
+        let params: SyslogAccessWriterParams = serde_qs::from_str(query)
+            .map_err(|e| Error::Invalid {
+                message: e.to_string(),
+            })?;
+        let map_io_err = |e| Error::Io { source: e };
+        let transport = if addr.is_empty() {
+            Self::new_local_transport()?
+        } else if params.protocol.as_deref() == Some("tcp") {
+            SyslogTransport::Tcp(TcpStream::connect(addr).map_err(map_io_err)?)
+        } else {
+            let socket = UdpSocket::bind("0.0.0.0:0").map_err(map_io_err)?;
+            socket.connect(addr).map_err(map_io_err)?;
+            SyslogTransport::Udp(socket)
+        };
+        Ok(Self {
+            transport,
+            facility: parse_facility(
+                params.facility.as_deref().unwrap_or_default(),
+            ),
+            hostname: get_hostname().to_string(),
+            app_name: params.app_name.unwrap_or("pingap".to_string()),
+            pid: std::process::id(),
+        })
+    }
+    #[cfg(unix)]
+    fn new_local_transport() -> Result<SyslogTransport> {
+        let socket = std::os::unix::net::UnixDatagram::unbound()
+            .map_err(|e| Error::Io { source: e })?;
+        let mut last_err = None;
+        for path in ["/dev/log", "/var/run/syslog", "/var/run/log"] {
+            match socket.connect(path) {
+                Ok(()) => return Ok(SyslogTransport::Unix(socket)),
+                Err(e) => last_err = Some(e),
+            }
+        }
+        Err(Error::Invalid {
+            message: format!(
+                "connect to local syslog fail, {}",
+                last_err.map(|e| e.to_string()).unwrap_or_default()
+            ),
+        })
+    }
+    #[cfg(not(unix))]
+    fn new_local_transport() -> Result<SyslogTransport> {
+        Err(Error::Invalid {
+            message: "local syslog is only supported on Unix systems"
+                .to_string(),
+        })
+    }
+    /// Formats the message as RFC 5424:
+    /// `<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA MSG`
+    fn format(&self, timestamp: &str, message: &str) -> String {
+        format!(
+            "<{}>1 {timestamp} {} {} {} access - {message}",
+            self.facility * 8 + SEVERITY_INFO,
+            self.hostname,
+            self.app_name,
+            self.pid,
+        )
+    }
+    fn send(&mut self, message: &str) -> io::Result<()> {
+        match &mut self.transport {
+            SyslogTransport::Udp(socket) => {
+                socket.send(message.as_bytes())?;
+            },
+            SyslogTransport::Tcp(stream) => {
+                stream.write_all(
+                    format!("{} {message}", message.len()).as_bytes(),
+                )?;
+            },
+            #[cfg(unix)]
+            SyslogTransport::Unix(socket) => {
+                socket.send(message.as_bytes())?;
+            },
+        }
+        Ok(())
+    }
+}
+
+impl Write for SyslogAccessWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let s = std::str::from_utf8(buf)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        for line in s.lines().filter(|line| !line.is_empty()) {
+            let message = self.format(&timestamp, line);
+            self.send(&message)?;
+        }
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        if let SyslogTransport::Tcp(stream) = &mut self.transport {
+            stream.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_facility() {
+        assert_eq!(16, parse_facility(""));
+        assert_eq!(3, parse_facility("daemon"));
+        assert_eq!(23, parse_facility("LOG_LOCAL7"));
+    }
+
+    #[test]
+    fn test_syslog_access_writer() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        server
+            .set_read_timeout(Some(Duration::from_secs(3)))
+            .unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let mut writer = SyslogAccessWriter::new(&format!(
+            "syslog://{addr}?protocol=udp&facility=local1&app_name=access"
+        ))
+        .unwrap();
+        let prefix = format!(
+            "<142>1 2025-01-01T00:00:00.000Z {} access {}",
+            get_hostname(),
+            std::process::id()
+        );
+        assert_eq!(
+            format!("{prefix} access - GET /ping 200"),
+            writer.format("2025-01-01T00:00:00.000Z", "GET /ping 200")
+        );
+
+        // each line is sent as a syslog message
+        writer.write_all(b"GET / 200\nGET /api 404\n").unwrap();
+        let mut buf = [0; 1024];
+        for expected in ["GET / 200", "GET /api 404"] {
+            let size = server.recv(&mut buf).unwrap();
+            let message = std::str::from_utf8(&buf[..size]).unwrap();
+            let (pri_version, rest) = message.split_once(' ').unwrap();
+            assert_eq!("<142>1", pri_version);
+            let fields: Vec<&str> = rest.splitn(6, ' ').collect();
+            assert_eq!(true, fields[0].ends_with('Z'));
+            assert_eq!(
+                vec![
+                    get_hostname().to_string(),
+                    "access".to_string(),
+                    std::process::id().to_string(),
+                    "access".to_string(),
+                    format!("- {expected}"),
+                ],
+                fields[1..].to_vec()
+            );
+        }
+    }
+}
@@ -13,32 +13,33 @@
 // limitations under the License.
 
 use super::LOG_TARGET;
-use super::file_appender::new_rolling_file_writer;
+use super::access_writer::new_access_log_writer;
 use async_trait::async_trait;
 use bytes::BytesMut;
 use pingap_core::Error;
 use pingora::server::ShutdownWatch;
 use pingora::services::background::BackgroundService;
 use serde::{Deserialize, Serialize};
-use std::io::{BufWriter, Write};
+use std::io::Write;
 use std::time::Duration;
 use tokio::sync::Mutex;
 use tokio::sync::mpsc::{Receiver, Sender, channel};
 use tracing::{error, info};
-use tracing_appender::rolling::RollingFileAppender;
 
 type Result<T> = std::result::Result<T, Error>;
 
 pub struct AsyncLoggerTask {
-    dir: String,
+    dir: Option<String>,
     path: String,
     channel_buffer: usize,
     receiver: Mutex<Option<Receiver<BytesMut>>>,
-    writer: Mutex<Option<BufWriter<RollingFileAppender>>>,
+    writer: Mutex<Option<Box<dyn Write + Send>>>,
     flush_timeout: Duration,
 }
 impl AsyncLoggerTask {
-    pub fn get_dir(&self) -> String {
+    /// Returns the directory of log file, it's none if the logs
+    /// are written to stdout, stderr or syslog.
+    pub fn get_dir(&self) -> Option<String> {
         self.dir.clone()
     }
 }
@@ -59,25 +60,21 @@ pub async fn new_async_logger(
     let params: AsyncLoggerWriterParams =
         serde_qs::from_str(query).unwrap_or_default();
 
-    let rolling_file_writer =
-        new_rolling_file_writer(&original_path).map_err(|e| {
-            Error::Invalid {
-                message: e.to_string(),
-            }
+    let access_log_writer =
+        new_access_log_writer(&original_path).map_err(|e| Error::Invalid {
+            message: e.to_string(),
         })?;
-
-    let buffered_writer = BufWriter::new(rolling_file_writer.writer);
     let channel_buffer = params.channel_buffer.unwrap_or(1000);
     let flush_timeout = params.flush_timeout.unwrap_or(Duration::from_secs(10));
 
     let (tx, rx) = channel::<BytesMut>(channel_buffer);
 
     let task = AsyncLoggerTask {
-        dir: rolling_file_writer.dir,
+        dir: access_log_writer.dir,
         channel_buffer,
         path: path.to_string(),
         receiver: Mutex::new(Some(rx)),
-        writer: Mutex::new(Some(buffered_writer)),
+        writer: Mutex::new(Some(access_log_writer.writer)),
         flush_timeout,
     };
 
@@ -121,7 +118,7 @@ impl BackgroundService for AsyncLoggerTask {
                     }
                     for mut msg in messages {
                         msg.extend_from_slice(b"\n");
-                        if let Err(e) = writer.write(&msg) {
+                        if let Err(e) = writer.write_all(&msg) {
                             error!(
                                 target: LOG_TARGET,
                                 error = %e,
@@ -13,11 +13,13 @@
 // limitations under the License.
 
 use super::Error;
+use bytesize::ByteSize;
 use pingap_util::resolve_path;
 use serde::{Deserialize, Serialize};
-use std::fs;
-use std::path::Path;
-use tracing_appender::rolling::RollingFileAppender;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
 
 type Result<T> = std::result::Result<T, Error>;
 
@@ -32,6 +34,10 @@ struct RollingFileWriterParams {
     file: String,
     #[serde(default)]
     rolling: String,
+    /// Rotate the file when its size exceeds the max size
+    max_size: Option<ByteSize>,
+    /// The max count of rotated files to keep
+    max_files: Option<usize>,
 }
 
 impl TryFrom<&str> for RollingFileWriterParams {
@@ -53,11 +59,10 @@ impl TryFrom<&str> for RollingFileWriterParams {
     }
 }
 
-pub(crate) fn new_rolling_file_writer(
-    log_path: &str,
-) -> Result<RollingFileWriter> {
-    let params = RollingFileWriterParams::try_from(log_path)?;
-    let file = resolve_path(params.file.as_str());
+/// Resolves the directory and file name of the log path,
+/// the directory is created if it does not exist.
+fn resolve_log_file(file: &str) -> Result<(PathBuf, String)> {
+    let file = resolve_path(file);
 
     let filepath = Path::new(&file);
     let dir = if filepath.is_dir() {
@@ -80,21 +85,149 @@ pub(crate) fn new_rolling_file_writer(
             .to_string_lossy()
             .to_string()
     };
-    let writer = match params.rolling.as_str() {
-        "minutely" => tracing_appender::rolling::minutely(dir, filename),
-        "hourly" => tracing_appender::rolling::hourly(dir, filename),
-        "never" => tracing_appender::rolling::never(dir, filename),
-        _ => tracing_appender::rolling::daily(dir, filename),
+    Ok((dir.to_path_buf(), filename))
+}
+
+pub(crate) fn new_rolling_file_writer(
+    log_path: &str,
+) -> Result<RollingFileWriter> {
+    let params = RollingFileWriterParams::try_from(log_path)?;
+    let (dir, filename) = resolve_log_file(&params.file)?;
+    let rotation = match params.rolling.as_str() {
+        "minutely" => Rotation::MINUTELY,
+        "hourly" => Rotation::HOURLY,
+        "never" => Rotation::NEVER,
+        _ => Rotation::DAILY,
     };
+    let mut builder = RollingFileAppender::builder().rotation(rotation);
+    if !filename.is_empty() {
+        builder = builder.filename_prefix(filename);
+    }
+    if let Some(max_files) = params.max_files {
+        builder = builder.max_log_files(max_files);
+    }
+    let writer = builder.build(&dir).map_err(|e| Error::Invalid {
+        message: e.to_string(),
+    })?;
     Ok(RollingFileWriter {
         dir: dir.to_string_lossy().to_string(),
         writer,
     })
 }
 
+/// A file writer which rotates the file by size, the rotated files are
+/// renamed to `{file}.1`, `{file}.2` ... and the oldest ones are removed.
+///
+/// The rotation is only done between two writes, so a line written by
+/// a single `write` call is never split across files.
+pub struct SizeRollingFileWriter {
+    path: PathBuf,
+    max_size: u64,
+    max_files: usize,
+    size: u64,
+    file: File,
+}
+
+impl SizeRollingFileWriter {
+    fn open(path: &Path) -> io::Result<(File, u64)> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok((file, size))
+    }
+    pub fn new(
+        path: PathBuf,
+        max_size: u64,
+        max_files: usize,
+    ) -> io::Result<Self> {
+        let (file, size) = Self::open(&path)?;
+        Ok(Self {
+            path,
+            max_size,
+            max_files,
+            size,
+            file,
+        })
+    }
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{index}"));
+        path.into()
+    }
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.rotated_path(self.max_files));
+            for index in (1..self.max_files).rev() {
+                let from = self.rotated_path(index);
+                if from.exists() {
+                    fs::rename(from, self.rotated_path(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated_path(1))?;
+        }
+        let (file, size) = Self::open(&self.path)?;
+        self.file = file;
+        self.size = size;
+        Ok(())
+    }
+}
+
+impl Write for SizeRollingFileWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        self.file.write_all(buf)?;
+        self.size += buf.len() as u64;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+pub(crate) struct FileWriter {
+    pub dir: String,
+    pub writer: Box<dyn Write + Send>,
+}
+
+/// Creates the file writer of log path, it rotates by size if `max_size`
+/// is set, otherwise it rotates by time(`rolling`).
+pub(crate) fn new_file_writer(log_path: &str) -> Result<FileWriter> {
+    let params = RollingFileWriterParams::try_from(log_path)?;
+    let Some(max_size) = params.max_size else {
+        let rolling_file_writer = new_rolling_file_writer(log_path)?;
+        return Ok(FileWriter {
+            dir: rolling_file_writer.dir,
+            writer: Box::new(rolling_file_writer.writer),
+        });
+    };
+    let (dir, filename) = resolve_log_file(&params.file)?;
+    if filename.is_empty() {
+        return Err(Error::Invalid {
+            message: "file name is required for size rotation".to_string(),
+        });
+    }
+    let writer = SizeRollingFileWriter::new(
+        dir.join(filename),
+        max_size.as_u64(),
+        params.max_files.unwrap_or(7),
+    )
+    .map_err(|e| Error::Io { source: e })?;
+    Ok(FileWriter {
+        dir: dir.to_string_lossy().to_string(),
+        writer: Box::new(writer),
+    })
+}
+
 #[cfg(test)]
 mod tests {
-    use super::RollingFileWriterParams;
+    use super::{RollingFileWriterParams, SizeRollingFileWriter};
+    use bytesize::ByteSize;
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
 
     #[test]
     fn test_try_from_path_only() {
@@ -105,6 +238,7 @@ mod tests {
             RollingFileWriterParams {
                 file: "access.log".to_string(),
                 rolling: "".to_string(), // rolling should be default
+                ..Default::default()
             }
         );
     }
@@ -118,6 +252,7 @@ mod tests {
             RollingFileWriterParams {
                 file: "error.log".to_string(),
                 rolling: "".to_string(),
+                ..Default::default()
             }
         );
     }
@@ -131,6 +266,7 @@ mod tests {
             RollingFileWriterParams {
                 file: "app.log".to_string(),
                 rolling: "daily".to_string(),
+                ..Default::default()
             }
         );
     }
@@ -145,6 +281,7 @@ mod tests {
             RollingFileWriterParams {
                 file: "metrics.log".to_string(),
                 rolling: "hourly".to_string(),
+                ..Default::default()
             }
         );
     }
@@ -158,6 +295,7 @@ mod tests {
             RollingFileWriterParams {
                 file: "".to_string(),
                 rolling: "".to_string(),
+                ..Default::default()
             }
         );
     }
@@ -171,7 +309,57 @@ mod tests {
             RollingFileWriterParams {
                 file: "".to_string(),
                 rolling: "monthly".to_string(),
+                ..Default::default()
             }
         );
     }
+
+    #[test]
+    fn test_try_from_with_size_params() {
+        let input = "access.log?max_size=1MB&max_files=3";
+        let params = RollingFileWriterParams::try_from(input).unwrap();
+        assert_eq!(
+            params,
+            RollingFileWriterParams {
+                file: "access.log".to_string(),
+                max_size: Some(ByteSize::mb(1)),
+                max_files: Some(3),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_size_rolling_file_writer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        let mut writer =
+            SizeRollingFileWriter::new(path.clone(), 20, 2).unwrap();
+        for i in 0..5 {
+            writer
+                .write_all(format!("line-{i}-0123456789\n").as_bytes())
+                .unwrap();
+        }
+        writer.flush().unwrap();
+
+        let read = |name: &str| {
+            std::fs::read_to_string(dir.path().join(name)).unwrap()
+        };
+        // each line exceeds half of max size, so every write rotates
+        assert_eq!("line-4-0123456789\n", read("access.log"));
+        assert_eq!("line-3-0123456789\n", read("access.log.1"));
+        assert_eq!("line-2-0123456789\n", read("access.log.2"));
+        // the oldest files are removed
+        assert_eq!(false, dir.path().join("access.log.3").exists());
+
+        // the size of existing file is counted after reopen
+        let mut writer = SizeRollingFileWriter::new(path, 40, 2).unwrap();
+        writer.write_all(b"line-5-0123456789\n").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(
+            "line-4-0123456789\nline-5-0123456789\n",
+            read("access.log")
+        );
+        assert_eq!("line-3-0123456789\n", read("access.log.1"));
+    }
 }
@@ -18,6 +18,7 @@ use tracing_subscriber::EnvFilter;
 use tracing_subscriber::filter::Directive;
 
 mod access;
+mod access_writer;
 mod async_logger;
 mod file_appender;
 #[cfg(unix)]
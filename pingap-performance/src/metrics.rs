@@ -17,9 +17,43 @@ use async_trait::async_trait;
 use pingap_core::{BackgroundTask, Error};
 use pingap_location::LocationProvider;
 use pingap_upstream::UpstreamProvider;
+use serde::Serialize;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use tracing::info;
 
+/// The sample of metric, the value is set for counter and gauge,
+/// the count and sum are set for histogram.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct MetricSample {
+    pub labels: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sum: Option<f64>,
+}
+
+/// The samples of metrics, the key is the metric name without prefix.
+pub type MetricGroup = BTreeMap<String, Vec<MetricSample>>;
+
+/// Structured snapshot of the prometheus metrics of all servers,
+/// the samples of each server are distinguished by the `server` label.
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct MetricsSnapshot {
+    /// Http requests and responses of clients
+    pub requests: MetricGroup,
+    /// Connections and timings of upstreams
+    pub upstreams: MetricGroup,
+    /// Connection reuses, tls handshakes and tcp connections
+    pub connections: MetricGroup,
+    /// Cache and compression
+    pub cache: MetricGroup,
+    /// Resource usage of process
+    pub process: MetricGroup,
+}
+
 struct PerformanceMetricsLogTask {
     location_provider: Arc<dyn LocationProvider>,
     upstream_provider: Arc<dyn UpstreamProvider>,
@@ -12,7 +12,10 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{Error, LOG_TARGET, Result, get_process_system_info};
+use super::{
+    Error, LOG_TARGET, MetricGroup, MetricSample, MetricsSnapshot, Result,
+    get_process_system_info,
+};
 use async_trait::async_trait;
 use humantime::parse_duration;
 use pingap_cache::{CACHE_READING_TIME, CACHE_WRITING_TIME};
@@ -21,6 +24,7 @@ use pingap_core::Error as ServiceError;
 use pingap_core::{Ctx, get_hostname};
 use pingora::proxy::Session;
 use prometheus::core::Collector;
+use prometheus::proto::MetricType;
 use prometheus::{
     Encoder, HistogramVec, Opts, ProtobufEncoder, Registry, TextEncoder,
 };
@@ -28,7 +32,7 @@ use prometheus::{
     Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, IntGaugeVec,
 };
 use smallvec::SmallVec;
-use std::sync::Arc;
+use std::sync::{Arc, LazyLock, RwLock};
 use std::time::Duration;
 use tracing::error;
 use url::Url;
@@ -91,6 +95,10 @@ pub struct Prometheus {
     /// Current number of active upstream connections, labeled by upstream
     upstream_connections_current: Box<IntGaugeVec>,
 
+    /// Expiry(unix timestamp) of the certificate presented by tls backend,
+    /// labeled by upstream and address
+    upstream_cert_not_after: Box<IntGaugeVec>,
+
     /// Histogram of TCP connection times to upstream servers in seconds, labeled by upstream
     upstream_tcp_connect_time: Box<HistogramVec>,
 
@@ -137,6 +145,53 @@ pub struct Prometheus {
 /// Milliseconds to seconds conversion factor
 const SECOND: f64 = 1000.0;
 
+/// The metric name prefix, it's removed from the snapshot
+const METRIC_PREFIX: &str = "pingap_";
+
+/// The prometheus metrics of servers, they're shared with the admin
+/// api to export the snapshot as json.
+static PROMETHEUS_LIST: LazyLock<RwLock<Vec<(String, Arc<Prometheus>)>>> =
+    LazyLock::new(|| RwLock::new(vec![]));
+
+/// Registers the prometheus metrics of server, the previous one of the
+/// same server is replaced.
+pub fn register_prometheus(server: &str, prometheus: Arc<Prometheus>) {
+    if let Ok(mut list) = PROMETHEUS_LIST.write() {
+        list.retain(|(name, _)| name != server);
+        list.push((server.to_string(), prometheus));
+    }
+}
+
+/// Returns the snapshot of the metrics of all registered servers.
+pub fn get_metrics_snapshot() -> MetricsSnapshot {
+    let mut snapshot = MetricsSnapshot::default();
+    let Ok(list) = PROMETHEUS_LIST.read() else {
+        return snapshot;
+    };
+    for (_, prometheus) in list.iter() {
+        prometheus.fill_snapshot(&mut snapshot);
+    }
+    snapshot
+}
+
+/// Returns the group of metric by its name.
+fn get_metric_group<'a>(
+    snapshot: &'a mut MetricsSnapshot,
+    name: &str,
+) -> &'a mut MetricGroup {
+    if name.starts_with("http_") {
+        &mut snapshot.requests
+    } else if name.starts_with("upstream_") {
+        &mut snapshot.upstreams
+    } else if name.starts_with("cache_") || name.starts_with("compression_") {
+        &mut snapshot.cache
+    } else if name == "memory" || name == "fd_count" {
+        &mut snapshot.process
+    } else {
+        &mut snapshot.connections
+    }
+}
+
 impl Prometheus {
     /// Records metrics at the start of request processing.
     ///
@@ -263,6 +318,19 @@ impl Prometheus {
                     .with_label_values(upstream_labels)
                     .set(count as i64);
             }
+            if let Some(not_after) = ctx
+                .upstream
+                .upstream_instance
+                .as_ref()
+                .and_then(|up| up.cert_not_after(&ctx.upstream.address))
+            {
+                self.upstream_cert_not_after
+                    .with_label_values(&[
+                        upstream.as_ref(),
+                        ctx.upstream.address.as_str(),
+                    ])
+                    .set(not_after);
+            }
             // upstream stats
             if let Some(upstream_tcp_connect_time) =
                 ctx.timing.upstream_tcp_connect
@@ -338,6 +406,57 @@ impl Prometheus {
         self.r.gather()
     }
 
+    /// Fills the snapshot with the gathered metrics, the samples of
+    /// the same metric are appended.
+    fn fill_snapshot(&self, snapshot: &mut MetricsSnapshot) {
+        for family in self.gather() {
+            let name = family.get_name();
+            let name = name.strip_prefix(METRIC_PREFIX).unwrap_or(name);
+            let field_type = family.get_field_type();
+            let samples = family.get_metric().iter().map(|metric| {
+                let mut sample = MetricSample {
+                    labels: metric
+                        .get_label()
+                        .iter()
+                        .map(|label| {
+                            (
+                                label.get_name().to_string(),
+                                label.get_value().to_string(),
+                            )
+                        })
+                        .collect(),
+                    ..Default::default()
+                };
+                match field_type {
+                    MetricType::COUNTER => {
+                        sample.value = Some(metric.get_counter().get_value());
+                    },
+                    MetricType::GAUGE => {
+                        sample.value = Some(metric.get_gauge().get_value());
+                    },
+                    MetricType::HISTOGRAM => {
+                        let histogram = metric.get_histogram();
+                        sample.count = Some(histogram.get_sample_count());
+                        sample.sum = Some(histogram.get_sample_sum());
+                    },
+                    _ => {},
+                }
+                sample
+            });
+            get_metric_group(snapshot, name)
+                .entry(name.to_string())
+                .or_default()
+                .extend(samples);
+        }
+    }
+
+    /// Returns the structured snapshot of the metrics.
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        let mut snapshot = MetricsSnapshot::default();
+        self.fill_snapshot(&mut snapshot);
+        snapshot
+    }
+
     /// Formats all metrics in Prometheus text format for scraping.
     ///
     /// # Returns
@@ -585,8 +704,40 @@ macro_rules! register_metric {
     }};
 }
 
+/// The default buckets of http response time(second)
+const DEFAULT_RESPONSE_TIME_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Custom histogram buckets of the latency metrics,
+/// the default buckets are used if they're empty.
+#[derive(Debug, Default, Clone)]
+pub struct PrometheusBuckets {
+    /// Buckets of http response time(second)
+    pub response_time: Vec<f64>,
+    /// Buckets of upstream processing and response time(second)
+    pub upstream_time: Vec<f64>,
+}
+
+/// Returns the custom buckets, or the default buckets if it's empty.
+fn get_buckets<'a>(
+    buckets: &'a [f64],
+    default_buckets: &'a [f64],
+) -> &'a [f64] {
+    if buckets.is_empty() {
+        default_buckets
+    } else {
+        buckets
+    }
+}
+
 /// Create a prometheus metrics for server
-pub fn new_prometheus(server: &str) -> Result<Prometheus> {
+pub fn new_prometheus(
+    server: &str,
+    buckets: &PrometheusBuckets,
+) -> Result<Prometheus> {
+    let response_time_buckets =
+        get_buckets(&buckets.response_time, &DEFAULT_RESPONSE_TIME_BUCKETS);
     let r = Registry::new();
     let http_requests_total = register_metric!(
         r,
@@ -638,9 +789,7 @@ pub fn new_prometheus(server: &str) -> Result<Prometheus> {
         "pingap_http_response_time",
         "pingap http response time(second)",
         &["location"],
-        &[
-            0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0
-        ]
+        response_time_buckets
     )?;
     let http_sent = register_metric!(
         r,
@@ -691,6 +840,14 @@ pub fn new_prometheus(server: &str) -> Result<Prometheus> {
         "pingap current connections of upstream",
         &["upstream"]
     )?;
+    let upstream_cert_not_after = register_metric!(
+        r,
+        new_int_gauge_vec,
+        server,
+        "pingap_upstream_cert_not_after",
+        "pingap certificate expiry of upstream backend(unix timestamp)",
+        &["upstream", "address"]
+    )?;
     let upstream_tcp_connect_time = register_metric!(
         r,
         new_histogram_vec,
@@ -724,7 +881,10 @@ pub fn new_prometheus(server: &str) -> Result<Prometheus> {
         "pingap_upstream_processing_time",
         "pingap upstream processing time(second)",
         &["upstream"],
-        &[0.01, 0.02, 0.1, 0.5, 1.0, 5.0, 10.0]
+        get_buckets(
+            &buckets.upstream_time,
+            &[0.01, 0.02, 0.1, 0.5, 1.0, 5.0, 10.0]
+        )
     )?;
     let upstream_response_time = register_metric!(
         r,
@@ -733,7 +893,10 @@ pub fn new_prometheus(server: &str) -> Result<Prometheus> {
         "pingap_upstream_response_time",
         "pingap upstream response time(second)",
         &["upstream"],
-        &[0.005, 0.01, 0.05, 0.1, 0.5, 1.0]
+        get_buckets(
+            &buckets.upstream_time,
+            &[0.005, 0.01, 0.05, 0.1, 0.5, 1.0]
+        )
     )?;
     let cache_lookup_time = register_metric!(
         r,
@@ -825,6 +988,7 @@ pub fn new_prometheus(server: &str) -> Result<Prometheus> {
         tls_handshake_time,
         upstream_connections,
         upstream_connections_current,
+        upstream_cert_not_after,
         upstream_tcp_connect_time,
         upstream_tls_handshake_time,
         upstream_reuses,
@@ -872,7 +1036,8 @@ mod tests {
         let mut session = Session::new_h1(Box::new(mock_io));
         session.read_request().await.unwrap();
 
-        let p = new_prometheus("pingap").unwrap();
+        let p =
+            new_prometheus("pingap", &PrometheusBuckets::default()).unwrap();
         p.before("");
 
         p.after(
@@ -918,5 +1083,74 @@ mod tests {
         );
         let buf = p.metrics().unwrap();
         assert_eq!(225, std::str::from_utf8(&buf).unwrap().split('\n').count());
+
+        let snapshot = p.snapshot();
+        let requests_total = &snapshot.requests["http_requests_total"];
+        assert_eq!(1, requests_total.len());
+        assert_eq!(Some(1.0), requests_total[0].value);
+        assert_eq!(
+            r#"{"location": "", "server": "pingap"}"#,
+            format!("{:?}", requests_total[0].labels)
+        );
+        let response_time = &snapshot.requests["http_response_time"];
+        assert_eq!(Some(1), response_time[0].count);
+        assert_eq!(None, response_time[0].value);
+        assert_eq!(
+            true,
+            snapshot.upstreams.contains_key("upstream_connections")
+        );
+        assert_eq!(
+            true,
+            snapshot.upstreams.contains_key("upstream_cert_not_after")
+        );
+        assert_eq!(
+            true,
+            snapshot.connections.contains_key("connection_reuses")
+        );
+        assert_eq!(true, snapshot.connections.contains_key("tcp_count"));
+        assert_eq!(true, snapshot.cache.contains_key("cache_lookup_time"));
+        assert_eq!(true, snapshot.process.contains_key("memory"));
+
+        register_prometheus("pingap", Arc::new(p));
+        let snapshot = get_metrics_snapshot();
+        assert_eq!(true, snapshot.requests.contains_key("http_requests_total"));
+    }
+
+    #[test]
+    fn test_prometheus_buckets() {
+        let p = new_prometheus(
+            "pingap",
+            &PrometheusBuckets {
+                response_time: vec![0.001, 0.002],
+                upstream_time: vec![0.003],
+            },
+        )
+        .unwrap();
+        p.http_response_time
+            .with_label_values(&[""])
+            .observe(0.0015);
+        p.upstream_response_time
+            .with_label_values(&["upstream"])
+            .observe(0.001);
+        p.upstream_processing_time
+            .with_label_values(&["upstream"])
+            .observe(0.001);
+        let buf = p.metrics().unwrap();
+        let metrics = std::str::from_utf8(&buf).unwrap();
+        for line in [
+            r#"pingap_http_response_time_bucket{location="",server="pingap",le="0.001"} 0"#,
+            r#"pingap_http_response_time_bucket{location="",server="pingap",le="0.002"} 1"#,
+            r#"pingap_upstream_response_time_bucket{server="pingap",upstream="upstream",le="0.003"} 1"#,
+            r#"pingap_upstream_processing_time_bucket{server="pingap",upstream="upstream",le="0.003"} 1"#,
+        ] {
+            assert_eq!(true, metrics.contains(line), "{line}");
+        }
+        // the default buckets are replaced
+        assert_eq!(
+            false,
+            metrics.contains(
+                r#"pingap_http_response_time_bucket{location="",server="pingap",le="0.005"}"#
+            )
+        );
     }
 }
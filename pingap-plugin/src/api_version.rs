@@ -0,0 +1,421 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_bool_conf, get_hash_key, get_plugin_factory, get_str_conf,
+    get_str_slice_conf,
+};
+use async_trait::async_trait;
+use ctor::ctor;
+use http::uri::PathAndQuery;
+use http::{HeaderName, Uri, header};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, HttpResponse, Plugin, PluginStep, RequestPluginResult,
+    new_internal_error,
+};
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The variable of the api version selected for the request
+pub const API_VERSION_VARIABLE: &str = "api_version";
+
+/// Normalizes the version, e.g. `v2`, `V2` and `2` are all `2`.
+fn normalize_version(value: &str) -> Option<String> {
+    let value = value.trim().trim_matches('"');
+    let value = value
+        .strip_prefix(['v', 'V'])
+        .unwrap_or(value)
+        .to_ascii_lowercase();
+    if value.is_empty()
+        || !value.chars().all(|c| c.is_ascii_alphanumeric() || c == '.')
+    {
+        return None;
+    }
+    Some(value)
+}
+
+/// Gets the version of the path prefix, e.g. `/v2/users`, the length of
+/// the prefix is also returned for stripping.
+fn get_path_version(path: &str) -> Option<(String, usize)> {
+    let segment = path.strip_prefix('/')?.split('/').next()?;
+    let version = segment.strip_prefix(['v', 'V'])?;
+    if !version.starts_with(|c: char| c.is_ascii_digit()) {
+        return None;
+    }
+    Some((normalize_version(version)?, segment.len() + 1))
+}
+
+/// ApiVersion plugin routes the request to the upstream of api version,
+/// the version is specified by the path prefix (`/v2/users`) or the
+/// request header (`Accept: application/vnd.api+json;version=2`), both of
+/// them are normalized, so `v2` and `2` select the same upstream. The path
+/// prefix takes precedence over the header, and the default version is
+/// used if neither is specified. The request of unsupported version is
+/// rejected with 400.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// upstreams = ["1:api-v1", "2:api-v2"]
+/// default_version = "1"
+/// # the version param of accept header, or the value of other header
+/// header = "Accept"
+/// # whether to remove the version prefix of path for upstream
+/// strip_prefix = true
+/// ```
+pub struct ApiVersion {
+    /// The upstream of each normalized version
+    upstreams: HashMap<String, Arc<str>>,
+    /// The version used if the request doesn't specify it
+    default_version: Option<String>,
+    /// The header of version
+    header: HeaderName,
+    /// Whether to remove the version prefix of path
+    strip_prefix: bool,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for ApiVersion {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_error = |message: String| Error::Invalid {
+            category: PluginCategory::ApiVersion.to_string(),
+            message,
+        };
+        let mut upstreams = HashMap::new();
+        for item in get_str_slice_conf(value, "upstreams") {
+            let Some((version, upstream)) =
+                item.split_once(':').and_then(|(version, upstream)| {
+                    let upstream = upstream.trim();
+                    if upstream.is_empty() {
+                        return None;
+                    }
+                    Some((normalize_version(version)?, upstream))
+                })
+            else {
+                return Err(new_error(format!(
+                    "upstream({item}) is invalid, it should be version:upstream"
+                )));
+            };
+            upstreams.insert(version, upstream.into());
+        }
+        if upstreams.is_empty() {
+            return Err(new_error("upstreams can't be empty".to_string()));
+        }
+        let default_version = get_str_conf(value, "default_version");
+        let default_version = if default_version.is_empty() {
+            None
+        } else {
+            let version =
+                normalize_version(&default_version).unwrap_or_default();
+            if !upstreams.contains_key(&version) {
+                return Err(new_error(format!(
+                    "default version({default_version}) has no upstream"
+                )));
+            }
+            Some(version)
+        };
+        let mut header = get_str_conf(value, "header");
+        if header.is_empty() {
+            header = header::ACCEPT.to_string();
+        }
+        let header = HeaderName::from_str(&header).map_err(|e| {
+            new_error(format!("header({header}) is invalid, {e}"))
+        })?;
+
+        Ok(Self {
+            upstreams,
+            default_version,
+            header,
+            strip_prefix: get_bool_conf(value, "strip_prefix"),
+            hash_value,
+        })
+    }
+}
+
+impl ApiVersion {
+    /// Creates a new api version plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new api version plugin");
+        Self::try_from(params)
+    }
+    /// Gets the version of request header, the `version` param is used
+    /// for the accept header, otherwise the whole value is the version.
+    fn get_header_version(&self, session: &Session) -> Option<String> {
+        let value = session
+            .req_header()
+            .headers
+            .get(&self.header)?
+            .to_str()
+            .ok()?;
+        if self.header != header::ACCEPT {
+            return normalize_version(value);
+        }
+        value
+            .split(',')
+            .flat_map(|item| item.split(';').skip(1))
+            .find_map(|param| {
+                let (key, value) = param.split_once('=')?;
+                if !key.trim().eq_ignore_ascii_case("version") {
+                    return None;
+                }
+                normalize_version(value)
+            })
+    }
+}
+
+#[async_trait]
+impl Plugin for ApiVersion {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Selects the upstream by the api version of request, the version
+    /// prefix of path is removed if `strip_prefix` is enabled.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let path_version = get_path_version(session.req_header().uri.path());
+        let prefix_length = path_version.as_ref().map(|(_, size)| *size);
+        let Some(version) = path_version
+            .map(|(version, _)| version)
+            .or_else(|| self.get_header_version(session))
+            .or_else(|| self.default_version.clone())
+        else {
+            return Ok(RequestPluginResult::Skipped);
+        };
+        let Some(upstream) = self.upstreams.get(&version) else {
+            return Ok(RequestPluginResult::Respond(
+                HttpResponse::bad_request(format!(
+                    "api version({version}) is not supported"
+                )),
+            ));
+        };
+
+        if let Some(prefix_length) = prefix_length.filter(|_| self.strip_prefix)
+        {
+            let req_header = session.req_header();
+            let path = &req_header.uri.path()[prefix_length..];
+            let path = if path.is_empty() { "/" } else { path };
+            let path_and_query = match req_header.uri.query() {
+                Some(query) => format!("{path}?{query}"),
+                None => path.to_string(),
+            };
+            let mut parts = req_header.uri.clone().into_parts();
+            parts.path_and_query = Some(
+                PathAndQuery::from_str(&path_and_query)
+                    .map_err(|e| new_internal_error(400, e))?,
+            );
+            let uri = Uri::from_parts(parts)
+                .map_err(|e| new_internal_error(400, e))?;
+            session.req_header_mut().set_uri(uri);
+        }
+        ctx.add_variable(API_VERSION_VARIABLE, &version);
+        ctx.upstream.name = upstream.clone();
+        Ok(RequestPluginResult::Continue)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("api_version", |params| {
+        Ok(Arc::new(ApiVersion::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    fn new_plugin(conf: &str) -> ApiVersion {
+        ApiVersion::new(&toml::from_str::<PluginConf>(conf).unwrap()).unwrap()
+    }
+
+    async fn handle(
+        plugin: &ApiVersion,
+        path: &str,
+        header: Option<&str>,
+    ) -> (RequestPluginResult, Ctx, String) {
+        let header = header.map(|v| format!("{v}\r\n")).unwrap_or_default();
+        let input_header =
+            format!("GET {path} HTTP/1.1\r\nHost: pingap.io\r\n{header}\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let mut ctx = Ctx::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        let uri = session.req_header().uri.to_string();
+        (result, ctx, uri)
+    }
+
+    #[test]
+    fn test_normalize_version() {
+        assert_eq!(Some("2".to_string()), normalize_version("v2"));
+        assert_eq!(Some("2".to_string()), normalize_version(" V2 "));
+        assert_eq!(Some("2.1".to_string()), normalize_version("\"2.1\""));
+        assert_eq!(None, normalize_version("v"));
+        assert_eq!(None, normalize_version("2/3"));
+
+        assert_eq!(Some(("2".to_string(), 3)), get_path_version("/v2/users"));
+        assert_eq!(Some(("2".to_string(), 3)), get_path_version("/v2"));
+        assert_eq!(None, get_path_version("/users/v2"));
+        assert_eq!(None, get_path_version("/video"));
+    }
+
+    #[test]
+    fn test_api_version_params() {
+        let plugin = new_plugin(
+            r#"
+upstreams = ["v1:api-v1", "2:api-v2"]
+default_version = "v1"
+"#,
+        );
+        assert_eq!("api-v1", plugin.upstreams.get("1").unwrap().as_ref());
+        assert_eq!("api-v2", plugin.upstreams.get("2").unwrap().as_ref());
+        assert_eq!(Some("1".to_string()), plugin.default_version);
+        assert_eq!("accept", plugin.header.as_str());
+
+        let result = ApiVersion::try_from(&PluginConf::default());
+        assert_eq!(
+            "Plugin api_version invalid, message: upstreams can't be empty",
+            result.err().unwrap().to_string()
+        );
+
+        let result = ApiVersion::try_from(
+            &toml::from_str::<PluginConf>(r#"upstreams = ["api-v1"]"#).unwrap(),
+        );
+        assert_eq!(
+            "Plugin api_version invalid, message: upstream(api-v1) is invalid, it should be version:upstream",
+            result.err().unwrap().to_string()
+        );
+
+        let result = ApiVersion::try_from(
+            &toml::from_str::<PluginConf>(
+                r#"
+upstreams = ["1:api-v1"]
+default_version = "3"
+"#,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin api_version invalid, message: default version(3) has no upstream",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_api_version() {
+        let plugin = new_plugin(
+            r#"
+upstreams = ["1:api-v1", "2:api-v2"]
+default_version = "1"
+strip_prefix = true
+"#,
+        );
+
+        // the path prefix selects the upstream and is removed
+        let (result, ctx, uri) = handle(&plugin, "/v2/users?id=1", None).await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!("api-v2", ctx.upstream.name.as_ref());
+        assert_eq!(Some("2"), ctx.get_variable(API_VERSION_VARIABLE));
+        assert_eq!("/users?id=1", uri);
+
+        let (_, ctx, uri) = handle(&plugin, "/v1", None).await;
+        assert_eq!("api-v1", ctx.upstream.name.as_ref());
+        assert_eq!("/", uri);
+
+        // the version param of accept header
+        let (result, ctx, uri) = handle(
+            &plugin,
+            "/users",
+            Some("Accept: application/vnd.api+json;version=2"),
+        )
+        .await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!("api-v2", ctx.upstream.name.as_ref());
+        assert_eq!("/users", uri);
+
+        // the path prefix takes precedence over the header
+        let (_, ctx, _) = handle(
+            &plugin,
+            "/v1/users",
+            Some("Accept: application/vnd.api+json; version=2"),
+        )
+        .await;
+        assert_eq!("api-v1", ctx.upstream.name.as_ref());
+
+        // the default version is used if it's not specified
+        let (result, ctx, uri) =
+            handle(&plugin, "/users", Some("Accept: application/json")).await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!("api-v1", ctx.upstream.name.as_ref());
+        assert_eq!(Some("1"), ctx.get_variable(API_VERSION_VARIABLE));
+        assert_eq!("/users", uri);
+
+        // the unsupported version is rejected
+        let (result, ctx, _) = handle(&plugin, "/v3/users", None).await;
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("result is not respond");
+        };
+        assert_eq!(400, resp.status.as_u16());
+        assert_eq!(
+            "api version(3) is not supported",
+            std::str::from_utf8(resp.body.as_ref()).unwrap()
+        );
+        assert_eq!("", ctx.upstream.name.as_ref());
+
+        // the version of custom header without default version
+        let plugin = new_plugin(
+            r#"
+upstreams = ["1:api-v1", "2:api-v2"]
+header = "X-Api-Version"
+"#,
+        );
+        let (_, ctx, uri) =
+            handle(&plugin, "/v2/users", Some("X-Api-Version: 1")).await;
+        // the prefix isn't removed by default
+        assert_eq!("api-v2", ctx.upstream.name.as_ref());
+        assert_eq!("/v2/users", uri);
+        let (_, ctx, _) =
+            handle(&plugin, "/users", Some("X-Api-Version: v1")).await;
+        assert_eq!("api-v1", ctx.upstream.name.as_ref());
+        let (result, ctx, _) = handle(&plugin, "/users", None).await;
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+        assert_eq!("", ctx.upstream.name.as_ref());
+    }
+}
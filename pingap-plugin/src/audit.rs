@@ -0,0 +1,512 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::jwt::JWT_CLAIM_VARIABLE_PREFIX;
+use super::{
+    Error, get_hash_key, get_int_conf, get_plugin_factory, get_str_conf,
+    get_str_slice_conf,
+};
+use async_trait::async_trait;
+use bytesize::ByteSize;
+use ctor::ctor;
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{Ctx, Plugin, ResponsePluginResult, get_client_ip};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, error};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+const REDACTED: &str = "***";
+
+/// Audit record of a request, serialized as one json line.
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+struct AuditRecord {
+    timestamp: String,
+    client_ip: String,
+    /// User identity from the client certificate or the verified jwt claim
+    user: Option<String>,
+    method: String,
+    path: String,
+    query: Option<String>,
+    status: u16,
+    request_id: Option<String>,
+}
+
+impl AuditRecord {
+    /// Replaces the value of the fields with `***`.
+    fn redact(&mut self, fields: &[String]) {
+        for field in fields.iter() {
+            match field.as_str() {
+                "client_ip" => self.client_ip = REDACTED.to_string(),
+                "user" => {
+                    if self.user.is_some() {
+                        self.user = Some(REDACTED.to_string());
+                    }
+                },
+                "path" => self.path = REDACTED.to_string(),
+                "query" => {
+                    if self.query.is_some() {
+                        self.query = Some(REDACTED.to_string());
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+/// Sink of the audit records, it's independent of the access log.
+trait AuditSink: Send + Sync {
+    fn emit(&self, record: &AuditRecord);
+}
+
+/// File sink appends the records to the file, and rotates the file
+/// when its size exceeds the limit, only `max_files` rotated files are kept.
+struct FileSink {
+    path: PathBuf,
+    max_file_size: u64,
+    max_files: usize,
+    file: Mutex<Option<File>>,
+}
+
+impl FileSink {
+    fn rotate(&self) -> std::io::Result<()> {
+        let rotated_path = |index: usize| {
+            PathBuf::from(format!("{}.{index}", self.path.display()))
+        };
+        let oldest = rotated_path(self.max_files);
+        if oldest.exists() {
+            std::fs::remove_file(oldest)?;
+        }
+        for index in (1..self.max_files).rev() {
+            let path = rotated_path(index);
+            if path.exists() {
+                std::fs::rename(path, rotated_path(index + 1))?;
+            }
+        }
+        if self.max_files > 0 {
+            std::fs::rename(&self.path, rotated_path(1))?;
+        } else {
+            std::fs::remove_file(&self.path)?;
+        }
+        Ok(())
+    }
+    fn write(&self, line: &[u8]) -> std::io::Result<()> {
+        let mut file = self.file.lock().map_err(|e| {
+            std::io::Error::other(format!("audit file lock fail: {e}"))
+        })?;
+        if let Some(current) = file.as_ref() {
+            if self.max_file_size > 0
+                && current.metadata()?.len() + line.len() as u64
+                    > self.max_file_size
+            {
+                file.take();
+                self.rotate()?;
+            }
+        }
+        if file.is_none() {
+            *file = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?,
+            );
+        }
+        if let Some(current) = file.as_mut() {
+            current.write_all(line)?;
+        }
+        Ok(())
+    }
+}
+
+impl AuditSink for FileSink {
+    fn emit(&self, record: &AuditRecord) {
+        let mut line = serde_json::to_vec(record).unwrap_or_default();
+        line.push(b'\n');
+        if let Err(e) = self.write(&line) {
+            error!(
+                error = %e,
+                file = self.path.display().to_string(),
+                "write audit record fail"
+            );
+        }
+    }
+}
+
+/// Http sink posts each record as json to the endpoint in background.
+struct HttpSink {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl AuditSink for HttpSink {
+    fn emit(&self, record: &AuditRecord) {
+        let request = self.client.post(&self.url).json(record);
+        let url = self.url.clone();
+        tokio::spawn(async move {
+            let result = request
+                .send()
+                .await
+                .and_then(|resp| resp.error_for_status().map(|_| ()));
+            if let Err(e) = result {
+                error!(error = %e, url, "send audit record fail");
+            }
+        });
+    }
+}
+
+/// Audit plugin records the structured records of requests to a separate
+/// sink for compliance, the sensitive fields can be redacted.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// # file sink, rotated by size
+/// file = "/var/log/pingap/audit.log"
+/// max_file_size = "100MB"
+/// max_files = 7
+/// # or http sink
+/// # url = "https://audit.example.com/records"
+/// # jwt claim(verified by the jwt plugin) used as user identity if there
+/// # is no client certificate
+/// identity_claim = "sub"
+/// redact = ["client_ip", "query"]
+/// ```
+pub struct Audit {
+    sink: Arc<dyn AuditSink>,
+    /// Claim of the verified jwt used as user identity
+    identity_claim: String,
+    /// Fields to redact: client_ip, user, path, query
+    redact: Vec<String>,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for Audit {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::Audit.to_string(),
+            message,
+        };
+        let file = get_str_conf(value, "file");
+        let url = get_str_conf(value, "url");
+        let sink: Arc<dyn AuditSink> = if !file.is_empty() {
+            let max_file_size = get_str_conf(value, "max_file_size");
+            let max_file_size = if max_file_size.is_empty() {
+                0
+            } else {
+                ByteSize::from_str(&max_file_size)
+                    .map_err(new_invalid_error)?
+                    .as_u64()
+            };
+            Arc::new(FileSink {
+                path: PathBuf::from(pingap_util::resolve_path(&file)),
+                max_file_size,
+                max_files: get_int_conf(value, "max_files") as usize,
+                file: Mutex::new(None),
+            })
+        } else if !url.is_empty() {
+            let client = reqwest::Client::builder()
+                .timeout(Duration::from_secs(10))
+                .build()
+                .map_err(|e| new_invalid_error(e.to_string()))?;
+            Arc::new(HttpSink { url, client })
+        } else {
+            return Err(new_invalid_error(
+                "file or url of audit sink should be set".to_string(),
+            ));
+        };
+
+        let mut identity_claim = get_str_conf(value, "identity_claim");
+        if identity_claim.is_empty() {
+            identity_claim = "sub".to_string();
+        }
+
+        Ok(Self {
+            sink,
+            identity_claim,
+            redact: get_str_slice_conf(value, "redact"),
+            hash_value,
+        })
+    }
+}
+
+impl Audit {
+    /// Creates a new audit plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new audit plugin");
+        Self::try_from(params)
+    }
+
+    /// Gets the user identity from the client certificate, or the claim
+    /// of the jwt which has been verified by the jwt plugin.
+    fn get_identity(&self, session: &Session, ctx: &Ctx) -> Option<String> {
+        let ssl_identity = session
+            .digest()
+            .and_then(|digest| digest.ssl_digest.as_ref())
+            .and_then(|ssl_digest| {
+                ssl_digest
+                    .organization
+                    .clone()
+                    .or_else(|| ssl_digest.serial_number.clone())
+            });
+        if ssl_identity.is_some() {
+            return ssl_identity;
+        }
+        // the claim variables are only set after the signature is verified
+        ctx.get_variable(&format!(
+            "{JWT_CLAIM_VARIABLE_PREFIX}{}",
+            self.identity_claim
+        ))
+        .map(|value| value.to_string())
+    }
+
+    fn new_record(
+        &self,
+        session: &Session,
+        ctx: &Ctx,
+        status: u16,
+    ) -> AuditRecord {
+        let req_header = session.req_header();
+        let mut record = AuditRecord {
+            timestamp: chrono::Local::now().to_rfc3339(),
+            client_ip: ctx
+                .conn
+                .client_ip
+                .clone()
+                .unwrap_or_else(|| get_client_ip(session)),
+            user: self.get_identity(session, ctx),
+            method: req_header.method.to_string(),
+            path: req_header.uri.path().to_string(),
+            query: req_header.uri.query().map(|query| query.to_string()),
+            status,
+            request_id: ctx.state.request_id.clone(),
+        };
+        record.redact(&self.redact);
+        record
+    }
+}
+
+#[async_trait]
+impl Plugin for Audit {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Emits the audit record of the request when the response is sent.
+    #[inline]
+    async fn handle_response(
+        &self,
+        session: &mut Session,
+        ctx: &mut Ctx,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<ResponsePluginResult> {
+        let record =
+            self.new_record(session, ctx, upstream_response.status.as_u16());
+        self.sink.emit(&record);
+        Ok(ResponsePluginResult::Unchanged)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory()
+        .register("audit", |params| Ok(Arc::new(Audit::new(params)?)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jwt::JwtAuth;
+    use crate::test_util;
+    use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep, RequestPluginResult};
+    use pretty_assertions::assert_eq;
+
+    #[derive(Default)]
+    struct MockSink {
+        records: Mutex<Vec<AuditRecord>>,
+    }
+
+    impl AuditSink for MockSink {
+        fn emit(&self, record: &AuditRecord) {
+            self.records.lock().unwrap().push(record.clone());
+        }
+    }
+
+    #[test]
+    fn test_audit_params() {
+        let result = Audit::try_from(&PluginConf::default());
+        assert_eq!(
+            "Plugin audit invalid, message: file or url of audit sink should be set",
+            result.err().unwrap().to_string()
+        );
+
+        let audit = Audit::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+url = "http://127.0.0.1:3000/audit"
+redact = ["query"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!("sub", audit.identity_claim);
+        assert_eq!(vec!["query".to_string()], audit.redact);
+    }
+
+    #[tokio::test]
+    async fn test_audit() {
+        let sink = Arc::new(MockSink::default());
+        let audit = Audit {
+            sink: sink.clone(),
+            identity_claim: "sub".to_string(),
+            redact: vec!["query".to_string()],
+            hash_value: "".to_string(),
+        };
+        let headers = ["X-Forwarded-For: 1.1.1.1"].join("\r\n");
+        let input_header =
+            format!("GET /users?token=abc HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let mut ctx = Ctx {
+            state: pingap_core::RequestState {
+                request_id: Some("abc".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        // the claim variable set by the jwt plugin after verification
+        ctx.add_variable("jwt_sub", "tree");
+        let mut upstream_response = ResponseHeader::build(403, None).unwrap();
+        let result = audit
+            .handle_response(&mut session, &mut ctx, &mut upstream_response)
+            .await
+            .unwrap();
+        assert_eq!(ResponsePluginResult::Unchanged, result);
+
+        let records = sink.records.lock().unwrap();
+        assert_eq!(1, records.len());
+        let record = &records[0];
+        assert_eq!(false, record.timestamp.is_empty());
+        assert_eq!(
+            AuditRecord {
+                timestamp: record.timestamp.clone(),
+                client_ip: "1.1.1.1".to_string(),
+                user: Some("tree".to_string()),
+                method: "GET".to_string(),
+                path: "/users".to_string(),
+                query: Some(REDACTED.to_string()),
+                status: 403,
+                request_id: Some("abc".to_string()),
+            },
+            record.clone()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_audit_forged_identity() {
+        let sink = Arc::new(MockSink::default());
+        let audit = Audit {
+            sink: sink.clone(),
+            identity_claim: "sub".to_string(),
+            redact: vec![],
+            hash_value: "".to_string(),
+        };
+        let jwt = JwtAuth::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+secret = "123123"
+header = "Authorization"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let header = URL_SAFE_NO_PAD.encode(r#"{"alg":"HS256","typ":"JWT"}"#);
+        let payload = URL_SAFE_NO_PAD.encode(r#"{"sub":"admin"}"#);
+        // the unsigned and forged tokens
+        for token in [
+            format!("{header}.{payload}."),
+            format!("{header}.{payload}.sig"),
+        ] {
+            let authorization = format!("Authorization: Bearer {token}");
+            let headers = ["{authorization}"].join("\r\n");
+            let input_header =
+                format!("GET /users?token=abc HTTP/1.1\r\n{headers}\r\n\r\n");
+            let mut session = test_util::new_session(&input_header).await;
+            let mut ctx = Ctx::default();
+            let result = jwt
+                .handle_request(PluginStep::Request, &mut session, &mut ctx)
+                .await
+                .unwrap();
+            assert_eq!(false, result == RequestPluginResult::Continue);
+
+            let mut upstream_response =
+                ResponseHeader::build(401, None).unwrap();
+            audit
+                .handle_response(&mut session, &mut ctx, &mut upstream_response)
+                .await
+                .unwrap();
+        }
+        let records = sink.records.lock().unwrap();
+        assert_eq!(2, records.len());
+        for record in records.iter() {
+            assert_eq!(None, record.user);
+        }
+    }
+
+    #[test]
+    fn test_file_sink() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("audit.log");
+        let sink = FileSink {
+            path: path.clone(),
+            max_file_size: 200,
+            max_files: 1,
+            file: Mutex::new(None),
+        };
+        let record = AuditRecord {
+            timestamp: "2025-01-01T00:00:00+00:00".to_string(),
+            client_ip: "1.1.1.1".to_string(),
+            method: "GET".to_string(),
+            path: "/".to_string(),
+            status: 200,
+            ..Default::default()
+        };
+        let line = format!("{}\n", serde_json::to_string(&record).unwrap());
+        sink.emit(&record);
+        assert_eq!(line, std::fs::read_to_string(&path).unwrap());
+
+        // the file is rotated when it exceeds the max size
+        for _ in 0..3 {
+            sink.emit(&record);
+        }
+        let rotated = dir.path().join("audit.log.1");
+        assert_eq!(true, rotated.exists());
+        assert_eq!(false, dir.path().join("audit.log.2").exists());
+        assert_eq!(true, std::fs::read_to_string(&path).unwrap().len() <= 200);
+    }
+}
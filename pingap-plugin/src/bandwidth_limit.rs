@@ -0,0 +1,189 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Error, get_hash_key, get_plugin_factory, get_str_conf};
+use async_trait::async_trait;
+use bytesize::ByteSize;
+use ctor::ctor;
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{BandwidthThrottle, Ctx, Plugin, ResponsePluginResult};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// BandwidthLimit plugin throttles the response body written to the client,
+/// it prevents a single download from saturating the link.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// rate = "1MB"    # bytes per second
+/// after = "2MB"   # the first bytes sent without throttling
+/// ```
+pub struct BandwidthLimit {
+    /// Bytes per second
+    rate: u64,
+    /// The first bytes sent without throttling
+    after: u64,
+    /// Unique identifier for this plugin instance
+    hash_value: String,
+}
+
+fn get_byte_size_conf(value: &PluginConf, key: &str) -> Result<u64> {
+    let size = get_str_conf(value, key);
+    if size.is_empty() {
+        return Ok(0);
+    }
+    ByteSize::from_str(&size)
+        .map(|size| size.as_u64())
+        .map_err(|e| Error::Invalid {
+            category: PluginCategory::BandwidthLimit.to_string(),
+            message: format!("{key}: {e}"),
+        })
+}
+
+impl TryFrom<&PluginConf> for BandwidthLimit {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let rate = get_byte_size_conf(value, "rate")?;
+        if rate == 0 {
+            return Err(Error::Invalid {
+                category: PluginCategory::BandwidthLimit.to_string(),
+                message: "rate should be greater than 0".to_string(),
+            });
+        }
+
+        Ok(Self {
+            rate,
+            after: get_byte_size_conf(value, "after")?,
+            hash_value,
+        })
+    }
+}
+
+impl BandwidthLimit {
+    /// Creates a new bandwidth limit plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new bandwidth limit plugin");
+        Self::try_from(params)
+    }
+}
+
+#[async_trait]
+impl Plugin for BandwidthLimit {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Sets the bandwidth throttle of the request, the response body
+    /// chunks are delayed by the proxy to keep the configured rate.
+    #[inline]
+    async fn handle_response(
+        &self,
+        _session: &mut Session,
+        ctx: &mut Ctx,
+        _upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<ResponsePluginResult> {
+        ctx.set_bandwidth_throttle(BandwidthThrottle::new(
+            self.rate, self.after,
+        ));
+        Ok(ResponsePluginResult::Unchanged)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("bandwidth_limit", |params| {
+        Ok(Arc::new(BandwidthLimit::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingap_config::PluginConf;
+    use pingap_core::Ctx;
+    use pingora::http::ResponseHeader;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use std::time::Duration;
+    use tokio_test::io::Builder;
+
+    #[test]
+    fn test_bandwidth_limit_params() {
+        let params = BandwidthLimit::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+rate = "1MiB"
+after = "2MiB"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(1024 * 1024, params.rate);
+        assert_eq!(2 * 1024 * 1024, params.after);
+
+        let result = BandwidthLimit::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+after = "2MiB"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin bandwidth_limit invalid, message: rate should be greater than 0",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bandwidth_limit() {
+        let plugin = BandwidthLimit::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+rate = "1KiB"
+after = "1KiB"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let input_header = "GET /vicanso/pingap HTTP/1.1\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+
+        let mut ctx = Ctx::default();
+        let mut upstream_response =
+            ResponseHeader::build_no_case(200, None).unwrap();
+        let result = plugin
+            .handle_response(&mut session, &mut ctx, &mut upstream_response)
+            .await
+            .unwrap();
+        assert_eq!(ResponsePluginResult::Unchanged, result);
+
+        assert_eq!(None, ctx.get_bandwidth_throttle_delay(1024));
+        let delay = ctx.get_bandwidth_throttle_delay(512).unwrap();
+        assert_eq!(true, delay <= Duration::from_millis(500));
+        assert_eq!(true, delay > Duration::from_millis(400));
+    }
+}
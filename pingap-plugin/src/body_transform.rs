@@ -0,0 +1,554 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_bool_conf, get_hash_key, get_plugin_factory, get_str_conf,
+};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use bytesize::ByteSize;
+use ctor::ctor;
+use http::{HeaderValue, StatusCode, header};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, HTTP_HEADER_CONTENT_JSON, HTTP_HEADER_TRANSFER_CHUNKED, HttpResponse,
+    ModifyResponseBody, Plugin, PluginStep, RequestPluginResult,
+    ResponseBodyPluginResult, ResponsePluginResult, write_continue_response,
+};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+const PLUGIN_ID: &str = "_body_transform_";
+
+/// The body read by the plugin is kept in the retry buffer of session,
+/// the buffer is limited to 64KB by pingora.
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+const FORM_CONTENT_TYPE: &str = "application/x-www-form-urlencoded";
+
+/// Key convention of the flattened nested json
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum KeyStyle {
+    /// `user[name]`, `tags[0]`
+    Bracket,
+    /// `user.name`, `tags.0`
+    Dot,
+}
+
+impl KeyStyle {
+    fn join(&self, prefix: &str, key: &str) -> String {
+        if prefix.is_empty() {
+            return key.to_string();
+        }
+        match self {
+            KeyStyle::Bracket => format!("{prefix}[{key}]"),
+            KeyStyle::Dot => format!("{prefix}.{key}"),
+        }
+    }
+}
+
+/// Flattens the json value to the key/value pairs of form.
+fn flatten_json(
+    prefix: &str,
+    value: &Value,
+    style: KeyStyle,
+    pairs: &mut Vec<(String, String)>,
+) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter() {
+                flatten_json(&style.join(prefix, key), value, style, pairs);
+            }
+        },
+        Value::Array(values) => {
+            for (index, value) in values.iter().enumerate() {
+                flatten_json(
+                    &style.join(prefix, &index.to_string()),
+                    value,
+                    style,
+                    pairs,
+                );
+            }
+        },
+        Value::String(value) => pairs.push((prefix.to_string(), value.clone())),
+        Value::Null => pairs.push((prefix.to_string(), "".to_string())),
+        _ => pairs.push((prefix.to_string(), value.to_string())),
+    }
+}
+
+/// Converts the json object to the form-encoded body.
+fn json_to_form(body: &[u8], style: KeyStyle) -> Result<Bytes, String> {
+    let value: Value = serde_json::from_slice(body)
+        .map_err(|e| format!("Request body is not valid json, {e}"))?;
+    if !value.is_object() {
+        return Err("Request body should be a json object".to_string());
+    }
+    let mut pairs = vec![];
+    flatten_json("", &value, style, &mut pairs);
+    let mut serializer = url::form_urlencoded::Serializer::new(String::new());
+    serializer.extend_pairs(pairs);
+    Ok(Bytes::from(serializer.finish()))
+}
+
+/// Converts the form-encoded body to the flat json object,
+/// the values of the repeated key are collected into an array.
+fn form_to_json(body: &[u8]) -> Bytes {
+    let mut map = Map::new();
+    for (key, value) in url::form_urlencoded::parse(body) {
+        let value = Value::String(value.to_string());
+        match map.get_mut(key.as_ref()) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(current) => {
+                let first = current.take();
+                *current = Value::Array(vec![first, value]);
+            },
+            None => {
+                map.insert(key.to_string(), value);
+            },
+        }
+    }
+    Bytes::from(Value::Object(map).to_string())
+}
+
+/// Buffers the form-encoded response body and converts it to json.
+struct FormToJson {
+    buffer: BytesMut,
+}
+
+impl ModifyResponseBody for FormToJson {
+    fn handle(
+        &mut self,
+        _session: &Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+    ) -> pingora::Result<()> {
+        if let Some(data) = body {
+            self.buffer.extend(&data[..]);
+            data.clear();
+        }
+        if end_of_stream {
+            *body = Some(form_to_json(&self.buffer));
+        }
+        Ok(())
+    }
+    fn name(&self) -> String {
+        "form_to_json".to_string()
+    }
+    fn buffering(&self) -> bool {
+        true
+    }
+}
+
+/// BodyTransform plugin converts the json request body to the form-encoded
+/// body for legacy backends, and optionally converts the form-encoded
+/// response body back to json.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// # key convention of nested json: "bracket"(user[name]) or "dot"(user.name)
+/// key_style = "bracket"
+/// max_body_size = "64KB"
+/// # convert the form-encoded response to json
+/// response = true
+/// ```
+pub struct BodyTransform {
+    key_style: KeyStyle,
+    /// Maximum size of the body to read
+    max_body_size: usize,
+    /// Whether to convert the form-encoded response to json
+    response: bool,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for BodyTransform {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::BodyTransform.to_string(),
+            message,
+        };
+        let key_style = match get_str_conf(value, "key_style").as_str() {
+            "" | "bracket" => KeyStyle::Bracket,
+            "dot" => KeyStyle::Dot,
+            style => {
+                return Err(new_invalid_error(format!(
+                    "key style({style}) should be bracket or dot"
+                )));
+            },
+        };
+
+        let max_body_size = get_str_conf(value, "max_body_size");
+        let max_body_size = if max_body_size.is_empty() {
+            MAX_BODY_SIZE
+        } else {
+            ByteSize::from_str(&max_body_size)
+                .map_err(new_invalid_error)?
+                .as_u64() as usize
+        };
+        if max_body_size > MAX_BODY_SIZE {
+            return Err(new_invalid_error(format!(
+                "max body size should be less than or equal to {}",
+                ByteSize(MAX_BODY_SIZE as u64)
+            )));
+        }
+
+        Ok(Self {
+            key_style,
+            max_body_size,
+            response: get_bool_conf(value, "response"),
+            hash_value,
+        })
+    }
+}
+
+impl BodyTransform {
+    /// Creates a new body transform plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new body transform plugin");
+        Self::try_from(params)
+    }
+}
+
+/// Returns true if the content type of headers starts with the value.
+fn is_content_type(headers: &http::HeaderMap, value: &str) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| {
+            content_type.to_lowercase().starts_with(value)
+        })
+}
+
+#[async_trait]
+impl Plugin for BodyTransform {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Reads the json request body and replaces it with the form-encoded
+    /// body, the content type and length are updated.
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request
+            || !is_content_type(
+                &session.req_header().headers,
+                "application/json",
+            )
+        {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let too_large = || {
+            RequestPluginResult::Respond(HttpResponse {
+                status: StatusCode::PAYLOAD_TOO_LARGE,
+                body: Bytes::from(format!(
+                    "Request body is too large, max:{}",
+                    ByteSize(self.max_body_size as u64)
+                )),
+                ..Default::default()
+            })
+        };
+        let content_length = session
+            .req_header()
+            .headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or_default();
+        if content_length > self.max_body_size {
+            return Ok(too_large());
+        }
+
+        write_continue_response(session, ctx).await?;
+        session.enable_retry_buffering();
+        let mut body = BytesMut::new();
+        while let Some(chunk) = session.read_request_body().await? {
+            if body.len() + chunk.len() > self.max_body_size {
+                return Ok(too_large());
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        let form = match json_to_form(&body, self.key_style) {
+            Ok(form) => form,
+            Err(message) => {
+                return Ok(RequestPluginResult::Respond(HttpResponse {
+                    status: StatusCode::BAD_REQUEST,
+                    body: Bytes::from(message),
+                    ..Default::default()
+                }));
+            },
+        };
+        let req_header = session.req_header_mut();
+        req_header.remove_header(&header::TRANSFER_ENCODING);
+        req_header.insert_header(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static(FORM_CONTENT_TYPE),
+        )?;
+        req_header.insert_header(header::CONTENT_LENGTH, form.len())?;
+        ctx.set_request_body(form);
+
+        Ok(RequestPluginResult::Continue)
+    }
+
+    /// Converts the form-encoded response to json if it's enabled.
+    async fn handle_response(
+        &self,
+        _session: &mut Session,
+        ctx: &mut Ctx,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<ResponsePluginResult> {
+        if !self.response
+            || !is_content_type(&upstream_response.headers, FORM_CONTENT_TYPE)
+            || upstream_response
+                .headers
+                .contains_key(header::CONTENT_ENCODING)
+            || !ctx.can_buffer_response(&upstream_response.headers)?
+        {
+            return Ok(ResponsePluginResult::Unchanged);
+        }
+        upstream_response.remove_header(&header::CONTENT_LENGTH);
+        let json = HTTP_HEADER_CONTENT_JSON.clone();
+        upstream_response.insert_header(json.0, json.1)?;
+        let chunked = HTTP_HEADER_TRANSFER_CHUNKED.clone();
+        upstream_response.insert_header(chunked.0, chunked.1)?;
+        ctx.add_modify_body_handler(
+            PLUGIN_ID,
+            Box::new(FormToJson {
+                buffer: BytesMut::new(),
+            }),
+        );
+        Ok(ResponsePluginResult::Modified)
+    }
+
+    fn handle_response_body(
+        &self,
+        session: &mut Session,
+        ctx: &mut Ctx,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+    ) -> pingora::Result<ResponseBodyPluginResult> {
+        let Some(modifier) = ctx.get_modify_body_handler(PLUGIN_ID) else {
+            return Ok(ResponseBodyPluginResult::Unchanged);
+        };
+        modifier.handle(session, body, end_of_stream)?;
+        if end_of_stream {
+            Ok(ResponseBodyPluginResult::FullyReplaced)
+        } else {
+            Ok(ResponseBodyPluginResult::PartialReplaced)
+        }
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("body_transform", |params| {
+        Ok(Arc::new(BodyTransform::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_body_transform_params() {
+        let transform = BodyTransform::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+key_style = "dot"
+response = true
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(KeyStyle::Dot, transform.key_style);
+        assert_eq!(MAX_BODY_SIZE, transform.max_body_size);
+        assert_eq!(true, transform.response);
+
+        let result = BodyTransform::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+key_style = "colon"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin body_transform invalid, message: key style(colon) should be bracket or dot",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_json_to_form() {
+        let body = r#"{"admin":false,"name":"pingap","remark":null,"user":{"age":1,"tags":["a","b"]}}"#;
+        assert_eq!(
+            Bytes::from(
+                "admin=false&name=pingap&remark=&user%5Bage%5D=1&user%5Btags%5D%5B0%5D=a&user%5Btags%5D%5B1%5D=b"
+            ),
+            json_to_form(body.as_bytes(), KeyStyle::Bracket).unwrap()
+        );
+        assert_eq!(
+            Bytes::from(
+                "admin=false&name=pingap&remark=&user.age=1&user.tags.0=a&user.tags.1=b"
+            ),
+            json_to_form(body.as_bytes(), KeyStyle::Dot).unwrap()
+        );
+        assert_eq!(
+            "Request body should be a json object",
+            json_to_form(b"[1]", KeyStyle::Dot).err().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_form_to_json() {
+        assert_eq!(
+            Bytes::from(r#"{"name":"pingap","tags":["a","b"]}"#),
+            form_to_json(b"name=pingap&tags=a&tags=b")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_body_transform() {
+        let transform = BodyTransform::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+response = true
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        let body = r#"{"name":"pingap","user":{"age":1}}"#;
+        let headers = ["Content-Type: application/json", "Content-Length: 34"]
+            .join("\r\n");
+        let input_header =
+            format!("POST /users HTTP/1.1\r\n{headers}\r\n\r\n{body}");
+        let mut session = test_util::new_session(&input_header).await;
+        let mut ctx = Ctx::default();
+        let result = transform
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        let form = "name=pingap&user%5Bage%5D=1";
+        assert_eq!(Some(&Bytes::from(form)), ctx.get_request_body());
+        let headers = &session.req_header().headers;
+        assert_eq!(
+            FORM_CONTENT_TYPE,
+            headers.get(header::CONTENT_TYPE).unwrap().to_str().unwrap()
+        );
+        assert_eq!(
+            form.len().to_string(),
+            headers
+                .get(header::CONTENT_LENGTH)
+                .unwrap()
+                .to_str()
+                .unwrap()
+        );
+
+        // other content type is skipped
+        let headers =
+            ["Content-Type: text/plain", "Content-Length: 6"].join("\r\n");
+        let input_header =
+            format!("POST /users HTTP/1.1\r\n{headers}\r\n\r\npingap");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = transform
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+
+        // invalid json
+        let body = "{";
+        let headers = ["Content-Type: application/json", "Content-Length: 1"]
+            .join("\r\n");
+        let input_header =
+            format!("POST /users HTTP/1.1\r\n{headers}\r\n\r\n{body}");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = transform
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("result is not Respond");
+        };
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status);
+
+        // form-encoded response is converted to json
+        let headers =
+            ["Content-Type: text/plain", "Content-Length: 0"].join("\r\n");
+        let input_header = format!("POST /users HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let mut ctx = Ctx::default();
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        upstream_response
+            .insert_header(header::CONTENT_TYPE, FORM_CONTENT_TYPE)
+            .unwrap();
+        upstream_response
+            .insert_header(header::CONTENT_LENGTH, "14")
+            .unwrap();
+        let result = transform
+            .handle_response(&mut session, &mut ctx, &mut upstream_response)
+            .await
+            .unwrap();
+        assert_eq!(ResponsePluginResult::Modified, result);
+        assert_eq!(
+            true,
+            upstream_response
+                .headers
+                .get(header::CONTENT_LENGTH)
+                .is_none()
+        );
+        let mut body = Some(Bytes::from("a=&name=pingap"));
+        let result = transform
+            .handle_response_body(&mut session, &mut ctx, &mut body, false)
+            .unwrap();
+        assert_eq!(ResponseBodyPluginResult::PartialReplaced, result);
+        assert_eq!(Some(Bytes::new()), body);
+        let mut body = None;
+        let result = transform
+            .handle_response_body(&mut session, &mut ctx, &mut body, true)
+            .unwrap();
+        assert_eq!(ResponseBodyPluginResult::FullyReplaced, result);
+        assert_eq!(Some(Bytes::from(r#"{"a":"","name":"pingap"}"#)), body);
+    }
+}
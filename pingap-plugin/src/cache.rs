@@ -92,6 +92,9 @@ pub struct Cache {
     headers: Option<Vec<String>>,
     // Whether to check the cache-control header, if not exist the response will not be cached.
     check_cache_control: bool,
+    // Whether to generate ETag from the body for the cached response without validators,
+    // the conditional requests of cache hits are responded with 304.
+    etag: bool,
     // IP-based access control for cache purge operations
     purge_ip_rules: IpRules,
     // Optional regex pattern to skip caching for certain requests
@@ -283,6 +286,7 @@ impl TryFrom<&PluginConf> for Cache {
             headers,
             purge_ip_rules,
             check_cache_control: get_bool_conf(value, "check_cache_control"),
+            etag: get_bool_conf(value, "etag"),
             skip,
         };
         Ok(params)
@@ -427,6 +431,7 @@ impl Plugin for Cache {
         if let Some(cache_info) = &mut ctx.cache {
             cache_info.max_ttl = self.max_ttl;
             cache_info.check_cache_control = self.check_cache_control;
+            cache_info.etag = self.etag;
         }
 
         // Enable caching for this session with configured components
@@ -482,12 +487,14 @@ lock = "2s"
 max_file_size = "100kb"
 predictor = true
 max_ttl = "1m"
+etag = true
 "###,
             )
             .unwrap(),
         )
         .unwrap();
         assert_eq!(true, params.eviction.is_some());
+        assert_eq!(true, params.etag);
         assert_eq!(
             r#"Some(["Accept-Encoding"])"#,
             format!("{:?}", params.headers)
@@ -0,0 +1,410 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Error, get_hash_key, get_plugin_factory, get_str_conf};
+use async_trait::async_trait;
+use ctor::ctor;
+use http::HeaderValue;
+use http::header::{self, HeaderName};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{Ctx, Plugin, ResponsePluginResult};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::sync::Arc;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+const SURROGATE_CONTROL: &str = "surrogate-control";
+
+/// The directives which can't be used together, the configured one
+/// replaces the other one of upstream in merge mode.
+const EXCLUSIVE_DIRECTIVES: [(&str, &str); 1] = [("public", "private")];
+
+/// How the configured directives are applied to the upstream response
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    /// Replace the headers of upstream response
+    Override,
+    /// Merge with the headers of upstream response, the configured
+    /// directive wins if both are set
+    Merge,
+}
+
+/// Splits the header value to the directives
+fn split_directives(value: &str) -> Vec<&str> {
+    value
+        .split(',')
+        .map(|item| item.trim())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Returns the lowercase name of directive, e.g. `max-age` of `max-age=300`
+fn get_directive_name(directive: &str) -> String {
+    directive
+        .split_once('=')
+        .map_or(directive, |(name, _)| name)
+        .trim()
+        .to_lowercase()
+}
+
+/// Merges the cache directives, the directive of upstream is kept
+/// unless it's configured or conflicts with the configured one.
+fn merge_cache_directives(upstream: &str, configured: &str) -> String {
+    let configured = split_directives(configured);
+    let mut names: Vec<String> = configured
+        .iter()
+        .map(|item| get_directive_name(item))
+        .collect();
+    for (a, b) in EXCLUSIVE_DIRECTIVES {
+        if names.iter().any(|name| name == a) {
+            names.push(b.to_string());
+        } else if names.iter().any(|name| name == b) {
+            names.push(a.to_string());
+        }
+    }
+    let mut directives: Vec<&str> = split_directives(upstream)
+        .into_iter()
+        .filter(|item| !names.contains(&get_directive_name(item)))
+        .collect();
+    directives.extend(configured);
+    directives.join(", ")
+}
+
+/// Merges the vary values, the duplicate values are ignored.
+fn merge_vary(upstream: &str, configured: &str) -> String {
+    let mut values = split_directives(upstream);
+    if values.contains(&"*") {
+        return "*".to_string();
+    }
+    for value in split_directives(configured) {
+        if value == "*" {
+            return "*".to_string();
+        }
+        if !values.iter().any(|item| item.eq_ignore_ascii_case(value)) {
+            values.push(value);
+        }
+    }
+    values.join(", ")
+}
+
+/// CacheDirectives plugin sets the `Cache-Control`, `Surrogate-Control`
+/// and `Vary` headers of response, it's useful for the CDN-fronted
+/// deployments which don't rely on the cache headers of origin.
+/// As plugins are bound to locations, e.g. `public, max-age=300` can be
+/// forced for static paths and `no-store` for apis.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// cache_control = "public, max-age=300"
+/// surrogate_control = "max-age=3600"
+/// vary = "Accept-Encoding"
+/// # override: replace the headers of upstream(default)
+/// # merge: merge with the headers of upstream, the configured one wins
+/// mode = "override"
+/// ```
+pub struct CacheDirectives {
+    /// The configured headers, the empty ones are ignored
+    headers: Vec<(HeaderName, String)>,
+    mode: Mode,
+    /// Unique identifier for this plugin instance
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for CacheDirectives {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::CacheDirectives.to_string(),
+            message,
+        };
+        let mut headers = vec![];
+        for (key, name) in [
+            ("cache_control", header::CACHE_CONTROL),
+            (
+                "surrogate_control",
+                HeaderName::from_static(SURROGATE_CONTROL),
+            ),
+            ("vary", header::VARY),
+        ] {
+            let header_value =
+                split_directives(&get_str_conf(value, key)).join(", ");
+            if header_value.is_empty() {
+                continue;
+            }
+            HeaderValue::from_str(&header_value)
+                .map_err(|e| new_invalid_error(format!("{key}: {e}")))?;
+            headers.push((name, header_value));
+        }
+        if headers.is_empty() {
+            return Err(new_invalid_error(
+                "cache_control, surrogate_control or vary should be set"
+                    .to_string(),
+            ));
+        }
+        let mode = match get_str_conf(value, "mode").as_str() {
+            "" | "override" => Mode::Override,
+            "merge" => Mode::Merge,
+            mode => {
+                return Err(new_invalid_error(format!(
+                    "mode({mode}) should be override or merge"
+                )));
+            },
+        };
+
+        Ok(Self {
+            headers,
+            mode,
+            hash_value,
+        })
+    }
+}
+
+impl CacheDirectives {
+    /// Creates a new cache directives plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new cache directives plugin");
+        Self::try_from(params)
+    }
+}
+
+#[async_trait]
+impl Plugin for CacheDirectives {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Sets the configured cache headers to the response, the multiple
+    /// headers of upstream are joined before merging.
+    async fn handle_response(
+        &self,
+        _session: &mut Session,
+        _ctx: &mut Ctx,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<ResponsePluginResult> {
+        for (name, configured) in self.headers.iter() {
+            let value = match self.mode {
+                Mode::Override => configured.clone(),
+                Mode::Merge => {
+                    let upstream = upstream_response
+                        .headers
+                        .get_all(name)
+                        .iter()
+                        .filter_map(|value| value.to_str().ok())
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    if *name == header::VARY {
+                        merge_vary(&upstream, configured)
+                    } else {
+                        merge_cache_directives(&upstream, configured)
+                    }
+                },
+            };
+            upstream_response.insert_header(name.clone(), value)?;
+        }
+        Ok(ResponsePluginResult::Modified)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("cache_directives", |params| {
+        Ok(Arc::new(CacheDirectives::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pingap_core::Ctx;
+    use pingora::http::ResponseHeader;
+    use pretty_assertions::assert_eq;
+
+    fn new_cache_directives(params: &str) -> CacheDirectives {
+        CacheDirectives::new(&toml::from_str::<PluginConf>(params).unwrap())
+            .unwrap()
+    }
+
+    fn new_upstream_response(headers: &[(&str, &str)]) -> ResponseHeader {
+        let mut upstream_response =
+            ResponseHeader::build_no_case(200, None).unwrap();
+        for (name, value) in headers {
+            upstream_response
+                .append_header(name.to_string(), *value)
+                .unwrap();
+        }
+        upstream_response
+    }
+
+    fn get_header<'a>(resp: &'a ResponseHeader, name: &str) -> &'a str {
+        resp.headers.get(name).unwrap().to_str().unwrap()
+    }
+
+    #[test]
+    fn test_cache_directives_params() {
+        let plugin = new_cache_directives(
+            r###"
+cache_control = "public,max-age=300"
+vary = "Accept-Encoding"
+mode = "merge"
+"###,
+        );
+        assert_eq!(Mode::Merge, plugin.mode);
+        assert_eq!(
+            r#"[("cache-control", "public, max-age=300"), ("vary", "Accept-Encoding")]"#,
+            format!("{:?}", plugin.headers)
+        );
+
+        let result = CacheDirectives::try_from(&PluginConf::default());
+        assert_eq!(
+            "Plugin cache_directives invalid, message: cache_control, surrogate_control or vary should be set",
+            result.err().unwrap().to_string()
+        );
+
+        let result = CacheDirectives::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+cache_control = "no-store"
+mode = "replace"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin cache_directives invalid, message: mode(replace) should be override or merge",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_merge_directives() {
+        assert_eq!(
+            "no-transform, public, max-age=300",
+            merge_cache_directives(
+                "private, max-age=60, no-transform",
+                "public, max-age=300"
+            )
+        );
+        assert_eq!("max-age=300", merge_cache_directives("", "max-age=300"));
+        assert_eq!(
+            "Accept-Encoding, Origin",
+            merge_vary("Accept-Encoding", "accept-encoding, Origin")
+        );
+        assert_eq!("*", merge_vary("*", "Origin"));
+        assert_eq!("Origin", merge_vary("", "Origin"));
+    }
+
+    #[tokio::test]
+    async fn test_cache_directives() {
+        // static location
+        let plugin = new_cache_directives(
+            r###"
+cache_control = "public, max-age=300"
+surrogate_control = "max-age=3600"
+vary = "Accept-Encoding"
+"###,
+        );
+        let mut session =
+            test_util::new_session("GET /static/app.js HTTP/1.1\r\n\r\n").await;
+        let mut upstream_response = new_upstream_response(&[
+            ("Cache-Control", "no-cache"),
+            ("Vary", "Origin"),
+        ]);
+        let result = plugin
+            .handle_response(
+                &mut session,
+                &mut Ctx::default(),
+                &mut upstream_response,
+            )
+            .await
+            .unwrap();
+        assert_eq!(ResponsePluginResult::Modified, result);
+        assert_eq!(
+            "public, max-age=300",
+            get_header(&upstream_response, "cache-control")
+        );
+        assert_eq!(
+            "max-age=3600",
+            get_header(&upstream_response, "surrogate-control")
+        );
+        assert_eq!("Accept-Encoding", get_header(&upstream_response, "vary"));
+
+        // api location
+        let plugin = new_cache_directives(
+            r###"
+cache_control = "no-store"
+"###,
+        );
+        let mut session =
+            test_util::new_session("GET /api/users HTTP/1.1\r\n\r\n").await;
+        let mut upstream_response =
+            new_upstream_response(&[("Cache-Control", "public, max-age=60")]);
+        plugin
+            .handle_response(
+                &mut session,
+                &mut Ctx::default(),
+                &mut upstream_response,
+            )
+            .await
+            .unwrap();
+        assert_eq!("no-store", get_header(&upstream_response, "cache-control"));
+        assert_eq!(true, upstream_response.headers.get("vary").is_none());
+
+        // merge with upstream
+        let plugin = new_cache_directives(
+            r###"
+cache_control = "public, max-age=300"
+vary = "Accept-Encoding"
+mode = "merge"
+"###,
+        );
+        let mut session =
+            test_util::new_session("GET /static/app.js HTTP/1.1\r\n\r\n").await;
+        let mut upstream_response = new_upstream_response(&[
+            ("Cache-Control", "private, max-age=60"),
+            ("Cache-Control", "stale-while-revalidate=30"),
+            ("Vary", "Origin"),
+        ]);
+        plugin
+            .handle_response(
+                &mut session,
+                &mut Ctx::default(),
+                &mut upstream_response,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            "stale-while-revalidate=30, public, max-age=300",
+            get_header(&upstream_response, "cache-control")
+        );
+        assert_eq!(
+            "Origin, Accept-Encoding",
+            get_header(&upstream_response, "vary")
+        );
+        assert_eq!(
+            1,
+            upstream_response
+                .headers
+                .get_all("cache-control")
+                .iter()
+                .count()
+        );
+    }
+}
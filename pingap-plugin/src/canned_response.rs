@@ -0,0 +1,254 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_duration_conf, get_hash_key, get_int_conf, get_plugin_factory,
+    get_str_conf, get_str_slice_conf,
+};
+use async_trait::async_trait;
+use ctor::ctor;
+use http::{HeaderValue, Method, StatusCode, header};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, HttpResponse, Plugin, PluginStep, RequestPluginResult, convert_headers,
+};
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::sync::Arc;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// CannedResponse plugin returns the configured response for the matching
+/// paths without contacting any upstream, e.g. `/version` or `/robots.txt`.
+/// Only `GET` and `HEAD` requests are responded, the others are proxied
+/// as usual. The content type is guessed from the path if it's not set.
+///
+/// The body can be set inline, or loaded from storage by `includes`,
+/// the storage value is a toml snippet like `body = "..."`.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// paths = ["/robots.txt"]
+/// status = 200
+/// headers = ["X-Robots-Tag: noindex"]
+/// body = "User-agent: *\nDisallow: /"
+/// max_age = "1h"
+/// ```
+pub struct CannedResponse {
+    paths: Vec<String>,
+    resp: HttpResponse,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for CannedResponse {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::CannedResponse.to_string(),
+            message,
+        };
+        let paths = get_str_slice_conf(value, "paths");
+        if paths.is_empty() {
+            return Err(new_invalid_error("paths can't be empty".to_string()));
+        }
+        let status = match get_int_conf(value, "status") {
+            0 => StatusCode::OK,
+            status => u16::try_from(status)
+                .ok()
+                .and_then(|status| StatusCode::from_u16(status).ok())
+                .ok_or_else(|| {
+                    new_invalid_error(format!("status({status}) is invalid"))
+                })?,
+        };
+        let headers = convert_headers(&get_str_slice_conf(value, "headers"))
+            .map_err(|e| new_invalid_error(e.to_string()))?;
+        Ok(Self {
+            paths,
+            resp: HttpResponse {
+                status,
+                body: get_str_conf(value, "body").into(),
+                max_age: get_duration_conf(value, "max_age")
+                    .map(|max_age| max_age.as_secs() as u32),
+                headers: Some(headers).filter(|headers| !headers.is_empty()),
+                ..Default::default()
+            },
+            hash_value,
+        })
+    }
+}
+
+impl CannedResponse {
+    /// Creates a new canned response plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new canned response plugin");
+        Self::try_from(params)
+    }
+
+    /// Returns true if the content type is set by the configured headers.
+    fn has_content_type(&self) -> bool {
+        self.resp.headers.as_ref().is_some_and(|headers| {
+            headers.iter().any(|(name, _)| name == header::CONTENT_TYPE)
+        })
+    }
+}
+
+#[async_trait]
+impl Plugin for CannedResponse {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Responds the canned response if the request path matches.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        _ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let req_header = session.req_header();
+        if ![Method::GET, Method::HEAD].contains(&req_header.method) {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let path = req_header.uri.path();
+        if !self.paths.iter().any(|item| item == path) {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let mut resp = self.resp.clone();
+        if !self.has_content_type() {
+            let content_type =
+                mime_guess::from_path(path).first_or_text_plain();
+            if let Ok(value) = HeaderValue::from_str(content_type.as_ref()) {
+                resp.headers
+                    .get_or_insert_default()
+                    .push((header::CONTENT_TYPE, value));
+            }
+        }
+        Ok(RequestPluginResult::Respond(resp))
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("canned_response", |params| {
+        Ok(Arc::new(CannedResponse::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    fn new_plugin(conf: &str) -> CannedResponse {
+        CannedResponse::new(&toml::from_str::<PluginConf>(conf).unwrap())
+            .unwrap()
+    }
+
+    async fn handle(
+        plugin: &CannedResponse,
+        method: &str,
+        path: &str,
+    ) -> RequestPluginResult {
+        let input_header =
+            format!("{method} {path} HTTP/1.1\r\nHost: pingap.io\r\n\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_canned_response_params() {
+        let plugin = new_plugin(
+            r###"
+paths = ["/version"]
+headers = ["Content-Type: application/json"]
+body = '{"version":"0.12.0"}'
+max_age = "1h"
+"###,
+        );
+        assert_eq!(StatusCode::OK, plugin.resp.status);
+        assert_eq!(Some(3600), plugin.resp.max_age);
+        assert_eq!(true, plugin.has_content_type());
+
+        let result = CannedResponse::try_from(&PluginConf::default());
+        assert_eq!(
+            "Plugin canned_response invalid, message: paths can't be empty",
+            result.err().unwrap().to_string()
+        );
+
+        let result = CannedResponse::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+paths = ["/version"]
+status = 1000
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin canned_response invalid, message: status(1000) is invalid",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_canned_response() {
+        let plugin = new_plugin(
+            r###"
+paths = ["/robots.txt", "/version"]
+body = "User-agent: *"
+"###,
+        );
+
+        // the configured path returns the canned response
+        let RequestPluginResult::Respond(resp) =
+            handle(&plugin, "GET", "/robots.txt?v=1").await
+        else {
+            panic!("request should be responded");
+        };
+        assert_eq!(StatusCode::OK, resp.status);
+        assert_eq!("User-agent: *", std::str::from_utf8(&resp.body).unwrap());
+        let headers = resp.headers.unwrap();
+        assert_eq!(header::CONTENT_TYPE, headers[0].0);
+        assert_eq!("text/plain", headers[0].1.to_str().unwrap());
+
+        let result = handle(&plugin, "HEAD", "/version").await;
+        assert_eq!(true, matches!(result, RequestPluginResult::Respond(_)));
+
+        // other paths and methods are proxied normally
+        let result = handle(&plugin, "GET", "/users").await;
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+        let result = handle(&plugin, "POST", "/version").await;
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+    }
+}
@@ -0,0 +1,502 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_duration_conf, get_hash_key, get_plugin_factory, get_str_conf,
+};
+use ahash::AHashMap;
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use ctor::ctor;
+use http::{Method, StatusCode, header};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, HttpResponse, Plugin, PluginStep, RequestPluginResult,
+    get_req_header_value, write_continue_response,
+};
+use pingora::proxy::Session;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, error};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+const TURNSTILE_VERIFY_URL: &str =
+    "https://challenges.cloudflare.com/turnstile/v0/siteverify";
+const RECAPTCHA_VERIFY_URL: &str =
+    "https://www.google.com/recaptcha/api/siteverify";
+
+/// The form body read by the plugin is kept in the retry buffer of session,
+/// which is limited to 64KB by pingora.
+const MAX_FORM_SIZE: usize = 64 * 1024;
+
+/// Maximum count of the cached verifications
+const MAX_CACHED_VERIFICATIONS: usize = 10_000;
+
+#[derive(Deserialize)]
+struct VerifyResponse {
+    success: bool,
+    #[serde(default, rename = "error-codes")]
+    error_codes: Vec<String>,
+}
+
+/// Captcha plugin verifies the captcha token(Cloudflare Turnstile or
+/// Google reCAPTCHA) by the siteverify api of provider before proxying,
+/// the request without valid token is rejected with 403.
+/// The token is read from the request header, or the field of
+/// `application/x-www-form-urlencoded` body.
+/// The verification result is cached briefly, because the token can only
+/// be verified once by the provider, e.g. the retried request.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// # turnstile(default) or recaptcha
+/// provider = "turnstile"
+/// secret = "0x4AAAAAAA"
+/// header = "X-Captcha-Token"
+/// # default is `cf-turnstile-response` or `g-recaptcha-response`
+/// form_field = "cf-turnstile-response"
+/// cache_ttl = "30s"
+/// timeout = "5s"
+/// ```
+pub struct Captcha {
+    secret: String,
+    verify_url: String,
+    header: String,
+    form_field: String,
+    cache_ttl: Duration,
+    /// Token to the expired time and result of verification
+    verifications: Mutex<AHashMap<String, (Instant, bool)>>,
+    client: reqwest::Client,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for Captcha {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::Captcha.to_string(),
+            message,
+        };
+        let secret = get_str_conf(value, "secret");
+        if secret.is_empty() {
+            return Err(new_invalid_error("secret can't be empty".to_string()));
+        }
+        let provider = get_str_conf(value, "provider");
+        let (default_verify_url, default_form_field) = match provider.as_str() {
+            "" | "turnstile" => (TURNSTILE_VERIFY_URL, "cf-turnstile-response"),
+            "recaptcha" => (RECAPTCHA_VERIFY_URL, "g-recaptcha-response"),
+            _ => {
+                return Err(new_invalid_error(format!(
+                    "provider({provider}) is not supported"
+                )));
+            },
+        };
+        let mut verify_url = get_str_conf(value, "verify_url");
+        if verify_url.is_empty() {
+            verify_url = default_verify_url.to_string();
+        }
+        let mut header = get_str_conf(value, "header");
+        if header.is_empty() {
+            header = "X-Captcha-Token".to_string();
+        }
+        let mut form_field = get_str_conf(value, "form_field");
+        if form_field.is_empty() {
+            form_field = default_form_field.to_string();
+        }
+        let client = reqwest::Client::builder()
+            .timeout(
+                get_duration_conf(value, "timeout")
+                    .unwrap_or(Duration::from_secs(5)),
+            )
+            .build()
+            .map_err(|e| new_invalid_error(e.to_string()))?;
+
+        Ok(Self {
+            secret,
+            verify_url,
+            header,
+            form_field,
+            cache_ttl: get_duration_conf(value, "cache_ttl")
+                .unwrap_or(Duration::from_secs(30)),
+            verifications: Mutex::new(AHashMap::new()),
+            client,
+            hash_value,
+        })
+    }
+}
+
+impl Captcha {
+    /// Creates a new captcha plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new captcha plugin");
+        Self::try_from(params)
+    }
+
+    /// Gets the cached result of verification
+    fn get_verification(&self, token: &str) -> Option<bool> {
+        let verifications = self.verifications.lock().ok()?;
+        verifications
+            .get(token)
+            .filter(|(expired_at, _)| *expired_at > Instant::now())
+            .map(|(_, success)| *success)
+    }
+
+    /// Caches the result of verification, the expired items are removed
+    /// if the cache is full.
+    fn set_verification(&self, token: &str, success: bool) {
+        if self.cache_ttl.is_zero() {
+            return;
+        }
+        let Ok(mut verifications) = self.verifications.lock() else {
+            return;
+        };
+        let now = Instant::now();
+        if verifications.len() >= MAX_CACHED_VERIFICATIONS {
+            verifications.retain(|_, (expired_at, _)| *expired_at > now);
+            if verifications.len() >= MAX_CACHED_VERIFICATIONS {
+                verifications.clear();
+            }
+        }
+        verifications
+            .insert(token.to_string(), (now + self.cache_ttl, success));
+    }
+
+    /// Verifies the token by the siteverify api, the failure of provider
+    /// is treated as verification failure and not cached.
+    async fn verify(&self, token: &str, client_ip: Option<&str>) -> bool {
+        if let Some(success) = self.get_verification(token) {
+            return success;
+        }
+        let mut body = format!(
+            "secret={}&response={}",
+            urlencoding::encode(&self.secret),
+            urlencoding::encode(token)
+        );
+        if let Some(client_ip) = client_ip {
+            body.push_str(&format!(
+                "&remoteip={}",
+                urlencoding::encode(client_ip)
+            ));
+        }
+        let result = async {
+            self.client
+                .post(&self.verify_url)
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(body)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<VerifyResponse>()
+                .await
+        }
+        .await;
+        let resp = match result {
+            Ok(resp) => resp,
+            Err(e) => {
+                error!(
+                    error = %e,
+                    url = self.verify_url,
+                    "verify captcha token fail"
+                );
+                return false;
+            },
+        };
+        if !resp.success {
+            debug!(
+                error_codes = resp.error_codes.join(","),
+                "captcha token is invalid"
+            );
+        }
+        self.set_verification(token, resp.success);
+        resp.success
+    }
+
+    /// Reads the token from the field of form body, the body is kept in
+    /// the retry buffer and sent to upstream later.
+    async fn get_form_token(
+        &self,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<Option<String>> {
+        let req_header = session.req_header();
+        let is_form = req_header.method == Method::POST
+            && get_req_header_value(req_header, header::CONTENT_TYPE.as_str())
+                .is_some_and(|value| {
+                    value.starts_with("application/x-www-form-urlencoded")
+                });
+        if !is_form {
+            return Ok(None);
+        }
+        write_continue_response(session, ctx).await?;
+        session.enable_retry_buffering();
+        let mut body = BytesMut::new();
+        while let Some(chunk) = session.read_request_body().await? {
+            if body.len() + chunk.len() > MAX_FORM_SIZE {
+                return Ok(None);
+            }
+            body.extend_from_slice(&chunk);
+        }
+        let token = url::form_urlencoded::parse(&body)
+            .find(|(key, _)| key == self.form_field.as_str())
+            .map(|(_, value)| value.to_string());
+        Ok(token)
+    }
+}
+
+#[async_trait]
+impl Plugin for Captcha {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Verifies the captcha token of request, rejects the request with 403
+    /// if the token is missing or invalid.
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let token =
+            match get_req_header_value(session.req_header(), &self.header) {
+                Some(token) => Some(token.to_string()),
+                None => self.get_form_token(session, ctx).await?,
+            };
+        let success = match token.filter(|token| !token.is_empty()) {
+            Some(token) => {
+                self.verify(&token, ctx.conn.client_ip.as_deref()).await
+            },
+            None => false,
+        };
+        if success {
+            return Ok(RequestPluginResult::Continue);
+        }
+        Ok(RequestPluginResult::Respond(HttpResponse {
+            status: StatusCode::FORBIDDEN,
+            body: Bytes::from_static(b"Captcha verification failed"),
+            ..Default::default()
+        }))
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory()
+        .register("captcha", |params| Ok(Arc::new(Captcha::new(params)?)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Starts a mock siteverify server, only the `valid` token passes,
+    /// returns the verify url and the count of verify requests.
+    async fn start_verify_server() -> (String, Arc<AtomicUsize>) {
+        let listener =
+            tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url =
+            format!("http://{}/siteverify", listener.local_addr().unwrap());
+        let count = Arc::new(AtomicUsize::new(0));
+        let verify_count = count.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                verify_count.fetch_add(1, Ordering::Relaxed);
+                let mut req = String::new();
+                let mut data = [0; 4096];
+                // read until the form body of content length is received
+                loop {
+                    let size = stream.read(&mut data).await.unwrap();
+                    if size == 0 {
+                        break;
+                    }
+                    req.push_str(&String::from_utf8_lossy(&data[..size]));
+                    let Some((header, body)) = req.split_once("\r\n\r\n")
+                    else {
+                        continue;
+                    };
+                    let content_length = header
+                        .lines()
+                        .find_map(|line| {
+                            let (name, value) = line.split_once(':')?;
+                            name.eq_ignore_ascii_case("content-length")
+                                .then(|| value.trim().parse::<usize>().ok())?
+                        })
+                        .unwrap_or_default();
+                    if body.len() >= content_length {
+                        break;
+                    }
+                }
+                let body = if req.contains("secret=secret&response=valid") {
+                    r#"{"success":true}"#
+                } else {
+                    r#"{"success":false,"error-codes":["invalid-input-response"]}"#
+                };
+                let resp = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(resp.as_bytes()).await.unwrap();
+            }
+        });
+        (url, count)
+    }
+
+    async fn handle(
+        captcha: &Captcha,
+        session: &mut Session,
+    ) -> RequestPluginResult {
+        captcha
+            .handle_request(PluginStep::Request, session, &mut Ctx::default())
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_captcha_params() {
+        let captcha = Captcha::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+provider = "recaptcha"
+secret = "secret"
+cache_ttl = "1m"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(RECAPTCHA_VERIFY_URL, captcha.verify_url);
+        assert_eq!("X-Captcha-Token", captcha.header);
+        assert_eq!("g-recaptcha-response", captcha.form_field);
+        assert_eq!(Duration::from_secs(60), captcha.cache_ttl);
+
+        let result = Captcha::try_from(&PluginConf::default());
+        assert_eq!(
+            "Plugin captcha invalid, message: secret can't be empty",
+            result.err().unwrap().to_string()
+        );
+
+        let result = Captcha::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+provider = "hcaptcha"
+secret = "secret"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin captcha invalid, message: provider(hcaptcha) is not supported",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_captcha() {
+        let (url, count) = start_verify_server().await;
+        let captcha = Captcha::new(
+            &toml::from_str::<PluginConf>(&format!(
+                r###"
+secret = "secret"
+verify_url = "{url}"
+"###
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!("cf-turnstile-response", captcha.form_field);
+
+        // valid token of header passes
+        let headers =
+            ["Content-Length: 0", "X-Captcha-Token: valid"].join("\r\n");
+        let input_header = format!("GET /login HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        assert_eq!(
+            true,
+            handle(&captcha, &mut session).await
+                == RequestPluginResult::Continue
+        );
+        assert_eq!(1, count.load(Ordering::Relaxed));
+
+        // the verification is cached
+        let headers =
+            ["Content-Length: 0", "X-Captcha-Token: valid"].join("\r\n");
+        let input_header = format!("GET /login HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        assert_eq!(
+            true,
+            handle(&captcha, &mut session).await
+                == RequestPluginResult::Continue
+        );
+        assert_eq!(1, count.load(Ordering::Relaxed));
+
+        // invalid token is rejected
+        let headers =
+            ["Content-Length: 0", "X-Captcha-Token: invalid"].join("\r\n");
+        let input_header = format!("GET /login HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let RequestPluginResult::Respond(resp) =
+            handle(&captcha, &mut session).await
+        else {
+            panic!("invalid token should be rejected");
+        };
+        assert_eq!(StatusCode::FORBIDDEN, resp.status);
+        assert_eq!(2, count.load(Ordering::Relaxed));
+
+        // missing token is rejected without verification
+        let headers = ["Content-Length: 0"].join("\r\n");
+        let input_header = format!("GET /login HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        assert_eq!(
+            true,
+            matches!(
+                handle(&captcha, &mut session).await,
+                RequestPluginResult::Respond(_)
+            )
+        );
+        assert_eq!(2, count.load(Ordering::Relaxed));
+
+        // valid token of form field passes, and the body is kept
+        let body = "name=pingap&cf-turnstile-response=valid";
+        let input_header = format!(
+            "POST /login HTTP/1.1\r\nContent-Length: {}\r\nContent-Type: application/x-www-form-urlencoded\r\n\r\n{body}",
+            body.len()
+        );
+        let mut session = test_util::new_session(&input_header).await;
+        assert_eq!(
+            true,
+            handle(&captcha, &mut session).await
+                == RequestPluginResult::Continue
+        );
+        assert_eq!(
+            Some(Bytes::from_static(body.as_bytes())),
+            session.get_retry_buffer()
+        );
+    }
+}
@@ -0,0 +1,424 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_bool_conf, get_hash_key, get_plugin_factory, get_str_conf,
+    get_str_slice_conf,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use ctor::ctor;
+use http::StatusCode;
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, HTTP_HEADER_CONTENT_TEXT, HTTP_HEADER_TRANSFER_CHUNKED, HttpResponse,
+    ModifyResponseBody, Plugin, PluginStep, RequestPluginResult,
+    ResponseBodyPluginResult, ResponsePluginResult,
+};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+const PLUGIN_ID: &str = "_content_type_restriction_";
+
+/// A single media type pattern, e.g. `application/json` or `application/*`.
+#[derive(Debug, Clone, PartialEq)]
+struct MediaTypePattern {
+    main_type: String,
+    // None means wildcard(`*`)
+    sub_type: Option<String>,
+}
+
+impl MediaTypePattern {
+    fn parse(value: &str) -> Option<Self> {
+        let value = value.trim().to_lowercase();
+        if value == "*" || value == "*/*" {
+            return Some(Self {
+                main_type: "*".to_string(),
+                sub_type: None,
+            });
+        }
+        let (main_type, sub_type) = value.split_once('/')?;
+        let main_type = main_type.trim();
+        let sub_type = sub_type.trim();
+        if main_type.is_empty() || sub_type.is_empty() {
+            return None;
+        }
+        Some(Self {
+            main_type: main_type.to_string(),
+            sub_type: if sub_type == "*" {
+                None
+            } else {
+                Some(sub_type.to_string())
+            },
+        })
+    }
+    /// Checks whether the media type (without parameters) matches the pattern.
+    fn is_match(&self, main_type: &str, sub_type: &str) -> bool {
+        if self.main_type != "*" && self.main_type != main_type {
+            return false;
+        }
+        match &self.sub_type {
+            Some(value) => value == sub_type,
+            None => true,
+        }
+    }
+}
+
+/// Splits a content type header value into its main and sub type,
+/// parameters such as `charset` are ignored.
+fn parse_content_type(value: &str) -> Option<(String, String)> {
+    let media_type = value.split(';').next()?.trim().to_lowercase();
+    let (main_type, sub_type) = media_type.split_once('/')?;
+    Some((main_type.trim().to_string(), sub_type.trim().to_string()))
+}
+
+fn is_allowed(patterns: &[MediaTypePattern], value: &str) -> bool {
+    let Some((main_type, sub_type)) = parse_content_type(value) else {
+        return false;
+    };
+    patterns
+        .iter()
+        .any(|item| item.is_match(&main_type, &sub_type))
+}
+
+fn parse_patterns(
+    value: &PluginConf,
+    key: &str,
+) -> Result<Vec<MediaTypePattern>> {
+    let mut patterns = vec![];
+    for item in get_str_slice_conf(value, key).iter() {
+        let pattern =
+            MediaTypePattern::parse(item).ok_or_else(|| Error::Invalid {
+                category: PluginCategory::ContentTypeRestriction.to_string(),
+                message: format!("invalid content type: {item}"),
+            })?;
+        patterns.push(pattern);
+    }
+    Ok(patterns)
+}
+
+/// ContentTypeRestriction plugin rejects requests whose `Content-Type` is not
+/// in the allow list, and optionally verifies the content type of responses.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// allow_list = ["application/json", "application/*"]
+/// required = false           # reject requests with body but without content type
+/// response_list = ["application/json"]
+/// message = "Optional custom error message"
+/// ```
+pub struct ContentTypeRestriction {
+    /// Execution phase of the plugin (request phase only)
+    plugin_step: PluginStep,
+    /// Allowed request content types, supports `type/*` wildcard
+    allow_list: Vec<MediaTypePattern>,
+    /// Whether a request with body must contain the content type header
+    required: bool,
+    /// Expected response content types, empty means no check
+    response_list: Vec<MediaTypePattern>,
+    /// Response returned when the request content type is not allowed
+    unsupported_resp: HttpResponse,
+    /// Body used when the upstream response content type is unexpected
+    mismatch_message: Bytes,
+    /// Unique identifier for plugin instance
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for ContentTypeRestriction {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let allow_list = parse_patterns(value, "allow_list")?;
+        let response_list = parse_patterns(value, "response_list")?;
+        if allow_list.is_empty() && response_list.is_empty() {
+            return Err(Error::Invalid {
+                category: PluginCategory::ContentTypeRestriction.to_string(),
+                message: "allow list and response list are both empty"
+                    .to_string(),
+            });
+        }
+
+        let mut message = get_str_conf(value, "message");
+        if message.is_empty() {
+            message = "Unsupported media type".to_string();
+        }
+
+        Ok(Self {
+            hash_value,
+            plugin_step: PluginStep::Request,
+            allow_list,
+            required: get_bool_conf(value, "required"),
+            response_list,
+            unsupported_resp: HttpResponse {
+                status: StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                body: Bytes::from(message),
+                ..Default::default()
+            },
+            mismatch_message: Bytes::from_static(
+                b"Upstream response content type is unexpected",
+            ),
+        })
+    }
+}
+
+impl ContentTypeRestriction {
+    /// Creates a new content type restriction plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(
+            params = params.to_string(),
+            "new content type restriction plugin"
+        );
+        Self::try_from(params)
+    }
+}
+
+/// Replaces the whole upstream response body with a fixed message.
+struct ReplaceBody {
+    body: Bytes,
+}
+
+impl ModifyResponseBody for ReplaceBody {
+    fn handle(
+        &mut self,
+        _session: &Session,
+        body: &mut Option<bytes::Bytes>,
+        end_of_stream: bool,
+    ) -> pingora::Result<()> {
+        if end_of_stream {
+            *body = Some(self.body.clone());
+        } else if let Some(data) = body {
+            data.clear();
+        }
+        Ok(())
+    }
+    fn name(&self) -> String {
+        "content_type_restriction".to_string()
+    }
+}
+
+#[async_trait]
+impl Plugin for ContentTypeRestriction {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Checks the request content type against the allow list.
+    ///
+    /// Requests without a content type are allowed unless `required` is set
+    /// and the request carries a body.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        _ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != self.plugin_step || self.allow_list.is_empty() {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let Some(value) = session.get_header(http::header::CONTENT_TYPE) else {
+            let has_body = !session.is_body_empty();
+            if self.required && has_body {
+                return Ok(RequestPluginResult::Respond(
+                    self.unsupported_resp.clone(),
+                ));
+            }
+            return Ok(RequestPluginResult::Continue);
+        };
+        let content_type = value.to_str().unwrap_or_default();
+        if !is_allowed(&self.allow_list, content_type) {
+            return Ok(RequestPluginResult::Respond(
+                self.unsupported_resp.clone(),
+            ));
+        }
+        Ok(RequestPluginResult::Continue)
+    }
+
+    /// Verifies the upstream response content type, an unexpected response
+    /// is converted to `502 Bad Gateway`.
+    async fn handle_response(
+        &self,
+        session: &mut Session,
+        ctx: &mut Ctx,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<ResponsePluginResult> {
+        if self.response_list.is_empty()
+            || upstream_response.status == StatusCode::NO_CONTENT
+            || upstream_response.status == StatusCode::NOT_MODIFIED
+        {
+            return Ok(ResponsePluginResult::Unchanged);
+        }
+        let content_type = upstream_response
+            .headers
+            .get(http::header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        if is_allowed(&self.response_list, content_type) {
+            return Ok(ResponsePluginResult::Unchanged);
+        }
+        warn!(
+            path = session.req_header().uri.path(),
+            content_type, "upstream response content type is unexpected"
+        );
+        let _ = upstream_response.set_status(StatusCode::BAD_GATEWAY);
+        upstream_response.remove_header(&http::header::CONTENT_LENGTH);
+        upstream_response.remove_header(&http::header::CONTENT_ENCODING);
+        let text = HTTP_HEADER_CONTENT_TEXT.clone();
+        let _ = upstream_response.insert_header(text.0, text.1);
+        let _ = upstream_response.insert_header(
+            http::header::TRANSFER_ENCODING,
+            HTTP_HEADER_TRANSFER_CHUNKED.1.clone(),
+        );
+        ctx.add_modify_body_handler(
+            PLUGIN_ID,
+            Box::new(ReplaceBody {
+                body: self.mismatch_message.clone(),
+            }),
+        );
+        Ok(ResponsePluginResult::Modified)
+    }
+
+    fn handle_response_body(
+        &self,
+        session: &mut Session,
+        ctx: &mut Ctx,
+        body: &mut Option<bytes::Bytes>,
+        end_of_stream: bool,
+    ) -> pingora::Result<ResponseBodyPluginResult> {
+        if let Some(modifier) = ctx.get_modify_body_handler(PLUGIN_ID) {
+            modifier.handle(session, body, end_of_stream)?;
+            let result = if end_of_stream {
+                ResponseBodyPluginResult::FullyReplaced
+            } else {
+                ResponseBodyPluginResult::PartialReplaced
+            };
+            Ok(result)
+        } else {
+            Ok(ResponseBodyPluginResult::Unchanged)
+        }
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("content_type_restriction", |params| {
+        Ok(Arc::new(ContentTypeRestriction::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_content_type_restriction_params() {
+        let params = ContentTypeRestriction::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+allow_list = ["application/json", "text/*"]
+response_list = ["application/json"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(2, params.allow_list.len());
+        assert_eq!(None, params.allow_list[1].sub_type);
+        assert_eq!(1, params.response_list.len());
+
+        let result = ContentTypeRestriction::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+allow_list = ["json"]
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin content_type_restriction invalid, message: invalid content type: json",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_is_allowed() {
+        let patterns = vec![
+            MediaTypePattern::parse("application/json").unwrap(),
+            MediaTypePattern::parse("text/*").unwrap(),
+        ];
+        assert_eq!(
+            true,
+            is_allowed(&patterns, "application/json; charset=utf-8")
+        );
+        assert_eq!(true, is_allowed(&patterns, "Text/Plain"));
+        assert_eq!(false, is_allowed(&patterns, "application/xml"));
+        assert_eq!(false, is_allowed(&patterns, ""));
+    }
+
+    #[tokio::test]
+    async fn test_content_type_restriction() {
+        let plugin = ContentTypeRestriction::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+allow_list = ["application/*"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        // wildcard match
+        let headers = ["Content-Type: application/json"].join("\r\n");
+        let input_header =
+            format!("POST /vicanso/pingap HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+
+        // disallowed content type
+        let headers = ["Content-Type: text/plain"].join("\r\n");
+        let input_header =
+            format!("POST /vicanso/pingap HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("result is not Respond");
+        };
+        assert_eq!(StatusCode::UNSUPPORTED_MEDIA_TYPE, resp.status);
+    }
+}
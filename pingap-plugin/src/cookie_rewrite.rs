@@ -0,0 +1,334 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_bool_conf, get_hash_key, get_plugin_factory, get_str_conf,
+};
+use async_trait::async_trait;
+use ctor::ctor;
+use http::{HeaderValue, header};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{Ctx, Plugin, ResponsePluginResult};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::sync::Arc;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// CookieRewrite plugin rewrites the attributes of `Set-Cookie` headers
+/// of upstream response, e.g. the upstream behind the tls terminated proxy
+/// doesn't set `Secure` or sets the domain of internal host.
+///
+/// - `secure` adds the `Secure` attribute
+/// - `same_site` sets or overrides the `SameSite` attribute, `None`
+///   implies `Secure` as it's required by browsers
+/// - `domain` rewrites the existing `Domain` attribute, the host-only
+///   cookie is kept as it is
+/// - `path` sets or overrides the `Path` attribute
+///
+/// # Configuration (TOML)
+/// ```toml
+/// secure = true
+/// same_site = "Lax"
+/// domain = "example.com"
+/// path = "/"
+/// ```
+pub struct CookieRewrite {
+    secure: bool,
+    same_site: Option<String>,
+    domain: Option<String>,
+    path: Option<String>,
+    hash_value: String,
+}
+
+/// Returns the lowercase name of cookie attribute, e.g. `path` of `Path=/`
+fn get_attribute_name(attribute: &str) -> String {
+    attribute
+        .split_once('=')
+        .map_or(attribute, |(name, _)| name)
+        .trim()
+        .to_lowercase()
+}
+
+impl TryFrom<&PluginConf> for CookieRewrite {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let same_site = get_str_conf(value, "same_site");
+        let same_site = match same_site.to_lowercase().as_str() {
+            "" => None,
+            "strict" => Some("Strict".to_string()),
+            "lax" => Some("Lax".to_string()),
+            "none" => Some("None".to_string()),
+            _ => {
+                return Err(Error::Invalid {
+                    category: PluginCategory::CookieRewrite.to_string(),
+                    message: format!(
+                        "same_site({same_site}) should be Strict, Lax or None"
+                    ),
+                });
+            },
+        };
+        let secure = get_bool_conf(value, "secure")
+            || same_site.as_deref() == Some("None");
+        let domain = Some(get_str_conf(value, "domain"))
+            .filter(|domain| !domain.is_empty());
+        let path =
+            Some(get_str_conf(value, "path")).filter(|path| !path.is_empty());
+        if !secure && same_site.is_none() && domain.is_none() && path.is_none()
+        {
+            return Err(Error::Invalid {
+                category: PluginCategory::CookieRewrite.to_string(),
+                message: "secure, same_site, domain or path should be set"
+                    .to_string(),
+            });
+        }
+
+        Ok(Self {
+            secure,
+            same_site,
+            domain,
+            path,
+            hash_value,
+        })
+    }
+}
+
+impl CookieRewrite {
+    /// Creates a new cookie rewrite plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new cookie rewrite plugin");
+        Self::try_from(params)
+    }
+
+    /// Rewrites the attributes of the `Set-Cookie` value, the name and
+    /// value of cookie and other attributes are kept.
+    fn rewrite(&self, cookie: &str) -> String {
+        let mut parts = cookie.split(';').map(|item| item.trim());
+        let mut values = vec![parts.next().unwrap_or_default().to_string()];
+        let mut has_domain = false;
+        for attribute in parts.filter(|item| !item.is_empty()) {
+            match get_attribute_name(attribute).as_str() {
+                "secure" if self.secure => {},
+                "samesite" if self.same_site.is_some() => {},
+                "path" if self.path.is_some() => {},
+                "domain" => {
+                    has_domain = true;
+                    if self.domain.is_none() {
+                        values.push(attribute.to_string());
+                    }
+                },
+                _ => values.push(attribute.to_string()),
+            }
+        }
+        if let Some(domain) = self.domain.as_ref().filter(|_| has_domain) {
+            values.push(format!("Domain={domain}"));
+        }
+        if let Some(path) = &self.path {
+            values.push(format!("Path={path}"));
+        }
+        if self.secure {
+            values.push("Secure".to_string());
+        }
+        if let Some(same_site) = &self.same_site {
+            values.push(format!("SameSite={same_site}"));
+        }
+        values.join("; ")
+    }
+}
+
+#[async_trait]
+impl Plugin for CookieRewrite {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Rewrites all the `Set-Cookie` headers of the response.
+    async fn handle_response(
+        &self,
+        _session: &mut Session,
+        _ctx: &mut Ctx,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<ResponsePluginResult> {
+        let cookies = upstream_response
+            .headers
+            .get_all(header::SET_COOKIE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .map(|value| self.rewrite(value))
+            .collect::<Vec<_>>();
+        if cookies.is_empty() {
+            return Ok(ResponsePluginResult::Unchanged);
+        }
+        upstream_response.remove_header(&header::SET_COOKIE);
+        for cookie in cookies {
+            if let Ok(value) = HeaderValue::from_str(&cookie) {
+                upstream_response.append_header(header::SET_COOKIE, value)?;
+            }
+        }
+        Ok(ResponsePluginResult::Modified)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("cookie_rewrite", |params| {
+        Ok(Arc::new(CookieRewrite::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingap_config::PluginConf;
+    use pingap_core::Ctx;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    fn new_cookie_rewrite(params: &str) -> CookieRewrite {
+        CookieRewrite::new(&toml::from_str::<PluginConf>(params).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_cookie_rewrite_params() {
+        let plugin = new_cookie_rewrite(
+            r###"
+same_site = "none"
+domain = "example.com"
+"###,
+        );
+        // SameSite=None implies Secure
+        assert_eq!(true, plugin.secure);
+        assert_eq!("None", plugin.same_site.unwrap());
+        assert_eq!("example.com", plugin.domain.unwrap());
+        assert_eq!(true, plugin.path.is_none());
+
+        let result = CookieRewrite::try_from(
+            &toml::from_str::<PluginConf>(r#"same_site = "loose""#).unwrap(),
+        );
+        assert_eq!(
+            "Plugin cookie_rewrite invalid, message: same_site(loose) should be Strict, Lax or None",
+            result.err().unwrap().to_string()
+        );
+
+        let result = CookieRewrite::try_from(&PluginConf::default());
+        assert_eq!(
+            "Plugin cookie_rewrite invalid, message: secure, same_site, domain or path should be set",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let plugin = new_cookie_rewrite(
+            r###"
+secure = true
+same_site = "Lax"
+domain = "example.com"
+path = "/"
+"###,
+        );
+        assert_eq!(
+            "sid=abc; HttpOnly; Domain=example.com; Path=/; Secure; SameSite=Lax",
+            plugin.rewrite(
+                "sid=abc; Domain=internal.local; Path=/app; HttpOnly; secure; SameSite=None"
+            )
+        );
+        // the host-only cookie doesn't get the domain
+        assert_eq!(
+            "lang=en; Max-Age=3600; Path=/; Secure; SameSite=Lax",
+            plugin.rewrite("lang=en; Max-Age=3600")
+        );
+
+        let plugin = new_cookie_rewrite(
+            r###"
+secure = true
+"###,
+        );
+        assert_eq!(
+            "sid=abc; Path=/app; SameSite=Strict; Secure",
+            plugin.rewrite("sid=abc; Path=/app; SameSite=Strict")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cookie_rewrite() {
+        let plugin = new_cookie_rewrite(
+            r###"
+secure = true
+same_site = "Strict"
+domain = "example.com"
+"###,
+        );
+        let mock_io = Builder::new()
+            .read(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        upstream_response
+            .append_header(
+                "Set-Cookie",
+                "sid=abc; Domain=internal.local; Path=/; HttpOnly",
+            )
+            .unwrap();
+        upstream_response
+            .append_header("Set-Cookie", "theme=dark; SameSite=None")
+            .unwrap();
+        upstream_response
+            .append_header("Set-Cookie", "lang=en; Secure")
+            .unwrap();
+        let result = plugin
+            .handle_response(
+                &mut session,
+                &mut Ctx::default(),
+                &mut upstream_response,
+            )
+            .await
+            .unwrap();
+        assert_eq!(ResponsePluginResult::Modified, result);
+        let cookies = upstream_response
+            .headers
+            .get_all("set-cookie")
+            .iter()
+            .map(|value| value.to_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "sid=abc; Path=/; HttpOnly; Domain=example.com; Secure; SameSite=Strict",
+                "theme=dark; Secure; SameSite=Strict",
+                "lang=en; Secure; SameSite=Strict",
+            ],
+            cookies
+        );
+
+        // no cookie
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        let result = plugin
+            .handle_response(
+                &mut session,
+                &mut Ctx::default(),
+                &mut upstream_response,
+            )
+            .await
+            .unwrap();
+        assert_eq!(ResponsePluginResult::Unchanged, result);
+    }
+}
@@ -12,7 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use super::{Error, get_hash_key, get_plugin_factory, get_str_conf};
+use super::{
+    Error, get_hash_key, get_plugin_factory, get_str_conf, get_str_slice_conf,
+};
 use async_trait::async_trait;
 use bytes::Bytes;
 use cookie::Cookie;
@@ -25,12 +27,12 @@ use pingap_core::{
     Ctx, HTTP_HEADER_NO_STORE, HttpResponse, Plugin, PluginStep,
     RequestPluginResult,
 };
-use pingap_core::{get_cookie_value, new_internal_error, now_sec};
-use pingap_util::base64_encode;
+use pingap_core::{get_cookie_value, new_internal_error};
+use pingap_util::SecureCookie;
 use pingora::proxy::Session;
-use sha2::{Digest, Sha256};
 use std::borrow::Cow;
 use std::sync::Arc;
+use std::time::Duration;
 use tracing::debug;
 
 type Result<T, E = Error> = std::result::Result<T, E>;
@@ -40,14 +42,16 @@ type Result<T, E = Error> = std::result::Result<T, E>;
 /// # Configuration
 /// - `token_path`: Endpoint for generating new CSRF tokens
 /// - `key`: Secret key for cryptographic operations
+/// - `old_keys`: Old keys of rotation, the tokens signed by them are still valid
 /// - `name`: Name of the CSRF token header/cookie (default: "x-csrf-token")
 /// - `ttl`: Token expiration time in seconds (0 means no expiration)
 pub struct Csrf {
     plugin_step: PluginStep,
     // Endpoint that clients call to get a new CSRF token (e.g., "/csrf-token")
     token_path: String,
-    // Secret key used for cryptographic operations - must be kept secure and consistent across instances
-    key: String,
+    // Signs the token by the secret key, and verifies it by the key and old keys
+    // The key must be kept secure and consistent across instances
+    secure_cookie: SecureCookie,
     // The name used for both the HTTP header and cookie (default: "x-csrf-token")
     // Client must send token in both header and cookie for double-submit validation
     name: String,
@@ -73,48 +77,66 @@ impl TryFrom<&PluginConf> for Csrf {
     fn try_from(value: &PluginConf) -> Result<Self> {
         let hash_value = get_hash_key(value);
 
-        let mut csrf = Self {
-            hash_value,
-            plugin_step: PluginStep::Request,
-            name: get_str_conf(value, "name"),
-            token_path: get_str_conf(value, "token_path"),
-            key: get_str_conf(value, "key"),
-            ttl: 0,
-            unauthorized_resp: HttpResponse {
-                status: StatusCode::UNAUTHORIZED,
-                body: Bytes::from("Csrf token is empty or invalid"),
-                ..Default::default()
-            },
-        };
-        if csrf.name.is_empty() {
-            csrf.name = "x-csrf-token".to_string();
+        let mut name = get_str_conf(value, "name");
+        if name.is_empty() {
+            name = "x-csrf-token".to_string();
         }
-        let ttl = get_str_conf(value, "ttl");
-        if !ttl.is_empty() {
-            let ttl = parse_duration(&ttl).map_err(|e| Error::Invalid {
-                category: PluginCategory::Csrf.to_string(),
-                message: e.to_string(),
-            })?;
-            csrf.ttl = ttl.as_secs();
+        let mut ttl = 0;
+        let value_ttl = get_str_conf(value, "ttl");
+        if !value_ttl.is_empty() {
+            ttl = parse_duration(&value_ttl)
+                .map_err(|e| Error::Invalid {
+                    category: PluginCategory::Csrf.to_string(),
+                    message: e.to_string(),
+                })?
+                .as_secs();
         }
 
         // Validation rules:
         // 1. token_path must be specified (where clients get new tokens)
-        if csrf.token_path.is_empty() {
+        let token_path = get_str_conf(value, "token_path");
+        if token_path.is_empty() {
             return Err(Error::Invalid {
                 category: PluginCategory::Csrf.to_string(),
                 message: "Token path is not allowed empty".to_string(),
             });
         }
         // 2. key must be specified (used for cryptographic operations)
-        if csrf.key.is_empty() {
+        let key = get_str_conf(value, "key");
+        if key.is_empty() {
             return Err(Error::Invalid {
                 category: PluginCategory::Csrf.to_string(),
                 message: "Key is not allowed empty".to_string(),
             });
         }
 
-        Ok(csrf)
+        // The token is signed by the key, and the old keys of rotation
+        // are only used to verify the token
+        let mut keys = vec![key];
+        keys.extend(get_str_slice_conf(value, "old_keys"));
+        let mut secure_cookie =
+            SecureCookie::new(&keys).map_err(|e| Error::Invalid {
+                category: PluginCategory::Csrf.to_string(),
+                message: e.to_string(),
+            })?;
+        if ttl > 0 {
+            secure_cookie =
+                secure_cookie.with_max_age(Duration::from_secs(ttl));
+        }
+
+        Ok(Self {
+            hash_value,
+            plugin_step: PluginStep::Request,
+            name,
+            token_path,
+            secure_cookie,
+            ttl,
+            unauthorized_resp: HttpResponse {
+                status: StatusCode::UNAUTHORIZED,
+                body: Bytes::from("Csrf token is empty or invalid"),
+                ..Default::default()
+            },
+        })
     }
 }
 
@@ -135,77 +157,38 @@ impl Csrf {
 /// Generates a new CSRF token with cryptographic protection
 ///
 /// # Format
-/// The token consists of three parts joined by dots:
-/// 1. Random ID (12 chars using nanoid)
-/// 2. Current timestamp in hex
-/// 3. SHA-256 signature of the above parts using the secret key
+/// The token is a random ID (12 chars using nanoid) signed by the secure
+/// cookie, which consists of the timestamp, the ID and the HMAC-SHA256
+/// signature joined by dots.
 ///
 /// # Arguments
-/// * `key` - Secret key used for signing the token
+/// * `secure_cookie` - Secure cookie used for signing the token
 ///
 /// # Returns
 /// A string containing the generated token
 #[inline]
-fn generate_token(key: &str) -> String {
+fn generate_token(secure_cookie: &SecureCookie) -> Result<String> {
     // Generate random ID using nanoid (URL-safe, 12 chars)
-    let id = nanoid!(12);
-    // Add current timestamp in hex format
-    let prefix = format!("{id}.{:x}", now_sec());
-
-    // Create cryptographic signature:
-    // - Uses SHA-256 for hashing
-    // - Signs the combination of random ID, timestamp, and secret key
-    // - This prevents token forgery as attackers don't know the secret key
-    let mut hasher = Sha256::new();
-    hasher.update(prefix.as_bytes());
-    hasher.update(key.as_bytes());
-    let hash256 = hasher.finalize();
-
-    // Combine all parts with dots
-    format!("{prefix}.{}", base64_encode(hash256))
+    secure_cookie
+        .encode(&nanoid!(12))
+        .map_err(|e| Error::Invalid {
+            category: PluginCategory::Csrf.to_string(),
+            message: e.to_string(),
+        })
 }
 
 /// Validates a CSRF token for authenticity and expiration
 ///
 /// # Arguments
-/// * `key` - Secret key used for validating the token signature
-/// * `ttl` - Time-to-live in seconds (0 means no expiration check)
+/// * `secure_cookie` - Secure cookie used for validating the token signature
+///   and expiration, the token signed by the old keys is also valid
 /// * `value` - The token string to validate
 ///
 /// # Returns
 /// `true` if the token is valid and not expired, `false` otherwise
-///
-/// # Security
-/// - Uses constant-time comparison for signature verification
-/// - Checks token format, expiration, and cryptographic signature
 #[inline]
-fn validate_token(key: &str, ttl: u64, value: &str) -> bool {
-    // Split token into its components
-    let arr: Vec<&str> = value.split('.').collect();
-    if arr.len() != 3 {
-        return false;
-    }
-
-    // Check expiration if TTL is configured
-    if ttl > 0 {
-        let now = now_sec();
-        // Parse timestamp from hex and compare with current time
-        if now - u64::from_str_radix(arr[1], 16).unwrap_or_default() > ttl {
-            return false;
-        }
-    }
-
-    // Verify signature by:
-    // 1. Reconstructing the original message (id + timestamp)
-    // 2. Creating a new signature with the secret key
-    // 3. Comparing with the provided signature
-    let mut hasher = Sha256::new();
-    hasher.update(format!("{}.{}", arr[0], arr[1]).as_bytes());
-    hasher.update(key.as_bytes());
-    let hash256 = hasher.finalize();
-
-    // Constant-time comparison to prevent timing attacks
-    arr[2] == base64_encode(hash256)
+fn validate_token(secure_cookie: &SecureCookie, value: &str) -> bool {
+    secure_cookie.decode(value).is_some()
 }
 
 #[async_trait]
@@ -248,7 +231,8 @@ impl Plugin for Csrf {
 
         // Handle token generation requests
         if session.req_header().uri.path() == self.token_path {
-            let token = generate_token(&self.key);
+            let token = generate_token(&self.secure_cookie)
+                .map_err(|e| new_internal_error(500, e))?;
 
             // Set token in cookie with security options:
             // - Path: "/" (valid for all paths)
@@ -303,7 +287,7 @@ impl Plugin for Csrf {
         if value
             != get_cookie_value(session.req_header(), &self.name)
                 .unwrap_or_default()
-            || !validate_token(&self.key, self.ttl, &value)
+            || !validate_token(&self.secure_cookie, &value)
         {
             return Ok(RequestPluginResult::Respond(
                 self.unauthorized_resp.clone(),
@@ -346,7 +330,7 @@ ttl = "1h"
         )
         .unwrap();
         assert_eq!("/csrf-token", params.token_path);
-        assert_eq!("WjrXUG47wu", params.key);
+        assert_eq!("x-csrf-token", params.name);
         assert_eq!(3600, params.ttl);
 
         let result = Csrf::try_from(
@@ -393,10 +377,21 @@ token_path = "/csrf-token"
 
     #[test]
     fn test_generate_token() {
-        let key = "123";
-        let value = generate_token(key);
-        assert_eq!(true, validate_token(key, 10, &value));
-        assert_eq!(false, validate_token(key, 10, &format!("{value}:1")));
+        let secure_cookie = SecureCookie::new(&["123"])
+            .unwrap()
+            .with_max_age(Duration::from_secs(10));
+        let value = generate_token(&secure_cookie).unwrap();
+        assert_eq!(true, validate_token(&secure_cookie, &value));
+        assert_eq!(
+            false,
+            validate_token(&secure_cookie, &format!("{value}:1"))
+        );
+
+        // the token signed by the old key is valid during rotation
+        let rotated = SecureCookie::new(&["456", "123"]).unwrap();
+        assert_eq!(true, validate_token(&rotated, &value));
+        let rotated = SecureCookie::new(&["456"]).unwrap();
+        assert_eq!(false, validate_token(&rotated, &value));
     }
 
     #[tokio::test]
@@ -435,7 +430,7 @@ ttl = "1h"
         let cookie = binding[1].1.to_str().unwrap();
         let c = Cookie::from_str(cookie).unwrap();
         assert_eq!("x-csrf-token", c.name());
-        assert_eq!(66, c.value().len());
+        assert_eq!(70, c.value().len());
 
         // validate fail
         let headers = [format!("x-csrf-token:{}", "123")].join("\r\n");
@@ -0,0 +1,574 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::redis_store::RedisStore;
+use super::{
+    Error, get_duration_conf, get_hash_key, get_int_conf, get_plugin_factory,
+    get_str_conf, get_str_slice_conf,
+};
+use ahash::AHashMap;
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use ctor::ctor;
+use http::{HeaderName, HeaderValue, Method, StatusCode};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, HttpResponse, Plugin, PluginStep, RequestPluginResult,
+    ResponsePluginResult, write_continue_response,
+};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The variable of the event id, it's used to release the event id
+/// if the upstream fails.
+pub const DEDUP_EVENT_ID_VARIABLE: &str = "dedup_event_id";
+
+const HTTP_HEADER_DEDUPLICATED: &str = "x-deduplicated";
+
+/// The body read by the plugin is kept in the retry buffer of session
+/// and sent to upstream later, the buffer is limited to 64KB by pingora.
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+// sweep the expired ids of memory store when it's larger than the size
+const MEMORY_SWEEP_SIZE: usize = 1024;
+
+/// Storage backend of the seen event ids, the redis store is shared
+/// by all pingap instances
+enum DedupStore {
+    Memory(Mutex<AHashMap<String, Instant>>),
+    Redis(RedisStore),
+}
+
+impl DedupStore {
+    /// Marks the event id as seen within the window, returns false if it
+    /// has been seen. The event is allowed if redis is unavailable.
+    async fn mark(&self, key: &str, window: Duration) -> bool {
+        match self {
+            Self::Memory(ids) => {
+                let Ok(mut ids) = ids.lock() else {
+                    return true;
+                };
+                let now = Instant::now();
+                if ids.len() >= MEMORY_SWEEP_SIZE {
+                    ids.retain(|_, expired_at| *expired_at > now);
+                }
+                if ids.get(key).is_some_and(|expired_at| *expired_at > now) {
+                    return false;
+                }
+                ids.insert(key.to_string(), now + window);
+                true
+            },
+            Self::Redis(store) => {
+                let cmd = redis::cmd("SET")
+                    .arg(key)
+                    .arg(1)
+                    .arg("NX")
+                    .arg("PX")
+                    .arg(window.as_millis() as u64)
+                    .clone();
+                store
+                    .query::<Option<String>>(cmd)
+                    .await
+                    .is_none_or(|result| result.is_some())
+            },
+        }
+    }
+    async fn remove(&self, key: &str) {
+        match self {
+            Self::Memory(ids) => {
+                if let Ok(mut ids) = ids.lock() {
+                    ids.remove(key);
+                }
+            },
+            Self::Redis(store) => {
+                let _ = store
+                    .query::<i64>(redis::cmd("DEL").arg(key).clone())
+                    .await;
+            },
+        }
+    }
+}
+
+/// Gets the value of json field by the dot separated path, e.g. `event.id`,
+/// the string and number values are supported.
+fn get_json_field(body: &[u8], path: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_slice(body).ok()?;
+    let value = path
+        .split('.')
+        .try_fold(&value, |value, field| value.get(field))?;
+    match value {
+        serde_json::Value::String(value) if !value.is_empty() => {
+            Some(value.clone())
+        },
+        serde_json::Value::Number(value) => Some(value.to_string()),
+        _ => None,
+    }
+}
+
+/// Dedup plugin drops the duplicate webhooks or events, the event id is
+/// read from the request header or the field of json body, and the
+/// request with an event id seen within the window is responded with
+/// `200`(or `409`) and `X-Deduplicated: true` header without hitting the
+/// upstream. The event id is released if the upstream responds 5xx, so
+/// the sender can redeliver it. The request without event id is passed.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// # the event id of header, or the field of json body
+/// header = "X-Event-Id"
+/// body_field = "event.id"
+/// window = "5m"
+/// # the status of duplicate request, 200 or 409
+/// status = 200
+/// methods = ["POST"]
+/// # optional, share the event ids via redis
+/// store = "redis://127.0.0.1:6379"
+/// timeout = "200ms"
+/// ```
+pub struct Dedup {
+    header: Option<HeaderName>,
+    body_field: String,
+    window: Duration,
+    status: StatusCode,
+    methods: Vec<Method>,
+    store: DedupStore,
+    prefix: String,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for Dedup {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::Dedup.to_string(),
+            message,
+        };
+        let header = get_str_conf(value, "header");
+        let header = if header.is_empty() {
+            None
+        } else {
+            Some(HeaderName::from_str(&header).map_err(|e| {
+                new_invalid_error(format!("header({header}) is invalid, {e}"))
+            })?)
+        };
+        let body_field = get_str_conf(value, "body_field");
+        if header.is_none() && body_field.is_empty() {
+            return Err(new_invalid_error(
+                "header or body field should be set".to_string(),
+            ));
+        }
+        let window = get_duration_conf(value, "window")
+            .unwrap_or(Duration::from_secs(5 * 60));
+        if window.is_zero() {
+            return Err(new_invalid_error(
+                "window should be greater than 0".to_string(),
+            ));
+        }
+        let status = match get_int_conf(value, "status") {
+            0 | 200 => StatusCode::OK,
+            409 => StatusCode::CONFLICT,
+            status => {
+                return Err(new_invalid_error(format!(
+                    "status({status}) should be 200 or 409"
+                )));
+            },
+        };
+        let methods = get_str_slice_conf(value, "methods");
+        let methods = if methods.is_empty() {
+            vec![Method::POST]
+        } else {
+            methods
+                .iter()
+                .map(|item| {
+                    Method::from_str(&item.to_uppercase()).map_err(|e| {
+                        new_invalid_error(format!(
+                            "method({item}) is invalid, {e}"
+                        ))
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+        let store = get_str_conf(value, "store");
+        let store = if store.is_empty() {
+            DedupStore::Memory(Mutex::new(AHashMap::new()))
+        } else {
+            DedupStore::Redis(RedisStore::new(value, PluginCategory::Dedup)?)
+        };
+        Ok(Self {
+            header,
+            body_field,
+            window,
+            status,
+            methods,
+            store,
+            prefix: format!("pingap:dedup:{hash_value}:"),
+            hash_value,
+        })
+    }
+}
+
+impl Dedup {
+    /// Creates a new dedup plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new dedup plugin");
+        Self::try_from(params)
+    }
+
+    /// Gets the event id of the request, the header takes precedence over
+    /// the body field. The body is kept in the retry buffer for upstream.
+    async fn get_event_id(
+        &self,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<Option<String>> {
+        if let Some(header) = &self.header {
+            let value = session.get_header_bytes(header);
+            if !value.is_empty() {
+                return Ok(Some(String::from_utf8_lossy(value).to_string()));
+            }
+        }
+        if self.body_field.is_empty() {
+            return Ok(None);
+        }
+        let content_length = session
+            .req_header()
+            .headers
+            .get(http::header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or_default();
+        if content_length > MAX_BODY_SIZE {
+            return Ok(None);
+        }
+        write_continue_response(session, ctx).await?;
+        session.enable_retry_buffering();
+        let mut body = BytesMut::new();
+        while let Some(chunk) = session.read_request_body().await? {
+            // the body exceeding the retry buffer can't be sent to upstream
+            if body.len() + chunk.len() > MAX_BODY_SIZE {
+                return Err(pingap_core::new_internal_error(
+                    413,
+                    "Request body is too large",
+                ));
+            }
+            body.extend_from_slice(&chunk);
+        }
+        Ok(get_json_field(&body, &self.body_field))
+    }
+}
+
+#[async_trait]
+impl Plugin for Dedup {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Responds the duplicate request directly, or marks the event id
+    /// as seen and forwards the request to upstream.
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request
+            || !self.methods.contains(&session.req_header().method)
+        {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let Some(event_id) = self.get_event_id(session, ctx).await? else {
+            return Ok(RequestPluginResult::Skipped);
+        };
+        let key = format!("{}{event_id}", self.prefix);
+        if !self.store.mark(&key, self.window).await {
+            debug!(event_id, "drop the duplicate event");
+            return Ok(RequestPluginResult::Respond(HttpResponse {
+                status: self.status,
+                headers: Some(vec![(
+                    HeaderName::from_static(HTTP_HEADER_DEDUPLICATED),
+                    HeaderValue::from_static("true"),
+                )]),
+                body: Bytes::from_static(b"Duplicate event"),
+                ..Default::default()
+            }));
+        }
+        ctx.add_variable(DEDUP_EVENT_ID_VARIABLE, &event_id);
+        Ok(RequestPluginResult::Continue)
+    }
+
+    /// Releases the event id if the upstream fails to process it
+    async fn handle_response(
+        &self,
+        _session: &mut Session,
+        ctx: &mut Ctx,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<ResponsePluginResult> {
+        if !upstream_response.status.is_server_error() {
+            return Ok(ResponsePluginResult::Unchanged);
+        }
+        if let Some(event_id) = ctx.get_variable(DEDUP_EVENT_ID_VARIABLE) {
+            let key = format!("{}{event_id}", self.prefix);
+            self.store.remove(&key).await;
+        }
+        Ok(ResponsePluginResult::Unchanged)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory()
+        .register("dedup", |params| Ok(Arc::new(Dedup::new(params)?)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+
+    fn new_plugin(conf: &str) -> Dedup {
+        Dedup::new(&toml::from_str::<PluginConf>(conf).unwrap()).unwrap()
+    }
+
+    async fn handle(
+        plugin: &Dedup,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> RequestPluginResult {
+        plugin
+            .handle_request(PluginStep::Request, session, ctx)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_dedup_params() {
+        let plugin = new_plugin(
+            r###"
+header = "X-Event-Id"
+"###,
+        );
+        assert_eq!("x-event-id", plugin.header.unwrap().as_str());
+        assert_eq!(Duration::from_secs(300), plugin.window);
+        assert_eq!(StatusCode::OK, plugin.status);
+        assert_eq!(vec![Method::POST], plugin.methods);
+        assert_eq!(true, matches!(plugin.store, DedupStore::Memory(_)));
+
+        let plugin = new_plugin(
+            r###"
+body_field = "event.id"
+status = 409
+window = "1m"
+store = "redis://127.0.0.1:6379"
+"###,
+        );
+        assert_eq!(StatusCode::CONFLICT, plugin.status);
+        assert_eq!(Duration::from_secs(60), plugin.window);
+        assert_eq!(true, matches!(plugin.store, DedupStore::Redis(_)));
+
+        for (conf, message) in [
+            ("", "header or body field should be set"),
+            (
+                "header = \"X-Event-Id\"\nstatus = 204",
+                "status(204) should be 200 or 409",
+            ),
+            // redis is built without tls
+            (
+                "header = \"X-Event-Id\"\nstore = \"rediss://127.0.0.1:6379\"",
+                "store(rediss://127.0.0.1:6379) should be redis://",
+            ),
+        ] {
+            let result =
+                Dedup::try_from(&toml::from_str::<PluginConf>(conf).unwrap());
+            assert_eq!(
+                format!("Plugin dedup invalid, message: {message}"),
+                result.err().unwrap().to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_json_field() {
+        let body = br#"{"id":"evt_1","event":{"id":123}}"#;
+        assert_eq!(Some("evt_1".to_string()), get_json_field(body, "id"));
+        assert_eq!(Some("123".to_string()), get_json_field(body, "event.id"));
+        assert_eq!(None, get_json_field(body, "event.type"));
+        assert_eq!(None, get_json_field(b"id=1", "id"));
+    }
+
+    #[tokio::test]
+    async fn test_dedup_header() {
+        let plugin = new_plugin(
+            r###"
+header = "X-Event-Id"
+"###,
+        );
+
+        let body = "{}";
+        let headers = [
+            "Host: pingap.io",
+            "X-Event-Id: evt_1",
+            "Content-Type: application/json",
+            "Content-Length: 2",
+        ]
+        .join("\r\n");
+        let input_header =
+            format!("POST /webhooks HTTP/1.1\r\n{headers}\r\n\r\n{body}");
+        let mut session = test_util::new_session(&input_header).await;
+        let mut ctx = Ctx::default();
+        let result = handle(&plugin, &mut session, &mut ctx).await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!(Some("evt_1"), ctx.get_variable(DEDUP_EVENT_ID_VARIABLE));
+
+        // the repeated event id within the window is deduplicated
+        let body = "{}";
+        let headers = [
+            "Host: pingap.io",
+            "X-Event-Id: evt_1",
+            "Content-Type: application/json",
+            "Content-Length: 2",
+        ]
+        .join("\r\n");
+        let input_header =
+            format!("POST /webhooks HTTP/1.1\r\n{headers}\r\n\r\n{body}");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = handle(&plugin, &mut session, &mut Ctx::default()).await;
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("result should be respond");
+        };
+        assert_eq!(StatusCode::OK, resp.status);
+        assert_eq!(
+            "true",
+            resp.headers.unwrap()[0].1.to_str().unwrap().to_string()
+        );
+
+        // the other event id and the request without event id are passed
+        let body = "{}";
+        let headers = [
+            "Host: pingap.io",
+            "X-Event-Id: evt_2",
+            "Content-Type: application/json",
+            "Content-Length: 2",
+        ]
+        .join("\r\n");
+        let input_header =
+            format!("POST /webhooks HTTP/1.1\r\n{headers}\r\n\r\n{body}");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = handle(&plugin, &mut session, &mut Ctx::default()).await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        let body = "{}";
+        let headers = [
+            "Host: pingap.io",
+            "Content-Type: application/json",
+            "Content-Length: 2",
+        ]
+        .join("\r\n");
+        let input_header =
+            format!("POST /webhooks HTTP/1.1\r\n{headers}\r\n\r\n{body}");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = handle(&plugin, &mut session, &mut Ctx::default()).await;
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+
+        // the event id is released if the upstream fails
+        let body = "{}";
+        let headers = [
+            "Host: pingap.io",
+            "X-Event-Id: evt_3",
+            "Content-Type: application/json",
+            "Content-Length: 2",
+        ]
+        .join("\r\n");
+        let input_header =
+            format!("POST /webhooks HTTP/1.1\r\n{headers}\r\n\r\n{body}");
+        let mut session = test_util::new_session(&input_header).await;
+        let mut ctx = Ctx::default();
+        handle(&plugin, &mut session, &mut ctx).await;
+        let mut upstream_response = ResponseHeader::build(503, None).unwrap();
+        plugin
+            .handle_response(&mut session, &mut ctx, &mut upstream_response)
+            .await
+            .unwrap();
+        let body = "{}";
+        let headers = [
+            "Host: pingap.io",
+            "X-Event-Id: evt_3",
+            "Content-Type: application/json",
+            "Content-Length: 2",
+        ]
+        .join("\r\n");
+        let input_header =
+            format!("POST /webhooks HTTP/1.1\r\n{headers}\r\n\r\n{body}");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = handle(&plugin, &mut session, &mut Ctx::default()).await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+    }
+
+    #[tokio::test]
+    async fn test_dedup_body_field() {
+        let plugin = new_plugin(
+            r###"
+body_field = "event.id"
+status = 409
+window = "50ms"
+"###,
+        );
+        let body = r#"{"event":{"id":"evt_1"}}"#;
+
+        let input_header = format!(
+            "POST /webhooks HTTP/1.1\r\nHost: pingap.io\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let mut session = test_util::new_session(&input_header).await;
+        let result = handle(&plugin, &mut session, &mut Ctx::default()).await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        // the body is kept for upstream
+        assert_eq!(
+            Some(Bytes::from_static(body.as_bytes())),
+            session.get_retry_buffer()
+        );
+
+        let input_header = format!(
+            "POST /webhooks HTTP/1.1\r\nHost: pingap.io\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let mut session = test_util::new_session(&input_header).await;
+        let result = handle(&plugin, &mut session, &mut Ctx::default()).await;
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("result should be respond");
+        };
+        assert_eq!(StatusCode::CONFLICT, resp.status);
+
+        // the event id is accepted again after the window
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let input_header = format!(
+            "POST /webhooks HTTP/1.1\r\nHost: pingap.io\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let mut session = test_util::new_session(&input_header).await;
+        let result = handle(&plugin, &mut session, &mut Ctx::default()).await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+    }
+}
@@ -24,7 +24,9 @@ use http::{HeaderValue, StatusCode, header};
 use humantime::parse_duration;
 use path_absolutize::Absolutize;
 use pingap_config::{PluginCategory, PluginConf};
-use pingap_core::{Ctx, HTTP_HEADER_CONTENT_TEXT, Plugin, PluginStep};
+use pingap_core::{
+    Ctx, HTTP_HEADER_CONTENT_HTML, HTTP_HEADER_CONTENT_TEXT, Plugin, PluginStep,
+};
 use pingap_core::{
     HttpChunkResponse, HttpHeader, HttpResponse, RequestPluginResult,
     convert_headers,
@@ -154,6 +156,14 @@ pub struct Directory {
     // Forces browser to download rather than display inline
     download: bool,
 
+    // Upstream to proxy the request to when reading the file fails
+    // (e.g. permission denied or disk error), it's not used for not found
+    io_error_upstream: Option<String>,
+
+    // Html page served with 503 when reading the file fails,
+    // it's loaded when the plugin is created
+    io_error_page: Option<String>,
+
     // Unique identifier for this plugin instance
     hash_value: String,
 }
@@ -303,6 +313,19 @@ impl TryFrom<&PluginConf> for Directory {
         if !index.starts_with("/") {
             index = format!("/{index}");
         }
+        let io_error_upstream = get_str_conf(value, "io_error_upstream");
+        let io_error_page = get_str_conf(value, "io_error_page");
+        let io_error_page = if io_error_page.is_empty() {
+            None
+        } else {
+            let file = pingap_util::resolve_path(&io_error_page);
+            Some(std::fs::read_to_string(&file).map_err(|e| {
+                Error::Invalid {
+                    category: PluginCategory::Directory.to_string(),
+                    message: format!("read io error page({file}) fail, {e}"),
+                }
+            })?)
+        };
         let params = Self {
             hash_value,
             autoindex: get_bool_conf(value, "autoindex"),
@@ -318,6 +341,9 @@ impl TryFrom<&PluginConf> for Directory {
             plugin_step: step,
             download: get_bool_conf(value, "download"),
             headers: Some(headers),
+            io_error_upstream: Some(io_error_upstream)
+                .filter(|value| !value.is_empty()),
+            io_error_page,
         };
         if ![PluginStep::Request, PluginStep::ProxyUpstream]
             .contains(&params.plugin_step)
@@ -348,6 +374,36 @@ impl Directory {
         debug!(params = params.to_string(), "new serve static file plugin");
         Self::try_from(params)
     }
+
+    /// Handles the io error of reading file(e.g. permission denied),
+    /// the request is proxied to the fallback upstream or responded with
+    /// the error page, the details of error are not responded.
+    fn handle_io_error(
+        &self,
+        ctx: &mut Ctx,
+        file: &Path,
+        err: std::io::Error,
+    ) -> RequestPluginResult {
+        error!(
+            error = err.to_string(),
+            file = format!("{file:?}"),
+            "read static file fail"
+        );
+        if let Some(upstream) = &self.io_error_upstream {
+            ctx.upstream.name = upstream.as_str().into();
+            return RequestPluginResult::Continue;
+        }
+        let resp = if let Some(page) = &self.io_error_page {
+            HttpResponse::builder(StatusCode::SERVICE_UNAVAILABLE)
+                .body(page.clone())
+                .header(HTTP_HEADER_CONTENT_HTML.clone())
+                .no_store()
+                .finish()
+        } else {
+            HttpResponse::unknown_error("Get file data fail")
+        };
+        RequestPluginResult::Respond(resp)
+    }
 }
 
 static IGNORE_RESPONSE: LazyLock<HttpResponse> =
@@ -535,8 +591,7 @@ impl Plugin for Directory {
                             ..Default::default()
                         },
                         Err(e) => {
-                            error!(error = e.to_string(), "read data fail");
-                            HttpResponse::bad_request(e.to_string())
+                            return Ok(self.handle_io_error(ctx, &file, e));
                         },
                     }
                 } else {
@@ -561,7 +616,7 @@ impl Plugin for Directory {
                 if err.kind() == std::io::ErrorKind::NotFound {
                     HttpResponse::not_found("Not Found")
                 } else {
-                    HttpResponse::unknown_error("Get file data fail")
+                    return Ok(self.handle_io_error(ctx, &file, err));
                 }
             },
         };
@@ -721,6 +776,85 @@ download = true
         );
     }
 
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_directory_io_error() {
+        let dir = tempfile::tempdir().unwrap();
+        // the symlink loop fails to read with io error
+        std::os::unix::fs::symlink(
+            dir.path().join("loop.html"),
+            dir.path().join("loop.html"),
+        )
+        .unwrap();
+        let page = dir.path().join("error.html");
+        std::fs::write(&page, "<p>Service Unavailable</p>").unwrap();
+        let handle = |plugin: Directory, path: &'static str| async move {
+            let input_header = format!("GET {path} HTTP/1.1\r\n\r\n");
+            let mock_io = Builder::new().read(input_header.as_bytes()).build();
+            let mut session = Session::new_h1(Box::new(mock_io));
+            session.read_request().await.unwrap();
+            let mut ctx = Ctx::default();
+            let result = plugin
+                .handle_request(PluginStep::Request, &mut session, &mut ctx)
+                .await
+                .unwrap();
+            (result, ctx)
+        };
+        let new_plugin = |conf: String| {
+            Directory::new(&toml::from_str::<PluginConf>(&conf).unwrap())
+                .unwrap()
+        };
+        let path = dir.path().to_string_lossy().to_string();
+
+        // the io error follows the fallback upstream
+        let conf = format!("path = \"{path}\"\nio_error_upstream = \"backup\"");
+        let (result, ctx) =
+            handle(new_plugin(conf.clone()), "/loop.html").await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!("backup", ctx.upstream.name.as_ref());
+        // the missing file is still not found
+        let (result, ctx) = handle(new_plugin(conf), "/missing.html").await;
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("result is not Respond");
+        };
+        assert_eq!(404, resp.status.as_u16());
+        assert_eq!("", ctx.upstream.name.as_ref());
+
+        // the io error is responded with the error page
+        let conf = format!(
+            "path = \"{path}\"\nio_error_page = \"{}\"",
+            page.to_string_lossy()
+        );
+        let (result, _) = handle(new_plugin(conf.clone()), "/loop.html").await;
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("result is not Respond");
+        };
+        assert_eq!(503, resp.status.as_u16());
+        assert_eq!(
+            "<p>Service Unavailable</p>",
+            std::string::String::from_utf8_lossy(resp.body.as_ref())
+        );
+        let (result, _) = handle(new_plugin(conf), "/missing.html").await;
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("result is not Respond");
+        };
+        assert_eq!(404, resp.status.as_u16());
+
+        // the io error page should be readable
+        let result = Directory::try_from(
+            &toml::from_str::<PluginConf>(&format!(
+                "path = \"{path}\"\nio_error_page = \"{path}/none.html\""
+            ))
+            .unwrap(),
+        );
+        assert_eq!(
+            true,
+            result.err().unwrap().to_string().starts_with(
+                "Plugin directory invalid, message: read io error page"
+            )
+        );
+    }
+
     #[tokio::test]
     async fn test_get_data() {
         let file = Path::new("./index.html").to_path_buf();
@@ -0,0 +1,507 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::jwt::JWT_CLAIM_VARIABLE_PREFIX;
+use super::{
+    Error, get_bool_conf, get_duration_conf, get_hash_key, get_int_conf,
+    get_plugin_factory, get_str_conf,
+};
+use ahash::AHashMap;
+use async_trait::async_trait;
+use bytes::Bytes;
+use ctor::ctor;
+use http::{HeaderName, HeaderValue, StatusCode};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, HttpResponse, Plugin, PluginStep, RequestPluginResult,
+    get_req_header_value,
+};
+use pingora::proxy::Session;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::{debug, error};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Maximum count of the cached entitlements
+const MAX_CACHED_ENTITLEMENTS: usize = 10_000;
+
+/// The placeholder of identity in the lookup url
+const IDENTITY_PLACEHOLDER: &str = "{identity}";
+
+#[derive(Deserialize, Clone, Debug, PartialEq)]
+struct EntitlementResponse {
+    /// Whether the subscription is active
+    active: bool,
+    /// The entitlement(e.g. plan) forwarded to upstream
+    #[serde(default)]
+    entitlement: Option<String>,
+}
+
+/// Entitlement plugin checks the subscription status of the authenticated
+/// identity by the entitlements service, the request of inactive
+/// subscription is rejected with 402(or 403), otherwise the entitlement
+/// is forwarded to upstream by the request header.
+/// The identity is the verified claim of jwt plugin(executed before this
+/// plugin) or the value of request header(e.g. api key).
+///
+/// The entitlements service responds json `{"active": true, "entitlement":
+/// "pro"}`, the result is cached briefly to avoid the lookup per request.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// # `{identity}` is replaced by the url encoded identity,
+/// # otherwise it's appended as the `identity` query
+/// url = "http://entitlements/subscriptions/{identity}"
+/// # identity from the jwt claim, default is `sub`
+/// claim = "sub"
+/// # or identity from the request header, e.g. api key
+/// # header = "X-Api-Key"
+/// # status of the inactive subscription, 402(default) or 403
+/// inactive_status = 402
+/// # request header of the entitlement, default is `X-Entitlement`
+/// entitlement_header = "X-Entitlement"
+/// cache_ttl = "30s"
+/// timeout = "5s"
+/// # forward the request if the entitlements service fails, default false
+/// fail_open = false
+/// ```
+pub struct Entitlement {
+    url: String,
+    /// The jwt claim used as identity
+    claim: String,
+    /// The request header used as identity, it takes precedence over claim
+    header: Option<String>,
+    inactive_status: StatusCode,
+    entitlement_header: HeaderName,
+    cache_ttl: Duration,
+    fail_open: bool,
+    /// Identity to the expired time and result of lookup
+    entitlements: Mutex<AHashMap<String, (Instant, EntitlementResponse)>>,
+    client: reqwest::Client,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for Entitlement {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::Entitlement.to_string(),
+            message,
+        };
+        let url = get_str_conf(value, "url");
+        if url.is_empty() {
+            return Err(new_invalid_error("url can't be empty".to_string()));
+        }
+        let inactive_status = match get_int_conf(value, "inactive_status") {
+            0 | 402 => StatusCode::PAYMENT_REQUIRED,
+            403 => StatusCode::FORBIDDEN,
+            status => {
+                return Err(new_invalid_error(format!(
+                    "inactive status({status}) should be 402 or 403"
+                )));
+            },
+        };
+        let mut claim = get_str_conf(value, "claim");
+        if claim.is_empty() {
+            claim = "sub".to_string();
+        }
+        let header = get_str_conf(value, "header");
+        let mut entitlement_header = get_str_conf(value, "entitlement_header");
+        if entitlement_header.is_empty() {
+            entitlement_header = "X-Entitlement".to_string();
+        }
+        let entitlement_header = HeaderName::from_bytes(
+            entitlement_header.as_bytes(),
+        )
+        .map_err(|e| new_invalid_error(format!("entitlement header: {e}")))?;
+        let client = reqwest::Client::builder()
+            .timeout(
+                get_duration_conf(value, "timeout")
+                    .unwrap_or(Duration::from_secs(5)),
+            )
+            .build()
+            .map_err(|e| new_invalid_error(e.to_string()))?;
+
+        Ok(Self {
+            url,
+            claim,
+            header: (!header.is_empty()).then_some(header),
+            inactive_status,
+            entitlement_header,
+            cache_ttl: get_duration_conf(value, "cache_ttl")
+                .unwrap_or(Duration::from_secs(30)),
+            fail_open: get_bool_conf(value, "fail_open"),
+            entitlements: Mutex::new(AHashMap::new()),
+            client,
+            hash_value,
+        })
+    }
+}
+
+impl Entitlement {
+    /// Creates a new entitlement plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new entitlement plugin");
+        Self::try_from(params)
+    }
+
+    /// Gets the identity from the request header or the jwt claim variable
+    fn get_identity(&self, session: &Session, ctx: &Ctx) -> Option<String> {
+        let identity = if let Some(header) = &self.header {
+            get_req_header_value(session.req_header(), header)
+        } else {
+            ctx.get_variable(&format!(
+                "{JWT_CLAIM_VARIABLE_PREFIX}{}",
+                self.claim
+            ))
+        };
+        identity
+            .filter(|value| !value.is_empty())
+            .map(|value| value.to_string())
+    }
+
+    /// Gets the lookup url of identity
+    fn get_url(&self, identity: &str) -> String {
+        let identity = urlencoding::encode(identity);
+        if self.url.contains(IDENTITY_PLACEHOLDER) {
+            return self.url.replace(IDENTITY_PLACEHOLDER, &identity);
+        }
+        let separator = if self.url.contains('?') { '&' } else { '?' };
+        format!("{}{separator}identity={identity}", self.url)
+    }
+
+    /// Gets the cached result of lookup
+    fn get_cached(&self, identity: &str) -> Option<EntitlementResponse> {
+        let entitlements = self.entitlements.lock().ok()?;
+        entitlements
+            .get(identity)
+            .filter(|(expired_at, _)| *expired_at > Instant::now())
+            .map(|(_, resp)| resp.clone())
+    }
+
+    /// Caches the result of lookup, the expired items are removed
+    /// if the cache is full.
+    fn set_cached(&self, identity: &str, resp: &EntitlementResponse) {
+        if self.cache_ttl.is_zero() {
+            return;
+        }
+        let Ok(mut entitlements) = self.entitlements.lock() else {
+            return;
+        };
+        let now = Instant::now();
+        if entitlements.len() >= MAX_CACHED_ENTITLEMENTS {
+            entitlements.retain(|_, (expired_at, _)| *expired_at > now);
+            if entitlements.len() >= MAX_CACHED_ENTITLEMENTS {
+                entitlements.clear();
+            }
+        }
+        entitlements
+            .insert(identity.to_string(), (now + self.cache_ttl, resp.clone()));
+    }
+
+    /// Looks up the entitlement of identity, the failure of service
+    /// is returned as none and not cached.
+    async fn lookup(&self, identity: &str) -> Option<EntitlementResponse> {
+        if let Some(resp) = self.get_cached(identity) {
+            return Some(resp);
+        }
+        let url = self.get_url(identity);
+        let result = async {
+            self.client
+                .get(&url)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<EntitlementResponse>()
+                .await
+        }
+        .await;
+        match result {
+            Ok(resp) => {
+                self.set_cached(identity, &resp);
+                Some(resp)
+            },
+            Err(e) => {
+                error!(error = %e, url, "lookup entitlement fail");
+                None
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for Entitlement {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Checks the subscription of identity, the request of inactive
+    /// subscription is rejected, the request without identity is
+    /// rejected with 403.
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        // the entitlement header should not be set by client
+        session
+            .req_header_mut()
+            .remove_header(&self.entitlement_header);
+        let Some(identity) = self.get_identity(session, ctx) else {
+            return Ok(RequestPluginResult::Respond(HttpResponse {
+                status: StatusCode::FORBIDDEN,
+                body: Bytes::from_static(b"Identity is missing"),
+                ..Default::default()
+            }));
+        };
+        let Some(resp) = self.lookup(&identity).await else {
+            if self.fail_open {
+                return Ok(RequestPluginResult::Continue);
+            }
+            return Ok(RequestPluginResult::Respond(HttpResponse {
+                status: StatusCode::SERVICE_UNAVAILABLE,
+                body: Bytes::from_static(
+                    b"Entitlements service is unavailable",
+                ),
+                ..Default::default()
+            }));
+        };
+        if !resp.active {
+            return Ok(RequestPluginResult::Respond(HttpResponse {
+                status: self.inactive_status,
+                body: Bytes::from_static(b"Subscription is inactive"),
+                ..Default::default()
+            }));
+        }
+        let entitlement =
+            resp.entitlement.unwrap_or_else(|| "active".to_string());
+        if let Ok(value) = HeaderValue::from_str(&entitlement) {
+            session
+                .req_header_mut()
+                .insert_header(self.entitlement_header.clone(), value)?;
+        }
+        Ok(RequestPluginResult::Continue)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("entitlement", |params| {
+        Ok(Arc::new(Entitlement::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Starts a mock entitlements server, `alice` is active with the `pro`
+    /// entitlement, `bob` is inactive and the others fail with 500,
+    /// returns the url and the count of lookup requests.
+    async fn start_entitlements_server() -> (String, Arc<AtomicUsize>) {
+        let listener =
+            tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "http://{}/subscriptions/{{identity}}",
+            listener.local_addr().unwrap()
+        );
+        let count = Arc::new(AtomicUsize::new(0));
+        let lookup_count = count.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                lookup_count.fetch_add(1, Ordering::Relaxed);
+                let mut req = String::new();
+                let mut data = [0; 4096];
+                while !req.contains("\r\n\r\n") {
+                    let size = stream.read(&mut data).await.unwrap();
+                    if size == 0 {
+                        break;
+                    }
+                    req.push_str(&String::from_utf8_lossy(&data[..size]));
+                }
+                let (status, body) =
+                    if req.starts_with("GET /subscriptions/alice ") {
+                        ("200 OK", r#"{"active":true,"entitlement":"pro"}"#)
+                    } else if req.starts_with("GET /subscriptions/bob ") {
+                        ("200 OK", r#"{"active":false}"#)
+                    } else {
+                        ("500 Internal Server Error", "")
+                    };
+                let resp = format!(
+                    "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                    body.len()
+                );
+                stream.write_all(resp.as_bytes()).await.unwrap();
+            }
+        });
+        (url, count)
+    }
+
+    async fn handle(
+        plugin: &Entitlement,
+        session: &mut Session,
+        identity: &str,
+    ) -> RequestPluginResult {
+        let mut ctx = Ctx::default();
+        if !identity.is_empty() {
+            ctx.add_variable(
+                &format!("{JWT_CLAIM_VARIABLE_PREFIX}sub"),
+                identity,
+            );
+        }
+        plugin
+            .handle_request(PluginStep::Request, session, &mut ctx)
+            .await
+            .unwrap()
+    }
+
+    fn get_status(result: RequestPluginResult) -> Option<StatusCode> {
+        match result {
+            RequestPluginResult::Respond(resp) => Some(resp.status),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_entitlement_params() {
+        let plugin = Entitlement::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+url = "http://127.0.0.1:3000/subscriptions"
+header = "X-Api-Key"
+inactive_status = 403
+cache_ttl = "1m"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(StatusCode::FORBIDDEN, plugin.inactive_status);
+        assert_eq!("x-entitlement", plugin.entitlement_header.as_str());
+        assert_eq!(Duration::from_secs(60), plugin.cache_ttl);
+        assert_eq!(
+            "http://127.0.0.1:3000/subscriptions?identity=a%2Fb",
+            plugin.get_url("a/b")
+        );
+
+        let result = Entitlement::try_from(&PluginConf::default());
+        assert_eq!(
+            "Plugin entitlement invalid, message: url can't be empty",
+            result.err().unwrap().to_string()
+        );
+
+        let result = Entitlement::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+url = "http://127.0.0.1:3000/subscriptions"
+inactive_status = 401
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin entitlement invalid, message: inactive status(401) should be 402 or 403",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_entitlement() {
+        let (url, count) = start_entitlements_server().await;
+        let plugin = Entitlement::new(
+            &toml::from_str::<PluginConf>(&format!(r#"url = "{url}""#))
+                .unwrap(),
+        )
+        .unwrap();
+
+        // the active subscription is forwarded with the entitlement,
+        // the header set by client is replaced
+        let headers = ["X-Entitlement: enterprise"].join("\r\n");
+        let input_header = format!("GET /api HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = handle(&plugin, &mut session, "alice").await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!(
+            "pro",
+            session.req_header().headers.get("x-entitlement").unwrap()
+        );
+        assert_eq!(1, count.load(Ordering::Relaxed));
+
+        // the result is cached
+        let mut session =
+            test_util::new_session("GET /api HTTP/1.1\r\n\r\n").await;
+        let result = handle(&plugin, &mut session, "alice").await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!(1, count.load(Ordering::Relaxed));
+
+        // the inactive subscription is rejected
+        let headers = ["X-Entitlement: pro"].join("\r\n");
+        let input_header = format!("GET /api HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = handle(&plugin, &mut session, "bob").await;
+        assert_eq!(Some(StatusCode::PAYMENT_REQUIRED), get_status(result));
+        assert_eq!(
+            true,
+            session.req_header().headers.get("x-entitlement").is_none()
+        );
+
+        // the request without identity is rejected
+        let mut session =
+            test_util::new_session("GET /api HTTP/1.1\r\n\r\n").await;
+        let result = handle(&plugin, &mut session, "").await;
+        assert_eq!(Some(StatusCode::FORBIDDEN), get_status(result));
+
+        // the failure of entitlements service is not cached
+        let mut session =
+            test_util::new_session("GET /api HTTP/1.1\r\n\r\n").await;
+        let result = handle(&plugin, &mut session, "carol").await;
+        assert_eq!(Some(StatusCode::SERVICE_UNAVAILABLE), get_status(result));
+        let result = handle(&plugin, &mut session, "carol").await;
+        assert_eq!(Some(StatusCode::SERVICE_UNAVAILABLE), get_status(result));
+        assert_eq!(4, count.load(Ordering::Relaxed));
+
+        // the request is forwarded if fail open
+        let plugin = Entitlement::new(
+            &toml::from_str::<PluginConf>(&format!(
+                r#"
+url = "{url}"
+fail_open = true
+"#
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+        let mut session =
+            test_util::new_session("GET /api HTTP/1.1\r\n\r\n").await;
+        let result = handle(&plugin, &mut session, "carol").await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+    }
+}
@@ -0,0 +1,318 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_bool_conf, get_duration_conf, get_hash_key, get_int_conf,
+    get_int_conf_or_default, get_plugin_factory,
+};
+use async_trait::async_trait;
+use ctor::ctor;
+use http::StatusCode;
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{Ctx, HttpResponse, Plugin, PluginStep, RequestPluginResult};
+use pingora::proxy::Session;
+use rand::{Rng, rng};
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// FaultInjection plugin injects the delay and error response for chaos
+/// testing, e.g. testing the timeout behavior of client. It does nothing
+/// unless `enabled` is set explicitly, so it can't run by accident.
+///
+/// The delay is fixed, or random between `delay` and `max_delay`,
+/// and the percent is the sampled rate of requests(0-100).
+///
+/// # Configuration (TOML)
+/// ```toml
+/// enabled = true
+/// delay = "100ms"
+/// max_delay = "1s"
+/// delay_percent = 50
+/// status = 503
+/// error_percent = 10
+/// ```
+pub struct FaultInjection {
+    enabled: bool,
+    delay: Option<Duration>,
+    max_delay: Option<Duration>,
+    delay_percent: u8,
+    status: StatusCode,
+    error_percent: u8,
+    hash_value: String,
+}
+
+/// Returns true if the sample hits the percent.
+fn sample(percent: u8) -> bool {
+    if percent == 0 {
+        return false;
+    }
+    percent >= 100 || rng().random_range(..100) < percent
+}
+
+impl TryFrom<&PluginConf> for FaultInjection {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let delay = get_duration_conf(value, "delay");
+        let max_delay = get_duration_conf(value, "max_delay");
+        if let (Some(delay), Some(max_delay)) = (delay, max_delay) {
+            if max_delay < delay {
+                return Err(Error::Invalid {
+                    category: PluginCategory::FaultInjection.to_string(),
+                    message: "max delay should be greater than delay"
+                        .to_string(),
+                });
+            }
+        }
+        let error_percent =
+            get_int_conf(value, "error_percent").clamp(0, 100) as u8;
+        let status = get_int_conf_or_default(value, "status", 503);
+        let status = u16::try_from(status)
+            .ok()
+            .and_then(|status| StatusCode::from_u16(status).ok())
+            .filter(|status| {
+                status.is_client_error() || status.is_server_error()
+            })
+            .ok_or_else(|| Error::Invalid {
+                category: PluginCategory::FaultInjection.to_string(),
+                message: format!("status({status}) should be 4xx or 5xx"),
+            })?;
+        Ok(Self {
+            enabled: get_bool_conf(value, "enabled"),
+            delay,
+            max_delay,
+            delay_percent: get_int_conf_or_default(value, "delay_percent", 100)
+                .clamp(0, 100) as u8,
+            status,
+            error_percent,
+            hash_value,
+        })
+    }
+}
+
+impl FaultInjection {
+    /// Creates a new fault injection plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new fault injection plugin");
+        Self::try_from(params)
+    }
+
+    /// Returns the delay of request, or none if it's not sampled.
+    fn get_delay(&self) -> Option<Duration> {
+        let delay = match (self.delay, self.max_delay) {
+            (Some(delay), Some(max_delay)) if max_delay > delay => {
+                rng().random_range(delay..=max_delay)
+            },
+            (Some(delay), _) => delay,
+            (None, Some(max_delay)) => {
+                rng().random_range(Duration::ZERO..=max_delay)
+            },
+            (None, None) => return None,
+        };
+        sample(self.delay_percent).then_some(delay)
+    }
+}
+
+#[async_trait]
+impl Plugin for FaultInjection {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Delays the request and responds the error status at the sampled rate.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        _session: &mut Session,
+        _ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request || !self.enabled {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let mut injected = false;
+        if let Some(delay) = self.get_delay() {
+            sleep(delay).await;
+            injected = true;
+        }
+        if sample(self.error_percent) {
+            return Ok(RequestPluginResult::Respond(HttpResponse {
+                status: self.status,
+                body: format!("Fault injection: {}", self.status).into(),
+                ..Default::default()
+            }));
+        }
+        if injected {
+            return Ok(RequestPluginResult::Continue);
+        }
+        Ok(RequestPluginResult::Skipped)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("fault_injection", |params| {
+        Ok(Arc::new(FaultInjection::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use std::time::Instant;
+    use tokio_test::io::Builder;
+
+    fn new_plugin(conf: &str) -> FaultInjection {
+        FaultInjection::new(&toml::from_str::<PluginConf>(conf).unwrap())
+            .unwrap()
+    }
+
+    async fn handle(plugin: &FaultInjection) -> RequestPluginResult {
+        let input_header = "GET /users HTTP/1.1\r\nHost: pingap.io\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_fault_injection_params() {
+        let plugin = FaultInjection::try_from(&PluginConf::default()).unwrap();
+        assert_eq!(false, plugin.enabled);
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, plugin.status);
+        assert_eq!(100, plugin.delay_percent);
+        assert_eq!(0, plugin.error_percent);
+
+        let result = FaultInjection::try_from(
+            &toml::from_str::<PluginConf>("status = 200").unwrap(),
+        );
+        assert_eq!(
+            "Plugin fault_injection invalid, message: status(200) should be 4xx or 5xx",
+            result.err().unwrap().to_string()
+        );
+
+        let result = FaultInjection::try_from(
+            &toml::from_str::<PluginConf>(
+                r#"
+delay = "1s"
+max_delay = "100ms"
+"#,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin fault_injection invalid, message: max delay should be greater than delay",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_sample() {
+        assert_eq!(false, (0..1000).any(|_| sample(0)));
+        assert_eq!(true, (0..1000).all(|_| sample(100)));
+        let hits = (0..10000).filter(|_| sample(30)).count();
+        assert_eq!(true, (2500..3500).contains(&hits), "hits: {hits}");
+    }
+
+    #[test]
+    fn test_get_delay() {
+        let plugin = new_plugin(
+            r#"
+delay = "100ms"
+max_delay = "200ms"
+"#,
+        );
+        for _ in 0..100 {
+            let delay = plugin.get_delay().unwrap();
+            assert_eq!(true, delay >= Duration::from_millis(100));
+            assert_eq!(true, delay <= Duration::from_millis(200));
+        }
+
+        let plugin = new_plugin(
+            r#"
+delay = "100ms"
+delay_percent = 0
+"#,
+        );
+        assert_eq!(None, plugin.get_delay());
+        assert_eq!(None, new_plugin("").get_delay());
+    }
+
+    #[tokio::test]
+    async fn test_fault_injection() {
+        // disabled by default
+        let plugin = new_plugin(
+            r#"
+delay = "1s"
+error_percent = 100
+"#,
+        );
+        let start = Instant::now();
+        assert_eq!(true, handle(&plugin).await == RequestPluginResult::Skipped);
+        assert_eq!(true, start.elapsed() < Duration::from_millis(100));
+
+        // delay the request
+        let plugin = new_plugin(
+            r#"
+enabled = true
+delay = "50ms"
+"#,
+        );
+        let start = Instant::now();
+        assert_eq!(
+            true,
+            handle(&plugin).await == RequestPluginResult::Continue
+        );
+        assert_eq!(true, start.elapsed() >= Duration::from_millis(50));
+
+        // inject error at the configured rate
+        let plugin = new_plugin(
+            r#"
+enabled = true
+status = 502
+error_percent = 50
+"#,
+        );
+        let mut errors = 0;
+        for _ in 0..1000 {
+            match handle(&plugin).await {
+                RequestPluginResult::Respond(resp) => {
+                    assert_eq!(StatusCode::BAD_GATEWAY, resp.status);
+                    errors += 1;
+                },
+                result => {
+                    assert_eq!(true, result == RequestPluginResult::Skipped)
+                },
+            }
+        }
+        assert_eq!(true, (400..600).contains(&errors), "errors: {errors}");
+    }
+}
@@ -0,0 +1,471 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_duration_conf, get_hash_key, get_plugin_factory,
+    get_str_slice_conf,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use ctor::ctor;
+use http::{HeaderValue, Method, StatusCode, header};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{Ctx, HttpResponse, Plugin, PluginStep, RequestPluginResult};
+use pingap_util::{IpRules, base64_decode};
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The default port of destination without port
+const DEFAULT_PORT: u16 = 443;
+
+#[derive(Debug, Clone)]
+enum HostPattern {
+    Any,
+    /// The suffix with leading dot, e.g. `.example.com`
+    Suffix(String),
+    Exact(String),
+    Ip(IpRules),
+}
+
+/// The destination rule of CONNECT tunnel, e.g. `*.example.com:443`,
+/// `10.0.0.0/8:*` or `[::1]:8443`.
+#[derive(Debug, Clone)]
+struct DestinationRule {
+    host: HostPattern,
+    /// None means any port
+    port: Option<u16>,
+}
+
+impl FromStr for DestinationRule {
+    type Err = String;
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        let value = value.trim();
+        let (host, port) = if let Some(value) = value.strip_prefix('[') {
+            let Some((host, rest)) = value.split_once(']') else {
+                return Err(format!("invalid destination({value})"));
+            };
+            (host, rest.strip_prefix(':'))
+        } else if value.matches(':').count() > 1 {
+            // ipv6 without brackets has no port
+            (value, None)
+        } else {
+            match value.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (value, None),
+            }
+        };
+        let port = match port {
+            Some("*") => None,
+            Some(port) => Some(
+                port.parse::<u16>()
+                    .map_err(|_| format!("invalid port of {value}"))?,
+            ),
+            None => Some(DEFAULT_PORT),
+        };
+        let host = normalize_host(host);
+        let host = if host.is_empty() {
+            return Err(format!("invalid destination({value})"));
+        } else if host == "*" {
+            HostPattern::Any
+        } else if let Some(suffix) = host.strip_prefix("*.") {
+            HostPattern::Suffix(format!(".{suffix}"))
+        } else if host.contains('/') || host.parse::<IpAddr>().is_ok() {
+            HostPattern::Ip(IpRules::new(&[host]))
+        } else {
+            HostPattern::Exact(host)
+        };
+        Ok(Self { host, port })
+    }
+}
+
+impl DestinationRule {
+    fn is_match_port(&self, port: u16) -> bool {
+        self.port.is_none_or(|value| value == port)
+    }
+    /// Checks whether the host name of destination matches the rule,
+    /// the ip rule is matched by the resolved addresses.
+    fn is_match_host(&self, host: &str, port: u16) -> bool {
+        if !self.is_match_port(port) {
+            return false;
+        }
+        match &self.host {
+            HostPattern::Any => true,
+            HostPattern::Suffix(suffix) => host.ends_with(suffix.as_str()),
+            HostPattern::Exact(value) => host == value,
+            HostPattern::Ip(_) => false,
+        }
+    }
+    fn is_match_addr(&self, addr: &SocketAddr) -> bool {
+        if !self.is_match_port(addr.port()) {
+            return false;
+        }
+        match &self.host {
+            HostPattern::Any => true,
+            HostPattern::Ip(rules) => rules.is_match_addr(&addr.ip()),
+            _ => false,
+        }
+    }
+}
+
+fn normalize_host(host: &str) -> String {
+    host.trim()
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .trim_end_matches('.')
+        .to_ascii_lowercase()
+}
+
+/// ForwardProxy plugin authorizes the CONNECT request, so pingap can be
+/// used as the forward proxy of allowed destinations(e.g. egress control).
+/// The destination is resolved and checked by the allow and deny rules,
+/// then the tunnel to the resolved addresses is established by the server.
+/// The deny rules take precedence, and the ip rules are also checked
+/// against the resolved addresses, so an allowed domain can't be used to
+/// reach a denied network.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// # host(exact, `*.suffix`, `*`, ip or cidr) and port(number or `*`),
+/// # the port is 443 if not set
+/// allow = ["*.example.com:443", "api.github.com", "10.0.0.0/8:*"]
+/// deny = ["10.0.0.1:*"]
+/// # base64 encoded "user:password" of Proxy-Authorization,
+/// # the authorization is not required if not set
+/// authorizations = ["dXNlcjpwYXNz"]
+/// resolve_timeout = "3s"
+/// ```
+pub struct ForwardProxy {
+    allow: Vec<DestinationRule>,
+    deny: Vec<DestinationRule>,
+    authorizations: Vec<Vec<u8>>,
+    resolve_timeout: Duration,
+    hash_value: String,
+}
+
+fn parse_rules(value: &PluginConf, key: &str) -> Result<Vec<DestinationRule>> {
+    get_str_slice_conf(value, key)
+        .iter()
+        .map(|item| {
+            DestinationRule::from_str(item).map_err(|message| Error::Invalid {
+                category: PluginCategory::ForwardProxy.to_string(),
+                message,
+            })
+        })
+        .collect()
+}
+
+impl TryFrom<&PluginConf> for ForwardProxy {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let allow = parse_rules(value, "allow")?;
+        if allow.is_empty() {
+            return Err(Error::Invalid {
+                category: PluginCategory::ForwardProxy.to_string(),
+                message: "allow destinations can't be empty".to_string(),
+            });
+        }
+        let mut authorizations = vec![];
+        for item in get_str_slice_conf(value, "authorizations").iter() {
+            let _ = base64_decode(item).map_err(|e| Error::Base64Decode {
+                category: PluginCategory::ForwardProxy.to_string(),
+                source: e,
+            })?;
+            authorizations.push(format!("Basic {item}").into_bytes());
+        }
+
+        Ok(Self {
+            allow,
+            deny: parse_rules(value, "deny")?,
+            authorizations,
+            resolve_timeout: get_duration_conf(value, "resolve_timeout")
+                .unwrap_or(Duration::from_secs(3)),
+            hash_value,
+        })
+    }
+}
+
+impl ForwardProxy {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new forward proxy plugin");
+        Self::try_from(params)
+    }
+    /// Checks the destination by its host name, the ip rules of allow list
+    /// are checked after resolving.
+    fn may_be_allowed(&self, host: &str, port: u16) -> bool {
+        if self.deny.iter().any(|rule| rule.is_match_host(host, port)) {
+            return false;
+        }
+        self.allow.iter().any(|rule| {
+            rule.is_match_host(host, port)
+                || (matches!(rule.host, HostPattern::Ip(_))
+                    && rule.is_match_port(port))
+        })
+    }
+    /// Checks the destination and its resolved addresses, the ip rules of
+    /// allow list must match all addresses.
+    fn is_allowed(&self, host: &str, port: u16, addrs: &[SocketAddr]) -> bool {
+        let denied = self.deny.iter().any(|rule| {
+            rule.is_match_host(host, port)
+                || addrs.iter().any(|addr| rule.is_match_addr(addr))
+        });
+        if denied {
+            return false;
+        }
+        self.allow.iter().any(|rule| {
+            rule.is_match_host(host, port)
+                || (!addrs.is_empty()
+                    && addrs.iter().all(|addr| rule.is_match_addr(addr)))
+        })
+    }
+}
+
+fn new_response(status: StatusCode, body: &'static str) -> HttpResponse {
+    HttpResponse {
+        status,
+        body: Bytes::from_static(body.as_bytes()),
+        ..Default::default()
+    }
+}
+
+#[async_trait]
+impl Plugin for ForwardProxy {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request
+            || session.req_header().method != Method::CONNECT
+        {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        if !self.authorizations.is_empty() {
+            let value = session.get_header_bytes(header::PROXY_AUTHORIZATION);
+            if !self.authorizations.iter().any(|auth| auth == value) {
+                let resp = HttpResponse {
+                    headers: Some(vec![(
+                        header::PROXY_AUTHENTICATE,
+                        HeaderValue::from_static(r#"Basic realm="pingap""#),
+                    )]),
+                    ..new_response(
+                        StatusCode::PROXY_AUTHENTICATION_REQUIRED,
+                        "Proxy authorization is required",
+                    )
+                };
+                return Ok(RequestPluginResult::Respond(resp));
+            }
+            // the credentials are not forwarded to destination
+            session
+                .req_header_mut()
+                .remove_header(&header::PROXY_AUTHORIZATION);
+        }
+
+        let uri = &session.req_header().uri;
+        let (Some(host), Some(port)) =
+            (uri.host().map(normalize_host), uri.port_u16())
+        else {
+            return Ok(RequestPluginResult::Respond(
+                HttpResponse::bad_request("Invalid CONNECT destination"),
+            ));
+        };
+        // the denied destination is rejected before resolving
+        if !self.may_be_allowed(&host, port) {
+            return Ok(RequestPluginResult::Respond(new_response(
+                StatusCode::FORBIDDEN,
+                "Destination is not allowed",
+            )));
+        }
+        let addrs = match tokio::time::timeout(
+            self.resolve_timeout,
+            tokio::net::lookup_host((host.as_str(), port)),
+        )
+        .await
+        {
+            Ok(Ok(addrs)) => addrs.collect::<Vec<_>>(),
+            _ => vec![],
+        };
+        if addrs.is_empty() {
+            return Ok(RequestPluginResult::Respond(new_response(
+                StatusCode::BAD_GATEWAY,
+                "Resolve destination fail",
+            )));
+        }
+        if !self.is_allowed(&host, port, &addrs) {
+            return Ok(RequestPluginResult::Respond(new_response(
+                StatusCode::FORBIDDEN,
+                "Destination is not allowed",
+            )));
+        }
+        ctx.upstream.tunnel_addrs = Some(addrs);
+        Ok(RequestPluginResult::Continue)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("forward_proxy", |params| {
+        Ok(Arc::new(ForwardProxy::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    fn new_plugin(conf: &str) -> ForwardProxy {
+        ForwardProxy::new(&toml::from_str::<PluginConf>(conf).unwrap()).unwrap()
+    }
+
+    async fn handle(
+        plugin: &ForwardProxy,
+        request_line: &str,
+        header: Option<&str>,
+    ) -> (RequestPluginResult, Ctx) {
+        let header = header.map(|v| format!("{v}\r\n")).unwrap_or_default();
+        let input_header = format!("{request_line}\r\n{header}\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let mut ctx = Ctx::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        (result, ctx)
+    }
+
+    fn get_status(result: &RequestPluginResult) -> Option<StatusCode> {
+        if let RequestPluginResult::Respond(resp) = result {
+            return Some(resp.status);
+        }
+        None
+    }
+
+    #[test]
+    fn test_destination_rule() {
+        let rule = DestinationRule::from_str("*.Example.com").unwrap();
+        assert_eq!(true, rule.is_match_host("api.example.com", 443));
+        assert_eq!(false, rule.is_match_host("example.com", 443));
+        assert_eq!(false, rule.is_match_host("api.example.com", 80));
+
+        let rule = DestinationRule::from_str("10.0.0.0/8:*").unwrap();
+        assert_eq!(false, rule.is_match_host("10.0.0.1", 22));
+        assert_eq!(true, rule.is_match_addr(&"10.0.0.1:22".parse().unwrap()));
+        assert_eq!(
+            false,
+            rule.is_match_addr(&"192.168.1.1:22".parse().unwrap())
+        );
+
+        let rule = DestinationRule::from_str("[::1]:8443").unwrap();
+        assert_eq!(true, rule.is_match_addr(&"[::1]:8443".parse().unwrap()));
+        assert_eq!(false, rule.is_match_addr(&"[::1]:443".parse().unwrap()));
+
+        assert_eq!(
+            "invalid port of api.github.com:abc",
+            DestinationRule::from_str("api.github.com:abc")
+                .err()
+                .unwrap()
+        );
+        assert_eq!(
+            "Plugin forward_proxy invalid, message: allow destinations can't be empty",
+            ForwardProxy::new(&PluginConf::default())
+                .err()
+                .unwrap()
+                .to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_forward_proxy() {
+        let plugin = new_plugin(
+            r###"
+allow = ["127.0.0.1:*", "localhost:8443"]
+deny = ["127.0.0.1:22"]
+"###,
+        );
+
+        // the allowed destination
+        let (result, ctx) =
+            handle(&plugin, "CONNECT 127.0.0.1:8080 HTTP/1.1", None).await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!(
+            Some(vec!["127.0.0.1:8080".parse::<SocketAddr>().unwrap()]),
+            ctx.upstream.tunnel_addrs
+        );
+
+        // the denied destination
+        let (result, ctx) =
+            handle(&plugin, "CONNECT 127.0.0.1:22 HTTP/1.1", None).await;
+        assert_eq!(Some(StatusCode::FORBIDDEN), get_status(&result));
+        assert_eq!(true, ctx.upstream.tunnel_addrs.is_none());
+
+        // not in the allow list
+        let (result, _) =
+            handle(&plugin, "CONNECT pingap.io:443 HTTP/1.1", None).await;
+        assert_eq!(Some(StatusCode::FORBIDDEN), get_status(&result));
+
+        // not CONNECT request
+        let (result, _) =
+            handle(&plugin, "GET / HTTP/1.1", Some("Host: pingap.io")).await;
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+    }
+
+    #[tokio::test]
+    async fn test_forward_proxy_authorization() {
+        let plugin = new_plugin(
+            r###"
+allow = ["127.0.0.1:*"]
+authorizations = ["dXNlcjpwYXNz"]
+"###,
+        );
+        let (result, _) =
+            handle(&plugin, "CONNECT 127.0.0.1:8080 HTTP/1.1", None).await;
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("the response should be returned");
+        };
+        assert_eq!(StatusCode::PROXY_AUTHENTICATION_REQUIRED, resp.status);
+        assert_eq!(
+            r#"Basic realm="pingap""#,
+            resp.headers.unwrap()[0].1.to_str().unwrap()
+        );
+
+        let (result, ctx) = handle(
+            &plugin,
+            "CONNECT 127.0.0.1:8080 HTTP/1.1",
+            Some("Proxy-Authorization: Basic dXNlcjpwYXNz"),
+        )
+        .await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!(true, ctx.upstream.tunnel_addrs.is_some());
+    }
+}
@@ -0,0 +1,567 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_duration_conf, get_hash_key, get_plugin_factory, get_str_conf,
+};
+use arc_swap::ArcSwapOption;
+use async_trait::async_trait;
+use ctor::ctor;
+use http::HeaderValue;
+use maxminddb::Reader;
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, Plugin, PluginStep, RequestPluginResult, get_client_ip, now_sec,
+};
+use pingora::proxy::Session;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, UNIX_EPOCH};
+use tracing::{debug, error, info};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+const HEADER_GEO_COUNTRY: &str = "X-Geo-Country";
+const HEADER_GEO_CITY: &str = "X-Geo-City";
+const HEADER_GEO_ASN: &str = "X-Geo-ASN";
+
+/// Variable names of the geo information, which can be used for
+/// upstream routing(e.g. `$geo_country`) and logging.
+pub const GEO_COUNTRY_VARIABLE: &str = "geo_country";
+pub const GEO_CITY_VARIABLE: &str = "geo_city";
+pub const GEO_ASN_VARIABLE: &str = "geo_asn";
+
+/// Default interval of checking whether the database files are updated
+const DEFAULT_RELOAD_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Default, Deserialize)]
+struct GeoNames {
+    names: Option<BTreeMap<String, String>>,
+}
+
+impl GeoNames {
+    fn english_name(self) -> Option<String> {
+        self.names?.remove("en")
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct GeoCountry {
+    iso_code: Option<String>,
+}
+
+/// The fields of GeoLite2 City/Country database
+#[derive(Debug, Default, Deserialize)]
+struct GeoCityRecord {
+    city: Option<GeoNames>,
+    country: Option<GeoCountry>,
+}
+
+/// The fields of GeoLite2 ASN database
+#[derive(Debug, Default, Deserialize)]
+struct GeoAsnRecord {
+    autonomous_system_number: Option<u32>,
+}
+
+/// The geo information of ip
+#[derive(Debug, Default, PartialEq)]
+struct GeoInfo {
+    country: Option<String>,
+    city: Option<String>,
+    asn: Option<u32>,
+}
+
+/// A maxmind database which is reloaded when the file is modified.
+struct GeoDatabase {
+    path: String,
+    reader: ArcSwapOption<Reader<Vec<u8>>>,
+    // modified time(ms) of the loaded file
+    modified: AtomicU64,
+}
+
+impl GeoDatabase {
+    /// Creates the database and loads the file, the load failure is logged
+    /// and the lookup returns none until the file is loaded.
+    fn new(path: &str) -> Self {
+        let db = Self {
+            path: path.to_string(),
+            reader: ArcSwapOption::empty(),
+            modified: AtomicU64::new(0),
+        };
+        db.reload();
+        db
+    }
+    /// Reloads the database if the file is modified,
+    /// the current database is kept if it fails.
+    fn reload(&self) -> bool {
+        let modified = match std::fs::metadata(&self.path)
+            .and_then(|meta| meta.modified())
+        {
+            Ok(modified) => modified
+                .duration_since(UNIX_EPOCH)
+                .map(|value| value.as_millis() as u64)
+                .unwrap_or_default(),
+            Err(e) => {
+                error!(
+                    category = PluginCategory::Geoip.to_string(),
+                    path = self.path,
+                    error = %e,
+                    "geoip database is unavailable"
+                );
+                return false;
+            },
+        };
+        if self.reader.load().is_some()
+            && self.modified.load(Ordering::Relaxed) == modified
+        {
+            return false;
+        }
+        match Reader::open_readfile(&self.path) {
+            Ok(reader) => {
+                info!(
+                    category = PluginCategory::Geoip.to_string(),
+                    path = self.path,
+                    database_type = reader.metadata.database_type,
+                    "load geoip database success"
+                );
+                self.reader.store(Some(Arc::new(reader)));
+                self.modified.store(modified, Ordering::Relaxed);
+                true
+            },
+            Err(e) => {
+                error!(
+                    category = PluginCategory::Geoip.to_string(),
+                    path = self.path,
+                    error = %e,
+                    "load geoip database fail"
+                );
+                false
+            },
+        }
+    }
+    fn lookup<T: DeserializeOwned>(&self, ip: IpAddr) -> Option<T> {
+        let reader = self.reader.load_full()?;
+        match reader.lookup::<T>(ip) {
+            Ok(value) => value,
+            Err(e) => {
+                debug!(
+                    category = PluginCategory::Geoip.to_string(),
+                    ip = ip.to_string(),
+                    error = %e,
+                    "lookup geoip fail"
+                );
+                None
+            },
+        }
+    }
+}
+
+/// Returns true if the ip is not routable on the internet,
+/// it's not in the geoip database.
+fn is_private_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+        },
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_private_ip(&IpAddr::V4(ip));
+            }
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local()
+        },
+    }
+}
+
+/// GeoIp plugin looks up the client ip in the MaxMind GeoLite2 databases,
+/// the country, city and asn are set to the request headers for upstream
+/// and to the variables of context for routing and logging.
+/// The database files are reloaded when they are modified.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// # path of GeoLite2 City or Country database
+/// path = "/opt/geoip/GeoLite2-City.mmdb"
+/// # path of GeoLite2 ASN database, optional
+/// asn_path = "/opt/geoip/GeoLite2-ASN.mmdb"
+/// # interval of checking whether the database files are modified
+/// reload_interval = "5m"
+/// ```
+pub struct GeoIp {
+    /// City or Country database
+    database: Arc<GeoDatabase>,
+    /// ASN database
+    asn_database: Option<Arc<GeoDatabase>>,
+    /// Interval(seconds) of checking the database files
+    reload_interval: u64,
+    /// The last time(seconds) of checking the database files
+    checked_at: AtomicU64,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for GeoIp {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let path = get_str_conf(value, "path");
+        if path.is_empty() {
+            return Err(Error::Invalid {
+                category: PluginCategory::Geoip.to_string(),
+                message: "geoip database path is empty".to_string(),
+            });
+        }
+        let asn_path = get_str_conf(value, "asn_path");
+        let asn_database = if asn_path.is_empty() {
+            None
+        } else {
+            Some(Arc::new(GeoDatabase::new(&asn_path)))
+        };
+        let reload_interval = get_duration_conf(value, "reload_interval")
+            .unwrap_or(DEFAULT_RELOAD_INTERVAL);
+
+        Ok(Self {
+            database: Arc::new(GeoDatabase::new(&path)),
+            asn_database,
+            reload_interval: reload_interval.as_secs(),
+            checked_at: AtomicU64::new(now_sec()),
+            hash_value,
+        })
+    }
+}
+
+impl GeoIp {
+    /// Creates a new geoip plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new geoip plugin");
+        Self::try_from(params)
+    }
+    /// Reloads the modified database files in background,
+    /// it's checked once per reload interval.
+    fn try_reload(&self) {
+        if self.reload_interval == 0 {
+            return;
+        }
+        let now = now_sec();
+        let checked_at = self.checked_at.load(Ordering::Relaxed);
+        if now < checked_at + self.reload_interval
+            || self
+                .checked_at
+                .compare_exchange(
+                    checked_at,
+                    now,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                )
+                .is_err()
+        {
+            return;
+        }
+        let mut databases = vec![self.database.clone()];
+        if let Some(asn_database) = &self.asn_database {
+            databases.push(asn_database.clone());
+        }
+        tokio::task::spawn_blocking(move || {
+            for db in databases {
+                db.reload();
+            }
+        });
+    }
+    /// Looks up the geo information of ip, none is returned for
+    /// the private ip.
+    fn lookup(&self, ip: IpAddr) -> Option<GeoInfo> {
+        if is_private_ip(&ip) {
+            return None;
+        }
+        let mut info = GeoInfo::default();
+        if let Some(record) = self.database.lookup::<GeoCityRecord>(ip) {
+            info.country = record.country.and_then(|country| country.iso_code);
+            info.city = record.city.and_then(GeoNames::english_name);
+        }
+        if let Some(record) = self
+            .asn_database
+            .as_ref()
+            .and_then(|db| db.lookup::<GeoAsnRecord>(ip))
+        {
+            info.asn = record.autonomous_system_number;
+        }
+        Some(info)
+    }
+}
+
+#[async_trait]
+impl Plugin for GeoIp {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Sets the geo information of client ip to the request headers and
+    /// variables. The geo headers from client are always removed, so they
+    /// can't be spoofed.
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        self.try_reload();
+        let req_header = session.req_header_mut();
+        for name in [HEADER_GEO_COUNTRY, HEADER_GEO_CITY, HEADER_GEO_ASN] {
+            req_header.remove_header(name);
+        }
+
+        let client_ip = ctx
+            .conn
+            .client_ip
+            .get_or_insert_with(|| get_client_ip(session));
+        let Some(info) = client_ip
+            .parse::<IpAddr>()
+            .ok()
+            .and_then(|ip| self.lookup(ip))
+        else {
+            return Ok(RequestPluginResult::Continue);
+        };
+
+        let req_header = session.req_header_mut();
+        if let Some(country) = &info.country {
+            req_header.insert_header(HEADER_GEO_COUNTRY, country)?;
+            ctx.add_variable(GEO_COUNTRY_VARIABLE, country);
+        }
+        if let Some(city) = &info.city {
+            // the city name may be non-ascii, e.g. Linköping
+            if let Ok(value) = HeaderValue::from_bytes(city.as_bytes()) {
+                req_header.insert_header(HEADER_GEO_CITY, value)?;
+            }
+            ctx.add_variable(GEO_CITY_VARIABLE, city);
+        }
+        if let Some(asn) = info.asn {
+            let asn = asn.to_string();
+            req_header.insert_header(HEADER_GEO_ASN, &asn)?;
+            ctx.add_variable(GEO_ASN_VARIABLE, &asn);
+        }
+
+        Ok(RequestPluginResult::Continue)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory()
+        .register("geoip", |params| Ok(Arc::new(GeoIp::new(params)?)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+
+    const TEST_DATABASE: &str =
+        concat!(env!("CARGO_MANIFEST_DIR"), "/assets/geoip-test.mmdb");
+
+    fn new_geoip() -> GeoIp {
+        GeoIp::try_from(
+            &toml::from_str::<PluginConf>(&format!(
+                r###"
+path = "{TEST_DATABASE}"
+asn_path = "{TEST_DATABASE}"
+"###
+            ))
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn get_header<'a>(session: &'a Session, name: &str) -> Option<&'a str> {
+        session
+            .req_header()
+            .headers
+            .get(name)
+            .map(|value| value.to_str().unwrap_or_default())
+    }
+
+    #[test]
+    fn test_geoip_params() {
+        let geoip = new_geoip();
+        assert_eq!(300, geoip.reload_interval);
+        assert_eq!(true, geoip.database.reader.load().is_some());
+        assert_eq!(true, geoip.asn_database.is_some());
+
+        let result = GeoIp::try_from(&PluginConf::default());
+        assert_eq!(
+            "Plugin geoip invalid, message: geoip database path is empty",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_is_private_ip() {
+        for ip in [
+            "10.0.0.1",
+            "192.168.1.1",
+            "172.16.0.1",
+            "127.0.0.1",
+            "169.254.1.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:192.168.1.1",
+        ] {
+            assert_eq!(true, is_private_ip(&ip.parse().unwrap()), "{ip}");
+        }
+        for ip in ["81.2.69.142", "1.1.1.1", "2001:4860::8888"] {
+            assert_eq!(false, is_private_ip(&ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[test]
+    fn test_lookup() {
+        let geoip = new_geoip();
+        assert_eq!(
+            Some(GeoInfo {
+                country: Some("GB".to_string()),
+                city: Some("London".to_string()),
+                asn: Some(20712),
+            }),
+            geoip.lookup("81.2.69.142".parse().unwrap())
+        );
+        assert_eq!(
+            Some(GeoInfo {
+                country: Some("SE".to_string()),
+                city: Some("Linköping".to_string()),
+                asn: Some(29518),
+            }),
+            geoip.lookup("89.160.20.128".parse().unwrap())
+        );
+        assert_eq!(
+            Some(GeoInfo {
+                country: Some("AU".to_string()),
+                city: None,
+                asn: Some(1221),
+            }),
+            geoip.lookup("1.128.0.1".parse().unwrap())
+        );
+        // not found
+        assert_eq!(
+            Some(GeoInfo::default()),
+            geoip.lookup("8.8.8.8".parse().unwrap())
+        );
+        assert_eq!(None, geoip.lookup("192.168.1.1".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_geoip() {
+        let geoip = new_geoip();
+
+        let headers =
+            ["X-Forwarded-For: 81.2.69.142", "X-Geo-Country: US"].join("\r\n");
+        let input_header = format!("GET /api HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let mut ctx = Ctx::default();
+        let result = geoip
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!(Some("GB"), get_header(&session, HEADER_GEO_COUNTRY));
+        assert_eq!(Some("London"), get_header(&session, HEADER_GEO_CITY));
+        assert_eq!(Some("20712"), get_header(&session, HEADER_GEO_ASN));
+        assert_eq!(Some("GB"), ctx.get_variable(GEO_COUNTRY_VARIABLE));
+        assert_eq!(Some("London"), ctx.get_variable(GEO_CITY_VARIABLE));
+        assert_eq!(Some("20712"), ctx.get_variable(GEO_ASN_VARIABLE));
+
+        let headers = ["X-Forwarded-For: 89.160.20.128"].join("\r\n");
+        let input_header = format!("GET /api HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let mut ctx = Ctx::default();
+        geoip
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(Some("SE"), get_header(&session, HEADER_GEO_COUNTRY));
+        assert_eq!(Some("29518"), get_header(&session, HEADER_GEO_ASN));
+        assert_eq!(
+            "Linköping".as_bytes(),
+            session
+                .req_header()
+                .headers
+                .get(HEADER_GEO_CITY)
+                .unwrap()
+                .as_bytes()
+        );
+
+        // the spoofed headers are removed for private ip
+        let headers = [
+            "X-Forwarded-For: 192.168.1.1",
+            "X-Geo-Country: US",
+            "X-Geo-ASN: 1",
+        ]
+        .join("\r\n");
+        let input_header = format!("GET /api HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let mut ctx = Ctx::default();
+        let result = geoip
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!(None, get_header(&session, HEADER_GEO_COUNTRY));
+        assert_eq!(None, get_header(&session, HEADER_GEO_ASN));
+        assert_eq!(None, ctx.get_variable(GEO_COUNTRY_VARIABLE));
+    }
+
+    #[tokio::test]
+    async fn test_geoip_load_fail() {
+        let geoip = GeoIp::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+path = "/tmp/not-exists-geoip.mmdb"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(true, geoip.database.reader.load().is_none());
+
+        let headers = ["X-Forwarded-For: 81.2.69.142"].join("\r\n");
+        let input_header = format!("GET /api HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let mut ctx = Ctx::default();
+        let result = geoip
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!(None, get_header(&session, HEADER_GEO_COUNTRY));
+        assert_eq!(None, ctx.get_variable(GEO_COUNTRY_VARIABLE));
+    }
+}
@@ -0,0 +1,293 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Error, get_hash_key, get_plugin_factory, get_str_slice_conf};
+use async_trait::async_trait;
+use ctor::ctor;
+use http::StatusCode;
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{Ctx, Plugin, ResponsePluginResult};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::sync::Arc;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+const HTTP_HEADER_GRPC_STATUS: &str = "grpc-status";
+
+/// The names of grpc status codes, the index is the code
+const GRPC_STATUS_NAMES: [&str; 17] = [
+    "OK",
+    "CANCELLED",
+    "UNKNOWN",
+    "INVALID_ARGUMENT",
+    "DEADLINE_EXCEEDED",
+    "NOT_FOUND",
+    "ALREADY_EXISTS",
+    "PERMISSION_DENIED",
+    "RESOURCE_EXHAUSTED",
+    "FAILED_PRECONDITION",
+    "ABORTED",
+    "OUT_OF_RANGE",
+    "UNIMPLEMENTED",
+    "INTERNAL",
+    "UNAVAILABLE",
+    "DATA_LOSS",
+    "UNAUTHENTICATED",
+];
+
+/// The default http status of grpc status codes, the index is the code
+const DEFAULT_HTTP_STATUSES: [u16; 17] = [
+    200, 499, 500, 400, 504, 404, 409, 403, 429, 400, 409, 400, 501, 500, 503,
+    500, 401,
+];
+
+/// Parses the grpc status of code or name, e.g. `5` or `NOT_FOUND`.
+fn parse_grpc_status(value: &str) -> Option<usize> {
+    let value = value.trim();
+    if let Ok(code) = value.parse::<usize>() {
+        return (code < GRPC_STATUS_NAMES.len()).then_some(code);
+    }
+    GRPC_STATUS_NAMES
+        .iter()
+        .position(|name| name.eq_ignore_ascii_case(value))
+}
+
+/// GrpcStatus plugin maps the `grpc-status` of the upstream response to the
+/// http status for REST clients, e.g. `NOT_FOUND` to `404` and
+/// `PERMISSION_DENIED` to `403`, instead of always `200`. The defaults
+/// follow the mapping of grpc-gateway, and they can be overridden.
+///
+/// Only the trailers-only response(the error response without message) has
+/// `grpc-status` in the headers, the status in the trailers can't be mapped
+/// because the response header has been sent.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// # grpc status(code or name):http status
+/// mapping = ["NOT_FOUND:410", "14:502"]
+/// ```
+pub struct GrpcStatus {
+    /// The http status of grpc status codes, the index is the code
+    statuses: Vec<StatusCode>,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for GrpcStatus {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::GrpcStatus.to_string(),
+            message,
+        };
+        let mut statuses = DEFAULT_HTTP_STATUSES
+            .iter()
+            .filter_map(|status| StatusCode::from_u16(*status).ok())
+            .collect::<Vec<_>>();
+        for item in get_str_slice_conf(value, "mapping") {
+            let (grpc_status, http_status) =
+                item.split_once(':').unwrap_or((item.as_str(), ""));
+            let Some(code) = parse_grpc_status(grpc_status) else {
+                return Err(new_invalid_error(format!(
+                    "grpc status({grpc_status}) is invalid"
+                )));
+            };
+            let http_status = http_status
+                .trim()
+                .parse::<u16>()
+                .ok()
+                .and_then(|status| StatusCode::from_u16(status).ok())
+                .ok_or_else(|| {
+                    new_invalid_error(format!(
+                        "http status of mapping({item}) is invalid"
+                    ))
+                })?;
+            statuses[code] = http_status;
+        }
+
+        Ok(Self {
+            statuses,
+            hash_value,
+        })
+    }
+}
+
+impl GrpcStatus {
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new grpc status plugin");
+        Self::try_from(params)
+    }
+}
+
+#[async_trait]
+impl Plugin for GrpcStatus {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Maps the grpc status of the trailers-only response to http status
+    async fn handle_response(
+        &self,
+        _session: &mut Session,
+        _ctx: &mut Ctx,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<ResponsePluginResult> {
+        if upstream_response.status != StatusCode::OK {
+            return Ok(ResponsePluginResult::Unchanged);
+        }
+        let Some(status) = upstream_response
+            .headers
+            .get(HTTP_HEADER_GRPC_STATUS)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<usize>().ok())
+        else {
+            return Ok(ResponsePluginResult::Unchanged);
+        };
+        // the unknown code is treated as UNKNOWN
+        let http_status = self
+            .statuses
+            .get(status)
+            .or_else(|| self.statuses.get(2))
+            .copied()
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        if http_status == upstream_response.status {
+            return Ok(ResponsePluginResult::Unchanged);
+        }
+        let _ = upstream_response.set_status(http_status);
+        Ok(ResponsePluginResult::Modified)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("grpc_status", |params| {
+        Ok(Arc::new(GrpcStatus::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingap_config::PluginConf;
+    use pingap_core::Ctx;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    async fn handle(
+        plugin: &GrpcStatus,
+        status: u16,
+        grpc_status: Option<&str>,
+    ) -> (ResponsePluginResult, u16) {
+        let input_header = "POST /helloworld.Greeter/SayHello HTTP/1.1\r\nHost: pingap.io\r\nContent-Type: application/grpc-web+proto\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let mut upstream_response =
+            ResponseHeader::build(status, None).unwrap();
+        if let Some(grpc_status) = grpc_status {
+            upstream_response
+                .insert_header(HTTP_HEADER_GRPC_STATUS, grpc_status)
+                .unwrap();
+        }
+        let result = plugin
+            .handle_response(
+                &mut session,
+                &mut Ctx::default(),
+                &mut upstream_response,
+            )
+            .await
+            .unwrap();
+        (result, upstream_response.status.as_u16())
+    }
+
+    #[test]
+    fn test_grpc_status_params() {
+        assert_eq!(Some(5), parse_grpc_status("not_found"));
+        assert_eq!(Some(16), parse_grpc_status(" 16 "));
+        assert_eq!(None, parse_grpc_status("17"));
+        assert_eq!(None, parse_grpc_status("MISSING"));
+
+        let plugin = GrpcStatus::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+mapping = ["NOT_FOUND:410", "14:502"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(StatusCode::GONE, plugin.statuses[5]);
+        assert_eq!(StatusCode::BAD_GATEWAY, plugin.statuses[14]);
+        assert_eq!(StatusCode::FORBIDDEN, plugin.statuses[7]);
+
+        for (mapping, message) in [
+            ("MISSING:404", "grpc status(MISSING) is invalid"),
+            (
+                "NOT_FOUND:abc",
+                "http status of mapping(NOT_FOUND:abc) is invalid",
+            ),
+            ("NOT_FOUND", "http status of mapping(NOT_FOUND) is invalid"),
+        ] {
+            let result = GrpcStatus::new(
+                &toml::from_str::<PluginConf>(&format!(
+                    r#"mapping = ["{mapping}"]"#
+                ))
+                .unwrap(),
+            );
+            assert_eq!(
+                format!("Plugin grpc_status invalid, message: {message}"),
+                result.err().unwrap().to_string()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_grpc_status() {
+        let plugin = GrpcStatus::new(&PluginConf::default()).unwrap();
+
+        for (grpc_status, status) in [
+            ("5", 404),
+            ("7", 403),
+            ("16", 401),
+            ("3", 400),
+            ("8", 429),
+            ("12", 501),
+            ("14", 503),
+            ("4", 504),
+            ("1", 499),
+            ("99", 500),
+        ] {
+            let (result, value) = handle(&plugin, 200, Some(grpc_status)).await;
+            assert_eq!(ResponsePluginResult::Modified, result);
+            assert_eq!(status, value, "grpc status: {grpc_status}");
+        }
+
+        // the ok status and the response without grpc status are unchanged
+        let (result, value) = handle(&plugin, 200, Some("0")).await;
+        assert_eq!(ResponsePluginResult::Unchanged, result);
+        assert_eq!(200, value);
+        let (result, value) = handle(&plugin, 200, None).await;
+        assert_eq!(ResponsePluginResult::Unchanged, result);
+        assert_eq!(200, value);
+        // the error response of upstream is unchanged
+        let (result, value) = handle(&plugin, 502, Some("14")).await;
+        assert_eq!(ResponsePluginResult::Unchanged, result);
+        assert_eq!(502, value);
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Error, get_duration_conf, get_hash_key, get_plugin_factory};
+use async_trait::async_trait;
+use ctor::ctor;
+use http::{HeaderValue, header};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{Ctx, Plugin, ResponsePluginResult};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The minimum max-age required by the hsts preload list
+const MIN_PRELOAD_MAX_AGE: Duration = Duration::from_secs(365 * 24 * 3600);
+
+/// HstsPreload plugin sets the `Strict-Transport-Security` header which
+/// meets the requirements of hsts preload list, the header is only set
+/// for the tls connection as browsers ignore it over http.
+///
+/// The config validation checks that the same host isn't served over
+/// http without redirecting to https, which is rejected by the preload list.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// max_age = "730d"  # at least 1 year, default 2 years
+/// ```
+pub struct HstsPreload {
+    /// The value of Strict-Transport-Security header
+    value: HeaderValue,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for HstsPreload {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let max_age = get_duration_conf(value, "max_age")
+            .unwrap_or(MIN_PRELOAD_MAX_AGE * 2);
+        if max_age < MIN_PRELOAD_MAX_AGE {
+            return Err(Error::Invalid {
+                category: PluginCategory::HstsPreload.to_string(),
+                message: "max_age should be at least 1 year for preload"
+                    .to_string(),
+            });
+        }
+        let value = HeaderValue::from_str(&format!(
+            "max-age={}; includeSubDomains; preload",
+            max_age.as_secs()
+        ))
+        .map_err(|e| Error::Invalid {
+            category: PluginCategory::HstsPreload.to_string(),
+            message: e.to_string(),
+        })?;
+
+        Ok(Self { value, hash_value })
+    }
+}
+
+impl HstsPreload {
+    /// Creates a new hsts preload plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new hsts preload plugin");
+        Self::try_from(params)
+    }
+}
+
+#[async_trait]
+impl Plugin for HstsPreload {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Sets the `Strict-Transport-Security` header of the tls response,
+    /// the header of upstream is replaced to meet the preload requirements.
+    #[inline]
+    async fn handle_response(
+        &self,
+        _session: &mut Session,
+        ctx: &mut Ctx,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<ResponsePluginResult> {
+        if ctx.conn.tls_version.is_none() {
+            return Ok(ResponsePluginResult::Unchanged);
+        }
+        upstream_response.insert_header(
+            header::STRICT_TRANSPORT_SECURITY,
+            self.value.clone(),
+        )?;
+        Ok(ResponsePluginResult::Modified)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("hsts_preload", |params| {
+        Ok(Arc::new(HstsPreload::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingap_config::PluginConf;
+    use pingap_core::Ctx;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    #[test]
+    fn test_hsts_preload_params() {
+        let plugin = HstsPreload::new(&PluginConf::default()).unwrap();
+        assert_eq!(
+            "max-age=63072000; includeSubDomains; preload",
+            plugin.value.to_str().unwrap()
+        );
+
+        let plugin = HstsPreload::new(
+            &toml::from_str::<PluginConf>(r#"max_age = "365d""#).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            "max-age=31536000; includeSubDomains; preload",
+            plugin.value.to_str().unwrap()
+        );
+
+        let result = HstsPreload::try_from(
+            &toml::from_str::<PluginConf>(r#"max_age = "30d""#).unwrap(),
+        );
+        assert_eq!(
+            "Plugin hsts_preload invalid, message: max_age should be at least 1 year for preload",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_hsts_preload() {
+        let plugin = HstsPreload::new(&PluginConf::default()).unwrap();
+        let mock_io = Builder::new()
+            .read(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n")
+            .build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+
+        // the http response is unchanged
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        let result = plugin
+            .handle_response(
+                &mut session,
+                &mut Ctx::default(),
+                &mut upstream_response,
+            )
+            .await
+            .unwrap();
+        assert_eq!(ResponsePluginResult::Unchanged, result);
+        assert_eq!(
+            true,
+            upstream_response
+                .headers
+                .get("strict-transport-security")
+                .is_none()
+        );
+
+        // the header of upstream is replaced
+        let mut ctx = Ctx::default();
+        ctx.conn.tls_version = Some("TLSv1.3".to_string());
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        upstream_response
+            .insert_header("Strict-Transport-Security", "max-age=3600")
+            .unwrap();
+        let result = plugin
+            .handle_response(&mut session, &mut ctx, &mut upstream_response)
+            .await
+            .unwrap();
+        assert_eq!(ResponsePluginResult::Modified, result);
+        assert_eq!(
+            "max-age=63072000; includeSubDomains; preload",
+            upstream_response
+                .headers
+                .get("strict-transport-security")
+                .unwrap()
+                .to_str()
+                .unwrap()
+        );
+    }
+}
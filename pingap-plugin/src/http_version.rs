@@ -0,0 +1,514 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Error, get_hash_key, get_plugin_factory, get_str_conf};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use bytesize::ByteSize;
+use ctor::ctor;
+use http::{HeaderMap, HeaderValue, Method, StatusCode, Version, header};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, HTTP_HEADER_TRANSFER_CHUNKED, HttpResponse, Plugin, PluginStep,
+    RequestPluginResult, ResponsePluginResult, write_continue_response,
+};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The chunked body read by the plugin is kept in the retry buffer of
+/// session, the buffer is limited to 64KB by pingora.
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// Returns true if the comma separated values of header contain the token.
+fn has_token(
+    headers: &HeaderMap,
+    name: header::HeaderName,
+    token: &str,
+) -> bool {
+    headers.get_all(name).iter().any(|value| {
+        value.to_str().is_ok_and(|value| {
+            value
+                .split(',')
+                .any(|item| item.trim().eq_ignore_ascii_case(token))
+        })
+    })
+}
+
+/// Returns true if the response doesn't have body, so its length is known.
+fn is_bodyless(method: &Method, status: StatusCode) -> bool {
+    method == Method::HEAD
+        || status.is_informational()
+        || status == StatusCode::NO_CONTENT
+        || status == StatusCode::NOT_MODIFIED
+}
+
+/// HttpVersion plugin translates the http version between the legacy
+/// HTTP/1.0 clients and HTTP/1.1 upstreams, or the HTTP/1.1 clients and
+/// HTTP/1.0 upstreams.
+///
+/// The request of HTTP/1.0 client is sent as HTTP/1.1, and the chunked
+/// response is sent to the client until the connection is closed, because
+/// HTTP/1.0 doesn't support the chunked encoding. The connection of client
+/// is kept alive only if it asks for and the length of response is known.
+///
+/// The chunked request body of HTTP/1.1 client is converted to the body of
+/// content length for the HTTP/1.0 upstream, and the response without length
+/// is sent to the client as chunked, so the connection can be kept alive.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// # the http version of upstream: "1.1" or "1.0"
+/// upstream_version = "1.1"
+/// # max size of the chunked request body converted for HTTP/1.0 upstream
+/// max_body_size = "64KB"
+/// ```
+pub struct HttpVersion {
+    /// The http version of request sent to upstream
+    upstream_version: Version,
+    /// Maximum size of the chunked request body to read
+    max_body_size: usize,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for HttpVersion {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::HttpVersion.to_string(),
+            message,
+        };
+        let upstream_version =
+            match get_str_conf(value, "upstream_version").as_str() {
+                "" | "1.1" => Version::HTTP_11,
+                "1.0" => Version::HTTP_10,
+                version => {
+                    return Err(new_invalid_error(format!(
+                        "upstream version({version}) should be 1.1 or 1.0"
+                    )));
+                },
+            };
+
+        let max_body_size = get_str_conf(value, "max_body_size");
+        let max_body_size = if max_body_size.is_empty() {
+            MAX_BODY_SIZE
+        } else {
+            ByteSize::from_str(&max_body_size)
+                .map_err(new_invalid_error)?
+                .as_u64() as usize
+        };
+        if max_body_size > MAX_BODY_SIZE {
+            return Err(new_invalid_error(format!(
+                "max body size should be less than or equal to {}",
+                ByteSize(MAX_BODY_SIZE as u64)
+            )));
+        }
+
+        Ok(Self {
+            upstream_version,
+            max_body_size,
+            hash_value,
+        })
+    }
+}
+
+impl HttpVersion {
+    /// Creates a new http version plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new http version plugin");
+        Self::try_from(params)
+    }
+    /// Reads the chunked request body, and sets the content length of
+    /// request for HTTP/1.0 upstream.
+    async fn convert_chunked_body(
+        &self,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<Option<HttpResponse>> {
+        write_continue_response(session, ctx).await?;
+        session.enable_retry_buffering();
+        let mut body = BytesMut::new();
+        while let Some(chunk) = session.read_request_body().await? {
+            if body.len() + chunk.len() > self.max_body_size {
+                return Ok(Some(HttpResponse {
+                    status: StatusCode::PAYLOAD_TOO_LARGE,
+                    body: Bytes::from(format!(
+                        "Request body is too large, max:{}",
+                        ByteSize(self.max_body_size as u64)
+                    )),
+                    ..Default::default()
+                }));
+            }
+            body.extend_from_slice(&chunk);
+        }
+        let req_header = session.req_header_mut();
+        req_header.remove_header(&header::TRANSFER_ENCODING);
+        req_header.insert_header(header::CONTENT_LENGTH, body.len())?;
+        Ok(None)
+    }
+}
+
+#[async_trait]
+impl Plugin for HttpVersion {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Sets the http version of upstream request if it's different from
+    /// the client, the chunked request body is converted for HTTP/1.0
+    /// upstream.
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let version = session.req_header().version;
+        if (version != Version::HTTP_10 && version != Version::HTTP_11)
+            || version == self.upstream_version
+        {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        ctx.upstream.version = Some(self.upstream_version);
+        if self.upstream_version == Version::HTTP_10
+            && has_token(
+                &session.req_header().headers,
+                header::TRANSFER_ENCODING,
+                "chunked",
+            )
+        {
+            if let Some(resp) = self.convert_chunked_body(session, ctx).await? {
+                return Ok(RequestPluginResult::Respond(resp));
+            }
+        }
+        Ok(RequestPluginResult::Continue)
+    }
+
+    /// Translates the version and framing of response to the version of
+    /// client, the connection headers of upstream are hop-by-hop, so they
+    /// are replaced.
+    async fn handle_response(
+        &self,
+        session: &mut Session,
+        _ctx: &mut Ctx,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<ResponsePluginResult> {
+        let req_header = session.req_header();
+        let version = req_header.version;
+        if (version != Version::HTTP_10 && version != Version::HTTP_11)
+            || version == upstream_response.version
+        {
+            return Ok(ResponsePluginResult::Unchanged);
+        }
+        let bodyless =
+            is_bodyless(&req_header.method, upstream_response.status);
+        let chunked = has_token(
+            &upstream_response.headers,
+            header::TRANSFER_ENCODING,
+            "chunked",
+        );
+        let has_length = upstream_response
+            .headers
+            .contains_key(header::CONTENT_LENGTH);
+        let client_keepalive =
+            has_token(&req_header.headers, header::CONNECTION, "keep-alive");
+
+        upstream_response.set_version(version);
+        upstream_response.remove_header(&header::CONNECTION);
+        upstream_response.remove_header("keep-alive");
+        if version == Version::HTTP_11 {
+            // the response of HTTP/1.0 upstream without length is ended by
+            // closing the connection, it's sent as chunked to keep alive
+            if !bodyless && !has_length && !chunked {
+                let chunked = HTTP_HEADER_TRANSFER_CHUNKED.clone();
+                upstream_response.insert_header(chunked.0, chunked.1)?;
+            }
+            return Ok(ResponsePluginResult::Modified);
+        }
+
+        // HTTP/1.0 client doesn't support the chunked encoding,
+        // the content length is ignored if the response is chunked
+        if chunked {
+            upstream_response.remove_header(&header::TRANSFER_ENCODING);
+            upstream_response.remove_header(&header::CONTENT_LENGTH);
+        }
+        if client_keepalive && (bodyless || (has_length && !chunked)) {
+            upstream_response.insert_header(
+                header::CONNECTION,
+                HeaderValue::from_static("keep-alive"),
+            )?;
+        } else {
+            // the body is ended by closing the connection
+            upstream_response.insert_header(
+                header::CONNECTION,
+                HeaderValue::from_static("close"),
+            )?;
+            session.set_keepalive(None);
+        }
+        Ok(ResponsePluginResult::Modified)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("http_version", |params| {
+        Ok(Arc::new(HttpVersion::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pretty_assertions::assert_eq;
+
+    fn new_http_version(params: &str) -> HttpVersion {
+        HttpVersion::try_from(&toml::from_str::<PluginConf>(params).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_http_version_params() {
+        let plugin = new_http_version("");
+        assert_eq!(Version::HTTP_11, plugin.upstream_version);
+        assert_eq!(MAX_BODY_SIZE, plugin.max_body_size);
+
+        let plugin = new_http_version(
+            r###"
+upstream_version = "1.0"
+max_body_size = "1KB"
+"###,
+        );
+        assert_eq!(Version::HTTP_10, plugin.upstream_version);
+        assert_eq!(1000, plugin.max_body_size);
+
+        let result = HttpVersion::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+upstream_version = "2"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin http_version invalid, message: upstream version(2) should be 1.1 or 1.0",
+            result.err().unwrap().to_string()
+        );
+
+        let result = HttpVersion::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+max_body_size = "1MB"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin http_version invalid, message: max body size should be less than or equal to 64.0 KiB",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_http10_client_with_chunked_upstream() {
+        let plugin = new_http_version("");
+
+        // the request is sent as HTTP/1.1
+        let mut session = test_util::new_session(
+            "GET /users HTTP/1.0\r\nHost: pingap.io\r\nConnection: keep-alive\r\n\r\n",
+        )
+        .await;
+        let mut ctx = Ctx::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!(Some(Version::HTTP_11), ctx.upstream.version);
+
+        // the chunked response is sent until the connection is closed
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        upstream_response
+            .insert_header("Transfer-Encoding", "chunked")
+            .unwrap();
+        upstream_response
+            .insert_header("Connection", "keep-alive")
+            .unwrap();
+        let result = plugin
+            .handle_response(&mut session, &mut ctx, &mut upstream_response)
+            .await
+            .unwrap();
+        assert_eq!(ResponsePluginResult::Modified, result);
+        assert_eq!(Version::HTTP_10, upstream_response.version);
+        assert_eq!(
+            true,
+            upstream_response.headers.get("Transfer-Encoding").is_none()
+        );
+        assert_eq!(
+            "close",
+            upstream_response.headers.get("Connection").unwrap()
+        );
+
+        // the response with content length keeps the connection alive
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        upstream_response
+            .insert_header("Content-Length", "5")
+            .unwrap();
+        let result = plugin
+            .handle_response(&mut session, &mut ctx, &mut upstream_response)
+            .await
+            .unwrap();
+        assert_eq!(ResponsePluginResult::Modified, result);
+        assert_eq!(Version::HTTP_10, upstream_response.version);
+        assert_eq!(
+            "5",
+            upstream_response.headers.get("Content-Length").unwrap()
+        );
+        assert_eq!(
+            "keep-alive",
+            upstream_response.headers.get("Connection").unwrap()
+        );
+
+        // the client doesn't ask for keep-alive
+        let mut session = test_util::new_session(
+            "GET /users HTTP/1.0\r\nHost: pingap.io\r\n\r\n",
+        )
+        .await;
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        upstream_response
+            .insert_header("Content-Length", "5")
+            .unwrap();
+        plugin
+            .handle_response(&mut session, &mut ctx, &mut upstream_response)
+            .await
+            .unwrap();
+        assert_eq!(
+            "close",
+            upstream_response.headers.get("Connection").unwrap()
+        );
+
+        // HTTP/1.1 client isn't translated
+        let mut session = test_util::new_session(
+            "GET /users HTTP/1.1\r\nHost: pingap.io\r\n\r\n",
+        )
+        .await;
+        let mut ctx = Ctx::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+        assert_eq!(None, ctx.upstream.version);
+    }
+
+    #[tokio::test]
+    async fn test_http11_client_with_http10_upstream() {
+        let plugin = new_http_version(
+            r###"
+upstream_version = "1.0"
+"###,
+        );
+
+        // the chunked request body is converted to content length
+        let mut session = test_util::new_session(
+            "POST /users HTTP/1.1\r\nHost: pingap.io\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+        )
+        .await;
+        let mut ctx = Ctx::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!(Some(Version::HTTP_10), ctx.upstream.version);
+        let headers = &session.req_header().headers;
+        assert_eq!(true, headers.get("Transfer-Encoding").is_none());
+        assert_eq!("11", headers.get("Content-Length").unwrap());
+
+        // the response without length is sent as chunked
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        upstream_response.set_version(Version::HTTP_10);
+        upstream_response
+            .insert_header("Connection", "close")
+            .unwrap();
+        let result = plugin
+            .handle_response(&mut session, &mut ctx, &mut upstream_response)
+            .await
+            .unwrap();
+        assert_eq!(ResponsePluginResult::Modified, result);
+        assert_eq!(Version::HTTP_11, upstream_response.version);
+        assert_eq!(
+            "chunked",
+            upstream_response.headers.get("Transfer-Encoding").unwrap()
+        );
+        assert_eq!(true, upstream_response.headers.get("Connection").is_none());
+
+        // the response with content length isn't chunked
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        upstream_response.set_version(Version::HTTP_10);
+        upstream_response
+            .insert_header("Content-Length", "5")
+            .unwrap();
+        plugin
+            .handle_response(&mut session, &mut ctx, &mut upstream_response)
+            .await
+            .unwrap();
+        assert_eq!(Version::HTTP_11, upstream_response.version);
+        assert_eq!(
+            true,
+            upstream_response.headers.get("Transfer-Encoding").is_none()
+        );
+
+        // the chunked request body is too large
+        let plugin = new_http_version(
+            r###"
+upstream_version = "1.0"
+max_body_size = "8B"
+"###,
+        );
+        let mut session = test_util::new_session(
+            "POST /users HTTP/1.1\r\nHost: pingap.io\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n",
+        )
+        .await;
+        let mut ctx = Ctx::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("request should be rejected");
+        };
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, resp.status);
+    }
+
+    #[test]
+    fn test_is_bodyless() {
+        assert_eq!(true, is_bodyless(&Method::HEAD, StatusCode::OK));
+        assert_eq!(true, is_bodyless(&Method::GET, StatusCode::NO_CONTENT));
+        assert_eq!(true, is_bodyless(&Method::GET, StatusCode::NOT_MODIFIED));
+        assert_eq!(false, is_bodyless(&Method::GET, StatusCode::OK));
+    }
+}
@@ -0,0 +1,829 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::redis_store::RedisStore;
+use super::{
+    Error, get_duration_conf, get_hash_key, get_plugin_factory, get_str_conf,
+    get_str_slice_conf,
+};
+use ahash::AHashMap;
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use bytesize::ByteSize;
+use ctor::ctor;
+use http::{HeaderName, HeaderValue, Method, StatusCode, header};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, HttpResponse, ModifyResponseBody, Plugin, PluginStep,
+    RequestPluginResult, ResponseBodyPluginResult, ResponsePluginResult,
+};
+use pingap_util::{base64_decode, base64_encode};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+const PLUGIN_ID: &str = "_idempotency_";
+
+const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+
+const HTTP_HEADER_IDEMPOTENT_REPLAYED: &str = "idempotent-replayed";
+
+// sweep the expired entries of memory store when it's larger than the size
+const MEMORY_SWEEP_SIZE: usize = 1024;
+
+/// The state of idempotency key
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+enum IdempotencyEntry {
+    /// The first request is being processed
+    Pending,
+    /// The response of the first request, the body is base64 encoded
+    Done {
+        status: u16,
+        headers: Vec<(String, String)>,
+        body: String,
+    },
+}
+
+impl IdempotencyEntry {
+    fn new_done(header: &ResponseHeader, body: &[u8]) -> Self {
+        let headers = header
+            .headers
+            .iter()
+            .filter(|(name, _)| {
+                ![
+                    header::CONNECTION,
+                    header::TRANSFER_ENCODING,
+                    header::CONTENT_LENGTH,
+                    header::UPGRADE,
+                    header::SET_COOKIE,
+                ]
+                .contains(name)
+            })
+            .map(|(name, value)| {
+                (
+                    name.to_string(),
+                    String::from_utf8_lossy(value.as_bytes()).to_string(),
+                )
+            })
+            .collect();
+        Self::Done {
+            status: header.status.as_u16(),
+            headers,
+            body: base64_encode(body),
+        }
+    }
+    /// Converts the cached response to http response,
+    /// it's none if the entry is pending.
+    fn to_response(&self) -> Option<HttpResponse> {
+        let Self::Done {
+            status,
+            headers,
+            body,
+        } = self
+        else {
+            return None;
+        };
+        let mut resp_headers: Vec<(HeaderName, HeaderValue)> = headers
+            .iter()
+            .filter_map(|(name, value)| {
+                Some((
+                    HeaderName::from_str(name).ok()?,
+                    HeaderValue::from_str(value).ok()?,
+                ))
+            })
+            .collect();
+        resp_headers.push((
+            HeaderName::from_static(HTTP_HEADER_IDEMPOTENT_REPLAYED),
+            HeaderValue::from_static("true"),
+        ));
+        Some(HttpResponse {
+            status: StatusCode::from_u16(*status).ok()?,
+            body: Bytes::from(base64_decode(body).ok()?),
+            headers: Some(resp_headers),
+            ..Default::default()
+        })
+    }
+}
+
+type MemoryEntries = Mutex<AHashMap<String, (Instant, IdempotencyEntry)>>;
+
+/// Storage backend of idempotency keys, the redis store is shared
+/// by all pingap instances
+enum IdempotencyStore {
+    Memory(MemoryEntries),
+    Redis(RedisStore),
+}
+
+impl IdempotencyStore {
+    fn set_memory(
+        entries: &MemoryEntries,
+        key: &str,
+        entry: IdempotencyEntry,
+        ttl: Duration,
+        only_absent: bool,
+    ) -> bool {
+        let Ok(mut entries) = entries.lock() else {
+            return false;
+        };
+        let now = Instant::now();
+        if entries.len() >= MEMORY_SWEEP_SIZE {
+            entries.retain(|_, (expired_at, _)| *expired_at > now);
+        }
+        if only_absent
+            && entries
+                .get(key)
+                .is_some_and(|(expired_at, _)| *expired_at > now)
+        {
+            return false;
+        }
+        entries.insert(key.to_string(), (now + ttl, entry));
+        true
+    }
+    async fn get(&self, key: &str) -> Option<IdempotencyEntry> {
+        match self {
+            Self::Memory(entries) => {
+                let entries = entries.lock().ok()?;
+                entries
+                    .get(key)
+                    .filter(|(expired_at, _)| *expired_at > Instant::now())
+                    .map(|(_, entry)| entry.clone())
+            },
+            Self::Redis(store) => {
+                let value: Option<String> =
+                    store.query(redis::cmd("GET").arg(key).clone()).await?;
+                serde_json::from_str(&value?).ok()
+            },
+        }
+    }
+    /// Reserves the key for the first request, returns false if
+    /// the key exists. The request is allowed if redis is unavailable.
+    async fn reserve(&self, key: &str, ttl: Duration) -> bool {
+        match self {
+            Self::Memory(entries) => Self::set_memory(
+                entries,
+                key,
+                IdempotencyEntry::Pending,
+                ttl,
+                true,
+            ),
+            Self::Redis(store) => {
+                let Ok(value) =
+                    serde_json::to_string(&IdempotencyEntry::Pending)
+                else {
+                    return true;
+                };
+                let cmd = redis::cmd("SET")
+                    .arg(key)
+                    .arg(value)
+                    .arg("NX")
+                    .arg("PX")
+                    .arg(ttl.as_millis() as u64)
+                    .clone();
+                store
+                    .query::<Option<String>>(cmd)
+                    .await
+                    .is_none_or(|result| result.is_some())
+            },
+        }
+    }
+    async fn save(&self, key: &str, entry: IdempotencyEntry, ttl: Duration) {
+        match self {
+            Self::Memory(entries) => {
+                Self::set_memory(entries, key, entry, ttl, false);
+            },
+            Self::Redis(store) => {
+                let Ok(value) = serde_json::to_string(&entry) else {
+                    return;
+                };
+                let cmd = redis::cmd("SET")
+                    .arg(key)
+                    .arg(value)
+                    .arg("PX")
+                    .arg(ttl.as_millis() as u64)
+                    .clone();
+                let _ = store.query::<Option<String>>(cmd).await;
+            },
+        }
+    }
+    async fn remove(&self, key: &str) {
+        match self {
+            Self::Memory(entries) => {
+                if let Ok(mut entries) = entries.lock() {
+                    entries.remove(key);
+                }
+            },
+            Self::Redis(store) => {
+                let _ = store
+                    .query::<i64>(redis::cmd("DEL").arg(key).clone())
+                    .await;
+            },
+        }
+    }
+    /// Saves the entry or removes the key(entry is none),
+    /// it's done immediately for memory store.
+    fn persist(
+        self: &Arc<Self>,
+        key: String,
+        entry: Option<IdempotencyEntry>,
+        ttl: Duration,
+    ) {
+        match self.as_ref() {
+            Self::Memory(entries) => {
+                if let Some(entry) = entry {
+                    Self::set_memory(entries, &key, entry, ttl, false);
+                } else if let Ok(mut entries) = entries.lock() {
+                    entries.remove(&key);
+                }
+            },
+            Self::Redis(_) => {
+                let Ok(handle) = tokio::runtime::Handle::try_current() else {
+                    return;
+                };
+                let store = self.clone();
+                handle.spawn(async move {
+                    if let Some(entry) = entry {
+                        store.save(&key, entry, ttl).await;
+                    } else {
+                        store.remove(&key).await;
+                    }
+                });
+            },
+        }
+    }
+}
+
+/// The response of the first request being recorded
+struct Recording {
+    header: Mutex<Option<ResponseHeader>>,
+}
+
+type Recordings = Arc<Mutex<AHashMap<String, Arc<Recording>>>>;
+
+/// Collects the response body of the first request, the response is saved
+/// at the end of stream. If the request is dropped before the end or the
+/// response can't be cached, the key is released so the client can retry.
+struct IdempotencyRecorder {
+    key: String,
+    recording: Arc<Recording>,
+    recordings: Recordings,
+    store: Arc<IdempotencyStore>,
+    ttl: Duration,
+    body: BytesMut,
+    max_body_size: usize,
+    too_large: bool,
+    done: bool,
+}
+
+impl IdempotencyRecorder {
+    fn finish(&mut self, completed: bool) {
+        if self.done {
+            return;
+        }
+        self.done = true;
+        if let Ok(mut recordings) = self.recordings.lock() {
+            recordings.remove(&self.key);
+        }
+        let header = self
+            .recording
+            .header
+            .lock()
+            .ok()
+            .and_then(|mut header| header.take());
+        let entry = header
+            .filter(|_| completed && !self.too_large)
+            .map(|header| IdempotencyEntry::new_done(&header, &self.body));
+        self.store.persist(self.key.clone(), entry, self.ttl);
+    }
+}
+
+impl ModifyResponseBody for IdempotencyRecorder {
+    fn handle(
+        &mut self,
+        _session: &Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+    ) -> pingora::Result<()> {
+        if let Some(data) = body {
+            if self.body.len() + data.len() > self.max_body_size {
+                self.too_large = true;
+                self.body.clear();
+            } else if !self.too_large {
+                self.body.extend_from_slice(data);
+            }
+        }
+        if end_of_stream {
+            self.finish(true);
+        }
+        Ok(())
+    }
+    fn name(&self) -> String {
+        "idempotency".to_string()
+    }
+}
+
+impl Drop for IdempotencyRecorder {
+    fn drop(&mut self) {
+        self.finish(false);
+    }
+}
+
+/// Idempotency plugin honors the `Idempotency-Key` header of the request,
+/// the first request with a key is forwarded to upstream and its response
+/// is cached for `ttl`. The repeat requests with the same key get the cached
+/// response(with `Idempotent-Replayed: true` header) without hitting the
+/// upstream, and the requests arriving while the first one is in progress
+/// are rejected with `409`. The keys are scoped by method and path.
+/// The 5xx response or the response body exceeding `max_body_size` is not
+/// cached, so the request can be retried.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// header = "Idempotency-Key"
+/// methods = ["POST", "PATCH"]
+/// ttl = "10m"
+/// max_body_size = "1MB"
+/// # optional, share the keys via redis
+/// store = "redis://127.0.0.1:6379"
+/// timeout = "200ms"
+/// ```
+pub struct Idempotency {
+    header: String,
+    methods: Vec<Method>,
+    ttl: Duration,
+    max_body_size: usize,
+    store: Arc<IdempotencyStore>,
+    recordings: Recordings,
+    prefix: String,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for Idempotency {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::Idempotency.to_string(),
+            message,
+        };
+        let mut header = get_str_conf(value, "header");
+        if header.is_empty() {
+            header = "Idempotency-Key".to_string();
+        }
+        let methods = get_str_slice_conf(value, "methods");
+        let methods = if methods.is_empty() {
+            vec![Method::POST, Method::PATCH]
+        } else {
+            methods
+                .iter()
+                .map(|item| {
+                    Method::from_str(&item.to_uppercase()).map_err(|e| {
+                        new_invalid_error(format!(
+                            "method({item}) is invalid, {e}"
+                        ))
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+        let max_body_size = get_str_conf(value, "max_body_size");
+        let max_body_size = if max_body_size.is_empty() {
+            DEFAULT_MAX_BODY_SIZE
+        } else {
+            ByteSize::from_str(&max_body_size)
+                .map_err(new_invalid_error)?
+                .as_u64() as usize
+        };
+        let ttl = get_duration_conf(value, "ttl")
+            .unwrap_or(Duration::from_secs(10 * 60));
+        if ttl.is_zero() {
+            return Err(new_invalid_error(
+                "ttl should be greater than 0".to_string(),
+            ));
+        }
+        let store = get_str_conf(value, "store");
+        let store = if store.is_empty() {
+            IdempotencyStore::Memory(Mutex::new(AHashMap::new()))
+        } else {
+            IdempotencyStore::Redis(RedisStore::new(
+                value,
+                PluginCategory::Idempotency,
+            )?)
+        };
+        Ok(Self {
+            header,
+            methods,
+            ttl,
+            max_body_size,
+            store: Arc::new(store),
+            recordings: Arc::new(Mutex::new(AHashMap::new())),
+            prefix: format!("pingap:idempotency:{hash_value}:"),
+            hash_value,
+        })
+    }
+}
+
+impl Idempotency {
+    /// Creates a new idempotency plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new idempotency plugin");
+        Self::try_from(params)
+    }
+
+    /// Gets the key of request, it's none if the request has no
+    /// idempotency key or the method is not matched.
+    fn get_key(&self, session: &Session) -> Option<String> {
+        let req_header = session.req_header();
+        if !self.methods.contains(&req_header.method) {
+            return None;
+        }
+        let value = session.get_header_bytes(&self.header);
+        if value.is_empty() {
+            return None;
+        }
+        Some(format!(
+            "{}{}:{}:{}",
+            self.prefix,
+            req_header.method,
+            req_header.uri.path(),
+            value.to_str_lossy()
+        ))
+    }
+}
+
+#[async_trait]
+impl Plugin for Idempotency {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Responds the cached response of the key, or the request becomes
+    /// the first one of the key forwarded to upstream.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let Some(key) = self.get_key(session) else {
+            return Ok(RequestPluginResult::Skipped);
+        };
+        if !self.store.reserve(&key, self.ttl).await {
+            let entry = self.store.get(&key).await;
+            if let Some(resp) = entry.as_ref().and_then(|e| e.to_response()) {
+                debug!(key, "replay the response of idempotency key");
+                return Ok(RequestPluginResult::Respond(resp));
+            }
+            return Ok(RequestPluginResult::Respond(HttpResponse {
+                status: StatusCode::CONFLICT,
+                body: Bytes::from_static(
+                    b"A request with the same idempotency key is being processed",
+                ),
+                ..Default::default()
+            }));
+        }
+        let recording = Arc::new(Recording {
+            header: Mutex::new(None),
+        });
+        if let Ok(mut recordings) = self.recordings.lock() {
+            recordings.insert(key.clone(), recording.clone());
+        }
+        ctx.add_modify_body_handler(
+            PLUGIN_ID,
+            Box::new(IdempotencyRecorder {
+                key,
+                recording,
+                recordings: self.recordings.clone(),
+                store: self.store.clone(),
+                ttl: self.ttl,
+                body: BytesMut::new(),
+                max_body_size: self.max_body_size,
+                too_large: false,
+                done: false,
+            }),
+        );
+        Ok(RequestPluginResult::Continue)
+    }
+
+    /// Records the response header of the first request if it can be cached
+    #[inline]
+    async fn handle_response(
+        &self,
+        session: &mut Session,
+        ctx: &mut Ctx,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<ResponsePluginResult> {
+        if ctx.get_modify_body_handler(PLUGIN_ID).is_none()
+            || upstream_response.status.is_server_error()
+        {
+            return Ok(ResponsePluginResult::Unchanged);
+        }
+        let Some(key) = self.get_key(session) else {
+            return Ok(ResponsePluginResult::Unchanged);
+        };
+        let recording = self
+            .recordings
+            .lock()
+            .ok()
+            .and_then(|recordings| recordings.get(&key).cloned());
+        if let Some(recording) = recording {
+            if let Ok(mut header) = recording.header.lock() {
+                *header = Some(upstream_response.clone());
+            }
+        }
+        Ok(ResponsePluginResult::Unchanged)
+    }
+
+    /// Collects the response body of the first request
+    #[inline]
+    fn handle_response_body(
+        &self,
+        session: &mut Session,
+        ctx: &mut Ctx,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+    ) -> pingora::Result<ResponseBodyPluginResult> {
+        if let Some(recorder) = ctx.get_modify_body_handler(PLUGIN_ID) {
+            recorder.handle(session, body, end_of_stream)?;
+        }
+        Ok(ResponseBodyPluginResult::Unchanged)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("idempotency", |params| {
+        Ok(Arc::new(Idempotency::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+
+    /// Forwards the request to the mock upstream,
+    /// returns the response of upstream.
+    async fn forward(
+        plugin: &Idempotency,
+        session: &mut Session,
+        ctx: &mut Ctx,
+        status: u16,
+        body: &'static str,
+    ) -> (StatusCode, Bytes) {
+        let mut upstream_response =
+            ResponseHeader::build(status, None).unwrap();
+        upstream_response
+            .insert_header(header::CONTENT_TYPE, "application/json")
+            .unwrap();
+        plugin
+            .handle_response(session, ctx, &mut upstream_response)
+            .await
+            .unwrap();
+        let body = Bytes::from_static(body.as_bytes());
+        plugin
+            .handle_response_body(session, ctx, &mut Some(body.clone()), true)
+            .unwrap();
+        (upstream_response.status, body)
+    }
+
+    #[test]
+    fn test_idempotency_params() {
+        let plugin = Idempotency::try_from(&PluginConf::default()).unwrap();
+        assert_eq!("Idempotency-Key", plugin.header);
+        assert_eq!(vec![Method::POST, Method::PATCH], plugin.methods);
+        assert_eq!(Duration::from_secs(600), plugin.ttl);
+        assert_eq!(DEFAULT_MAX_BODY_SIZE, plugin.max_body_size);
+        assert_eq!(
+            true,
+            matches!(plugin.store.as_ref(), IdempotencyStore::Memory(_))
+        );
+
+        let plugin = Idempotency::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+header = "X-Request-Key"
+methods = ["post", "put"]
+ttl = "1m"
+max_body_size = "64KB"
+store = "redis://127.0.0.1:6379"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!("X-Request-Key", plugin.header);
+        assert_eq!(vec![Method::POST, Method::PUT], plugin.methods);
+        assert_eq!(Duration::from_secs(60), plugin.ttl);
+        assert_eq!(64 * 1000, plugin.max_body_size);
+        assert_eq!(
+            true,
+            matches!(plugin.store.as_ref(), IdempotencyStore::Redis(_))
+        );
+
+        let result = Idempotency::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+store = "memcached://127.0.0.1:11211"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin idempotency invalid, message: store(memcached://127.0.0.1:11211) should be redis://",
+            result.err().unwrap().to_string()
+        );
+
+        // redis is built without tls
+        let result = Idempotency::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+store = "rediss://127.0.0.1:6379"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin idempotency invalid, message: store(rediss://127.0.0.1:6379) should be redis://",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_replay() {
+        let plugin = Idempotency::new(&PluginConf::default()).unwrap();
+
+        // the first request is forwarded to upstream
+        let headers =
+            ["Host: pingap.io", "Idempotency-Key: order-1"].join("\r\n");
+        let input_header =
+            format!("POST /orders HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let mut ctx = Ctx::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+
+        // the duplicate request is rejected while the first one is processing
+        let headers =
+            ["Host: pingap.io", "Idempotency-Key: order-1"].join("\r\n");
+        let input_header =
+            format!("POST /orders HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut duplicate_session = test_util::new_session(&input_header).await;
+        let result = plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut duplicate_session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("result should be respond");
+        };
+        assert_eq!(StatusCode::CONFLICT, resp.status);
+
+        let (status, body) =
+            forward(&plugin, &mut session, &mut ctx, 201, r#"{"id":1}"#).await;
+        assert_eq!(StatusCode::CREATED, status);
+
+        // the duplicate request gets the cached first response
+        let headers =
+            ["Host: pingap.io", "Idempotency-Key: order-1"].join("\r\n");
+        let input_header =
+            format!("POST /orders HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut duplicate_session = test_util::new_session(&input_header).await;
+        let result = plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut duplicate_session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("result should be respond");
+        };
+        assert_eq!(status, resp.status);
+        assert_eq!(body, resp.body);
+        let headers = resp.headers.unwrap();
+        assert_eq!(
+            true,
+            headers
+                .iter()
+                .any(|(name, value)| name == HTTP_HEADER_IDEMPOTENT_REPLAYED
+                    && value == "true")
+        );
+        assert_eq!(
+            true,
+            headers
+                .iter()
+                .any(|(name, value)| name == &header::CONTENT_TYPE
+                    && value == "application/json")
+        );
+
+        // the request of other key or method is forwarded to upstream
+        let headers =
+            ["Host: pingap.io", "Idempotency-Key: order-2"].join("\r\n");
+        let input_header =
+            format!("POST /orders HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        let headers =
+            ["Host: pingap.io", "Idempotency-Key: order-1"].join("\r\n");
+        let input_header = format!("GET /orders HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_not_cached() {
+        let plugin = Idempotency::new(&PluginConf::default()).unwrap();
+
+        // the 5xx response isn't cached, the request can be retried
+        let headers =
+            ["Host: pingap.io", "Idempotency-Key: order-1"].join("\r\n");
+        let input_header =
+            format!("POST /orders HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let mut ctx = Ctx::default();
+        plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        forward(&plugin, &mut session, &mut ctx, 503, "unavailable").await;
+        let headers =
+            ["Host: pingap.io", "Idempotency-Key: order-1"].join("\r\n");
+        let input_header =
+            format!("POST /orders HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let mut ctx = Ctx::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+
+        // the key is released if the request is dropped before the end
+        drop(ctx);
+        let headers =
+            ["Host: pingap.io", "Idempotency-Key: order-1"].join("\r\n");
+        let input_header =
+            format!("POST /orders HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!(true, plugin.recordings.lock().unwrap().is_empty());
+    }
+}
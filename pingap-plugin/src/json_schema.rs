@@ -0,0 +1,401 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_hash_key, get_plugin_factory, get_str_conf, get_str_slice_conf,
+};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use bytesize::ByteSize;
+use ctor::ctor;
+use http::{Method, StatusCode};
+use jsonschema::Validator;
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, HttpResponse, Plugin, PluginStep, RequestPluginResult,
+    write_continue_response,
+};
+use pingora::proxy::Session;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The body read by the plugin is kept in the retry buffer of session
+/// and sent to upstream later, the buffer is limited to 64KB by pingora.
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+#[derive(Serialize)]
+struct SchemaError {
+    /// JSON pointer of the invalid value, e.g. `/name`
+    path: String,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct SchemaErrorResponse {
+    message: String,
+    errors: Vec<SchemaError>,
+}
+
+/// JsonSchema plugin validates the JSON request body against the configured
+/// JSON Schema, the invalid body is rejected with 400 and a list of errors
+/// before the request reaches the upstream.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// schema = '''
+/// {"type": "object", "required": ["name"]}
+/// '''
+/// methods = ["POST", "PUT", "PATCH"]
+/// max_body_size = "64KB"
+/// ```
+pub struct JsonSchema {
+    /// Compiled validator of the schema
+    validator: Validator,
+    /// Only the requests of these methods are validated
+    methods: Vec<Method>,
+    /// Maximum size of the body to read
+    max_body_size: usize,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for JsonSchema {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::JsonSchema.to_string(),
+            message,
+        };
+        let schema: serde_json::Value =
+            serde_json::from_str(&get_str_conf(value, "schema"))
+                .map_err(|e| new_invalid_error(format!("schema: {e}")))?;
+        let validator = jsonschema::validator_for(&schema)
+            .map_err(|e| new_invalid_error(format!("schema: {e}")))?;
+
+        let mut methods = vec![];
+        for method in get_str_slice_conf(value, "methods") {
+            let method = Method::from_str(&method.to_uppercase())
+                .map_err(|e| new_invalid_error(e.to_string()))?;
+            methods.push(method);
+        }
+        if methods.is_empty() {
+            methods = vec![Method::POST, Method::PUT, Method::PATCH];
+        }
+
+        let max_body_size = get_str_conf(value, "max_body_size");
+        let max_body_size = if max_body_size.is_empty() {
+            MAX_BODY_SIZE
+        } else {
+            ByteSize::from_str(&max_body_size)
+                .map_err(new_invalid_error)?
+                .as_u64() as usize
+        };
+        if max_body_size > MAX_BODY_SIZE {
+            return Err(new_invalid_error(format!(
+                "max body size should be less than or equal to {}",
+                ByteSize(MAX_BODY_SIZE as u64)
+            )));
+        }
+
+        Ok(Self {
+            validator,
+            methods,
+            max_body_size,
+            hash_value,
+        })
+    }
+}
+
+impl JsonSchema {
+    /// Creates a new json schema plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new json schema plugin");
+        Self::try_from(params)
+    }
+
+    /// Validates the body, returns the errors if the body is invalid.
+    fn validate(&self, body: &[u8]) -> Option<SchemaErrorResponse> {
+        let instance: serde_json::Value = match serde_json::from_slice(body) {
+            Ok(value) => value,
+            Err(e) => {
+                return Some(SchemaErrorResponse {
+                    message: "Request body is not valid json".to_string(),
+                    errors: vec![SchemaError {
+                        path: "".to_string(),
+                        message: e.to_string(),
+                    }],
+                });
+            },
+        };
+        let errors: Vec<SchemaError> = self
+            .validator
+            .iter_errors(&instance)
+            .map(|e| SchemaError {
+                path: e.instance_path.to_string(),
+                message: e.to_string(),
+            })
+            .collect();
+        if errors.is_empty() {
+            return None;
+        }
+        Some(SchemaErrorResponse {
+            message: "Request body does not match the schema".to_string(),
+            errors,
+        })
+    }
+}
+
+#[async_trait]
+impl Plugin for JsonSchema {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Reads and validates the request body, the body is kept in the retry
+    /// buffer and sent to upstream if it's valid.
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request
+            || !self.methods.contains(&session.req_header().method)
+        {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let too_large = || {
+            RequestPluginResult::Respond(HttpResponse {
+                status: StatusCode::PAYLOAD_TOO_LARGE,
+                body: Bytes::from(format!(
+                    "Request body is too large, max:{}",
+                    ByteSize(self.max_body_size as u64)
+                )),
+                ..Default::default()
+            })
+        };
+        let content_length = session
+            .req_header()
+            .headers
+            .get(http::header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or_default();
+        if content_length > self.max_body_size {
+            return Ok(too_large());
+        }
+
+        write_continue_response(session, ctx).await?;
+        session.enable_retry_buffering();
+        let mut body = BytesMut::new();
+        while let Some(chunk) = session.read_request_body().await? {
+            if body.len() + chunk.len() > self.max_body_size {
+                return Ok(too_large());
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        let Some(resp) = self.validate(&body) else {
+            return Ok(RequestPluginResult::Continue);
+        };
+        Ok(RequestPluginResult::Respond(
+            HttpResponse::try_from_json_status(&resp, StatusCode::BAD_REQUEST)?,
+        ))
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("json_schema", |params| {
+        Ok(Arc::new(JsonSchema::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pretty_assertions::assert_eq;
+
+    fn new_json_schema() -> JsonSchema {
+        JsonSchema::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+schema = '''
+{
+    "type": "object",
+    "required": ["name"],
+    "properties": {
+        "name": {"type": "string"},
+        "age": {"type": "integer", "minimum": 0}
+    }
+}
+'''
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_json_schema_params() {
+        let schema = new_json_schema();
+        assert_eq!(
+            vec![Method::POST, Method::PUT, Method::PATCH],
+            schema.methods
+        );
+        assert_eq!(MAX_BODY_SIZE, schema.max_body_size);
+
+        let result = JsonSchema::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+schema = '{"type": "object"'
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            true,
+            result
+                .err()
+                .unwrap()
+                .to_string()
+                .starts_with("Plugin json_schema invalid, message: schema:")
+        );
+
+        let result = JsonSchema::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+schema = '{"type": "object"}'
+max_body_size = "1MB"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin json_schema invalid, message: max body size should be less than or equal to 64.0 KiB",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_json_schema() {
+        let schema = new_json_schema();
+
+        // valid body passes, and is kept for upstream
+        let body = r#"{"name":"pingap","age":1}"#;
+        let input_header = format!(
+            "POST /users HTTP/1.1\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        let mut session = test_util::new_session(&input_header).await;
+        let result = schema
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!(
+            Some(Bytes::from_static(body.as_bytes())),
+            session.get_retry_buffer()
+        );
+
+        // get method is skipped
+        let headers = ["Content-Type: application/json", "Content-Length: 0"]
+            .join("\r\n");
+        let input_header = format!("GET /users HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = schema
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+
+        // invalid body returns structured errors
+        let body = r#"{"age":-1}"#;
+        let headers = ["Content-Type: application/json", "Content-Length: 10"]
+            .join("\r\n");
+        let input_header =
+            format!("POST /users HTTP/1.1\r\n{headers}\r\n\r\n{body}");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = schema
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("result is not Respond");
+        };
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status);
+        let data: serde_json::Value =
+            serde_json::from_slice(&resp.body).unwrap();
+        assert_eq!(
+            "Request body does not match the schema",
+            data["message"].as_str().unwrap()
+        );
+        let mut paths: Vec<&str> = data["errors"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|item| item["path"].as_str().unwrap())
+            .collect();
+        paths.sort();
+        assert_eq!(vec!["", "/age"], paths);
+
+        // invalid json
+        let body = "{";
+        let headers = ["Content-Type: application/json", "Content-Length: 1"]
+            .join("\r\n");
+        let input_header =
+            format!("POST /users HTTP/1.1\r\n{headers}\r\n\r\n{body}");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = schema
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("result is not Respond");
+        };
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status);
+        assert_eq!(
+            true,
+            std::str::from_utf8(&resp.body)
+                .unwrap()
+                .contains("Request body is not valid json")
+        );
+    }
+}
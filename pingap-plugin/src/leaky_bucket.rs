@@ -0,0 +1,328 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_hash_key, get_int_conf, get_plugin_factory, get_step_conf,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use ctor::ctor;
+use http::StatusCode;
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{Ctx, HttpResponse, Plugin, PluginStep, RequestPluginResult};
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The max rate, the emission interval is 1ns
+const MAX_RATE: u32 = 1_000_000_000;
+
+/// LeakyBucket plugin smooths request bursts of a location, the requests
+/// over the rate are queued and released at a steady rate, only when the
+/// queue is full the request is rejected with 429.
+///
+/// It's implemented with GCRA(generic cell rate algorithm), the theoretical
+/// arrival time is the only state, so no background task is required.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// rate = 10      # requests per second
+/// burst = 5      # requests allowed to pass without delay
+/// queue = 100    # max number of delayed requests
+/// ```
+pub struct LeakyBucket {
+    /// Interval between two released requests
+    emission_interval: Duration,
+    /// Tolerance of burst, the requests inside it are not delayed
+    burst_tolerance: Duration,
+    /// Maximum number of queued(delayed) requests
+    queue: u32,
+    /// Theoretical arrival time of the next request
+    tat: Mutex<Option<Instant>>,
+    /// Response returned when the queue is full
+    full_resp: HttpResponse,
+    plugin_step: PluginStep,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for LeakyBucket {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::LeakyBucket.to_string(),
+            message,
+        };
+        // the fractional rate is not supported
+        if value.get("rate").is_some_and(|v| v.as_integer().is_none()) {
+            return Err(new_invalid_error(
+                "rate should be an integer".to_string(),
+            ));
+        }
+        let rate = get_int_conf(value, "rate");
+        if rate <= 0 {
+            return Err(new_invalid_error(
+                "rate should be greater than 0".to_string(),
+            ));
+        }
+        // the emission interval should be at least 1ns
+        let rate = u32::try_from(rate)
+            .ok()
+            .filter(|rate| *rate <= MAX_RATE)
+            .ok_or_else(|| {
+                new_invalid_error(format!(
+                    "rate({rate}) should be less than or equal to {MAX_RATE}"
+                ))
+            })?;
+        let get_u32_conf = |key: &str| {
+            let value = get_int_conf(value, key);
+            u32::try_from(value).map_err(|_| {
+                new_invalid_error(format!("{key}({value}) is invalid"))
+            })
+        };
+        let emission_interval = Duration::from_secs(1) / rate;
+        let burst = get_u32_conf("burst")?;
+        let queue = get_u32_conf("queue")?;
+        let plugin_step = get_step_conf(value, PluginStep::Request);
+        if ![PluginStep::Request, PluginStep::ProxyUpstream]
+            .contains(&plugin_step)
+        {
+            return Err(Error::Invalid {
+                category: PluginCategory::LeakyBucket.to_string(),
+                message: "Leaky bucket plugin should be executed at request or proxy upstream step".to_string(),
+            });
+        }
+
+        Ok(Self {
+            emission_interval,
+            burst_tolerance: emission_interval * burst,
+            queue,
+            tat: Mutex::new(None),
+            full_resp: HttpResponse {
+                status: StatusCode::TOO_MANY_REQUESTS,
+                body: Bytes::from_static(b"Too many requests, queue is full"),
+                ..Default::default()
+            },
+            plugin_step,
+            hash_value,
+        })
+    }
+}
+
+impl LeakyBucket {
+    /// Creates a new leaky bucket plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new leaky bucket plugin");
+        Self::try_from(params)
+    }
+
+    /// Reserves a slot for the request arriving at `now`.
+    ///
+    /// Returns the delay before the request can be released,
+    /// or `None` if the queue is full.
+    fn reserve(&self, now: Instant) -> Option<Duration> {
+        let Ok(mut tat) = self.tat.lock() else {
+            return Some(Duration::ZERO);
+        };
+        let current = tat.map_or(now, |value| value.max(now));
+        let delay = current
+            .saturating_duration_since(now)
+            .saturating_sub(self.burst_tolerance);
+        if !delay.is_zero() {
+            let position = delay
+                .as_nanos()
+                .div_ceil(self.emission_interval.as_nanos().max(1));
+            if position > self.queue as u128 {
+                return None;
+            }
+        }
+        *tat = Some(current + self.emission_interval);
+        Some(delay)
+    }
+}
+
+#[async_trait]
+impl Plugin for LeakyBucket {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Delays the request until its slot is released,
+    /// responds 429 if the queue is full.
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        _session: &mut Session,
+        _ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != self.plugin_step {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let Some(delay) = self.reserve(Instant::now()) else {
+            return Ok(RequestPluginResult::Respond(self.full_resp.clone()));
+        };
+        if !delay.is_zero() {
+            sleep(delay).await;
+        }
+        Ok(RequestPluginResult::Continue)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("leaky_bucket", |params| {
+        Ok(Arc::new(LeakyBucket::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pretty_assertions::assert_eq;
+
+    fn new_leaky_bucket(conf: &str) -> LeakyBucket {
+        LeakyBucket::new(&toml::from_str::<PluginConf>(conf).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_leaky_bucket_params() {
+        let bucket = new_leaky_bucket(
+            r###"
+rate = 10
+burst = 2
+queue = 5
+"###,
+        );
+        assert_eq!(Duration::from_millis(100), bucket.emission_interval);
+        assert_eq!(Duration::from_millis(200), bucket.burst_tolerance);
+        assert_eq!(5, bucket.queue);
+
+        let result = LeakyBucket::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+rate = 0
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin leaky_bucket invalid, message: rate should be greater than 0",
+            result.err().unwrap().to_string()
+        );
+
+        for (conf, message) in [
+            ("rate = 10.5", "rate should be an integer"),
+            (
+                "rate = 5000000000",
+                "rate(5000000000) should be less than or equal to 1000000000",
+            ),
+            ("rate = 10\nburst = -1", "burst(-1) is invalid"),
+            (
+                "rate = 10\nqueue = 5000000000",
+                "queue(5000000000) is invalid",
+            ),
+        ] {
+            let result = LeakyBucket::try_from(
+                &toml::from_str::<PluginConf>(conf).unwrap(),
+            );
+            assert_eq!(
+                format!("Plugin leaky_bucket invalid, message: {message}"),
+                result.err().unwrap().to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_leaky_bucket_reserve() {
+        let bucket = new_leaky_bucket(
+            r###"
+rate = 10
+burst = 1
+queue = 2
+"###,
+        );
+        let now = Instant::now();
+        // burst requests are not delayed
+        assert_eq!(Some(Duration::ZERO), bucket.reserve(now));
+        assert_eq!(Some(Duration::ZERO), bucket.reserve(now));
+        // queued requests are released at the configured rate
+        assert_eq!(Some(Duration::from_millis(100)), bucket.reserve(now));
+        assert_eq!(Some(Duration::from_millis(200)), bucket.reserve(now));
+        // queue is full
+        assert_eq!(None, bucket.reserve(now));
+
+        // the bucket leaks over time
+        let now = now + Duration::from_millis(300);
+        assert_eq!(Some(Duration::ZERO), bucket.reserve(now));
+        assert_eq!(Some(Duration::from_millis(100)), bucket.reserve(now));
+    }
+
+    #[tokio::test]
+    async fn test_leaky_bucket() {
+        let bucket = new_leaky_bucket(
+            r###"
+rate = 20
+queue = 1
+"###,
+        );
+        let input_header = "GET /vicanso/pingap HTTP/1.1\r\n\r\n";
+        let mut session = test_util::new_session(input_header).await;
+
+        let start = Instant::now();
+        let result = bucket
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        // the second request is delayed for one interval(50ms)
+        let result = bucket
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!(true, start.elapsed() >= Duration::from_millis(45));
+
+        // fill the queue, then overflow returns 429
+        let _ = bucket.reserve(Instant::now());
+        let result = bucket
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("result is not Respond");
+        };
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, resp.status);
+    }
+}
@@ -137,27 +137,87 @@ pub fn get_hash_key(conf: &PluginConf) -> String {
 }
 
 mod accept_encoding;
+mod api_version;
+mod audit;
+mod bandwidth_limit;
 mod basic_auth;
+mod body_transform;
 mod cache;
+mod cache_directives;
+mod canned_response;
+mod captcha;
 mod combined_auth;
 mod compression;
+mod content_type_restriction;
+mod cookie_rewrite;
 mod cors;
 mod csrf;
+mod dedup;
 mod directory;
+mod entitlement;
+mod fault_injection;
+mod forward_proxy;
+mod geoip;
+mod grpc_status;
+mod hsts_preload;
+mod http_version;
+mod idempotency;
 mod ip_restriction;
+mod json_schema;
 mod jwt;
 mod key_auth;
+mod leaky_bucket;
 mod limit;
+mod method_override;
+mod min_upload_rate;
+mod minify;
 mod mock;
+mod mtls_jwt;
+mod path_map;
 mod ping;
+mod probe;
+mod query_strip;
+mod quota;
 mod redirect;
+mod redirect_limit;
 mod referer_restriction;
+mod replay_protection;
+mod request_coalescing;
+mod request_decompression;
 mod request_id;
+mod request_signing;
 mod response_headers;
+mod scope_restriction;
+mod security_headers;
+mod spa_fallback;
+mod status_map;
 mod sub_filter;
+mod tarpit;
+mod tier_limit;
+mod tls_version_restriction;
+mod trace_context;
 mod traffic_splitting;
 mod ua_restriction;
+mod upstream_override;
+mod upstream_retry;
+mod www_redirect;
 
 mod plugin;
+mod redis_store;
 
 pub use plugin::get_plugin_factory;
+
+#[cfg(test)]
+mod test_util {
+    use pingora::proxy::Session;
+    use tokio_test::io::Builder;
+
+    /// Creates the http/1.1 session of the raw request(header and body),
+    /// the request header has been read.
+    pub async fn new_session(input: impl AsRef<[u8]>) -> Session {
+        let mock_io = Builder::new().read(input.as_ref()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        session
+    }
+}
@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use super::redis_store::{FailMode, RedisStore, get_fail_mode_conf};
 use super::{
     Error, get_hash_key, get_int_conf, get_int_conf_or_default,
     get_plugin_factory, get_step_conf, get_str_conf,
@@ -28,8 +29,9 @@ use pingap_core::{
     get_client_ip, get_cookie_value, get_query_value, get_req_header_value,
 };
 use pingora::proxy::Session;
+use redis::Script;
 use std::borrow::Cow;
-use std::sync::Arc;
+use std::sync::{Arc, LazyLock};
 use std::time::Duration;
 use tracing::debug;
 
@@ -44,6 +46,82 @@ pub enum LimitTag {
     Query,         // Use value from a specified URL query parameter
 }
 
+/// Token bucket implemented as a lua script, so the read-refill-consume
+/// cycle is atomic even when many pingap instances share the same redis.
+///
+/// KEYS[1]: bucket key
+/// ARGV[1]: capacity of the bucket
+/// ARGV[2]: refill tokens per millisecond
+/// ARGV[3]: ttl of the bucket in milliseconds
+///
+/// Returns `{allowed, remaining}`.
+static TOKEN_BUCKET_SCRIPT: LazyLock<Script> = LazyLock::new(|| {
+    Script::new(
+        r#"
+local capacity = tonumber(ARGV[1])
+local refill = tonumber(ARGV[2])
+local ttl = tonumber(ARGV[3])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+local data = redis.call('HMGET', KEYS[1], 'tokens', 'ts')
+local tokens = tonumber(data[1])
+local ts = tonumber(data[2])
+if tokens == nil or ts == nil then
+    tokens = capacity
+    ts = now
+end
+tokens = math.min(capacity, tokens + math.max(0, now - ts) * refill)
+local allowed = 0
+if tokens >= 1 then
+    tokens = tokens - 1
+    allowed = 1
+end
+redis.call('HSET', KEYS[1], 'tokens', tokens, 'ts', now)
+redis.call('PEXPIRE', KEYS[1], ttl)
+return {allowed, math.floor(tokens)}
+"#,
+    )
+});
+
+/// Rate limiter whose counters are stored in redis,
+/// so the limit is shared by all pingap instances.
+pub struct RedisRate {
+    store: RedisStore,
+    /// Maximum number of requests within the interval(bucket capacity)
+    capacity: u64,
+    interval: Duration,
+    fail_mode: FailMode,
+    /// Prefix of the bucket key
+    prefix: String,
+}
+
+impl RedisRate {
+    /// Consumes a token of the bucket, returns whether the request is allowed.
+    /// If redis is unavailable, the result depends on the fail mode.
+    pub async fn observe(&self, key: &str) -> bool {
+        let key = format!("{}{key}", self.prefix);
+        let capacity = self.capacity;
+        let interval_ms = self.interval.as_millis().max(1) as u64;
+        let refill = capacity as f64 / interval_ms as f64;
+        let result = self
+            .store
+            .execute(|mut conn| async move {
+                TOKEN_BUCKET_SCRIPT
+                    .key(key)
+                    .arg(capacity)
+                    .arg(refill)
+                    .arg(interval_ms * 2)
+                    .invoke_async::<(i64, u64)>(&mut conn)
+                    .await
+            })
+            .await;
+        match result {
+            Some((allowed, _)) => allowed == 1,
+            None => self.fail_mode == FailMode::Open,
+        }
+    }
+}
+
 // Limiter implements rate limiting and concurrent request limiting
 // It can be configured via TOML with settings like:
 // ```toml
@@ -52,6 +130,8 @@ pub enum LimitTag {
 // key = "session_id"     # name of header/cookie/query param to use
 // max = 100             # maximum requests allowed
 // interval = "60s"      # time window for rate limiting
+// store = "redis://127.0.0.1:6379"  # optional, share counters via redis
+// fail_mode = "open"    # or "closed", when redis is unavailable
 // ```
 /// A rate limiter or concurrent request limiter that can be configured to limit based on
 /// different request attributes (IP, headers, cookies, query params)
@@ -77,6 +157,11 @@ pub struct Limiter {
     /// Only used when configured as a rate limiter (type = "rate")
     rate: Option<Rate>,
 
+    /// Tracks request counts in redis with a token bucket,
+    /// the limit is enforced across all pingap instances.
+    /// Only used when configured as a rate limiter with a `redis://` store
+    redis: Option<RedisRate>,
+
     /// When to apply the limiting logic:
     /// - PluginStep::Request: During initial request processing
     /// - PluginStep::ProxyUpstream: Before forwarding to upstream server
@@ -104,6 +189,9 @@ pub struct Limiter {
 /// * `key` - Name of header/cookie/query parameter to use
 /// * `max` - Maximum allowed requests/connections
 /// * `interval` - Time window for rate limiting (e.g. "60s")
+/// * `store` - Optional `redis://` url for cluster-wide rate limiting
+/// * `fail_mode` - "open" or "closed", behavior when redis is unavailable
+/// * `timeout` - Timeout of redis operations (e.g. "100ms")
 impl TryFrom<&PluginConf> for Limiter {
     type Error = Error;
     fn try_from(value: &PluginConf) -> Result<Self> {
@@ -134,8 +222,25 @@ impl TryFrom<&PluginConf> for Limiter {
         // Create either inflight or rate limiter based on config
         let mut inflight = None;
         let mut rate = None;
+        let mut redis = None;
         let mut max = get_int_conf(value, "max") as f64;
-        if get_str_conf(value, "type") == "inflight" {
+        let store = get_str_conf(value, "store");
+        let is_inflight = get_str_conf(value, "type") == "inflight";
+        if !store.is_empty() {
+            if is_inflight {
+                return Err(Error::Invalid {
+                    category: PluginCategory::Limit.to_string(),
+                    message: "Only rate limit supports redis store".to_string(),
+                });
+            }
+            redis = Some(RedisRate {
+                store: RedisStore::new(value, PluginCategory::Limit)?,
+                capacity: max.max(0.0) as u64,
+                interval,
+                fail_mode: get_fail_mode_conf(value, PluginCategory::Limit)?,
+                prefix: format!("pingap:limit:{hash_value}:"),
+            });
+        } else if is_inflight {
             // Inflight limiter uses atomic counters to track concurrent requests
             inflight = Some(Inflight::new());
         } else {
@@ -156,6 +261,7 @@ impl TryFrom<&PluginConf> for Limiter {
             max,
             inflight,
             rate,
+            redis,
             plugin_step: step,
             weight,
         };
@@ -194,22 +300,10 @@ impl Limiter {
         debug!(params = params.to_string(), "new limit plugin");
         Self::try_from(params)
     }
-    /// Increments and checks the limit counter for the current request
-    ///
-    /// # Arguments
-    /// * `session` - The HTTP session containing request details
-    /// * `ctx` - Mutable state context for storing request data
-    ///
-    /// # Returns
-    /// * `Result<()>` - Ok if within limits, Error if limit exceeded
-    ///
-    /// # Effects
-    /// * For rate limiting: Records request in time window
-    /// * For inflight limiting: Increments counter and stores RAII guard in context
-    /// * For IP-based limiting: Stores client IP in context
-    pub fn incr(&self, session: &Session, ctx: &mut Ctx) -> Result<()> {
+    /// Extracts the value used as the limiting key from the request
+    fn get_key(&self, session: &Session, ctx: &mut Ctx) -> String {
         // Extract the key value based on configured tag type
-        let key = match self.tag {
+        match self.tag {
             LimitTag::Query => {
                 // Get value from URL query parameter
                 get_query_value(session.req_header(), &self.key)
@@ -236,7 +330,23 @@ impl Limiter {
 
                 ip.to_string()
             },
-        };
+        }
+    }
+    /// Increments and checks the limit counter for the current request
+    ///
+    /// # Arguments
+    /// * `session` - The HTTP session containing request details
+    /// * `ctx` - Mutable state context for storing request data
+    ///
+    /// # Returns
+    /// * `Result<()>` - Ok if within limits, Error if limit exceeded
+    ///
+    /// # Effects
+    /// * For rate limiting: Records request in time window
+    /// * For inflight limiting: Increments counter and stores RAII guard in context
+    /// * For IP-based limiting: Stores client IP in context
+    pub fn incr(&self, session: &Session, ctx: &mut Ctx) -> Result<()> {
+        let key = self.get_key(session, ctx);
 
         // Skip limiting if no key found (e.g., missing header/cookie)
         if key.is_empty() {
@@ -314,6 +424,25 @@ impl Plugin for Limiter {
             return Ok(RequestPluginResult::Skipped);
         }
 
+        // Counters are shared via redis
+        if let Some(redis) = &self.redis {
+            let key = self.get_key(session, ctx);
+            if !key.is_empty() && !redis.observe(&key).await {
+                return Ok(RequestPluginResult::Respond(HttpResponse {
+                    status: StatusCode::TOO_MANY_REQUESTS,
+                    body: Error::Exceed {
+                        category: PluginCategory::Limit.to_string(),
+                        max: self.max,
+                        value: self.max + 1.0,
+                    }
+                    .to_string()
+                    .into(),
+                    ..Default::default()
+                }));
+            }
+            return Ok(RequestPluginResult::Continue);
+        }
+
         // Try to increment counter
         if let Err(e) = self.incr(session, ctx) {
             // If limit exceeded, return 429 Too Many Requests
@@ -338,32 +467,14 @@ fn init() {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util;
     use http::StatusCode;
     use pingap_config::PluginConf;
     use pingap_core::{Ctx, PluginStep};
-    use pingora::proxy::Session;
     use pretty_assertions::assert_eq;
     use std::time::Duration;
-    use tokio_test::io::Builder;
-
-    async fn new_session() -> Session {
-        let headers = [
-            "Host: github.com",
-            "Referer: https://github.com/",
-            "User-Agent: pingap/0.1.1",
-            "Cookie: deviceId=abc",
-            "Accept: application/json",
-            "X-Uuid: 138q71",
-            "X-Forwarded-For: 1.1.1.1, 192.168.1.2",
-        ]
-        .join("\r\n");
-        let input_header =
-            format!("GET /vicanso/pingap?key=1 HTTP/1.1\r\n{headers}\r\n\r\n");
-        let mock_io = Builder::new().read(input_header.as_bytes()).build();
-        let mut session = Session::new_h1(Box::new(mock_io));
-        session.read_request().await.unwrap();
-        session
-    }
+
+    const INPUT_HEADER: &str = "GET /vicanso/pingap?key=1 HTTP/1.1\r\nHost: github.com\r\nReferer: https://github.com/\r\nUser-Agent: pingap/0.1.1\r\nCookie: deviceId=abc\r\nAccept: application/json\r\nX-Uuid: 138q71\r\nX-Forwarded-For: 1.1.1.1, 192.168.1.2\r\n\r\n";
 
     #[test]
     fn test_limit_params() {
@@ -421,7 +532,7 @@ max = 10
         let mut ctx = Ctx {
             ..Default::default()
         };
-        let session = new_session().await;
+        let session = test_util::new_session(INPUT_HEADER).await;
 
         limiter.incr(&session, &mut ctx).unwrap();
         assert_eq!(true, ctx.state.guard.is_some());
@@ -444,7 +555,7 @@ max = 10
         let mut ctx = Ctx {
             ..Default::default()
         };
-        let session = new_session().await;
+        let session = test_util::new_session(INPUT_HEADER).await;
 
         limiter.incr(&session, &mut ctx).unwrap();
         assert_eq!(true, ctx.state.guard.is_some());
@@ -467,7 +578,7 @@ max = 10
         let mut ctx = Ctx {
             ..Default::default()
         };
-        let session = new_session().await;
+        let session = test_util::new_session(INPUT_HEADER).await;
 
         limiter.incr(&session, &mut ctx).unwrap();
         assert_eq!(true, ctx.state.guard.is_some());
@@ -488,7 +599,7 @@ max = 10
         let mut ctx = Ctx {
             ..Default::default()
         };
-        let session = new_session().await;
+        let session = test_util::new_session(INPUT_HEADER).await;
 
         limiter.incr(&session, &mut ctx).unwrap();
         assert_eq!(true, ctx.state.guard.is_some());
@@ -509,9 +620,7 @@ max = 0
         let headers = ["X-Forwarded-For: 1.1.1.1"].join("\r\n");
         let input_header =
             format!("GET /vicanso/pingap?size=1 HTTP/1.1\r\n{headers}\r\n\r\n");
-        let mock_io = Builder::new().read(input_header.as_bytes()).build();
-        let mut session = Session::new_h1(Box::new(mock_io));
-        session.read_request().await.unwrap();
+        let mut session = test_util::new_session(&input_header).await;
         let result = limiter
             .handle_request(
                 PluginStep::Request,
@@ -565,9 +674,7 @@ interval = "1s"
         let headers = ["X-Forwarded-For: 1.1.1.1"].join("\r\n");
         let input_header =
             format!("GET /vicanso/pingap?size=1 HTTP/1.1\r\n{headers}\r\n\r\n");
-        let mock_io = Builder::new().read(input_header.as_bytes()).build();
-        let mut session = Session::new_h1(Box::new(mock_io));
-        session.read_request().await.unwrap();
+        let mut session = test_util::new_session(&input_header).await;
         let result = limiter
             .handle_request(
                 PluginStep::Request,
@@ -615,4 +722,149 @@ interval = "1s"
             .unwrap();
         assert_eq!(true, result == RequestPluginResult::Continue);
     }
+
+    #[test]
+    fn test_redis_limit_params() {
+        let limiter = Limiter::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+type = "rate"
+max = 10
+interval = "1m"
+store = "redis://127.0.0.1:6379"
+fail_mode = "closed"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let redis = limiter.redis.unwrap();
+        assert_eq!(FailMode::Closed, redis.fail_mode);
+        assert_eq!(10, redis.capacity);
+        assert_eq!(true, limiter.rate.is_none());
+
+        let result = Limiter::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+type = "inflight"
+max = 10
+store = "redis://127.0.0.1:6379"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin limit invalid, message: Only rate limit supports redis store",
+            result.err().unwrap().to_string()
+        );
+
+        // redis is built without tls
+        let result = Limiter::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+type = "rate"
+max = 10
+store = "rediss://127.0.0.1:6379"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin limit invalid, message: store(rediss://127.0.0.1:6379) should be redis://",
+            result.err().unwrap().to_string()
+        );
+
+        let result = Limiter::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+type = "rate"
+max = 10
+store = "redis://127.0.0.1:6379"
+fail_mode = "close"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin limit invalid, message: fail mode(close) should be open or closed",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redis_limit_fail_mode() {
+        // nothing listens on port 1, so redis is always unavailable
+        let new_limiter = |fail_mode: &str| {
+            Limiter::new(
+                &toml::from_str::<PluginConf>(&format!(
+                    r###"
+type = "rate"
+max = 1
+store = "redis://127.0.0.1:1"
+timeout = "50ms"
+fail_mode = "{fail_mode}"
+"###
+                ))
+                .unwrap(),
+            )
+            .unwrap()
+        };
+        let mut session = test_util::new_session(INPUT_HEADER).await;
+
+        let result = new_limiter("open")
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+
+        let result = new_limiter("closed")
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("result is not Respond");
+        };
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, resp.status);
+    }
+
+    /// Runs against a local redis, set `PINGAP_TEST_REDIS` to enable it,
+    /// e.g. `PINGAP_TEST_REDIS=redis://127.0.0.1:6379`
+    #[tokio::test]
+    async fn test_redis_limit_token_bucket() {
+        let Ok(url) = std::env::var("PINGAP_TEST_REDIS") else {
+            return;
+        };
+        let limiter = Limiter::new(
+            &toml::from_str::<PluginConf>(&format!(
+                r###"
+type = "rate"
+max = 5
+interval = "1h"
+store = "{url}"
+fail_mode = "closed"
+"###
+            ))
+            .unwrap(),
+        )
+        .unwrap();
+        let redis = limiter.redis.unwrap();
+        let key = nanoid::nanoid!(16);
+
+        // each call consumes exactly one token until the bucket is empty
+        let mut allowed_count = 0;
+        for _ in 0..10 {
+            if redis.observe(&key).await {
+                allowed_count += 1;
+            }
+        }
+        assert_eq!(5, allowed_count);
+    }
 }
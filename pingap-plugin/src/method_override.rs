@@ -0,0 +1,302 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_hash_key, get_plugin_factory, get_str_conf, get_str_slice_conf,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use ctor::ctor;
+use http::{HeaderName, Method, StatusCode};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{Ctx, HttpResponse, Plugin, PluginStep, RequestPluginResult};
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+const DEFAULT_OVERRIDE_HEADER: &str = "X-HTTP-Method-Override";
+
+/// MethodOverride plugin changes the forwarded method by the override
+/// header, for legacy clients which can only send GET/POST.
+/// Only the request of source methods(default POST) can be overridden,
+/// and the override method should be in the allowlist, otherwise the
+/// request is rejected. The safe GET method is not a source method by
+/// default, because overriding it to an unsafe method bypasses the
+/// csrf protection of browser.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// header = "X-HTTP-Method-Override"
+/// # the methods which can be overridden
+/// source_methods = ["POST"]
+/// # the allowed override methods
+/// methods = ["PUT", "PATCH", "DELETE"]
+/// ```
+pub struct MethodOverride {
+    header: HeaderName,
+    source_methods: Vec<Method>,
+    methods: Vec<Method>,
+    hash_value: String,
+}
+
+/// Parses the methods, the default methods are used if it's empty
+fn parse_methods(
+    values: Vec<String>,
+    default: &[Method],
+) -> Result<Vec<Method>> {
+    if values.is_empty() {
+        return Ok(default.to_vec());
+    }
+    values
+        .iter()
+        .map(|value| {
+            Method::from_str(&value.trim().to_uppercase()).map_err(|e| {
+                Error::Invalid {
+                    category: PluginCategory::MethodOverride.to_string(),
+                    message: format!("method({value}) is invalid, {e}"),
+                }
+            })
+        })
+        .collect()
+}
+
+impl TryFrom<&PluginConf> for MethodOverride {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let mut header = get_str_conf(value, "header");
+        if header.is_empty() {
+            header = DEFAULT_OVERRIDE_HEADER.to_string();
+        }
+        let header =
+            HeaderName::from_str(&header).map_err(|e| Error::Invalid {
+                category: PluginCategory::MethodOverride.to_string(),
+                message: format!("header({header}) is invalid, {e}"),
+            })?;
+        Ok(Self {
+            header,
+            source_methods: parse_methods(
+                get_str_slice_conf(value, "source_methods"),
+                &[Method::POST],
+            )?,
+            methods: parse_methods(
+                get_str_slice_conf(value, "methods"),
+                &[Method::PUT, Method::PATCH, Method::DELETE],
+            )?,
+            hash_value,
+        })
+    }
+}
+
+impl MethodOverride {
+    /// Creates a new method override plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new method override plugin");
+        Self::try_from(params)
+    }
+}
+
+#[async_trait]
+impl Plugin for MethodOverride {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Sets the method of request to the override method, and removes the
+    /// override header before the request is forwarded to upstream.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        _ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let Some(value) = session.req_header().headers.get(&self.header) else {
+            return Ok(RequestPluginResult::Skipped);
+        };
+        let method = value
+            .to_str()
+            .ok()
+            .and_then(|value| {
+                Method::from_str(&value.trim().to_uppercase()).ok()
+            })
+            .filter(|method| self.methods.contains(method));
+        let Some(method) = method.filter(|_| {
+            self.source_methods.contains(&session.req_header().method)
+        }) else {
+            return Ok(RequestPluginResult::Respond(HttpResponse {
+                status: StatusCode::METHOD_NOT_ALLOWED,
+                body: Bytes::from(format!(
+                    "Method override from {} to {} is not allowed",
+                    session.req_header().method,
+                    String::from_utf8_lossy(value.as_bytes())
+                )),
+                ..Default::default()
+            }));
+        };
+        let header = session.req_header_mut();
+        header.remove_header(&self.header);
+        header.set_method(method);
+        Ok(RequestPluginResult::Continue)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("method_override", |params| {
+        Ok(Arc::new(MethodOverride::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_method_override_params() {
+        let plugin = MethodOverride::try_from(&PluginConf::default()).unwrap();
+        assert_eq!("x-http-method-override", plugin.header.as_str());
+        assert_eq!(vec![Method::POST], plugin.source_methods);
+        assert_eq!(
+            vec![Method::PUT, Method::PATCH, Method::DELETE],
+            plugin.methods
+        );
+
+        let plugin = MethodOverride::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+header = "X-Method"
+source_methods = ["get", "POST"]
+methods = ["delete"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!("x-method", plugin.header.as_str());
+        assert_eq!(vec![Method::GET, Method::POST], plugin.source_methods);
+        assert_eq!(vec![Method::DELETE], plugin.methods);
+
+        let result = MethodOverride::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+methods = ["DEL ETE"]
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin method_override invalid, message: method(DEL ETE) is invalid, invalid HTTP method",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_method_override() {
+        let plugin = MethodOverride::new(&PluginConf::default()).unwrap();
+
+        // the upstream method is overridden
+        let headers =
+            ["Host: pingap.io", "X-HTTP-Method-Override: put"].join("\r\n");
+        let input_header =
+            format!("POST /users/1 HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!(Method::PUT, session.req_header().method);
+        assert_eq!(
+            true,
+            session
+                .req_header()
+                .headers
+                .get(DEFAULT_OVERRIDE_HEADER)
+                .is_none()
+        );
+
+        // request without override header is skipped
+        let headers = ["Host: pingap.io"].join("\r\n");
+        let input_header =
+            format!("POST /users/1 HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+        assert_eq!(Method::POST, session.req_header().method);
+
+        // the override method is not in the allowlist
+        let headers =
+            ["Host: pingap.io", "X-HTTP-Method-Override: CONNECT"].join("\r\n");
+        let input_header =
+            format!("POST /users/1 HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let RequestPluginResult::Respond(resp) = plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap()
+        else {
+            panic!("override to CONNECT should be rejected");
+        };
+        assert_eq!(StatusCode::METHOD_NOT_ALLOWED, resp.status);
+        assert_eq!(
+            "Method override from POST to CONNECT is not allowed",
+            std::string::String::from_utf8_lossy(resp.body.as_ref())
+        );
+
+        // the safe GET method can't be overridden
+        let headers =
+            ["Host: pingap.io", "X-HTTP-Method-Override: DELETE"].join("\r\n");
+        let input_header =
+            format!("GET /users/1 HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, matches!(result, RequestPluginResult::Respond(_)));
+        assert_eq!(Method::GET, session.req_header().method);
+    }
+}
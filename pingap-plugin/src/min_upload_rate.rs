@@ -0,0 +1,249 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_duration_conf, get_hash_key, get_plugin_factory, get_str_conf,
+};
+use async_trait::async_trait;
+use bytesize::ByteSize;
+use ctor::ctor;
+use http::header;
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, MinUploadRate as UploadRate, Plugin, PluginStep, RequestPluginResult,
+};
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// MinUploadRate plugin protects against the slow upload(slowloris body)
+/// attack, the client should upload the request body faster than the
+/// rate in each window, otherwise the request is aborted with 408.
+/// The read timeout of the request body is also set to the window,
+/// so the stalled upload is aborted too.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// rate = "1KB"    # minimum bytes per second
+/// window = "10s"  # the window of checking the throughput, default 10s
+/// ```
+pub struct MinUploadRate {
+    /// Minimum bytes per second
+    rate: u64,
+    /// The window of checking the throughput
+    window: Duration,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for MinUploadRate {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::MinUploadRate.to_string(),
+            message,
+        };
+        let rate = get_str_conf(value, "rate");
+        let rate = if rate.is_empty() {
+            0
+        } else {
+            ByteSize::from_str(&rate)
+                .map(|size| size.as_u64())
+                .map_err(|e| new_invalid_error(format!("rate: {e}")))?
+        };
+        if rate == 0 {
+            return Err(new_invalid_error(
+                "rate should be greater than 0".to_string(),
+            ));
+        }
+        let window = get_duration_conf(value, "window")
+            .unwrap_or(Duration::from_secs(10));
+        if window.is_zero() {
+            return Err(new_invalid_error(
+                "window should be greater than 0".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            rate,
+            window,
+            hash_value,
+        })
+    }
+}
+
+impl MinUploadRate {
+    /// Creates a new min upload rate plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new min upload rate plugin");
+        Self::try_from(params)
+    }
+}
+
+/// Returns true if the request has body to upload.
+fn has_body(session: &Session) -> bool {
+    let headers = &session.req_header().headers;
+    if headers.contains_key(header::TRANSFER_ENCODING) {
+        return true;
+    }
+    headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok())
+        .is_some_and(|size| size > 0)
+}
+
+#[async_trait]
+impl Plugin for MinUploadRate {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Sets the minimum upload rate of the request, the request body
+    /// chunks are checked by the proxy.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request || !has_body(session) {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        ctx.set_min_upload_rate(UploadRate::new(
+            self.rate,
+            self.window,
+            Instant::now(),
+        ));
+        session.set_read_timeout(Some(self.window));
+        Ok(RequestPluginResult::Continue)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("min_upload_rate", |params| {
+        Ok(Arc::new(MinUploadRate::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    fn new_min_upload_rate() -> MinUploadRate {
+        MinUploadRate::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+rate = "1KiB"
+window = "200ms"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap()
+    }
+
+    /// Uploads the body and returns true if the upload is too slow.
+    async fn upload(
+        plugin: &MinUploadRate,
+        chunks: &[&str],
+        interval: Duration,
+    ) -> bool {
+        let size: usize = chunks.iter().map(|chunk| chunk.len()).sum();
+        let input_header =
+            format!("POST /upload HTTP/1.1\r\nContent-Length: {size}\r\n\r\n");
+        let mut builder = Builder::new();
+        builder.read(input_header.as_bytes());
+        for chunk in chunks {
+            builder.wait(interval).read(chunk.as_bytes());
+        }
+        let mut session = Session::new_h1(Box::new(builder.build()));
+        session.read_request().await.unwrap();
+
+        let mut ctx = Ctx::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        while let Some(buf) = session.read_request_body().await.unwrap() {
+            if ctx.is_upload_too_slow(buf.len()) {
+                return true;
+            }
+        }
+        false
+    }
+
+    #[test]
+    fn test_min_upload_rate_params() {
+        let plugin = new_min_upload_rate();
+        assert_eq!(1024, plugin.rate);
+        assert_eq!(Duration::from_millis(200), plugin.window);
+
+        let result = MinUploadRate::try_from(&PluginConf::default());
+        assert_eq!(
+            "Plugin min_upload_rate invalid, message: rate should be greater than 0",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_min_upload_rate() {
+        let plugin = new_min_upload_rate();
+
+        // the request without body is skipped
+        let mock_io = Builder::new().read(b"GET / HTTP/1.1\r\n\r\n").build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let mut ctx = Ctx::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+        assert_eq!(false, ctx.is_upload_too_slow(1));
+
+        // the normal speed upload completes
+        let chunk = "a".repeat(1024);
+        assert_eq!(
+            false,
+            upload(
+                &plugin,
+                &[&chunk, &chunk, &chunk],
+                Duration::from_millis(10)
+            )
+            .await
+        );
+
+        // the too slow upload is aborted
+        assert_eq!(
+            true,
+            upload(&plugin, &["hello", "hello"], Duration::from_millis(150))
+                .await
+        );
+    }
+}
@@ -0,0 +1,881 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_bool_conf, get_hash_key, get_plugin_factory, get_str_conf,
+    get_str_slice_conf,
+};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use bytesize::ByteSize;
+use ctor::ctor;
+use http::{StatusCode, header};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, HTTP_HEADER_TRANSFER_CHUNKED, ModifyResponseBody, Plugin,
+    ResponseBodyPluginResult, ResponsePluginResult,
+};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+const PLUGIN_ID: &str = "_minify_";
+
+const DEFAULT_MIN_SIZE: usize = 1024;
+const DEFAULT_MAX_SIZE: usize = 1024 * 1024;
+
+/// The kind of content which can be minified
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MinifyKind {
+    Html,
+    Css,
+    Js,
+}
+
+impl FromStr for MinifyKind {
+    type Err = String;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "html" => Ok(MinifyKind::Html),
+            "css" => Ok(MinifyKind::Css),
+            "js" | "javascript" => Ok(MinifyKind::Js),
+            _ => Err(format!("minify type({value}) should be html, css or js")),
+        }
+    }
+}
+
+impl MinifyKind {
+    /// Gets the kind of content from the content type of response
+    fn from_content_type(content_type: &str) -> Option<Self> {
+        let mime = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_lowercase();
+        match mime.as_str() {
+            "text/html" => Some(MinifyKind::Html),
+            "text/css" => Some(MinifyKind::Css),
+            "application/javascript"
+            | "text/javascript"
+            | "application/x-javascript" => Some(MinifyKind::Js),
+            _ => None,
+        }
+    }
+    fn minify(&self, data: &[u8]) -> Option<Vec<u8>> {
+        // only the utf-8 content is minified
+        let value = std::str::from_utf8(data).ok()?;
+        let result = match self {
+            MinifyKind::Html => minify_html(value),
+            MinifyKind::Css => minify_css(value),
+            MinifyKind::Js => minify_js(value),
+        };
+        Some(result.into_bytes())
+    }
+}
+
+/// Copies the quoted string(or regex literal) which starts at `start`,
+/// returns the index after the closing quote.
+fn copy_quoted(
+    bytes: &[u8],
+    start: usize,
+    quote: u8,
+    out: &mut Vec<u8>,
+) -> usize {
+    let mut index = start + 1;
+    let mut in_class = false;
+    while index < bytes.len() {
+        let b = bytes[index];
+        if b == b'\\' {
+            index += 2;
+            continue;
+        }
+        // the slash in the character class of regex doesn't end it
+        if quote == b'/' {
+            if b == b'[' {
+                in_class = true;
+            } else if b == b']' {
+                in_class = false;
+            } else if b == b'\n' {
+                break;
+            }
+        }
+        if b == quote && !in_class {
+            index += 1;
+            break;
+        }
+        index += 1;
+    }
+    let end = index.min(bytes.len());
+    out.extend_from_slice(&bytes[start..end]);
+    end
+}
+
+fn find_from(bytes: &[u8], start: usize, pattern: &[u8]) -> Option<usize> {
+    bytes
+        .get(start..)?
+        .windows(pattern.len())
+        .position(|window| window == pattern)
+        .map(|position| position + start)
+}
+
+/// Minifies the css, the comments(except `/*! ... */`) are removed
+/// and the whitespace is collapsed.
+fn minify_css(value: &str) -> String {
+    // the whitespace around these characters can be removed
+    const TRIMMABLE: &[u8] = b"{};,";
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut pending_space = false;
+    let mut index = 0;
+    while index < bytes.len() {
+        let b = bytes[index];
+        if b == b'/' && bytes.get(index + 1) == Some(&b'*') {
+            let end = find_from(bytes, index + 2, b"*/")
+                .map(|end| end + 2)
+                .unwrap_or(bytes.len());
+            // keep the license comment
+            if bytes.get(index + 2) == Some(&b'!') {
+                out.extend_from_slice(&bytes[index..end]);
+            }
+            index = end;
+            continue;
+        }
+        if b.is_ascii_whitespace() {
+            pending_space = true;
+            index += 1;
+            continue;
+        }
+        if pending_space {
+            pending_space = false;
+            if let Some(last) = out.last() {
+                if !TRIMMABLE.contains(last)
+                    && *last != b':'
+                    && !TRIMMABLE.contains(&b)
+                {
+                    out.push(b' ');
+                }
+            }
+        }
+        if b == b'"' || b == b'\'' {
+            index = copy_quoted(bytes, index, b, &mut out);
+            continue;
+        }
+        // the last semicolon of block is unnecessary
+        if b == b'}' && out.last() == Some(&b';') {
+            out.pop();
+        }
+        out.push(b);
+        index += 1;
+    }
+    String::from_utf8(out).unwrap_or_else(|_| value.to_string())
+}
+
+/// Returns true if the slash starts a regex literal instead of division,
+/// it's decided by the last significant output.
+fn is_regex_allowed(out: &[u8]) -> bool {
+    const KEYWORDS: [&[u8]; 8] = [
+        b"return", b"typeof", b"case", b"do", b"else", b"in", b"void", b"throw",
+    ];
+    let Some(last) = out.iter().rev().find(|b| !b.is_ascii_whitespace()) else {
+        return true;
+    };
+    if b"(,=:[!&|?{};+-*%<>~^".contains(last) {
+        return true;
+    }
+    let trimmed = out.trim_ascii_end();
+    KEYWORDS.iter().any(|keyword| {
+        trimmed.ends_with(keyword)
+            && trimmed
+                .len()
+                .checked_sub(keyword.len() + 1)
+                .map(|i| !is_js_ident(trimmed[i]))
+                .unwrap_or(true)
+    })
+}
+
+fn is_js_ident(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'$' || b >= 0x80
+}
+
+/// Minifies the javascript conservatively, the comments(except `/*! ... */`)
+/// and indentation are removed, but the line breaks are kept to avoid
+/// breaking the automatic semicolon insertion.
+fn minify_js(value: &str) -> String {
+    // the whitespace around these characters can be removed
+    const TRIMMABLE: &[u8] = b"{}()[];,=:";
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut pending_space = false;
+    let mut pending_newline = false;
+    let mut index = 0;
+    while index < bytes.len() {
+        let b = bytes[index];
+        let next = bytes.get(index + 1).copied();
+        if b == b'/' && next == Some(b'/') {
+            index = find_from(bytes, index, b"\n").unwrap_or(bytes.len());
+            continue;
+        }
+        if b == b'/' && next == Some(b'*') {
+            let end = find_from(bytes, index + 2, b"*/")
+                .map(|end| end + 2)
+                .unwrap_or(bytes.len());
+            if bytes.get(index + 2) == Some(&b'!') {
+                out.extend_from_slice(&bytes[index..end]);
+                pending_newline = true;
+            } else if bytes[index..end].contains(&b'\n') {
+                pending_newline = true;
+            } else {
+                pending_space = true;
+            }
+            index = end;
+            continue;
+        }
+        if b == b'\n' || b == b'\r' {
+            pending_newline = true;
+            index += 1;
+            continue;
+        }
+        if b.is_ascii_whitespace() {
+            pending_space = true;
+            index += 1;
+            continue;
+        }
+        if let Some(&last) = out.last() {
+            if pending_newline && !b"{;,(".contains(&last) {
+                out.push(b'\n');
+            } else if (pending_space || pending_newline)
+                && !TRIMMABLE.contains(&last)
+                && !TRIMMABLE.contains(&b)
+            {
+                out.push(b' ');
+            }
+        }
+        pending_space = false;
+        pending_newline = false;
+        if b == b'"' || b == b'\'' || b == b'`' {
+            index = copy_quoted(bytes, index, b, &mut out);
+            continue;
+        }
+        if b == b'/' && is_regex_allowed(&out) {
+            index = copy_quoted(bytes, index, b, &mut out);
+            continue;
+        }
+        out.push(b);
+        index += 1;
+    }
+    String::from_utf8(out).unwrap_or_else(|_| value.to_string())
+}
+
+/// Finds the raw text element(pre, textarea, script and style) starts at
+/// `index`, returns the tag name.
+fn get_raw_text_tag(lower: &[u8], index: usize) -> Option<&'static str> {
+    ["pre", "textarea", "script", "style"]
+        .into_iter()
+        .find(|tag| {
+            let start = index + 1;
+            let end = start + tag.len();
+            lower.get(start..end) == Some(tag.as_bytes())
+                && lower.get(end).is_some_and(|b| {
+                    b.is_ascii_whitespace() || b"/>".contains(b)
+                })
+        })
+}
+
+/// Minifies the html, the comments(except conditional comments) are removed
+/// and the whitespace is collapsed. The content of pre, textarea and script
+/// is kept as it is, the inline style is minified as css.
+fn minify_html(value: &str) -> String {
+    let bytes = value.as_bytes();
+    // the ascii lowercase keeps the byte positions
+    let lower = value.to_ascii_lowercase();
+    let lower = lower.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut pending_space = false;
+    let mut index = 0;
+    while index < bytes.len() {
+        let b = bytes[index];
+        if bytes[index..].starts_with(b"<!--") {
+            let end = find_from(bytes, index + 4, b"-->")
+                .map(|end| end + 3)
+                .unwrap_or(bytes.len());
+            // keep the conditional comments of ie
+            if bytes[index..].starts_with(b"<!--[if") {
+                if pending_space && !out.is_empty() {
+                    out.push(b' ');
+                }
+                pending_space = false;
+                out.extend_from_slice(&bytes[index..end]);
+            }
+            index = end;
+            continue;
+        }
+        if b.is_ascii_whitespace() {
+            pending_space = true;
+            index += 1;
+            continue;
+        }
+        if pending_space {
+            pending_space = false;
+            if !out.is_empty() {
+                out.push(b' ');
+            }
+        }
+        if b != b'<' {
+            out.push(b);
+            index += 1;
+            continue;
+        }
+        if let Some(tag) = get_raw_text_tag(lower, index) {
+            let close_tag = format!("</{tag}");
+            let end = find_from(lower, index, close_tag.as_bytes())
+                .and_then(|close| find_from(bytes, close, b">"))
+                .map(|end| end + 1)
+                .unwrap_or(bytes.len());
+            let content_start = find_from(bytes, index, b">")
+                .map(|start| start + 1)
+                .unwrap_or(end)
+                .min(end);
+            let content_end = end
+                .checked_sub(close_tag.len() + 1)
+                .filter(|content_end| *content_end >= content_start);
+            match content_end {
+                Some(content_end) if tag == "style" => {
+                    out.extend_from_slice(&bytes[index..content_start]);
+                    if let Ok(css) =
+                        std::str::from_utf8(&bytes[content_start..content_end])
+                    {
+                        out.extend_from_slice(minify_css(css).as_bytes());
+                    }
+                    out.extend_from_slice(&bytes[content_end..end]);
+                },
+                _ => out.extend_from_slice(&bytes[index..end]),
+            }
+            index = end;
+            continue;
+        }
+        // the tag, whitespace is collapsed except in the attribute values
+        let mut tag_space = false;
+        while index < bytes.len() {
+            let b = bytes[index];
+            if b == b'"' || b == b'\'' {
+                if tag_space && out.last() != Some(&b'=') {
+                    out.push(b' ');
+                }
+                tag_space = false;
+                index = copy_quoted(bytes, index, b, &mut out);
+                continue;
+            }
+            index += 1;
+            if b.is_ascii_whitespace() {
+                tag_space = true;
+                continue;
+            }
+            if tag_space && !b"=/>".contains(&b) && out.last() != Some(&b'=') {
+                out.push(b' ');
+            }
+            tag_space = false;
+            out.push(b);
+            if b == b'>' {
+                break;
+            }
+        }
+    }
+    String::from_utf8(out).unwrap_or_else(|_| value.to_string())
+}
+
+/// Buffers the response body and minifies it at the end of stream.
+struct Minifier {
+    kind: MinifyKind,
+    min_size: usize,
+    max_size: usize,
+    buffer: BytesMut,
+    /// The body is too large, it's sent as it is
+    bypass: bool,
+}
+
+impl ModifyResponseBody for Minifier {
+    fn handle(
+        &mut self,
+        _session: &Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+    ) -> pingora::Result<()> {
+        if self.bypass {
+            return Ok(());
+        }
+        if let Some(data) = body {
+            self.buffer.extend(&data[..]);
+            data.clear();
+        }
+        if self.buffer.len() > self.max_size {
+            self.bypass = true;
+            *body = Some(self.buffer.split().freeze());
+            return Ok(());
+        }
+        if !end_of_stream {
+            return Ok(());
+        }
+        let data = self.buffer.split().freeze();
+        let data = if data.len() < self.min_size {
+            data
+        } else {
+            self.kind.minify(&data).map(Bytes::from).unwrap_or(data)
+        };
+        *body = Some(data);
+        Ok(())
+    }
+    fn name(&self) -> String {
+        "minify".to_string()
+    }
+    fn buffering(&self) -> bool {
+        !self.bypass
+    }
+}
+
+/// Minify plugin removes the comments and whitespace of html, css and
+/// javascript responses for bandwidth savings.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// types = ["html", "css", "js"]
+/// # the response smaller than min size isn't minified
+/// min_size = "1KB"
+/// # the response larger than max size is sent as it is
+/// max_size = "1MB"
+/// # skip the already minified file, e.g. app.min.js
+/// skip_minified = true
+/// # skip the dynamic response(no-store, private or set-cookie)
+/// skip_dynamic = true
+/// ```
+pub struct Minify {
+    kinds: Vec<MinifyKind>,
+    min_size: usize,
+    max_size: usize,
+    skip_minified: bool,
+    skip_dynamic: bool,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for Minify {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::Minify.to_string(),
+            message,
+        };
+        let types = get_str_slice_conf(value, "types");
+        let kinds = if types.is_empty() {
+            vec![MinifyKind::Html, MinifyKind::Css, MinifyKind::Js]
+        } else {
+            types
+                .iter()
+                .map(|item| MinifyKind::from_str(item))
+                .collect::<Result<Vec<_>, String>>()
+                .map_err(new_invalid_error)?
+        };
+        let get_size = |key: &str, default_value: usize| -> Result<usize> {
+            let value = get_str_conf(value, key);
+            if value.is_empty() {
+                return Ok(default_value);
+            }
+            ByteSize::from_str(&value)
+                .map(|size| size.as_u64() as usize)
+                .map_err(new_invalid_error)
+        };
+        let min_size = get_size("min_size", DEFAULT_MIN_SIZE)?;
+        let max_size = get_size("max_size", DEFAULT_MAX_SIZE)?;
+        if min_size > max_size {
+            return Err(new_invalid_error(
+                "min size should be less than or equal to max size".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            kinds,
+            min_size,
+            max_size,
+            skip_minified: get_bool_conf(value, "skip_minified"),
+            skip_dynamic: get_bool_conf(value, "skip_dynamic"),
+            hash_value,
+        })
+    }
+}
+
+impl Minify {
+    /// Creates a new minify plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new minify plugin");
+        Self::try_from(params)
+    }
+}
+
+/// Returns true if the response is generated for the request,
+/// it shouldn't be cached by the shared cache.
+fn is_dynamic_response(headers: &http::HeaderMap) -> bool {
+    if headers.contains_key(header::SET_COOKIE) {
+        return true;
+    }
+    headers
+        .get(header::CACHE_CONTROL)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            let value = value.to_lowercase();
+            value.contains("no-store") || value.contains("private")
+        })
+}
+
+#[async_trait]
+impl Plugin for Minify {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Sets up the minifier if the response is html, css or javascript.
+    async fn handle_response(
+        &self,
+        session: &mut Session,
+        ctx: &mut Ctx,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<ResponsePluginResult> {
+        // the compressed or partial content can't be minified
+        if upstream_response.status != StatusCode::OK
+            || upstream_response
+                .headers
+                .contains_key(header::CONTENT_ENCODING)
+        {
+            return Ok(ResponsePluginResult::Unchanged);
+        }
+        let Some(kind) = upstream_response
+            .headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(MinifyKind::from_content_type)
+            .filter(|kind| self.kinds.contains(kind))
+        else {
+            return Ok(ResponsePluginResult::Unchanged);
+        };
+        if self.skip_minified
+            && session.req_header().uri.path().contains(".min.")
+        {
+            return Ok(ResponsePluginResult::Unchanged);
+        }
+        if self.skip_dynamic && is_dynamic_response(&upstream_response.headers)
+        {
+            return Ok(ResponsePluginResult::Unchanged);
+        }
+        let content_length = upstream_response
+            .headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok());
+        if let Some(content_length) = content_length {
+            if content_length < self.min_size || content_length > self.max_size
+            {
+                return Ok(ResponsePluginResult::Unchanged);
+            }
+        }
+        if !ctx.can_buffer_response(&upstream_response.headers)? {
+            return Ok(ResponsePluginResult::Unchanged);
+        }
+
+        upstream_response.remove_header(&header::CONTENT_LENGTH);
+        let chunked = HTTP_HEADER_TRANSFER_CHUNKED.clone();
+        upstream_response.insert_header(chunked.0, chunked.1)?;
+        ctx.add_modify_body_handler(
+            PLUGIN_ID,
+            Box::new(Minifier {
+                kind,
+                min_size: self.min_size,
+                max_size: self.max_size,
+                buffer: BytesMut::new(),
+                bypass: false,
+            }),
+        );
+        Ok(ResponsePluginResult::Modified)
+    }
+
+    fn handle_response_body(
+        &self,
+        session: &mut Session,
+        ctx: &mut Ctx,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+    ) -> pingora::Result<ResponseBodyPluginResult> {
+        let Some(modifier) = ctx.get_modify_body_handler(PLUGIN_ID) else {
+            return Ok(ResponseBodyPluginResult::Unchanged);
+        };
+        modifier.handle(session, body, end_of_stream)?;
+        if end_of_stream {
+            Ok(ResponseBodyPluginResult::FullyReplaced)
+        } else {
+            Ok(ResponseBodyPluginResult::PartialReplaced)
+        }
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory()
+        .register("minify", |params| Ok(Arc::new(Minify::new(params)?)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pretty_assertions::assert_eq;
+
+    fn new_minify(conf: &str) -> Minify {
+        Minify::try_from(&toml::from_str::<PluginConf>(conf).unwrap()).unwrap()
+    }
+
+    fn new_response(content_type: &str) -> ResponseHeader {
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header(header::CONTENT_TYPE, content_type)
+            .unwrap();
+        resp
+    }
+
+    #[test]
+    fn test_minify_params() {
+        let minify = new_minify(
+            r###"
+types = ["html", "css"]
+min_size = "2KB"
+skip_minified = true
+"###,
+        );
+        assert_eq!(vec![MinifyKind::Html, MinifyKind::Css], minify.kinds);
+        assert_eq!(2000, minify.min_size);
+        assert_eq!(DEFAULT_MAX_SIZE, minify.max_size);
+        assert_eq!(true, minify.skip_minified);
+        assert_eq!(false, minify.skip_dynamic);
+
+        let result = Minify::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+types = ["json"]
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin minify invalid, message: minify type(json) should be html, css or js",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_minify_html() {
+        let html = r###"<!DOCTYPE html>
+<html>
+  <head>
+    <!-- the comment is removed -->
+    <!--[if IE]><p>ie</p><![endif]-->
+    <style>
+      body {
+        color: red;
+        margin : 0 auto;
+      }
+    </style>
+    <script>
+      // keep the script
+      var a = 1;
+    </script>
+  </head>
+  <body   class="main   page" >
+    <p>Hello     world</p>
+    <pre>
+  keep   the    pre
+    </pre>
+  </body>
+</html>
+"###;
+        assert_eq!(
+            r###"<!DOCTYPE html> <html> <head> <!--[if IE]><p>ie</p><![endif]--> <style>body{color:red;margin :0 auto}</style> <script>
+      // keep the script
+      var a = 1;
+    </script> </head> <body class="main   page"> <p>Hello world</p> <pre>
+  keep   the    pre
+    </pre> </body> </html>"###,
+            minify_html(html)
+        );
+    }
+
+    #[test]
+    fn test_minify_css() {
+        let css = r###"/*! license */
+/* comment */
+a  >  b ,
+.content::after {
+    content: "a   b";
+    color : #fff ;
+}
+@media screen and (max-width: 600px) {
+  .main { width: calc(100% - 10px); }
+}
+"###;
+        assert_eq!(
+            r###"/*! license */ a > b,.content::after{content:"a   b";color :#fff}@media screen and (max-width:600px){.main{width:calc(100% - 10px)}}"###,
+            minify_css(css)
+        );
+    }
+
+    #[test]
+    fn test_minify_js() {
+        let js = r###"/*! license */
+// comment
+function add(a, b) {
+    /* comment */
+    return a + +b;
+}
+var re = /\/\/ not comment/g;
+var s = "// not comment";
+var t = `multi
+   line`;
+var c = 4 / 2 / 1
+"###;
+        assert_eq!(
+            r###"/*! license */
+function add(a,b){return a + +b;}
+var re=/\/\/ not comment/g;var s="// not comment";var t=`multi
+   line`;var c=4 / 2 / 1"###,
+            minify_js(js)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_minify() {
+        let minify = new_minify(
+            r###"
+min_size = "10B"
+skip_minified = true
+skip_dynamic = true
+"###,
+        );
+        let html = "<html>\n  <body>\n    <!-- comment -->\n    <p>Hello   world</p>\n  </body>\n</html>\n";
+
+        // html is minified
+        let headers = ["Host: pingap"].join("\r\n");
+        let input_header =
+            format!("GET /index.html HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let mut ctx = Ctx::default();
+        let mut resp = new_response("text/html; charset=utf-8");
+        resp.insert_header(header::CONTENT_LENGTH, html.len())
+            .unwrap();
+        let result = minify
+            .handle_response(&mut session, &mut ctx, &mut resp)
+            .await
+            .unwrap();
+        assert_eq!(ResponsePluginResult::Modified, result);
+        assert_eq!(true, resp.headers.get(header::CONTENT_LENGTH).is_none());
+
+        let mut body = Some(Bytes::from(&html[..20]));
+        let result = minify
+            .handle_response_body(&mut session, &mut ctx, &mut body, false)
+            .unwrap();
+        assert_eq!(ResponseBodyPluginResult::PartialReplaced, result);
+        assert_eq!(true, body.unwrap_or_default().is_empty());
+        let mut body = Some(Bytes::from(&html[20..]));
+        let result = minify
+            .handle_response_body(&mut session, &mut ctx, &mut body, true)
+            .unwrap();
+        assert_eq!(ResponseBodyPluginResult::FullyReplaced, result);
+        assert_eq!(
+            "<html> <body> <p>Hello world</p> </body> </html>",
+            std::str::from_utf8(&body.unwrap()).unwrap()
+        );
+
+        // binary and other types are untouched
+        for content_type in
+            ["image/png", "application/json", "application/octet-stream"]
+        {
+            let headers = ["Host: pingap"].join("\r\n");
+            let input_header =
+                format!("GET /index.html HTTP/1.1\r\n{headers}\r\n\r\n");
+            let mut session = test_util::new_session(&input_header).await;
+            let mut ctx = Ctx::default();
+            let mut resp = new_response(content_type);
+            let result = minify
+                .handle_response(&mut session, &mut ctx, &mut resp)
+                .await
+                .unwrap();
+            assert_eq!(ResponsePluginResult::Unchanged, result);
+            let mut body = Some(Bytes::from_static(b"\x89PNG\r\n  \n"));
+            let result = minify
+                .handle_response_body(&mut session, &mut ctx, &mut body, true)
+                .unwrap();
+            assert_eq!(ResponseBodyPluginResult::Unchanged, result);
+            assert_eq!(Some(Bytes::from_static(b"\x89PNG\r\n  \n")), body);
+        }
+
+        // the compressed response is untouched
+        let mut resp = new_response("text/html");
+        resp.insert_header(header::CONTENT_ENCODING, "gzip")
+            .unwrap();
+        let result = minify
+            .handle_response(&mut session, &mut Ctx::default(), &mut resp)
+            .await
+            .unwrap();
+        assert_eq!(ResponsePluginResult::Unchanged, result);
+
+        // the small response is untouched
+        let mut resp = new_response("text/css");
+        resp.insert_header(header::CONTENT_LENGTH, 5).unwrap();
+        let result = minify
+            .handle_response(&mut session, &mut Ctx::default(), &mut resp)
+            .await
+            .unwrap();
+        assert_eq!(ResponsePluginResult::Unchanged, result);
+
+        // the already minified file is skipped
+        let headers = ["Host: pingap"].join("\r\n");
+        let input_header =
+            format!("GET /app.min.js HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = minify
+            .handle_response(
+                &mut session,
+                &mut Ctx::default(),
+                &mut new_response("application/javascript"),
+            )
+            .await
+            .unwrap();
+        assert_eq!(ResponsePluginResult::Unchanged, result);
+
+        // the dynamic response is skipped
+        let headers = ["Host: pingap"].join("\r\n");
+        let input_header =
+            format!("GET /index.html HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let mut resp = new_response("text/html");
+        resp.insert_header(header::CACHE_CONTROL, "private, max-age=0")
+            .unwrap();
+        let result = minify
+            .handle_response(&mut session, &mut Ctx::default(), &mut resp)
+            .await
+            .unwrap();
+        assert_eq!(ResponsePluginResult::Unchanged, result);
+    }
+}
@@ -0,0 +1,622 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_bool_conf, get_duration_conf, get_hash_key, get_plugin_factory,
+    get_str_conf, get_str_slice_conf,
+};
+use async_trait::async_trait;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use ctor::ctor;
+use http::{HeaderName, HeaderValue, StatusCode, header};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, HttpResponse, Plugin, PluginStep, RequestPluginResult,
+    new_internal_error,
+};
+use pingora::protocols::Ssl;
+use pingora::proxy::Session;
+use pingora::tls::hash::MessageDigest;
+use pingora::tls::nid::Nid;
+use pingora::tls::x509::{X509NameRef, X509Ref, X509VerifyResult};
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+/// The field of client certificate, which can be mapped to the claim
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum CertField {
+    /// The common name of subject
+    CommonName,
+    /// The subject alternative names(dns, email, uri and ip)
+    San,
+    /// The organization of subject
+    Organization,
+    /// The serial number in hex
+    Serial,
+    /// The base64url encoded sha256 of the certificate(x5t#S256)
+    Fingerprint,
+}
+
+impl FromStr for CertField {
+    type Err = String;
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "cn" => Ok(CertField::CommonName),
+            "san" => Ok(CertField::San),
+            "organization" => Ok(CertField::Organization),
+            "serial" => Ok(CertField::Serial),
+            "fingerprint" => Ok(CertField::Fingerprint),
+            _ => Err(format!(
+                "field({value}) should be cn, san, organization, serial or fingerprint"
+            )),
+        }
+    }
+}
+
+/// The identity of the verified client certificate
+#[derive(Debug, Clone, Default, PartialEq)]
+struct ClientCertIdentity {
+    common_name: Option<String>,
+    san: Vec<String>,
+    organization: Option<String>,
+    serial: Option<String>,
+    fingerprint: Option<String>,
+}
+
+fn get_name_entry(name: &X509NameRef, nid: Nid) -> Option<String> {
+    name.entries_by_nid(nid)
+        .next()
+        .and_then(|entry| entry.data().as_utf8().ok())
+        .map(|value| value.to_string())
+}
+
+impl ClientCertIdentity {
+    /// Gets the identity from the client certificate.
+    fn from_certificate(cert: &X509Ref) -> Self {
+        let subject = cert.subject_name();
+        let san = cert
+            .subject_alt_names()
+            .map(|names| {
+                names
+                    .iter()
+                    .filter_map(|name| {
+                        if let Some(value) = name
+                            .dnsname()
+                            .or_else(|| name.email())
+                            .or_else(|| name.uri())
+                        {
+                            return Some(value.to_string());
+                        }
+                        let ip = name.ipaddress()?;
+                        let ip = if let Ok(octets) = <[u8; 4]>::try_from(ip) {
+                            IpAddr::from(octets)
+                        } else {
+                            IpAddr::from(<[u8; 16]>::try_from(ip).ok()?)
+                        };
+                        Some(ip.to_string())
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let serial = cert
+            .serial_number()
+            .to_bn()
+            .ok()
+            .and_then(|value| value.to_hex_str().ok())
+            .map(|value| value.to_string());
+        let fingerprint = cert
+            .digest(MessageDigest::sha256())
+            .ok()
+            .map(|value| URL_SAFE_NO_PAD.encode(value));
+        Self {
+            common_name: get_name_entry(subject, Nid::COMMONNAME),
+            san,
+            organization: get_name_entry(subject, Nid::ORGANIZATIONNAME),
+            serial,
+            fingerprint,
+        }
+    }
+    /// Gets the value of certificate field for claim.
+    fn get(&self, field: CertField) -> Option<Value> {
+        let value = match field {
+            CertField::CommonName => self.common_name.clone(),
+            CertField::San => {
+                if self.san.is_empty() {
+                    return None;
+                }
+                return Some(Value::from(self.san.clone()));
+            },
+            CertField::Organization => self.organization.clone(),
+            CertField::Serial => self.serial.clone(),
+            CertField::Fingerprint => self.fingerprint.clone(),
+        };
+        value.map(Value::from)
+    }
+}
+
+/// Gets the identity of client certificate, the certificate of http/1.x
+/// connection should be verified, the http/2 connection only has the
+/// digest of certificate, which is present after successful handshake.
+fn get_client_cert_identity(session: &Session) -> Option<ClientCertIdentity> {
+    if let Some(ssl) = session.stream().and_then(|stream| stream.get_ssl()) {
+        if ssl.verify_result() != X509VerifyResult::OK {
+            return None;
+        }
+        let cert = ssl.peer_certificate()?;
+        return Some(ClientCertIdentity::from_certificate(&cert));
+    }
+    let ssl_digest = session.digest()?.ssl_digest.as_ref()?;
+    if ssl_digest.cert_digest.is_empty() {
+        return None;
+    }
+    Some(ClientCertIdentity {
+        organization: ssl_digest.organization.clone(),
+        serial: ssl_digest.serial_number.clone(),
+        fingerprint: Some(URL_SAFE_NO_PAD.encode(&ssl_digest.cert_digest)),
+        ..Default::default()
+    })
+}
+
+/// MtlsJwt plugin mints a short-lived jwt from the verified client
+/// certificate and forwards it as `Authorization: Bearer <token>`, so the
+/// backends get a uniform identity format instead of the raw certificate.
+/// The authorization header of client is always removed to avoid spoofing.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// secret = "signing-secret"
+/// algorithm = "HS256"  # or HS512
+/// issuer = "pingap"
+/// audience = "backend"
+/// ttl = "60s"
+/// # the field of sub claim: cn, san, organization, serial or fingerprint
+/// subject = "cn"
+/// # the other claims mapped from the certificate fields
+/// claims = ["san:san", "org:organization", "x5t#S256:fingerprint"]
+/// # the header of jwt, default Authorization
+/// header = "Authorization"
+/// # respond 401 if the request has no verified client certificate
+/// required = true
+/// ```
+pub struct MtlsJwt {
+    secret: String,
+    is_hs512: bool,
+    issuer: Option<String>,
+    audience: Option<String>,
+    ttl: Duration,
+    subject: CertField,
+    claims: Vec<(String, CertField)>,
+    header: HeaderName,
+    required: bool,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for MtlsJwt {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_error = |message: String| Error::Invalid {
+            category: PluginCategory::MtlsJwt.to_string(),
+            message,
+        };
+        let secret = get_str_conf(value, "secret");
+        if secret.is_empty() {
+            return Err(new_error("secret can't be empty".to_string()));
+        }
+        let is_hs512 = match get_str_conf(value, "algorithm").as_str() {
+            "" | "HS256" => false,
+            "HS512" => true,
+            algorithm => {
+                return Err(new_error(format!(
+                    "algorithm({algorithm}) should be HS256 or HS512"
+                )));
+            },
+        };
+        let subject = get_str_conf(value, "subject");
+        let subject = if subject.is_empty() {
+            CertField::CommonName
+        } else {
+            CertField::from_str(&subject).map_err(new_error)?
+        };
+        let mut claims = vec![];
+        for item in get_str_slice_conf(value, "claims") {
+            let Some((claim, field)) = item.split_once(':') else {
+                return Err(new_error(format!(
+                    "claim({item}) is invalid, it should be claim:field"
+                )));
+            };
+            let claim = claim.trim();
+            if claim.is_empty() {
+                return Err(new_error(format!(
+                    "claim({item}) is invalid, it should be claim:field"
+                )));
+            }
+            claims.push((
+                claim.to_string(),
+                CertField::from_str(field).map_err(new_error)?,
+            ));
+        }
+        let ttl = get_duration_conf(value, "ttl").unwrap_or(DEFAULT_TTL);
+        if ttl.is_zero() {
+            return Err(new_error("ttl should be greater than 0".to_string()));
+        }
+        let mut header = get_str_conf(value, "header");
+        if header.is_empty() {
+            header = header::AUTHORIZATION.to_string();
+        }
+        let header = HeaderName::from_str(&header).map_err(|e| {
+            new_error(format!("header({header}) is invalid, {e}"))
+        })?;
+        let get_option = |key: &str| {
+            let value = get_str_conf(value, key);
+            if value.is_empty() { None } else { Some(value) }
+        };
+        let required = if value.contains_key("required") {
+            get_bool_conf(value, "required")
+        } else {
+            true
+        };
+
+        Ok(Self {
+            secret,
+            is_hs512,
+            issuer: get_option("issuer"),
+            audience: get_option("audience"),
+            ttl,
+            subject,
+            claims,
+            header,
+            required,
+            hash_value,
+        })
+    }
+}
+
+impl MtlsJwt {
+    /// Creates a new mtls jwt plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new mtls jwt plugin");
+        Self::try_from(params)
+    }
+    /// Signs the jwt of client certificate identity, it's expired
+    /// after the ttl from now.
+    fn sign(&self, identity: &ClientCertIdentity, now: u64) -> String {
+        let mut payload = Map::new();
+        for (claim, field) in &self.claims {
+            if let Some(value) = identity.get(*field) {
+                payload.insert(claim.clone(), value);
+            }
+        }
+        if let Some(value) = identity.get(self.subject) {
+            // the sub claim should be a string
+            let value = match value {
+                Value::Array(values) => {
+                    values.into_iter().next().unwrap_or_default()
+                },
+                value => value,
+            };
+            payload.insert("sub".to_string(), value);
+        }
+        if let Some(issuer) = &self.issuer {
+            payload.insert("iss".to_string(), Value::from(issuer.as_str()));
+        }
+        if let Some(audience) = &self.audience {
+            payload.insert("aud".to_string(), Value::from(audience.as_str()));
+        }
+        payload.insert("iat".to_string(), Value::from(now));
+        payload.insert(
+            "exp".to_string(),
+            Value::from(now + self.ttl.as_secs().max(1)),
+        );
+
+        let alg = if self.is_hs512 { "HS512" } else { "HS256" };
+        // spellchecker:off
+        let header =
+            URL_SAFE_NO_PAD.encode(format!(r#"{{"alg":"{alg}","typ":"JWT"}}"#));
+        // spellchecker:on
+        let payload =
+            URL_SAFE_NO_PAD.encode(Value::Object(payload).to_string());
+        let content = format!("{header}.{payload}");
+        let secret = self.secret.as_bytes();
+        let sign = if self.is_hs512 {
+            URL_SAFE_NO_PAD
+                .encode(hmac_sha512::HMAC::mac(content.as_bytes(), secret))
+        } else {
+            URL_SAFE_NO_PAD
+                .encode(hmac_sha256::HMAC::mac(content.as_bytes(), secret))
+        };
+        format!("{content}.{sign}")
+    }
+}
+
+#[async_trait]
+impl Plugin for MtlsJwt {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Replaces the authorization header with the jwt minted from
+    /// the verified client certificate.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        _ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        session.req_header_mut().remove_header(&self.header);
+        let Some(identity) = get_client_cert_identity(session) else {
+            if !self.required {
+                return Ok(RequestPluginResult::Skipped);
+            }
+            return Ok(RequestPluginResult::Respond(HttpResponse {
+                status: StatusCode::UNAUTHORIZED,
+                body: "verified client certificate is required".into(),
+                ..Default::default()
+            }));
+        };
+        let token = self.sign(&identity, pingap_core::now_sec());
+        let value = HeaderValue::from_str(&format!("Bearer {token}"))
+            .map_err(|e| new_internal_error(500, e))?;
+        session
+            .req_header_mut()
+            .insert_header(self.header.clone(), value)?;
+        Ok(RequestPluginResult::Continue)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory()
+        .register("mtls_jwt", |params| Ok(Arc::new(MtlsJwt::new(params)?)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pingora::proxy::Session;
+    use pingora::tls::asn1::Asn1Time;
+    use pingora::tls::bn::BigNum;
+    use pingora::tls::ec::{EcGroup, EcKey};
+    use pingora::tls::pkey::PKey;
+    use pingora::tls::x509::extension::SubjectAlternativeName;
+    use pingora::tls::x509::{X509, X509NameBuilder};
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    fn new_plugin(conf: &str) -> MtlsJwt {
+        MtlsJwt::new(&toml::from_str::<PluginConf>(conf).unwrap()).unwrap()
+    }
+
+    fn new_client_certificate() -> X509 {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let mut name = X509NameBuilder::new().unwrap();
+        name.append_entry_by_nid(Nid::COMMONNAME, "order-service")
+            .unwrap();
+        name.append_entry_by_nid(Nid::ORGANIZATIONNAME, "pingap")
+            .unwrap();
+        let name = name.build();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        let serial = BigNum::from_u32(0x1234).unwrap().to_asn1_integer();
+        builder.set_serial_number(&serial.unwrap()).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder
+            .set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        builder
+            .set_not_after(&Asn1Time::days_from_now(1).unwrap())
+            .unwrap();
+        let san = SubjectAlternativeName::new()
+            .dns("order.internal")
+            .email("order@pingap.io")
+            .ip("10.0.0.1")
+            .build(&builder.x509v3_context(None, None))
+            .unwrap();
+        builder.append_extension(san).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        builder.build()
+    }
+
+    fn decode_payload(token: &str, secret: &str) -> Value {
+        let (content, sign) = token.rsplit_once('.').unwrap();
+        assert_eq!(
+            URL_SAFE_NO_PAD.encode(hmac_sha256::HMAC::mac(
+                content.as_bytes(),
+                secret.as_bytes()
+            )),
+            sign
+        );
+        let (header, payload) = content.split_once('.').unwrap();
+        assert_eq!(
+            r#"{"alg":"HS256","typ":"JWT"}"#,
+            String::from_utf8(URL_SAFE_NO_PAD.decode(header).unwrap()).unwrap()
+        );
+        serde_json::from_slice(&URL_SAFE_NO_PAD.decode(payload).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_mtls_jwt_params() {
+        let plugin = new_plugin(r#"secret = "123""#);
+        assert_eq!(false, plugin.is_hs512);
+        assert_eq!(CertField::CommonName, plugin.subject);
+        assert_eq!(DEFAULT_TTL, plugin.ttl);
+        assert_eq!("authorization", plugin.header.as_str());
+        assert_eq!(true, plugin.required);
+
+        let result = MtlsJwt::try_from(&PluginConf::default());
+        assert_eq!(
+            "Plugin mtls_jwt invalid, message: secret can't be empty",
+            result.err().unwrap().to_string()
+        );
+
+        let result = MtlsJwt::try_from(
+            &toml::from_str::<PluginConf>(
+                r#"
+secret = "123"
+algorithm = "RS256"
+"#,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin mtls_jwt invalid, message: algorithm(RS256) should be HS256 or HS512",
+            result.err().unwrap().to_string()
+        );
+
+        let result = MtlsJwt::try_from(
+            &toml::from_str::<PluginConf>(
+                r#"
+secret = "123"
+claims = ["email:mail"]
+"#,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin mtls_jwt invalid, message: field(mail) should be cn, san, organization, serial or fingerprint",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_mtls_jwt_sign() {
+        let cert = new_client_certificate();
+        let identity = ClientCertIdentity::from_certificate(&cert);
+        assert_eq!(Some("order-service".to_string()), identity.common_name);
+        assert_eq!(Some("pingap".to_string()), identity.organization);
+        assert_eq!(Some("1234".to_string()), identity.serial);
+        assert_eq!(
+            vec!["order.internal", "order@pingap.io", "10.0.0.1"],
+            identity.san
+        );
+        assert_eq!(
+            URL_SAFE_NO_PAD
+                .encode(cert.digest(MessageDigest::sha256()).unwrap()),
+            identity.fingerprint.clone().unwrap()
+        );
+
+        let plugin = new_plugin(
+            r#"
+secret = "123"
+issuer = "pingap"
+audience = "backend"
+ttl = "30s"
+claims = ["san:san", "org:organization", "x5t#S256:fingerprint"]
+"#,
+        );
+        let token = plugin.sign(&identity, 1700000000);
+        let payload = decode_payload(&token, "123");
+        assert_eq!(
+            serde_json::json!({
+                "sub": "order-service",
+                "iss": "pingap",
+                "aud": "backend",
+                "iat": 1700000000,
+                "exp": 1700000030,
+                "san": ["order.internal", "order@pingap.io", "10.0.0.1"],
+                "org": "pingap",
+                "x5t#S256": identity.fingerprint.clone().unwrap(),
+            }),
+            payload
+        );
+
+        // the first san is used as the subject
+        let plugin = new_plugin(
+            r#"
+secret = "123"
+subject = "san"
+"#,
+        );
+        let payload =
+            decode_payload(&plugin.sign(&identity, 1700000000), "123");
+        assert_eq!(
+            serde_json::json!({
+                "sub": "order.internal",
+                "iat": 1700000000,
+                "exp": 1700000060,
+            }),
+            payload
+        );
+    }
+
+    #[tokio::test]
+    async fn test_mtls_jwt() {
+        let new_session = || async {
+            let mock_io = Builder::new()
+                .read(b"GET / HTTP/1.1\r\nHost: pingap.io\r\nAuthorization: Bearer forged\r\n\r\n")
+                .build();
+            let mut session = Session::new_h1(Box::new(mock_io));
+            session.read_request().await.unwrap();
+            session
+        };
+
+        // the request without client certificate is rejected
+        let plugin = new_plugin(r#"secret = "123""#);
+        let mut session = new_session().await;
+        let result = plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("result is not respond");
+        };
+        assert_eq!(401, resp.status.as_u16());
+
+        // the forged authorization is removed
+        let plugin = new_plugin(
+            r#"
+secret = "123"
+required = false
+"#,
+        );
+        let mut session = new_session().await;
+        let result = plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+        assert_eq!(
+            true,
+            session.req_header().headers.get("Authorization").is_none()
+        );
+    }
+}
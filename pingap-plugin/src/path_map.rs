@@ -0,0 +1,295 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Error, get_bool_conf, get_hash_key, get_plugin_factory};
+use ahash::AHashMap;
+use async_trait::async_trait;
+use ctor::ctor;
+use http::uri::PathAndQuery;
+use http::{HeaderValue, StatusCode, Uri, header};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{Ctx, HttpResponse, Plugin, PluginStep, RequestPluginResult};
+use pingap_core::{HTTP_HEADER_NO_STORE, new_internal_error};
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// PathMap plugin rewrites the request paths by the exact-match mapping
+/// table(old path to new path), it's useful for the migration of urls.
+/// The matched path is rewritten before the request is forwarded to
+/// upstream, or the client is redirected(301) to the new path in redirect
+/// mode, the query is preserved. The unmatched paths pass through.
+///
+/// The large table can be stored in storage and loaded by `includes`,
+/// the storage value is a toml snippet of the `[mapping]` table.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// redirect = false
+///
+/// [mapping]
+/// "/old/about.html" = "/about"
+/// "/blog/2019/pingap" = "/posts/pingap"
+/// ```
+pub struct PathMap {
+    mapping: AHashMap<String, String>,
+    redirect: bool,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for PathMap {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::PathMap.to_string(),
+            message,
+        };
+        let redirect = get_bool_conf(value, "redirect");
+        let table = value
+            .get("mapping")
+            .and_then(|v| v.as_table())
+            .cloned()
+            .unwrap_or_default();
+        let mut mapping = AHashMap::with_capacity(table.len());
+        for (from, to) in table {
+            let to = to.as_str().unwrap_or_default();
+            // the absolute url is only allowed for redirect
+            let valid = to.starts_with('/')
+                || (redirect
+                    && (to.starts_with("http://")
+                        || to.starts_with("https://")));
+            if !from.starts_with('/') || !valid {
+                return Err(new_invalid_error(format!(
+                    "mapping({from} => {to}) is invalid"
+                )));
+            }
+            mapping.insert(from, to.to_string());
+        }
+        if mapping.is_empty() {
+            return Err(new_invalid_error(
+                "mapping can't be empty".to_string(),
+            ));
+        }
+        Ok(Self {
+            mapping,
+            redirect,
+            hash_value,
+        })
+    }
+}
+
+impl PathMap {
+    /// Creates a new path map plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        // only the size is logged, the mapping table may be large
+        debug!(
+            mapping = params
+                .get("mapping")
+                .and_then(|v| v.as_table())
+                .map(|v| v.len()),
+            "new path map plugin"
+        );
+        Self::try_from(params)
+    }
+}
+
+#[async_trait]
+impl Plugin for PathMap {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Rewrites the matched request path to the new path,
+    /// or redirects the client to it in redirect mode.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        _ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let req_header = session.req_header();
+        let Some(path) = self.mapping.get(req_header.uri.path()) else {
+            return Ok(RequestPluginResult::Skipped);
+        };
+        let path_and_query = match req_header.uri.query() {
+            Some(query) => format!("{path}?{query}"),
+            None => path.to_string(),
+        };
+
+        if self.redirect {
+            let location = HeaderValue::from_str(&path_and_query)
+                .map_err(|e| new_internal_error(500, e))?;
+            return Ok(RequestPluginResult::Respond(HttpResponse {
+                status: StatusCode::MOVED_PERMANENTLY,
+                headers: Some(vec![
+                    (header::LOCATION, location),
+                    HTTP_HEADER_NO_STORE.clone(),
+                ]),
+                ..Default::default()
+            }));
+        }
+
+        let mut parts = req_header.uri.clone().into_parts();
+        parts.path_and_query = Some(
+            PathAndQuery::from_str(&path_and_query)
+                .map_err(|e| new_internal_error(500, e))?,
+        );
+        let uri =
+            Uri::from_parts(parts).map_err(|e| new_internal_error(500, e))?;
+        session.req_header_mut().set_uri(uri);
+        Ok(RequestPluginResult::Continue)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory()
+        .register("path_map", |params| Ok(Arc::new(PathMap::new(params)?)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+
+    fn new_plugin(conf: &str) -> PathMap {
+        PathMap::new(&toml::from_str::<PluginConf>(conf).unwrap()).unwrap()
+    }
+
+    async fn handle(
+        plugin: &PathMap,
+        uri: &str,
+    ) -> (RequestPluginResult, Session) {
+        let input_header =
+            format!("GET {uri} HTTP/1.1\r\nHost: pingap.io\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        (result, session)
+    }
+
+    #[test]
+    fn test_path_map_params() {
+        let plugin = new_plugin(
+            r###"
+[mapping]
+"/old" = "/new"
+"###,
+        );
+        assert_eq!(Some(&"/new".to_string()), plugin.mapping.get("/old"));
+        assert_eq!(false, plugin.redirect);
+
+        for (conf, message) in [
+            ("", "mapping can't be empty"),
+            (
+                "[mapping]\n\"old\" = \"/new\"",
+                "mapping(old => /new) is invalid",
+            ),
+            (
+                "[mapping]\n\"/old\" = \"https://pingap.io/new\"",
+                "mapping(/old => https://pingap.io/new) is invalid",
+            ),
+        ] {
+            let result =
+                PathMap::try_from(&toml::from_str::<PluginConf>(conf).unwrap());
+            assert_eq!(
+                format!("Plugin path_map invalid, message: {message}"),
+                result.err().unwrap().to_string()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_path_map() {
+        let plugin = new_plugin(
+            r###"
+[mapping]
+"/old/about.html" = "/about"
+"/blog/2019/pingap" = "/posts/pingap"
+"###,
+        );
+        // the mapped path is rewritten with the query
+        let (result, session) =
+            handle(&plugin, "/old/about.html?lang=en").await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!("/about?lang=en", session.req_header().uri.to_string());
+
+        // the unmapped path is untouched
+        let (result, session) = handle(&plugin, "/old/about").await;
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+        assert_eq!("/old/about", session.req_header().uri.to_string());
+
+        // redirect to the new path
+        let plugin = new_plugin(
+            r###"
+redirect = true
+
+[mapping]
+"/old/about.html" = "/about"
+"/docs" = "https://docs.pingap.io/"
+"###,
+        );
+        for (uri, location) in [
+            ("/old/about.html?lang=en", "/about?lang=en"),
+            ("/docs", "https://docs.pingap.io/"),
+        ] {
+            let (result, _) = handle(&plugin, uri).await;
+            let RequestPluginResult::Respond(resp) = result else {
+                panic!("request should be redirected");
+            };
+            assert_eq!(StatusCode::MOVED_PERMANENTLY, resp.status);
+            assert_eq!(location, resp.headers.unwrap()[0].1.to_str().unwrap());
+        }
+        let (result, _) = handle(&plugin, "/about").await;
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+    }
+
+    #[tokio::test]
+    async fn test_path_map_large_table() {
+        let mut conf = "[mapping]\n".to_string();
+        for i in 0..100_000 {
+            conf.push_str(&format!("\"/old/{i}\" = \"/new/{i}\"\n"));
+        }
+        let plugin = new_plugin(&conf);
+        assert_eq!(100_000, plugin.mapping.len());
+
+        let (result, session) = handle(&plugin, "/old/99999").await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!("/new/99999", session.req_header().uri.to_string());
+
+        let (result, session) = handle(&plugin, "/old/100000").await;
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+        assert_eq!("/old/100000", session.req_header().uri.to_string());
+    }
+}
@@ -0,0 +1,258 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_hash_key, get_plugin_factory, get_str_conf, get_str_slice_conf,
+};
+use async_trait::async_trait;
+use ctor::ctor;
+use http::{Method, StatusCode};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, HttpResponse, Plugin, PluginStep, RequestPluginResult, convert_headers,
+};
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Probe plugin answers the probe requests of uptime monitors directly,
+/// e.g. `OPTIONS *` or `HEAD /`, so they don't hit the upstream.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// # "METHOD path", "*" matches all paths of the method
+/// probes = ["OPTIONS *", "HEAD /"]
+/// body = "ok"
+/// headers = ["Cache-Control: no-store"]
+/// ```
+pub struct Probe {
+    /// The (method, path) of probe requests, `None` path matches all paths
+    probes: Vec<(Method, Option<String>)>,
+    /// The response of probe requests
+    resp: HttpResponse,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for Probe {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::Probe.to_string(),
+            message,
+        };
+        let mut probes = vec![];
+        for item in get_str_slice_conf(value, "probes") {
+            let Some((method, path)) = item.trim().split_once(' ') else {
+                return Err(new_invalid_error(format!(
+                    "probe({item}) should be in `METHOD path` format"
+                )));
+            };
+            let method = Method::from_str(&method.to_uppercase())
+                .map_err(|e| new_invalid_error(e.to_string()))?;
+            let path = path.trim();
+            let path = if path == "*" {
+                None
+            } else {
+                Some(path.to_string())
+            };
+            probes.push((method, path));
+        }
+        if probes.is_empty() {
+            return Err(new_invalid_error("probes can't be empty".to_string()));
+        }
+
+        let headers = get_str_slice_conf(value, "headers");
+        let headers = if headers.is_empty() {
+            None
+        } else {
+            Some(
+                convert_headers(&headers)
+                    .map_err(|e| new_invalid_error(e.to_string()))?,
+            )
+        };
+
+        Ok(Self {
+            probes,
+            resp: HttpResponse {
+                status: StatusCode::OK,
+                body: get_str_conf(value, "body").into(),
+                headers,
+                ..Default::default()
+            },
+            hash_value,
+        })
+    }
+}
+
+impl Probe {
+    /// Creates a new probe plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new probe plugin");
+        Self::try_from(params)
+    }
+
+    /// Returns true if the request matches one of the probes
+    fn is_probe(&self, method: &Method, path: &str) -> bool {
+        self.probes.iter().any(|(probe_method, probe_path)| {
+            probe_method == method
+                && probe_path.as_ref().is_none_or(|value| value == path)
+        })
+    }
+}
+
+#[async_trait]
+impl Plugin for Probe {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Responds the probe request directly, other requests are proxied.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        _ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let req_header = session.req_header();
+        if !self.is_probe(&req_header.method, req_header.uri.path()) {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        Ok(RequestPluginResult::Respond(self.resp.clone()))
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory()
+        .register("probe", |params| Ok(Arc::new(Probe::new(params)?)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_probe_params() {
+        let probe = Probe::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+probes = ["options *", "HEAD /"]
+body = "ok"
+headers = ["Cache-Control: no-store"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            vec![
+                (Method::OPTIONS, None),
+                (Method::HEAD, Some("/".to_string()))
+            ],
+            probe.probes
+        );
+        assert_eq!(b"ok", probe.resp.body.as_ref());
+        assert_eq!(
+            r#"Some([("cache-control", "no-store")])"#,
+            format!("{:?}", probe.resp.headers)
+        );
+
+        let result = Probe::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+probes = ["HEAD"]
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin probe invalid, message: probe(HEAD) should be in `METHOD path` format",
+            result.err().unwrap().to_string()
+        );
+
+        let result = Probe::try_from(&PluginConf::default());
+        assert_eq!(
+            "Plugin probe invalid, message: probes can't be empty",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_probe() {
+        let probe = Probe::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+probes = ["OPTIONS *", "HEAD /"]
+body = "ok"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        // configured probes are answered locally
+        for (method, path) in
+            [("OPTIONS", "*"), ("OPTIONS", "/api"), ("HEAD", "/")]
+        {
+            let mut session = test_util::new_session(&format!(
+                "{method} {path} HTTP/1.1\r\n\r\n"
+            ))
+            .await;
+            let result = probe
+                .handle_request(
+                    PluginStep::Request,
+                    &mut session,
+                    &mut Ctx::default(),
+                )
+                .await
+                .unwrap();
+            let RequestPluginResult::Respond(resp) = result else {
+                panic!("result is not Respond");
+            };
+            assert_eq!(StatusCode::OK, resp.status);
+            assert_eq!(b"ok", resp.body.as_ref());
+        }
+
+        // other requests are proxied
+        for (method, path) in [("HEAD", "/api"), ("GET", "/")] {
+            let mut session = test_util::new_session(&format!(
+                "{method} {path} HTTP/1.1\r\n\r\n"
+            ))
+            .await;
+            let result = probe
+                .handle_request(
+                    PluginStep::Request,
+                    &mut session,
+                    &mut Ctx::default(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(true, result == RequestPluginResult::Skipped);
+        }
+    }
+}
@@ -0,0 +1,284 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_bool_conf, get_hash_key, get_plugin_factory, get_str_slice_conf,
+};
+use async_trait::async_trait;
+use ctor::ctor;
+use glob::Pattern;
+use http::uri::PathAndQuery;
+use http::{HeaderValue, Method, StatusCode, Uri, header};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{Ctx, HttpResponse, Plugin, PluginStep, RequestPluginResult};
+use pingap_core::{HTTP_HEADER_NO_STORE, new_internal_error};
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// QueryStrip plugin removes the query parameters matching the glob
+/// patterns(e.g. `utm_*`) from the request, before the request is
+/// forwarded to upstream and the cache key is computed, so the plugin
+/// should be placed before the cache plugin.
+/// In redirect mode, the `GET` and `HEAD` requests are redirected(301)
+/// to the clean url instead.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// params = ["utm_*", "fbclid", "gclid"]
+/// redirect = false
+/// ```
+pub struct QueryStrip {
+    params: Vec<Pattern>,
+    redirect: bool,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for QueryStrip {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let params = get_str_slice_conf(value, "params")
+            .iter()
+            .map(|param| {
+                Pattern::new(param).map_err(|e| Error::Invalid {
+                    category: PluginCategory::QueryStrip.to_string(),
+                    message: format!("param({param}) is invalid, {e}"),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if params.is_empty() {
+            return Err(Error::Invalid {
+                category: PluginCategory::QueryStrip.to_string(),
+                message: "params can't be empty".to_string(),
+            });
+        }
+        Ok(Self {
+            params,
+            redirect: get_bool_conf(value, "redirect"),
+            hash_value,
+        })
+    }
+}
+
+impl QueryStrip {
+    /// Creates a new query strip plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new query strip plugin");
+        Self::try_from(params)
+    }
+
+    /// Returns the query without the matched params,
+    /// or none if no param is matched.
+    fn strip(&self, query: &str) -> Option<String> {
+        let mut stripped = false;
+        let kept: Vec<&str> = query
+            .split('&')
+            .filter(|pair| {
+                let name = pair.split_once('=').map_or(*pair, |(name, _)| name);
+                let matched = !name.is_empty()
+                    && self.params.iter().any(|param| param.matches(name));
+                stripped |= matched;
+                !matched
+            })
+            .collect();
+        if !stripped {
+            return None;
+        }
+        Some(kept.join("&"))
+    }
+}
+
+#[async_trait]
+impl Plugin for QueryStrip {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Removes the matched query parameters from the request uri,
+    /// or redirects the client to the clean url in redirect mode.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        _ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let req_header = session.req_header();
+        let Some(query) =
+            req_header.uri.query().and_then(|query| self.strip(query))
+        else {
+            return Ok(RequestPluginResult::Skipped);
+        };
+        let path = req_header.uri.path();
+        let path_and_query = if query.is_empty() {
+            path.to_string()
+        } else {
+            format!("{path}?{query}")
+        };
+
+        if self.redirect
+            && [Method::GET, Method::HEAD].contains(&req_header.method)
+        {
+            let location = HeaderValue::from_str(&path_and_query)
+                .map_err(|e| new_internal_error(400, e))?;
+            return Ok(RequestPluginResult::Respond(HttpResponse {
+                status: StatusCode::MOVED_PERMANENTLY,
+                headers: Some(vec![
+                    (header::LOCATION, location),
+                    HTTP_HEADER_NO_STORE.clone(),
+                ]),
+                ..Default::default()
+            }));
+        }
+
+        let mut parts = req_header.uri.clone().into_parts();
+        parts.path_and_query = Some(
+            PathAndQuery::from_str(&path_and_query)
+                .map_err(|e| new_internal_error(400, e))?,
+        );
+        let uri =
+            Uri::from_parts(parts).map_err(|e| new_internal_error(400, e))?;
+        session.req_header_mut().set_uri(uri);
+        Ok(RequestPluginResult::Continue)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("query_strip", |params| {
+        Ok(Arc::new(QueryStrip::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    fn new_plugin(conf: &str) -> QueryStrip {
+        QueryStrip::new(&toml::from_str::<PluginConf>(conf).unwrap()).unwrap()
+    }
+
+    async fn handle(
+        plugin: &QueryStrip,
+        method: &str,
+        uri: &str,
+    ) -> (RequestPluginResult, Session) {
+        let input_header =
+            format!("{method} {uri} HTTP/1.1\r\nHost: pingap.io\r\n\r\n");
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let result = plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        (result, session)
+    }
+
+    #[test]
+    fn test_query_strip_params() {
+        let plugin = new_plugin(r#"params = ["utm_*", "fbclid"]"#);
+        assert_eq!(2, plugin.params.len());
+        assert_eq!(false, plugin.redirect);
+
+        let result = QueryStrip::try_from(&PluginConf::default());
+        assert_eq!(
+            "Plugin query_strip invalid, message: params can't be empty",
+            result.err().unwrap().to_string()
+        );
+
+        let result = QueryStrip::try_from(
+            &toml::from_str::<PluginConf>(r#"params = ["utm_[*"]"#).unwrap(),
+        );
+        assert_eq!(
+            true,
+            result.err().unwrap().to_string().starts_with(
+                "Plugin query_strip invalid, message: param(utm_[*) is invalid"
+            )
+        );
+    }
+
+    #[test]
+    fn test_strip() {
+        let plugin = new_plugin(r#"params = ["utm_*", "fbclid"]"#);
+        assert_eq!(
+            Some("id=1&name=pingap".to_string()),
+            plugin.strip("utm_source=google&id=1&fbclid=abc&name=pingap")
+        );
+        assert_eq!(
+            Some("".to_string()),
+            plugin.strip("utm_source=google&utm_medium=cpc")
+        );
+        assert_eq!(None, plugin.strip("id=1&utm=2&source_utm_id=3"));
+    }
+
+    #[tokio::test]
+    async fn test_query_strip() {
+        let plugin = new_plugin(r#"params = ["utm_*", "fbclid"]"#);
+
+        // matching params are stripped, others are preserved
+        let (result, session) =
+            handle(&plugin, "GET", "/users?utm_source=a&id=1&fbclid=b").await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!("/users?id=1", session.req_header().uri.to_string());
+
+        let (result, session) =
+            handle(&plugin, "GET", "/users?utm_source=a").await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!("/users", session.req_header().uri.to_string());
+
+        let (result, session) = handle(&plugin, "GET", "/users?id=1").await;
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+        assert_eq!("/users?id=1", session.req_header().uri.to_string());
+
+        // redirect to the clean url
+        let plugin = new_plugin(
+            r###"
+params = ["utm_*"]
+redirect = true
+"###,
+        );
+        let (result, _) =
+            handle(&plugin, "GET", "/users?utm_source=a&id=1").await;
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("request should be redirected");
+        };
+        assert_eq!(StatusCode::MOVED_PERMANENTLY, resp.status);
+        assert_eq!("/users?id=1", resp.headers.unwrap()[0].1.to_str().unwrap());
+
+        // the unsafe method is not redirected
+        let (result, session) =
+            handle(&plugin, "POST", "/users?utm_source=a&id=1").await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!("/users?id=1", session.req_header().uri.to_string());
+    }
+}
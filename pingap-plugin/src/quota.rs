@@ -0,0 +1,560 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::limit::LimitTag;
+use super::redis_store::{FailMode, RedisStore, get_fail_mode_conf};
+use super::{
+    Error, get_hash_key, get_int_conf, get_plugin_factory, get_str_conf,
+};
+use ahash::AHashMap;
+use async_trait::async_trait;
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use ctor::ctor;
+use http::{HeaderName, HeaderValue, StatusCode, header};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, HttpResponse, Plugin, PluginStep, RequestPluginResult, get_client_ip,
+    get_cookie_value, get_query_value, get_req_header_value,
+};
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+// sweep the expired counters of memory store when it's larger than the size
+const MEMORY_SWEEP_SIZE: usize = 1024;
+
+static HTTP_HEADER_RATE_LIMIT_LIMIT: HeaderName =
+    HeaderName::from_static("x-ratelimit-limit");
+static HTTP_HEADER_RATE_LIMIT_REMAINING: HeaderName =
+    HeaderName::from_static("x-ratelimit-remaining");
+static HTTP_HEADER_RATE_LIMIT_RESET: HeaderName =
+    HeaderName::from_static("x-ratelimit-reset");
+
+/// The window of quota, it's reset at the start of next window(UTC)
+#[derive(PartialEq, Debug, Clone, Copy)]
+enum QuotaWindow {
+    Daily,
+    Monthly,
+}
+
+impl QuotaWindow {
+    /// Returns the id of window and the timestamp(seconds) it's reset at
+    fn get_period(&self, now: DateTime<Utc>) -> (String, i64) {
+        let (id, next) = match self {
+            QuotaWindow::Daily => (
+                now.format("%Y%m%d").to_string(),
+                now.date_naive().succ_opt(),
+            ),
+            QuotaWindow::Monthly => {
+                let (year, month) = if now.month() == 12 {
+                    (now.year() + 1, 1)
+                } else {
+                    (now.year(), now.month() + 1)
+                };
+                (
+                    now.format("%Y%m").to_string(),
+                    NaiveDate::from_ymd_opt(year, month, 1),
+                )
+            },
+        };
+        let reset_at = next
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map(|date| date.and_utc().timestamp())
+            .unwrap_or_default();
+        (id, reset_at)
+    }
+}
+
+/// Counters of quota in redis, they're shared by all pingap instances
+struct RedisCounter {
+    store: RedisStore,
+    fail_mode: FailMode,
+}
+
+impl RedisCounter {
+    async fn incr(&self, key: &str, reset_at: i64) -> Option<u64> {
+        let pipe = redis::pipe()
+            .atomic()
+            .cmd("INCR")
+            .arg(key)
+            .cmd("EXPIREAT")
+            .arg(key)
+            .arg(reset_at)
+            .ignore()
+            .clone();
+        let (count,): (u64,) = self
+            .store
+            .execute(
+                |mut conn| async move { pipe.query_async(&mut conn).await },
+            )
+            .await?;
+        Some(count)
+    }
+}
+
+type MemoryCounters = Mutex<AHashMap<String, (i64, u64)>>;
+
+/// Storage backend of quota counters
+enum QuotaStore {
+    Memory(MemoryCounters),
+    Redis(RedisCounter),
+}
+
+impl QuotaStore {
+    /// Increments the counter of key which expires at `reset_at`,
+    /// returns the count after incrementing. It's none if redis is
+    /// unavailable, the request is handled by the fail mode.
+    async fn incr(&self, key: &str, reset_at: i64, now: i64) -> Option<u64> {
+        match self {
+            Self::Memory(counters) => {
+                let mut counters = counters.lock().ok()?;
+                if counters.len() >= MEMORY_SWEEP_SIZE {
+                    counters.retain(|_, (value, _)| *value > now);
+                }
+                // the key contains the id of window, so the counter of
+                // next window starts from zero
+                let counter =
+                    counters.entry(key.to_string()).or_insert((reset_at, 0));
+                counter.1 += 1;
+                Some(counter.1)
+            },
+            Self::Redis(store) => store.incr(key, reset_at).await,
+        }
+    }
+}
+
+/// Quota plugin limits the total requests of identity(ip, header, cookie
+/// or query) in a daily or monthly window, the counter is reset at the
+/// start of next window(UTC). The exhausted request is rejected with 429,
+/// and the `X-RateLimit-*` headers tell the quota and when it's reset.
+/// The counters are kept in memory, or shared via redis.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// tag = "ip"            # or "header", "cookie", "query"
+/// key = "X-Api-Key"     # name of header/cookie/query param to use
+/// quota = 1000
+/// window = "daily"      # or "monthly"
+/// # optional, share the counters via redis
+/// store = "redis://127.0.0.1:6379"
+/// timeout = "200ms"
+/// fail_mode = "open"    # or "closed", when redis is unavailable
+/// ```
+pub struct Quota {
+    tag: LimitTag,
+    /// The name of the header/cookie/query parameter used as identity
+    key: String,
+    /// Maximum number of requests in the window
+    quota: u64,
+    window: QuotaWindow,
+    store: QuotaStore,
+    prefix: String,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for Quota {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::Quota.to_string(),
+            message,
+        };
+        let tag = match get_str_conf(value, "tag").as_str() {
+            "cookie" => LimitTag::Cookie,
+            "header" => LimitTag::RequestHeader,
+            "query" => LimitTag::Query,
+            _ => LimitTag::Ip,
+        };
+        let key = get_str_conf(value, "key");
+        if tag != LimitTag::Ip && key.is_empty() {
+            return Err(new_invalid_error(
+                "key is required for header, cookie or query tag".to_string(),
+            ));
+        }
+        let quota = get_int_conf(value, "quota");
+        if quota <= 0 {
+            return Err(new_invalid_error(
+                "quota should be greater than 0".to_string(),
+            ));
+        }
+        let window = match get_str_conf(value, "window").as_str() {
+            "" | "daily" => QuotaWindow::Daily,
+            "monthly" => QuotaWindow::Monthly,
+            window => {
+                return Err(new_invalid_error(format!(
+                    "window({window}) should be daily or monthly"
+                )));
+            },
+        };
+        let store = get_str_conf(value, "store");
+        let store = if store.is_empty() {
+            QuotaStore::Memory(Mutex::new(AHashMap::new()))
+        } else {
+            QuotaStore::Redis(RedisCounter {
+                store: RedisStore::new(value, PluginCategory::Quota)?,
+                fail_mode: get_fail_mode_conf(value, PluginCategory::Quota)?,
+            })
+        };
+
+        Ok(Self {
+            tag,
+            key,
+            quota: quota as u64,
+            window,
+            store,
+            prefix: format!("pingap:quota:{hash_value}:"),
+            hash_value,
+        })
+    }
+}
+
+impl Quota {
+    /// Creates a new quota plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new quota plugin");
+        Self::try_from(params)
+    }
+
+    /// Gets the identity of request, the client ip is used by default
+    fn get_identity(&self, session: &Session, ctx: &mut Ctx) -> String {
+        match self.tag {
+            LimitTag::Query => get_query_value(session.req_header(), &self.key)
+                .unwrap_or_default()
+                .to_string(),
+            LimitTag::RequestHeader => {
+                get_req_header_value(session.req_header(), &self.key)
+                    .unwrap_or_default()
+                    .to_string()
+            },
+            LimitTag::Cookie => {
+                get_cookie_value(session.req_header(), &self.key)
+                    .unwrap_or_default()
+                    .to_string()
+            },
+            LimitTag::Ip => ctx
+                .conn
+                .client_ip
+                .get_or_insert_with(|| get_client_ip(session))
+                .to_string(),
+        }
+    }
+
+    /// Counts the request of identity in the current window, returns the
+    /// count and the timestamp the window is reset at.
+    async fn incr(
+        &self,
+        identity: &str,
+        now: DateTime<Utc>,
+    ) -> (Option<u64>, i64) {
+        let (id, reset_at) = self.window.get_period(now);
+        let key = format!("{}{id}:{identity}", self.prefix);
+        let count = self.store.incr(&key, reset_at, now.timestamp()).await;
+        (count, reset_at)
+    }
+
+    /// Returns true if the request should be allowed when redis is
+    /// unavailable.
+    fn fail_open(&self) -> bool {
+        match &self.store {
+            QuotaStore::Redis(store) => store.fail_mode == FailMode::Open,
+            QuotaStore::Memory(_) => true,
+        }
+    }
+
+    /// New the 429 response with the quota headers
+    fn new_exhausted_response(&self, reset_at: i64, now: i64) -> HttpResponse {
+        let retry_after = (reset_at - now).max(1);
+        HttpResponse {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            body: format!(
+                "Plugin {}, quota {} is exhausted",
+                PluginCategory::Quota,
+                self.quota
+            )
+            .into(),
+            headers: Some(vec![
+                (
+                    HTTP_HEADER_RATE_LIMIT_LIMIT.clone(),
+                    HeaderValue::from(self.quota),
+                ),
+                (
+                    HTTP_HEADER_RATE_LIMIT_REMAINING.clone(),
+                    HeaderValue::from(0),
+                ),
+                (
+                    HTTP_HEADER_RATE_LIMIT_RESET.clone(),
+                    HeaderValue::from(reset_at),
+                ),
+                (header::RETRY_AFTER, HeaderValue::from(retry_after)),
+            ]),
+            ..Default::default()
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for Quota {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Counts the request of identity, returns 429 if the quota of current
+    /// window is exhausted. The request without identity is skipped.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let identity = self.get_identity(session, ctx);
+        if identity.is_empty() {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let now = Utc::now();
+        let (count, reset_at) = self.incr(&identity, now).await;
+        let exhausted = match count {
+            Some(count) => count > self.quota,
+            None => !self.fail_open(),
+        };
+        if !exhausted {
+            return Ok(RequestPluginResult::Continue);
+        }
+        Ok(RequestPluginResult::Respond(
+            self.new_exhausted_response(reset_at, now.timestamp()),
+        ))
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory()
+        .register("quota", |params| Ok(Arc::new(Quota::new(params)?)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use chrono::TimeZone;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pretty_assertions::assert_eq;
+
+    fn new_quota(params: &str) -> Quota {
+        Quota::try_from(&toml::from_str::<PluginConf>(params).unwrap()).unwrap()
+    }
+
+    fn new_time(
+        year: i32,
+        month: u32,
+        day: u32,
+        hour: u32,
+        min: u32,
+        sec: u32,
+    ) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(year, month, day, hour, min, sec)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_quota_params() {
+        let quota = new_quota(
+            r###"
+tag = "header"
+key = "X-Api-Key"
+quota = 100
+window = "monthly"
+"###,
+        );
+        assert_eq!(LimitTag::RequestHeader, quota.tag);
+        assert_eq!("X-Api-Key", quota.key);
+        assert_eq!(100, quota.quota);
+        assert_eq!(QuotaWindow::Monthly, quota.window);
+        assert_eq!(true, matches!(quota.store, QuotaStore::Memory(_)));
+
+        let quota = new_quota(
+            r###"
+quota = 100
+store = "redis://127.0.0.1:6379"
+fail_mode = "closed"
+"###,
+        );
+        assert_eq!(LimitTag::Ip, quota.tag);
+        assert_eq!(QuotaWindow::Daily, quota.window);
+        assert_eq!(true, matches!(quota.store, QuotaStore::Redis(_)));
+        assert_eq!(false, quota.fail_open());
+
+        for (params, message) in [
+            (
+                r###"
+tag = "header"
+quota = 100
+"###,
+                "Plugin quota invalid, message: key is required for header, cookie or query tag",
+            ),
+            (
+                r###"
+quota = 0
+"###,
+                "Plugin quota invalid, message: quota should be greater than 0",
+            ),
+            (
+                r###"
+quota = 100
+window = "weekly"
+"###,
+                "Plugin quota invalid, message: window(weekly) should be daily or monthly",
+            ),
+            (
+                r###"
+quota = 100
+store = "memcached://127.0.0.1:11211"
+"###,
+                "Plugin quota invalid, message: store(memcached://127.0.0.1:11211) should be redis://",
+            ),
+            (
+                r###"
+quota = 100
+store = "rediss://127.0.0.1:6379"
+"###,
+                "Plugin quota invalid, message: store(rediss://127.0.0.1:6379) should be redis://",
+            ),
+            (
+                r###"
+quota = 100
+store = "redis://127.0.0.1:6379"
+fail_mode = "close"
+"###,
+                "Plugin quota invalid, message: fail mode(close) should be open or closed",
+            ),
+        ] {
+            let result =
+                Quota::try_from(&toml::from_str::<PluginConf>(params).unwrap());
+            assert_eq!(message, result.err().unwrap().to_string());
+        }
+    }
+
+    #[test]
+    fn test_quota_window() {
+        let (id, reset_at) =
+            QuotaWindow::Daily.get_period(new_time(2025, 3, 31, 23, 59, 59));
+        assert_eq!("20250331", id);
+        assert_eq!(new_time(2025, 4, 1, 0, 0, 0).timestamp(), reset_at);
+
+        let (id, reset_at) =
+            QuotaWindow::Monthly.get_period(new_time(2025, 2, 14, 8, 0, 0));
+        assert_eq!("202502", id);
+        assert_eq!(new_time(2025, 3, 1, 0, 0, 0).timestamp(), reset_at);
+
+        let (id, reset_at) =
+            QuotaWindow::Monthly.get_period(new_time(2025, 12, 31, 8, 0, 0));
+        assert_eq!("202512", id);
+        assert_eq!(new_time(2026, 1, 1, 0, 0, 0).timestamp(), reset_at);
+    }
+
+    #[tokio::test]
+    async fn test_quota_reset() {
+        let quota = new_quota(
+            r###"
+quota = 2
+"###,
+        );
+        let now = new_time(2025, 3, 31, 23, 59, 0);
+        let reset_at = new_time(2025, 4, 1, 0, 0, 0).timestamp();
+        // the remaining quota decreases
+        assert_eq!((Some(1), reset_at), quota.incr("1.1.1.1", now).await);
+        assert_eq!((Some(2), reset_at), quota.incr("1.1.1.1", now).await);
+        assert_eq!((Some(3), reset_at), quota.incr("1.1.1.1", now).await);
+        // the quota of other identity is independent
+        assert_eq!((Some(1), reset_at), quota.incr("2.2.2.2", now).await);
+
+        // the counter is reset at the window boundary
+        let now = new_time(2025, 4, 1, 0, 0, 0);
+        let reset_at = new_time(2025, 4, 2, 0, 0, 0).timestamp();
+        assert_eq!((Some(1), reset_at), quota.incr("1.1.1.1", now).await);
+    }
+
+    #[tokio::test]
+    async fn test_quota() {
+        let quota = new_quota(
+            r###"
+tag = "header"
+key = "X-Api-Key"
+quota = 1
+"###,
+        );
+        let headers = ["X-Api-Key: abc"].join("\r\n");
+        let input_header = format!("GET /api HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = quota
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+
+        let result = quota
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("the quota should be exhausted");
+        };
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, resp.status);
+        assert_eq!(
+            "Plugin quota, quota 1 is exhausted",
+            std::string::String::from_utf8_lossy(resp.body.as_ref())
+        );
+        let headers = resp.headers.unwrap();
+        assert_eq!("x-ratelimit-limit", headers[0].0.as_str());
+        assert_eq!("1", headers[0].1.to_str().unwrap());
+        assert_eq!("x-ratelimit-remaining", headers[1].0.as_str());
+        assert_eq!("0", headers[1].1.to_str().unwrap());
+        assert_eq!("x-ratelimit-reset", headers[2].0.as_str());
+        assert_eq!(
+            QuotaWindow::Daily.get_period(Utc::now()).1.to_string(),
+            headers[2].1.to_str().unwrap()
+        );
+        assert_eq!("retry-after", headers[3].0.as_str());
+
+        // request without identity is skipped
+        let mut session =
+            test_util::new_session("GET /api HTTP/1.1\r\n\r\n").await;
+        let result = quota
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+    }
+}
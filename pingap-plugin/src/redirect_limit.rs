@@ -0,0 +1,473 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_hash_key, get_int_conf, get_plugin_factory, get_str_conf,
+};
+use async_trait::async_trait;
+use ctor::ctor;
+use http::uri::PathAndQuery;
+use http::{HeaderValue, StatusCode, Uri, header};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, HTTP_HEADER_NO_STORE, HttpResponse, Plugin, PluginStep,
+    RequestPluginResult, ResponsePluginResult, get_host, new_internal_error,
+};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The variable of ctx to save the redirect hops of current request
+pub const REDIRECT_HOPS_VARIABLE: &str = "redirect_hops";
+
+/// Splits the redirect hops from the query, returns the hops
+/// and the query without the hop param.
+fn split_hops(query: &str, param: &str) -> (Option<u32>, String) {
+    let mut hops = None;
+    let kept: Vec<&str> = query
+        .split('&')
+        .filter(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((*pair, ""));
+            if name != param {
+                return true;
+            }
+            hops = value.parse::<u32>().ok();
+            false
+        })
+        .collect();
+    (hops, kept.join("&"))
+}
+
+/// RedirectLimit plugin caps the redirect chains of upstream, the same host
+/// redirects are tagged with a hop counter(query param), so the redirect
+/// loop is broken with `508`(or `502`) when the hops exceed the limit.
+/// The hop param is removed before the request is forwarded to upstream.
+/// If follow is false, the redirects of upstream are not allowed at all,
+/// they are responded with `502`.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// # the max redirects of a chain
+/// max = 10
+/// # the query param of redirect hops
+/// param = "_redirects"
+/// # the status of too many redirects, 508 or 502
+/// status = 508
+/// follow = true
+/// ```
+pub struct RedirectLimit {
+    max: u32,
+    param: String,
+    status: StatusCode,
+    follow: bool,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for RedirectLimit {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::RedirectLimit.to_string(),
+            message,
+        };
+        let max = match get_int_conf(value, "max") {
+            0 => 10,
+            max => u32::try_from(max).map_err(|_| {
+                new_invalid_error(format!("max({max}) is invalid"))
+            })?,
+        };
+        let mut param = get_str_conf(value, "param");
+        if param.is_empty() {
+            param = "_redirects".to_string();
+        }
+        let status = match get_int_conf(value, "status") {
+            0 | 508 => StatusCode::LOOP_DETECTED,
+            502 => StatusCode::BAD_GATEWAY,
+            status => {
+                return Err(new_invalid_error(format!(
+                    "status({status}) should be 508 or 502"
+                )));
+            },
+        };
+        let follow = value
+            .get("follow")
+            .and_then(|v| v.as_bool())
+            .unwrap_or(true);
+
+        Ok(Self {
+            max,
+            param,
+            status,
+            follow,
+            hash_value,
+        })
+    }
+}
+
+impl RedirectLimit {
+    /// Creates a new redirect limit plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new redirect limit plugin");
+        Self::try_from(params)
+    }
+
+    /// Returns the location with the hop param if it's redirected
+    /// to the same host, otherwise none.
+    fn tag_location(
+        &self,
+        location: &str,
+        host: Option<&str>,
+        hops: u32,
+    ) -> Option<String> {
+        let same_host = if location.starts_with('/') {
+            !location.starts_with("//")
+        } else {
+            let uri = Uri::from_str(location).ok()?;
+            uri.host().is_some() && uri.host() == host
+        };
+        if !same_host {
+            return None;
+        }
+        let (location, fragment) = location
+            .split_once('#')
+            .map_or((location, None), |(value, fragment)| {
+                (value, Some(fragment))
+            });
+        let (path, query) = location.split_once('?').unwrap_or((location, ""));
+        let (_, query) = split_hops(query, &self.param);
+        let mut value = if query.is_empty() {
+            format!("{path}?{}={hops}", self.param)
+        } else {
+            format!("{path}?{query}&{}={hops}", self.param)
+        };
+        if let Some(fragment) = fragment {
+            value.push('#');
+            value.push_str(fragment);
+        }
+        Some(value)
+    }
+}
+
+#[async_trait]
+impl Plugin for RedirectLimit {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Reads and removes the hop param of request, the request is rejected
+    /// if the hops of redirect chain exceed the limit.
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request || !self.follow {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let req_header = session.req_header();
+        let Some(query) = req_header.uri.query() else {
+            return Ok(RequestPluginResult::Skipped);
+        };
+        let (Some(hops), query) = split_hops(query, &self.param) else {
+            return Ok(RequestPluginResult::Skipped);
+        };
+        if hops > self.max {
+            warn!(
+                path = req_header.uri.path(),
+                referer = req_header
+                    .headers
+                    .get(header::REFERER)
+                    .and_then(|value| value.to_str().ok()),
+                hops,
+                max = self.max,
+                "redirect chain is too long"
+            );
+            return Ok(RequestPluginResult::Respond(HttpResponse {
+                status: self.status,
+                headers: Some(vec![HTTP_HEADER_NO_STORE.clone()]),
+                body: "Too many redirects".into(),
+                ..Default::default()
+            }));
+        }
+        ctx.add_variable(REDIRECT_HOPS_VARIABLE, &hops.to_string());
+
+        let path = req_header.uri.path();
+        let path_and_query = if query.is_empty() {
+            path.to_string()
+        } else {
+            format!("{path}?{query}")
+        };
+        let mut parts = req_header.uri.clone().into_parts();
+        parts.path_and_query = Some(
+            PathAndQuery::from_str(&path_and_query)
+                .map_err(|e| new_internal_error(400, e))?,
+        );
+        let uri =
+            Uri::from_parts(parts).map_err(|e| new_internal_error(400, e))?;
+        session.req_header_mut().set_uri(uri);
+        Ok(RequestPluginResult::Continue)
+    }
+
+    /// Tags the same host redirect of upstream with the next hops,
+    /// or rejects the redirect if follow is false.
+    async fn handle_response(
+        &self,
+        session: &mut Session,
+        ctx: &mut Ctx,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<ResponsePluginResult> {
+        if !upstream_response.status.is_redirection() {
+            return Ok(ResponsePluginResult::Unchanged);
+        }
+        let Some(location) = upstream_response
+            .headers
+            .get(header::LOCATION)
+            .and_then(|value| value.to_str().ok())
+        else {
+            return Ok(ResponsePluginResult::Unchanged);
+        };
+        let req_header = session.req_header();
+        if !self.follow {
+            warn!(
+                path = req_header.uri.path(),
+                location, "redirect of upstream is not allowed"
+            );
+            let _ = upstream_response.set_status(StatusCode::BAD_GATEWAY);
+            upstream_response.remove_header(&header::LOCATION);
+            return Ok(ResponsePluginResult::Modified);
+        }
+        let hops = ctx
+            .get_variable(REDIRECT_HOPS_VARIABLE)
+            .and_then(|value| value.parse::<u32>().ok())
+            .unwrap_or_default();
+        let Some(location) =
+            self.tag_location(location, get_host(req_header), hops + 1)
+        else {
+            return Ok(ResponsePluginResult::Unchanged);
+        };
+        let location = HeaderValue::from_str(&location)
+            .map_err(|e| new_internal_error(500, e))?;
+        let _ = upstream_response.insert_header(header::LOCATION, location);
+        Ok(ResponsePluginResult::Modified)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("redirect_limit", |params| {
+        Ok(Arc::new(RedirectLimit::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pretty_assertions::assert_eq;
+
+    fn new_plugin(conf: &str) -> RedirectLimit {
+        RedirectLimit::new(&toml::from_str::<PluginConf>(conf).unwrap())
+            .unwrap()
+    }
+
+    /// Requests the uri, and returns the location of upstream redirect
+    /// or the status of the response plugin.
+    async fn follow(
+        plugin: &RedirectLimit,
+        uri: &str,
+        location: &str,
+    ) -> std::result::Result<String, StatusCode> {
+        let headers = ["Host: pingap.io"].join("\r\n");
+        let input_header = format!("GET {uri} HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let mut ctx = Ctx::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        if let RequestPluginResult::Respond(resp) = result {
+            return Err(resp.status);
+        }
+        let mut upstream_response =
+            ResponseHeader::build(StatusCode::FOUND, None).unwrap();
+        upstream_response
+            .insert_header(header::LOCATION, location)
+            .unwrap();
+        plugin
+            .handle_response(&mut session, &mut ctx, &mut upstream_response)
+            .await
+            .unwrap();
+        Ok(upstream_response
+            .headers
+            .get(header::LOCATION)
+            .map(|value| value.to_str().unwrap().to_string())
+            .unwrap_or_default())
+    }
+
+    #[test]
+    fn test_redirect_limit_params() {
+        let plugin = new_plugin("");
+        assert_eq!(10, plugin.max);
+        assert_eq!("_redirects", plugin.param);
+        assert_eq!(StatusCode::LOOP_DETECTED, plugin.status);
+        assert_eq!(true, plugin.follow);
+
+        let plugin = new_plugin(
+            r###"
+max = 3
+param = "hops"
+status = 502
+follow = false
+"###,
+        );
+        assert_eq!(3, plugin.max);
+        assert_eq!("hops", plugin.param);
+        assert_eq!(StatusCode::BAD_GATEWAY, plugin.status);
+        assert_eq!(false, plugin.follow);
+
+        for (conf, message) in [
+            ("max = -1", "max(-1) is invalid"),
+            ("status = 500", "status(500) should be 508 or 502"),
+        ] {
+            let result = RedirectLimit::try_from(
+                &toml::from_str::<PluginConf>(conf).unwrap(),
+            );
+            assert_eq!(
+                format!("Plugin redirect_limit invalid, message: {message}"),
+                result.err().unwrap().to_string()
+            );
+        }
+    }
+
+    #[test]
+    fn test_tag_location() {
+        let plugin = new_plugin("");
+        assert_eq!(
+            Some("/login?_redirects=1".to_string()),
+            plugin.tag_location("/login", Some("pingap.io"), 1)
+        );
+        assert_eq!(
+            Some("/login?a=1&_redirects=3#top".to_string()),
+            plugin.tag_location(
+                "/login?_redirects=2&a=1#top",
+                Some("pingap.io"),
+                3
+            )
+        );
+        assert_eq!(
+            Some("https://pingap.io/login?_redirects=2".to_string()),
+            plugin.tag_location(
+                "https://pingap.io/login",
+                Some("pingap.io"),
+                2
+            )
+        );
+        // the redirects to other hosts are not tagged
+        assert_eq!(
+            None,
+            plugin.tag_location(
+                "https://github.com/login",
+                Some("pingap.io"),
+                1
+            )
+        );
+        assert_eq!(
+            None,
+            plugin.tag_location("//github.com/login", Some("pingap.io"), 1)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redirect_limit() {
+        let plugin = new_plugin("max = 3");
+
+        // the hop param is removed before forwarding to upstream
+        let headers = ["Host: pingap.io"].join("\r\n");
+        let input_header =
+            format!("GET /a?id=1&_redirects=2 HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let mut ctx = Ctx::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!("/a?id=1", session.req_header().uri.to_string());
+        assert_eq!(Some("2"), ctx.get_variable(REDIRECT_HOPS_VARIABLE));
+
+        // the redirect loop of /a -> /b -> /a is broken at the limit
+        let mut uri = "/a".to_string();
+        let mut chain = vec![];
+        let status = loop {
+            let location = if uri.starts_with("/a") { "/b" } else { "/a" };
+            match follow(&plugin, &uri, location).await {
+                Ok(location) => {
+                    chain.push(location.clone());
+                    uri = location;
+                },
+                Err(err) => break err,
+            }
+        };
+        assert_eq!(
+            vec![
+                "/b?_redirects=1",
+                "/a?_redirects=2",
+                "/b?_redirects=3",
+                "/a?_redirects=4",
+            ],
+            chain
+        );
+        assert_eq!(StatusCode::LOOP_DETECTED, status);
+
+        // the redirect to other host is unchanged
+        assert_eq!(
+            Ok("https://github.com/".to_string()),
+            follow(&plugin, "/a", "https://github.com/").await
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redirect_not_follow() {
+        let plugin = new_plugin("follow = false");
+        let headers = ["Host: pingap.io"].join("\r\n");
+        let input_header = format!("GET /a HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let mut upstream_response =
+            ResponseHeader::build(StatusCode::FOUND, None).unwrap();
+        upstream_response
+            .insert_header(header::LOCATION, "/b")
+            .unwrap();
+        let result = plugin
+            .handle_response(
+                &mut session,
+                &mut Ctx::default(),
+                &mut upstream_response,
+            )
+            .await
+            .unwrap();
+        assert_eq!(ResponsePluginResult::Modified, result);
+        assert_eq!(StatusCode::BAD_GATEWAY, upstream_response.status);
+        assert_eq!(None, upstream_response.headers.get(header::LOCATION));
+    }
+}
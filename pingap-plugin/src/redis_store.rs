@@ -0,0 +1,219 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Error, get_duration_conf, get_str_conf};
+use pingap_config::{PluginCategory, PluginConf};
+use redis::aio::ConnectionManager;
+use std::future::Future;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+use tracing::error;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Behavior of the plugin when redis is unavailable
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum FailMode {
+    /// Allow the request to pass
+    Open,
+    /// Reject the request as if the limit was exceeded
+    Closed,
+}
+
+/// Gets the `fail_mode` of plugin config, it's open by default.
+/// Any other value than "open" or "closed" is rejected, so a typo
+/// doesn't turn off the enforcement when redis is unavailable.
+pub(crate) fn get_fail_mode_conf(
+    value: &PluginConf,
+    category: PluginCategory,
+) -> Result<FailMode> {
+    match get_str_conf(value, "fail_mode").as_str() {
+        "" | "open" => Ok(FailMode::Open),
+        "closed" => Ok(FailMode::Closed),
+        fail_mode => Err(Error::Invalid {
+            category: category.to_string(),
+            message: format!("fail mode({fail_mode}) should be open or closed"),
+        }),
+    }
+}
+
+/// Redis store of plugin, it's shared by all pingap instances.
+/// The connection is created on first use, and every operation
+/// is limited by the timeout.
+pub(crate) struct RedisStore {
+    client: redis::Client,
+    conn: OnceCell<ConnectionManager>,
+    /// Timeout for connecting to and executing commands on redis
+    timeout: Duration,
+    category: PluginCategory,
+}
+
+impl RedisStore {
+    /// Creates the store of the `store` url and `timeout`(200ms by default)
+    /// of plugin config.
+    pub fn new(value: &PluginConf, category: PluginCategory) -> Result<Self> {
+        let store = get_str_conf(value, "store");
+        // redis is built without tls, so `rediss://` is not supported
+        if !store.starts_with("redis://") {
+            return Err(Error::Invalid {
+                category: category.to_string(),
+                message: format!("store({store}) should be redis://"),
+            });
+        }
+        let client = redis::Client::open(store.as_str()).map_err(|e| {
+            Error::Invalid {
+                category: category.to_string(),
+                message: e.to_string(),
+            }
+        })?;
+        Ok(Self {
+            client,
+            conn: OnceCell::new(),
+            timeout: get_duration_conf(value, "timeout")
+                .unwrap_or(Duration::from_millis(200)),
+            category,
+        })
+    }
+    async fn get_conn(&self) -> redis::RedisResult<ConnectionManager> {
+        let conn = self
+            .conn
+            .get_or_try_init(|| ConnectionManager::new(self.client.clone()))
+            .await?;
+        Ok(conn.clone())
+    }
+    /// Executes the operation on the connection within the timeout.
+    /// It's none if redis is unavailable, and the error is logged.
+    pub async fn execute<T, F, Fut>(&self, f: F) -> Option<T>
+    where
+        F: FnOnce(ConnectionManager) -> Fut,
+        Fut: Future<Output = redis::RedisResult<T>>,
+    {
+        let result = tokio::time::timeout(self.timeout, async {
+            let conn = self.get_conn().await?;
+            f(conn).await
+        })
+        .await;
+        match result {
+            Ok(Ok(value)) => Some(value),
+            Ok(Err(e)) => {
+                error!(
+                    category = self.category.to_string(),
+                    error = e.to_string(),
+                    "redis store fail"
+                );
+                None
+            },
+            Err(_) => {
+                error!(
+                    category = self.category.to_string(),
+                    "redis store timeout"
+                );
+                None
+            },
+        }
+    }
+    /// Queries the command within the timeout, see [`Self::execute`].
+    pub async fn query<T: redis::FromRedisValue>(
+        &self,
+        cmd: redis::Cmd,
+    ) -> Option<T> {
+        self.execute(|mut conn| async move { cmd.query_async(&mut conn).await })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn new_conf(params: &str) -> PluginConf {
+        toml::from_str::<PluginConf>(params).unwrap()
+    }
+
+    #[test]
+    fn test_fail_mode_conf() {
+        assert_eq!(
+            FailMode::Open,
+            get_fail_mode_conf(&new_conf(""), PluginCategory::Limit).unwrap()
+        );
+        assert_eq!(
+            FailMode::Closed,
+            get_fail_mode_conf(
+                &new_conf(r#"fail_mode = "closed""#),
+                PluginCategory::Limit
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "Plugin limit invalid, message: fail mode(close) should be open or closed",
+            get_fail_mode_conf(
+                &new_conf(r#"fail_mode = "close""#),
+                PluginCategory::Limit
+            )
+            .err()
+            .unwrap()
+            .to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_redis_store() {
+        let store = RedisStore::new(
+            &new_conf(
+                r#"
+store = "redis://127.0.0.1:6379"
+timeout = "1s"
+"#,
+            ),
+            PluginCategory::Dedup,
+        )
+        .unwrap();
+        assert_eq!(Duration::from_secs(1), store.timeout);
+
+        // redis is built without tls
+        for (params, message) in [
+            (
+                r#"store = "rediss://127.0.0.1:6379""#,
+                "Plugin dedup invalid, message: store(rediss://127.0.0.1:6379) should be redis://",
+            ),
+            (
+                r#"store = "memcached://127.0.0.1:11211""#,
+                "Plugin dedup invalid, message: store(memcached://127.0.0.1:11211) should be redis://",
+            ),
+        ] {
+            let result =
+                RedisStore::new(&new_conf(params), PluginCategory::Dedup);
+            assert_eq!(message, result.err().unwrap().to_string());
+        }
+
+        // nothing listens on port 1, so redis is always unavailable
+        let store = RedisStore::new(
+            &new_conf(
+                r#"
+store = "redis://127.0.0.1:1"
+timeout = "50ms"
+"#,
+            ),
+            PluginCategory::Dedup,
+        )
+        .unwrap();
+        assert_eq!(
+            None,
+            store
+                .query::<Option<String>>(redis::cmd("GET").arg("key").clone())
+                .await
+        );
+    }
+}
@@ -0,0 +1,430 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_duration_conf, get_hash_key, get_int_conf, get_plugin_factory,
+    get_str_conf,
+};
+use ahash::AHashMap;
+use async_trait::async_trait;
+use ctor::ctor;
+use http::{HeaderName, StatusCode};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, HTTP_HEADER_NO_STORE, HttpResponse, Plugin, PluginStep,
+    RequestPluginResult,
+};
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+// the max length of nonce
+const MAX_NONCE_LENGTH: usize = 128;
+
+#[derive(Debug, PartialEq)]
+enum SigningAlgorithm {
+    HmacSha256,
+    HmacSha512,
+}
+
+/// ReplayProtection plugin verifies the HMAC signed requests and rejects
+/// the replays, the string to sign is `METHOD\nPATH?QUERY\nTIMESTAMP\nNONCE`
+/// and the signature is sent in hex format. The timestamp(unix seconds)
+/// should be within the allowed skew, and the nonce can only be used once
+/// within the validity window, the request is rejected with `401` if the
+/// timestamp is stale or the nonce is reused.
+///
+/// The nonces are tracked in a bounded memory cache, the request is rejected
+/// with `503` if the cache is full of the unexpired nonces.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// secret = "my-secret"
+/// # hmac-sha256(default) or hmac-sha512
+/// algorithm = "hmac-sha256"
+/// signature_header = "X-Signature"
+/// timestamp_header = "X-Timestamp"
+/// nonce_header = "X-Nonce"
+/// # the allowed clock skew of timestamp
+/// skew = "5m"
+/// # the max nonces of cache
+/// max_nonces = 100000
+/// ```
+pub struct ReplayProtection {
+    secret: String,
+    algorithm: SigningAlgorithm,
+    signature_header: HeaderName,
+    timestamp_header: HeaderName,
+    nonce_header: HeaderName,
+    skew: i64,
+    max_nonces: usize,
+    /// The used nonces and their expired time(unix seconds)
+    nonces: Mutex<AHashMap<String, i64>>,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for ReplayProtection {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::ReplayProtection.to_string(),
+            message,
+        };
+        let secret = get_str_conf(value, "secret");
+        if secret.is_empty() {
+            return Err(new_invalid_error("secret can't be empty".to_string()));
+        }
+        let algorithm = match get_str_conf(value, "algorithm").as_str() {
+            "" | "hmac-sha256" => SigningAlgorithm::HmacSha256,
+            "hmac-sha512" => SigningAlgorithm::HmacSha512,
+            algorithm => {
+                return Err(new_invalid_error(format!(
+                    "algorithm({algorithm}) is not supported"
+                )));
+            },
+        };
+        let get_header_name = |key: &str, default_value: &str| {
+            let mut name = get_str_conf(value, key);
+            if name.is_empty() {
+                name = default_value.to_string();
+            }
+            HeaderName::from_str(&name).map_err(|e| {
+                new_invalid_error(format!("{key}({name}) is invalid, {e}"))
+            })
+        };
+        let skew = get_duration_conf(value, "skew")
+            .unwrap_or(Duration::from_secs(5 * 60));
+        if skew.is_zero() {
+            return Err(new_invalid_error(
+                "skew should be greater than 0".to_string(),
+            ));
+        }
+        let max_nonces = match get_int_conf(value, "max_nonces") {
+            0 => 100_000,
+            max_nonces => usize::try_from(max_nonces).map_err(|_| {
+                new_invalid_error(format!(
+                    "max nonces({max_nonces}) is invalid"
+                ))
+            })?,
+        };
+
+        Ok(Self {
+            secret,
+            algorithm,
+            signature_header: get_header_name(
+                "signature_header",
+                "X-Signature",
+            )?,
+            timestamp_header: get_header_name(
+                "timestamp_header",
+                "X-Timestamp",
+            )?,
+            nonce_header: get_header_name("nonce_header", "X-Nonce")?,
+            skew: skew.as_secs() as i64,
+            max_nonces,
+            nonces: Mutex::new(AHashMap::new()),
+            hash_value,
+        })
+    }
+}
+
+impl ReplayProtection {
+    /// Creates a new replay protection plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new replay protection plugin");
+        Self::try_from(params)
+    }
+
+    /// Signs the `METHOD\nPATH?QUERY\nTIMESTAMP\nNONCE` by hmac,
+    /// returns the hex signature
+    fn sign(
+        &self,
+        method: &str,
+        path_and_query: &str,
+        timestamp: &str,
+        nonce: &str,
+    ) -> String {
+        let content =
+            format!("{method}\n{path_and_query}\n{timestamp}\n{nonce}");
+        let secret = self.secret.as_bytes();
+        match self.algorithm {
+            SigningAlgorithm::HmacSha256 => {
+                hex::encode(hmac_sha256::HMAC::mac(content.as_bytes(), secret))
+            },
+            SigningAlgorithm::HmacSha512 => {
+                hex::encode(hmac_sha512::HMAC::mac(content.as_bytes(), secret))
+            },
+        }
+    }
+
+    /// Records the nonce until it's expired,
+    /// returns the status and message if the nonce can't be used.
+    fn use_nonce(
+        &self,
+        nonce: &str,
+        expired_at: i64,
+        now: i64,
+    ) -> Option<(StatusCode, &'static str)> {
+        let Ok(mut nonces) = self.nonces.lock() else {
+            return Some((
+                StatusCode::SERVICE_UNAVAILABLE,
+                "Nonce cache is unavailable",
+            ));
+        };
+        if nonces.get(nonce).is_some_and(|value| *value >= now) {
+            return Some((StatusCode::UNAUTHORIZED, "Nonce is reused"));
+        }
+        if nonces.len() >= self.max_nonces {
+            nonces.retain(|_, value| *value >= now);
+            if nonces.len() >= self.max_nonces {
+                return Some((
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "Nonce cache is full",
+                ));
+            }
+        }
+        nonces.insert(nonce.to_string(), expired_at);
+        None
+    }
+
+    /// Verifies the signature, timestamp and nonce of the request,
+    /// returns the status and message if it fails.
+    fn verify(
+        &self,
+        session: &Session,
+        now: i64,
+    ) -> Option<(StatusCode, &'static str)> {
+        let header = session.req_header();
+        let get_value = |name: &HeaderName| {
+            header
+                .headers
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .unwrap_or_default()
+        };
+        let signature = get_value(&self.signature_header);
+        let timestamp = get_value(&self.timestamp_header);
+        let nonce = get_value(&self.nonce_header);
+        if signature.is_empty() || timestamp.is_empty() || nonce.is_empty() {
+            return Some((
+                StatusCode::UNAUTHORIZED,
+                "Signature, timestamp or nonce is missing",
+            ));
+        }
+        if nonce.len() > MAX_NONCE_LENGTH {
+            return Some((StatusCode::UNAUTHORIZED, "Nonce is too long"));
+        }
+        let Ok(value) = timestamp.parse::<i64>() else {
+            return Some((StatusCode::UNAUTHORIZED, "Timestamp is invalid"));
+        };
+        if (now - value).abs() > self.skew {
+            return Some((StatusCode::UNAUTHORIZED, "Timestamp is stale"));
+        }
+        let path_and_query = header
+            .uri
+            .path_and_query()
+            .map_or(header.uri.path(), |value| value.as_str());
+        let expected =
+            self.sign(header.method.as_str(), path_and_query, timestamp, nonce);
+        if !signature.eq_ignore_ascii_case(&expected) {
+            return Some((StatusCode::UNAUTHORIZED, "Signature is invalid"));
+        }
+        // the request is valid until the timestamp exceeds the skew,
+        // so the nonce is kept until then
+        self.use_nonce(nonce, value + self.skew, now)
+    }
+}
+
+#[async_trait]
+impl Plugin for ReplayProtection {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Rejects the request if the signature is invalid,
+    /// the timestamp is stale or the nonce is reused.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        _ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let now = chrono::Utc::now().timestamp();
+        let Some((status, message)) = self.verify(session, now) else {
+            return Ok(RequestPluginResult::Continue);
+        };
+        Ok(RequestPluginResult::Respond(HttpResponse {
+            status,
+            headers: Some(vec![HTTP_HEADER_NO_STORE.clone()]),
+            body: message.into(),
+            ..Default::default()
+        }))
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("replay_protection", |params| {
+        Ok(Arc::new(ReplayProtection::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pretty_assertions::assert_eq;
+
+    fn new_plugin(conf: &str) -> ReplayProtection {
+        ReplayProtection::new(&toml::from_str::<PluginConf>(conf).unwrap())
+            .unwrap()
+    }
+
+    async fn verify(
+        plugin: &ReplayProtection,
+        timestamp: i64,
+        nonce: &str,
+        now: i64,
+    ) -> Option<(StatusCode, &'static str)> {
+        let timestamp = timestamp.to_string();
+        let signature =
+            plugin.sign("GET", "/api/users?id=1", &timestamp, nonce);
+        let input_header = format!(
+            "GET /api/users?id=1 HTTP/1.1\r\nX-Signature: {signature}\r\nX-Timestamp: {timestamp}\r\nX-Nonce: {nonce}\r\n\r\n"
+        );
+        let session = test_util::new_session(&input_header).await;
+        plugin.verify(&session, now)
+    }
+
+    #[test]
+    fn test_replay_protection_params() {
+        let plugin = new_plugin(r#"secret = "secret""#);
+        assert_eq!(SigningAlgorithm::HmacSha256, plugin.algorithm);
+        assert_eq!("x-signature", plugin.signature_header.as_str());
+        assert_eq!("x-timestamp", plugin.timestamp_header.as_str());
+        assert_eq!("x-nonce", plugin.nonce_header.as_str());
+        assert_eq!(300, plugin.skew);
+        assert_eq!(100_000, plugin.max_nonces);
+
+        for (conf, message) in [
+            ("", "secret can't be empty"),
+            (
+                "secret = \"secret\"\nalgorithm = \"md5\"",
+                "algorithm(md5) is not supported",
+            ),
+            (
+                "secret = \"secret\"\nmax_nonces = -1",
+                "max nonces(-1) is invalid",
+            ),
+        ] {
+            let result = ReplayProtection::try_from(
+                &toml::from_str::<PluginConf>(conf).unwrap(),
+            );
+            assert_eq!(
+                format!("Plugin replay_protection invalid, message: {message}"),
+                result.err().unwrap().to_string()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replay_protection() {
+        let plugin = new_plugin(
+            r###"
+secret = "secret"
+skew = "60s"
+"###,
+        );
+        let now = 1_700_000_000;
+        assert_eq!(None, verify(&plugin, now, "nonce1", now).await);
+        // the replayed nonce is rejected
+        assert_eq!(
+            Some((StatusCode::UNAUTHORIZED, "Nonce is reused")),
+            verify(&plugin, now, "nonce1", now + 10).await
+        );
+        assert_eq!(None, verify(&plugin, now, "nonce2", now + 10).await);
+
+        // the stale timestamp fails
+        assert_eq!(
+            Some((StatusCode::UNAUTHORIZED, "Timestamp is stale")),
+            verify(&plugin, now - 61, "nonce3", now).await
+        );
+        assert_eq!(
+            Some((StatusCode::UNAUTHORIZED, "Timestamp is stale")),
+            verify(&plugin, now + 61, "nonce3", now).await
+        );
+        // the replay after the validity window is stale
+        assert_eq!(
+            Some((StatusCode::UNAUTHORIZED, "Timestamp is stale")),
+            verify(&plugin, now, "nonce1", now + 61).await
+        );
+
+        // the forged signature is rejected
+        let input_header = format!(
+            "GET /api/users?id=1 HTTP/1.1\r\nX-Signature: abc\r\nX-Timestamp: {now}\r\nX-Nonce: nonce4\r\n\r\n"
+        );
+        let mut session = test_util::new_session(&input_header).await;
+        assert_eq!(
+            Some((StatusCode::UNAUTHORIZED, "Signature is invalid")),
+            plugin.verify(&session, now)
+        );
+
+        let result = plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("result is not Respond");
+        };
+        assert_eq!(StatusCode::UNAUTHORIZED, resp.status);
+    }
+
+    #[tokio::test]
+    async fn test_replay_protection_nonce_cache() {
+        let plugin = new_plugin(
+            r###"
+secret = "secret"
+skew = "60s"
+max_nonces = 2
+"###,
+        );
+        let now = 1_700_000_000;
+        assert_eq!(None, verify(&plugin, now, "nonce1", now).await);
+        assert_eq!(None, verify(&plugin, now, "nonce2", now).await);
+        // the cache is full of unexpired nonces
+        assert_eq!(
+            Some((StatusCode::SERVICE_UNAVAILABLE, "Nonce cache is full")),
+            verify(&plugin, now, "nonce3", now).await
+        );
+        // the expired nonces are swept
+        assert_eq!(None, verify(&plugin, now + 61, "nonce3", now + 61).await);
+        assert_eq!(1, plugin.nonces.lock().unwrap().len());
+    }
+}
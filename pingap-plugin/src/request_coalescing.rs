@@ -0,0 +1,512 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_duration_conf, get_hash_key, get_plugin_factory, get_str_conf,
+    get_str_slice_conf,
+};
+use ahash::AHashMap;
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use bytesize::ByteSize;
+use ctor::ctor;
+use http::{Method, header};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, HttpResponse, ModifyResponseBody, Plugin, PluginStep,
+    RequestPluginResult, ResponseBodyPluginResult, ResponsePluginResult,
+    get_cache_key,
+};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+const PLUGIN_ID: &str = "_request_coalescing_";
+
+const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+
+#[derive(Clone, Default)]
+enum FlightState {
+    #[default]
+    Pending,
+    /// The shared response, it's none if the response can't be shared
+    Done(Option<HttpResponse>),
+}
+
+/// The upstream fetch of the first request, the identical requests wait
+/// for it and share its response.
+struct Flight {
+    created_at: Instant,
+    sender: watch::Sender<FlightState>,
+    header: Mutex<Option<ResponseHeader>>,
+}
+
+type Flights = Arc<Mutex<AHashMap<String, Arc<Flight>>>>;
+
+/// Collects the response body of the first request, the response is
+/// shared to the waiting requests at the end of stream. If the request is
+/// dropped before the end, the waiting requests are forwarded to upstream.
+struct FlightLeader {
+    key: String,
+    flight: Arc<Flight>,
+    flights: Flights,
+    body: BytesMut,
+    max_body_size: usize,
+    too_large: bool,
+    done: bool,
+}
+
+impl FlightLeader {
+    /// Removes the flight and notifies the waiting requests, the response
+    /// isn't shared if the request is not completed.
+    fn finish(&mut self, completed: bool) {
+        if self.done {
+            return;
+        }
+        self.done = true;
+        if let Ok(mut flights) = self.flights.lock() {
+            if flights
+                .get(&self.key)
+                .is_some_and(|flight| Arc::ptr_eq(flight, &self.flight))
+            {
+                flights.remove(&self.key);
+            }
+        }
+        let header = self
+            .flight
+            .header
+            .lock()
+            .ok()
+            .and_then(|mut header| header.take());
+        let response =
+            header
+                .filter(|_| completed && !self.too_large)
+                .map(|header| {
+                    new_shared_response(&header, self.body.split().freeze())
+                });
+        self.flight.sender.send_replace(FlightState::Done(response));
+    }
+}
+
+impl ModifyResponseBody for FlightLeader {
+    fn handle(
+        &mut self,
+        _session: &Session,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+    ) -> pingora::Result<()> {
+        if let Some(data) = body {
+            if self.body.len() + data.len() > self.max_body_size {
+                self.too_large = true;
+                self.body.clear();
+            } else if !self.too_large {
+                self.body.extend_from_slice(data);
+            }
+        }
+        if end_of_stream {
+            self.finish(true);
+        }
+        Ok(())
+    }
+    fn name(&self) -> String {
+        "request_coalescing".to_string()
+    }
+}
+
+impl Drop for FlightLeader {
+    fn drop(&mut self) {
+        self.finish(false);
+    }
+}
+
+/// Creates the response shared to the waiting requests, the hop-by-hop
+/// and length headers are not copied.
+fn new_shared_response(header: &ResponseHeader, body: Bytes) -> HttpResponse {
+    let headers = header
+        .headers
+        .iter()
+        .filter(|(name, _)| {
+            ![
+                header::CONNECTION,
+                header::TRANSFER_ENCODING,
+                header::CONTENT_LENGTH,
+                header::UPGRADE,
+            ]
+            .contains(name)
+        })
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    HttpResponse {
+        status: header.status,
+        body,
+        headers: Some(headers),
+        ..Default::default()
+    }
+}
+
+/// RequestCoalescing plugin deduplicates the concurrent identical GET
+/// requests(single-flight). The first request is forwarded to upstream,
+/// and the identical requests wait for it and share its response, which
+/// avoids the thundering herd of the same resource. The requests are keyed
+/// like the cache(namespace, cache keys, method and uri), and the values
+/// of configured headers are appended to the key.
+/// The 5xx response, the response with `Set-Cookie` or the response body
+/// exceeding `max_body_size` is not shared, the waiting requests are
+/// forwarded to upstream as well as the requests waiting over `wait_timeout`.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// # the request headers appended to the key
+/// headers = ["Accept-Encoding"]
+/// wait_timeout = "10s"
+/// max_body_size = "1MB"
+/// ```
+pub struct RequestCoalescing {
+    headers: Vec<String>,
+    wait_timeout: Duration,
+    max_body_size: usize,
+    flights: Flights,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for RequestCoalescing {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let max_body_size = get_str_conf(value, "max_body_size");
+        let max_body_size = if max_body_size.is_empty() {
+            DEFAULT_MAX_BODY_SIZE
+        } else {
+            ByteSize::from_str(&max_body_size)
+                .map_err(|message| Error::Invalid {
+                    category: PluginCategory::RequestCoalescing.to_string(),
+                    message,
+                })?
+                .as_u64() as usize
+        };
+        Ok(Self {
+            headers: get_str_slice_conf(value, "headers"),
+            wait_timeout: get_duration_conf(value, "wait_timeout")
+                .unwrap_or(Duration::from_secs(10)),
+            max_body_size,
+            flights: Arc::new(Mutex::new(AHashMap::new())),
+            hash_value,
+        })
+    }
+}
+
+impl RequestCoalescing {
+    /// Creates a new request coalescing plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new request coalescing plugin");
+        Self::try_from(params)
+    }
+
+    /// Gets the key of request, it's the cache key with the header values
+    fn get_key(&self, session: &Session, ctx: &Ctx) -> String {
+        let req_header = session.req_header();
+        let mut key =
+            get_cache_key(ctx, req_header.method.as_str(), &req_header.uri)
+                .combined();
+        for name in self.headers.iter() {
+            key.push(':');
+            key.push_str(&session.get_header_bytes(name).to_str_lossy());
+        }
+        key
+    }
+}
+
+#[async_trait]
+impl Plugin for RequestCoalescing {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Waits for the in-flight identical request and responds its response,
+    /// or the request becomes the first one forwarded to upstream.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request
+            || session.req_header().method != Method::GET
+        {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let key = self.get_key(session, ctx);
+        let receiver = {
+            let Ok(mut flights) = self.flights.lock() else {
+                return Ok(RequestPluginResult::Skipped);
+            };
+            match flights.get(&key) {
+                // the expired flight is replaced by the current request
+                Some(flight)
+                    if flight.created_at.elapsed() < self.wait_timeout =>
+                {
+                    Some(flight.sender.subscribe())
+                },
+                _ => {
+                    let (sender, _) = watch::channel(FlightState::Pending);
+                    let flight = Arc::new(Flight {
+                        created_at: Instant::now(),
+                        sender,
+                        header: Mutex::new(None),
+                    });
+                    flights.insert(key.clone(), flight.clone());
+                    ctx.add_modify_body_handler(
+                        PLUGIN_ID,
+                        Box::new(FlightLeader {
+                            key: key.clone(),
+                            flight,
+                            flights: self.flights.clone(),
+                            body: BytesMut::new(),
+                            max_body_size: self.max_body_size,
+                            too_large: false,
+                            done: false,
+                        }),
+                    );
+                    None
+                },
+            }
+        };
+        let Some(mut receiver) = receiver else {
+            return Ok(RequestPluginResult::Continue);
+        };
+        let result = tokio::time::timeout(
+            self.wait_timeout,
+            receiver.wait_for(|state| matches!(state, FlightState::Done(_))),
+        )
+        .await;
+        if let Ok(Ok(state)) = result {
+            if let FlightState::Done(Some(resp)) = state.clone() {
+                debug!(key, "share the response of in-flight request");
+                return Ok(RequestPluginResult::Respond(resp));
+            }
+        }
+        // the response can't be shared, forward the request to upstream
+        Ok(RequestPluginResult::Skipped)
+    }
+
+    /// Records the response header of the first request if it can be shared
+    #[inline]
+    async fn handle_response(
+        &self,
+        session: &mut Session,
+        ctx: &mut Ctx,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<ResponsePluginResult> {
+        if ctx.get_modify_body_handler(PLUGIN_ID).is_none()
+            || upstream_response.status.is_server_error()
+            || upstream_response.headers.contains_key(header::SET_COOKIE)
+        {
+            return Ok(ResponsePluginResult::Unchanged);
+        }
+        let key = self.get_key(session, ctx);
+        let flight = self
+            .flights
+            .lock()
+            .ok()
+            .and_then(|flights| flights.get(&key).cloned());
+        if let Some(flight) = flight {
+            if let Ok(mut header) = flight.header.lock() {
+                *header = Some(upstream_response.clone());
+            }
+        }
+        Ok(ResponsePluginResult::Unchanged)
+    }
+
+    /// Collects the response body of the first request
+    #[inline]
+    fn handle_response_body(
+        &self,
+        session: &mut Session,
+        ctx: &mut Ctx,
+        body: &mut Option<Bytes>,
+        end_of_stream: bool,
+    ) -> pingora::Result<ResponseBodyPluginResult> {
+        if let Some(leader) = ctx.get_modify_body_handler(PLUGIN_ID) {
+            leader.handle(session, body, end_of_stream)?;
+        }
+        Ok(ResponseBodyPluginResult::Unchanged)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("request_coalescing", |params| {
+        Ok(Arc::new(RequestCoalescing::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use http::StatusCode;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Sends the request, the request forwarded to upstream gets the
+    /// response of upstream after a while.
+    async fn send_request(
+        plugin: Arc<RequestCoalescing>,
+        upstream_calls: Arc<AtomicUsize>,
+        status: u16,
+    ) -> (StatusCode, Bytes) {
+        let headers = ["Host: pingap.io"].join("\r\n");
+        let input_header =
+            format!("GET /assets/app.js HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let mut ctx = Ctx::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        if let RequestPluginResult::Respond(resp) = result {
+            return (resp.status, resp.body);
+        }
+        upstream_calls.fetch_add(1, Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let mut upstream_response =
+            ResponseHeader::build(status, None).unwrap();
+        upstream_response
+            .insert_header(header::CONTENT_TYPE, "application/javascript")
+            .unwrap();
+        plugin
+            .handle_response(&mut session, &mut ctx, &mut upstream_response)
+            .await
+            .unwrap();
+        let body = Bytes::from_static(b"console.log('pingap')");
+        plugin
+            .handle_response_body(
+                &mut session,
+                &mut ctx,
+                &mut Some(body.clone()),
+                true,
+            )
+            .unwrap();
+        (upstream_response.status, body)
+    }
+
+    #[test]
+    fn test_request_coalescing_params() {
+        let plugin =
+            RequestCoalescing::try_from(&PluginConf::default()).unwrap();
+        assert_eq!(true, plugin.headers.is_empty());
+        assert_eq!(Duration::from_secs(10), plugin.wait_timeout);
+        assert_eq!(DEFAULT_MAX_BODY_SIZE, plugin.max_body_size);
+
+        let plugin = RequestCoalescing::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+headers = ["Accept-Encoding"]
+wait_timeout = "3s"
+max_body_size = "64KB"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(vec!["Accept-Encoding".to_string()], plugin.headers);
+        assert_eq!(Duration::from_secs(3), plugin.wait_timeout);
+        assert_eq!(64 * 1000, plugin.max_body_size);
+    }
+
+    #[tokio::test]
+    async fn test_request_coalescing() {
+        let plugin =
+            Arc::new(RequestCoalescing::new(&PluginConf::default()).unwrap());
+
+        // the concurrent identical requests result in a single upstream call
+        let upstream_calls = Arc::new(AtomicUsize::new(0));
+        let mut set = tokio::task::JoinSet::new();
+        for _ in 0..10 {
+            set.spawn(send_request(
+                plugin.clone(),
+                upstream_calls.clone(),
+                200,
+            ));
+        }
+        let results = set.join_all().await;
+        assert_eq!(1, upstream_calls.load(Ordering::Relaxed));
+        assert_eq!(10, results.len());
+        for (status, body) in results {
+            assert_eq!(StatusCode::OK, status);
+            assert_eq!(b"console.log('pingap')", body.as_ref());
+        }
+        assert_eq!(true, plugin.flights.lock().unwrap().is_empty());
+
+        // the 5xx response isn't shared
+        let upstream_calls = Arc::new(AtomicUsize::new(0));
+        let mut set = tokio::task::JoinSet::new();
+        for _ in 0..3 {
+            set.spawn(send_request(
+                plugin.clone(),
+                upstream_calls.clone(),
+                503,
+            ));
+        }
+        set.join_all().await;
+        assert_eq!(3, upstream_calls.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn test_request_coalescing_leader_dropped() {
+        let plugin = RequestCoalescing::new(&PluginConf::default()).unwrap();
+        let headers = ["Host: pingap.io"].join("\r\n");
+        let input_header =
+            format!("GET /assets/app.js HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let mut ctx = Ctx::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+
+        let drop_leader = async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            drop(ctx);
+        };
+        let headers = ["Host: pingap.io"].join("\r\n");
+        let input_header =
+            format!("GET /assets/app.js HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let mut waiter_ctx = Ctx::default();
+        let (result, _) = tokio::join!(
+            plugin.handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut waiter_ctx
+            ),
+            drop_leader
+        );
+        // the waiting request is forwarded to upstream
+        assert_eq!(true, result.unwrap() == RequestPluginResult::Skipped);
+        assert_eq!(true, plugin.flights.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,479 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Error, get_hash_key, get_plugin_factory, get_str_conf};
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use bytesize::ByteSize;
+use ctor::ctor;
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use http::{StatusCode, header};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, HttpResponse, Plugin, PluginStep, RequestPluginResult,
+    write_continue_response,
+};
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::io::Read;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The compressed body read by the plugin is kept in the retry buffer of
+/// session, the buffer is limited to 64KB by pingora.
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+/// Default max size of the decompressed body
+const DEFAULT_MAX_DECOMPRESSED_SIZE: usize = 1024 * 1024;
+
+/// Content encoding of the request body
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Encoding {
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    /// Parses the content encoding, only the single gzip or deflate
+    /// encoding is supported.
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "gzip" | "x-gzip" => Some(Encoding::Gzip),
+            "deflate" => Some(Encoding::Deflate),
+            _ => None,
+        }
+    }
+}
+
+/// Reads the decoder to the end, returns None if the decompressed
+/// data is larger than the max size.
+fn read_limited<R: Read>(
+    reader: R,
+    max_size: usize,
+) -> std::io::Result<Option<Vec<u8>>> {
+    let mut buf = vec![];
+    // read one more byte to detect the data exceeds the limit
+    reader.take(max_size as u64 + 1).read_to_end(&mut buf)?;
+    if buf.len() > max_size {
+        return Ok(None);
+    }
+    Ok(Some(buf))
+}
+
+/// Decompresses the body, the deflate body should be zlib format,
+/// but some clients send the raw deflate data, so it's also accepted.
+fn decompress(
+    encoding: Encoding,
+    body: &[u8],
+    max_size: usize,
+) -> std::io::Result<Option<Vec<u8>>> {
+    match encoding {
+        Encoding::Gzip => read_limited(GzDecoder::new(body), max_size),
+        Encoding::Deflate => read_limited(ZlibDecoder::new(body), max_size)
+            .or_else(|_| read_limited(DeflateDecoder::new(body), max_size)),
+    }
+}
+
+/// RequestDecompression plugin decompresses the gzip or deflate request
+/// body before forwarding it to upstream, for the backends which don't
+/// support the compressed request body. The size of decompressed body is
+/// limited to prevent the zip bomb.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// # max size of the compressed request body
+/// max_body_size = "64KB"
+/// # max size of the decompressed request body
+/// max_decompressed_size = "1MB"
+/// ```
+pub struct RequestDecompression {
+    /// Maximum size of the compressed body to read
+    max_body_size: usize,
+    /// Maximum size of the decompressed body
+    max_decompressed_size: usize,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for RequestDecompression {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::RequestDecompression.to_string(),
+            message,
+        };
+        let get_size = |key: &str, default_value: usize| -> Result<usize> {
+            let size = get_str_conf(value, key);
+            if size.is_empty() {
+                return Ok(default_value);
+            }
+            Ok(ByteSize::from_str(&size)
+                .map_err(new_invalid_error)?
+                .as_u64() as usize)
+        };
+
+        let max_body_size = get_size("max_body_size", MAX_BODY_SIZE)?;
+        if max_body_size > MAX_BODY_SIZE {
+            return Err(new_invalid_error(format!(
+                "max body size should be less than or equal to {}",
+                ByteSize(MAX_BODY_SIZE as u64)
+            )));
+        }
+        let max_decompressed_size =
+            get_size("max_decompressed_size", DEFAULT_MAX_DECOMPRESSED_SIZE)?;
+        if max_decompressed_size == 0 {
+            return Err(new_invalid_error(
+                "max decompressed size should be greater than 0".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            max_body_size,
+            max_decompressed_size,
+            hash_value,
+        })
+    }
+}
+
+impl RequestDecompression {
+    /// Creates a new request decompression plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(
+            params = params.to_string(),
+            "new request decompression plugin"
+        );
+        Self::try_from(params)
+    }
+}
+
+/// Creates the response of the rejected request.
+fn new_reject_response(status: StatusCode, message: String) -> HttpResponse {
+    HttpResponse {
+        status,
+        body: Bytes::from(message),
+        ..Default::default()
+    }
+}
+
+#[async_trait]
+impl Plugin for RequestDecompression {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Reads the compressed request body and replaces it with the
+    /// decompressed body, the content encoding is removed and the content
+    /// length is updated. The request of other encodings is forwarded as is.
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let Some(encoding) = session
+            .req_header()
+            .headers
+            .get(header::CONTENT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Encoding::parse)
+        else {
+            return Ok(RequestPluginResult::Skipped);
+        };
+        let too_large = || {
+            RequestPluginResult::Respond(new_reject_response(
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!(
+                    "Request body is too large, max:{}",
+                    ByteSize(self.max_body_size as u64)
+                ),
+            ))
+        };
+        let content_length = session
+            .req_header()
+            .headers
+            .get(header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or_default();
+        if content_length > self.max_body_size {
+            return Ok(too_large());
+        }
+
+        write_continue_response(session, ctx).await?;
+        session.enable_retry_buffering();
+        let mut body = BytesMut::new();
+        while let Some(chunk) = session.read_request_body().await? {
+            if body.len() + chunk.len() > self.max_body_size {
+                return Ok(too_large());
+            }
+            body.extend_from_slice(&chunk);
+        }
+
+        let data = match decompress(encoding, &body, self.max_decompressed_size)
+        {
+            Ok(Some(data)) => Bytes::from(data),
+            Ok(None) => {
+                return Ok(RequestPluginResult::Respond(new_reject_response(
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                    format!(
+                        "Decompressed request body is too large, max:{}",
+                        ByteSize(self.max_decompressed_size as u64)
+                    ),
+                )));
+            },
+            Err(e) => {
+                return Ok(RequestPluginResult::Respond(new_reject_response(
+                    StatusCode::BAD_REQUEST,
+                    format!("Request body decompress fail, {e}"),
+                )));
+            },
+        };
+        let req_header = session.req_header_mut();
+        req_header.remove_header(&header::CONTENT_ENCODING);
+        req_header.remove_header(&header::TRANSFER_ENCODING);
+        req_header.insert_header(header::CONTENT_LENGTH, data.len())?;
+        ctx.set_request_body(data);
+
+        Ok(RequestPluginResult::Continue)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("request_decompression", |params| {
+        Ok(Arc::new(RequestDecompression::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use flate2::Compression;
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pretty_assertions::assert_eq;
+    use std::io::Write;
+
+    fn new_request_decompression(params: &str) -> RequestDecompression {
+        RequestDecompression::try_from(
+            &toml::from_str::<PluginConf>(params).unwrap(),
+        )
+        .unwrap()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn new_request(encoding: &str, body: &[u8]) -> Vec<u8> {
+        let mut input = format!(
+            "POST /users HTTP/1.1\r\nHost: pingap.io\r\nContent-Type: application/json\r\nContent-Encoding: {encoding}\r\nContent-Length: {}\r\n\r\n",
+            body.len()
+        )
+        .into_bytes();
+        input.extend_from_slice(body);
+        input
+    }
+
+    #[test]
+    fn test_request_decompression_params() {
+        let plugin = new_request_decompression("");
+        assert_eq!(MAX_BODY_SIZE, plugin.max_body_size);
+        assert_eq!(DEFAULT_MAX_DECOMPRESSED_SIZE, plugin.max_decompressed_size);
+
+        let plugin = new_request_decompression(
+            r###"
+max_body_size = "1KB"
+max_decompressed_size = "10KB"
+"###,
+        );
+        assert_eq!(1000, plugin.max_body_size);
+        assert_eq!(10000, plugin.max_decompressed_size);
+
+        let result = RequestDecompression::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+max_body_size = "1MB"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin request_decompression invalid, message: max body size should be less than or equal to 64.0 KiB",
+            result.err().unwrap().to_string()
+        );
+
+        let result = RequestDecompression::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+max_decompressed_size = "0B"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin request_decompression invalid, message: max decompressed size should be greater than 0",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_decompress() {
+        let data = br#"{"name":"pingap"}"#;
+        assert_eq!(
+            Some(data.to_vec()),
+            decompress(Encoding::Gzip, &gzip(data), 1024).unwrap()
+        );
+
+        let mut encoder = ZlibEncoder::new(vec![], Compression::default());
+        encoder.write_all(data).unwrap();
+        let zlib = encoder.finish().unwrap();
+        assert_eq!(
+            Some(data.to_vec()),
+            decompress(Encoding::Deflate, &zlib, 1024).unwrap()
+        );
+
+        let mut encoder =
+            flate2::write::DeflateEncoder::new(vec![], Compression::default());
+        encoder.write_all(data).unwrap();
+        let raw = encoder.finish().unwrap();
+        assert_eq!(
+            Some(data.to_vec()),
+            decompress(Encoding::Deflate, &raw, 1024).unwrap()
+        );
+
+        // exactly the max size is allowed
+        assert_eq!(
+            Some(data.to_vec()),
+            decompress(Encoding::Gzip, &gzip(data), data.len()).unwrap()
+        );
+        assert_eq!(
+            None,
+            decompress(Encoding::Gzip, &gzip(data), data.len() - 1).unwrap()
+        );
+
+        assert_eq!(true, decompress(Encoding::Gzip, b"pingap", 1024).is_err());
+
+        assert_eq!(Some(Encoding::Gzip), Encoding::parse("GZIP"));
+        assert_eq!(Some(Encoding::Gzip), Encoding::parse("x-gzip"));
+        assert_eq!(Some(Encoding::Deflate), Encoding::parse("deflate"));
+        assert_eq!(None, Encoding::parse("br"));
+        assert_eq!(None, Encoding::parse("gzip, br"));
+    }
+
+    #[tokio::test]
+    async fn test_request_decompression() {
+        let plugin = new_request_decompression("");
+
+        // the gzipped request body is decompressed
+        let data = br#"{"name":"pingap"}"#;
+        let mut session =
+            test_util::new_session(new_request("gzip", &gzip(data))).await;
+        let mut ctx = Ctx::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!(Some(&Bytes::from_static(data)), ctx.get_request_body());
+        let headers = &session.req_header().headers;
+        assert_eq!(true, headers.get("Content-Encoding").is_none());
+        assert_eq!(
+            data.len().to_string(),
+            headers.get("Content-Length").unwrap().to_str().unwrap()
+        );
+
+        // the invalid gzip body is rejected
+        let mut session =
+            test_util::new_session(new_request("gzip", b"pingap")).await;
+        let mut ctx = Ctx::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("request should be rejected");
+        };
+        assert_eq!(StatusCode::BAD_REQUEST, resp.status);
+
+        // the unsupported encoding is forwarded as is
+        let mut session =
+            test_util::new_session(new_request("br", b"pingap")).await;
+        let mut ctx = Ctx::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+        assert_eq!(None, ctx.get_request_body());
+    }
+
+    #[tokio::test]
+    async fn test_request_decompression_bomb() {
+        let plugin = new_request_decompression(
+            r###"
+max_decompressed_size = "1MB"
+"###,
+        );
+        // 10MB zeros are compressed to about 10KB
+        let bomb = gzip(&vec![0; 10 * 1024 * 1024]);
+        assert_eq!(true, bomb.len() < MAX_BODY_SIZE);
+
+        let mut session =
+            test_util::new_session(new_request("gzip", &bomb)).await;
+        let mut ctx = Ctx::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("request should be rejected");
+        };
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, resp.status);
+        assert_eq!(
+            "Decompressed request body is too large, max:976.6 KiB",
+            std::str::from_utf8(&resp.body).unwrap()
+        );
+        assert_eq!(None, ctx.get_request_body());
+
+        // the compressed body is too large
+        let plugin = new_request_decompression(
+            r###"
+max_body_size = "1KB"
+"###,
+        );
+        let mut session =
+            test_util::new_session(new_request("gzip", &bomb)).await;
+        let mut ctx = Ctx::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("request should be rejected");
+        };
+        assert_eq!(StatusCode::PAYLOAD_TOO_LARGE, resp.status);
+    }
+}
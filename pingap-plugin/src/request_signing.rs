@@ -0,0 +1,307 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Error, get_hash_key, get_plugin_factory, get_str_conf};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use ctor::ctor;
+use http::{HeaderName, HeaderValue};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{Ctx, Plugin, PluginStep, RequestPluginResult};
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+#[derive(Debug, PartialEq)]
+enum SigningAlgorithm {
+    HmacSha256,
+    HmacSha512,
+}
+
+#[derive(Debug, PartialEq)]
+enum DateFormat {
+    /// IMF-fixdate of RFC 7231, e.g. `Tue, 15 Nov 1994 08:12:31 GMT`
+    Http,
+    /// Unix timestamp in seconds
+    Unix,
+}
+
+/// RequestSigning plugin signs the request forwarded to upstream by HMAC,
+/// the string to sign is `METHOD\nPATH?QUERY\nDATE`, and the signature
+/// is sent in hex format. The date header is generated by pingap when the
+/// request is signed, the upstream should verify it within its allowed
+/// clock skew. The date and signature headers of client are replaced.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// secret = "my-secret"
+/// # hmac-sha256(default) or hmac-sha512
+/// algorithm = "hmac-sha256"
+/// signature_header = "X-Signature"
+/// date_header = "X-Date"
+/// # http(default) or unix
+/// date_format = "http"
+/// ```
+pub struct RequestSigning {
+    secret: String,
+    algorithm: SigningAlgorithm,
+    signature_header: HeaderName,
+    date_header: HeaderName,
+    date_format: DateFormat,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for RequestSigning {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::RequestSigning.to_string(),
+            message,
+        };
+        let secret = get_str_conf(value, "secret");
+        if secret.is_empty() {
+            return Err(new_invalid_error("secret can't be empty".to_string()));
+        }
+        let algorithm = match get_str_conf(value, "algorithm").as_str() {
+            "" | "hmac-sha256" => SigningAlgorithm::HmacSha256,
+            "hmac-sha512" => SigningAlgorithm::HmacSha512,
+            algorithm => {
+                return Err(new_invalid_error(format!(
+                    "algorithm({algorithm}) is not supported"
+                )));
+            },
+        };
+        let date_format = match get_str_conf(value, "date_format").as_str() {
+            "" | "http" => DateFormat::Http,
+            "unix" => DateFormat::Unix,
+            date_format => {
+                return Err(new_invalid_error(format!(
+                    "date format({date_format}) is not supported"
+                )));
+            },
+        };
+        let get_header_name = |key: &str, default_value: &str| {
+            let mut name = get_str_conf(value, key);
+            if name.is_empty() {
+                name = default_value.to_string();
+            }
+            HeaderName::from_str(&name).map_err(|e| {
+                new_invalid_error(format!("{key}({name}) is invalid, {e}"))
+            })
+        };
+
+        Ok(Self {
+            secret,
+            algorithm,
+            signature_header: get_header_name(
+                "signature_header",
+                "X-Signature",
+            )?,
+            date_header: get_header_name("date_header", "X-Date")?,
+            date_format,
+            hash_value,
+        })
+    }
+}
+
+impl RequestSigning {
+    /// Creates a new request signing plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new request signing plugin");
+        Self::try_from(params)
+    }
+
+    /// Formats the date of signing
+    fn format_date(&self, date: DateTime<Utc>) -> String {
+        match self.date_format {
+            DateFormat::Http => {
+                date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+            },
+            DateFormat::Unix => date.timestamp().to_string(),
+        }
+    }
+
+    /// Signs the `METHOD\nPATH?QUERY\nDATE` by hmac, returns the hex signature
+    fn sign(&self, method: &str, path_and_query: &str, date: &str) -> String {
+        let content = format!("{method}\n{path_and_query}\n{date}");
+        let secret = self.secret.as_bytes();
+        match self.algorithm {
+            SigningAlgorithm::HmacSha256 => {
+                hex::encode(hmac_sha256::HMAC::mac(content.as_bytes(), secret))
+            },
+            SigningAlgorithm::HmacSha512 => {
+                hex::encode(hmac_sha512::HMAC::mac(content.as_bytes(), secret))
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for RequestSigning {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Sets the date and signature headers of the request, so the request
+    /// forwarded to upstream is signed.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        _ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let date = self.format_date(Utc::now());
+        let header = session.req_header();
+        let path_and_query = header
+            .uri
+            .path_and_query()
+            .map_or(header.uri.path(), |value| value.as_str());
+        let signature =
+            self.sign(header.method.as_str(), path_and_query, &date);
+
+        let header = session.req_header_mut();
+        // the date and signature are ascii strings
+        if let Ok(value) = HeaderValue::from_str(&date) {
+            let _ = header.insert_header(self.date_header.clone(), value);
+        }
+        if let Ok(value) = HeaderValue::from_str(&signature) {
+            let _ = header.insert_header(self.signature_header.clone(), value);
+        }
+        Ok(RequestPluginResult::Continue)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("request_signing", |params| {
+        Ok(Arc::new(RequestSigning::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    fn new_signing(conf: &str) -> RequestSigning {
+        RequestSigning::new(&toml::from_str::<PluginConf>(conf).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_request_signing_params() {
+        let signing = new_signing(r#"secret = "secret""#);
+        assert_eq!(SigningAlgorithm::HmacSha256, signing.algorithm);
+        assert_eq!(DateFormat::Http, signing.date_format);
+        assert_eq!("x-signature", signing.signature_header.as_str());
+        assert_eq!("x-date", signing.date_header.as_str());
+
+        let result = RequestSigning::try_from(&PluginConf::default());
+        assert_eq!(
+            "Plugin request_signing invalid, message: secret can't be empty",
+            result.err().unwrap().to_string()
+        );
+
+        let result = RequestSigning::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+secret = "secret"
+algorithm = "md5"
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin request_signing invalid, message: algorithm(md5) is not supported",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_sign() {
+        let signing = new_signing(r#"secret = "secret""#);
+        let date = DateTime::from_timestamp(784887151, 0).unwrap();
+        assert_eq!("Tue, 15 Nov 1994 08:12:31 GMT", signing.format_date(date));
+        assert_eq!(
+            "c25d2113d67f28dc4acf9a2cf5efb596becf7b372e542049b671c60055549795",
+            signing.sign("GET", "/api/users?id=1", &signing.format_date(date))
+        );
+
+        let signing = new_signing(
+            r###"
+secret = "secret"
+algorithm = "hmac-sha512"
+date_format = "unix"
+"###,
+        );
+        assert_eq!("784887151", signing.format_date(date));
+        assert_eq!(
+            "f6467f85137b6dfe6120e5c4d6cf4a5e94eb210937481d99c5ca30e9a2a4cc7fa3d52240c7303aaaed366cdbf10f67769ff119dba82ec0f4d77f96ade8692c41",
+            signing.sign("POST", "/api/users", &signing.format_date(date))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_request_signing() {
+        let signing = new_signing(
+            r###"
+secret = "secret"
+date_format = "unix"
+"###,
+        );
+        let input_header =
+            "GET /api/users?id=1 HTTP/1.1\r\nX-Signature: fake\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+
+        let result = signing
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+
+        let headers = &session.req_header().headers;
+        let date = headers.get("X-Date").unwrap().to_str().unwrap();
+        // the date is generated when signing
+        let timestamp = date.parse::<i64>().unwrap();
+        assert_eq!(true, (Utc::now().timestamp() - timestamp).abs() <= 1);
+        // the signature of client is replaced
+        let signatures: Vec<_> =
+            headers.get_all("X-Signature").iter().collect();
+        assert_eq!(1, signatures.len());
+        assert_eq!(
+            signing.sign("GET", "/api/users?id=1", date),
+            signatures[0].to_str().unwrap()
+        );
+    }
+}
@@ -0,0 +1,295 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::jwt::JWT_CLAIM_VARIABLE_PREFIX;
+use super::{
+    Error, get_hash_key, get_plugin_factory, get_str_conf, get_str_slice_conf,
+};
+use async_trait::async_trait;
+use ctor::ctor;
+use http::{HeaderValue, StatusCode, header};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{Ctx, HttpResponse, Plugin, PluginStep, RequestPluginResult};
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::sync::Arc;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The claims of granted scopes, `scope` is the space-delimited string of
+/// RFC 8693 and `scp` is used by some providers(e.g. Azure AD, Okta).
+const DEFAULT_SCOPE_CLAIMS: [&str; 2] = ["scope", "scp"];
+
+/// ScopeRestriction plugin enforces the OAuth2 scopes of access token,
+/// the token should be verified by the jwt plugin(executed before this
+/// plugin) and all the required scopes should be granted by its `scope`
+/// or `scp` claim. As plugins are bound to locations, the required scopes
+/// can differ per location.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// # the scopes which should be all granted
+/// scopes = ["orders:read", "orders:write"]
+/// # the claim of granted scopes, default is `scope` then `scp`
+/// claim = "scope"
+/// ```
+pub struct ScopeRestriction {
+    /// The required scopes
+    scopes: Vec<String>,
+    /// The claims of granted scopes, the first present one is used
+    claims: Vec<String>,
+    /// The `WWW-Authenticate` header of insufficient scope response
+    authenticate: HeaderValue,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for ScopeRestriction {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::ScopeRestriction.to_string(),
+            message,
+        };
+        let mut scopes: Vec<String> = vec![];
+        for item in get_str_slice_conf(value, "scopes") {
+            for scope in item.split_whitespace() {
+                if !scopes.iter().any(|item| item == scope) {
+                    scopes.push(scope.to_string());
+                }
+            }
+        }
+        if scopes.is_empty() {
+            return Err(new_invalid_error("scopes can't be empty".to_string()));
+        }
+        let claim = get_str_conf(value, "claim");
+        let claims = if claim.is_empty() {
+            DEFAULT_SCOPE_CLAIMS.map(|item| item.to_string()).to_vec()
+        } else {
+            vec![claim]
+        };
+        // RFC 6750, the scope necessary to access the resource
+        let authenticate = HeaderValue::from_str(&format!(
+            r#"Bearer error="insufficient_scope", scope="{}""#,
+            scopes.join(" ")
+        ))
+        .map_err(|e| new_invalid_error(e.to_string()))?;
+
+        Ok(Self {
+            scopes,
+            claims,
+            authenticate,
+            hash_value,
+        })
+    }
+}
+
+impl ScopeRestriction {
+    /// Creates a new scope restriction plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new scope restriction plugin");
+        Self::try_from(params)
+    }
+
+    /// Returns the required scopes which are not granted
+    fn get_missing_scopes(&self, ctx: &Ctx) -> Vec<&str> {
+        let granted: Vec<&str> = self
+            .claims
+            .iter()
+            .find_map(|claim| {
+                ctx.get_variable(&format!("{JWT_CLAIM_VARIABLE_PREFIX}{claim}"))
+            })
+            .map(|value| value.split_whitespace().collect())
+            .unwrap_or_default();
+        self.scopes
+            .iter()
+            .map(|scope| scope.as_str())
+            .filter(|scope| !granted.contains(scope))
+            .collect()
+    }
+}
+
+#[async_trait]
+impl Plugin for ScopeRestriction {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Returns 403 with the missing scopes if any required scope
+    /// isn't granted by the access token.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        _session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let missing = self.get_missing_scopes(ctx);
+        if missing.is_empty() {
+            return Ok(RequestPluginResult::Continue);
+        }
+        Ok(RequestPluginResult::Respond(HttpResponse {
+            status: StatusCode::FORBIDDEN,
+            body: format!(
+                "Insufficient scope, required: {}, missing: {}",
+                self.scopes.join(" "),
+                missing.join(" ")
+            )
+            .into(),
+            headers: Some(vec![(
+                header::WWW_AUTHENTICATE,
+                self.authenticate.clone(),
+            )]),
+            ..Default::default()
+        }))
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("scope_restriction", |params| {
+        Ok(Arc::new(ScopeRestriction::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pretty_assertions::assert_eq;
+
+    fn new_scope_restriction(params: &str) -> ScopeRestriction {
+        ScopeRestriction::new(&toml::from_str::<PluginConf>(params).unwrap())
+            .unwrap()
+    }
+
+    fn new_ctx(claims: &[(&str, &str)]) -> Ctx {
+        let mut ctx = Ctx::default();
+        for (key, value) in claims {
+            ctx.add_variable(
+                &format!("{JWT_CLAIM_VARIABLE_PREFIX}{key}"),
+                value,
+            );
+        }
+        ctx
+    }
+
+    async fn handle(
+        plugin: &ScopeRestriction,
+        claims: &[(&str, &str)],
+    ) -> RequestPluginResult {
+        let mut session =
+            test_util::new_session("GET /orders HTTP/1.1\r\n\r\n").await;
+        plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut new_ctx(claims),
+            )
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_scope_restriction_params() {
+        let plugin = new_scope_restriction(
+            r###"
+scopes = ["orders:read orders:write", "orders:read"]
+"###,
+        );
+        assert_eq!(vec!["orders:read", "orders:write"], plugin.scopes);
+        assert_eq!(vec!["scope", "scp"], plugin.claims);
+        assert_eq!(
+            r#"Bearer error="insufficient_scope", scope="orders:read orders:write""#,
+            plugin.authenticate
+        );
+
+        let plugin = new_scope_restriction(
+            r###"
+scopes = ["admin"]
+claim = "permissions"
+"###,
+        );
+        assert_eq!(vec!["permissions"], plugin.claims);
+
+        let result = ScopeRestriction::try_from(&PluginConf::default());
+        assert_eq!(
+            "Plugin scope_restriction invalid, message: scopes can't be empty",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scope_restriction() {
+        let plugin = new_scope_restriction(
+            r###"
+scopes = ["orders:read", "orders:write"]
+"###,
+        );
+
+        // all scopes are granted
+        let result =
+            handle(&plugin, &[("scope", "openid orders:write orders:read")])
+                .await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        // the scp claim(joined string array)
+        let result =
+            handle(&plugin, &[("scp", "orders:read orders:write")]).await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+
+        // missing the required scope
+        let result = handle(&plugin, &[("scope", "openid orders:read")]).await;
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("the request should be rejected");
+        };
+        assert_eq!(StatusCode::FORBIDDEN, resp.status);
+        assert_eq!(
+            "Insufficient scope, required: orders:read orders:write, missing: orders:write",
+            std::str::from_utf8(&resp.body).unwrap()
+        );
+        assert_eq!(
+            r#"Bearer error="insufficient_scope", scope="orders:read orders:write""#,
+            resp.headers.unwrap()[0].1
+        );
+
+        // no scope claim
+        let result = handle(&plugin, &[("sub", "alice")]).await;
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("the request should be rejected");
+        };
+        assert_eq!(StatusCode::FORBIDDEN, resp.status);
+        assert_eq!(
+            "Insufficient scope, required: orders:read orders:write, missing: orders:read orders:write",
+            std::str::from_utf8(&resp.body).unwrap()
+        );
+
+        // the scope of other location
+        let plugin = new_scope_restriction(
+            r###"
+scopes = ["admin"]
+"###,
+        );
+        let result = handle(&plugin, &[("scope", "orders:read")]).await;
+        assert_eq!(true, matches!(result, RequestPluginResult::Respond(_)));
+        let result = handle(&plugin, &[("scope", "admin")]).await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+    }
+}
@@ -0,0 +1,273 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_bool_conf, get_hash_key, get_plugin_factory, get_str_conf,
+};
+use async_trait::async_trait;
+use ctor::ctor;
+use http::HeaderValue;
+use http::header::HeaderName;
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{Ctx, Plugin, ResponsePluginResult};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::sync::Arc;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The supported security headers, (config key, header name, default value).
+/// An empty default value means the header is only set when configured.
+const SECURITY_HEADERS: [(&str, &str, &str); 5] = [
+    ("content_security_policy", "content-security-policy", ""),
+    ("x_frame_options", "x-frame-options", "SAMEORIGIN"),
+    (
+        "x_content_type_options",
+        "x-content-type-options",
+        "nosniff",
+    ),
+    (
+        "referrer_policy",
+        "referrer-policy",
+        "strict-origin-when-cross-origin",
+    ),
+    ("permissions_policy", "permissions-policy", ""),
+];
+
+/// SecurityHeaders plugin injects common security related headers
+/// into the response.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// content_security_policy = "default-src 'self'"
+/// x_frame_options = "DENY"
+/// x_content_type_options = "nosniff"
+/// referrer_policy = "no-referrer"
+/// permissions_policy = "geolocation=()"
+/// # overwrite the headers set by upstream, default is false
+/// overwrite = false
+/// ```
+///
+/// Set a header to `"off"` to disable it. As plugins are bound to locations,
+/// different locations can use different instances to override the values.
+pub struct SecurityHeaders {
+    /// The security headers which will be set
+    headers: Vec<(HeaderName, HeaderValue)>,
+    /// Whether to replace the header already set by upstream
+    overwrite: bool,
+    /// Unique identifier for this plugin instance
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for SecurityHeaders {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let mut headers = vec![];
+        for (key, name, default_value) in SECURITY_HEADERS {
+            let mut header_value = get_str_conf(value, key);
+            if header_value.is_empty() {
+                header_value = default_value.to_string();
+            }
+            if header_value.is_empty() || header_value == "off" {
+                continue;
+            }
+            let header_value =
+                HeaderValue::from_str(&header_value).map_err(|e| {
+                    Error::Invalid {
+                        category: PluginCategory::SecurityHeaders.to_string(),
+                        message: format!("{key}: {e}"),
+                    }
+                })?;
+            headers.push((HeaderName::from_static(name), header_value));
+        }
+
+        Ok(Self {
+            headers,
+            overwrite: get_bool_conf(value, "overwrite"),
+            hash_value,
+        })
+    }
+}
+
+impl SecurityHeaders {
+    /// Creates a new security headers plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new security headers plugin");
+        Self::try_from(params)
+    }
+}
+
+#[async_trait]
+impl Plugin for SecurityHeaders {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Sets the security headers, the headers provided by upstream are
+    /// preserved unless `overwrite` is enabled.
+    #[inline]
+    async fn handle_response(
+        &self,
+        _session: &mut Session,
+        _ctx: &mut Ctx,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<ResponsePluginResult> {
+        let mut modified = false;
+        for (name, value) in self.headers.iter() {
+            if !self.overwrite && upstream_response.headers.contains_key(name) {
+                continue;
+            }
+            let _ = upstream_response.insert_header(name, value);
+            modified = true;
+        }
+        if modified {
+            Ok(ResponsePluginResult::Modified)
+        } else {
+            Ok(ResponsePluginResult::Unchanged)
+        }
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("security_headers", |params| {
+        Ok(Arc::new(SecurityHeaders::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pingap_core::Ctx;
+    use pingora::http::ResponseHeader;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_security_headers_params() {
+        let params = SecurityHeaders::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+content_security_policy = "default-src 'self'"
+x_frame_options = "off"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            r#"[("content-security-policy", "default-src 'self'"), ("x-content-type-options", "nosniff"), ("referrer-policy", "strict-origin-when-cross-origin")]"#,
+            format!("{:?}", params.headers)
+        );
+        assert_eq!(false, params.overwrite);
+    }
+
+    #[tokio::test]
+    async fn test_security_headers() {
+        let plugin = SecurityHeaders::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+x_frame_options = "DENY"
+permissions_policy = "geolocation=()"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let mut session =
+            test_util::new_session("GET /vicanso/pingap HTTP/1.1\r\n\r\n")
+                .await;
+
+        // added when absent
+        let mut upstream_response =
+            ResponseHeader::build_no_case(200, None).unwrap();
+        let result = plugin
+            .handle_response(
+                &mut session,
+                &mut Ctx::default(),
+                &mut upstream_response,
+            )
+            .await
+            .unwrap();
+        assert_eq!(ResponsePluginResult::Modified, result);
+        assert_eq!(
+            "DENY",
+            upstream_response.headers.get("x-frame-options").unwrap()
+        );
+        assert_eq!(
+            "geolocation=()",
+            upstream_response.headers.get("permissions-policy").unwrap()
+        );
+
+        // preserved when upstream provided
+        let mut upstream_response =
+            ResponseHeader::build_no_case(200, None).unwrap();
+        upstream_response
+            .insert_header("X-Frame-Options", "SAMEORIGIN")
+            .unwrap();
+        plugin
+            .handle_response(
+                &mut session,
+                &mut Ctx::default(),
+                &mut upstream_response,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            "SAMEORIGIN",
+            upstream_response.headers.get("x-frame-options").unwrap()
+        );
+        assert_eq!(
+            "nosniff",
+            upstream_response
+                .headers
+                .get("x-content-type-options")
+                .unwrap()
+        );
+
+        // overwrite the upstream headers
+        let plugin = SecurityHeaders::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+x_frame_options = "DENY"
+overwrite = true
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        let mut upstream_response =
+            ResponseHeader::build_no_case(200, None).unwrap();
+        upstream_response
+            .insert_header("X-Frame-Options", "SAMEORIGIN")
+            .unwrap();
+        plugin
+            .handle_response(
+                &mut session,
+                &mut Ctx::default(),
+                &mut upstream_response,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            "DENY",
+            upstream_response.headers.get("x-frame-options").unwrap()
+        );
+    }
+}
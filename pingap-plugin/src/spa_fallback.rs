@@ -0,0 +1,354 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_hash_key, get_plugin_factory, get_str_conf, get_str_slice_conf,
+};
+use async_trait::async_trait;
+use ctor::ctor;
+use http::uri::PathAndQuery;
+use http::{Method, Uri, header};
+use path_absolutize::Absolutize;
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, Plugin, PluginStep, RequestPluginResult, new_internal_error,
+};
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use substring::Substring;
+use tokio::fs;
+use tracing::debug;
+use urlencoding::decode;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// SpaFallback plugin serves the fallback file(e.g. `index.html`) of
+/// single-page app for the client-side routes. If the requested file
+/// doesn't exist and the request accepts html, the request path is
+/// rewritten to the fallback file, then it's served with 200 by the
+/// directory plugin, so the plugin should be placed before the
+/// directory plugin. The missing assets(by extension) are still 404.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// # the same path of directory plugin
+/// path = "/opt/app/dist"
+/// # the fallback file, default is index.html
+/// fallback = "index.html"
+/// # the extensions of assets, default is any extension except html
+/// asset_extensions = ["js", "css", "png"]
+/// ```
+pub struct SpaFallback {
+    /// The root directory of static files
+    path: PathBuf,
+    /// The fallback file, it starts with "/"
+    fallback: String,
+    /// The extensions(lowercase) of assets, empty means any extension
+    asset_extensions: Vec<String>,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for SpaFallback {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let path = get_str_conf(value, "path");
+        if path.is_empty() {
+            return Err(Error::Invalid {
+                category: PluginCategory::SpaFallback.to_string(),
+                message: "spa fallback path is empty".to_string(),
+            });
+        }
+        let mut fallback = get_str_conf(value, "fallback");
+        if fallback.is_empty() {
+            fallback = "index.html".to_string();
+        }
+        if !fallback.starts_with('/') {
+            fallback = format!("/{fallback}");
+        }
+        let asset_extensions = get_str_slice_conf(value, "asset_extensions")
+            .iter()
+            .map(|ext| ext.trim_start_matches('.').to_lowercase())
+            .filter(|ext| !ext.is_empty())
+            .collect();
+
+        Ok(Self {
+            path: Path::new(&pingap_util::resolve_path(&path)).to_path_buf(),
+            fallback,
+            asset_extensions,
+            hash_value,
+        })
+    }
+}
+
+impl SpaFallback {
+    /// Creates a new spa fallback plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new spa fallback plugin");
+        Self::try_from(params)
+    }
+
+    /// Returns true if the path is an asset request(by extension),
+    /// the html pages are treated as routes.
+    fn is_asset(&self, path: &str) -> bool {
+        let name = path.rsplit('/').next().unwrap_or_default();
+        let Some((_, ext)) = name.rsplit_once('.') else {
+            return false;
+        };
+        let ext = ext.to_lowercase();
+        if self.asset_extensions.is_empty() {
+            return !ext.is_empty() && ext != "html" && ext != "htm";
+        }
+        self.asset_extensions.contains(&ext)
+    }
+
+    /// Returns true if the requested file(or directory) exists, the path
+    /// outside of the root directory is treated as existing, so it's
+    /// handled by the directory plugin.
+    async fn exists(&self, path: &str) -> bool {
+        let filename = match decode(path) {
+            Ok(decoded_value) => decoded_value.into_owned(),
+            Err(_) => path.to_string(),
+        };
+        let Ok(file) = self
+            .path
+            .join(filename.substring(1, filename.len()))
+            .absolutize()
+            .map(|file| file.to_path_buf())
+        else {
+            return true;
+        };
+        if !file.starts_with(&self.path) {
+            return true;
+        }
+        fs::metadata(&file).await.is_ok()
+    }
+}
+
+/// Returns true if the request accepts html, e.g. the navigation
+/// request of browser.
+fn accept_html(session: &Session) -> bool {
+    session
+        .get_header(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value.contains("text/html")
+                || value.contains("application/xhtml+xml")
+        })
+}
+
+#[async_trait]
+impl Plugin for SpaFallback {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Rewrites the request path to the fallback file if the requested
+    /// route doesn't exist.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        _ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        if ![Method::GET, Method::HEAD].contains(&session.req_header().method)
+            || !accept_html(session)
+        {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let path = session.req_header().uri.path().to_string();
+        if path.len() <= 1 || self.is_asset(&path) || self.exists(&path).await {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        debug!(path, fallback = self.fallback.as_str(), "spa fallback");
+        let req_header = session.req_header();
+        let path_and_query = match req_header.uri.query() {
+            Some(query) => format!("{}?{query}", self.fallback),
+            None => self.fallback.clone(),
+        };
+        let mut parts = req_header.uri.clone().into_parts();
+        parts.path_and_query = Some(
+            PathAndQuery::from_str(&path_and_query)
+                .map_err(|e| new_internal_error(400, e))?,
+        );
+        let uri =
+            Uri::from_parts(parts).map_err(|e| new_internal_error(400, e))?;
+        session.req_header_mut().set_uri(uri);
+        Ok(RequestPluginResult::Continue)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("spa_fallback", |params| {
+        Ok(Arc::new(SpaFallback::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::directory::Directory;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pretty_assertions::assert_eq;
+
+    fn new_conf(params: &str) -> PluginConf {
+        toml::from_str::<PluginConf>(params).unwrap()
+    }
+
+    #[test]
+    fn test_spa_fallback_params() {
+        let plugin = SpaFallback::new(&new_conf(
+            r###"
+path = "./"
+"###,
+        ))
+        .unwrap();
+        assert_eq!("/index.html", plugin.fallback);
+        assert_eq!(true, plugin.asset_extensions.is_empty());
+        assert_eq!(true, plugin.is_asset("/assets/app.JS"));
+        assert_eq!(false, plugin.is_asset("/users/1"));
+        assert_eq!(false, plugin.is_asset("/about.html"));
+        assert_eq!(false, plugin.is_asset("/v1.0/users"));
+
+        let plugin = SpaFallback::new(&new_conf(
+            r###"
+path = "./"
+fallback = "app.html"
+asset_extensions = [".js", "css"]
+"###,
+        ))
+        .unwrap();
+        assert_eq!("/app.html", plugin.fallback);
+        assert_eq!(vec!["js", "css"], plugin.asset_extensions);
+        assert_eq!(true, plugin.is_asset("/app.js"));
+        assert_eq!(false, plugin.is_asset("/users/john.doe"));
+
+        let result = SpaFallback::try_from(&PluginConf::default());
+        assert_eq!(
+            "Plugin spa_fallback invalid, message: spa fallback path is empty",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_spa_fallback() {
+        let params = new_conf(
+            r###"
+path = "./"
+"###,
+        );
+        let plugin = SpaFallback::new(&params).unwrap();
+        let dir = Directory::new(&params).unwrap();
+
+        // the unknown html route serves index.html
+        let headers = ["Accept: text/html,*/*;q=0.8"].join("\r\n");
+        let input_header =
+            format!("GET /users/1?tab=2 HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!("/index.html?tab=2", session.req_header().uri.to_string());
+        let result = dir
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("result is not Respond");
+        };
+        assert_eq!(200, resp.status.as_u16());
+        assert_eq!(
+            r#"("content-type", "text/html")"#,
+            format!("{:?}", resp.headers.unwrap()[0])
+        );
+
+        // the missing asset is 404
+        let headers = ["Accept: text/html"].join("\r\n");
+        let input_header =
+            format!("GET /assets/app.js HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+        let result = dir
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        let RequestPluginResult::Respond(resp) = result else {
+            panic!("result is not Respond");
+        };
+        assert_eq!(404, resp.status.as_u16());
+
+        // the request doesn't accept html
+        let headers = ["Accept: application/json"].join("\r\n");
+        let input_header =
+            format!("GET /users/1 HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+
+        // the existing path is served by directory plugin
+        let headers = ["Accept: text/html"].join("\r\n");
+        let input_header = format!("GET /src HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+        assert_eq!("/src", session.req_header().uri.to_string());
+    }
+}
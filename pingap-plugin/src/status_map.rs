@@ -0,0 +1,334 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_hash_key, get_plugin_factory, get_str_conf, get_str_slice_conf,
+};
+use ahash::AHashMap;
+use async_trait::async_trait;
+use bytes::Bytes;
+use ctor::ctor;
+use http::StatusCode;
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, HTTP_HEADER_CONTENT_TEXT, HTTP_HEADER_TRANSFER_CHUNKED,
+    ModifyResponseBody, Plugin, ResponseBodyPluginResult, ResponsePluginResult,
+};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::sync::Arc;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+const PLUGIN_ID: &str = "_status_map_";
+
+struct ReplaceBody {
+    body: Bytes,
+}
+
+impl ModifyResponseBody for ReplaceBody {
+    fn handle(
+        &mut self,
+        _session: &Session,
+        body: &mut Option<bytes::Bytes>,
+        end_of_stream: bool,
+    ) -> pingora::Result<()> {
+        if end_of_stream {
+            *body = Some(self.body.clone());
+        } else if let Some(data) = body {
+            data.clear();
+        }
+        Ok(())
+    }
+    fn name(&self) -> String {
+        "status_map".to_string()
+    }
+}
+
+/// StatusMap plugin remaps the status codes of upstream response to the
+/// client-facing ones for compatibility, e.g. `418` of backend to `503`.
+/// The reason phrase can be adjusted along with the status, otherwise the
+/// default reason of the new status is used. The body is passed through
+/// unless the replacement body is configured, the unmapped statuses are
+/// unchanged.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// # upstream status:client status [reason phrase]
+/// mapping = ["418:503", "404:410 Resource Removed"]
+/// # replace the body of the mapped responses(text/plain), default is none
+/// body = "Service is unavailable"
+/// ```
+pub struct StatusMap {
+    /// The client status and reason phrase of upstream statuses
+    mapping: AHashMap<StatusCode, (StatusCode, Option<String>)>,
+    body: Option<Bytes>,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for StatusMap {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::StatusMap.to_string(),
+            message,
+        };
+        let parse_status = |value: &str| {
+            value
+                .trim()
+                .parse::<u16>()
+                .ok()
+                .and_then(|status| StatusCode::from_u16(status).ok())
+        };
+        let mut mapping = AHashMap::new();
+        for item in get_str_slice_conf(value, "mapping") {
+            let (from, to) =
+                item.split_once(':').unwrap_or((item.as_str(), ""));
+            let (to, reason) = match to.trim().split_once(' ') {
+                Some((to, reason)) => (to, Some(reason.trim().to_string())),
+                None => (to, None),
+            };
+            let (Some(from), Some(to)) = (parse_status(from), parse_status(to))
+            else {
+                return Err(new_invalid_error(format!(
+                    "mapping({item}) is invalid"
+                )));
+            };
+            mapping.insert(from, (to, reason.filter(|v| !v.is_empty())));
+        }
+        if mapping.is_empty() {
+            return Err(new_invalid_error(
+                "mapping can't be empty".to_string(),
+            ));
+        }
+        let body = get_str_conf(value, "body");
+
+        Ok(Self {
+            mapping,
+            body: (!body.is_empty()).then(|| Bytes::from(body)),
+            hash_value,
+        })
+    }
+}
+
+impl StatusMap {
+    /// Creates a new status map plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new status map plugin");
+        Self::try_from(params)
+    }
+}
+
+#[async_trait]
+impl Plugin for StatusMap {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Rewrites the mapped status and reason phrase of upstream response,
+    /// and replaces the body if it's configured.
+    async fn handle_response(
+        &self,
+        _session: &mut Session,
+        ctx: &mut Ctx,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<ResponsePluginResult> {
+        let Some((status, reason)) =
+            self.mapping.get(&upstream_response.status)
+        else {
+            return Ok(ResponsePluginResult::Unchanged);
+        };
+        let _ = upstream_response.set_status(*status);
+        // the reason phrase of upstream doesn't match the new status
+        let _ = upstream_response.set_reason_phrase(reason.as_deref());
+        let Some(body) = &self.body else {
+            return Ok(ResponsePluginResult::Modified);
+        };
+        upstream_response.remove_header(&http::header::CONTENT_LENGTH);
+        upstream_response.remove_header(&http::header::CONTENT_ENCODING);
+        let text = HTTP_HEADER_CONTENT_TEXT.clone();
+        let _ = upstream_response.insert_header(text.0, text.1);
+        let _ = upstream_response.insert_header(
+            http::header::TRANSFER_ENCODING,
+            HTTP_HEADER_TRANSFER_CHUNKED.1.clone(),
+        );
+        ctx.add_modify_body_handler(
+            PLUGIN_ID,
+            Box::new(ReplaceBody { body: body.clone() }),
+        );
+        Ok(ResponsePluginResult::Modified)
+    }
+
+    fn handle_response_body(
+        &self,
+        session: &mut Session,
+        ctx: &mut Ctx,
+        body: &mut Option<bytes::Bytes>,
+        end_of_stream: bool,
+    ) -> pingora::Result<ResponseBodyPluginResult> {
+        if let Some(modifier) = ctx.get_modify_body_handler(PLUGIN_ID) {
+            modifier.handle(session, body, end_of_stream)?;
+            let result = if end_of_stream {
+                ResponseBodyPluginResult::FullyReplaced
+            } else {
+                ResponseBodyPluginResult::PartialReplaced
+            };
+            Ok(result)
+        } else {
+            Ok(ResponseBodyPluginResult::Unchanged)
+        }
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory()
+        .register("status_map", |params| Ok(Arc::new(StatusMap::new(params)?)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pingap_core::Ctx;
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+
+    fn new_plugin(conf: &str) -> StatusMap {
+        StatusMap::new(&toml::from_str::<PluginConf>(conf).unwrap()).unwrap()
+    }
+
+    async fn handle(
+        plugin: &StatusMap,
+        status: u16,
+    ) -> (ResponsePluginResult, ResponseHeader, Session, Ctx) {
+        let input_header = "GET /api/users HTTP/1.1\r\nHost: pingap.io\r\n\r\n";
+        let mut session = test_util::new_session(input_header).await;
+        let mut upstream_response =
+            ResponseHeader::build(status, None).unwrap();
+        upstream_response
+            .insert_header(http::header::CONTENT_LENGTH, "5")
+            .unwrap();
+        let mut ctx = Ctx::default();
+        let result = plugin
+            .handle_response(&mut session, &mut ctx, &mut upstream_response)
+            .await
+            .unwrap();
+        (result, upstream_response, session, ctx)
+    }
+
+    #[test]
+    fn test_status_map_params() {
+        let plugin = new_plugin(
+            r###"
+mapping = ["418:503", "404:410 Resource Removed"]
+"###,
+        );
+        assert_eq!(
+            Some(&(StatusCode::SERVICE_UNAVAILABLE, None)),
+            plugin.mapping.get(&StatusCode::IM_A_TEAPOT)
+        );
+        assert_eq!(
+            Some(&(StatusCode::GONE, Some("Resource Removed".to_string()))),
+            plugin.mapping.get(&StatusCode::NOT_FOUND)
+        );
+        assert_eq!(true, plugin.body.is_none());
+
+        for (conf, message) in [
+            ("", "mapping can't be empty"),
+            (r#"mapping = ["418"]"#, "mapping(418) is invalid"),
+            (r#"mapping = ["418:abc"]"#, "mapping(418:abc) is invalid"),
+            (r#"mapping = ["1000:503"]"#, "mapping(1000:503) is invalid"),
+        ] {
+            let result = StatusMap::try_from(
+                &toml::from_str::<PluginConf>(conf).unwrap(),
+            );
+            assert_eq!(
+                format!("Plugin status_map invalid, message: {message}"),
+                result.err().unwrap().to_string()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_status_map() {
+        let plugin = new_plugin(
+            r###"
+mapping = ["418:503", "404:410 Resource Removed"]
+"###,
+        );
+        // the mapped status is rewritten, the body is untouched
+        let (result, resp, _, mut ctx) = handle(&plugin, 418).await;
+        assert_eq!(ResponsePluginResult::Modified, result);
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, resp.status);
+        assert_eq!(Some("Service Unavailable"), resp.get_reason_phrase());
+        assert_eq!("5", resp.headers.get("content-length").unwrap());
+        assert_eq!(true, ctx.get_modify_body_handler(PLUGIN_ID).is_none());
+
+        let (result, resp, _, _) = handle(&plugin, 404).await;
+        assert_eq!(ResponsePluginResult::Modified, result);
+        assert_eq!(StatusCode::GONE, resp.status);
+        assert_eq!(Some("Resource Removed"), resp.get_reason_phrase());
+
+        // the unmapped statuses pass through unchanged
+        for status in [200, 500, 503] {
+            let (result, resp, _, _) = handle(&plugin, status).await;
+            assert_eq!(ResponsePluginResult::Unchanged, result);
+            assert_eq!(status, resp.status.as_u16());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_status_map_body() {
+        let plugin = new_plugin(
+            r###"
+mapping = ["418:503"]
+body = "Service is unavailable"
+"###,
+        );
+        let (result, resp, mut session, mut ctx) = handle(&plugin, 418).await;
+        assert_eq!(ResponsePluginResult::Modified, result);
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, resp.status);
+        assert_eq!(true, resp.headers.get("content-length").is_none());
+
+        let mut body = Some(Bytes::from("hello"));
+        let result = plugin
+            .handle_response_body(&mut session, &mut ctx, &mut body, false)
+            .unwrap();
+        assert_eq!(ResponseBodyPluginResult::PartialReplaced, result);
+        assert_eq!(true, body.unwrap().is_empty());
+
+        let mut body = None;
+        let result = plugin
+            .handle_response_body(&mut session, &mut ctx, &mut body, true)
+            .unwrap();
+        assert_eq!(ResponseBodyPluginResult::FullyReplaced, result);
+        assert_eq!(Bytes::from("Service is unavailable"), body.unwrap());
+
+        // the body of unmapped status is untouched
+        let (result, _, mut session, mut ctx) = handle(&plugin, 200).await;
+        assert_eq!(ResponsePluginResult::Unchanged, result);
+        let mut body = Some(Bytes::from("hello"));
+        let result = plugin
+            .handle_response_body(&mut session, &mut ctx, &mut body, true)
+            .unwrap();
+        assert_eq!(ResponseBodyPluginResult::Unchanged, result);
+        assert_eq!(Bytes::from("hello"), body.unwrap());
+    }
+}
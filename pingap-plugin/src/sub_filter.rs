@@ -190,6 +190,9 @@ impl ModifyResponseBody for SubFilterReplacer {
     fn name(&self) -> String {
         "sub_filter".to_string()
     }
+    fn buffering(&self) -> bool {
+        true
+    }
 }
 
 impl TryFrom<&PluginConf> for SubFilter {
@@ -296,6 +299,10 @@ impl Plugin for SubFilter {
         }
 
         if is_matched {
+            // the response is too large to be buffered
+            if !ctx.can_buffer_response(&upstream_response.headers)? {
+                return Ok(ResponsePluginResult::Unchanged);
+            }
             // Remove content-length since we're modifying the body
             upstream_response.remove_header(&http::header::CONTENT_LENGTH);
             // Switch to chunked transfer encoding
@@ -0,0 +1,348 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_duration_conf, get_hash_key, get_int_conf_or_default,
+    get_plugin_factory, get_str_slice_conf,
+};
+use async_trait::async_trait;
+use ctor::ctor;
+use glob::Pattern;
+use http::StatusCode;
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, HttpResponse, Plugin, PluginStep, RequestPluginResult, get_client_ip,
+};
+use pingap_util::IpRules;
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{debug, info};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Tarpit plugin slows down the scanners and abusers, the requests of
+/// bad paths or blocked ips are delayed before responding a minimal error,
+/// so the attacker is tied up while the delayed request costs nothing but
+/// a timer. The tarpitted requests are bounded by `max_tarpitted`, the
+/// requests beyond it are responded immediately, so the plugin can't
+/// exhaust the connections of pingap.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// paths = ["/wp-admin*", "*.php", "/.env"]
+/// ip_list = ["192.168.1.1", "10.0.0.0/24"]
+/// delay = "10s"
+/// max_tarpitted = 100
+/// status = 403
+/// ```
+pub struct Tarpit {
+    /// The glob patterns of bad paths
+    paths: Vec<Pattern>,
+    /// The blocked ips
+    ip_rules: Option<IpRules>,
+    /// The delay before responding
+    delay: Duration,
+    /// The max count of requests being tarpitted
+    max_tarpitted: u64,
+    /// The count of requests being tarpitted
+    tarpitted: AtomicU64,
+    status: StatusCode,
+    hash_value: String,
+}
+
+/// Decreases the tarpitted count when the request is responded.
+struct TarpittedGuard<'a>(&'a AtomicU64);
+
+impl Drop for TarpittedGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl TryFrom<&PluginConf> for Tarpit {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::Tarpit.to_string(),
+            message,
+        };
+        let paths = get_str_slice_conf(value, "paths")
+            .iter()
+            .map(|path| {
+                Pattern::new(path).map_err(|e| new_invalid_error(e.to_string()))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let ip_list = get_str_slice_conf(value, "ip_list");
+        if paths.is_empty() && ip_list.is_empty() {
+            return Err(new_invalid_error(
+                "paths or ip_list should be set".to_string(),
+            ));
+        }
+        let status = get_int_conf_or_default(value, "status", 403);
+        let status = u16::try_from(status)
+            .ok()
+            .and_then(|status| StatusCode::from_u16(status).ok())
+            .filter(|status| {
+                status.is_client_error() || status.is_server_error()
+            })
+            .ok_or_else(|| {
+                new_invalid_error(format!(
+                    "status({status}) should be 4xx or 5xx"
+                ))
+            })?;
+
+        Ok(Self {
+            paths,
+            ip_rules: (!ip_list.is_empty()).then(|| IpRules::new(&ip_list)),
+            delay: get_duration_conf(value, "delay")
+                .unwrap_or(Duration::from_secs(10)),
+            max_tarpitted: get_int_conf_or_default(value, "max_tarpitted", 100)
+                .max(0) as u64,
+            tarpitted: AtomicU64::new(0),
+            status,
+            hash_value,
+        })
+    }
+}
+
+impl Tarpit {
+    /// Creates a new tarpit plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new tarpit plugin");
+        Self::try_from(params)
+    }
+
+    /// Returns true if the request is from the known-bad client.
+    fn is_match(&self, path: &str, ip: &str) -> bool {
+        if self.paths.iter().any(|pattern| pattern.matches(path)) {
+            return true;
+        }
+        self.ip_rules
+            .as_ref()
+            .is_some_and(|ip_rules| ip_rules.is_match(ip).unwrap_or_default())
+    }
+
+    /// Acquires a tarpit slot, returns none if the tarpitted requests
+    /// reach the max count.
+    fn acquire(&self) -> Option<TarpittedGuard<'_>> {
+        self.tarpitted
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |count| {
+                (count < self.max_tarpitted).then_some(count + 1)
+            })
+            .ok()
+            .map(|_| TarpittedGuard(&self.tarpitted))
+    }
+}
+
+#[async_trait]
+impl Plugin for Tarpit {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Delays the matched request before responding the error,
+    /// the connection isn't reused.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let ip = ctx
+            .conn
+            .client_ip
+            .get_or_insert_with(|| get_client_ip(session));
+        if !self.is_match(session.req_header().uri.path(), ip) {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        if let Some(_guard) = self.acquire() {
+            info!(
+                ip = ip.as_str(),
+                path = session.req_header().uri.path(),
+                delay = format!("{:?}", self.delay),
+                "tarpit request"
+            );
+            sleep(self.delay).await;
+        }
+        session.set_keepalive(None);
+        Ok(RequestPluginResult::Respond(HttpResponse {
+            status: self.status,
+            ..Default::default()
+        }))
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory()
+        .register("tarpit", |params| Ok(Arc::new(Tarpit::new(params)?)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use std::time::Instant;
+    use tokio_test::io::Builder;
+
+    fn new_tarpit(params: &str) -> Tarpit {
+        Tarpit::new(&toml::from_str::<PluginConf>(params).unwrap()).unwrap()
+    }
+
+    async fn handle(
+        plugin: &Tarpit,
+        path: &str,
+        ip: &str,
+    ) -> RequestPluginResult {
+        let input_header = format!(
+            "GET {path} HTTP/1.1\r\nHost: pingap.io\r\nX-Forwarded-For: {ip}\r\n\r\n"
+        );
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_tarpit_params() {
+        let plugin = new_tarpit(
+            r###"
+paths = ["/wp-admin*", "*.php"]
+"###,
+        );
+        assert_eq!(2, plugin.paths.len());
+        assert_eq!(true, plugin.ip_rules.is_none());
+        assert_eq!(Duration::from_secs(10), plugin.delay);
+        assert_eq!(100, plugin.max_tarpitted);
+        assert_eq!(StatusCode::FORBIDDEN, plugin.status);
+
+        let result = Tarpit::try_from(&PluginConf::default());
+        assert_eq!(
+            "Plugin tarpit invalid, message: paths or ip_list should be set",
+            result.err().unwrap().to_string()
+        );
+
+        let result = Tarpit::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+ip_list = ["10.0.0.0/24"]
+status = 200
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin tarpit invalid, message: status(200) should be 4xx or 5xx",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_tarpit_match() {
+        let plugin = new_tarpit(
+            r###"
+paths = ["/wp-admin*", "*.php", "/.env"]
+ip_list = ["10.0.0.0/24"]
+"###,
+        );
+        assert_eq!(true, plugin.is_match("/wp-admin/setup", "1.1.1.1"));
+        assert_eq!(true, plugin.is_match("/xmlrpc.php", "1.1.1.1"));
+        assert_eq!(true, plugin.is_match("/.env", "1.1.1.1"));
+        assert_eq!(true, plugin.is_match("/users", "10.0.0.8"));
+        assert_eq!(false, plugin.is_match("/users", "1.1.1.1"));
+        assert_eq!(false, plugin.is_match("/users", "invalid"));
+    }
+
+    #[tokio::test]
+    async fn test_tarpit() {
+        let plugin = new_tarpit(
+            r###"
+paths = ["*.php"]
+ip_list = ["10.0.0.0/24"]
+delay = "200ms"
+status = 429
+"###,
+        );
+
+        // the matched request is delayed
+        let start = Instant::now();
+        let RequestPluginResult::Respond(resp) =
+            handle(&plugin, "/xmlrpc.php", "1.1.1.1").await
+        else {
+            panic!("the request should be tarpitted");
+        };
+        assert_eq!(true, start.elapsed() >= Duration::from_millis(200));
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, resp.status);
+        assert_eq!(true, resp.body.is_empty());
+        assert_eq!(0, plugin.tarpitted.load(Ordering::Relaxed));
+
+        let start = Instant::now();
+        let result = handle(&plugin, "/users", "10.0.0.8").await;
+        assert_eq!(true, matches!(result, RequestPluginResult::Respond(_)));
+        assert_eq!(true, start.elapsed() >= Duration::from_millis(200));
+
+        // the non-matching request passes through immediately
+        let start = Instant::now();
+        let result = handle(&plugin, "/users", "1.1.1.1").await;
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+        assert_eq!(true, start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn test_tarpit_bounded() {
+        let plugin = new_tarpit(
+            r###"
+paths = ["*.php"]
+delay = "300ms"
+max_tarpitted = 1
+"###,
+        );
+        let start = Instant::now();
+        let (first, second) =
+            tokio::join!(handle(&plugin, "/index.php", "1.1.1.1"), async {
+                // wait until the first request is tarpitted
+                sleep(Duration::from_millis(50)).await;
+                let start = Instant::now();
+                let result = handle(&plugin, "/index.php", "1.1.1.1").await;
+                (result, start.elapsed())
+            });
+        assert_eq!(true, matches!(first, RequestPluginResult::Respond(_)));
+        assert_eq!(true, start.elapsed() >= Duration::from_millis(300));
+        // the request beyond the max count is responded immediately
+        let (second, elapsed) = second;
+        assert_eq!(true, matches!(second, RequestPluginResult::Respond(_)));
+        assert_eq!(true, elapsed < Duration::from_millis(100));
+        assert_eq!(0, plugin.tarpitted.load(Ordering::Relaxed));
+    }
+}
@@ -0,0 +1,467 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::jwt::JWT_CLAIM_VARIABLE_PREFIX;
+use super::{
+    Error, get_hash_key, get_plugin_factory, get_str_conf, get_str_slice_conf,
+};
+use ahash::AHashMap;
+use async_trait::async_trait;
+use ctor::ctor;
+use http::{HeaderValue, StatusCode, header};
+use humantime::parse_duration;
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, HttpResponse, Plugin, PluginStep, Rate, RequestPluginResult,
+    get_req_header_value,
+};
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// The rate limit of a tier, e.g. `free 10/1m`
+struct Tier {
+    name: String,
+    /// Maximum number of requests within the interval
+    max: isize,
+    interval: Duration,
+    rate: Rate,
+}
+
+impl Tier {
+    /// Parses the tier from `name max/interval`, e.g. `pro 100/1m`
+    fn parse(value: &str) -> Option<Self> {
+        let (name, limit) = value.trim().split_once(' ')?;
+        let (max, interval) = limit.trim().split_once('/')?;
+        let max = max.trim().parse::<isize>().ok()?;
+        let interval = parse_duration(interval.trim()).ok()?;
+        if interval.is_zero() {
+            return None;
+        }
+        Some(Self {
+            name: name.to_string(),
+            max,
+            interval,
+            rate: Rate::new(interval),
+        })
+    }
+}
+
+/// TierLimit plugin limits the requests of authenticated identity,
+/// the limit is looked up by the tier of identity, e.g. free vs pro.
+/// The identity is the verified claim of jwt plugin(executed before this
+/// plugin) or the value of request header(e.g. api key).
+///
+/// The tier mapping is part of the plugin config, so it's reloaded
+/// with the config.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// # identity from the jwt claim, default is `sub`
+/// claim = "sub"
+/// # or identity from the request header, e.g. api key
+/// # header = "X-Api-Key"
+/// # tier limits in `name max/interval` format
+/// tiers = ["free 10/1m", "pro 1000/1m"]
+/// # identity to tier mapping in `identity:tier` format
+/// members = ["alice:pro"]
+/// # jwt claim of the tier, it takes precedence over the members
+/// tier_claim = "plan"
+/// # tier of the unmapped identity, default is the first tier
+/// default_tier = "free"
+/// ```
+pub struct TierLimit {
+    /// The jwt claim used as identity
+    claim: String,
+    /// The request header used as identity, it takes precedence over claim
+    header: Option<String>,
+    /// The jwt claim of the tier
+    tier_claim: Option<String>,
+    tiers: Vec<Tier>,
+    /// Identity to the index of tier
+    members: AHashMap<String, usize>,
+    /// The index of default tier
+    default_tier: usize,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for TierLimit {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::TierLimit.to_string(),
+            message,
+        };
+        let mut tiers: Vec<Tier> = vec![];
+        for item in get_str_slice_conf(value, "tiers") {
+            let tier = Tier::parse(&item).ok_or_else(|| {
+                new_invalid_error(format!(
+                    "tier({item}) should be in `name max/interval` format"
+                ))
+            })?;
+            tiers.push(tier);
+        }
+        if tiers.is_empty() {
+            return Err(new_invalid_error("tiers can't be empty".to_string()));
+        }
+        let get_tier_index =
+            |name: &str| {
+                tiers.iter().position(|tier| tier.name == name).ok_or_else(
+                    || new_invalid_error(format!("tier({name}) is not found")),
+                )
+            };
+
+        let mut members = AHashMap::new();
+        for item in get_str_slice_conf(value, "members") {
+            let Some((identity, tier)) = item.split_once(':') else {
+                return Err(new_invalid_error(format!(
+                    "member({item}) should be in `identity:tier` format"
+                )));
+            };
+            members.insert(
+                identity.trim().to_string(),
+                get_tier_index(tier.trim())?,
+            );
+        }
+        let default_tier = get_str_conf(value, "default_tier");
+        let default_tier = if default_tier.is_empty() {
+            0
+        } else {
+            get_tier_index(&default_tier)?
+        };
+
+        let mut claim = get_str_conf(value, "claim");
+        if claim.is_empty() {
+            claim = "sub".to_string();
+        }
+        let header = get_str_conf(value, "header");
+        let tier_claim = get_str_conf(value, "tier_claim");
+
+        Ok(Self {
+            claim,
+            header: (!header.is_empty()).then_some(header),
+            tier_claim: (!tier_claim.is_empty()).then_some(tier_claim),
+            tiers,
+            members,
+            default_tier,
+            hash_value,
+        })
+    }
+}
+
+impl TierLimit {
+    /// Creates a new tier limit plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new tier limit plugin");
+        Self::try_from(params)
+    }
+
+    /// Gets the identity from the request header or the jwt claim variable
+    fn get_identity(&self, session: &Session, ctx: &Ctx) -> Option<String> {
+        let identity = if let Some(header) = &self.header {
+            get_req_header_value(session.req_header(), header)
+        } else {
+            ctx.get_variable(&format!(
+                "{JWT_CLAIM_VARIABLE_PREFIX}{}",
+                self.claim
+            ))
+        };
+        identity
+            .filter(|value| !value.is_empty())
+            .map(|value| value.to_string())
+    }
+
+    /// Gets the tier of identity, the tier claim takes precedence over
+    /// the members mapping, then the default tier is used.
+    fn get_tier(&self, identity: &str, ctx: &Ctx) -> &Tier {
+        let tier_of_claim = self.tier_claim.as_ref().and_then(|tier_claim| {
+            let name = ctx.get_variable(&format!(
+                "{JWT_CLAIM_VARIABLE_PREFIX}{tier_claim}"
+            ))?;
+            self.tiers.iter().find(|tier| tier.name == name)
+        });
+        tier_of_claim.unwrap_or_else(|| {
+            let index = self
+                .members
+                .get(identity)
+                .copied()
+                .unwrap_or(self.default_tier);
+            &self.tiers[index]
+        })
+    }
+}
+
+#[async_trait]
+impl Plugin for TierLimit {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Counts the request of identity in the window of its tier, returns
+    /// 429 with the `Retry-After` of tier interval if the limit is exceeded.
+    /// The request without identity is skipped.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let Some(identity) = self.get_identity(session, ctx) else {
+            return Ok(RequestPluginResult::Skipped);
+        };
+        let tier = self.get_tier(&identity, ctx);
+        let value = tier.rate.observe(&identity, 1);
+        if value <= tier.max {
+            return Ok(RequestPluginResult::Continue);
+        }
+        let retry_after = tier.interval.as_secs().max(1);
+        Ok(RequestPluginResult::Respond(HttpResponse {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            body: Error::Exceed {
+                category: format!(
+                    "{}({})",
+                    PluginCategory::TierLimit,
+                    tier.name
+                ),
+                max: tier.max as f64,
+                value: value as f64,
+            }
+            .to_string()
+            .into(),
+            headers: Some(vec![(
+                header::RETRY_AFTER,
+                HeaderValue::from(retry_after),
+            )]),
+            ..Default::default()
+        }))
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory()
+        .register("tier_limit", |params| Ok(Arc::new(TierLimit::new(params)?)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pretty_assertions::assert_eq;
+
+    fn new_ctx(claims: &[(&str, &str)]) -> Ctx {
+        let mut ctx = Ctx::default();
+        for (key, value) in claims {
+            ctx.add_variable(
+                &format!("{JWT_CLAIM_VARIABLE_PREFIX}{key}"),
+                value,
+            );
+        }
+        ctx
+    }
+
+    async fn handle(limit: &TierLimit, identity: &str) -> RequestPluginResult {
+        let mut session =
+            test_util::new_session("GET /api HTTP/1.1\r\n\r\n").await;
+        limit
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut new_ctx(&[("sub", identity)]),
+            )
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_tier_limit_params() {
+        let limit = TierLimit::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+tiers = ["free 10/1m", "pro 1000/10s"]
+members = ["alice:pro"]
+tier_claim = "plan"
+default_tier = "free"
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+        assert_eq!("sub", limit.claim);
+        assert_eq!(Some("plan".to_string()), limit.tier_claim);
+        assert_eq!(
+            vec![
+                ("free".to_string(), 10, Duration::from_secs(60)),
+                ("pro".to_string(), 1000, Duration::from_secs(10)),
+            ],
+            limit
+                .tiers
+                .iter()
+                .map(|tier| (tier.name.clone(), tier.max, tier.interval))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(Some(&1), limit.members.get("alice"));
+        assert_eq!(0, limit.default_tier);
+
+        let result = TierLimit::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+tiers = ["free 10"]
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin tier_limit invalid, message: tier(free 10) should be in `name max/interval` format",
+            result.err().unwrap().to_string()
+        );
+
+        let result = TierLimit::try_from(
+            &toml::from_str::<PluginConf>(
+                r###"
+tiers = ["free 10/1m"]
+members = ["alice:pro"]
+"###,
+            )
+            .unwrap(),
+        );
+        assert_eq!(
+            "Plugin tier_limit invalid, message: tier(pro) is not found",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tier_limit() {
+        let limit = TierLimit::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+tiers = ["free 1/1m", "pro 3/10s"]
+members = ["alice:pro"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        // bob is in the default free tier
+        assert_eq!(
+            true,
+            handle(&limit, "bob").await == RequestPluginResult::Continue
+        );
+        let RequestPluginResult::Respond(resp) = handle(&limit, "bob").await
+        else {
+            panic!("bob should be limited");
+        };
+        assert_eq!(StatusCode::TOO_MANY_REQUESTS, resp.status);
+        assert_eq!(
+            r#"Some([("retry-after", "60")])"#,
+            format!("{:?}", resp.headers)
+        );
+        assert_eq!(
+            "Plugin tier_limit(free), exceed limit 2/1",
+            std::string::String::from_utf8_lossy(resp.body.as_ref())
+        );
+
+        // alice is in the pro tier with a higher limit
+        for _ in 0..3 {
+            assert_eq!(
+                true,
+                handle(&limit, "alice").await == RequestPluginResult::Continue
+            );
+        }
+        let RequestPluginResult::Respond(resp) = handle(&limit, "alice").await
+        else {
+            panic!("alice should be limited");
+        };
+        assert_eq!(
+            r#"Some([("retry-after", "10")])"#,
+            format!("{:?}", resp.headers)
+        );
+
+        // request without identity is skipped
+        let mut session =
+            test_util::new_session("GET /api HTTP/1.1\r\n\r\n").await;
+        let result = limit
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+    }
+
+    #[tokio::test]
+    async fn test_tier_limit_by_header_and_tier_claim() {
+        let limit = TierLimit::new(
+            &toml::from_str::<PluginConf>(
+                r###"
+header = "X-Api-Key"
+tier_claim = "plan"
+tiers = ["free 1/1m", "pro 2/1m"]
+"###,
+            )
+            .unwrap(),
+        )
+        .unwrap();
+
+        // the tier is from the jwt claim
+        for expected in [true, true, false] {
+            let headers = ["X-Api-Key: key1"].join("\r\n");
+            let input_header =
+                format!("GET /api HTTP/1.1\r\n{headers}\r\n\r\n");
+            let mut session = test_util::new_session(&input_header).await;
+            let result = limit
+                .handle_request(
+                    PluginStep::Request,
+                    &mut session,
+                    &mut new_ctx(&[("plan", "pro")]),
+                )
+                .await
+                .unwrap();
+            assert_eq!(expected, result == RequestPluginResult::Continue);
+        }
+
+        // the default tier is used without tier claim
+        for expected in [true, false] {
+            let headers = ["X-Api-Key: key2"].join("\r\n");
+            let input_header =
+                format!("GET /api HTTP/1.1\r\n{headers}\r\n\r\n");
+            let mut session = test_util::new_session(&input_header).await;
+            let result = limit
+                .handle_request(
+                    PluginStep::Request,
+                    &mut session,
+                    &mut Ctx::default(),
+                )
+                .await
+                .unwrap();
+            assert_eq!(expected, result == RequestPluginResult::Continue);
+        }
+    }
+}
@@ -0,0 +1,271 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_bool_conf, get_hash_key, get_plugin_factory, get_str_conf,
+};
+use async_trait::async_trait;
+use bytes::Bytes;
+use ctor::ctor;
+use http::StatusCode;
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{Ctx, HttpResponse, Plugin, PluginStep, RequestPluginResult};
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::sync::Arc;
+use tracing::{debug, warn};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Converts the tls version to a comparable number, e.g. `TLSv1.2` or
+/// `1.2` to 12. The version string of openssl for TLS 1.0 is `TLSv1`.
+fn parse_tls_version(value: &str) -> Option<u8> {
+    let value = value.trim();
+    let value = value
+        .get(..4)
+        .filter(|prefix| prefix.eq_ignore_ascii_case("tlsv"))
+        .map_or(value, |_| &value[4..]);
+    match value {
+        "1" | "1.0" => Some(10),
+        "1.1" => Some(11),
+        "1.2" => Some(12),
+        "1.3" => Some(13),
+        _ => None,
+    }
+}
+
+/// TlsVersionRestriction plugin requires a minimum negotiated TLS version
+/// of the client connection for the location, e.g. TLS 1.3 for `/admin`
+/// while TLS 1.2 is allowed for other locations. The plain http request is
+/// treated as below the minimum version.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// min_version = "1.3"
+/// message = "TLS 1.3 is required"
+/// # only log the downgrade alert, the request is not rejected
+/// alert_only = false
+/// ```
+pub struct TlsVersionRestriction {
+    /// The minimum tls version, e.g. 13 for TLS 1.3
+    min_version: u8,
+    /// Only log the downgrade alert without rejecting the request
+    alert_only: bool,
+    forbidden_resp: HttpResponse,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for TlsVersionRestriction {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let min_version = get_str_conf(value, "min_version");
+        let Some(min_version) = parse_tls_version(&min_version) else {
+            return Err(Error::Invalid {
+                category: PluginCategory::TlsVersionRestriction.to_string(),
+                message: format!("min version({min_version}) is invalid"),
+            });
+        };
+        let mut message = get_str_conf(value, "message");
+        if message.is_empty() {
+            message = "TLS version is too low".to_string();
+        }
+        Ok(Self {
+            min_version,
+            alert_only: get_bool_conf(value, "alert_only"),
+            forbidden_resp: HttpResponse {
+                status: StatusCode::FORBIDDEN,
+                body: Bytes::from(message),
+                ..Default::default()
+            },
+            hash_value,
+        })
+    }
+}
+
+impl TlsVersionRestriction {
+    /// Creates a new tls version restriction plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(
+            params = params.to_string(),
+            "new tls version restriction plugin"
+        );
+        Self::try_from(params)
+    }
+
+    /// Returns true if the negotiated tls version is below the minimum
+    fn is_below(&self, tls_version: Option<&str>) -> bool {
+        tls_version
+            .and_then(parse_tls_version)
+            .is_none_or(|version| version < self.min_version)
+    }
+}
+
+#[async_trait]
+impl Plugin for TlsVersionRestriction {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Rejects the request if the tls version of connection is below
+    /// the minimum version, or only logs it in alert only mode.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let tls_version = ctx.conn.tls_version.as_deref();
+        if !self.is_below(tls_version) {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        warn!(
+            client_ip = ctx.conn.client_ip.as_deref(),
+            tls_version,
+            path = session.req_header().uri.path(),
+            alert_only = self.alert_only,
+            "tls version is below the minimum version"
+        );
+        if self.alert_only {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        Ok(RequestPluginResult::Respond(self.forbidden_resp.clone()))
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("tls_version_restriction", |params| {
+        Ok(Arc::new(TlsVersionRestriction::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingap_config::PluginConf;
+    use pingap_core::{ConnectionInfo, Ctx, PluginStep};
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    fn new_plugin(conf: &str) -> TlsVersionRestriction {
+        TlsVersionRestriction::new(&toml::from_str::<PluginConf>(conf).unwrap())
+            .unwrap()
+    }
+
+    async fn handle(
+        plugin: &TlsVersionRestriction,
+        tls_version: Option<&str>,
+    ) -> RequestPluginResult {
+        let input_header = "GET /admin HTTP/1.1\r\nHost: pingap.io\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let mut ctx = Ctx {
+            conn: ConnectionInfo {
+                tls_version: tls_version.map(|v| v.to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_tls_version() {
+        assert_eq!(Some(10), parse_tls_version("TLSv1"));
+        assert_eq!(Some(12), parse_tls_version("TLSv1.2"));
+        assert_eq!(Some(13), parse_tls_version("tlsv1.3"));
+        assert_eq!(Some(13), parse_tls_version("1.3"));
+        assert_eq!(None, parse_tls_version("SSLv3"));
+    }
+
+    #[test]
+    fn test_tls_version_restriction_params() {
+        let plugin = new_plugin(
+            r###"
+min_version = "TLSv1.3"
+alert_only = true
+"###,
+        );
+        assert_eq!(13, plugin.min_version);
+        assert_eq!(true, plugin.alert_only);
+        assert_eq!(
+            b"TLS version is too low",
+            plugin.forbidden_resp.body.as_ref()
+        );
+
+        let result = TlsVersionRestriction::try_from(&PluginConf::default());
+        assert_eq!(
+            "Plugin tls_version_restriction invalid, message: min version() is invalid",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_tls_version_restriction() {
+        // the restricted location requires tls 1.3
+        let restricted = new_plugin(r#"min_version = "1.3""#);
+        // other location allows tls 1.2
+        let default = new_plugin(r#"min_version = "1.2""#);
+
+        let RequestPluginResult::Respond(resp) =
+            handle(&restricted, Some("TLSv1.2")).await
+        else {
+            panic!("tls 1.2 should be rejected");
+        };
+        assert_eq!(StatusCode::FORBIDDEN, resp.status);
+        assert_eq!(
+            true,
+            handle(&default, Some("TLSv1.2")).await
+                == RequestPluginResult::Skipped
+        );
+        assert_eq!(
+            true,
+            handle(&restricted, Some("TLSv1.3")).await
+                == RequestPluginResult::Skipped
+        );
+
+        // plain http is below any tls version
+        assert_eq!(
+            true,
+            matches!(
+                handle(&default, None).await,
+                RequestPluginResult::Respond(_)
+            )
+        );
+
+        // alert only mode doesn't reject the request
+        let alert_only = new_plugin(
+            r###"
+min_version = "1.3"
+alert_only = true
+"###,
+        );
+        assert_eq!(
+            true,
+            handle(&alert_only, Some("TLSv1.2")).await
+                == RequestPluginResult::Skipped
+        );
+    }
+}
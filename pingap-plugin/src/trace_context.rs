@@ -0,0 +1,421 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Error, get_hash_key, get_plugin_factory, get_str_slice_conf};
+use async_trait::async_trait;
+use ctor::ctor;
+use http::HeaderName;
+use http::header::HeaderMap;
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{Ctx, Plugin, PluginStep, RequestPluginResult};
+use pingora::proxy::Session;
+use rand::{Rng, rng};
+use std::borrow::Cow;
+use std::sync::Arc;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+static HTTP_HEADER_NAME_TRACEPARENT: HeaderName =
+    HeaderName::from_static("traceparent");
+static HTTP_HEADER_NAME_TRACESTATE: HeaderName =
+    HeaderName::from_static("tracestate");
+static HTTP_HEADER_NAME_B3: HeaderName = HeaderName::from_static("b3");
+static HTTP_HEADER_NAME_X_B3_TRACE_ID: HeaderName =
+    HeaderName::from_static("x-b3-traceid");
+static HTTP_HEADER_NAME_X_B3_SPAN_ID: HeaderName =
+    HeaderName::from_static("x-b3-spanid");
+static HTTP_HEADER_NAME_X_B3_SAMPLED: HeaderName =
+    HeaderName::from_static("x-b3-sampled");
+
+/// TraceContext plugin ensures the trace context headers are forwarded
+/// to the upstream, so the distributed trace isn't broken at the proxy
+/// even without exporting spans. The existing headers are propagated
+/// unchanged, the missing headers are generated(with the trace id of
+/// the other format if it exists).
+///
+/// - `w3c`: `traceparent`, the `tracestate` is propagated as it is
+/// - `b3`: `X-B3-TraceId`, `X-B3-SpanId` and `X-B3-Sampled`, the single
+///   `b3` header is also accepted
+///
+/// # Configuration (TOML)
+/// ```toml
+/// # default is ["w3c"]
+/// propagation = ["w3c", "b3"]
+/// ```
+pub struct TraceContext {
+    w3c: bool,
+    b3: bool,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for TraceContext {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let mut propagation = get_str_slice_conf(value, "propagation");
+        if propagation.is_empty() {
+            propagation.push("w3c".to_string());
+        }
+        let mut w3c = false;
+        let mut b3 = false;
+        for item in propagation.iter() {
+            match item.trim().to_lowercase().as_str() {
+                "w3c" => w3c = true,
+                "b3" => b3 = true,
+                _ => {
+                    return Err(Error::Invalid {
+                        category: PluginCategory::TraceContext.to_string(),
+                        message: format!(
+                            "propagation({item}) should be w3c or b3"
+                        ),
+                    });
+                },
+            }
+        }
+
+        Ok(Self {
+            w3c,
+            b3,
+            hash_value,
+        })
+    }
+}
+
+/// Returns true if the value is lowercase hex of the length
+/// and it's not all zeros.
+fn is_valid_id(value: &str, len: usize) -> bool {
+    value.len() == len
+        && value
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        && value.bytes().any(|b| b != b'0')
+}
+
+/// Generates the random id in lowercase hex of the bytes.
+fn new_id(bytes: usize) -> String {
+    let mut id = String::with_capacity(bytes * 2);
+    let mut rng = rng();
+    while id.len() < bytes * 2 {
+        // avoid the invalid all zeros id
+        let value: u64 = rng.random_range(1..=u64::MAX);
+        id.push_str(&format!("{value:016x}"));
+    }
+    id.truncate(bytes * 2);
+    id
+}
+
+/// Returns the trace id and sampled flag of the valid `traceparent`,
+/// the format is `version-trace_id-parent_id-flags`.
+fn get_w3c_trace(headers: &HeaderMap) -> Option<(String, bool)> {
+    let value = headers.get(&HTTP_HEADER_NAME_TRACEPARENT)?.to_str().ok()?;
+    let mut parts = value.trim().split('-');
+    let version = parts.next()?;
+    let trace_id = parts.next()?;
+    let parent_id = parts.next()?;
+    let flags = parts.next()?;
+    if version.len() != 2
+        || version == "ff"
+        || !is_valid_id(trace_id, 32)
+        || !is_valid_id(parent_id, 16)
+        || flags.len() != 2
+    {
+        return None;
+    }
+    let flags = u8::from_str_radix(flags, 16).ok()?;
+    Some((trace_id.to_string(), flags & 0x01 == 0x01))
+}
+
+/// Returns the trace id(padded to 128 bits) and sampled flag of the
+/// `X-B3-TraceId` or the single `b3` header.
+fn get_b3_trace(headers: &HeaderMap) -> Option<(String, bool)> {
+    let get_value = |name: &HeaderName| {
+        headers.get(name).and_then(|value| value.to_str().ok())
+    };
+    let (trace_id, sampled) =
+        if let Some(value) = get_value(&HTTP_HEADER_NAME_B3) {
+            // b3: trace_id-span_id-sampled-parent_span_id
+            let mut parts = value.trim().split('-');
+            let trace_id = parts.next()?;
+            let _ = parts.next()?;
+            (trace_id, parts.next())
+        } else {
+            (
+                get_value(&HTTP_HEADER_NAME_X_B3_TRACE_ID)?,
+                get_value(&HTTP_HEADER_NAME_X_B3_SAMPLED),
+            )
+        };
+    let trace_id = trace_id.trim();
+    if !is_valid_id(trace_id, 16) && !is_valid_id(trace_id, 32) {
+        return None;
+    }
+    // the sampling decision is deferred if it's not set
+    let sampled = sampled.is_none_or(|value| matches!(value, "1" | "d"));
+    Some((format!("{trace_id:0>32}"), sampled))
+}
+
+impl TraceContext {
+    /// Creates a new trace context plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new trace context plugin");
+        Self::try_from(params)
+    }
+}
+
+#[async_trait]
+impl Plugin for TraceContext {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Generates the missing trace context headers of the request.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        _ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let headers = &session.req_header().headers;
+        let w3c_trace = get_w3c_trace(headers);
+        let b3_trace = get_b3_trace(headers);
+        let w3c_missing = self.w3c && w3c_trace.is_none();
+        let b3_missing = self.b3 && b3_trace.is_none();
+        if !w3c_missing && !b3_missing {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let (trace_id, sampled) =
+            w3c_trace.or(b3_trace).unwrap_or_else(|| (new_id(16), true));
+        debug!(
+            trace_id = trace_id.as_str(),
+            "generate trace context headers"
+        );
+
+        let req_header = session.req_header_mut();
+        if w3c_missing {
+            let flags = if sampled { "01" } else { "00" };
+            let traceparent = format!("00-{trace_id}-{}-{flags}", new_id(8));
+            // the tracestate of invalid traceparent is discarded
+            req_header.remove_header(&HTTP_HEADER_NAME_TRACESTATE);
+            req_header
+                .insert_header(&HTTP_HEADER_NAME_TRACEPARENT, traceparent)?;
+        }
+        if b3_missing {
+            req_header.remove_header(&HTTP_HEADER_NAME_B3);
+            req_header
+                .insert_header(&HTTP_HEADER_NAME_X_B3_TRACE_ID, &trace_id)?;
+            req_header
+                .insert_header(&HTTP_HEADER_NAME_X_B3_SPAN_ID, new_id(8))?;
+            req_header.insert_header(
+                &HTTP_HEADER_NAME_X_B3_SAMPLED,
+                if sampled { "1" } else { "0" },
+            )?;
+        }
+        Ok(RequestPluginResult::Continue)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("trace_context", |params| {
+        Ok(Arc::new(TraceContext::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    fn new_trace_context(params: &str) -> TraceContext {
+        TraceContext::new(&toml::from_str::<PluginConf>(params).unwrap())
+            .unwrap()
+    }
+
+    async fn handle(
+        plugin: &TraceContext,
+        headers: &[&str],
+    ) -> (RequestPluginResult, Session) {
+        let input_header = format!(
+            "GET / HTTP/1.1\r\nHost: pingap.io\r\n{}\r\n",
+            headers
+                .iter()
+                .map(|header| format!("{header}\r\n"))
+                .collect::<String>()
+        );
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let result = plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        (result, session)
+    }
+
+    fn get_header<'a>(session: &'a Session, name: &str) -> &'a str {
+        session
+            .req_header()
+            .headers
+            .get(name)
+            .map(|value| value.to_str().unwrap())
+            .unwrap_or_default()
+    }
+
+    #[test]
+    fn test_trace_context_params() {
+        let plugin = new_trace_context("");
+        assert_eq!(true, plugin.w3c);
+        assert_eq!(false, plugin.b3);
+
+        let plugin = new_trace_context(r#"propagation = ["W3C", "b3"]"#);
+        assert_eq!(true, plugin.w3c);
+        assert_eq!(true, plugin.b3);
+
+        let result = TraceContext::try_from(
+            &toml::from_str::<PluginConf>(r#"propagation = ["jaeger"]"#)
+                .unwrap(),
+        );
+        assert_eq!(
+            "Plugin trace_context invalid, message: propagation(jaeger) should be w3c or b3",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_get_trace() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "traceparent",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00"
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            Some(("4bf92f3577b34da6a3ce929d0e0e4736".to_string(), false)),
+            get_w3c_trace(&headers)
+        );
+        headers.insert(
+            "traceparent",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01"
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(None, get_w3c_trace(&headers));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-b3-traceid", "a3ce929d0e0e4736".parse().unwrap());
+        assert_eq!(
+            Some(("0000000000000000a3ce929d0e0e4736".to_string(), true)),
+            get_b3_trace(&headers)
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            "b3",
+            "80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-0"
+                .parse()
+                .unwrap(),
+        );
+        assert_eq!(
+            Some(("80f198ee56343ba864fe8b2a57d3eff7".to_string(), false)),
+            get_b3_trace(&headers)
+        );
+
+        let id = new_id(16);
+        assert_eq!(true, is_valid_id(&id, 32));
+        assert_eq!(true, is_valid_id(&new_id(8), 16));
+    }
+
+    #[tokio::test]
+    async fn test_trace_context() {
+        let plugin = new_trace_context(r#"propagation = ["w3c", "b3"]"#);
+
+        // the missing trace headers are generated with the same trace id
+        let (result, session) = handle(&plugin, &[]).await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        let (trace_id, sampled) =
+            get_w3c_trace(&session.req_header().headers).unwrap();
+        assert_eq!(true, sampled);
+        assert_eq!(trace_id, get_header(&session, "x-b3-traceid"));
+        assert_eq!(true, is_valid_id(get_header(&session, "x-b3-spanid"), 16));
+        assert_eq!("1", get_header(&session, "x-b3-sampled"));
+
+        // the existing trace headers are propagated unchanged
+        let traceparent =
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let (result, session) = handle(
+            &plugin,
+            &[
+                &format!("traceparent: {traceparent}"),
+                "tracestate: congo=t61rcWkgMzE",
+                "X-B3-TraceId: 4bf92f3577b34da6a3ce929d0e0e4736",
+                "X-B3-SpanId: 00f067aa0ba902b7",
+                "X-B3-Sampled: 1",
+            ],
+        )
+        .await;
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+        assert_eq!(traceparent, get_header(&session, "traceparent"));
+        assert_eq!("congo=t61rcWkgMzE", get_header(&session, "tracestate"));
+        assert_eq!("00f067aa0ba902b7", get_header(&session, "x-b3-spanid"));
+
+        // the b3 headers are generated from the existing traceparent
+        let (result, session) = handle(
+            &plugin,
+            &[
+                "traceparent: 00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00",
+                "tracestate: congo=t61rcWkgMzE",
+            ],
+        )
+        .await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!(
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00",
+            get_header(&session, "traceparent")
+        );
+        assert_eq!("congo=t61rcWkgMzE", get_header(&session, "tracestate"));
+        assert_eq!(
+            "4bf92f3577b34da6a3ce929d0e0e4736",
+            get_header(&session, "x-b3-traceid")
+        );
+        assert_eq!("0", get_header(&session, "x-b3-sampled"));
+
+        // the invalid traceparent is replaced, and tracestate is discarded
+        let plugin = new_trace_context("");
+        let (result, session) = handle(
+            &plugin,
+            &["traceparent: invalid", "tracestate: congo=t61rcWkgMzE"],
+        )
+        .await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!(
+            true,
+            get_w3c_trace(&session.req_header().headers).is_some()
+        );
+        assert_eq!("", get_header(&session, "tracestate"));
+        assert_eq!("", get_header(&session, "x-b3-traceid"));
+    }
+}
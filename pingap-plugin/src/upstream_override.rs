@@ -0,0 +1,251 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_bool_conf, get_hash_key, get_plugin_factory, get_str_conf,
+    get_str_slice_conf,
+};
+use async_trait::async_trait;
+use ctor::ctor;
+use http::HeaderName;
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, Plugin, PluginStep, RequestPluginResult, get_client_ip,
+};
+use pingap_util::IpRules;
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::sync::Arc;
+use tracing::{debug, info};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+const DEFAULT_OVERRIDE_HEADER: &str = "X-Pingap-Upstream";
+
+/// UpstreamOverride plugin forces the upstream of location by the request
+/// header for debugging, e.g. `X-Pingap-Upstream: canary`. It's disabled
+/// by default, and only the request from the trusted ip list can override
+/// the upstream, the header of other requests is ignored. The override
+/// header is removed before the request is forwarded to upstream.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// enabled = true
+/// header = "X-Pingap-Upstream"
+/// ip_list = ["10.0.0.0/8"]
+/// # the upstreams which can be selected, empty means any upstream
+/// upstreams = ["canary"]
+/// ```
+pub struct UpstreamOverride {
+    enabled: bool,
+    header: HeaderName,
+    ip_rules: IpRules,
+    upstreams: Vec<String>,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for UpstreamOverride {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let mut header = get_str_conf(value, "header");
+        if header.is_empty() {
+            header = DEFAULT_OVERRIDE_HEADER.to_string();
+        }
+        let header =
+            HeaderName::from_str(&header).map_err(|e| Error::Invalid {
+                category: PluginCategory::UpstreamOverride.to_string(),
+                message: format!("header({header}) is invalid, {e}"),
+            })?;
+        let ip_list = get_str_slice_conf(value, "ip_list");
+        let enabled = get_bool_conf(value, "enabled");
+        if enabled && ip_list.is_empty() {
+            return Err(Error::Invalid {
+                category: PluginCategory::UpstreamOverride.to_string(),
+                message: "ip list can't be empty".to_string(),
+            });
+        }
+        Ok(Self {
+            enabled,
+            header,
+            ip_rules: IpRules::new(&ip_list),
+            upstreams: get_str_slice_conf(value, "upstreams"),
+            hash_value,
+        })
+    }
+}
+
+impl UpstreamOverride {
+    /// Creates a new upstream override plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new upstream override plugin");
+        Self::try_from(params)
+    }
+}
+
+#[async_trait]
+impl Plugin for UpstreamOverride {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Sets the upstream of context to the value of override header,
+    /// if the plugin is enabled and the client ip is trusted.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let Some(value) = session.req_header_mut().remove_header(&self.header)
+        else {
+            return Ok(RequestPluginResult::Skipped);
+        };
+        if !self.enabled {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let upstream = value.to_str().unwrap_or_default().trim();
+        if upstream.is_empty()
+            || (!self.upstreams.is_empty()
+                && !self.upstreams.iter().any(|item| item == upstream))
+        {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let ip = ctx
+            .conn
+            .client_ip
+            .get_or_insert_with(|| get_client_ip(session));
+        if !self.ip_rules.is_match(ip).unwrap_or_default() {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        info!(
+            client_ip = ip.as_str(),
+            location = ctx.upstream.location.as_ref(),
+            upstream,
+            "override upstream by request header"
+        );
+        ctx.upstream.name = upstream.into();
+        Ok(RequestPluginResult::Continue)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("upstream_override", |params| {
+        Ok(Arc::new(UpstreamOverride::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingap_config::PluginConf;
+    use pingap_core::{ConnectionInfo, Ctx, PluginStep};
+    use pingora::proxy::Session;
+    use pretty_assertions::assert_eq;
+    use tokio_test::io::Builder;
+
+    fn new_plugin(conf: &str) -> UpstreamOverride {
+        UpstreamOverride::new(&toml::from_str::<PluginConf>(conf).unwrap())
+            .unwrap()
+    }
+
+    async fn handle(
+        plugin: &UpstreamOverride,
+        client_ip: &str,
+        upstream: &str,
+    ) -> (RequestPluginResult, Ctx) {
+        let input_header = format!(
+            "GET /users HTTP/1.1\r\nHost: pingap.io\r\nX-Pingap-Upstream: {upstream}\r\n\r\n"
+        );
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        let mut ctx = Ctx {
+            conn: ConnectionInfo {
+                client_ip: Some(client_ip.to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        // the override header is not forwarded to upstream
+        assert_eq!(
+            true,
+            session
+                .req_header()
+                .headers
+                .get(DEFAULT_OVERRIDE_HEADER)
+                .is_none()
+        );
+        (result, ctx)
+    }
+
+    #[test]
+    fn test_upstream_override_params() {
+        let plugin =
+            UpstreamOverride::try_from(&PluginConf::default()).unwrap();
+        assert_eq!(false, plugin.enabled);
+        assert_eq!("x-pingap-upstream", plugin.header.as_str());
+
+        let result = UpstreamOverride::try_from(
+            &toml::from_str::<PluginConf>("enabled = true").unwrap(),
+        );
+        assert_eq!(
+            "Plugin upstream_override invalid, message: ip list can't be empty",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upstream_override() {
+        let plugin = new_plugin(
+            r###"
+enabled = true
+ip_list = ["10.0.0.0/8"]
+upstreams = ["canary"]
+"###,
+        );
+
+        // the trusted ip routes to the named upstream
+        let (result, ctx) = handle(&plugin, "10.1.1.1", "canary").await;
+        assert_eq!(true, result == RequestPluginResult::Continue);
+        assert_eq!("canary", ctx.upstream.name.as_ref());
+
+        // the untrusted ip is ignored
+        let (result, ctx) = handle(&plugin, "192.168.1.1", "canary").await;
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+        assert_eq!("", ctx.upstream.name.as_ref());
+
+        // the upstream is not in the allowed list
+        let (result, ctx) = handle(&plugin, "10.1.1.1", "charts").await;
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+        assert_eq!("", ctx.upstream.name.as_ref());
+
+        // the plugin is disabled
+        let plugin = new_plugin(r#"ip_list = ["10.0.0.0/8"]"#);
+        let (result, ctx) = handle(&plugin, "10.1.1.1", "canary").await;
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+        assert_eq!("", ctx.upstream.name.as_ref());
+    }
+}
@@ -0,0 +1,447 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_duration_conf, get_hash_key, get_int_conf_or_default,
+    get_plugin_factory, get_str_slice_conf,
+};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use ctor::ctor;
+use http::{Method, StatusCode, header};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, Plugin, PluginStep, RequestPluginResult, ResponsePluginResult,
+};
+use pingora::http::ResponseHeader;
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Parses the `Retry-After` header, which is delay seconds or http date
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    let seconds = date.timestamp() - Utc::now().timestamp();
+    Some(Duration::from_secs(seconds.max(0) as u64))
+}
+
+/// UpstreamRetry plugin retries the whole request when the upstream
+/// responds a retryable status(e.g. 502/503/429), it's different from the
+/// retry of connection failure. Only the idempotent methods are retried by
+/// default, other methods should be allowed explicitly. The delay between
+/// attempts is increased exponentially from `backoff` to `max_backoff`,
+/// and the `Retry-After` of 429 response is honored, the response is passed
+/// to the client if the `Retry-After` is greater than `max_backoff`.
+/// The request body is buffered for resending, the request whose body
+/// exceeds the retry buffer is not retried.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// statuses = [502, 503, 429]
+/// # the total attempts including the first request
+/// max_attempts = 3
+/// backoff = "100ms"
+/// max_backoff = "5s"
+/// # allow the non idempotent method explicitly
+/// methods = ["GET", "HEAD", "OPTIONS", "PUT", "DELETE", "POST"]
+/// ```
+pub struct UpstreamRetry {
+    statuses: Vec<StatusCode>,
+    methods: Vec<Method>,
+    max_attempts: u8,
+    backoff: Duration,
+    max_backoff: Duration,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for UpstreamRetry {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let new_invalid_error = |message: String| Error::Invalid {
+            category: PluginCategory::UpstreamRetry.to_string(),
+            message,
+        };
+        let mut statuses = vec![];
+        if let Some(values) = value.get("statuses").and_then(|v| v.as_array()) {
+            for item in values {
+                let status = item
+                    .as_integer()
+                    .and_then(|v| u16::try_from(v).ok())
+                    .and_then(|v| StatusCode::from_u16(v).ok())
+                    .ok_or_else(|| {
+                        new_invalid_error(format!("status({item}) is invalid"))
+                    })?;
+                statuses.push(status);
+            }
+        }
+        if statuses.is_empty() {
+            statuses = vec![
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::TOO_MANY_REQUESTS,
+            ];
+        }
+
+        let methods = get_str_slice_conf(value, "methods");
+        let methods = if methods.is_empty() {
+            // the idempotent methods of rfc 9110
+            vec![
+                Method::GET,
+                Method::HEAD,
+                Method::OPTIONS,
+                Method::TRACE,
+                Method::PUT,
+                Method::DELETE,
+            ]
+        } else {
+            methods
+                .iter()
+                .map(|item| {
+                    Method::from_str(&item.trim().to_uppercase()).map_err(|e| {
+                        new_invalid_error(format!(
+                            "method({item}) is invalid, {e}"
+                        ))
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?
+        };
+
+        let max_attempts = get_int_conf_or_default(value, "max_attempts", 3);
+        let Ok(max_attempts) = u8::try_from(max_attempts) else {
+            return Err(new_invalid_error(format!(
+                "max attempts({max_attempts}) is invalid"
+            )));
+        };
+        if max_attempts == 0 {
+            return Err(new_invalid_error(
+                "max attempts should be greater than 0".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            statuses,
+            methods,
+            max_attempts,
+            backoff: get_duration_conf(value, "backoff")
+                .unwrap_or(Duration::from_millis(100)),
+            max_backoff: get_duration_conf(value, "max_backoff")
+                .unwrap_or(Duration::from_secs(5)),
+            hash_value,
+        })
+    }
+}
+
+impl UpstreamRetry {
+    /// Creates a new upstream retry plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new upstream retry plugin");
+        Self::try_from(params)
+    }
+
+    /// Gets the delay before the next attempt, the `Retry-After` of 429
+    /// response is used if it exists. Returns None if the delay exceeds
+    /// the max backoff.
+    fn get_delay(
+        &self,
+        upstream_response: &ResponseHeader,
+        retries: u8,
+    ) -> Option<Duration> {
+        let retry_after =
+            if upstream_response.status == StatusCode::TOO_MANY_REQUESTS {
+                upstream_response
+                    .headers
+                    .get(header::RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(parse_retry_after)
+            } else {
+                None
+            };
+        let Some(retry_after) = retry_after else {
+            let delay = self
+                .backoff
+                .saturating_mul(2_u32.saturating_pow(retries as u32));
+            return Some(delay.min(self.max_backoff));
+        };
+        (retry_after <= self.max_backoff).then_some(retry_after)
+    }
+}
+
+#[async_trait]
+impl Plugin for UpstreamRetry {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Buffers the request body of retryable method, so the request can
+    /// be resent to upstream.
+    #[inline]
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        _ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request
+            || !self.methods.contains(&session.req_header().method)
+        {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        session.enable_retry_buffering();
+        Ok(RequestPluginResult::Continue)
+    }
+
+    /// Sets the retry delay of upstream if the response status is
+    /// retryable, then the proxy retries the request after the delay.
+    #[inline]
+    fn handle_upstream_response(
+        &self,
+        session: &mut Session,
+        ctx: &mut Ctx,
+        upstream_response: &mut ResponseHeader,
+    ) -> pingora::Result<ResponsePluginResult> {
+        if !self.statuses.contains(&upstream_response.status)
+            || !self.methods.contains(&session.req_header().method)
+            || ctx.upstream.status_retries + 1 >= self.max_attempts
+        {
+            return Ok(ResponsePluginResult::Unchanged);
+        }
+        // the request body can't be resent
+        if session.retry_buffer_truncated() {
+            debug!(
+                status = upstream_response.status.as_u16(),
+                "request body exceeds the retry buffer, skip retry"
+            );
+            return Ok(ResponsePluginResult::Unchanged);
+        }
+        let Some(delay) =
+            self.get_delay(upstream_response, ctx.upstream.status_retries)
+        else {
+            return Ok(ResponsePluginResult::Unchanged);
+        };
+        ctx.upstream.status_retries += 1;
+        ctx.upstream.retry_delay = Some(delay);
+        Ok(ResponsePluginResult::Unchanged)
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("upstream_retry", |params| {
+        Ok(Arc::new(UpstreamRetry::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pretty_assertions::assert_eq;
+
+    fn new_plugin(conf: &str) -> UpstreamRetry {
+        UpstreamRetry::new(&toml::from_str::<PluginConf>(conf).unwrap())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_upstream_retry_params() {
+        let plugin = UpstreamRetry::try_from(&PluginConf::default()).unwrap();
+        assert_eq!(
+            vec![
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::TOO_MANY_REQUESTS,
+            ],
+            plugin.statuses
+        );
+        assert_eq!(false, plugin.methods.contains(&Method::POST));
+        assert_eq!(3, plugin.max_attempts);
+        assert_eq!(Duration::from_millis(100), plugin.backoff);
+        assert_eq!(Duration::from_secs(5), plugin.max_backoff);
+
+        let plugin = new_plugin(
+            r###"
+statuses = [504]
+methods = ["post"]
+max_attempts = 2
+backoff = "1s"
+max_backoff = "2s"
+"###,
+        );
+        assert_eq!(vec![StatusCode::GATEWAY_TIMEOUT], plugin.statuses);
+        assert_eq!(vec![Method::POST], plugin.methods);
+        assert_eq!(2, plugin.max_attempts);
+        assert_eq!(Duration::from_secs(1), plugin.backoff);
+        assert_eq!(Duration::from_secs(2), plugin.max_backoff);
+
+        let result = UpstreamRetry::try_from(
+            &toml::from_str::<PluginConf>("statuses = [1000]").unwrap(),
+        );
+        assert_eq!(
+            "Plugin upstream_retry invalid, message: status(1000) is invalid",
+            result.err().unwrap().to_string()
+        );
+
+        let result = UpstreamRetry::try_from(
+            &toml::from_str::<PluginConf>("max_attempts = 0").unwrap(),
+        );
+        assert_eq!(
+            "Plugin upstream_retry invalid, message: max attempts should be greater than 0",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(Some(Duration::from_secs(2)), parse_retry_after(" 2 "));
+        assert_eq!(
+            Some(Duration::ZERO),
+            parse_retry_after("Tue, 15 Nov 1994 08:12:31 GMT")
+        );
+        assert_eq!(None, parse_retry_after("soon"));
+    }
+
+    #[tokio::test]
+    async fn test_upstream_retry() {
+        let plugin = UpstreamRetry::new(&PluginConf::default()).unwrap();
+
+        // the 503 response is retried, then the 200 response is passed
+        let headers = ["Host: pingap.io", "Content-Length: 0"].join("\r\n");
+        let input_header =
+            format!("GET /api/users HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let mut ctx = Ctx::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Continue);
+
+        let mut upstream_response = ResponseHeader::build(503, None).unwrap();
+        plugin
+            .handle_upstream_response(
+                &mut session,
+                &mut ctx,
+                &mut upstream_response,
+            )
+            .unwrap();
+        assert_eq!(Some(Duration::from_millis(100)), ctx.upstream.retry_delay);
+        assert_eq!(1, ctx.upstream.status_retries);
+
+        // the delay is consumed by proxy before the next attempt
+        ctx.upstream.retry_delay.take();
+        let mut upstream_response = ResponseHeader::build(200, None).unwrap();
+        plugin
+            .handle_upstream_response(
+                &mut session,
+                &mut ctx,
+                &mut upstream_response,
+            )
+            .unwrap();
+        assert_eq!(None, ctx.upstream.retry_delay);
+        assert_eq!(StatusCode::OK, upstream_response.status);
+
+        // the backoff is increased, then it stops at max attempts
+        let mut upstream_response = ResponseHeader::build(502, None).unwrap();
+        plugin
+            .handle_upstream_response(
+                &mut session,
+                &mut ctx,
+                &mut upstream_response,
+            )
+            .unwrap();
+        assert_eq!(Some(Duration::from_millis(200)), ctx.upstream.retry_delay);
+        ctx.upstream.retry_delay.take();
+        plugin
+            .handle_upstream_response(
+                &mut session,
+                &mut ctx,
+                &mut upstream_response,
+            )
+            .unwrap();
+        assert_eq!(None, ctx.upstream.retry_delay);
+
+        // the non idempotent request isn't retried
+        let headers = ["Host: pingap.io", "Content-Length: 0"].join("\r\n");
+        let input_header =
+            format!("POST /api/users HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let mut ctx = Ctx::default();
+        let result = plugin
+            .handle_request(PluginStep::Request, &mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, result == RequestPluginResult::Skipped);
+        let mut upstream_response = ResponseHeader::build(503, None).unwrap();
+        plugin
+            .handle_upstream_response(
+                &mut session,
+                &mut ctx,
+                &mut upstream_response,
+            )
+            .unwrap();
+        assert_eq!(None, ctx.upstream.retry_delay);
+        assert_eq!(0, ctx.upstream.status_retries);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_retry_after() {
+        let plugin = UpstreamRetry::new(&PluginConf::default()).unwrap();
+        let headers = ["Host: pingap.io", "Content-Length: 0"].join("\r\n");
+        let input_header =
+            format!("GET /api/users HTTP/1.1\r\n{headers}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+
+        // the retry after of 429 is honored
+        let mut ctx = Ctx::default();
+        let mut upstream_response = ResponseHeader::build(429, None).unwrap();
+        upstream_response
+            .insert_header(header::RETRY_AFTER, "1")
+            .unwrap();
+        plugin
+            .handle_upstream_response(
+                &mut session,
+                &mut ctx,
+                &mut upstream_response,
+            )
+            .unwrap();
+        assert_eq!(Some(Duration::from_secs(1)), ctx.upstream.retry_delay);
+
+        // the retry after exceeds the max backoff
+        let mut ctx = Ctx::default();
+        upstream_response
+            .insert_header(header::RETRY_AFTER, "3600")
+            .unwrap();
+        plugin
+            .handle_upstream_response(
+                &mut session,
+                &mut ctx,
+                &mut upstream_response,
+            )
+            .unwrap();
+        assert_eq!(None, ctx.upstream.retry_delay);
+    }
+}
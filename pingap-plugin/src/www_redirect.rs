@@ -0,0 +1,359 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{
+    Error, get_hash_key, get_plugin_factory, get_str_conf, get_str_slice_conf,
+};
+use async_trait::async_trait;
+use ctor::ctor;
+use http::{HeaderValue, StatusCode, header};
+use pingap_config::{PluginCategory, PluginConf};
+use pingap_core::{
+    Ctx, HTTP_HEADER_NO_STORE, HttpResponse, Plugin, PluginStep,
+    RequestPluginResult, new_internal_error,
+};
+use pingora::proxy::Session;
+use std::borrow::Cow;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tracing::debug;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+const WWW_PREFIX: &str = "www.";
+
+/// The path prefix of acme http-01 challenge, it's exempt by default
+const ACME_CHALLENGE_PATH: &str = "/.well-known/acme-challenge/";
+
+/// WwwRedirect plugin redirects(301) the requests between `www.` and apex
+/// domains to the canonical host, the path and query are preserved.
+/// The acme challenge path is exempt by default, so the certificates of
+/// both hosts can be issued.
+///
+/// Without the domains, the `www` preference only applies to the host with
+/// two labels(e.g. `pingap.io`), so the other subdomains are not redirected.
+///
+/// # Configuration (TOML)
+/// ```toml
+/// # the canonical host: www or apex
+/// canonical = "www"
+/// # the apex domains, default is all
+/// domains = ["pingap.io"]
+/// # the path prefixes are not redirected
+/// exclude_paths = ["/.well-known/acme-challenge/"]
+/// ```
+pub struct WwwRedirect {
+    www: bool,
+    domains: Vec<String>,
+    exclude_paths: Vec<String>,
+    hash_value: String,
+}
+
+impl TryFrom<&PluginConf> for WwwRedirect {
+    type Error = Error;
+    fn try_from(value: &PluginConf) -> Result<Self> {
+        let hash_value = get_hash_key(value);
+        let www = match get_str_conf(value, "canonical").as_str() {
+            "www" => true,
+            "apex" => false,
+            canonical => {
+                return Err(Error::Invalid {
+                    category: PluginCategory::WwwRedirect.to_string(),
+                    message: format!(
+                        "canonical({canonical}) should be www or apex"
+                    ),
+                });
+            },
+        };
+        let domains = get_str_slice_conf(value, "domains")
+            .iter()
+            .map(|domain| {
+                let domain = domain.trim().to_lowercase();
+                domain
+                    .strip_prefix(WWW_PREFIX)
+                    .map(|domain| domain.to_string())
+                    .unwrap_or(domain)
+            })
+            .filter(|domain| !domain.is_empty())
+            .collect();
+        let mut exclude_paths = get_str_slice_conf(value, "exclude_paths");
+        if value.get("exclude_paths").is_none() {
+            exclude_paths = vec![ACME_CHALLENGE_PATH.to_string()];
+        }
+
+        Ok(Self {
+            www,
+            domains,
+            exclude_paths,
+            hash_value,
+        })
+    }
+}
+
+impl WwwRedirect {
+    /// Creates a new www redirect plugin from the configuration.
+    pub fn new(params: &PluginConf) -> Result<Self> {
+        debug!(params = params.to_string(), "new www redirect plugin");
+        Self::try_from(params)
+    }
+
+    /// Returns the canonical host if the host is not canonical,
+    /// the port of host is preserved.
+    fn get_canonical_host(&self, host: &str) -> Option<String> {
+        let (name, port) = match host.rsplit_once(':') {
+            Some((name, port)) if !name.contains(':') => (name, Some(port)),
+            _ => (host, None),
+        };
+        let name = name.to_lowercase();
+        if name.parse::<IpAddr>().is_ok() {
+            return None;
+        }
+        let apex = name.strip_prefix(WWW_PREFIX);
+        let canonical = match (self.www, apex) {
+            // apex to www
+            (true, None) => {
+                let matched = if self.domains.is_empty() {
+                    name.split('.').count() == 2
+                } else {
+                    self.domains.contains(&name)
+                };
+                if !matched {
+                    return None;
+                }
+                format!("{WWW_PREFIX}{name}")
+            },
+            // www to apex
+            (false, Some(apex)) => {
+                if !apex.contains('.')
+                    || (!self.domains.is_empty()
+                        && !self.domains.iter().any(|domain| domain == apex))
+                {
+                    return None;
+                }
+                apex.to_string()
+            },
+            _ => return None,
+        };
+        Some(match port {
+            Some(port) => format!("{canonical}:{port}"),
+            None => canonical,
+        })
+    }
+}
+
+#[async_trait]
+impl Plugin for WwwRedirect {
+    #[inline]
+    fn config_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(&self.hash_value)
+    }
+
+    /// Redirects the request of non-canonical host to the canonical host.
+    async fn handle_request(
+        &self,
+        step: PluginStep,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<RequestPluginResult> {
+        if step != PluginStep::Request {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        let req_header = session.req_header();
+        let path = req_header.uri.path();
+        if self.exclude_paths.iter().any(|item| path.starts_with(item)) {
+            return Ok(RequestPluginResult::Skipped);
+        }
+        // http2 has the host in the uri
+        let host = req_header
+            .uri
+            .authority()
+            .map(|authority| authority.as_str())
+            .or_else(|| {
+                req_header
+                    .headers
+                    .get(header::HOST)
+                    .and_then(|value| value.to_str().ok())
+            })
+            .unwrap_or_default();
+        let Some(host) = self.get_canonical_host(host) else {
+            return Ok(RequestPluginResult::Skipped);
+        };
+        let scheme = if ctx.conn.tls_version.is_some() {
+            "https"
+        } else {
+            "http"
+        };
+        let path_and_query = req_header
+            .uri
+            .path_and_query()
+            .map_or(path, |value| value.as_str());
+        let location = HeaderValue::from_str(&format!(
+            "{scheme}://{host}{path_and_query}"
+        ))
+        .map_err(|e| new_internal_error(400, e))?;
+        Ok(RequestPluginResult::Respond(HttpResponse {
+            status: StatusCode::MOVED_PERMANENTLY,
+            headers: Some(vec![
+                (header::LOCATION, location),
+                HTTP_HEADER_NO_STORE.clone(),
+            ]),
+            ..Default::default()
+        }))
+    }
+}
+
+#[ctor]
+fn init() {
+    get_plugin_factory().register("www_redirect", |params| {
+        Ok(Arc::new(WwwRedirect::new(params)?))
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util;
+    use pingap_config::PluginConf;
+    use pingap_core::{Ctx, PluginStep};
+    use pretty_assertions::assert_eq;
+
+    fn new_plugin(conf: &str) -> WwwRedirect {
+        WwwRedirect::new(&toml::from_str::<PluginConf>(conf).unwrap()).unwrap()
+    }
+
+    /// Returns the location of redirect, or none if it's not redirected.
+    async fn handle(
+        plugin: &WwwRedirect,
+        host: &str,
+        uri: &str,
+    ) -> Option<String> {
+        let input_header =
+            format!("GET {uri} HTTP/1.1\r\nHost: {host}\r\n\r\n");
+        let mut session = test_util::new_session(&input_header).await;
+        let result = plugin
+            .handle_request(
+                PluginStep::Request,
+                &mut session,
+                &mut Ctx::default(),
+            )
+            .await
+            .unwrap();
+        let RequestPluginResult::Respond(resp) = result else {
+            return None;
+        };
+        assert_eq!(StatusCode::MOVED_PERMANENTLY, resp.status);
+        resp.headers
+            .unwrap_or_default()
+            .into_iter()
+            .find(|(name, _)| *name == header::LOCATION)
+            .map(|(_, value)| value.to_str().unwrap().to_string())
+    }
+
+    #[test]
+    fn test_www_redirect_params() {
+        let plugin = new_plugin(
+            r###"
+canonical = "www"
+domains = ["www.Pingap.io"]
+"###,
+        );
+        assert_eq!(true, plugin.www);
+        assert_eq!(vec!["pingap.io".to_string()], plugin.domains);
+        assert_eq!(vec![ACME_CHALLENGE_PATH.to_string()], plugin.exclude_paths);
+
+        let plugin = new_plugin(
+            r###"
+canonical = "apex"
+exclude_paths = []
+"###,
+        );
+        assert_eq!(false, plugin.www);
+        assert_eq!(true, plugin.exclude_paths.is_empty());
+
+        let result = WwwRedirect::try_from(&PluginConf::default());
+        assert_eq!(
+            "Plugin www_redirect invalid, message: canonical() should be www or apex",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_get_canonical_host() {
+        let plugin = new_plugin(r#"canonical = "www""#);
+        assert_eq!(
+            Some("www.pingap.io".to_string()),
+            plugin.get_canonical_host("Pingap.io")
+        );
+        assert_eq!(
+            Some("www.pingap.io:8080".to_string()),
+            plugin.get_canonical_host("pingap.io:8080")
+        );
+        assert_eq!(None, plugin.get_canonical_host("www.pingap.io"));
+        assert_eq!(None, plugin.get_canonical_host("api.pingap.io"));
+        assert_eq!(None, plugin.get_canonical_host("127.0.0.1:8080"));
+        assert_eq!(None, plugin.get_canonical_host("[::1]:8080"));
+
+        let plugin = new_plugin(
+            r###"
+canonical = "www"
+domains = ["pingap.co.uk"]
+"###,
+        );
+        assert_eq!(
+            Some("www.pingap.co.uk".to_string()),
+            plugin.get_canonical_host("pingap.co.uk")
+        );
+        assert_eq!(None, plugin.get_canonical_host("pingap.io"));
+
+        let plugin = new_plugin(r#"canonical = "apex""#);
+        assert_eq!(
+            Some("pingap.io".to_string()),
+            plugin.get_canonical_host("www.pingap.io")
+        );
+        assert_eq!(None, plugin.get_canonical_host("pingap.io"));
+        assert_eq!(None, plugin.get_canonical_host("www.localhost"));
+    }
+
+    #[tokio::test]
+    async fn test_www_redirect() {
+        let plugin = new_plugin(r#"canonical = "www""#);
+        // the non-canonical host is redirected with path and query
+        assert_eq!(
+            Some("http://www.pingap.io/docs?lang=en".to_string()),
+            handle(&plugin, "pingap.io", "/docs?lang=en").await
+        );
+        assert_eq!(None, handle(&plugin, "www.pingap.io", "/docs").await);
+        // the acme challenge path is exempt
+        assert_eq!(
+            None,
+            handle(&plugin, "pingap.io", "/.well-known/acme-challenge/token")
+                .await
+        );
+
+        let plugin = new_plugin(r#"canonical = "apex""#);
+        assert_eq!(
+            Some("http://pingap.io/".to_string()),
+            handle(&plugin, "www.pingap.io", "/").await
+        );
+        assert_eq!(
+            None,
+            handle(
+                &plugin,
+                "www.pingap.io",
+                "/.well-known/acme-challenge/token"
+            )
+            .await
+        );
+    }
+}
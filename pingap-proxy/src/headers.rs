@@ -12,40 +12,134 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use http::header::CONNECTION;
+use http::{HeaderName, HeaderValue, Version};
 use pingap_core::{Ctx, convert_header_value};
-use pingora::http::RequestHeader;
+use pingora::http::{RequestHeader, ResponseHeader};
 use pingora::proxy::Session;
 
 /// Sets or appends proxy-related headers before forwarding request
-/// Handles both default reverse proxy headers and custom configured headers
+/// Handles both default reverse proxy headers and custom configured headers,
+/// the headers of upstream are applied after the location's, so the shared
+/// requirements of backend(e.g. auth token) are not overridden.
 #[inline]
 pub fn set_append_proxy_headers(
     session: &Session,
     ctx: &Ctx,
     header: &mut RequestHeader,
 ) {
-    if let Some(location) = &ctx.upstream.location_instance {
-        if let Some(headers) = location.headers() {
-            for (k, v, append) in headers {
-                let value = convert_header_value(v, session, ctx)
-                    .unwrap_or_else(|| v.clone());
-                if *append {
-                    let _ = header.append_header(k, value);
-                } else {
-                    let _ = header.insert_header(k, value);
-                }
+    let location_headers = ctx
+        .upstream
+        .location_instance
+        .as_ref()
+        .and_then(|location| location.headers());
+    let upstream_headers = ctx
+        .upstream
+        .upstream_instance
+        .as_ref()
+        .and_then(|upstream| upstream.request_headers());
+    for headers in [location_headers, upstream_headers].into_iter().flatten() {
+        for (k, v, append) in headers {
+            let value = convert_header_value(v, session, ctx)
+                .unwrap_or_else(|| v.clone());
+            if *append {
+                let _ = header.append_header(k, value);
+            } else {
+                let _ = header.insert_header(k, value);
             }
         }
     }
 }
 
+/// Sets or appends the response headers of upstream, they're applied
+/// before the response plugins of location.
+#[inline]
+pub fn set_append_upstream_response_headers(
+    session: &Session,
+    ctx: &Ctx,
+    header: &mut ResponseHeader,
+) {
+    let Some(headers) = ctx
+        .upstream
+        .upstream_instance
+        .as_ref()
+        .and_then(|upstream| upstream.response_headers())
+    else {
+        return;
+    };
+    for (k, v, append) in headers {
+        let value =
+            convert_header_value(v, session, ctx).unwrap_or_else(|| v.clone());
+        if *append {
+            let _ = header.append_header(k, value);
+        } else {
+            let _ = header.insert_header(k, value);
+        }
+    }
+}
+
+/// Sets the matched route(`location/upstream`) header of upstream request,
+/// so the backend can log which route served the request.
+#[inline]
+pub fn set_route_header(
+    name: &HeaderName,
+    ctx: &Ctx,
+    header: &mut RequestHeader,
+) {
+    let value = format!("{}/{}", ctx.upstream.location, ctx.upstream.name);
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        let _ = header.insert_header(name.clone(), value);
+    }
+}
+
+/// Sets the http version of upstream request translated by plugin.
+/// The connection headers of client are hop-by-hop, so they're removed,
+/// and the HTTP/1.0 request asks for keep-alive to reuse the connection.
+#[inline]
+pub fn set_upstream_version(ctx: &Ctx, header: &mut RequestHeader) {
+    let Some(version) = ctx.upstream.version else {
+        return;
+    };
+    header.set_version(version);
+    header.remove_header(&CONNECTION);
+    header.remove_header("keep-alive");
+    if version == Version::HTTP_10 {
+        let _ = header
+            .insert_header(CONNECTION, HeaderValue::from_static("keep-alive"));
+    }
+}
+
+/// Sets `Connection: close` to the upstream request if the connection
+/// shouldn't be reused, the keepalive of location overrides the upstream's.
+/// It's skipped for HTTP/2 as the connection headers are not allowed.
+#[inline]
+pub fn set_upstream_connection(ctx: &Ctx, header: &mut RequestHeader) {
+    let keepalive = ctx.upstream.keepalive.unwrap_or_else(|| {
+        ctx.upstream
+            .upstream_instance
+            .as_ref()
+            .is_none_or(|upstream| upstream.keepalive())
+    });
+    if keepalive || header.version == Version::HTTP_2 {
+        return;
+    }
+    header.remove_header("keep-alive");
+    let _ = header.insert_header(CONNECTION, HeaderValue::from_static("close"));
+}
+
 #[cfg(test)]
 mod tests {
-    use super::set_append_proxy_headers;
-    use pingap_config::LocationConf;
-    use pingap_core::Ctx;
+    use super::{
+        set_append_proxy_headers, set_append_upstream_response_headers,
+        set_route_header, set_upstream_connection, set_upstream_version,
+    };
+    use http::{HeaderName, StatusCode, Version};
+    use pingap_config::{LocationConf, UpstreamConf};
+    use pingap_core::{Ctx, UpstreamInstance};
     use pingap_location::Location;
-    use pingora::{http::RequestHeader, proxy::Session};
+    use pingap_upstream::Upstream;
+    use pingora::http::{RequestHeader, ResponseHeader};
+    use pingora::proxy::Session;
     use pretty_assertions::assert_eq;
     use std::sync::Arc;
     use tokio_test::io::Builder;
@@ -150,4 +244,273 @@ mod tests {
             header.headers.get("x-trace-id").unwrap().to_str().unwrap()
         );
     }
+
+    #[tokio::test]
+    async fn test_set_append_upstream_headers() {
+        let session = new_session().await;
+        let upstream = Arc::new(
+            Upstream::new(
+                "charts",
+                &UpstreamConf {
+                    addrs: vec!["127.0.0.1:5000".to_string()],
+                    proxy_set_headers: Some(vec![
+                        "Authorization: Bearer abc".to_string(),
+                    ]),
+                    proxy_add_headers: Some(vec!["x-server: 789".to_string()]),
+                    response_set_headers: Some(vec![
+                        "x-upstream: charts".to_string(),
+                    ]),
+                    response_add_headers: Some(vec![
+                        "x-response-id: $hostname".to_string(),
+                    ]),
+                    ..Default::default()
+                },
+                None,
+            )
+            .unwrap(),
+        );
+
+        // the headers of upstream are applied for all the locations
+        for (name, conf) in [
+            (
+                "api",
+                LocationConf {
+                    proxy_set_headers: Some(vec![
+                        "Authorization: Bearer xyz".to_string(),
+                    ]),
+                    proxy_add_headers: Some(vec!["x-server: 123".to_string()]),
+                    ..Default::default()
+                },
+            ),
+            ("web", LocationConf::default()),
+        ] {
+            let mut ctx = Ctx::default();
+            ctx.upstream.location_instance =
+                Some(Arc::new(Location::new(name, &conf).unwrap()));
+            ctx.upstream.upstream_instance = Some(upstream.clone());
+
+            let mut header = RequestHeader::build("GET", b"/", None).unwrap();
+            set_append_proxy_headers(&session, &ctx, &mut header);
+            // the header of upstream is applied after the location's
+            assert_eq!(
+                "Bearer abc",
+                header.headers.get("authorization").unwrap()
+            );
+            let servers = header
+                .headers
+                .get_all("x-server")
+                .iter()
+                .map(|v| v.to_str().unwrap())
+                .collect::<Vec<_>>();
+            if name == "api" {
+                assert_eq!(vec!["123", "789"], servers);
+            } else {
+                assert_eq!(vec!["789"], servers);
+            }
+
+            let mut header = ResponseHeader::build(200, None).unwrap();
+            header.insert_header("x-upstream", "backend").unwrap();
+            set_append_upstream_response_headers(&session, &ctx, &mut header);
+            assert_eq!("charts", header.headers.get("x-upstream").unwrap());
+            assert_eq!(
+                pingap_core::get_hostname(),
+                header.headers.get("x-response-id").unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_set_route_header() {
+        let mut ctx = Ctx::default();
+        ctx.upstream.location = "api".into();
+        ctx.upstream.name = "charts".into();
+        let mut header = RequestHeader::build("GET", b"/", None).unwrap();
+        set_route_header(
+            &HeaderName::from_static("x-pingap-route"),
+            &ctx,
+            &mut header,
+        );
+        assert_eq!(
+            "api/charts",
+            header
+                .headers
+                .get("x-pingap-route")
+                .unwrap()
+                .to_str()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_set_upstream_version() {
+        let mut header = RequestHeader::build("GET", b"/", None).unwrap();
+        header.set_version(Version::HTTP_10);
+        header.insert_header("Connection", "close").unwrap();
+
+        // the version isn't translated
+        let mut ctx = Ctx::default();
+        set_upstream_version(&ctx, &mut header);
+        assert_eq!(Version::HTTP_10, header.version);
+        assert_eq!("close", header.headers.get("connection").unwrap());
+
+        ctx.upstream.version = Some(Version::HTTP_11);
+        set_upstream_version(&ctx, &mut header);
+        assert_eq!(Version::HTTP_11, header.version);
+        assert_eq!(true, header.headers.get("connection").is_none());
+
+        ctx.upstream.version = Some(Version::HTTP_10);
+        set_upstream_version(&ctx, &mut header);
+        assert_eq!(Version::HTTP_10, header.version);
+        assert_eq!("keep-alive", header.headers.get("connection").unwrap());
+    }
+
+    struct TestUpstream {
+        keepalive: bool,
+    }
+
+    impl UpstreamInstance for TestUpstream {
+        fn on_transport_failure(&self, _address: &str) {}
+        fn on_response(&self, _address: &str, _status: StatusCode) {}
+        fn completed(&self) -> i32 {
+            0
+        }
+        fn keepalive(&self) -> bool {
+            self.keepalive
+        }
+    }
+
+    #[test]
+    fn test_set_upstream_connection() {
+        // keep-alive pooling by default
+        let mut ctx = Ctx::default();
+        let mut header = RequestHeader::build("GET", b"/", None).unwrap();
+        set_upstream_connection(&ctx, &mut header);
+        assert_eq!(true, header.headers.get("connection").is_none());
+
+        // the upstream closes the connection per request
+        ctx.upstream.upstream_instance =
+            Some(Arc::new(TestUpstream { keepalive: false }));
+        header.insert_header("Keep-Alive", "timeout=5").unwrap();
+        set_upstream_connection(&ctx, &mut header);
+        assert_eq!("close", header.headers.get("connection").unwrap());
+        assert_eq!(true, header.headers.get("keep-alive").is_none());
+
+        // the location overrides the keepalive of upstream
+        ctx.upstream.keepalive = Some(true);
+        let mut header = RequestHeader::build("GET", b"/", None).unwrap();
+        set_upstream_connection(&ctx, &mut header);
+        assert_eq!(true, header.headers.get("connection").is_none());
+
+        ctx.upstream.upstream_instance =
+            Some(Arc::new(TestUpstream { keepalive: true }));
+        ctx.upstream.keepalive = Some(false);
+        set_upstream_connection(&ctx, &mut header);
+        assert_eq!("close", header.headers.get("connection").unwrap());
+
+        // the translated HTTP/1.0 request asks for keep-alive, it's
+        // overridden by close
+        ctx.upstream.version = Some(Version::HTTP_10);
+        let mut header = RequestHeader::build("GET", b"/", None).unwrap();
+        set_upstream_version(&ctx, &mut header);
+        set_upstream_connection(&ctx, &mut header);
+        assert_eq!("close", header.headers.get("connection").unwrap());
+
+        // the connection header isn't allowed for HTTP/2
+        let mut header = RequestHeader::build("GET", b"/", None).unwrap();
+        header.set_version(Version::HTTP_2);
+        set_upstream_connection(&ctx, &mut header);
+        assert_eq!(true, header.headers.get("connection").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_upstream_connection_reuse() {
+        use pingora::connectors::http::Connector;
+        use pingora::upstreams::peer::{HttpPeer, Peer};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpListener;
+
+        /// Serves the requests of connection until it asks for close,
+        /// returns the address and the count of accepted connections.
+        async fn serve() -> (String, Arc<AtomicUsize>) {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap().to_string();
+            let accepted = Arc::new(AtomicUsize::new(0));
+            let count = accepted.clone();
+            tokio::spawn(async move {
+                while let Ok((mut stream, _)) = listener.accept().await {
+                    count.fetch_add(1, Ordering::Relaxed);
+                    tokio::spawn(async move {
+                        let mut data = vec![];
+                        let mut buf = vec![0; 1024];
+                        loop {
+                            let Ok(size) = stream.read(&mut buf).await else {
+                                return;
+                            };
+                            if size == 0 {
+                                return;
+                            }
+                            data.extend_from_slice(&buf[..size]);
+                            let request =
+                                String::from_utf8_lossy(&data).to_lowercase();
+                            let Some(index) = request.find("\r\n\r\n") else {
+                                continue;
+                            };
+                            let close =
+                                request[..index].contains("connection: close");
+                            data.drain(..index + 4);
+                            let connection =
+                                if close { "close" } else { "keep-alive" };
+                            let resp = format!(
+                                "HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: {connection}\r\n\r\nok"
+                            );
+                            if stream.write_all(resp.as_bytes()).await.is_err()
+                                || close
+                            {
+                                return;
+                            }
+                        }
+                    });
+                }
+            });
+            (addr, accepted)
+        }
+
+        for (keepalive, expected) in [(true, 1), (false, 3)] {
+            let (addr, accepted) = serve().await;
+            let mut ctx = Ctx::default();
+            ctx.upstream.upstream_instance =
+                Some(Arc::new(TestUpstream { keepalive }));
+            let connector = Connector::new(None);
+            let peer = HttpPeer::new(addr, false, "".to_string());
+            for _ in 0..3 {
+                let mut header =
+                    RequestHeader::build("GET", b"/", None).unwrap();
+                header.insert_header("Host", "pingap.io").unwrap();
+                set_upstream_connection(&ctx, &mut header);
+
+                let (mut session, _) =
+                    connector.get_http_session(&peer).await.unwrap();
+                session
+                    .write_request_header(Box::new(header))
+                    .await
+                    .unwrap();
+                session.finish_request_body().await.unwrap();
+                session.read_response_header().await.unwrap();
+                let mut body = vec![];
+                while let Some(data) =
+                    session.read_response_body().await.unwrap()
+                {
+                    body.extend_from_slice(&data);
+                }
+                assert_eq!(b"ok".to_vec(), body);
+                connector
+                    .release_http_session(session, &peer, peer.idle_timeout())
+                    .await;
+            }
+            // the connection is reused in keep-alive mode,
+            // and a new one is accepted per request in close mode
+            assert_eq!(expected, accepted.load(Ordering::Relaxed));
+        }
+    }
 }
@@ -16,18 +16,22 @@ use ahash::AHashMap;
 use std::sync::Arc;
 
 mod headers;
+mod proxy_protocol;
 mod server;
 mod server_conf;
 #[cfg(feature = "tracing")]
 mod tracing;
+mod tunnel;
 static LOG_TARGET: &str = "pingap::proxy";
 
 pub(crate) use headers::*;
+pub use proxy_protocol::*;
 pub use server::*;
 pub use server_conf::*;
 #[allow(unused_imports)]
 #[cfg(feature = "tracing")]
 pub(crate) use tracing::*;
+pub(crate) use tunnel::*;
 
 pub trait ServerLocationsProvider: Send + Sync {
     /// Get the locations of the server
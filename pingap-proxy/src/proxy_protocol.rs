@@ -0,0 +1,268 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::LOG_TARGET;
+use ahash::AHashMap;
+use async_trait::async_trait;
+use pingap_core::{ProxyProtocolMode, read_proxy_protocol};
+use pingap_util::IpRules;
+use pingora::apps::ServerApp;
+use pingora::protocols::Stream;
+use pingora::server::ShutdownWatch;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{debug, error};
+
+/// Timeout of reading the PROXY protocol header of accepted connection
+const PROXY_PROTOCOL_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Source addresses of the PROXY protocol header by the connection id,
+/// the address is removed when the connection is closed.
+#[derive(Default)]
+pub struct ProxyProtocolAddrs {
+    addrs: Mutex<AHashMap<usize, SocketAddr>>,
+}
+
+impl ProxyProtocolAddrs {
+    /// Gets the source address of the connection
+    pub fn get(&self, id: usize) -> Option<SocketAddr> {
+        self.addrs.lock().ok()?.get(&id).copied()
+    }
+    fn insert(&self, id: usize, addr: SocketAddr) {
+        if let Ok(mut addrs) = self.addrs.lock() {
+            addrs.insert(id, addr);
+        }
+    }
+    fn remove(&self, id: usize) {
+        if let Ok(mut addrs) = self.addrs.lock() {
+            addrs.remove(&id);
+        }
+    }
+}
+
+/// ProxyProtocolApp reads the PROXY protocol header of the accepted
+/// connection before the application(http) reads the request, the
+/// connection is closed if the header is required but missing, invalid
+/// or sent by the untrusted source.
+pub struct ProxyProtocolApp<A> {
+    app: Arc<A>,
+    mode: ProxyProtocolMode,
+    /// Trusted sources of the header, all sources are trusted if it's none
+    trusted: Option<IpRules>,
+    addrs: Arc<ProxyProtocolAddrs>,
+}
+
+impl<A> ProxyProtocolApp<A> {
+    pub fn new(
+        app: A,
+        mode: ProxyProtocolMode,
+        trusted: Option<IpRules>,
+        addrs: Arc<ProxyProtocolAddrs>,
+    ) -> Self {
+        Self {
+            app: Arc::new(app),
+            mode,
+            trusted,
+            addrs,
+        }
+    }
+    /// Returns true if the peer of connection is the trusted source
+    fn is_trusted(&self, stream: &Stream) -> bool {
+        let Some(trusted) = &self.trusted else {
+            return true;
+        };
+        stream
+            .get_socket_digest()
+            .and_then(|digest| {
+                digest.peer_addr().and_then(|addr| addr.as_inet()).copied()
+            })
+            .is_some_and(|addr| trusted.is_match_addr(&addr.ip()))
+    }
+}
+
+#[async_trait]
+impl<A> ServerApp for ProxyProtocolApp<A>
+where
+    A: ServerApp + Send + Sync + 'static,
+{
+    async fn process_new(
+        self: &Arc<Self>,
+        mut stream: Stream,
+        shutdown: &ShutdownWatch,
+    ) -> Option<Stream> {
+        if self.mode == ProxyProtocolMode::Off {
+            return self.app.process_new(stream, shutdown).await;
+        }
+        let id = stream.id() as usize;
+        let trusted = self.is_trusted(&stream);
+        match read_proxy_protocol(
+            stream.as_mut(),
+            self.mode,
+            trusted,
+            PROXY_PROTOCOL_READ_TIMEOUT,
+        )
+        .await
+        {
+            Ok(header) => {
+                if let Some(addr) = header.and_then(|header| header.source()) {
+                    debug!(
+                        target: LOG_TARGET,
+                        id,
+                        addr = addr.to_string(),
+                        "read proxy protocol header"
+                    );
+                    self.addrs.insert(id, addr);
+                }
+            },
+            Err(e) => {
+                error!(
+                    target: LOG_TARGET,
+                    error = %e,
+                    "read proxy protocol header fail, the connection is closed"
+                );
+                return None;
+            },
+        }
+        // the reused connection is processed here, so the header is only
+        // read once at the beginning of connection
+        let mut reused = self.app.process_new(stream, shutdown).await;
+        while let Some(stream) = reused {
+            reused = self.app.process_new(stream, shutdown).await;
+        }
+        self.addrs.remove(id);
+        None
+    }
+
+    async fn cleanup(&self) {
+        self.app.cleanup().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingap_core::{ProxyProtocolHeader, ProxyProtocolVersion};
+    use pingora::protocols::l4::stream::Stream as L4Stream;
+    use pretty_assertions::assert_eq;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Responds the source address of PROXY protocol header and the
+    /// request line read from the connection.
+    struct EchoApp {
+        addrs: Arc<ProxyProtocolAddrs>,
+    }
+
+    #[async_trait]
+    impl ServerApp for EchoApp {
+        async fn process_new(
+            self: &Arc<Self>,
+            mut stream: Stream,
+            _shutdown: &ShutdownWatch,
+        ) -> Option<Stream> {
+            let mut buf = vec![0; 16];
+            stream.read_exact(&mut buf).await.ok()?;
+            let addr = self
+                .addrs
+                .get(stream.id() as usize)
+                .map(|addr| addr.to_string())
+                .unwrap_or_default();
+            let resp = format!("{addr} {}", String::from_utf8_lossy(&buf));
+            stream.write_all(resp.as_bytes()).await.ok()?;
+            stream.flush().await.ok()?;
+            None
+        }
+    }
+
+    /// Accepts the connection which sends the data through the app,
+    /// returns the response of connection.
+    async fn request(
+        mode: ProxyProtocolMode,
+        trusted: Option<IpRules>,
+        data: &[u8],
+    ) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        client.write_all(data).await.unwrap();
+        let (stream, _) = listener.accept().await.unwrap();
+
+        let addrs = Arc::new(ProxyProtocolAddrs::default());
+        let app = Arc::new(ProxyProtocolApp::new(
+            EchoApp {
+                addrs: addrs.clone(),
+            },
+            mode,
+            trusted,
+            addrs.clone(),
+        ));
+        let (_tx, shutdown) = tokio::sync::watch::channel(false);
+        let result = app
+            .process_new(Box::new(L4Stream::from(stream)), &shutdown)
+            .await;
+        assert_eq!(true, result.is_none());
+        // the address is removed after the connection is closed
+        assert_eq!(true, addrs.addrs.lock().unwrap().is_empty());
+
+        let mut resp = String::new();
+        client.read_to_string(&mut resp).await.unwrap_or_default();
+        resp
+    }
+
+    #[tokio::test]
+    async fn test_proxy_protocol_app() {
+        let request_line = b"GET / HTTP/1.1\r\n";
+        let mut data = ProxyProtocolHeader::new(
+            ProxyProtocolVersion::V1,
+            "192.168.1.1:3000".parse().unwrap(),
+            "10.0.0.1:80".parse().unwrap(),
+        )
+        .to_bytes();
+        data.extend_from_slice(request_line);
+
+        // the source address of header is used as client address
+        for mode in [ProxyProtocolMode::Required, ProxyProtocolMode::Optional] {
+            assert_eq!(
+                "192.168.1.1:3000 GET / HTTP/1.1\r\n",
+                request(mode, None, &data).await
+            );
+        }
+
+        // the connection without header is rejected if it's required,
+        // the app doesn't read the request
+        assert_eq!(
+            "",
+            request(ProxyProtocolMode::Required, None, request_line).await
+        );
+        // the connection without header is accepted if it's optional
+        assert_eq!(
+            " GET / HTTP/1.1\r\n",
+            request(ProxyProtocolMode::Optional, None, request_line).await
+        );
+        // the header is passed to app if it's off
+        assert_eq!(
+            " PROXY TCP4 192.1",
+            request(ProxyProtocolMode::Off, None, &data).await
+        );
+
+        // the header of untrusted source is rejected
+        let trusted = IpRules::new(&["10.0.0.0/8"]);
+        assert_eq!(
+            "",
+            request(ProxyProtocolMode::Optional, Some(trusted), &data).await
+        );
+    }
+}
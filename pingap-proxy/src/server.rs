@@ -17,27 +17,42 @@ use super::tracing::{
     initialize_telemetry, inject_telemetry_headers, set_otel_request_attrs,
     set_otel_upstream_attrs, update_otel_cache_attrs,
 };
-use super::{LOG_TARGET, ServerConf, set_append_proxy_headers};
+use super::{
+    LOG_TARGET, ProxyProtocolAddrs, ProxyProtocolApp, ServerConf, proxy_tunnel,
+    set_append_proxy_headers, set_append_upstream_response_headers,
+    set_route_header, set_upstream_connection, set_upstream_version,
+};
 use crate::ServerLocationsProvider;
 use async_trait::async_trait;
 use bstr::ByteSlice;
 use bytes::Bytes;
 use bytes::BytesMut;
-use http::StatusCode;
-use pingap_acme::handle_lets_encrypt;
+use http::{HeaderName, HeaderValue, StatusCode};
+use pingap_acme::{
+    WELL_KNOWN_PATH_PREFIX, handle_lets_encrypt, normalize_challenge_prefix,
+};
 use pingap_certificate::CertificateProvider;
-use pingap_certificate::{GlobalCertificate, TlsSettingParams};
-use pingap_config::ConfigManager;
+use pingap_certificate::{
+    GlobalCertificate, SessionResumptionParams, TicketKeys, TlsSettingParams,
+};
+use pingap_config::{
+    AccessLogLevel, AcmeFallback, ConfigManager, FairQueueKey, LocationConf,
+    RequestFraming, UnmatchedAction,
+};
 use pingap_core::BackgroundTask;
 #[cfg(feature = "tracing")]
 use pingap_core::HttpResponse;
 use pingap_core::LocationInstance;
 use pingap_core::PluginProvider;
 use pingap_core::{
-    CompressionStat, Ctx, PluginStep, RequestPluginResult,
-    ResponseBodyPluginResult, ResponsePluginResult, get_cache_key,
+    CompressionStat, Ctx, FairQueue, PluginStep, ProxyProtocolHeader,
+    ProxyProtocolMode, RequestPluginResult, ResponseBodyPluginResult,
+    ResponsePluginResult, SocketOptions, TlsVerifyFailureAction, get_cache_key,
+};
+use pingap_core::{
+    HTTP_HEADER_NAME_X_GENERATE_ETAG, HTTP_HEADER_NAME_X_REQUEST_ID,
+    get_digest_detail, is_draining,
 };
-use pingap_core::{HTTP_HEADER_NAME_X_REQUEST_ID, get_digest_detail};
 use pingap_core::{Plugin, new_internal_error};
 use pingap_location::{Location, LocationProvider};
 use pingap_logger::{Parser, parse_access_log_directive};
@@ -45,10 +60,12 @@ use pingap_logger::{Parser, parse_access_log_directive};
 use pingap_otel::{KeyValue, trace::Span};
 #[cfg(feature = "tracing")]
 use pingap_performance::{
-    Prometheus, new_prometheus, new_prometheus_push_service,
+    Prometheus, PrometheusBuckets, new_prometheus, new_prometheus_push_service,
+    register_prometheus,
 };
 use pingap_performance::{accept_request, end_request};
 use pingap_upstream::{Upstream, UpstreamProvider};
+use pingap_util::IpRules;
 use pingora::apps::HttpServerOptions;
 use pingora::cache::cache_control::DirectiveValue;
 use pingora::cache::cache_control::{CacheControl, InterpretCacheControl};
@@ -66,19 +83,21 @@ use pingora::modules::http::compression::{
 use pingora::modules::http::grpc_web::{GrpcWeb, GrpcWebBridge};
 use pingora::protocols::Digest;
 use pingora::protocols::http::error_resp;
-use pingora::proxy::{FailToProxy, HttpProxy, http_proxy_service};
+use pingora::proxy::{FailToProxy, HttpProxy, http_proxy};
 use pingora::proxy::{ProxyHttp, Session};
 use pingora::server::configuration;
 use pingora::services::listening::Service;
 use pingora::upstreams::peer::{HttpPeer, Peer};
 use scopeguard::defer;
 use snafu::Snafu;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::LazyLock;
 use std::sync::atomic::{AtomicI32, AtomicU64, Ordering};
 use std::time::{Duration, Instant};
 use tokio::sync::mpsc::Sender;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 #[derive(Debug, Snafu)]
 pub enum Error {
@@ -87,6 +106,48 @@ pub enum Error {
 }
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// Name of the location proxying the unmatched request to upstream
+static UNMATCHED_LOCATION: &str = "unmatched";
+
+/// Status of closing the connection without response
+const STATUS_CLOSE_WITHOUT_RESPONSE: u16 = 444;
+
+/// Handler of the request matching no location
+enum UnmatchedHandler {
+    /// Responds the status
+    Status(u16),
+    /// Serves the request by the location
+    Location(String),
+    /// Serves the request by the location proxying to the upstream
+    Upstream(Arc<Location>),
+}
+
+impl UnmatchedHandler {
+    fn new(value: &str) -> Result<Self> {
+        let new_error = |message: String| Error::Common {
+            category: "unmatched_action".to_string(),
+            message,
+        };
+        let action = value
+            .parse::<UnmatchedAction>()
+            .map_err(|e| new_error(e.to_string()))?;
+        let handler = match action {
+            UnmatchedAction::Status(status) => Self::Status(status),
+            UnmatchedAction::Location(name) => Self::Location(name),
+            UnmatchedAction::Upstream(name) => {
+                let conf = LocationConf {
+                    upstream: Some(name),
+                    ..Default::default()
+                };
+                let location = Location::new(UNMATCHED_LOCATION, &conf)
+                    .map_err(|e| new_error(e.to_string()))?;
+                Self::Upstream(Arc::new(location))
+            },
+        };
+        Ok(handler)
+    }
+}
+
 #[inline]
 pub fn get_start_time(started_at: &Instant) -> i32 {
     // the offset of start time
@@ -131,6 +192,9 @@ pub struct Server {
     /// Optional parser for customizing access log format and output
     log_parser: Option<Parser>,
 
+    /// Level of access log, e.g. only logs the failed requests
+    access_log_level: AccessLogLevel,
+
     /// HTML/JSON template used for rendering error responses
     error_template: String,
 
@@ -149,18 +213,28 @@ pub struct Server {
     /// Maximum TLS protocol version to accept
     tls_max_version: Option<String>,
 
+    /// Session resumption settings(tickets, cache and rotating ticket keys)
+    tls_session_resumption: SessionResumptionParams,
+
     /// Whether HTTP/2 protocol is enabled
     enabled_h2: bool,
 
     /// Whether Let's Encrypt certificate automation is enabled
     lets_encrypt_enabled: bool,
 
+    /// Path prefix of the ACME http-01 challenge
+    acme_challenge_prefix: String,
+
     /// Whether to use global certificate store for TLS
     global_certificates: bool,
 
     /// TCP socket configuration options (keepalive, TCP fastopen etc)
     tcp_socket_options: Option<TcpSocketOptions>,
 
+    /// TCP_NODELAY option applied to accepted connections, pingora enables
+    /// it for every connection, so it's only set when configured
+    socket_options: Option<SocketOptions>,
+
     /// Prometheus metrics registry when metrics collection is enabled
     #[cfg(feature = "tracing")]
     prometheus: Option<Arc<Prometheus>>,
@@ -182,10 +256,50 @@ pub struct Server {
     /// Whether to enable server-timing header
     enable_server_timing: bool,
 
+    /// The request header carrying the matched route to upstream
+    route_header: Option<HeaderName>,
+
+    /// Handler of the request matching no location
+    unmatched_handler: Option<UnmatchedHandler>,
+
+    /// Host for the HTTP/1.0 request without host header
+    default_host: Option<HeaderValue>,
+
+    /// Whether to reject the HTTP/1.0 request without host header
+    /// and default host
+    require_host: bool,
+
+    /// Fallback of the tls server when the acme certificate is not yet issued
+    acme_fallback: Option<AcmeFallback>,
+
+    /// Whether the tls server is running in the acme fallback mode
+    acme_fallback_enabled: bool,
+
+    /// Validation of request framing(content-length/transfer-encoding)
+    request_framing: RequestFraming,
+
+    /// PROXY protocol header of the accepted connections
+    proxy_protocol: ProxyProtocolMode,
+
+    /// Trusted sources of the PROXY protocol header, all sources are
+    /// trusted if it's none
+    proxy_protocol_trusted: Option<IpRules>,
+
+    /// Source addresses of the PROXY protocol header by connection id
+    proxy_protocol_addrs: Arc<ProxyProtocolAddrs>,
+
+    /// Fair queue sharing the capacity across the fairness keys
+    fair_queue: Option<Arc<FairQueue>>,
+
+    /// Fairness key of the queued requests
+    fair_queue_key: FairQueueKey,
+
     // downstream read timeout
     downstream_read_timeout: Option<Duration>,
     // downstream write timeout
     downstream_write_timeout: Option<Duration>,
+    // downstream idle timeout(seconds) of keep-alive connection
+    downstream_idle_timeout: Option<u64>,
 
     // server locations
     server_locations_provider: Arc<dyn ServerLocationsProvider>,
@@ -209,7 +323,7 @@ pub struct Server {
 }
 
 pub struct ServerServices {
-    pub lb: Service<HttpProxy<Server>>,
+    pub lb: Service<ProxyProtocolApp<HttpProxy<Server>>>,
 }
 
 const META_DEFAULTS: CacheMetaDefaults =
@@ -238,20 +352,34 @@ impl Server {
     /// - Threading configuration
     pub fn new(conf: &ServerConf, ctx: AppContext) -> Result<Self> {
         debug!(target: LOG_TARGET, config = conf.to_string(), "new server");
+        let access_log_level = conf
+            .access_log_level
+            .as_deref()
+            .map(|value| value.parse::<AccessLogLevel>())
+            .transpose()
+            .map_err(|e| Error::Common {
+                category: "access_log_level".to_string(),
+                message: e.to_string(),
+            })?
+            .unwrap_or_default();
         let mut p = None;
         let (access_log, _) =
             parse_access_log_directive(conf.access_log.as_ref());
         if let Some(access_log) = access_log {
-            p = Some(Parser::from(access_log.as_str()));
+            if access_log_level != AccessLogLevel::Off {
+                p = Some(Parser::from(access_log.as_str()));
+            }
         }
         let tcp_socket_options = if conf.tcp_fastopen.is_some()
             || conf.tcp_keepalive.is_some()
             || conf.reuse_port.is_some()
+            || conf.dscp.is_some()
         {
             let mut opts = TcpSocketOptions::default();
             opts.tcp_fastopen = conf.tcp_fastopen;
             opts.tcp_keepalive.clone_from(&conf.tcp_keepalive);
             opts.so_reuseport = conf.reuse_port;
+            opts.dscp = conf.dscp;
             Some(opts)
         } else {
             None
@@ -262,12 +390,104 @@ impl Server {
         let prometheus = if prometheus_metrics.is_empty() {
             None
         } else {
-            let p = new_prometheus(&conf.name).map_err(|e| Error::Common {
-                category: "prometheus".to_string(),
+            let buckets = PrometheusBuckets {
+                response_time: conf.prometheus_response_time_buckets.clone(),
+                upstream_time: conf.prometheus_upstream_time_buckets.clone(),
+            };
+            let p = new_prometheus(&conf.name, &buckets).map_err(|e| {
+                Error::Common {
+                    category: "prometheus".to_string(),
+                    message: e.to_string(),
+                }
+            })?;
+            let p = Arc::new(p);
+            register_prometheus(&conf.name, p.clone());
+            Some(p)
+        };
+        let route_header = conf
+            .route_header
+            .as_ref()
+            .map(|value| HeaderName::from_bytes(value.as_bytes()))
+            .transpose()
+            .map_err(|e| Error::Common {
+                category: "route_header".to_string(),
                 message: e.to_string(),
             })?;
-            Some(Arc::new(p))
+        // the ticket keys are shared by all the listen addresses of server
+        let ticket_keys = conf.tls_ticket_key_rotation.map(|rotation| {
+            Arc::new(TicketKeys::new(
+                rotation,
+                conf.tls_ticket_key_overlap.unwrap_or(rotation),
+            ))
+        });
+        let tls_session_resumption = SessionResumptionParams {
+            session_tickets: conf.tls_session_tickets,
+            session_cache_size: conf.tls_session_cache_size,
+            ticket_keys,
         };
+        let unmatched_handler = conf
+            .unmatched_action
+            .as_deref()
+            .map(UnmatchedHandler::new)
+            .transpose()?;
+        let default_host = conf
+            .default_host
+            .as_ref()
+            .map(|value| HeaderValue::from_str(value))
+            .transpose()
+            .map_err(|e| Error::Common {
+                category: "default_host".to_string(),
+                message: e.to_string(),
+            })?;
+        let acme_fallback = conf
+            .acme_fallback
+            .as_deref()
+            .map(|value| value.parse::<AcmeFallback>())
+            .transpose()
+            .map_err(|e| Error::Common {
+                category: "acme_fallback".to_string(),
+                message: e.to_string(),
+            })?;
+        let request_framing = conf
+            .request_framing
+            .as_deref()
+            .map(|value| value.parse::<RequestFraming>())
+            .transpose()
+            .map_err(|e| Error::Common {
+                category: "request_framing".to_string(),
+                message: e.to_string(),
+            })?
+            .unwrap_or_default();
+        let proxy_protocol = conf
+            .proxy_protocol
+            .as_deref()
+            .map(|value| value.parse::<ProxyProtocolMode>())
+            .transpose()
+            .map_err(|e| Error::Common {
+                category: "proxy_protocol".to_string(),
+                message: e.to_string(),
+            })?
+            .unwrap_or_default();
+        let fair_queue_key = conf
+            .fair_queue_key
+            .as_deref()
+            .map(|value| value.parse::<FairQueueKey>())
+            .transpose()
+            .map_err(|e| Error::Common {
+                category: "fair_queue_key".to_string(),
+                message: e.to_string(),
+            })?
+            .unwrap_or_default();
+        let fair_queue = conf
+            .fair_queue_capacity
+            .filter(|capacity| *capacity > 0)
+            .map(|capacity| {
+                FairQueue::new(
+                    capacity,
+                    conf.fair_queue_depth.unwrap_or(100).max(1),
+                    conf.fair_queue_timeout.unwrap_or(Duration::from_secs(10)),
+                )
+            });
         let s = Server {
             name: conf.name.clone(),
             admin: conf.admin,
@@ -275,16 +495,23 @@ impl Server {
             processing: AtomicI32::new(0),
             addr: conf.addr.clone(),
             log_parser: p,
+            access_log_level,
             error_template: conf.error_template.clone(),
             tls_cipher_list: conf.tls_cipher_list.clone(),
             tls_ciphersuites: conf.tls_ciphersuites.clone(),
             tls_min_version: conf.tls_min_version.clone(),
             tls_max_version: conf.tls_max_version.clone(),
+            tls_session_resumption,
             threads: conf.threads,
             lets_encrypt_enabled: false,
+            acme_challenge_prefix: WELL_KNOWN_PATH_PREFIX.to_string(),
             global_certificates: conf.global_certificates,
             enabled_h2: conf.enabled_h2,
             tcp_socket_options,
+            socket_options: conf.tcp_nodelay.map(|tcp_nodelay| SocketOptions {
+                tcp_nodelay: Some(tcp_nodelay),
+                ..Default::default()
+            }),
             prometheus_push_mode: prometheus_metrics.contains("://"),
             #[cfg(feature = "tracing")]
             enabled_otel: conf.otlp_exporter.is_some(),
@@ -293,9 +520,27 @@ impl Server {
             #[cfg(feature = "tracing")]
             prometheus,
             enable_server_timing: conf.enable_server_timing,
+            route_header,
+            unmatched_handler,
+            default_host,
+            require_host: conf.require_host,
+            acme_fallback,
+            acme_fallback_enabled: false,
+            request_framing,
+            proxy_protocol,
+            proxy_protocol_trusted: conf
+                .proxy_protocol_trusted
+                .as_ref()
+                .map(|trusted| IpRules::new(trusted)),
+            proxy_protocol_addrs: Arc::new(ProxyProtocolAddrs::default()),
+            fair_queue,
+            fair_queue_key,
             modules: conf.modules.clone(),
             downstream_read_timeout: conf.downstream_read_timeout,
             downstream_write_timeout: conf.downstream_write_timeout,
+            downstream_idle_timeout: conf
+                .downstream_idle_timeout
+                .map(|timeout| timeout.as_secs().max(1)),
             server_locations_provider: ctx.server_locations_provider,
             location_provider: ctx.location_provider,
             upstream_provider: ctx.upstream_provider,
@@ -307,9 +552,26 @@ impl Server {
         Ok(s)
     }
     /// Enable lets encrypt proxy plugin for handling ACME challenges at
-    /// `/.well-known/acme-challenge` path
-    pub fn enable_lets_encrypt(&mut self) {
+    /// the challenge prefix(default: `/.well-known/acme-challenge/`)
+    pub fn enable_lets_encrypt(&mut self, challenge_prefix: Option<&str>) {
         self.lets_encrypt_enabled = true;
+        self.acme_challenge_prefix =
+            normalize_challenge_prefix(challenge_prefix.unwrap_or_default());
+    }
+    /// Returns whether the request should be written to access log,
+    /// the request is failed if the status >= 400 or it has an error.
+    fn should_access_log(&self, ctx: &Ctx, has_error: bool) -> bool {
+        match self.access_log_level {
+            AccessLogLevel::All => true,
+            AccessLogLevel::Off => false,
+            AccessLogLevel::Error => {
+                has_error
+                    || ctx
+                        .state
+                        .status
+                        .is_none_or(|status| status.as_u16() >= 400)
+            },
+        }
     }
     /// Get the prometheus push service configuration if enabled.
     /// Returns a tuple of (metrics endpoint, service future) if push mode is configured.
@@ -346,6 +608,18 @@ impl Server {
         }
     }
 
+    /// Enables the acme fallback of the tls server if it's configured,
+    /// it should be called when the acme certificate is not yet issued.
+    /// Returns true if the server runs in the fallback mode.
+    pub fn enable_acme_fallback(&mut self) -> bool {
+        self.acme_fallback_enabled =
+            self.global_certificates && self.acme_fallback.is_some();
+        self.acme_fallback_enabled
+    }
+    /// Returns true if the server listens with tls.
+    fn is_tls(&self) -> bool {
+        self.global_certificates && !self.acme_fallback_enabled
+    }
     /// Starts the server and sets up TCP/TLS listening endpoints.
     /// - Configures listeners for each address
     /// - Sets up TLS if enabled
@@ -361,12 +635,32 @@ impl Server {
         let name = self.name.clone();
         let mut dynamic_cert = None;
         // tls
-        if self.global_certificates {
+        if self.is_tls() {
             dynamic_cert =
                 Some(GlobalCertificate::new(self.certificate_provider.clone()));
+        } else if self.acme_fallback_enabled {
+            warn!(
+                target: LOG_TARGET,
+                name,
+                addr,
+                fallback = ?self.acme_fallback,
+                "acme certificate is not yet issued, the tls server runs as http until it is issued"
+            );
         }
 
         let is_tls = dynamic_cert.is_some();
+        let proxy_protocol = self.proxy_protocol;
+        // the header is read before http, the tls handshake is done by
+        // the listener before it
+        if is_tls && proxy_protocol != ProxyProtocolMode::Off {
+            return Err(Error::Common {
+                category: "proxy_protocol".to_string(),
+                message: "proxy protocol is not supported by tls server"
+                    .to_string(),
+            });
+        }
+        let proxy_protocol_trusted = self.proxy_protocol_trusted.clone();
+        let proxy_protocol_addrs = self.proxy_protocol_addrs.clone();
 
         let enabled_h2 = self.enabled_h2;
         let threads = if let Some(threads) = self.threads {
@@ -395,15 +689,23 @@ impl Server {
         let cipher_suites = self.tls_ciphersuites.clone();
         let tls_min_version = self.tls_min_version.clone();
         let tls_max_version = self.tls_max_version.clone();
-        let mut lb = http_proxy_service(&conf, self);
+        let tls_session_resumption = self.tls_session_resumption.clone();
+        let mut http_logic = http_proxy(&conf, self);
         // use h2c if not tls and enable http2
         if !is_tls && enabled_h2 {
-            if let Some(http_logic) = lb.app_logic_mut() {
-                let mut http_server_options = HttpServerOptions::default();
-                http_server_options.h2c = true;
-                http_logic.server_options = Some(http_server_options);
-            }
-        }
+            let mut http_server_options = HttpServerOptions::default();
+            http_server_options.h2c = true;
+            http_logic.server_options = Some(http_server_options);
+        }
+        let mut lb = Service::new(
+            "Pingora HTTP Proxy Service".to_string(),
+            ProxyProtocolApp::new(
+                http_logic,
+                proxy_protocol,
+                proxy_protocol_trusted,
+                proxy_protocol_addrs,
+            ),
+        );
         lb.threads = threads;
         // support listen multi address
         for addr in addr.split(',') {
@@ -417,6 +719,7 @@ impl Server {
                         cipher_suites: cipher_suites.clone(),
                         tls_min_version: tls_min_version.clone(),
                         tls_max_version: tls_max_version.clone(),
+                        session_resumption: tls_session_resumption.clone(),
                     })
                     .map_err(|e| Error::Common {
                         category: "tls".to_string(),
@@ -458,6 +761,18 @@ impl Server {
     fn initialize_context(&self, session: &mut Session, ctx: &mut Ctx) {
         session.set_read_timeout(self.downstream_read_timeout);
         session.set_write_timeout(self.downstream_write_timeout);
+        // the keepalive timeout only applies to the idle time waiting for
+        // next request, so the upgraded websocket and streaming sse
+        // connections are not affected, they are governed by the read
+        // and write timeout. It's ignored if the client disallows reuse.
+        if let Some(idle_timeout) = self.downstream_idle_timeout {
+            session.set_keepalive(Some(idle_timeout));
+        }
+        // the request on the keep-alive connection is still handled during
+        // draining, but the connection is closed after the response
+        if is_draining() {
+            session.set_keepalive(None);
+        }
 
         if let Some(stream) = session.stream() {
             ctx.conn.id = stream.id() as usize;
@@ -479,6 +794,21 @@ impl Server {
             ctx.conn.tls_cipher = digest_detail.tls_cipher;
             ctx.conn.tls_version = digest_detail.tls_version;
         };
+        // apply socket options only once for the new connection,
+        // the id of stream is the fd of connection
+        #[cfg(unix)]
+        if let Some(opts) = &self.socket_options {
+            if let Some(stream) = session.stream().filter(|_| !ctx.conn.reused)
+            {
+                if let Err(e) = opts.apply(stream.id()) {
+                    error!(
+                        target: LOG_TARGET,
+                        error = e.to_string(),
+                        "set downstream socket options fail"
+                    );
+                }
+            }
+        }
         accept_request();
 
         ctx.state.processing_count =
@@ -497,6 +827,12 @@ impl Server {
             ctx.conn.server_addr = Some(addr.ip().to_string());
             ctx.conn.server_port = Some(addr.port());
         }
+        // the source address of PROXY protocol header is the real client
+        if let Some(addr) = self.proxy_protocol_addrs.get(ctx.conn.id) {
+            ctx.conn.remote_addr = Some(addr.ip().to_string());
+            ctx.conn.remote_port = Some(addr.port());
+            ctx.conn.client_ip = Some(addr.ip().to_string());
+        }
     }
 
     #[inline]
@@ -505,28 +841,32 @@ impl Server {
         session: &mut Session,
         ctx: &mut Ctx,
     ) -> pingora::Result<()> {
+        self.validate_host(session)?;
         let header = session.req_header();
         let host = pingap_core::get_host(header).unwrap_or_default();
         let path = header.uri.path();
 
-        // locations not found
-        let Some(locations) = self.server_locations_provider.get(&self.name)
-        else {
-            return Ok(());
-        };
-
         // use find_map to optimize logic, performance and readability
-        let matched_info = locations.iter().find_map(|name| {
-            let location = self.location_provider.get(name)?;
-            let (matched, captures) = location.match_host_path(host, path);
-            if matched {
-                Some((location, captures))
-            } else {
-                None
-            }
-        });
+        let matched_info = self
+            .server_locations_provider
+            .get(&self.name)
+            .and_then(|locations| {
+                locations.iter().find_map(|name| {
+                    let location = self.location_provider.get(name)?;
+                    let (matched, captures) =
+                        location.match_host_path(host, path);
+                    if matched {
+                        Some((location, captures))
+                    } else {
+                        None
+                    }
+                })
+            });
 
-        let Some((location, captures)) = matched_info else {
+        // the unmatched request is served by the location of unmatched action
+        let Some((location, captures)) = matched_info
+            .or_else(|| Some((self.get_unmatched_location()?, None)))
+        else {
             return Ok(());
         };
 
@@ -535,6 +875,18 @@ impl Server {
         ctx.upstream.location_instance = Some(location.clone());
         ctx.upstream.max_retries = location.max_retries;
         ctx.upstream.max_retry_window = location.max_retry_window;
+        ctx.upstream.retry_on_reset = location.retry_on_upstream_reset;
+        ctx.upstream.keepalive = location.upstream_keepalive;
+        ctx.upstream
+            .fallback_upstream
+            .clone_from(&location.fallback_upstream);
+        ctx.upstream
+            .fallback_page
+            .clone_from(&location.fallback_page);
+        ctx.upstream.max_response_buffer_size =
+            location.max_response_buffer_size;
+        ctx.upstream.response_buffer_overflow_error =
+            location.response_buffer_overflow_error;
         if let Some(captures) = captures {
             ctx.extend_variables(captures);
         }
@@ -584,6 +936,153 @@ impl Server {
         Ok(())
     }
 
+    /// Acquires the permit of fair queue, the request waits in the queue
+    /// of its fairness key if the capacity of server is exhausted, and
+    /// it's rejected with `503` if the queue is full or waiting timeout.
+    async fn acquire_fair_queue(
+        &self,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> pingora::Result<()> {
+        let Some(fair_queue) = &self.fair_queue else {
+            return Ok(());
+        };
+        let key = get_fair_queue_key(&self.fair_queue_key, session, ctx);
+        match fair_queue.acquire(&key).await {
+            Ok(permit) => {
+                ctx.state.fair_queue_permit = Some(permit);
+                Ok(())
+            },
+            Err(e) => {
+                warn!(
+                    target: LOG_TARGET,
+                    server = self.name,
+                    key,
+                    error = %e,
+                    "reject request of fair queue"
+                );
+                Err(new_internal_error(503, e))
+            },
+        }
+    }
+
+    /// Validates the host header of request to avoid routing ambiguity.
+    /// The request with multiple conflicting host headers is rejected,
+    /// and the HTTP/1.0 request without host uses the default host.
+    /// It's accepted without host if the default host is not set,
+    /// unless the host is required.
+    #[inline]
+    fn validate_host(&self, session: &mut Session) -> pingora::Result<()> {
+        let header = session.req_header();
+        let mut hosts = header.headers.get_all(http::header::HOST).iter();
+        if let Some(host) = hosts.next() {
+            if hosts.any(|value| value != host) {
+                return Err(new_internal_error(
+                    400,
+                    "multiple conflicting host headers",
+                ));
+            }
+            return Ok(());
+        }
+        if header.version != http::Version::HTTP_10
+            || header.uri.host().is_some()
+        {
+            return Ok(());
+        }
+        let Some(default_host) = &self.default_host else {
+            if self.require_host {
+                return Err(new_internal_error(400, "host header is missing"));
+            }
+            return Ok(());
+        };
+        session
+            .req_header_mut()
+            .insert_header(http::header::HOST, default_host.clone())?;
+        Ok(())
+    }
+
+    /// Validates the framing of request to defend against request smuggling,
+    /// it must be called before the request body is read.
+    /// The request with conflicting content-length headers or invalid
+    /// transfer-encoding is rejected, the request with both content-length
+    /// and transfer-encoding is rejected in strict mode, or the
+    /// content-length is removed and the connection is closed after it in
+    /// lenient mode. The malformed chunk (non-hex or overflowed size) is
+    /// rejected by the body reader with 400.
+    #[inline]
+    fn validate_framing(&self, session: &mut Session) -> pingora::Result<()> {
+        let header = session.req_header();
+        // http/2 has its own framing
+        if header.version == http::Version::HTTP_2 {
+            return Ok(());
+        }
+        let mut lengths =
+            header.headers.get_all(http::header::CONTENT_LENGTH).iter();
+        let content_length = lengths.next();
+        if let Some(length) = content_length {
+            if lengths.any(|value| value != length) {
+                return Err(new_internal_error(
+                    400,
+                    "multiple conflicting content-length headers",
+                ));
+            }
+            if length.is_empty()
+                || !length.as_bytes().iter().all(u8::is_ascii_digit)
+            {
+                return Err(new_internal_error(400, "invalid content-length"));
+            }
+        }
+        let mut codings = vec![];
+        for value in header.headers.get_all(http::header::TRANSFER_ENCODING) {
+            let value = value.to_str().map_err(|_| {
+                new_internal_error(400, "invalid transfer-encoding")
+            })?;
+            codings.extend(
+                value.split(',').map(|item| item.trim().to_lowercase()),
+            );
+        }
+        if codings.is_empty() {
+            return Ok(());
+        }
+        // the chunked must be the final coding, and it's the only
+        // coding accepted in strict mode
+        let strict = self.request_framing == RequestFraming::Strict;
+        if header.version == http::Version::HTTP_10
+            || codings.last().map(String::as_str) != Some("chunked")
+            || (strict && codings.len() != 1)
+        {
+            return Err(new_internal_error(400, "invalid transfer-encoding"));
+        }
+        if content_length.is_some() {
+            if strict {
+                return Err(new_internal_error(
+                    400,
+                    "conflicting content-length and transfer-encoding",
+                ));
+            }
+            // the transfer-encoding overrides the content-length,
+            // the connection is closed after the ambiguous request
+            session
+                .req_header_mut()
+                .remove_header(&http::header::CONTENT_LENGTH);
+            session.set_keepalive(None);
+        }
+        Ok(())
+    }
+
+    /// Returns the location of unmatched action for the request matching
+    /// no location of server.
+    #[inline]
+    fn get_unmatched_location(&self) -> Option<Arc<Location>> {
+        match self.unmatched_handler.as_ref()? {
+            UnmatchedHandler::Location(name) => {
+                self.location_provider.get(name)
+            },
+            UnmatchedHandler::Upstream(location) => Some(location.clone()),
+            UnmatchedHandler::Status(_) => None,
+        }
+    }
+
     #[inline]
     async fn handle_admin_request(
         &self,
@@ -610,6 +1109,7 @@ impl Server {
                 self.config_manager.clone(),
                 session,
                 ctx,
+                &self.acme_challenge_prefix,
             )
             .await
             {
@@ -620,6 +1120,42 @@ impl Server {
         }
         None // not enable ACME, continue
     }
+    /// Returns the http location of the request if the tls server
+    /// redirects to http before the acme certificate is issued.
+    fn get_acme_fallback_location(
+        &self,
+        header: &RequestHeader,
+    ) -> Option<String> {
+        if !self.acme_fallback_enabled
+            || self.acme_fallback != Some(AcmeFallback::Redirect)
+        {
+            return None;
+        }
+        let host = pingap_core::get_host(header).unwrap_or_default();
+        let path_and_query = header
+            .uri
+            .path_and_query()
+            .map(|value| value.as_str())
+            .unwrap_or("/");
+        Some(format!("http://{host}{path_and_query}"))
+    }
+    #[inline]
+    async fn handle_acme_fallback(
+        &self,
+        session: &mut Session,
+        ctx: &mut Ctx,
+    ) -> Option<pingora::Result<bool>> {
+        let location = self.get_acme_fallback_location(session.req_header())?;
+        let resp = match HttpResponse::redirect(&location) {
+            Ok(resp) => resp,
+            Err(e) => return Some(Err(e)),
+        };
+        ctx.state.status = Some(resp.status);
+        if let Err(e) = resp.send(session).await {
+            return Some(Err(e));
+        }
+        Some(Ok(true))
+    }
     #[inline]
     #[cfg(feature = "tracing")]
     async fn handle_metrics_request(
@@ -658,7 +1194,11 @@ impl Server {
                 "No matching location, host:{host} path:{}",
                 header.uri.path()
             );
-            return Err(pingap_core::new_internal_error(500, message));
+            let status = match &self.unmatched_handler {
+                Some(UnmatchedHandler::Status(status)) => *status,
+                _ => 500,
+            };
+            return Err(pingap_core::new_internal_error(status, message));
         };
 
         debug!(
@@ -687,12 +1227,53 @@ impl Server {
             return Ok(true);
         }
 
+        // the CONNECT request is only tunneled after it's authorized
+        // by the forward proxy plugin
+        if session.req_header().method == http::Method::CONNECT {
+            let Some(addrs) = ctx.upstream.tunnel_addrs.take() else {
+                return Err(pingap_core::new_internal_error(
+                    405,
+                    "CONNECT is not allowed",
+                ));
+            };
+            let (request_size, response_size) =
+                proxy_tunnel(session, &addrs).await?;
+            ctx.state.status = Some(StatusCode::OK);
+            ctx.state.payload_size = request_size as usize;
+            debug!(
+                target: LOG_TARGET,
+                request_size,
+                response_size,
+                "tunnel is closed"
+            );
+            return Ok(true);
+        }
+
         Ok(false)
     }
 }
 
 const MODULE_GRPC_WEB: &str = "grpc-web";
 
+/// Returns true if the response is an informational response except
+/// `101 Switching Protocols`, which is the final response of upgrade request.
+#[inline]
+fn is_interim_response(resp: &ResponseHeader) -> bool {
+    resp.status.is_informational()
+        && resp.status != StatusCode::SWITCHING_PROTOCOLS
+}
+
+/// Signals `Connection: close` for the draining server, so the client
+/// doesn't send more requests on the connection. The http/2 connection
+/// is closed by the shutdown of server.
+#[inline]
+fn set_connection_close(session: &mut Session, resp: &mut ResponseHeader) {
+    session.set_keepalive(None);
+    if !session.is_http2() {
+        let _ = resp.insert_header(http::header::CONNECTION, "close");
+    }
+}
+
 impl Server {
     #[inline]
     fn get_context_plugins(
@@ -1053,6 +1634,198 @@ fn get_upstream_with_variables(
     upstreams.get(key)
 }
 
+/// Error type used to serve the fallback page of location
+const UPSTREAM_FALLBACK_PAGE: &str = "UpstreamFallbackPage";
+
+/// Error type of malformed chunk of request body(from pingora)
+const INVALID_CHUNK: &str = "InvalidChunk";
+
+/// Returns the status of downstream error, the malformed request
+/// is responded with 400.
+fn get_downstream_error_status(etype: &pingora::ErrorType) -> u16 {
+    match etype {
+        pingora::ErrorType::ConnectTimedout
+        | pingora::ErrorType::ReadTimedout => 408,
+        // client close the connection
+        pingora::ErrorType::ConnectionClosed => 499,
+        pingora::ErrorType::InvalidHTTPHeader => 400,
+        pingora::ErrorType::Custom(name) if *name == INVALID_CHUNK => 400,
+        _ => 500,
+    }
+}
+
+/// Action applied when the upstream responds with 5xx
+#[derive(Debug, PartialEq)]
+enum UpstreamFallback {
+    /// Retry the current upstream
+    Retry,
+    /// Switch to the fallback upstream
+    Upstream,
+    /// Serve the fallback page
+    Page,
+}
+
+/// Returns true if the request can be retried, based on max retries
+/// and max retry window of the location, the retry is also withdrawn
+/// from the retry budget of upstream.
+#[inline]
+fn can_retry(ctx: &Ctx) -> bool {
+    let Some(max_retries) = ctx.upstream.max_retries else {
+        return false;
+    };
+    if ctx.upstream.retries >= max_retries {
+        return false;
+    }
+    if let Some(max_retry_window) = ctx.upstream.max_retry_window {
+        if ctx.timing.created_at.elapsed() > max_retry_window {
+            return false;
+        }
+    }
+    ctx.upstream
+        .upstream_instance
+        .as_ref()
+        .is_none_or(|upstream_instance| upstream_instance.acquire_retry())
+}
+
+/// Gets the fairness key of request, the empty value of header, cookie
+/// or query shares the same queue.
+fn get_fair_queue_key(
+    key: &FairQueueKey,
+    session: &Session,
+    ctx: &mut Ctx,
+) -> String {
+    let req_header = session.req_header();
+    let value = match key {
+        FairQueueKey::Ip => {
+            return ctx
+                .conn
+                .client_ip
+                .get_or_insert_with(|| pingap_core::get_client_ip(session))
+                .clone();
+        },
+        FairQueueKey::Header(name) => {
+            pingap_core::get_req_header_value(req_header, name)
+        },
+        FairQueueKey::Cookie(name) => {
+            pingap_core::get_cookie_value(req_header, name)
+        },
+        FairQueueKey::Query(name) => {
+            pingap_core::get_query_value(req_header, name)
+        },
+    };
+    value.unwrap_or_default().to_string()
+}
+
+/// Returns true if the upstream connection is closed or reset while
+/// the response is being read.
+#[inline]
+fn is_upstream_reset(e: &pingora::Error) -> bool {
+    matches!(
+        e.etype(),
+        pingora::ErrorType::ConnectionClosed | pingora::ErrorType::ReadError
+    )
+}
+
+/// Returns true if the method is idempotent, the request can be sent
+/// to upstream again without side effects.
+#[inline]
+fn is_idempotent_method(method: &http::Method) -> bool {
+    matches!(
+        *method,
+        http::Method::GET
+            | http::Method::HEAD
+            | http::Method::OPTIONS
+            | http::Method::PUT
+            | http::Method::DELETE
+            | http::Method::TRACE
+    )
+}
+
+/// Gets the PROXY protocol header sent to the new upstream connection,
+/// the tls upstream with PROXY protocol is rejected by the config.
+fn get_proxy_protocol_header(ctx: &Ctx) -> Option<ProxyProtocolHeader> {
+    let version = ctx
+        .upstream
+        .upstream_instance
+        .as_ref()
+        .and_then(|up| up.proxy_protocol())?;
+    let client_ip = ctx
+        .conn
+        .client_ip
+        .as_ref()
+        .or(ctx.conn.remote_addr.as_ref())?;
+    let source = SocketAddr::new(
+        client_ip.parse().ok()?,
+        ctx.conn.remote_port.unwrap_or_default(),
+    );
+    let destination = SocketAddr::new(
+        ctx.conn.server_addr.as_ref()?.parse().ok()?,
+        ctx.conn.server_port.unwrap_or_default(),
+    );
+    Some(ProxyProtocolHeader::new(version, source, destination))
+}
+
+/// Gets the group key of upstream connections with the PROXY protocol
+/// header, it's sent once per connection, so the connection can only be
+/// reused by the same client address(ip and port) of header.
+fn get_proxy_protocol_group_key(ctx: &Ctx) -> Option<u64> {
+    let source = get_proxy_protocol_header(ctx)?.source()?;
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+/// Applies the action of upstream when the certificate of backend fails
+/// verification, returns true if the request should be retried.
+/// The insecure connection and fallback upstream are only tried once.
+fn on_tls_verify_failure(ctx: &mut Ctx, address: &str, message: &str) -> bool {
+    let Some(upstream_instance) = ctx.upstream.upstream_instance.clone() else {
+        return false;
+    };
+    match upstream_instance.on_tls_verify_failure(address, message) {
+        TlsVerifyFailureAction::Insecure if !ctx.upstream.tls_insecure => {
+            ctx.upstream.tls_insecure = true;
+            true
+        },
+        TlsVerifyFailureAction::Fallback(name) if !ctx.upstream.fallback => {
+            ctx.upstream.name = name;
+            ctx.upstream.fallback = true;
+            true
+        },
+        _ => false,
+    }
+}
+
+/// Gets the fallback action for the upstream response status.
+/// The upstream is retried first, then the fallback upstream is used,
+/// the fallback page is served at last.
+fn get_upstream_fallback(
+    ctx: &mut Ctx,
+    status: StatusCode,
+) -> Option<UpstreamFallback> {
+    if !status.is_server_error()
+        || (ctx.upstream.fallback_upstream.is_none()
+            && ctx.upstream.fallback_page.is_none())
+    {
+        return None;
+    }
+    if !ctx.upstream.fallback {
+        if can_retry(ctx) {
+            ctx.upstream.retries += 1;
+            return Some(UpstreamFallback::Retry);
+        }
+        if let Some(fallback_upstream) = &ctx.upstream.fallback_upstream {
+            ctx.upstream.name = fallback_upstream.clone();
+            ctx.upstream.fallback = true;
+            return Some(UpstreamFallback::Upstream);
+        }
+    }
+    ctx.upstream
+        .fallback_page
+        .as_ref()
+        .map(|_| UpstreamFallback::Page)
+}
+
 #[async_trait]
 impl ProxyHttp for Server {
     type CTX = Ctx;
@@ -1092,10 +1865,14 @@ impl ProxyHttp for Server {
         defer!(debug!(target: LOG_TARGET, "<-- early request filter"););
 
         self.initialize_context(session, ctx);
+        // the framing is validated before any body read, pingora selects
+        // the body framing lazily at the first read of request body
+        self.validate_framing(session)?;
         #[cfg(feature = "tracing")]
         if self.enabled_otel {
             initialize_telemetry(&self.name, session, ctx);
         }
+        self.acquire_fair_queue(session, ctx).await?;
         self.find_and_apply_location(session, ctx).await?;
 
         Ok(())
@@ -1126,6 +1903,10 @@ impl ProxyHttp for Server {
         if let Some(result) = self.handle_acme_challenge(session, ctx).await {
             return result;
         }
+        // redirect to http before the acme certificate is issued
+        if let Some(result) = self.handle_acme_fallback(session, ctx).await {
+            return result;
+        }
         // prometheus metrics pull request
         #[cfg(feature = "tracing")]
         if let Some(result) = self.handle_metrics_request(session, ctx).await {
@@ -1167,7 +1948,7 @@ impl ProxyHttp for Server {
         debug!(target: LOG_TARGET, "--> upstream peer");
         defer!(debug!(target: LOG_TARGET, "<-- upstream peer"););
 
-        let peer = ctx
+        let mut peer = ctx
             .upstream
             .location_instance
             .clone()
@@ -1187,6 +1968,11 @@ impl ProxyHttp for Server {
                         self.upstream_provider.as_ref(),
                     )?
                 };
+                // only the original request is recorded for retry budget
+                if ctx.upstream.retries == 0 && ctx.upstream.status_retries == 0
+                {
+                    upstream.on_request();
+                }
                 ctx.upstream.upstream_instance = Some(upstream.clone());
                 Some(upstream)
             })
@@ -1222,6 +2008,15 @@ impl ProxyHttp for Server {
                     ),
                 )
             })?;
+        // the certificate failed verification and the upstream allows
+        // to proceed with the insecure connection
+        if ctx.upstream.tls_insecure {
+            peer.options.verify_cert = false;
+            peer.options.verify_hostname = false;
+        }
+        if let Some(group_key) = get_proxy_protocol_group_key(ctx) {
+            peer.group_key = group_key;
+        }
 
         // start connect to upstream
         ctx.timing.upstream_connect =
@@ -1236,7 +2031,7 @@ impl ProxyHttp for Server {
         _session: &mut Session,
         reused: bool,
         _peer: &HttpPeer,
-        #[cfg(unix)] _fd: std::os::unix::io::RawFd,
+        #[cfg(unix)] fd: std::os::unix::io::RawFd,
         #[cfg(windows)] _sock: std::os::windows::io::RawSocket,
         digest: Option<&Digest>,
         ctx: &mut Self::CTX,
@@ -1252,6 +2047,38 @@ impl ProxyHttp for Server {
             ctx.update_upstream_timing_from_digest(digest, reused);
         }
         ctx.upstream.reused = reused;
+        // apply socket options only once for the new connection
+        #[cfg(unix)]
+        if !reused {
+            if let Some(opts) = ctx
+                .upstream
+                .upstream_instance
+                .as_ref()
+                .and_then(|up| up.socket_options())
+            {
+                if let Err(e) = opts.apply(fd) {
+                    error!(
+                        target: LOG_TARGET,
+                        error = e.to_string(),
+                        "set upstream socket options fail"
+                    );
+                }
+            }
+            if let Some(header) = get_proxy_protocol_header(ctx) {
+                if let Err(e) = header.write_to_fd(fd).await {
+                    error!(
+                        target: LOG_TARGET,
+                        error = e.to_string(),
+                        "write proxy protocol header fail"
+                    );
+                    return Err(pingora::Error::because(
+                        pingora::ErrorType::ConnectError,
+                        "write proxy protocol header fail",
+                        e,
+                    ));
+                }
+            }
+        }
 
         // upstream start processing
         ctx.timing.upstream_processing =
@@ -1266,19 +2093,54 @@ impl ProxyHttp for Server {
         ctx: &mut Self::CTX,
         mut e: Box<pingora::Error>,
     ) -> Box<pingora::Error> {
+        let address = peer.address().to_string();
         if let Some(upstream_instance) = &ctx.upstream.upstream_instance {
-            upstream_instance.on_transport_failure(&peer.address().to_string());
+            upstream_instance.on_transport_failure(&address);
         }
-        let Some(max_retries) = ctx.upstream.max_retries else {
+        if e.etype() == &pingora::ErrorType::InvalidCert
+            && on_tls_verify_failure(ctx, &address, &e.to_string())
+        {
+            e.set_retry(true);
             return e;
-        };
-        if ctx.upstream.retries >= max_retries {
+        }
+        if !can_retry(ctx) {
             return e;
         }
-        if let Some(max_retry_window) = ctx.upstream.max_retry_window {
-            if ctx.timing.created_at.elapsed() > max_retry_window {
-                return e;
-            }
+        ctx.upstream.retries += 1;
+        e.set_retry(true);
+        e
+    }
+    /// Handles the error after the upstream connection is established.
+    /// The idempotent request is retried if the upstream resets the
+    /// connection before the response is sent to client, the committed
+    /// response can't be retried as the header has been written.
+    fn error_while_proxy(
+        &self,
+        peer: &HttpPeer,
+        session: &mut Session,
+        e: Box<pingora::Error>,
+        ctx: &mut Self::CTX,
+        client_reused: bool,
+    ) -> Box<pingora::Error> {
+        let mut e = e.more_context(format!("Peer: {peer}"));
+        let retry_buffer_truncated = session.as_ref().retry_buffer_truncated();
+        e.retry
+            .decide_reuse(client_reused && !retry_buffer_truncated);
+        if session.response_written().is_some() {
+            e.set_retry(false);
+            return e;
+        }
+        if !ctx.upstream.retry_on_reset
+            || e.retry()
+            || retry_buffer_truncated
+            || !is_upstream_reset(&e)
+            || !is_idempotent_method(&session.req_header().method)
+            || !can_retry(ctx)
+        {
+            return e;
+        }
+        if let Some(upstream_instance) = &ctx.upstream.upstream_instance {
+            upstream_instance.on_transport_failure(&peer.address().to_string());
         }
         ctx.upstream.retries += 1;
         e.set_retry(true);
@@ -1298,6 +2160,16 @@ impl ProxyHttp for Server {
         debug!(target: LOG_TARGET, "--> upstream request filter");
         defer!(debug!(target: LOG_TARGET, "<-- upstream request filter"););
         set_append_proxy_headers(session, ctx, upstream_response);
+        set_upstream_version(ctx, upstream_response);
+        set_upstream_connection(ctx, upstream_response);
+        if let Some(route_header) = &self.route_header {
+            set_route_header(route_header, ctx, upstream_response);
+        }
+        // the body has been read after pingap sent `100 Continue`,
+        // so the upstream should not send the interim response again
+        if ctx.state.continue_sent {
+            upstream_response.remove_header(&http::header::EXPECT);
+        }
         Ok(())
     }
     /// Filters request body chunks before sending upstream.
@@ -1314,6 +2186,13 @@ impl ProxyHttp for Server {
     {
         debug!(target: LOG_TARGET, "--> request body filter");
         defer!(debug!(target: LOG_TARGET, "<-- request body filter"););
+        // the whole body is buffered by plugin and sent as one chunk,
+        // so the chunk is replaced by the new body
+        if let Some(request_body) = ctx.get_request_body() {
+            if body.is_some() {
+                *body = Some(request_body.clone());
+            }
+        }
         if let Some(buf) = body {
             ctx.state.payload_size += buf.len();
             if let Some(location) = &ctx.upstream.location_instance {
@@ -1325,6 +2204,12 @@ impl ProxyHttp for Server {
                     ));
                 }
             }
+            if ctx.is_upload_too_slow(buf.len()) {
+                return Err(new_internal_error(
+                    408,
+                    "request body is uploaded too slowly",
+                ));
+            }
         }
         Ok(())
     }
@@ -1371,9 +2256,9 @@ impl ProxyHttp for Server {
         debug!(target: LOG_TARGET, "--> response cache filter");
         defer!(debug!(target: LOG_TARGET, "<-- response cache filter"););
 
-        let (check_cache_control, max_ttl) = ctx.cache.as_ref().map_or(
-            (false, None), // ctx.cache is None
-            |c| (c.check_cache_control, c.max_ttl),
+        let (check_cache_control, max_ttl, etag) = ctx.cache.as_ref().map_or(
+            (false, None, false), // ctx.cache is None
+            |c| (c.check_cache_control, c.max_ttl, c.etag),
         );
 
         let mut cc = CacheControl::from_resp_headers(resp);
@@ -1390,12 +2275,21 @@ impl ProxyHttp for Server {
             ));
         }
 
-        Ok(resp_cacheable(
-            cc.as_ref(),
-            resp.clone(),
-            false,
-            &META_DEFAULTS,
-        ))
+        let mut resp = resp.clone();
+        // the ETag is generated by the cache storage when the body
+        // is fully stored, so only the cached header is marked
+        if etag
+            && resp.status == StatusCode::OK
+            && !resp.headers.contains_key(http::header::ETAG)
+            && !resp.headers.contains_key(http::header::LAST_MODIFIED)
+        {
+            let _ = resp.insert_header(
+                HTTP_HEADER_NAME_X_GENERATE_ETAG.clone(),
+                HeaderValue::from_static("1"),
+            );
+        }
+
+        Ok(resp_cacheable(cc.as_ref(), resp, false, &META_DEFAULTS))
     }
 
     async fn response_filter(
@@ -1409,8 +2303,19 @@ impl ProxyHttp for Server {
     {
         debug!(target: LOG_TARGET, "--> response filter");
         defer!(debug!(target: LOG_TARGET, "<-- response filter"););
+        // the interim response(e.g. `100 Continue`) is relayed as it is,
+        // the final response will be filtered later
+        if is_interim_response(upstream_response) {
+            return Ok(());
+        }
+        if is_draining() {
+            set_connection_close(session, upstream_response);
+        }
         if session.cache.enabled() {
             self.handle_cache_headers(session, upstream_response, ctx);
+            // the internal header should not be sent to the client
+            let _ = upstream_response
+                .remove_header(&HTTP_HEADER_NAME_X_GENERATE_ETAG);
         }
 
         // call response plugin
@@ -1433,11 +2338,82 @@ impl ProxyHttp for Server {
     ) -> pingora::Result<()> {
         debug!(target: LOG_TARGET, "--> upstream response filter");
         defer!(debug!(target: LOG_TARGET, "<-- upstream response filter"););
+        if is_interim_response(upstream_response) {
+            return Ok(());
+        }
+        set_append_upstream_response_headers(session, ctx, upstream_response);
         self.handle_upstream_response_plugin(session, ctx, upstream_response)?;
-        #[cfg(feature = "tracing")]
+        // the plugin decides to retry the request by the response status,
+        // the response is returned if the retry budget is exhausted
+        let retry_delay = ctx.upstream.retry_delay.take().filter(|_| {
+            ctx.upstream.upstream_instance.as_ref().is_none_or(
+                |upstream_instance| upstream_instance.acquire_retry(),
+            )
+        });
+        if let Some(delay) = retry_delay {
+            if let Some(upstream_instance) = &ctx.upstream.upstream_instance {
+                upstream_instance.on_response(
+                    &ctx.upstream.address,
+                    upstream_response.status,
+                );
+                // the processing count is increased again by the next attempt
+                upstream_instance.completed();
+            }
+            debug!(
+                target: LOG_TARGET,
+                status = upstream_response.status.as_u16(),
+                delay = delay.as_millis() as u64,
+                "retry upstream request"
+            );
+            if !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+            let mut e = pingora::Error::explain(
+                pingora::ErrorType::HTTPStatus(
+                    upstream_response.status.as_u16(),
+                ),
+                "upstream responds retryable status",
+            );
+            e.set_retry(true);
+            return Err(e);
+        }
+        #[cfg(feature = "tracing")]
         inject_telemetry_headers(ctx, upstream_response);
         ctx.upstream.status = Some(upstream_response.status);
 
+        if let Some(fallback) =
+            get_upstream_fallback(ctx, upstream_response.status)
+        {
+            if let Some(upstream_instance) = &ctx.upstream.upstream_instance {
+                upstream_instance.on_response(
+                    &ctx.upstream.address,
+                    upstream_response.status,
+                );
+                // the processing count is increased again by the next attempt
+                if fallback != UpstreamFallback::Page {
+                    upstream_instance.completed();
+                }
+            }
+            debug!(
+                target: LOG_TARGET,
+                status = upstream_response.status.as_u16(),
+                "upstream fallback: {fallback:?}"
+            );
+            if fallback == UpstreamFallback::Page {
+                return Err(pingora::Error::new(pingora::ErrorType::Custom(
+                    UPSTREAM_FALLBACK_PAGE,
+                )));
+            }
+            let mut e = pingora::Error::explain(
+                pingora::ErrorType::HTTPStatus(
+                    upstream_response.status.as_u16(),
+                ),
+                "upstream responds 5xx",
+            );
+            e.set_retry(true);
+            return Err(e);
+        }
+
         if ctx.state.status.is_none() {
             ctx.state.status = Some(upstream_response.status);
             // start to get upstream response data
@@ -1509,8 +2485,16 @@ impl ProxyHttp for Server {
     {
         debug!(target: LOG_TARGET, "--> response body filter");
         defer!(debug!(target: LOG_TARGET, "<-- response body filter"););
+        if let Some(data) = body {
+            ctx.upstream.response_buffered_size += data.len();
+            ctx.limit_response_buffer(ctx.upstream.response_buffered_size)?;
+        }
         self.handle_response_body_plugin(session, ctx, body, end_of_stream)?;
-        Ok(None)
+        // delay the next chunk if the bandwidth is throttled
+        let delay = body
+            .as_ref()
+            .and_then(|body| ctx.get_bandwidth_throttle_delay(body.len()));
+        Ok(delay)
     }
 
     /// Handles proxy failures and generates appropriate error responses.
@@ -1532,22 +2516,59 @@ impl ProxyHttp for Server {
         defer!(debug!(target: LOG_TARGET, "<-- fail to proxy"););
         let server_session = session.as_mut();
 
+        let fallback_page = if e.etype()
+            == &pingora::ErrorType::Custom(UPSTREAM_FALLBACK_PAGE)
+        {
+            ctx.upstream.fallback_page.clone()
+        } else {
+            None
+        };
+
         let code = match e.etype() {
+            _ if fallback_page.is_some() => 503,
             pingora::HTTPStatus(code) => *code,
             // spellchecker:off
             _ => match e.esource() {
                 pingora::ErrorSource::Upstream => 502,
-                pingora::ErrorSource::Downstream => match e.etype() {
-                    pingora::ErrorType::ConnectTimedout => 408,
-                    // client close the connection
-                    pingora::ErrorType::ConnectionClosed => 499,
-                    _ => 500,
+                pingora::ErrorSource::Downstream => {
+                    get_downstream_error_status(e.etype())
                 },
                 pingora::ErrorSource::Internal
                 | pingora::ErrorSource::Unset => 500,
             },
             // spellchecker:on
         };
+        // close the connection without response, e.g. the unmatched request
+        if code == STATUS_CLOSE_WITHOUT_RESPONSE {
+            debug!(
+                target: LOG_TARGET,
+                error = %e,
+                "close connection without response"
+            );
+            ctx.state.status = StatusCode::from_u16(code).ok();
+            server_session.set_keepalive(None);
+            return FailToProxy {
+                error_code: code,
+                can_reuse_downstream: false,
+            };
+        }
+        // the response header has been sent to client, the error page
+        // can't be written, close the connection so the client doesn't
+        // take the truncated body as a complete response
+        if let Some(header) = server_session.response_written() {
+            error!(
+                target: LOG_TARGET,
+                error = %e,
+                status = header.status.as_u16(),
+                path = server_session.req_header().uri.path(),
+                "upstream fails after the response is committed, close the connection"
+            );
+            server_session.set_keepalive(None);
+            return FailToProxy {
+                error_code: code,
+                can_reuse_downstream: false,
+            };
+        }
         let mut resp = match code {
             502 => error_resp::HTTP_502_RESPONSE.clone(),
             400 => error_resp::HTTP_400_RESPONSE.clone(),
@@ -1556,12 +2577,14 @@ impl ProxyHttp for Server {
         };
 
         let error_type = e.etype().as_str();
-        let content = self
-            .error_template
-            .replace("{{version}}", pingap_util::get_pkg_version())
-            .replace("{{content}}", &e.to_string())
-            .replace("{{error_type}}", error_type);
-        let buf = Bytes::from(content);
+        let buf = fallback_page.unwrap_or_else(|| {
+            let content = self
+                .error_template
+                .replace("{{version}}", pingap_util::get_pkg_version())
+                .replace("{{content}}", &e.to_string())
+                .replace("{{error_type}}", error_type);
+            Bytes::from(content)
+        });
         ctx.state.status = Some(
             StatusCode::from_u16(code)
                 .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
@@ -1626,7 +2649,7 @@ impl ProxyHttp for Server {
     async fn logging(
         &self,
         session: &mut Session,
-        _e: Option<&pingora::Error>,
+        e: Option<&pingora::Error>,
         ctx: &mut Self::CTX,
     ) where
         Self::CTX: Send + Sync,
@@ -1683,7 +2706,11 @@ impl ProxyHttp for Server {
         #[cfg(feature = "tracing")]
         set_otel_request_attrs(session, ctx);
 
-        if let Some(p) = &self.log_parser {
+        if let Some(p) = self
+            .log_parser
+            .as_ref()
+            .filter(|_| self.should_access_log(ctx, e.is_some()))
+        {
             let buf = p.format(session, ctx);
             if let Some(logger) = &self.access_logger {
                 let _ = logger.try_send(buf);
@@ -1742,6 +2769,11 @@ mod tests {
 
     /// Creates a new test server instance with default configuration
     fn new_server() -> Server {
+        new_server_with_upstream("127.0.0.1:5000")
+    }
+
+    /// Creates the server whose location proxies to the upstream address.
+    fn new_server_with_upstream(addr: &str) -> Server {
         let toml_data = r###"
 [upstreams.charts]
 # upstream address list
@@ -1789,6 +2821,12 @@ locations = ["lo"]
 # the threads count for server (default 1)
 threads = 1
 
+# the header carrying the matched route to upstream (default none)
+route_header = "X-Pingap-Route"
+
+# the idle timeout of keep-alive connection (default none)
+downstream_idle_timeout = "30s"
+
 [plugins.stats]
 value = "/stats"
 category = "stats"
@@ -1801,7 +2839,8 @@ value = "PLpKJqvfkjTcYTDpauJf+2JnEayP+bm+0Oe60Jk="
 [storages.proxySetHeader]
 category = "config"
 value = 'proxy_set_headers = ["name:value"]'
-        "###;
+        "###
+        .replace("127.0.0.1:5000", addr);
         let pingap_conf = PingapConfig::new(toml_data.as_ref(), false).unwrap();
 
         let location = Arc::new(
@@ -1888,6 +2927,328 @@ value = 'proxy_set_headers = ["name:value"]'
         .unwrap()
     }
 
+    #[tokio::test]
+    async fn test_access_log_level() {
+        // admin server logs only the failed requests,
+        // api server logs all the requests
+        let mut admin_server = new_server();
+        admin_server.access_log_level = AccessLogLevel::Error;
+        admin_server.log_parser = Some(Parser::from("{status}"));
+        let (admin_tx, mut admin_rx) = tokio::sync::mpsc::channel(10);
+        admin_server.access_logger = Some(admin_tx);
+
+        let mut api_server = new_server();
+        api_server.access_log_level = AccessLogLevel::All;
+        api_server.log_parser = Some(Parser::from("{status}"));
+        let (api_tx, mut api_rx) = tokio::sync::mpsc::channel(10);
+        api_server.access_logger = Some(api_tx);
+
+        for status in [200, 404, 502] {
+            for server in [&admin_server, &api_server] {
+                let input_header = "GET /vicanso/pingap HTTP/1.1\r\n\r\n";
+                let mock_io =
+                    Builder::new().read(input_header.as_bytes()).build();
+                let mut session = Session::new_h1(Box::new(mock_io));
+                session.read_request().await.unwrap();
+                let mut ctx = Ctx::default();
+                ctx.state.status = Some(StatusCode::from_u16(status).unwrap());
+                server.logging(&mut session, None, &mut ctx).await;
+            }
+        }
+
+        let mut admin_logs = vec![];
+        while let Ok(buf) = admin_rx.try_recv() {
+            admin_logs.push(String::from_utf8_lossy(&buf).to_string());
+        }
+        assert_eq!(vec!["404", "502"], admin_logs);
+
+        let mut api_logs = vec![];
+        while let Ok(buf) = api_rx.try_recv() {
+            api_logs.push(String::from_utf8_lossy(&buf).to_string());
+        }
+        assert_eq!(vec!["200", "404", "502"], api_logs);
+
+        // the request with error is logged
+        let ctx = Ctx::default();
+        assert_eq!(true, admin_server.should_access_log(&ctx, true));
+        api_server.access_log_level = AccessLogLevel::Off;
+        assert_eq!(false, api_server.should_access_log(&ctx, true));
+    }
+
+    #[tokio::test]
+    async fn test_proxy_expect_continue() {
+        use pingora::apps::ServerApp;
+        use pingora::protocols::l4::stream::Stream as L4Stream;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::{TcpListener, TcpStream};
+
+        /// Reads the data of stream until the end of header, returns the
+        /// header and the data read after it.
+        async fn read_header(stream: &mut TcpStream) -> (String, Vec<u8>) {
+            let mut data = vec![];
+            let mut buf = vec![0; 1024];
+            loop {
+                let size = stream.read(&mut buf).await.unwrap();
+                assert_ne!(0, size);
+                data.extend_from_slice(&buf[..size]);
+                if let Some(index) =
+                    data.windows(4).position(|window| window == b"\r\n\r\n")
+                {
+                    let rest = data.split_off(index + 4);
+                    return (String::from_utf8_lossy(&data).to_string(), rest);
+                }
+            }
+        }
+
+        // the upstream sends `100 Continue` before reading the body
+        let upstream = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream.local_addr().unwrap().to_string();
+        let upstream_handle = tokio::spawn(async move {
+            let (mut stream, _) = upstream.accept().await.unwrap();
+            let (header, mut body) = read_header(&mut stream).await;
+            assert_eq!(
+                true,
+                header.to_lowercase().contains("expect: 100-continue")
+            );
+            stream
+                .write_all(b"HTTP/1.1 100 Continue\r\n\r\n")
+                .await
+                .unwrap();
+            let mut buf = vec![0; 1024];
+            while body.len() < 10 {
+                let size = stream.read(&mut buf).await.unwrap();
+                assert_ne!(0, size);
+                body.extend_from_slice(&buf[..size]);
+            }
+            let mut resp =
+                b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n".to_vec();
+            resp.extend_from_slice(&body);
+            stream.write_all(&resp).await.unwrap();
+            body
+        });
+
+        let app = Arc::new(http_proxy(
+            &Arc::new(configuration::ServerConf::default()),
+            new_server_with_upstream(&upstream_addr),
+        ));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (_tx, shutdown) = tokio::sync::watch::channel(false);
+            app.process_new(Box::new(L4Stream::from(stream)), &shutdown)
+                .await;
+        });
+
+        let mut client = TcpStream::connect(addr).await.unwrap();
+        client
+            .write_all(b"POST /upload HTTP/1.1\r\nHost: pingap.io\r\nExpect: 100-continue\r\nContent-Length: 10\r\n\r\n")
+            .await
+            .unwrap();
+        // the interim response of upstream is relayed to the client,
+        // and the body is sent only after it
+        let (interim, _) = read_header(&mut client).await;
+        assert_eq!(true, interim.starts_with("HTTP/1.1 100 Continue\r\n"));
+        for chunk in [b"01234".as_slice(), b"56789".as_slice()] {
+            client.write_all(chunk).await.unwrap();
+            client.flush().await.unwrap();
+        }
+
+        let (header, mut body) = read_header(&mut client).await;
+        assert_eq!(true, header.starts_with("HTTP/1.1 200 OK\r\n"));
+        let mut buf = vec![0; 1024];
+        while body.len() < 10 {
+            let size = client.read(&mut buf).await.unwrap();
+            assert_ne!(0, size);
+            body.extend_from_slice(&buf[..size]);
+        }
+        assert_eq!(b"0123456789".to_vec(), body);
+        // the body is streamed to the upstream
+        assert_eq!(b"0123456789".to_vec(), upstream_handle.await.unwrap());
+    }
+
+    #[test]
+    fn test_is_interim_response() {
+        for (status, interim) in
+            [(100, true), (103, true), (101, false), (200, false)]
+        {
+            let resp = ResponseHeader::build(status, None).unwrap();
+            assert_eq!(interim, is_interim_response(&resp));
+        }
+    }
+
+    #[test]
+    fn test_get_upstream_fallback() {
+        let status = StatusCode::BAD_GATEWAY;
+        // no fallback
+        let mut ctx = Ctx::default();
+        ctx.upstream.max_retries = Some(1);
+        assert_eq!(None, get_upstream_fallback(&mut ctx, status));
+
+        // retry, then switch to fallback upstream, then serve fallback page
+        let mut ctx = Ctx {
+            upstream: UpstreamInfo {
+                name: "charts".into(),
+                max_retries: Some(1),
+                fallback_upstream: Some("backup".into()),
+                fallback_page: Some(Bytes::from_static(b"maintenance")),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            None,
+            get_upstream_fallback(&mut ctx, StatusCode::NOT_FOUND)
+        );
+        assert_eq!(
+            Some(UpstreamFallback::Retry),
+            get_upstream_fallback(&mut ctx, status)
+        );
+        assert_eq!(1, ctx.upstream.retries);
+        assert_eq!(
+            Some(UpstreamFallback::Upstream),
+            get_upstream_fallback(&mut ctx, status)
+        );
+        assert_eq!("backup", ctx.upstream.name.as_ref());
+        assert_eq!(true, ctx.upstream.fallback);
+        assert_eq!(
+            Some(UpstreamFallback::Page),
+            get_upstream_fallback(&mut ctx, status)
+        );
+
+        // only fallback upstream, the 5xx of fallback upstream is passed
+        let mut ctx = Ctx {
+            upstream: UpstreamInfo {
+                fallback_upstream: Some("backup".into()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            Some(UpstreamFallback::Upstream),
+            get_upstream_fallback(&mut ctx, status)
+        );
+        assert_eq!(None, get_upstream_fallback(&mut ctx, status));
+
+        // only fallback page
+        let mut ctx = Ctx {
+            upstream: UpstreamInfo {
+                fallback_page: Some(Bytes::from_static(b"maintenance")),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            Some(UpstreamFallback::Page),
+            get_upstream_fallback(&mut ctx, status)
+        );
+    }
+
+    #[test]
+    fn test_on_tls_verify_failure() {
+        let new_ctx = |action: &str| {
+            let up = Upstream::new(
+                "charts",
+                &pingap_config::UpstreamConf {
+                    addrs: vec!["127.0.0.1:5000".to_string()],
+                    tls_verify_failure: Some(action.to_string()),
+                    tls_verify_fallback_upstream: Some("backup".to_string()),
+                    ..Default::default()
+                },
+                None,
+            )
+            .unwrap();
+            let mut ctx = Ctx::default();
+            ctx.upstream.name = "charts".into();
+            ctx.upstream.upstream_instance = Some(Arc::new(up));
+            ctx
+        };
+        let message = "certificate verify failed";
+
+        // no upstream instance
+        let mut ctx = Ctx::default();
+        assert_eq!(
+            false,
+            on_tls_verify_failure(&mut ctx, "127.0.0.1:5000", message)
+        );
+
+        // fail the request
+        let mut ctx = new_ctx("fail");
+        assert_eq!(
+            false,
+            on_tls_verify_failure(&mut ctx, "127.0.0.1:5000", message)
+        );
+        assert_eq!(false, ctx.upstream.tls_insecure);
+        assert_eq!("charts", ctx.upstream.name.as_ref());
+
+        // retry with insecure connection only once
+        let mut ctx = new_ctx("insecure");
+        assert_eq!(
+            true,
+            on_tls_verify_failure(&mut ctx, "127.0.0.1:5000", message)
+        );
+        assert_eq!(true, ctx.upstream.tls_insecure);
+        assert_eq!("charts", ctx.upstream.name.as_ref());
+        assert_eq!(
+            false,
+            on_tls_verify_failure(&mut ctx, "127.0.0.1:5000", message)
+        );
+
+        // switch to fallback upstream only once
+        let mut ctx = new_ctx("fallback");
+        assert_eq!(
+            true,
+            on_tls_verify_failure(&mut ctx, "127.0.0.1:5000", message)
+        );
+        assert_eq!(false, ctx.upstream.tls_insecure);
+        assert_eq!("backup", ctx.upstream.name.as_ref());
+        assert_eq!(true, ctx.upstream.fallback);
+        assert_eq!(
+            false,
+            on_tls_verify_failure(&mut ctx, "127.0.0.1:5000", message)
+        );
+    }
+
+    #[test]
+    fn test_get_proxy_protocol_header() {
+        let up = Upstream::new(
+            "charts",
+            &pingap_config::UpstreamConf {
+                addrs: vec!["127.0.0.1:5000".to_string()],
+                proxy_protocol: Some("v1".to_string()),
+                ..Default::default()
+            },
+            None,
+        )
+        .unwrap();
+        let mut ctx = Ctx::default();
+        ctx.upstream.upstream_instance = Some(Arc::new(up));
+        ctx.conn.client_ip = Some("192.168.0.1".to_string());
+        ctx.conn.remote_port = Some(56324);
+        ctx.conn.server_addr = Some("192.168.0.11".to_string());
+        ctx.conn.server_port = Some(80);
+
+        let header = get_proxy_protocol_header(&ctx).unwrap();
+        assert_eq!(
+            b"PROXY TCP4 192.168.0.1 192.168.0.11 56324 80\r\n".to_vec(),
+            header.to_bytes()
+        );
+
+        // the connections are grouped by the client address
+        let group_key = get_proxy_protocol_group_key(&ctx).unwrap();
+        assert_eq!(Some(group_key), get_proxy_protocol_group_key(&ctx));
+        ctx.conn.remote_port = Some(56325);
+        assert_ne!(Some(group_key), get_proxy_protocol_group_key(&ctx));
+        ctx.conn.client_ip = Some("192.168.0.2".to_string());
+        ctx.conn.remote_port = Some(56324);
+        assert_ne!(Some(group_key), get_proxy_protocol_group_key(&ctx));
+
+        // upstream without proxy protocol
+        ctx.upstream.upstream_instance = None;
+        assert_eq!(true, get_proxy_protocol_header(&ctx).is_none());
+        assert_eq!(None, get_proxy_protocol_group_key(&ctx));
+    }
+
     #[test]
     fn test_new_server() {
         let server = new_server();
@@ -1917,6 +3278,436 @@ value = 'proxy_set_headers = ["name:value"]'
         assert_eq!("lo", ctx.upstream.location.as_ref());
     }
 
+    #[tokio::test]
+    async fn test_validate_host() {
+        async fn new_session(input_header: &str) -> Session {
+            let mock_io = Builder::new().read(input_header.as_bytes()).build();
+            let mut session = Session::new_h1(Box::new(mock_io));
+            session.read_request().await.unwrap();
+            session
+        }
+        let mut server = new_server();
+
+        // multiple conflicting host headers are rejected
+        let mut session = new_session(
+            "GET / HTTP/1.1\r\nHost: pingap.io\r\nHost: evil.com\r\n\r\n",
+        )
+        .await;
+        let err = server.validate_host(&mut session).unwrap_err();
+        assert_eq!(pingora::ErrorType::HTTPStatus(400), err.etype);
+        assert_eq!(
+            true,
+            err.to_string()
+                .contains("multiple conflicting host headers")
+        );
+        let mut session = new_session(
+            "GET / HTTP/1.1\r\nHost: pingap.io\r\nHost: pingap.io\r\n\r\n",
+        )
+        .await;
+        assert_eq!(true, server.validate_host(&mut session).is_ok());
+
+        // http/1.0 without host is accepted by default
+        let mut session = new_session("GET / HTTP/1.0\r\n\r\n").await;
+        assert_eq!(true, server.validate_host(&mut session).is_ok());
+        assert_eq!(true, session.req_header().headers.get("Host").is_none());
+
+        // http/1.0 without host is rejected if host is required
+        server.require_host = true;
+        let mut session = new_session("GET / HTTP/1.0\r\n\r\n").await;
+        let err = server.validate_host(&mut session).unwrap_err();
+        assert_eq!(pingora::ErrorType::HTTPStatus(400), err.etype);
+        assert_eq!(true, err.to_string().contains("host header is missing"));
+
+        // http/1.0 without host uses the default host
+        server.default_host = Some(HeaderValue::from_static("pingap.io"));
+        let mut session = new_session("GET / HTTP/1.0\r\n\r\n").await;
+        assert_eq!(true, server.validate_host(&mut session).is_ok());
+        assert_eq!(
+            "pingap.io",
+            session.req_header().headers.get("Host").unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_validate_framing() {
+        async fn new_session(input_header: &str) -> Session {
+            let mock_io = Builder::new().read(input_header.as_bytes()).build();
+            let mut session = Session::new_h1(Box::new(mock_io));
+            session.read_request().await.unwrap();
+            session
+        }
+        let mut server = new_server();
+        assert_eq!(RequestFraming::Strict, server.request_framing);
+
+        // both content-length and transfer-encoding is rejected
+        let input_header = "POST / HTTP/1.1\r\nHost: pingap.io\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n";
+        let mut session = new_session(input_header).await;
+        let err = server.validate_framing(&mut session).unwrap_err();
+        assert_eq!(pingora::ErrorType::HTTPStatus(400), err.etype);
+        assert_eq!(
+            true,
+            err.to_string()
+                .contains("conflicting content-length and transfer-encoding")
+        );
+
+        // the content-length is removed in lenient mode
+        server.request_framing = RequestFraming::Lenient;
+        let mut session = new_session(input_header).await;
+        assert_eq!(true, server.validate_framing(&mut session).is_ok());
+        assert_eq!(
+            true,
+            session.req_header().headers.get("Content-Length").is_none()
+        );
+        assert_eq!(None, session.get_keepalive());
+
+        // other codings are only accepted in lenient mode
+        let input_header = "POST / HTTP/1.1\r\nHost: pingap.io\r\nTransfer-Encoding: gzip, chunked\r\n\r\n";
+        let mut session = new_session(input_header).await;
+        assert_eq!(true, server.validate_framing(&mut session).is_ok());
+        server.request_framing = RequestFraming::Strict;
+        let mut session = new_session(input_header).await;
+        let err = server.validate_framing(&mut session).unwrap_err();
+        assert_eq!(true, err.to_string().contains("invalid transfer-encoding"));
+
+        // the chunked should be the final coding
+        let mut session = new_session(
+            "POST / HTTP/1.1\r\nHost: pingap.io\r\nTransfer-Encoding: chunked, gzip\r\n\r\n",
+        )
+        .await;
+        let err = server.validate_framing(&mut session).unwrap_err();
+        assert_eq!(true, err.to_string().contains("invalid transfer-encoding"));
+
+        let mut session = new_session(
+            "POST / HTTP/1.1\r\nHost: pingap.io\r\nContent-Length: 5\r\nContent-Length: 6\r\n\r\n",
+        )
+        .await;
+        let err = server.validate_framing(&mut session).unwrap_err();
+        assert_eq!(
+            true,
+            err.to_string()
+                .contains("multiple conflicting content-length headers")
+        );
+
+        let mut session = new_session(
+            "POST / HTTP/1.1\r\nHost: pingap.io\r\nTransfer-Encoding: chunked\r\n\r\n",
+        )
+        .await;
+        assert_eq!(true, server.validate_framing(&mut session).is_ok());
+        // the connection of unambiguous chunked request is reused
+        assert_eq!(true, session.get_keepalive().is_some());
+        let mut session = new_session(
+            "POST / HTTP/1.1\r\nHost: pingap.io\r\nContent-Length: 5\r\n\r\n",
+        )
+        .await;
+        assert_eq!(true, server.validate_framing(&mut session).is_ok());
+        assert_eq!(true, session.get_keepalive().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_malformed_chunk() {
+        let server = new_server();
+        // non-hex, overflowed and negative chunk size
+        for size in ["zz", "1ffffffffffffffff", "fffffffffffffffffff", "-5"] {
+            let input_header = format!(
+                "POST / HTTP/1.1\r\nHost: pingap.io\r\nTransfer-Encoding: chunked\r\n\r\n{size}\r\nhello\r\n0\r\n\r\n"
+            );
+            let mock_io = Builder::new().read(input_header.as_bytes()).build();
+            let mut session = Session::new_h1(Box::new(mock_io));
+            session.read_request().await.unwrap();
+            assert_eq!(true, server.validate_framing(&mut session).is_ok());
+            let err = session.read_request_body().await.unwrap_err();
+            assert_eq!(400, get_downstream_error_status(err.etype()));
+        }
+
+        assert_eq!(
+            400,
+            get_downstream_error_status(&pingora::ErrorType::InvalidHTTPHeader)
+        );
+        assert_eq!(
+            499,
+            get_downstream_error_status(&pingora::ErrorType::ConnectionClosed)
+        );
+        assert_eq!(
+            408,
+            get_downstream_error_status(&pingora::ErrorType::ReadTimedout)
+        );
+        assert_eq!(
+            500,
+            get_downstream_error_status(&pingora::ErrorType::InternalError)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_downstream_idle_timeout() {
+        let server = new_server();
+        assert_eq!(Some(30), server.downstream_idle_timeout);
+
+        // the keep-alive connection is closed after idle for 30s
+        let input_header = "GET /vicanso/pingap HTTP/1.1\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        server
+            .early_request_filter(&mut session, &mut Ctx::default())
+            .await
+            .unwrap();
+        assert_eq!(Some(30), session.get_keepalive());
+
+        // the connection is not reused if client disallows it
+        let input_header =
+            "GET /vicanso/pingap HTTP/1.1\r\nConnection: close\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        server
+            .early_request_filter(&mut session, &mut Ctx::default())
+            .await
+            .unwrap();
+        assert_eq!(None, session.get_keepalive());
+    }
+
+    #[tokio::test]
+    async fn test_error_while_proxy() {
+        let server = new_server();
+        let peer = HttpPeer::new("127.0.0.1:5000", false, "".to_string());
+        let new_ctx = || Ctx {
+            upstream: UpstreamInfo {
+                max_retries: Some(1),
+                retry_on_reset: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let new_session = |method: &str| {
+            let input_header =
+                format!("{method} /vicanso/pingap HTTP/1.1\r\n\r\n");
+            let mock_io = Builder::new().read(input_header.as_bytes()).build();
+            Session::new_h1(Box::new(mock_io))
+        };
+
+        // the idempotent request is retried after the upstream reset
+        let mut session = new_session("GET");
+        session.read_request().await.unwrap();
+        let mut ctx = new_ctx();
+        let e = server.error_while_proxy(
+            &peer,
+            &mut session,
+            pingora::Error::new(pingora::ErrorType::ConnectionClosed),
+            &mut ctx,
+            false,
+        );
+        assert_eq!(true, e.retry());
+        assert_eq!(1, ctx.upstream.retries);
+
+        // the max retries is reached
+        let e = server.error_while_proxy(
+            &peer,
+            &mut session,
+            pingora::Error::new(pingora::ErrorType::ConnectionClosed),
+            &mut ctx,
+            false,
+        );
+        assert_eq!(false, e.retry());
+        assert_eq!(1, ctx.upstream.retries);
+
+        // the non idempotent request isn't retried
+        let mut session = new_session("POST");
+        session.read_request().await.unwrap();
+        let mut ctx = new_ctx();
+        let e = server.error_while_proxy(
+            &peer,
+            &mut session,
+            pingora::Error::new(pingora::ErrorType::ConnectionClosed),
+            &mut ctx,
+            false,
+        );
+        assert_eq!(false, e.retry());
+        assert_eq!(0, ctx.upstream.retries);
+
+        // the retry on reset is disabled
+        let mut session = new_session("GET");
+        session.read_request().await.unwrap();
+        let mut ctx = new_ctx();
+        ctx.upstream.retry_on_reset = false;
+        let e = server.error_while_proxy(
+            &peer,
+            &mut session,
+            pingora::Error::new(pingora::ErrorType::ReadError),
+            &mut ctx,
+            false,
+        );
+        assert_eq!(false, e.retry());
+        assert_eq!(0, ctx.upstream.retries);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_reset_after_response_committed() {
+        let server = new_server();
+        let peer = HttpPeer::new("127.0.0.1:5000", false, "".to_string());
+        // nothing is written after the response header
+        let mock_io = Builder::new()
+            .read(b"GET /vicanso/pingap HTTP/1.1\r\n\r\n")
+            .write(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n")
+            .build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        session.set_keepalive(Some(30));
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        resp.insert_header("Content-Length", "10").unwrap();
+        session.write_response_header(Box::new(resp)).await.unwrap();
+
+        let mut ctx = Ctx {
+            upstream: UpstreamInfo {
+                max_retries: Some(1),
+                retry_on_reset: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let e = server.error_while_proxy(
+            &peer,
+            &mut session,
+            pingora::Error::new(pingora::ErrorType::ConnectionClosed),
+            &mut ctx,
+            false,
+        );
+        assert_eq!(false, e.retry());
+        assert_eq!(0, ctx.upstream.retries);
+
+        let result = server.fail_to_proxy(&mut session, &e, &mut ctx).await;
+        assert_eq!(502, result.error_code);
+        assert_eq!(false, result.can_reuse_downstream);
+        assert_eq!(None, session.get_keepalive());
+    }
+
+    #[tokio::test]
+    async fn test_acquire_fair_queue() {
+        let mut server = new_server();
+        server.fair_queue =
+            Some(FairQueue::new(1, 1, Duration::from_millis(20)));
+        server.fair_queue_key = FairQueueKey::Header("X-Tenant".to_string());
+        let new_session = |tenant: &str| {
+            let input_header = format!(
+                "GET /vicanso/pingap HTTP/1.1\r\nX-Tenant: {tenant}\r\n\r\n"
+            );
+            let mock_io = Builder::new().read(input_header.as_bytes()).build();
+            Session::new_h1(Box::new(mock_io))
+        };
+
+        let mut session = new_session("a");
+        session.read_request().await.unwrap();
+        assert_eq!(
+            "a",
+            get_fair_queue_key(
+                &server.fair_queue_key,
+                &session,
+                &mut Ctx::default()
+            )
+        );
+        let mut ctx = Ctx::default();
+        server
+            .acquire_fair_queue(&mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, ctx.state.fair_queue_permit.is_some());
+
+        // the capacity is exhausted, waiting in the queue timeout
+        let mut session = new_session("b");
+        session.read_request().await.unwrap();
+        let err = server
+            .acquire_fair_queue(&mut session, &mut Ctx::default())
+            .await
+            .unwrap_err();
+        assert_eq!(&pingora::ErrorType::HTTPStatus(503), err.etype());
+        assert_eq!(
+            "HTTPStatus context: waiting in the queue timeout cause:  InternalError",
+            err.to_string().trim()
+        );
+
+        // the capacity is released when the request is done
+        drop(ctx);
+        let mut ctx = Ctx::default();
+        server
+            .acquire_fair_queue(&mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(true, ctx.state.fair_queue_permit.is_some());
+
+        let mut ctx = Ctx::default();
+        ctx.conn.client_ip = Some("192.168.1.1".to_string());
+        assert_eq!(
+            "192.168.1.1",
+            get_fair_queue_key(&FairQueueKey::Ip, &session, &mut ctx)
+        );
+        assert_eq!(
+            "",
+            get_fair_queue_key(
+                &FairQueueKey::Query("app".to_string()),
+                &session,
+                &mut ctx
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_set_connection_close() {
+        let server = new_server();
+        let input_header = "GET /vicanso/pingap HTTP/1.1\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+        server
+            .early_request_filter(&mut session, &mut Ctx::default())
+            .await
+            .unwrap();
+        assert_eq!(Some(30), session.get_keepalive());
+
+        // the response of draining server closes the keep-alive connection
+        let mut resp = ResponseHeader::build(200, None).unwrap();
+        set_connection_close(&mut session, &mut resp);
+        assert_eq!("close", resp.headers.get("Connection").unwrap());
+        // no more request is accepted on the connection
+        assert_eq!(None, session.get_keepalive());
+    }
+
+    #[tokio::test]
+    async fn test_upstream_request_filter() {
+        let server = new_server();
+
+        let input_header = "GET /vicanso/pingap HTTP/1.1\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+
+        let mut ctx = Ctx {
+            upstream: UpstreamInfo {
+                location: "lo".to_string().into(),
+                name: "charts".to_string().into(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut upstream_request =
+            RequestHeader::build("GET", b"/vicanso/pingap", None).unwrap();
+        server
+            .upstream_request_filter(
+                &mut session,
+                &mut upstream_request,
+                &mut ctx,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            "lo/charts",
+            upstream_request
+                .headers
+                .get("x-pingap-route")
+                .unwrap()
+                .to_str()
+                .unwrap()
+        );
+    }
+
     #[tokio::test]
     async fn test_request_filter() {
         let server = new_server();
@@ -1941,6 +3732,151 @@ value = 'proxy_set_headers = ["name:value"]'
         assert_eq!(false, done);
     }
 
+    #[tokio::test]
+    async fn test_connect_request() {
+        let server = new_server();
+        let input_header = "CONNECT pingap.io:443 HTTP/1.1\r\n\r\n";
+        let mock_io = Builder::new().read(input_header.as_bytes()).build();
+        let mut session = Session::new_h1(Box::new(mock_io));
+        session.read_request().await.unwrap();
+
+        let location = server.location_provider.get("lo").unwrap();
+        let mut ctx = Ctx {
+            upstream: UpstreamInfo {
+                location: "lo".to_string().into(),
+                location_instance: Some(location.clone()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        // the CONNECT request isn't authorized by forward proxy plugin
+        let err = server
+            .request_filter(&mut session, &mut ctx)
+            .await
+            .unwrap_err();
+        assert_eq!(&pingora::ErrorType::HTTPStatus(405), err.etype());
+    }
+
+    #[tokio::test]
+    async fn test_unmatched_action() {
+        struct EmptyServerLocationsLoader {}
+        impl ServerLocationsProvider for EmptyServerLocationsLoader {
+            fn get(&self, _name: &str) -> Option<Arc<Vec<String>>> {
+                None
+            }
+        }
+        async fn new_session() -> Session {
+            let input_header = "GET /vicanso/pingap HTTP/1.1\r\n\r\n";
+            let mock_io = Builder::new().read(input_header.as_bytes()).build();
+            let mut session = Session::new_h1(Box::new(mock_io));
+            session.read_request().await.unwrap();
+            session
+        }
+        let mut server = new_server();
+        server.server_locations_provider =
+            Arc::new(EmptyServerLocationsLoader {});
+
+        // respond the status of unmatched action, the default is 500
+        for (action, status) in
+            [(None, 500), (Some("404"), 404), (Some("444"), 444)]
+        {
+            server.unmatched_handler =
+                action.map(|value| UnmatchedHandler::new(value).unwrap());
+            let mut session = new_session().await;
+            let mut ctx = Ctx::default();
+            server
+                .early_request_filter(&mut session, &mut ctx)
+                .await
+                .unwrap();
+            assert_eq!(true, ctx.upstream.location_instance.is_none());
+            let err = server
+                .request_filter(&mut session, &mut ctx)
+                .await
+                .unwrap_err();
+            assert_eq!(&pingora::ErrorType::HTTPStatus(status), err.etype());
+        }
+
+        // serve the unmatched request by the default location
+        server.unmatched_handler =
+            Some(UnmatchedHandler::new("location:lo").unwrap());
+        let mut session = new_session().await;
+        let mut ctx = Ctx::default();
+        server
+            .early_request_filter(&mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!("lo", ctx.upstream.location.as_ref());
+        let done = server.request_filter(&mut session, &mut ctx).await.unwrap();
+        assert_eq!(false, done);
+
+        // proxy the unmatched request to the default upstream
+        server.unmatched_handler =
+            Some(UnmatchedHandler::new("upstream:diving").unwrap());
+        let mut session = new_session().await;
+        let mut ctx = Ctx::default();
+        server
+            .early_request_filter(&mut session, &mut ctx)
+            .await
+            .unwrap();
+        assert_eq!(UNMATCHED_LOCATION, ctx.upstream.location.as_ref());
+        assert_eq!(
+            "diving",
+            ctx.upstream.location_instance.as_ref().unwrap().upstream()
+        );
+        let done = server.request_filter(&mut session, &mut ctx).await.unwrap();
+        assert_eq!(false, done);
+
+        let result = UnmatchedHandler::new("200");
+        assert_eq!(
+            "Common error, category: unmatched_action, Invalid error unmatched action(200) is invalid",
+            result.err().unwrap().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_acme_fallback() {
+        // http server doesn't have fallback
+        let mut server = new_server();
+        server.acme_fallback = Some(AcmeFallback::Http);
+        assert_eq!(false, server.enable_acme_fallback());
+        assert_eq!(false, server.is_tls());
+
+        // tls server without fallback runs in tls
+        let mut server = new_server();
+        server.global_certificates = true;
+        assert_eq!(false, server.enable_acme_fallback());
+        assert_eq!(true, server.is_tls());
+
+        // tls server serves http before the certificate is issued
+        let mut server = new_server();
+        server.global_certificates = true;
+        server.acme_fallback = Some(AcmeFallback::Http);
+        assert_eq!(true, server.enable_acme_fallback());
+        assert_eq!(false, server.is_tls());
+        let mut session = Session::new_h1(Box::new(
+            Builder::new()
+                .read(b"GET /vicanso/pingap?size=1 HTTP/1.1\r\nHost: pingap.io\r\n\r\n")
+                .build(),
+        ));
+        session.read_request().await.unwrap();
+        assert_eq!(
+            None,
+            server.get_acme_fallback_location(session.req_header())
+        );
+        assert_eq!(true, server.run(Arc::new(Default::default())).is_ok());
+
+        // tls server redirects to http before the certificate is issued
+        let mut server = new_server();
+        server.global_certificates = true;
+        server.acme_fallback = Some(AcmeFallback::Redirect);
+        assert_eq!(true, server.enable_acme_fallback());
+        assert_eq!(false, server.is_tls());
+        assert_eq!(
+            Some("http://pingap.io/vicanso/pingap?size=1".to_string()),
+            server.get_acme_fallback_location(session.req_header())
+        );
+    }
+
     #[tokio::test]
     async fn test_cache_key_callback() {
         let server = new_server();
@@ -2066,5 +4002,45 @@ value = 'proxy_set_headers = ["name:value"]'
             )
             .unwrap();
         assert_eq!(false, result.is_cacheable());
+
+        // mark the response without validators to generate etag
+        let mut ctx = Ctx {
+            cache: Some(CacheInfo {
+                etag: true,
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let mut upstream_response =
+            ResponseHeader::build_no_case(200, None).unwrap();
+        upstream_response
+            .append_header("Cache-Control", "max-age=100")
+            .unwrap();
+        let RespCacheable::Cacheable(meta) = server
+            .response_cache_filter(&session, &upstream_response, &mut ctx)
+            .unwrap()
+        else {
+            panic!("response is not cacheable");
+        };
+        assert_eq!(
+            true,
+            meta.headers()
+                .contains_key(&HTTP_HEADER_NAME_X_GENERATE_ETAG)
+        );
+
+        upstream_response
+            .append_header("ETag", r#""pingap""#)
+            .unwrap();
+        let RespCacheable::Cacheable(meta) = server
+            .response_cache_filter(&session, &upstream_response, &mut ctx)
+            .unwrap()
+        else {
+            panic!("response is not cacheable");
+        };
+        assert_eq!(
+            false,
+            meta.headers()
+                .contains_key(&HTTP_HEADER_NAME_X_GENERATE_ETAG)
+        );
     }
 }
@@ -36,6 +36,9 @@ pub struct ServerConf {
     // None means access logging is disabled
     pub access_log: Option<String>,
 
+    // Access log level: "all", "error" (only failed requests) or "off"
+    pub access_log_level: Option<String>,
+
     // List of location route identifiers that this server will handle
     pub locations: Vec<String>,
 
@@ -55,6 +58,18 @@ pub struct ServerConf {
     // Common values: "TLSv1.2", "TLSv1.3"
     pub tls_max_version: Option<String>,
 
+    // Whether TLS session tickets are enabled, None means enabled
+    pub tls_session_tickets: Option<bool>,
+
+    // Size of server side TLS session cache, 0 disables the cache
+    pub tls_session_cache_size: Option<usize>,
+
+    // Interval of rotating the session ticket key
+    pub tls_ticket_key_rotation: Option<Duration>,
+
+    // Duration that the retired ticket key can still decrypt tickets
+    pub tls_ticket_key_overlap: Option<Duration>,
+
     // Number of worker threads for handling connections
     // None means use system default
     pub threads: Option<usize>,
@@ -76,6 +91,14 @@ pub struct ServerConf {
     /// See the [man page](https://man7.org/linux/man-pages/man7/socket.7.html) for more information.
     pub reuse_port: Option<bool>,
 
+    // Whether to set TCP_NODELAY on accepted connections
+    // None means the default of pingora(enabled)
+    pub tcp_nodelay: Option<bool>,
+
+    // DSCP value used to mark the packets of accepted connections
+    // None means the system default
+    pub dscp: Option<u8>,
+
     // Whether to use globally configured TLS certificates
     // False means the server is using http protocol
     pub global_certificates: bool,
@@ -88,6 +111,14 @@ pub struct ServerConf {
     // None means metrics collection is disabled
     pub prometheus_metrics: Option<String>,
 
+    // Histogram buckets of http response time metric
+    // Empty means using the default buckets
+    pub prometheus_response_time_buckets: Vec<f64>,
+
+    // Histogram buckets of upstream processing and response time metrics
+    // Empty means using the default buckets
+    pub prometheus_upstream_time_buckets: Vec<f64>,
+
     // OpenTelemetry exporter configuration string
     // Used for distributed tracing support
     pub otlp_exporter: Option<String>,
@@ -99,11 +130,51 @@ pub struct ServerConf {
     // Whether to enable server-timing header
     pub enable_server_timing: bool,
 
+    // Request header carrying the matched route to upstream
+    pub route_header: Option<String>,
+
+    // Action for the request matching no location
+    pub unmatched_action: Option<String>,
+
+    // Host for the HTTP/1.0 request without host header
+    pub default_host: Option<String>,
+
+    // Reject the HTTP/1.0 request without host header and default host
+    pub require_host: bool,
+
+    // Fallback of the tls server when the acme certificate is not yet issued
+    pub acme_fallback: Option<String>,
+
+    // Validation of Content-Length and Transfer-Encoding, strict or lenient,
+    // the malformed chunk size is rejected by the http parser
+    pub request_framing: Option<String>,
+
+    // PROXY protocol header of the accepted connections
+    pub proxy_protocol: Option<String>,
+
+    // Trusted sources of the PROXY protocol header
+    pub proxy_protocol_trusted: Option<Vec<String>>,
+
+    // Maximum number of concurrent requests of the fair queue
+    pub fair_queue_capacity: Option<usize>,
+
+    // Fairness key of the queued requests
+    pub fair_queue_key: Option<String>,
+
+    // Maximum number of queued requests of each fairness key
+    pub fair_queue_depth: Option<usize>,
+
+    // Maximum time of request waiting in the fair queue
+    pub fair_queue_timeout: Option<Duration>,
+
     // downstream read timeout
     pub downstream_read_timeout: Option<Duration>,
 
     // downstream write timeout
     pub downstream_write_timeout: Option<Duration>,
+
+    // downstream idle timeout of keep-alive connection
+    pub downstream_idle_timeout: Option<Duration>,
 }
 
 impl fmt::Display for ServerConf {
@@ -128,6 +199,11 @@ impl fmt::Display for ServerConf {
             "    Access Log: {}",
             self.access_log.as_deref().unwrap_or("disabled")
         )?;
+        writeln!(
+            f,
+            "    Access Log Level: {}",
+            self.access_log_level.as_deref().unwrap_or("all")
+        )?;
         if !self.locations.is_empty() {
             writeln!(f, "    Locations: {}", self.locations.join(", "))?;
         }
@@ -146,6 +222,11 @@ impl fmt::Display for ServerConf {
             "    Downstream Write Timeout: {}",
             format_opt_duration(&self.downstream_write_timeout)
         )?;
+        writeln!(
+            f,
+            "    Downstream Idle Timeout: {}",
+            format_opt_duration(&self.downstream_idle_timeout)
+        )?;
 
         // --- TLS ---
         writeln!(f, "  - TLS Settings:")?;
@@ -170,6 +251,26 @@ impl fmt::Display for ServerConf {
             "    Ciphersuites (TLS 1.3): {}",
             self.tls_ciphersuites.clone().unwrap_or_default()
         )?;
+        writeln!(
+            f,
+            "    Session Tickets: {}",
+            self.tls_session_tickets.unwrap_or(true)
+        )?;
+        writeln!(
+            f,
+            "    Session Cache Size: {}",
+            format_opt_usize(&self.tls_session_cache_size)
+        )?;
+        writeln!(
+            f,
+            "    Ticket Key Rotation: {}",
+            format_opt_duration(&self.tls_ticket_key_rotation)
+        )?;
+        writeln!(
+            f,
+            "    Ticket Key Overlap: {}",
+            format_opt_duration(&self.tls_ticket_key_overlap)
+        )?;
 
         // --- TCP ---
         writeln!(f, "  - TCP Settings:")?;
@@ -199,6 +300,44 @@ impl fmt::Display for ServerConf {
             self.otlp_exporter.as_deref().unwrap_or("disabled")
         )?;
         writeln!(f, "    Server-Timing Header: {}", self.enable_server_timing)?;
+        writeln!(
+            f,
+            "    Route Header: {}",
+            self.route_header.as_deref().unwrap_or("disabled")
+        )?;
+        writeln!(
+            f,
+            "    Unmatched Action: {}",
+            self.unmatched_action.as_deref().unwrap_or("default")
+        )?;
+        writeln!(
+            f,
+            "    Default Host: {}",
+            self.default_host.as_deref().unwrap_or("none")
+        )?;
+        writeln!(f, "    Require Host: {}", self.require_host)?;
+        writeln!(
+            f,
+            "    ACME Fallback: {}",
+            self.acme_fallback.as_deref().unwrap_or("none")
+        )?;
+        writeln!(
+            f,
+            "    Request Framing: {}",
+            self.request_framing.as_deref().unwrap_or("strict")
+        )?;
+        writeln!(
+            f,
+            "    Proxy Protocol: {}",
+            self.proxy_protocol.as_deref().unwrap_or("off")
+        )?;
+        writeln!(
+            f,
+            "    Fair Queue Capacity: {}",
+            self.fair_queue_capacity
+                .filter(|value| *value > 0)
+                .map_or("disabled".to_string(), |value| value.to_string())
+        )?;
 
         // --- Extensibility ---
         if let Some(modules) = &self.modules {
@@ -257,8 +396,13 @@ pub fn parse_from_conf(conf: PingapConfig) -> Vec<ServerConf> {
             tls_ciphersuites: item.tls_ciphersuites.clone(),
             tls_min_version: item.tls_min_version.clone(),
             tls_max_version: item.tls_max_version.clone(),
+            tls_session_tickets: item.tls_session_tickets,
+            tls_session_cache_size: item.tls_session_cache_size,
+            tls_ticket_key_rotation: item.tls_ticket_key_rotation,
+            tls_ticket_key_overlap: item.tls_ticket_key_overlap,
             addr: item.addr,
             access_log: item.access_log,
+            access_log_level: item.access_log_level,
             locations: item.locations.unwrap_or_default(),
             threads: item.threads,
             global_certificates: item.global_certificates.unwrap_or_default(),
@@ -266,13 +410,36 @@ pub fn parse_from_conf(conf: PingapConfig) -> Vec<ServerConf> {
             tcp_keepalive,
             tcp_fastopen: item.tcp_fastopen,
             reuse_port: item.reuse_port,
+            tcp_nodelay: item.tcp_nodelay,
+            dscp: item.dscp,
             prometheus_metrics: item.prometheus_metrics,
+            prometheus_response_time_buckets: item
+                .prometheus_response_time_buckets
+                .clone()
+                .unwrap_or_default(),
+            prometheus_upstream_time_buckets: item
+                .prometheus_upstream_time_buckets
+                .clone()
+                .unwrap_or_default(),
             otlp_exporter: item.otlp_exporter.clone(),
             modules: item.modules.clone(),
             enable_server_timing: item.enable_server_timing.unwrap_or_default(),
+            route_header: item.route_header.clone(),
+            unmatched_action: item.unmatched_action.clone(),
+            default_host: item.default_host.clone(),
+            require_host: item.require_host.unwrap_or_default(),
+            acme_fallback: item.acme_fallback.clone(),
+            request_framing: item.request_framing.clone(),
+            proxy_protocol: item.proxy_protocol.clone(),
+            proxy_protocol_trusted: item.proxy_protocol_trusted.clone(),
+            fair_queue_capacity: item.fair_queue_capacity,
+            fair_queue_key: item.fair_queue_key.clone(),
+            fair_queue_depth: item.fair_queue_depth,
+            fair_queue_timeout: item.fair_queue_timeout,
             error_template,
             downstream_read_timeout: item.downstream_read_timeout,
             downstream_write_timeout: item.downstream_write_timeout,
+            downstream_idle_timeout: item.downstream_idle_timeout,
         });
     }
 
@@ -315,18 +482,24 @@ mod tests {
   - General Settings:
     Admin Role: false
     Access Log: combined
+    Access Log Level: all
     Locations: charts-location
     Worker Threads: 4
   - Protocols & Timeouts:
     HTTP/2 Enabled: true
     Downstream Read Timeout: default
     Downstream Write Timeout: default
+    Downstream Idle Timeout: default
   - TLS Settings:
     Global Certificates: false
     Min Version: 
     Max Version: 
     Cipher List (TLS <1.3): 
     Ciphersuites (TLS 1.3): 
+    Session Tickets: true
+    Session Cache Size: default
+    Ticket Key Rotation: default
+    Ticket Key Overlap: default
   - TCP Settings:
     Keepalive: idle=10s, interval=5s, count=10
     Fast Open: 10
@@ -335,6 +508,14 @@ mod tests {
     Prometheus Endpoint: disabled
     OTLP Exporter: disabled
     Server-Timing Header: false
+    Route Header: disabled
+    Unmatched Action: default
+    Default Host: none
+    Require Host: false
+    ACME Fallback: none
+    Request Framing: strict
+    Proxy Protocol: off
+    Fair Queue Capacity: disabled
 "#,
             conf.to_string()
         );
@@ -345,18 +526,24 @@ mod tests {
   - General Settings:
     Admin Role: false
     Access Log: combined
+    Access Log Level: all
     Locations: charts-location
     Worker Threads: 4
   - Protocols & Timeouts:
     HTTP/2 Enabled: true
     Downstream Read Timeout: default
     Downstream Write Timeout: default
+    Downstream Idle Timeout: default
   - TLS Settings:
     Global Certificates: false
     Min Version: 
     Max Version: 
     Cipher List (TLS <1.3): 
     Ciphersuites (TLS 1.3): 
+    Session Tickets: true
+    Session Cache Size: default
+    Ticket Key Rotation: default
+    Ticket Key Overlap: default
   - TCP Settings:
     Keepalive: idle=10s, interval=5s, count=10
     Fast Open: 10
@@ -365,6 +552,14 @@ mod tests {
     Prometheus Endpoint: disabled
     OTLP Exporter: disabled
     Server-Timing Header: false
+    Route Header: disabled
+    Unmatched Action: default
+    Default Host: none
+    Require Host: false
+    ACME Fallback: none
+    Request Framing: strict
+    Proxy Protocol: off
+    Fair Queue Capacity: disabled
 "#,
             conf.to_string()
         );
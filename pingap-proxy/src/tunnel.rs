@@ -0,0 +1,200 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::LOG_TARGET;
+use pingap_core::new_internal_error;
+use pingora::protocols::http::ServerSession;
+use pingora::protocols::l4::stream::Stream;
+use pingora::proxy::Session;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncWriteExt, copy_bidirectional};
+use tokio::net::{TcpStream, UnixStream};
+use tracing::{debug, error};
+
+const TUNNEL_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Connects to the first available address of the tunnel.
+async fn connect(addrs: &[SocketAddr]) -> pingora::Result<TcpStream> {
+    let mut message = "no address of tunnel".to_string();
+    for addr in addrs {
+        match tokio::time::timeout(
+            TUNNEL_CONNECT_TIMEOUT,
+            TcpStream::connect(addr),
+        )
+        .await
+        {
+            Ok(Ok(stream)) => return Ok(stream),
+            Ok(Err(e)) => message = format!("connect {addr} fail: {e}"),
+            Err(_) => message = format!("connect {addr} timeout"),
+        }
+    }
+    Err(new_internal_error(502, message))
+}
+
+/// Serializes the request header of CONNECT, it's used to build the
+/// placeholder session.
+fn get_request_head(session: &Session) -> Vec<u8> {
+    let header = session.req_header();
+    let mut buf =
+        format!("{} {} HTTP/1.1\r\n", header.method, header.uri).into_bytes();
+    for (name, value) in header.headers.iter() {
+        buf.extend_from_slice(name.as_str().as_bytes());
+        buf.extend_from_slice(b": ");
+        buf.extend_from_slice(value.as_bytes());
+        buf.extend_from_slice(b"\r\n");
+    }
+    buf.extend_from_slice(b"\r\n");
+    buf
+}
+
+/// Takes the downstream stream of the http/1.1 session, it's replaced by
+/// a placeholder session with the same request header, so the logging
+/// after the tunnel still works.
+async fn take_downstream_stream(
+    session: &mut Session,
+) -> pingora::Result<Stream> {
+    let (placeholder, mut peer) =
+        UnixStream::pair().map_err(|e| new_internal_error(500, e))?;
+    peer.write_all(&get_request_head(session))
+        .await
+        .map_err(|e| new_internal_error(500, e))?;
+    drop(peer);
+    let mut placeholder =
+        ServerSession::new_http1(Box::new(Stream::from(placeholder)));
+    placeholder.read_request().await?;
+    placeholder.set_keepalive(None);
+
+    let downstream = std::mem::replace(
+        &mut session.downstream_session,
+        Box::new(placeholder),
+    );
+    match *downstream {
+        ServerSession::H1(session) => Ok(session.into_inner()),
+        other => {
+            session.downstream_session = Box::new(other);
+            Err(new_internal_error(
+                405,
+                "CONNECT is only supported by http/1.1",
+            ))
+        },
+    }
+}
+
+/// Establishes the tunnel of CONNECT request to the authorized addresses,
+/// the bytes are copied in both directions until either side is closed.
+///
+/// Returns the bytes of (client to upstream, upstream to client).
+pub async fn proxy_tunnel(
+    session: &mut Session,
+    addrs: &[SocketAddr],
+) -> pingora::Result<(u64, u64)> {
+    if session.is_http2() {
+        return Err(new_internal_error(
+            405,
+            "CONNECT is only supported by http/1.1",
+        ));
+    }
+    // the error response is sent by session if the connection fails
+    let mut upstream = connect(addrs).await?;
+    let mut downstream = take_downstream_stream(session).await?;
+    downstream
+        .write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n")
+        .await
+        .map_err(|e| new_internal_error(500, e))?;
+    downstream
+        .flush()
+        .await
+        .map_err(|e| new_internal_error(500, e))?;
+    debug!(
+        target: LOG_TARGET,
+        addr = upstream.peer_addr().map(|addr| addr.to_string()).ok(),
+        "tunnel is established"
+    );
+    match copy_bidirectional(&mut downstream, &mut upstream).await {
+        Ok(size) => Ok(size),
+        Err(e) => {
+            // the tunnel is interrupted, the response has been sent
+            error!(target: LOG_TARGET, error = %e, "tunnel is interrupted");
+            Ok((0, 0))
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn test_proxy_tunnel() {
+        // the echo server of destination
+        let echo = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let echo_addr = echo.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = echo.accept().await.unwrap();
+            let mut buf = [0; 4];
+            stream.read_exact(&mut buf).await.unwrap();
+            stream.write_all(&buf).await.unwrap();
+        });
+
+        let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        let handle = tokio::spawn(async move {
+            let (stream, _) = proxy.accept().await.unwrap();
+            let mut session = Session::new_h1(Box::new(Stream::from(stream)));
+            session.read_request().await.unwrap();
+            let size = proxy_tunnel(&mut session, &[echo_addr]).await.unwrap();
+            // the request header is kept for logging
+            assert_eq!(
+                echo_addr.to_string(),
+                session.req_header().uri.to_string()
+            );
+            size
+        });
+
+        let mut client = TcpStream::connect(proxy_addr).await.unwrap();
+        client
+            .write_all(
+                format!(
+                    "CONNECT {echo_addr} HTTP/1.1\r\nHost: {echo_addr}\r\n\r\n"
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+        let established = b"HTTP/1.1 200 Connection Established\r\n\r\n";
+        let mut buf = vec![0; established.len()];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(established.to_vec(), buf);
+
+        client.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        client.read_exact(&mut buf).await.unwrap();
+        assert_eq!(b"ping", &buf);
+        drop(client);
+        assert_eq!((4, 4), handle.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_proxy_tunnel_connect_fail() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+        let err = connect(&[addr]).await.err().unwrap();
+        assert_eq!(pingora::ErrorType::HTTPStatus(502), err.etype);
+    }
+}
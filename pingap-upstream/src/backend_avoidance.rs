@@ -0,0 +1,145 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use dashmap::DashMap;
+use http::StatusCode;
+use std::collections::HashSet;
+use std::time::{Duration, Instant};
+
+/// BackendAvoidance skips the backend that just failed a request for a
+/// short window, so the retry doesn't re-pick the same backend during a
+/// transient blip. It's softer than circuit breaking: a single failure
+/// is enough, and the backend is reconsidered after the window or after
+/// it responds successfully.
+pub struct BackendAvoidance {
+    /// The duration of avoiding the failed backend
+    window: Duration,
+    /// The status codes treated as failure, default is 5xx
+    failure_status_codes: Option<HashSet<StatusCode>>,
+    /// The last failed time of each backend
+    failed_at: DashMap<String, Instant>,
+}
+
+impl BackendAvoidance {
+    pub fn new(window: Duration, failure_status_codes: &[u16]) -> Self {
+        let failure_status_codes = failure_status_codes
+            .iter()
+            .flat_map(|code| StatusCode::from_u16(*code).ok())
+            .collect::<HashSet<StatusCode>>();
+        Self {
+            window,
+            failure_status_codes: (!failure_status_codes.is_empty())
+                .then_some(failure_status_codes),
+            failed_at: DashMap::new(),
+        }
+    }
+
+    fn on_failure_at(&self, address: &str, now: Instant) {
+        self.failed_at.insert(address.to_string(), now);
+    }
+
+    fn is_avoided_at(&self, address: &str, now: Instant) -> bool {
+        let Some(failed_at) = self.failed_at.get(address).map(|value| *value)
+        else {
+            return false;
+        };
+        if now.saturating_duration_since(failed_at) < self.window {
+            return true;
+        }
+        // the window is passed, the backend is reconsidered
+        self.failed_at
+            .remove_if(address, |_, value| *value == failed_at);
+        false
+    }
+
+    /// Records the transport failure(e.g. connect refused) of backend.
+    pub fn on_transport_failure(&self, address: &str) {
+        self.on_failure_at(address, Instant::now());
+    }
+
+    /// Records the response of backend, the failed backend is avoided
+    /// and the successful backend is reconsidered immediately.
+    pub fn on_response(&self, address: &str, status: StatusCode) {
+        let is_failure = self.failure_status_codes.as_ref().map_or_else(
+            || status.is_server_error(),
+            |codes| codes.contains(&status),
+        );
+        if is_failure {
+            self.on_failure_at(address, Instant::now());
+        } else if !self.failed_at.is_empty() {
+            self.failed_at.remove(address);
+        }
+    }
+
+    /// Returns true if the backend failed within the avoidance window.
+    #[inline]
+    pub fn is_avoided(&self, address: &str) -> bool {
+        if self.failed_at.is_empty() {
+            return false;
+        }
+        self.is_avoided_at(address, Instant::now())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_backend_avoidance() {
+        let avoidance = BackendAvoidance::new(Duration::from_secs(2), &[]);
+        let addr = "127.0.0.1:5000";
+        let now = Instant::now();
+        assert_eq!(false, avoidance.is_avoided_at(addr, now));
+
+        // the failed backend is avoided in the window
+        avoidance.on_failure_at(addr, now);
+        assert_eq!(true, avoidance.is_avoided_at(addr, now));
+        assert_eq!(
+            true,
+            avoidance.is_avoided_at(addr, now + Duration::from_millis(1999))
+        );
+        assert_eq!(false, avoidance.is_avoided_at("127.0.0.1:5001", now));
+
+        // then it's reconsidered
+        assert_eq!(
+            false,
+            avoidance.is_avoided_at(addr, now + Duration::from_secs(2))
+        );
+        assert_eq!(true, avoidance.failed_at.is_empty());
+    }
+
+    #[test]
+    fn test_backend_avoidance_on_response() {
+        let avoidance = BackendAvoidance::new(Duration::from_secs(10), &[]);
+        let addr = "127.0.0.1:5000";
+        avoidance.on_response(addr, StatusCode::BAD_GATEWAY);
+        assert_eq!(true, avoidance.is_avoided(addr));
+        // the successful response clears the avoidance
+        avoidance.on_response(addr, StatusCode::OK);
+        assert_eq!(false, avoidance.is_avoided(addr));
+
+        avoidance.on_transport_failure(addr);
+        assert_eq!(true, avoidance.is_avoided(addr));
+
+        // only the configured status codes are treated as failure
+        let avoidance =
+            BackendAvoidance::new(Duration::from_secs(10), &[429, 503]);
+        avoidance.on_response(addr, StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(false, avoidance.is_avoided(addr));
+        avoidance.on_response(addr, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(true, avoidance.is_avoided(addr));
+    }
+}
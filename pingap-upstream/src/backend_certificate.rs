@@ -0,0 +1,161 @@
+// Copyright 2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::Error;
+use pingap_certificate::{Certificate, parse_certificate};
+use pingora::tls::ssl::{SslConnector, SslMethod, SslVerifyMode};
+use pingora::tls::tokio_ssl::SslStream;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::net::TcpStream;
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+fn new_backend_certificate_error(message: String) -> Error {
+    Error::Common {
+        category: "backend_certificate".to_string(),
+        message,
+    }
+}
+
+async fn do_fetch_backend_certificate(
+    addr: &str,
+    sni: &str,
+) -> Result<Certificate> {
+    let stream = TcpStream::connect(addr)
+        .await
+        .map_err(|e| new_backend_certificate_error(e.to_string()))?;
+    let mut builder = SslConnector::builder(SslMethod::tls_client())
+        .map_err(|e| new_backend_certificate_error(e.to_string()))?;
+    // only the expiry of certificate is checked here,
+    // it's verified by the upstream connection if `verify_cert` is set
+    builder.set_verify(SslVerifyMode::NONE);
+    let mut config = builder
+        .build()
+        .configure()
+        .map_err(|e| new_backend_certificate_error(e.to_string()))?;
+    config.set_verify_hostname(false);
+    config.set_use_server_name_indication(!sni.is_empty());
+    let ssl = config
+        .into_ssl(sni)
+        .map_err(|e| new_backend_certificate_error(e.to_string()))?;
+    let mut stream = SslStream::new(ssl, stream)
+        .map_err(|e| new_backend_certificate_error(e.to_string()))?;
+    Pin::new(&mut stream)
+        .connect()
+        .await
+        .map_err(|e| new_backend_certificate_error(e.to_string()))?;
+    let cert = stream.ssl().peer_certificate().ok_or_else(|| {
+        new_backend_certificate_error(
+            "backend doesn't present certificate".to_string(),
+        )
+    })?;
+    let pem = cert
+        .to_pem()
+        .map_err(|e| new_backend_certificate_error(e.to_string()))?;
+    parse_certificate(&pem)
+        .map_err(|e| new_backend_certificate_error(e.to_string()))
+}
+
+/// Connects to the tls backend and gets the certificate presented by it
+pub(crate) async fn fetch_backend_certificate(
+    addr: &str,
+    sni: &str,
+    timeout: Duration,
+) -> Result<Certificate> {
+    tokio::time::timeout(timeout, do_fetch_backend_certificate(addr, sni))
+        .await
+        .map_err(|_| {
+            new_backend_certificate_error(format!(
+                "fetch certificate of {addr} timeout"
+            ))
+        })?
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use pingora::tls::pkey::PKey;
+    use pingora::tls::ssl::{Ssl, SslAcceptor};
+    use pingora::tls::x509::X509;
+    use pretty_assertions::assert_eq;
+    use tokio::net::TcpListener;
+
+    /// Starts a tls server with the certificate expired after the days,
+    /// returns the address and the expiry of certificate.
+    pub(crate) async fn start_tls_server(days: i64) -> (String, i64) {
+        let mut params =
+            rcgen::CertificateParams::new(vec!["pingap.io".to_string()])
+                .unwrap();
+        params.not_before =
+            time::OffsetDateTime::now_utc() - time::Duration::days(1);
+        params.not_after =
+            time::OffsetDateTime::now_utc() + time::Duration::days(days);
+        let key_pair = rcgen::KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key_pair).unwrap();
+        let not_after = params.not_after.unix_timestamp();
+
+        let mut acceptor =
+            SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+        acceptor
+            .set_certificate(&X509::from_pem(cert.pem().as_bytes()).unwrap())
+            .unwrap();
+        acceptor
+            .set_private_key(
+                &PKey::private_key_from_pem(
+                    key_pair.serialize_pem().as_bytes(),
+                )
+                .unwrap(),
+            )
+            .unwrap();
+        let acceptor = acceptor.build();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let Ok(ssl) = Ssl::new(acceptor.context()) else {
+                    continue;
+                };
+                let Ok(mut stream) = SslStream::new(ssl, stream) else {
+                    continue;
+                };
+                let _ = Pin::new(&mut stream).accept().await;
+            }
+        });
+        (addr, not_after)
+    }
+
+    #[tokio::test]
+    async fn test_fetch_backend_certificate() {
+        let (addr, not_after) = start_tls_server(3).await;
+        let cert = fetch_backend_certificate(
+            &addr,
+            "pingap.io",
+            Duration::from_secs(3),
+        )
+        .await
+        .unwrap();
+        assert_eq!(not_after, cert.not_after);
+        assert_eq!(vec!["pingap.io".to_string()], cert.domains);
+
+        let result = fetch_backend_certificate(
+            "127.0.0.1:1",
+            "",
+            Duration::from_secs(3),
+        )
+        .await;
+        assert_eq!(true, result.is_err());
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use ahash::AHashSet;
+
+/// The prefix of zone value read from the environment variable
+const ENV_PREFIX: &str = "$ENV:";
+
+/// BackendLocality prefers the backends in the same zone as the pingap
+/// instance. The other zones are only used when the local backends are
+/// unavailable(unhealthy, drained, tripped) or saturated.
+#[derive(Debug)]
+pub struct BackendLocality {
+    /// The backends in the same zone as the pingap instance
+    local_backends: AHashSet<String>,
+    /// The max processing requests per local backend,
+    /// the local zone is saturated if it's exceeded
+    max_processing: Option<u32>,
+}
+
+impl BackendLocality {
+    /// Creates the locality from the zone of instance and the zones of
+    /// backends(format: `addr zone`), returns none if the zone is empty
+    /// or no backend is in the same zone.
+    pub fn new(
+        zone: &str,
+        backend_zones: &[String],
+        max_processing: Option<u32>,
+    ) -> Option<Self> {
+        let zone = match zone.strip_prefix(ENV_PREFIX) {
+            Some(key) => std::env::var(key).unwrap_or_default(),
+            None => zone.to_string(),
+        };
+        if zone.is_empty() {
+            return None;
+        }
+        let local_backends: AHashSet<String> = backend_zones
+            .iter()
+            .filter_map(|item| item.split_once(' '))
+            .filter(|(_, backend_zone)| backend_zone.trim() == zone)
+            .map(|(addr, _)| addr.trim().to_string())
+            .collect();
+        if local_backends.is_empty() {
+            return None;
+        }
+        Some(Self {
+            local_backends,
+            max_processing: max_processing.filter(|value| *value > 0),
+        })
+    }
+
+    /// Returns true if the backend is in the same zone.
+    #[inline]
+    pub fn is_local(&self, address: &str) -> bool {
+        self.local_backends.contains(address)
+    }
+
+    /// Returns true if the processing requests of upstream exceed
+    /// the capacity of local backends.
+    #[inline]
+    pub fn is_saturated(&self, processing: i32) -> bool {
+        let Some(max_processing) = self.max_processing else {
+            return false;
+        };
+        let capacity = max_processing as usize * self.local_backends.len();
+        processing.max(0) as usize >= capacity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_backend_locality() {
+        let backend_zones = vec![
+            "10.0.0.1:8080 zone-a".to_string(),
+            "10.0.0.2:8080 zone-a".to_string(),
+            "10.0.1.1:8080 zone-b".to_string(),
+        ];
+        assert_eq!(
+            true,
+            BackendLocality::new("", &backend_zones, None).is_none()
+        );
+        assert_eq!(
+            true,
+            BackendLocality::new(
+                "$ENV:PINGAP_TEST_LOCALITY_ZONE",
+                &backend_zones,
+                None
+            )
+            .is_none()
+        );
+        // no backend is in the zone
+        assert_eq!(
+            true,
+            BackendLocality::new("zone-c", &backend_zones, None).is_none()
+        );
+
+        let locality =
+            BackendLocality::new("zone-a", &backend_zones, Some(10)).unwrap();
+        assert_eq!(true, locality.is_local("10.0.0.1:8080"));
+        assert_eq!(true, locality.is_local("10.0.0.2:8080"));
+        assert_eq!(false, locality.is_local("10.0.1.1:8080"));
+
+        // the capacity of two local backends is 20
+        assert_eq!(false, locality.is_saturated(0));
+        assert_eq!(false, locality.is_saturated(19));
+        assert_eq!(true, locality.is_saturated(20));
+
+        let locality =
+            BackendLocality::new("zone-b", &backend_zones, None).unwrap();
+        assert_eq!(false, locality.is_saturated(10_000));
+    }
+}
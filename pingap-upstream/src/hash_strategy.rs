@@ -26,7 +26,8 @@ pub enum HashStrategy {
     Header(String),
     Cookie(String),
     Query(String),
-    Path, // Default
+    Path,      // Default
+    PathQuery, // Path with query
 }
 
 impl HashStrategy {
@@ -66,6 +67,13 @@ impl HashStrategy {
             HashStrategy::Path => {
                 Cow::Borrowed(session.req_header().uri.path())
             },
+            HashStrategy::PathQuery => {
+                let uri = &session.req_header().uri;
+                Cow::Borrowed(
+                    uri.path_and_query()
+                        .map_or(uri.path(), |value| value.as_str()),
+                )
+            },
         }
     }
 }
@@ -78,6 +86,7 @@ impl From<(&str, &str)> for HashStrategy {
             "header" => HashStrategy::Header(tuple.1.to_string()),
             "cookie" => HashStrategy::Cookie(tuple.1.to_string()),
             "query" => HashStrategy::Query(tuple.1.to_string()),
+            "path" if tuple.1 == "query" => HashStrategy::PathQuery,
             _ => HashStrategy::Path,
         }
     }
@@ -107,6 +116,10 @@ mod tests {
                 == HashStrategy::from(("query", "id"))
         );
         assert!(HashStrategy::Path == HashStrategy::from(("", "")));
+        assert!(HashStrategy::Path == HashStrategy::from(("path", "")));
+        assert!(
+            HashStrategy::PathQuery == HashStrategy::from(("path", "query"))
+        );
     }
 
     #[tokio::test]
@@ -158,5 +171,9 @@ mod tests {
             "/vicanso/pingap",
             HashStrategy::Path.get_value(&session, &None)
         );
+        assert_eq!(
+            "/vicanso/pingap?id=1234",
+            HashStrategy::PathQuery.get_value(&session, &None)
+        );
     }
 }
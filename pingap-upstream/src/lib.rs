@@ -17,11 +17,16 @@ use snafu::Snafu;
 use std::collections::HashMap;
 use std::sync::Arc;
 
+mod backend_avoidance;
+mod backend_certificate;
 mod backend_circuit_state;
+mod backend_locality;
 mod backend_stats;
 mod hash_strategy;
 mod peer_tracer;
+mod retry_budget;
 mod upstream;
+mod upstream_proxy;
 static LOG_TARGET: &str = "pingap::upstream";
 
 #[derive(Debug, Snafu)]
@@ -84,6 +89,17 @@ pub trait UpstreamProvider: Send + Sync {
         healthy_status
     }
 
+    /// Get the health and drain status of the backends of all upstreams
+    ///
+    /// # Returns
+    /// * `HashMap<String, Vec<UpstreamBackendStatus>>` - Backends status of all upstreams
+    fn backends_status(&self) -> HashMap<String, Vec<UpstreamBackendStatus>> {
+        self.list()
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.backends_status()))
+            .collect()
+    }
+
     /// Get the stats of all upstreams
     ///
     /// # Returns
@@ -0,0 +1,216 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// The sliding window is divided into buckets, the oldest bucket is
+/// dropped as the window slides.
+const BUCKET_COUNT: u64 = 10;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryBudgetConfig {
+    /// The maximum ratio of retries to original requests in the window,
+    /// e.g. 0.2 allows one retry for every five requests.
+    pub ratio: f64,
+    /// The retries always allowed in the window, it keeps retrying
+    /// possible when the traffic is low.
+    pub min_retries: u64,
+    /// The duration of the sliding window.
+    pub window: Duration,
+}
+
+impl Default for RetryBudgetConfig {
+    fn default() -> Self {
+        Self {
+            ratio: 0.2,
+            min_retries: 10,
+            window: Duration::from_secs(10),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Bucket {
+    // the slot index of the bucket, the counts are reset when it's reused
+    slot: AtomicU64,
+    requests: AtomicU64,
+    retries: AtomicU64,
+}
+
+/// RetryBudget caps the ratio of retries to original requests over a
+/// sliding window, the retries beyond the budget are suppressed to avoid
+/// amplifying the load of an upstream during an outage.
+pub struct RetryBudget {
+    config: RetryBudgetConfig,
+    // the duration(ms) of each bucket
+    bucket_ms: u64,
+    buckets: Vec<Bucket>,
+    started_at: Instant,
+}
+
+impl RetryBudget {
+    pub fn new(config: RetryBudgetConfig) -> Self {
+        let bucket_ms =
+            (config.window.as_millis() as u64 / BUCKET_COUNT).max(1);
+        Self {
+            config,
+            bucket_ms,
+            buckets: (0..BUCKET_COUNT).map(|_| Bucket::default()).collect(),
+            started_at: Instant::now(),
+        }
+    }
+
+    #[inline]
+    fn now_ms(&self) -> u64 {
+        self.started_at.elapsed().as_millis() as u64
+    }
+
+    /// Returns the bucket of the time, it's reset if it belongs to
+    /// the previous round of window.
+    fn get_bucket(&self, now_ms: u64) -> &Bucket {
+        // the slot starts from 1, so the zero slot of new bucket is stale
+        let slot = now_ms / self.bucket_ms + 1;
+        let bucket = &self.buckets[(slot % BUCKET_COUNT) as usize];
+        let current = bucket.slot.load(Ordering::Acquire);
+        if current != slot
+            && bucket
+                .slot
+                .compare_exchange(
+                    current,
+                    slot,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                )
+                .is_ok()
+        {
+            bucket.requests.store(0, Ordering::Relaxed);
+            bucket.retries.store(0, Ordering::Relaxed);
+        }
+        bucket
+    }
+
+    /// Returns the count of requests and retries in the window.
+    fn get_counts(&self, now_ms: u64) -> (u64, u64) {
+        let slot = now_ms / self.bucket_ms + 1;
+        let oldest = slot.saturating_sub(BUCKET_COUNT - 1);
+        self.buckets
+            .iter()
+            .filter(|bucket| {
+                let value = bucket.slot.load(Ordering::Acquire);
+                value >= oldest && value <= slot
+            })
+            .fold((0, 0), |(requests, retries), bucket| {
+                (
+                    requests + bucket.requests.load(Ordering::Relaxed),
+                    retries + bucket.retries.load(Ordering::Relaxed),
+                )
+            })
+    }
+
+    fn record_request_at(&self, now_ms: u64) {
+        self.get_bucket(now_ms)
+            .requests
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn try_retry_at(&self, now_ms: u64) -> bool {
+        let (requests, retries) = self.get_counts(now_ms);
+        let allowed = ((requests as f64 * self.config.ratio) as u64)
+            .max(self.config.min_retries);
+        if retries >= allowed {
+            return false;
+        }
+        self.get_bucket(now_ms)
+            .retries
+            .fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// Records an original request(not a retry) to the upstream.
+    pub fn record_request(&self) {
+        self.record_request_at(self.now_ms());
+    }
+
+    /// Withdraws a retry from the budget, returns false if the budget
+    /// is exhausted and the retry should be suppressed.
+    pub fn try_retry(&self) -> bool {
+        self.try_retry_at(self.now_ms())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    fn new_retry_budget() -> RetryBudget {
+        RetryBudget::new(RetryBudgetConfig {
+            ratio: 0.2,
+            min_retries: 2,
+            window: Duration::from_secs(10),
+        })
+    }
+
+    #[test]
+    fn test_retry_budget_exhausted() {
+        let budget = new_retry_budget();
+        // the min retries are allowed without requests
+        assert_eq!(true, budget.try_retry_at(0));
+        assert_eq!(true, budget.try_retry_at(0));
+        assert_eq!(false, budget.try_retry_at(0));
+
+        // 20 requests allow 4 retries
+        for _ in 0..20 {
+            budget.record_request_at(500);
+        }
+        assert_eq!((20, 2), budget.get_counts(500));
+        assert_eq!(true, budget.try_retry_at(500));
+        assert_eq!(true, budget.try_retry_at(500));
+        assert_eq!(false, budget.try_retry_at(500));
+        assert_eq!(false, budget.try_retry_at(900));
+        assert_eq!((20, 4), budget.get_counts(900));
+    }
+
+    #[test]
+    fn test_retry_budget_window_slides() {
+        let budget = new_retry_budget();
+        for _ in 0..10 {
+            budget.record_request_at(0);
+        }
+        assert_eq!(true, budget.try_retry_at(0));
+        assert_eq!(true, budget.try_retry_at(0));
+        assert_eq!(false, budget.try_retry_at(5_000));
+
+        // the retries are still in the window
+        assert_eq!(false, budget.try_retry_at(9_999));
+        // the first bucket is dropped as the window slides
+        assert_eq!((0, 0), budget.get_counts(10_000));
+        assert_eq!(true, budget.try_retry_at(10_000));
+
+        // the reused bucket is reset
+        for _ in 0..50 {
+            budget.record_request_at(20_000);
+        }
+        assert_eq!((50, 0), budget.get_counts(20_000));
+        for _ in 0..10 {
+            assert_eq!(true, budget.try_retry_at(25_000));
+        }
+        assert_eq!(false, budget.try_retry_at(25_000));
+        // the requests of 20s are dropped, the retries of 25s are kept
+        assert_eq!((0, 10), budget.get_counts(30_000));
+        assert_eq!(false, budget.try_retry_at(30_000));
+        assert_eq!(true, budget.try_retry_at(35_000));
+    }
+}
@@ -21,10 +21,12 @@ use substring::Substring;
 mod crypto;
 mod format;
 mod ip;
+mod secure_cookie;
 
 pub use crypto::{aes_decrypt, aes_encrypt};
 pub use format::*;
 pub use ip::IpRules;
+pub use secure_cookie::SecureCookie;
 
 /// Error enum for various error types in the utility module
 #[derive(Debug, Snafu)]
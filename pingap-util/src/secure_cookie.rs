@@ -0,0 +1,214 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use super::{Error, aes_decrypt, aes_encrypt, base64_decode, base64_encode};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+fn now_sec() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Compares the bytes in constant time to prevent timing attacks
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// SecureCookie signs the cookie value by HMAC-SHA256, and optionally
+/// encrypts it by AES-GCM-SIV, so the value can't be tampered or read by
+/// client. The cookie value is `TIMESTAMP.DATA.SIGNATURE`.
+///
+/// The first key is used to sign new values, and all the keys are used to
+/// verify, so the old key still verifies during the rotation overlap.
+/// The expired or tampered value is treated as absent.
+#[derive(Debug, Clone)]
+pub struct SecureCookie {
+    keys: Vec<String>,
+    encrypted: bool,
+    max_age: Option<Duration>,
+}
+
+impl SecureCookie {
+    /// Creates a new secure cookie with the keys, the first one is the
+    /// current key and the others are the old keys of rotation.
+    pub fn new<T: AsRef<str>>(keys: &[T]) -> Result<Self> {
+        let keys: Vec<String> = keys
+            .iter()
+            .map(|key| key.as_ref().to_string())
+            .filter(|key| !key.is_empty())
+            .collect();
+        if keys.is_empty() {
+            return Err(Error::Invalid {
+                message: "secure cookie key can't be empty".to_string(),
+            });
+        }
+        Ok(Self {
+            keys,
+            encrypted: false,
+            max_age: None,
+        })
+    }
+    /// Encrypts the value besides signing it
+    pub fn with_encryption(mut self, encrypted: bool) -> Self {
+        self.encrypted = encrypted;
+        self
+    }
+    /// Sets the max age of value, the value exceeds it is expired
+    pub fn with_max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+    fn sign(key: &str, payload: &str) -> [u8; 32] {
+        hmac_sha256::HMAC::mac(payload.as_bytes(), key.as_bytes())
+    }
+    fn encode_with_timestamp(
+        &self,
+        value: &str,
+        timestamp: u64,
+    ) -> Result<String> {
+        let key = &self.keys[0];
+        let data = if self.encrypted {
+            aes_encrypt(key, value)?
+        } else {
+            base64_encode(value)
+        };
+        let payload = format!("{timestamp:x}.{data}");
+        let signature = base64_encode(Self::sign(key, &payload));
+        Ok(format!("{payload}.{signature}"))
+    }
+    /// Signs(and encrypts) the value by the current key
+    pub fn encode(&self, value: &str) -> Result<String> {
+        self.encode_with_timestamp(value, now_sec())
+    }
+    /// Verifies the cookie value by all the keys, returns the original value,
+    /// or none if the cookie value is expired or tampered.
+    pub fn decode(&self, cookie_value: &str) -> Option<String> {
+        let (payload, signature) = cookie_value.rsplit_once('.')?;
+        let (timestamp, data) = payload.split_once('.')?;
+        let signature = base64_decode(signature).ok()?;
+        let key = self.keys.iter().find(|key| {
+            constant_time_eq(&Self::sign(key, payload), &signature)
+        })?;
+        if let Some(max_age) = self.max_age {
+            let timestamp = u64::from_str_radix(timestamp, 16).ok()?;
+            if now_sec().saturating_sub(timestamp) > max_age.as_secs() {
+                return None;
+            }
+        }
+        if self.encrypted {
+            return aes_decrypt(key, data).ok();
+        }
+        String::from_utf8(base64_decode(data).ok()?).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_secure_cookie() {
+        let secure_cookie = SecureCookie::new(&["secret"]).unwrap();
+        let value = secure_cookie.encode("user=pingap").unwrap();
+        assert_eq!(3, value.split('.').count());
+        assert_eq!(
+            "user=pingap",
+            secure_cookie.decode(&value).unwrap_or_default()
+        );
+
+        // encrypted value can't be read by client
+        let encrypted = SecureCookie::new(&["secret"])
+            .unwrap()
+            .with_encryption(true);
+        let value = encrypted.encode("user=pingap").unwrap();
+        assert_eq!(false, value.contains(&base64_encode("user=pingap")));
+        assert_eq!("user=pingap", encrypted.decode(&value).unwrap_or_default());
+
+        assert_eq!(
+            "Invalid secure cookie key can't be empty",
+            SecureCookie::new(&[""]).err().unwrap().to_string()
+        );
+    }
+
+    #[test]
+    fn test_secure_cookie_tamper() {
+        let secure_cookie = SecureCookie::new(&["secret"]).unwrap();
+        let value = secure_cookie.encode("user=pingap").unwrap();
+        let (timestamp, rest) = value.split_once('.').unwrap();
+        let (_, signature) = rest.split_once('.').unwrap();
+
+        // the data is tampered
+        let tampered =
+            format!("{timestamp}.{}.{signature}", base64_encode("user=admin"));
+        assert_eq!(None, secure_cookie.decode(&tampered));
+        // the timestamp is tampered
+        let tampered = format!("{}.{rest}", "ffffffff");
+        assert_eq!(None, secure_cookie.decode(&tampered));
+        // signed by other key
+        let other = SecureCookie::new(&["other"]).unwrap();
+        assert_eq!(None, other.decode(&value));
+        assert_eq!(None, secure_cookie.decode("invalid"));
+
+        // the expired value is treated as absent
+        let secure_cookie = secure_cookie.with_max_age(Duration::from_secs(60));
+        let value = secure_cookie
+            .encode_with_timestamp("user=pingap", now_sec() - 120)
+            .unwrap();
+        assert_eq!(None, secure_cookie.decode(&value));
+        let value = secure_cookie.encode("user=pingap").unwrap();
+        assert_eq!(
+            "user=pingap",
+            secure_cookie.decode(&value).unwrap_or_default()
+        );
+    }
+
+    #[test]
+    fn test_secure_cookie_rotation() {
+        for encrypted in [false, true] {
+            let old = SecureCookie::new(&["old"])
+                .unwrap()
+                .with_encryption(encrypted);
+            let value = old.encode("user=pingap").unwrap();
+
+            // the old key still verifies during overlap
+            let rotated = SecureCookie::new(&["new", "old"])
+                .unwrap()
+                .with_encryption(encrypted);
+            assert_eq!(
+                "user=pingap",
+                rotated.decode(&value).unwrap_or_default()
+            );
+            // new value is signed by the new key
+            let new_value = rotated.encode("user=pingap").unwrap();
+            assert_eq!(None, old.decode(&new_value));
+            let current = SecureCookie::new(&["new"])
+                .unwrap()
+                .with_encryption(encrypted);
+            assert_eq!(
+                "user=pingap",
+                current.decode(&new_value).unwrap_or_default()
+            );
+            // the old key is removed after overlap
+            assert_eq!(None, current.decode(&value));
+        }
+    }
+}
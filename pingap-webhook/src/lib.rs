@@ -14,18 +14,112 @@
 
 use async_trait::async_trait;
 use pingap_core::{
-    Notification, NotificationData, NotificationLevel, get_hostname,
+    Notification, NotificationData, NotificationLevel, get_hostname, now_sec,
 };
 use serde_json::{Map, Value};
+use snafu::{ResultExt, Snafu};
 use std::time::Duration;
 use tracing::{error, info};
 
 pub static LOG_TARGET: &str = "pingap::webhook";
 
+/// Category of the test notification
+pub static TEST_NOTIFICATION_CATEGORY: &str = "test";
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Invalid error {message}"))]
+    Invalid { message: String },
+    #[snafu(display("Template error {source}"))]
+    Template { source: serde_json::Error },
+    #[snafu(display("Request error {source}"))]
+    Request { source: reqwest::Error },
+}
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Response of the webhook endpoint
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebhookResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+/// The payload format of the webhook
+#[derive(Debug, Clone, PartialEq)]
+pub enum WebhookType {
+    /// Wecom(企业微信) markdown message
+    Wecom,
+    /// Dingtalk markdown message
+    Dingtalk,
+    /// Payload rendered from the custom json template
+    Custom,
+    /// Plain json object with all notification fields
+    Normal,
+}
+
+impl From<&str> for WebhookType {
+    fn from(value: &str) -> Self {
+        match value.to_lowercase().as_str() {
+            "wecom" => WebhookType::Wecom,
+            "dingtalk" => WebhookType::Dingtalk,
+            "custom" => WebhookType::Custom,
+            _ => WebhookType::Normal,
+        }
+    }
+}
+
+/// Values used to render the custom webhook template
+pub struct TemplateValues<'a> {
+    pub category: &'a str,
+    pub level: &'a str,
+    pub title: &'a str,
+    pub message: &'a str,
+    pub hostname: &'a str,
+    pub ip: &'a str,
+    pub timestamp: u64,
+}
+
+/// Escapes the value so that it can be placed inside a json string.
+fn escape_json_str(value: &str) -> String {
+    let escaped = Value::String(value.to_string()).to_string();
+    // remove the surrounding quotes
+    escaped[1..escaped.len() - 1].to_string()
+}
+
+/// Renders the custom json template, the placeholders are replaced with
+/// json escaped values, so they should be placed inside json strings,
+/// e.g. `{"text": "{{message}}"}`.
+///
+/// Supported placeholders: `{{category}}`, `{{level}}`, `{{title}}`,
+/// `{{remark}}`(alias of title), `{{message}}`, `{{hostname}}`, `{{ip}}`
+/// and `{{timestamp}}`.
+///
+/// Returns an error if the rendered payload is not valid json.
+pub fn render_template(
+    template: &str,
+    values: &TemplateValues,
+) -> Result<Value, serde_json::Error> {
+    let title = escape_json_str(values.title);
+    let payload = template
+        .replace("{{category}}", &escape_json_str(values.category))
+        .replace("{{level}}", &escape_json_str(values.level))
+        .replace("{{title}}", &title)
+        .replace("{{remark}}", &title)
+        .replace("{{message}}", &escape_json_str(values.message))
+        .replace("{{hostname}}", &escape_json_str(values.hostname))
+        .replace("{{ip}}", &escape_json_str(values.ip))
+        .replace("{{timestamp}}", &values.timestamp.to_string());
+    serde_json::from_str(&payload)
+}
+
 pub struct WebhookNotificationSender {
     url: String,
     category: String,
     notifications: Vec<String>,
+    /// Json body template for custom webhook
+    template: Option<String>,
+    /// Extra http headers of the webhook request
+    headers: Vec<(String, String)>,
 }
 
 impl WebhookNotificationSender {
@@ -38,39 +132,42 @@ impl WebhookNotificationSender {
             url,
             category,
             notifications,
+            template: None,
+            headers: vec![],
         }
     }
 
-    /// Sends a notification via configured webhook
-    ///
-    /// Formats and sends the notification based on the webhook type (wecom, dingtalk, etc).
-    /// Will log success/failure and handle timeouts.
-    ///
-    /// # Arguments
-    /// * `params` - The notification parameters including category, level, message and optional remark
-    pub async fn send_notification(&self, params: NotificationData) {
+    /// Sets the json body template of the custom webhook
+    pub fn with_template(mut self, template: Option<String>) -> Self {
+        self.template = template.filter(|item| !item.is_empty());
+        self
+    }
+
+    /// Sets the extra http headers, each item is in `Name: value` format
+    pub fn with_headers(mut self, headers: Vec<String>) -> Self {
+        self.headers = headers
+            .iter()
+            .filter_map(|item| {
+                item.split_once(':').map(|(name, value)| {
+                    (name.trim().to_string(), value.trim().to_string())
+                })
+            })
+            .collect();
+        self
+    }
+
+    /// Builds the json payload of the notification based on the webhook
+    /// type (wecom, dingtalk, etc).
+    fn new_payload(
+        &self,
+        params: NotificationData,
+    ) -> Result<Map<String, Value>> {
         let title = &params.title;
-        info!(
-            target: LOG_TARGET,
-            notification = params.category,
-            title,
-            message = params.message,
-            "webhook notification"
-        );
-        let webhook_type = &self.category;
-        let url = &self.url;
-        if url.is_empty() {
-            return;
-        }
-        let found = self.notifications.contains(&params.category.to_string());
-        if !found {
-            return;
-        }
+        let webhook_type = WebhookType::from(self.category.as_str());
         let category = params.category.to_string();
         let level = params.level;
         let ip = local_ip_list().join(";");
 
-        let client = reqwest::Client::new();
         let mut data = serde_json::Map::new();
         let hostname = get_hostname();
         // TODO get app name from config
@@ -89,8 +186,8 @@ impl WebhookNotificationSender {
                 >message: {}"###,
             params.message
         );
-        match webhook_type.to_lowercase().as_str() {
-            "wecom" => {
+        match webhook_type {
+            WebhookType::Wecom => {
                 let mut markdown_data = Map::new();
                 markdown_data
                     .insert("content".to_string(), Value::String(content));
@@ -103,7 +200,7 @@ impl WebhookNotificationSender {
                     Value::Object(markdown_data),
                 );
             },
-            "dingtalk" => {
+            WebhookType::Dingtalk => {
                 let mut markdown_data = serde_json::Map::new();
                 markdown_data.insert(
                     "title".to_string(),
@@ -120,7 +217,31 @@ impl WebhookNotificationSender {
                     Value::Object(markdown_data),
                 );
             },
-            _ => {
+            WebhookType::Custom => {
+                let template = self.template.as_deref().unwrap_or_default();
+                let level = level.to_string();
+                let values = TemplateValues {
+                    category: &category,
+                    level: &level,
+                    title,
+                    message: &params.message,
+                    hostname,
+                    ip: &ip,
+                    timestamp: now_sec(),
+                };
+                match render_template(template, &values)
+                    .context(TemplateSnafu)?
+                {
+                    Value::Object(value) => data = value,
+                    _ => {
+                        return Err(Error::Invalid {
+                            message: "webhook template should be a json object"
+                                .to_string(),
+                        });
+                    },
+                }
+            },
+            WebhookType::Normal => {
                 data.insert("name".to_string(), Value::String(name));
                 data.insert(
                     "level".to_string(),
@@ -138,21 +259,77 @@ impl WebhookNotificationSender {
                 );
             },
         }
+        Ok(data)
+    }
 
-        match client
-            .post(url)
+    /// Posts the notification to the webhook url and returns the response
+    /// of the endpoint.
+    async fn post(&self, params: NotificationData) -> Result<WebhookResponse> {
+        if self.url.is_empty() {
+            return Err(Error::Invalid {
+                message: "webhook url is empty".to_string(),
+            });
+        }
+        let data = self.new_payload(params)?;
+        let client = reqwest::Client::new();
+        let mut req = client.post(&self.url);
+        for (name, value) in self.headers.iter() {
+            req = req.header(name, value);
+        }
+        let res = req
             .json(&data)
             .timeout(Duration::from_secs(30))
             .send()
             .await
-        {
+            .context(RequestSnafu)?;
+        let status = res.status().as_u16();
+        let body = res.text().await.context(RequestSnafu)?;
+        Ok(WebhookResponse { status, body })
+    }
+
+    /// Sends a synthetic notification(level info, category test) to the
+    /// webhook, it's used to confirm the formatting and connectivity,
+    /// so the configured notifications are not checked.
+    pub async fn send_test_notification(&self) -> Result<WebhookResponse> {
+        self.post(NotificationData {
+            category: TEST_NOTIFICATION_CATEGORY.to_string(),
+            level: NotificationLevel::Info,
+            title: "Test notification".to_string(),
+            message: "This is a test notification from pingap".to_string(),
+        })
+        .await
+    }
+
+    /// Sends a notification via configured webhook
+    ///
+    /// Formats and sends the notification based on the webhook type (wecom, dingtalk, etc).
+    /// Will log success/failure and handle timeouts.
+    ///
+    /// # Arguments
+    /// * `params` - The notification parameters including category, level, message and optional remark
+    pub async fn send_notification(&self, params: NotificationData) {
+        info!(
+            target: LOG_TARGET,
+            notification = params.category,
+            title = params.title,
+            message = params.message,
+            "webhook notification"
+        );
+        if self.url.is_empty() {
+            return;
+        }
+        let found = self.notifications.contains(&params.category.to_string());
+        if !found {
+            return;
+        }
+        match self.post(params).await {
             Ok(res) => {
-                if res.status().as_u16() < 400 {
+                if res.status < 400 {
                     info!(target: LOG_TARGET, "send webhook success");
                 } else {
                     error!(
                         target: LOG_TARGET,
-                        status = res.status().to_string(),
+                        status = res.status,
                         "send webhook fail"
                     );
                 }
@@ -195,3 +372,155 @@ fn local_ip_list() -> Vec<String> {
         .map(|item| item.to_string())
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn test_webhook_type() {
+        assert_eq!(WebhookType::Wecom, WebhookType::from("wecom"));
+        assert_eq!(WebhookType::Dingtalk, WebhookType::from("DingTalk"));
+        assert_eq!(WebhookType::Custom, WebhookType::from("custom"));
+        assert_eq!(WebhookType::Normal, WebhookType::from(""));
+    }
+
+    #[test]
+    fn test_render_template() {
+        let values = TemplateValues {
+            category: "backend_unhealthy",
+            level: "error",
+            title: "Upstream \"charts\" is unhealthy",
+            message: "line1\nline2\t\\",
+            hostname: "pingap",
+            ip: "192.168.1.1",
+            timestamp: 1700000000,
+        };
+        let value = render_template(
+            r#"{"text": "[{{level}}] {{category}}: {{message}}", "remark": "{{remark}}", "ts": {{timestamp}}, "host": "{{hostname}}({{ip}})"}"#,
+            &values,
+        )
+        .unwrap();
+        assert_eq!(
+            "[error] backend_unhealthy: line1\nline2\t\\",
+            value["text"].as_str().unwrap()
+        );
+        assert_eq!(
+            r#"Upstream "charts" is unhealthy"#,
+            value["remark"].as_str().unwrap()
+        );
+        assert_eq!(1700000000, value["ts"].as_u64().unwrap());
+        assert_eq!("pingap(192.168.1.1)", value["host"].as_str().unwrap());
+
+        let result = render_template(r#"{"text": {{message}}}"#, &values);
+        assert_eq!(true, result.is_err());
+    }
+
+    #[test]
+    fn test_with_headers() {
+        let sender = WebhookNotificationSender::new(
+            "http://127.0.0.1/webhook".to_string(),
+            "custom".to_string(),
+            vec![],
+        )
+        .with_template(Some(r#"{"text": "{{message}}"}"#.to_string()))
+        .with_headers(vec![
+            "Authorization: Bearer token".to_string(),
+            "invalid".to_string(),
+        ]);
+        assert_eq!(true, sender.template.is_some());
+        assert_eq!(
+            vec![("Authorization".to_string(), "Bearer token".to_string())],
+            sender.headers
+        );
+    }
+
+    /// Starts a http server which responds the status and the received
+    /// request body, the request is returned from the join handle.
+    async fn start_webhook_server(
+        status: &'static str,
+    ) -> (String, tokio::task::JoinHandle<String>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener =
+            tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/webhook", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![];
+            let mut data = [0; 4096];
+            // read until the whole json body is received
+            while !buf.ends_with(b"}") {
+                let size = stream.read(&mut data).await.unwrap();
+                if size == 0 {
+                    break;
+                }
+                buf.extend_from_slice(&data[..size]);
+            }
+            let body = r#"{"errcode":0}"#;
+            let resp = format!(
+                "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            stream.write_all(resp.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&buf).to_string()
+        });
+        (url, handle)
+    }
+
+    #[tokio::test]
+    async fn test_send_test_notification() {
+        let (url, handle) = start_webhook_server("200 OK").await;
+        // test notification is sent even if it's not in the notifications
+        let sender =
+            WebhookNotificationSender::new(url, "".to_string(), vec![])
+                .with_headers(vec!["X-Token: pingap".to_string()]);
+        let resp = sender.send_test_notification().await.unwrap();
+        assert_eq!(
+            WebhookResponse {
+                status: 200,
+                body: r#"{"errcode":0}"#.to_string(),
+            },
+            resp
+        );
+        let req = handle.await.unwrap();
+        assert_eq!(true, req.starts_with("POST /webhook HTTP/1.1"));
+        assert_eq!(true, req.to_lowercase().contains("x-token: pingap"));
+        assert_eq!(true, req.contains(r#""category":"test""#));
+        assert_eq!(true, req.contains(r#""level":"info""#));
+
+        // the failure status of the endpoint is surfaced
+        let (url, handle) =
+            start_webhook_server("500 Internal Server Error").await;
+        let sender =
+            WebhookNotificationSender::new(url, "custom".to_string(), vec![])
+                .with_template(Some(
+                    r#"{"text": "[{{level}}] {{category}}: {{message}}"}"#
+                        .to_string(),
+                ));
+        let resp = sender.send_test_notification().await.unwrap();
+        assert_eq!(500, resp.status);
+        let req = handle.await.unwrap();
+        assert_eq!(
+            true,
+            req.contains(
+                r#"{"text":"[info] test: This is a test notification from pingap"}"#
+            )
+        );
+
+        // empty url
+        let sender = WebhookNotificationSender::new(
+            "".to_string(),
+            "".to_string(),
+            vec![],
+        );
+        assert_eq!(
+            "Invalid error webhook url is empty",
+            sender
+                .send_test_notification()
+                .await
+                .unwrap_err()
+                .to_string()
+        );
+    }
+}
@@ -12,16 +12,33 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::process::restart;
+use crate::webhook::send_notification;
 use ahash::AHashMap;
 use arc_swap::ArcSwap;
+use async_trait::async_trait;
+use pingap_acme::AcmeStorage;
 use pingap_certificate::{
-    CertificateProvider, DEFAULT_SERVER_NAME, DynamicCertificates,
+    CertificateProvider, DynamicCertificates, find_certificate,
     parse_certificates,
 };
-use pingap_config::CertificateConf;
-use std::collections::HashMap;
+use pingap_config::{CertificateConf, ConfigManager, LocationConf};
+use pingap_core::{
+    BackgroundTask, Error as ServiceError, NotificationData, NotificationLevel,
+};
+use pingap_util::{is_pem, resolve_path};
+use pingora::server::ShutdownWatch;
+use pingora::services::background::BackgroundService;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::LazyLock;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+static LOG_TARGET: &str = "main::certificates";
 
 struct Provider {
     certificates: ArcSwap<DynamicCertificates>,
@@ -35,19 +52,7 @@ impl CertificateProvider for Provider {
         &self,
         sni: &str,
     ) -> Option<Arc<pingap_certificate::TlsCertificate>> {
-        let certs = self.certificates.load();
-        certs
-            .get(sni)
-            .or_else(|| {
-                // If exact match fails, try wildcard match without new string allocation.
-                sni.split_once('.')
-                    .and_then(|(_, domain)| certs.get(&format!("*.{}", domain)))
-            })
-            .or_else(|| {
-                // Fallback to the default certificate.
-                certs.get(DEFAULT_SERVER_NAME)
-            })
-            .cloned()
+        find_certificate(&self.certificates.load(), sni)
     }
     fn list(&self) -> Arc<DynamicCertificates> {
         self.certificates.load().clone()
@@ -77,9 +82,17 @@ pub fn new_certificate_provider() -> Arc<dyn CertificateProvider> {
 /// Supports multiple domains per certificate and wildcard certificates.
 pub fn try_update_certificates(
     certificate_configs: &HashMap<String, CertificateConf>,
+) -> (Vec<String>, String) {
+    update_certificates(CERTIFICATE_PROVIDER.as_ref(), certificate_configs)
+}
+
+/// Updates the certificate store of provider, see [`try_update_certificates`]
+fn update_certificates(
+    provider: &dyn CertificateProvider,
+    certificate_configs: &HashMap<String, CertificateConf>,
 ) -> (Vec<String>, String) {
     let (new_certs, errors) = parse_certificates(certificate_configs);
-    let old_certs = CERTIFICATE_PROVIDER.list();
+    let old_certs = provider.list();
     let updated_certificates: Vec<String> = new_certs
         .iter()
         .filter(|(name, cert)| {
@@ -95,6 +108,762 @@ pub fn try_update_certificates(
         .map(|(name, msg)| format!("{}({})", msg, name))
         .collect();
 
-    CERTIFICATE_PROVIDER.store(new_certs);
+    provider.store(new_certs);
     (updated_certificates, error_messages.join(";"))
 }
+
+/// Merges the certificates issued to the dedicated acme storage into the
+/// certificates of config, nothing is changed if it's not set.
+pub async fn merge_acme_certificates(
+    config_manager: &Arc<ConfigManager>,
+    certificates: &mut HashMap<String, CertificateConf>,
+) -> Result<(), pingap_config::Error> {
+    AcmeStorage::resolve(config_manager.clone())
+        .merge_certificates(certificates)
+        .await
+        .map_err(|e| pingap_config::Error::Invalid {
+            message: e.to_string(),
+        })
+}
+
+/// Reloads only the certificates section from the config storage and
+/// hot-swaps the certificate store, the other sections of the current
+/// config (upstreams, locations, ...) are untouched.
+///
+/// # Returns
+/// * `Vec<String>` - List of domain names whose certificates were updated
+/// * `String` - Semicolon-separated list of parsing errors
+pub async fn reload_certificates(
+    config_manager: &Arc<ConfigManager>,
+) -> Result<(Vec<String>, String), pingap_config::Error> {
+    let mut certificates = config_manager
+        .load_all()
+        .await?
+        .to_pingap_config(true)?
+        .certificates;
+    merge_acme_certificates(config_manager, &mut certificates).await?;
+    let result = try_update_certificates(&certificates);
+
+    let mut current_config =
+        config_manager.get_current_config().as_ref().clone();
+    current_config.certificates = certificates;
+    config_manager.set_current_config(current_config);
+    Ok(result)
+}
+
+/// CertificateReloadService reloads the certificates when the process
+/// receives the SIGUSR1 signal, e.g. `kill -USR1 <pid>` after an external
+/// tool updates the certificates in storage.
+pub struct CertificateReloadService {
+    config_manager: Arc<ConfigManager>,
+}
+
+pub fn new_certificate_reload_service(
+    config_manager: Arc<ConfigManager>,
+) -> CertificateReloadService {
+    CertificateReloadService { config_manager }
+}
+
+#[async_trait]
+impl BackgroundService for CertificateReloadService {
+    #[cfg(unix)]
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        use tokio::signal::unix::{SignalKind, signal};
+        let mut reload_signal = match signal(SignalKind::user_defined1()) {
+            Ok(reload_signal) => reload_signal,
+            Err(e) => {
+                error!(
+                    target: LOG_TARGET,
+                    error = %e,
+                    "listen certificate reload signal fail"
+                );
+                return;
+            },
+        };
+        loop {
+            tokio::select! {
+                _ = shutdown.changed() => {
+                    break;
+                }
+                _ = reload_signal.recv() => {
+                    match reload_certificates(&self.config_manager).await {
+                        Ok((updated_certificates, errors)) => {
+                            info!(
+                                target: LOG_TARGET,
+                                updated = updated_certificates.join(","),
+                                "reload certificates success"
+                            );
+                            if !errors.is_empty() {
+                                error!(
+                                    target: LOG_TARGET,
+                                    error = errors,
+                                    "parse certificate fail"
+                                );
+                                send_notification(NotificationData {
+                                    category: "parse_certificate_fail".to_string(),
+                                    level: NotificationLevel::Error,
+                                    message: errors,
+                                    ..Default::default()
+                                })
+                                .await;
+                            }
+                        },
+                        Err(e) => {
+                            error!(
+                                target: LOG_TARGET,
+                                error = %e,
+                                "reload certificates fail"
+                            );
+                        },
+                    }
+                }
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    async fn start(&self, _shutdown: ShutdownWatch) {}
+}
+
+/// The modified time and size of file, the file is changed if any of them
+/// is changed.
+#[derive(Debug, Clone, PartialEq)]
+struct FileSnapshot {
+    modified: Option<SystemTime>,
+    len: u64,
+}
+
+fn get_file_snapshot(file: &Path) -> Option<FileSnapshot> {
+    let metadata = std::fs::metadata(file).ok()?;
+    Some(FileSnapshot {
+        modified: metadata.modified().ok(),
+        len: metadata.len(),
+    })
+}
+
+/// Returns the cert and key files of each certificate, the inline pem
+/// values are ignored.
+fn get_certificate_files(
+    certificates: &HashMap<String, CertificateConf>,
+) -> HashMap<PathBuf, Vec<String>> {
+    let mut files: HashMap<PathBuf, Vec<String>> = HashMap::new();
+    for (name, conf) in certificates.iter() {
+        for value in [
+            &conf.tls_cert,
+            &conf.tls_key,
+            &conf.tls_alt_cert,
+            &conf.tls_alt_key,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if value.is_empty() || is_pem(value) {
+                continue;
+            }
+            files
+                .entry(PathBuf::from(resolve_path(value)))
+                .or_default()
+                .push(name.clone());
+        }
+    }
+    files
+}
+
+/// CertificateFileWatcher detects the changes of certificate files,
+/// the changes are reported after the files are unchanged for the
+/// debounce, so the half written files are not read.
+struct CertificateFileWatcher {
+    debounce: Duration,
+    snapshots: HashMap<PathBuf, Option<FileSnapshot>>,
+    changed_files: HashSet<PathBuf>,
+    changed_at: Option<Instant>,
+}
+
+impl CertificateFileWatcher {
+    fn new(debounce: Duration) -> Self {
+        Self {
+            debounce,
+            snapshots: HashMap::new(),
+            changed_files: HashSet::new(),
+            changed_at: None,
+        }
+    }
+
+    /// Checks the files and returns the changed files if they are stable
+    /// for the debounce, the first check only records the snapshots.
+    fn check(
+        &mut self,
+        files: &[PathBuf],
+        now: Instant,
+    ) -> Option<Vec<PathBuf>> {
+        self.snapshots.retain(|file, _| files.contains(file));
+        for file in files {
+            let snapshot = get_file_snapshot(file);
+            match self.snapshots.get(file) {
+                Some(previous) if *previous == snapshot => {},
+                Some(_) => {
+                    self.snapshots.insert(file.clone(), snapshot);
+                    self.changed_files.insert(file.clone());
+                    self.changed_at = Some(now);
+                },
+                None => {
+                    self.snapshots.insert(file.clone(), snapshot);
+                },
+            }
+        }
+        let changed_at = self.changed_at?;
+        if now.saturating_duration_since(changed_at) < self.debounce {
+            return None;
+        }
+        Some(self.changed_files.iter().cloned().collect())
+    }
+
+    /// Keeps the changes pending and retries after the debounce.
+    fn retry(&mut self, now: Instant) {
+        self.changed_at = Some(now);
+    }
+
+    /// Clears the changes after the certificates are reloaded.
+    fn done(&mut self) {
+        self.changed_files.clear();
+        self.changed_at = None;
+    }
+}
+
+/// CertificateWatchTask hot reloads the certificates whose cert or key
+/// files are changed on disk, e.g. renewed by an external tool.
+struct CertificateWatchTask {
+    config_manager: Arc<ConfigManager>,
+    provider: Arc<dyn CertificateProvider>,
+    watcher: Mutex<CertificateFileWatcher>,
+}
+
+impl CertificateWatchTask {
+    /// Returns the reloaded certificates, it's empty if nothing is changed
+    /// or the changed files are not ready.
+    async fn check_at(&self, now: Instant) -> Vec<String> {
+        let certificates = self
+            .config_manager
+            .get_current_config()
+            .certificates
+            .clone();
+        let files = get_certificate_files(&certificates);
+        let mut watcher = self.watcher.lock().await;
+        let Some(changed_files) =
+            watcher.check(&files.keys().cloned().collect::<Vec<_>>(), now)
+        else {
+            return vec![];
+        };
+        let mut names = changed_files
+            .iter()
+            .flat_map(|file| files.get(file).cloned().unwrap_or_default())
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        let changed_certificates = certificates
+            .iter()
+            .filter(|(name, _)| names.contains(name))
+            .map(|(name, conf)| (name.clone(), conf.clone()))
+            .collect::<HashMap<_, _>>();
+        // the files may be half written, retry after the debounce
+        let (_, errors) = parse_certificates(&changed_certificates);
+        if !errors.is_empty() {
+            warn!(
+                target: LOG_TARGET,
+                certificates = names.join(","),
+                error = errors
+                    .iter()
+                    .map(|(name, msg)| format!("{msg}({name})"))
+                    .collect::<Vec<_>>()
+                    .join(";"),
+                "certificate files are not ready, retry later"
+            );
+            watcher.retry(now);
+            return vec![];
+        }
+        watcher.done();
+        let (_, errors) =
+            update_certificates(self.provider.as_ref(), &certificates);
+        if !errors.is_empty() {
+            error!(target: LOG_TARGET, error = errors, "parse certificate fail");
+        }
+        names
+    }
+}
+
+#[async_trait]
+impl BackgroundTask for CertificateWatchTask {
+    async fn execute(&self, _count: u32) -> Result<bool, ServiceError> {
+        let reloaded = self.check_at(Instant::now()).await;
+        if reloaded.is_empty() {
+            return Ok(false);
+        }
+        info!(
+            target: LOG_TARGET,
+            certificates = reloaded.join(","),
+            "certificate files are changed, reload certificates success"
+        );
+        Ok(true)
+    }
+}
+
+/// Create a task to watch the cert and key files of certificates,
+/// the changed certificates are reloaded after the debounce.
+pub fn new_certificate_watch_service(
+    config_manager: Arc<ConfigManager>,
+    debounce: Duration,
+) -> Box<dyn BackgroundTask> {
+    Box::new(CertificateWatchTask {
+        config_manager,
+        provider: new_certificate_provider(),
+        watcher: Mutex::new(CertificateFileWatcher::new(debounce)),
+    })
+}
+
+/// Returns the domains of acme certificates which are not yet issued,
+/// the tls servers of them fall back before the certificates are issued.
+pub fn get_acme_pending_domains(
+    certificates: &HashMap<String, CertificateConf>,
+) -> Vec<String> {
+    let mut domains = certificates
+        .values()
+        .filter(|conf| {
+            conf.acme.as_ref().is_some_and(|acme| !acme.is_empty())
+                && conf.tls_cert.as_ref().is_none_or(|cert| cert.is_empty())
+        })
+        .flat_map(|conf| {
+            conf.domains
+                .clone()
+                .unwrap_or_default()
+                .split(',')
+                .map(|domain| domain.trim().to_lowercase())
+                .filter(|domain| !domain.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    domains.sort();
+    domains.dedup();
+    domains
+}
+
+/// Returns whether the server may serve any of the pending acme domains.
+/// The location without host or with regexp host may serve any domain,
+/// so the server of it is also regarded as pending.
+pub fn is_acme_pending_server(
+    server_locations: &[String],
+    locations: &HashMap<String, LocationConf>,
+    pending_domains: &[String],
+) -> bool {
+    if pending_domains.is_empty() {
+        return false;
+    }
+    server_locations
+        .iter()
+        .filter_map(|name| locations.get(name))
+        .any(|location| {
+            let hosts = location
+                .host
+                .as_deref()
+                .unwrap_or_default()
+                .split(',')
+                .map(|host| host.trim().to_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect::<Vec<_>>();
+            if hosts.is_empty() {
+                return true;
+            }
+            hosts.iter().any(|host| {
+                if host.starts_with('~') {
+                    return true;
+                }
+                pending_domains.iter().any(|domain| {
+                    if let Some(suffix) = domain.strip_prefix('*') {
+                        host.ends_with(suffix) || host == domain
+                    } else {
+                        host == domain
+                    }
+                })
+            })
+        })
+}
+
+/// AcmeFallbackTask restarts pingap once any of the pending acme
+/// certificates is issued, so the tls servers which fall back to http
+/// are listened as tls again.
+struct AcmeFallbackTask {
+    provider: Arc<dyn CertificateProvider>,
+    pending_domains: Vec<String>,
+    restarted: AtomicBool,
+}
+
+impl AcmeFallbackTask {
+    /// Returns the pending domains whose certificates are issued,
+    /// the default certificate doesn't count.
+    fn get_issued_domains(&self) -> Vec<String> {
+        let certificates = self.provider.list();
+        self.pending_domains
+            .iter()
+            .filter(|domain| certificates.contains_key(domain.as_str()))
+            .cloned()
+            .collect()
+    }
+}
+
+#[async_trait]
+impl BackgroundTask for AcmeFallbackTask {
+    async fn execute(&self, _count: u32) -> Result<bool, ServiceError> {
+        if self.restarted.load(Ordering::Relaxed) {
+            return Ok(false);
+        }
+        let issued_domains = self.get_issued_domains();
+        if issued_domains.is_empty() {
+            return Ok(false);
+        }
+        self.restarted.store(true, Ordering::Relaxed);
+        info!(
+            target: LOG_TARGET,
+            domains = issued_domains.join(","),
+            "acme certificate is issued, restart to listen as tls"
+        );
+        tokio::spawn(restart());
+        Ok(true)
+    }
+}
+
+/// Create a task to switch the fallback tls servers back to tls once
+/// the pending acme certificates are issued.
+pub fn new_acme_fallback_service(
+    provider: Arc<dyn CertificateProvider>,
+    pending_domains: Vec<String>,
+) -> Box<dyn BackgroundTask> {
+    Box::new(AcmeFallbackTask {
+        provider,
+        pending_domains,
+        restarted: AtomicBool::new(false),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pingap_certificate::rcgen;
+    use pingap_config::{
+        Category, PingapConfig, UpstreamConf, new_file_config_manager,
+    };
+    use pretty_assertions::{assert_eq, assert_ne};
+
+    #[tokio::test]
+    async fn test_reload_certificates() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_manager = Arc::new(
+            new_file_config_manager(dir.path().to_str().unwrap()).unwrap(),
+        );
+        let certified_key =
+            rcgen::generate_simple_self_signed(vec!["pingap.io".to_string()])
+                .unwrap();
+        config_manager
+            .update(
+                Category::Certificate,
+                "pingap",
+                &CertificateConf {
+                    tls_cert: Some(certified_key.cert.pem()),
+                    tls_key: Some(certified_key.key_pair.serialize_pem()),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+        // the stored upstream is changed, but not applied
+        config_manager
+            .update(
+                Category::Upstream,
+                "charts",
+                &UpstreamConf {
+                    addrs: vec!["127.0.0.1:5001".to_string()],
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+
+        let mut current_config = PingapConfig::default();
+        current_config.upstreams.insert(
+            "charts".to_string(),
+            UpstreamConf {
+                addrs: vec!["127.0.0.1:5000".to_string()],
+                ..Default::default()
+            },
+        );
+        config_manager.set_current_config(current_config);
+
+        let (updated_certificates, errors) =
+            reload_certificates(&config_manager).await.unwrap();
+        assert_eq!(vec!["pingap.io".to_string()], updated_certificates);
+        assert_eq!("", errors);
+        assert_eq!(
+            Some("pingap".to_string()),
+            new_certificate_provider()
+                .get("pingap.io")
+                .and_then(|cert| cert.name.clone())
+        );
+
+        let current_config = config_manager.get_current_config();
+        assert_eq!(true, current_config.certificates.contains_key("pingap"));
+        assert_eq!(
+            vec!["127.0.0.1:5000".to_string()],
+            current_config.upstreams["charts"].addrs
+        );
+    }
+
+    #[test]
+    fn test_acme_pending_server() {
+        let mut certificates = HashMap::new();
+        certificates.insert(
+            "pending".to_string(),
+            CertificateConf {
+                domains: Some("pingap.io, *.pingap.io".to_string()),
+                acme: Some("lets_encrypt".to_string()),
+                ..Default::default()
+            },
+        );
+        certificates.insert(
+            "issued".to_string(),
+            CertificateConf {
+                domains: Some("issued.com".to_string()),
+                acme: Some("lets_encrypt".to_string()),
+                tls_cert: Some("cert".to_string()),
+                ..Default::default()
+            },
+        );
+        let pending_domains = get_acme_pending_domains(&certificates);
+        assert_eq!(
+            vec!["*.pingap.io".to_string(), "pingap.io".to_string()],
+            pending_domains
+        );
+
+        let mut locations = HashMap::new();
+        for (name, host) in [
+            ("pingap", Some("pingap.io")),
+            ("sub", Some("api.pingap.io")),
+            ("issued", Some("issued.com")),
+            ("regexp", Some("~(?<name>.+).com")),
+            ("all", None),
+        ] {
+            locations.insert(
+                name.to_string(),
+                LocationConf {
+                    host: host.map(|host| host.to_string()),
+                    ..Default::default()
+                },
+            );
+        }
+        for (server_locations, pending) in [
+            (vec!["pingap"], true),
+            (vec!["sub"], true),
+            (vec!["issued"], false),
+            (vec!["issued", "pingap"], true),
+            (vec!["regexp"], true),
+            (vec!["all"], true),
+        ] {
+            let server_locations = server_locations
+                .into_iter()
+                .map(|name| name.to_string())
+                .collect::<Vec<_>>();
+            assert_eq!(
+                pending,
+                is_acme_pending_server(
+                    &server_locations,
+                    &locations,
+                    &pending_domains
+                )
+            );
+        }
+        assert_eq!(
+            false,
+            is_acme_pending_server(&["all".to_string()], &locations, &[])
+        );
+    }
+
+    #[test]
+    fn test_acme_fallback_issued_domains() {
+        // the provider of test, the global one is updated by other tests
+        let provider = Arc::new(Provider {
+            certificates: ArcSwap::from_pointee(AHashMap::new()),
+        });
+        let task = AcmeFallbackTask {
+            provider: provider.clone(),
+            pending_domains: vec!["acme.pingap.io".to_string()],
+            restarted: AtomicBool::new(false),
+        };
+        let certified_key = rcgen::generate_simple_self_signed(vec![
+            "default.pingap.io".to_string(),
+        ])
+        .unwrap();
+        let mut certificates = HashMap::new();
+        certificates.insert(
+            "default".to_string(),
+            CertificateConf {
+                tls_cert: Some(certified_key.cert.pem()),
+                tls_key: Some(certified_key.key_pair.serialize_pem()),
+                is_default: Some(true),
+                ..Default::default()
+            },
+        );
+        update_certificates(provider.as_ref(), &certificates);
+        // the default certificate isn't the issued one
+        assert_eq!(true, provider.get("acme.pingap.io").is_some());
+        assert_eq!(true, task.get_issued_domains().is_empty());
+
+        let certified_key = rcgen::generate_simple_self_signed(vec![
+            "acme.pingap.io".to_string(),
+        ])
+        .unwrap();
+        certificates.insert(
+            "acme".to_string(),
+            CertificateConf {
+                tls_cert: Some(certified_key.cert.pem()),
+                tls_key: Some(certified_key.key_pair.serialize_pem()),
+                ..Default::default()
+            },
+        );
+        update_certificates(provider.as_ref(), &certificates);
+        assert_eq!(
+            vec!["acme.pingap.io".to_string()],
+            task.get_issued_domains()
+        );
+    }
+
+    #[test]
+    fn test_certificate_file_watcher() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("pingap.crt");
+        std::fs::write(&file, "cert").unwrap();
+        let files = vec![file.clone()];
+        let debounce = Duration::from_secs(2);
+        let mut watcher = CertificateFileWatcher::new(debounce);
+        let now = Instant::now();
+
+        // the first check only records the snapshots
+        assert_eq!(true, watcher.check(&files, now).is_none());
+        assert_eq!(true, watcher.check(&files, now + debounce).is_none());
+
+        // the changed file is reported after the debounce
+        std::fs::write(&file, "new cert").unwrap();
+        assert_eq!(true, watcher.check(&files, now).is_none());
+        let now = now + Duration::from_secs(1);
+        // it's written again, the debounce restarts
+        std::fs::write(&file, "new cert again").unwrap();
+        assert_eq!(true, watcher.check(&files, now).is_none());
+        assert_eq!(
+            true,
+            watcher
+                .check(&files, now + Duration::from_millis(1999))
+                .is_none()
+        );
+        assert_eq!(
+            Some(vec![file.clone()]),
+            watcher.check(&files, now + debounce)
+        );
+
+        // retry after the debounce
+        watcher.retry(now + debounce);
+        assert_eq!(
+            true,
+            watcher
+                .check(&files, now + Duration::from_secs(3))
+                .is_none()
+        );
+        assert_eq!(
+            Some(vec![file.clone()]),
+            watcher.check(&files, now + Duration::from_secs(4))
+        );
+
+        watcher.done();
+        assert_eq!(
+            true,
+            watcher
+                .check(&files, now + Duration::from_secs(10))
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_certificate_watch() {
+        let dir = tempfile::tempdir().unwrap();
+        let cert_file = dir.path().join("watch.crt");
+        let key_file = dir.path().join("watch.key");
+        let write_certificate = || {
+            let certified_key = rcgen::generate_simple_self_signed(vec![
+                "watch.pingap.io".to_string(),
+            ])
+            .unwrap();
+            std::fs::write(&cert_file, certified_key.cert.pem()).unwrap();
+            std::fs::write(&key_file, certified_key.key_pair.serialize_pem())
+                .unwrap();
+        };
+        write_certificate();
+
+        let config_manager = Arc::new(
+            new_file_config_manager(dir.path().to_str().unwrap()).unwrap(),
+        );
+        let mut current_config = PingapConfig::default();
+        current_config.certificates.insert(
+            "watch".to_string(),
+            CertificateConf {
+                tls_cert: Some(cert_file.to_string_lossy().to_string()),
+                tls_key: Some(key_file.to_string_lossy().to_string()),
+                ..Default::default()
+            },
+        );
+        config_manager.set_current_config(current_config);
+
+        // the provider of test, the global one is updated by other tests
+        let provider = Arc::new(Provider {
+            certificates: ArcSwap::from_pointee(AHashMap::new()),
+        });
+        let debounce = Duration::from_secs(2);
+        let task = CertificateWatchTask {
+            config_manager,
+            provider: provider.clone(),
+            watcher: Mutex::new(CertificateFileWatcher::new(debounce)),
+        };
+        let now = Instant::now();
+        assert_eq!(true, task.check_at(now).await.is_empty());
+        update_certificates(
+            provider.as_ref(),
+            &task.config_manager.get_current_config().certificates,
+        );
+        let cert = provider.get("watch.pingap.io").unwrap();
+        let (x509, _) = cert.certificate.as_ref().unwrap();
+        let previous_der = x509.to_der().unwrap();
+
+        // the half written cert file is retried
+        let pem = std::fs::read_to_string(&cert_file).unwrap();
+        std::fs::write(&cert_file, &pem[..pem.len() / 2]).unwrap();
+        assert_eq!(true, task.check_at(now).await.is_empty());
+        assert_eq!(true, task.check_at(now + debounce).await.is_empty());
+        assert_eq!(
+            true,
+            task.check_at(now + Duration::from_secs(3)).await.is_empty()
+        );
+
+        // the cert files are written completely
+        write_certificate();
+        let now = now + Duration::from_secs(3);
+        assert_eq!(true, task.check_at(now).await.is_empty());
+        assert_eq!(
+            vec!["watch".to_string()],
+            task.check_at(now + debounce).await
+        );
+        let cert = provider.get("watch.pingap.io").unwrap();
+        let (x509, _) = cert.certificate.as_ref().unwrap();
+        assert_ne!(previous_der, x509.to_der().unwrap());
+
+        // nothing is changed
+        assert_eq!(
+            true,
+            task.check_at(now + Duration::from_secs(10))
+                .await
+                .is_empty()
+        );
+    }
+}
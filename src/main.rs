@@ -12,7 +12,9 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::certificates::{new_certificate_provider, try_update_certificates};
+use crate::certificates::{
+    merge_acme_certificates, new_certificate_provider, try_update_certificates,
+};
 use crate::config_manager::{get_config_manager, try_init_config_manager};
 use crate::locations::new_location_provider;
 use crate::locations::try_init_locations;
@@ -24,13 +26,13 @@ use crate::upstreams::try_init_upstreams;
 use bytes::BytesMut;
 use clap::Parser;
 use crossbeam_channel::Receiver;
-use pingap_acme::new_lets_encrypt_service;
+use pingap_acme::{new_lets_encrypt_renewal_service, new_lets_encrypt_service};
 use pingap_cache::new_storage_clear_service;
 use pingap_certificate::{
     new_certificate_validity_service,
     new_self_signed_certificate_validity_service,
 };
-use pingap_config::PingapConfig;
+use pingap_config::{AccessLogLevel, PingapConfig};
 use pingap_config::{ConfigManager, ETCD_PROTOCOL};
 use pingap_core::BackgroundTaskService;
 #[cfg(feature = "imageoptim")]
@@ -144,6 +146,14 @@ struct Args {
     /// Default threads for each server
     #[arg(long)]
     threads: Option<usize>,
+    /// Dedicated storage of acme state(account, challenge tokens and
+    /// issued certificates), the config storage is used if not set
+    #[arg(long)]
+    acme_storage: Option<String>,
+    /// Cache ttl of acme challenge answers and unknown tokens,
+    /// e.g. `60s,5s`(default), `0s,0s` disables the cache
+    #[arg(long)]
+    acme_challenge_cache: Option<String>,
 }
 
 fn new_server_config(
@@ -200,7 +210,15 @@ fn get_config(
                     match config_manager.load_all().await {
                         Ok(config) => {
                             // TODO 原有的load config有admin模式
-                            let result = config.to_pingap_config(true);
+                            let result = match config.to_pingap_config(true) {
+                                Ok(mut conf) => merge_acme_certificates(
+                                    &config_manager,
+                                    &mut conf.certificates,
+                                )
+                                .await
+                                .map(|_| conf),
+                                Err(e) => Err(e),
+                            };
                             if let Err(e) = s.send(result) {
                                 println!("sender fail, {e}");
                             }
@@ -436,6 +454,12 @@ fn run() -> Result<(), Box<dyn Error>> {
 
     // Initialize configuration
     let config_manager = try_init_config_manager(&args.conf)?;
+    if let Some(acme_storage) = &args.acme_storage {
+        pingap_acme::try_init_acme_storage(acme_storage)?;
+    }
+    if let Some(challenge_cache) = &args.acme_challenge_cache {
+        pingap_acme::try_init_challenge_cache(challenge_cache)?;
+    }
 
     let r = get_config(get_config_manager()?);
     let config = match r.recv() {
@@ -489,16 +513,7 @@ fn run() -> Result<(), Box<dyn Error>> {
 
     let basic_conf = &config.basic;
 
-    let webhook_url = basic_conf.webhook.clone().unwrap_or_default();
-    webhook::init_webhook_notification_sender(
-        webhook_url,
-        config.basic.webhook_type.clone().unwrap_or_default(),
-        config
-            .basic
-            .webhook_notifications
-            .clone()
-            .unwrap_or_default(),
-    );
+    webhook::init_webhook_notification_sender(basic_conf);
 
     // return if test mode
     if args.test {
@@ -509,6 +524,9 @@ fn run() -> Result<(), Box<dyn Error>> {
     let auto_restart_check_interval = basic_conf
         .auto_restart_check_interval
         .map_or(Duration::from_secs(90), |item| item);
+    let auto_reload_debounce = basic_conf
+        .auto_reload_debounce
+        .unwrap_or(Duration::from_secs(1));
 
     #[cfg(feature = "perf")]
     info!(target: LOG_TARGET, "Enable feature perf");
@@ -545,7 +563,11 @@ fn run() -> Result<(), Box<dyn Error>> {
         process::set_restart_process_command(cmd);
     }
 
-    try_init_upstreams(&config.upstreams, webhook::get_webhook_sender())?;
+    try_init_upstreams(
+        &config.upstreams,
+        config.basic.upstream_proxy.as_deref(),
+        webhook::get_webhook_sender(),
+    )?;
     try_init_locations(&config.locations)?;
     try_init_server_locations(&config.servers, &config.locations)?;
     let certificates = config.certificates.clone();
@@ -686,6 +708,10 @@ fn run() -> Result<(), Box<dyn Error>> {
         let dns_challenge = certificate.dns_challenge.unwrap_or_default();
         !acme.is_empty() && !domains.is_empty() && !dns_challenge
     });
+    // the tls servers of these domains may fall back before they're issued
+    let acme_pending_domains =
+        certificates::get_acme_pending_domains(&certificates);
+    let mut acme_fallback_enabled = false;
 
     if std::env::var("PINGAP_DISABLE_ACME")
         .unwrap_or_default()
@@ -693,12 +719,26 @@ fn run() -> Result<(), Box<dyn Error>> {
     {
         simple_background_service.add_task(
             "lets_encrypt",
-            new_lets_encrypt_service(
+            new_lets_encrypt_service(config_manager.clone()),
+        );
+        // the dedicated worker drains the renewal queue,
+        // so the slow acme order doesn't block the other tasks
+        let mut renewal_service = BackgroundTaskService::new_single(
+            "lets_encrypt_renewal",
+            Duration::from_secs(30),
+            "lets_encrypt_renewal",
+            new_lets_encrypt_renewal_service(
                 config_manager.clone(),
                 certificate_provider.clone(),
                 webhook::get_webhook_sender(),
             ),
         );
+        renewal_service.set_immediately(true);
+        renewal_service.set_initial_delay(Some(Duration::from_secs(3)));
+        my_server.add_service(background_service(
+            &renewal_service.name(),
+            renewal_service,
+        ));
     }
 
     let (updated_certificates, errors) = try_update_certificates(&certificates);
@@ -726,11 +766,20 @@ fn run() -> Result<(), Box<dyn Error>> {
         let listen_80_port = server_conf.addr.ends_with(":80");
         let (_, log_path) =
             parse_access_log_directive(server_conf.access_log.as_ref());
-
-        let access_logger = if let Some(log_path) = log_path {
+        let access_log_disabled = server_conf
+            .access_log_level
+            .as_deref()
+            .and_then(|level| level.parse::<AccessLogLevel>().ok())
+            == Some(AccessLogLevel::Off);
+
+        let access_logger = if let Some(log_path) =
+            log_path.filter(|_| !access_log_disabled)
+        {
             let r = new_access_logger(&log_path);
             let (tx, task) = r.recv()??;
-            application_log_paths.push(task.get_dir());
+            if let Some(dir) = task.get_dir() {
+                application_log_paths.push(dir);
+            }
             my_server.add_service(background_service("access_logger", task));
             Some(tx)
         } else {
@@ -746,8 +795,18 @@ fn run() -> Result<(), Box<dyn Error>> {
             logger: access_logger,
         };
         let mut ps = Server::new(&server_conf, ctx)?;
+        if certificates::is_acme_pending_server(
+            &server_conf.locations,
+            &config.locations,
+            &acme_pending_domains,
+        ) && ps.enable_acme_fallback()
+        {
+            acme_fallback_enabled = true;
+        }
         if enabled_http_challenge && listen_80_port {
-            ps.enable_lets_encrypt();
+            ps.enable_lets_encrypt(
+                config.basic.acme_challenge_prefix.as_deref(),
+            );
         }
         if let Some(service) = ps.get_prometheus_push_service() {
             simple_background_service.add_task("prometheus_push", service);
@@ -755,6 +814,15 @@ fn run() -> Result<(), Box<dyn Error>> {
         let services = ps.run(my_server.configuration.clone())?;
         my_server.add_service(services.lb);
     }
+    if acme_fallback_enabled {
+        simple_background_service.add_task(
+            "acme_fallback",
+            certificates::new_acme_fallback_service(
+                certificate_provider.clone(),
+                acme_pending_domains,
+            ),
+        );
+    }
 
     let basic_config = &config.basic;
     if !application_log_paths.is_empty()
@@ -790,6 +858,7 @@ fn run() -> Result<(), Box<dyn Error>> {
                     config_manager.clone(),
                     reload_handle,
                     auto_restart_check_interval,
+                    auto_reload_debounce,
                     only_hot_reload,
                 ),
             ));
@@ -807,6 +876,36 @@ fn run() -> Result<(), Box<dyn Error>> {
         }
     }
 
+    my_server.add_service(background_service(
+        "certificate_reload",
+        certificates::new_certificate_reload_service(config_manager.clone()),
+    ));
+
+    if let Some(interval) = basic_conf.certificate_watch_interval {
+        let certificate_watch_service = BackgroundTaskService::new_single(
+            "certificate_watch",
+            interval,
+            "certificate_watch",
+            certificates::new_certificate_watch_service(
+                config_manager.clone(),
+                basic_conf
+                    .certificate_watch_debounce
+                    .unwrap_or(Duration::from_secs(2)),
+            ),
+        );
+        my_server.add_service(background_service(
+            &certificate_watch_service.name(),
+            certificate_watch_service,
+        ));
+    }
+
+    if basic_config.drain_connection_close.unwrap_or(true) {
+        my_server.add_service(background_service(
+            "drain",
+            process::new_drain_service(),
+        ));
+    }
+
     my_server.add_service(background_service(
         &simple_background_service.name(),
         simple_background_service,
@@ -13,10 +13,11 @@
 // limitations under the License.
 
 use super::{get_hash_key, get_int_conf, get_str_conf, get_str_slice_conf};
-use crate::certificates::new_certificate_provider;
+use crate::certificates::{new_certificate_provider, reload_certificates};
 use crate::config_manager::get_config_manager;
 use crate::process::{get_start_time, restart_now};
 use crate::upstreams::new_upstream_provider;
+use crate::webhook::new_webhook_notification_sender;
 use async_trait::async_trait;
 use bytes::Bytes;
 use bytes::{BufMut, BytesMut};
@@ -40,6 +41,7 @@ use pingap_config::{
 use pingap_core::{
     Ctx, HttpResponse, Plugin, PluginStep, RequestPluginResult, TtlLruLimit,
 };
+use pingap_performance::MetricGroup;
 use pingap_performance::get_process_system_info;
 use pingap_performance::get_processing_accepted;
 use pingap_plugin::{Error, get_plugin_factory};
@@ -172,6 +174,96 @@ struct BasicInfo {
     git_hash: String,
 }
 
+#[derive(Serialize)]
+struct MetricsRequests {
+    accepted: u64,
+    processing: i32,
+    metrics: MetricGroup,
+}
+
+#[derive(Serialize)]
+struct MetricsUpstreams {
+    healthy_status: HashMap<String, UpstreamHealthyStatus>,
+    metrics: MetricGroup,
+}
+
+#[derive(Serialize)]
+struct MetricsConnections {
+    fd_count: usize,
+    tcp_count: usize,
+    tcp6_count: usize,
+    metrics: MetricGroup,
+}
+
+#[derive(Serialize)]
+struct CertificateExpiry {
+    domains: Vec<String>,
+    not_before: i64,
+    not_after: i64,
+    issuer: String,
+}
+
+/// The json snapshot of metrics, the prometheus metrics are only
+/// available with the tracing feature.
+#[derive(Serialize)]
+struct MetricsResp {
+    requests: MetricsRequests,
+    upstreams: MetricsUpstreams,
+    connections: MetricsConnections,
+    certificates: HashMap<String, CertificateExpiry>,
+    cache: MetricGroup,
+    process: MetricGroup,
+}
+
+fn get_metrics_resp() -> MetricsResp {
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "tracing")] {
+            let snapshot = pingap_performance::get_metrics_snapshot();
+        } else {
+            let snapshot = pingap_performance::MetricsSnapshot::default();
+        }
+    }
+    let info = get_process_system_info();
+    let (processing, accepted) = get_processing_accepted();
+    let certificates = new_certificate_provider()
+        .list()
+        .iter()
+        .filter_map(|(name, cert)| {
+            let info = cert.info.as_ref()?;
+            let key = cert.name.clone().unwrap_or_else(|| name.clone());
+            Some((
+                key,
+                CertificateExpiry {
+                    domains: info.domains.clone(),
+                    not_before: info.not_before,
+                    not_after: info.not_after,
+                    issuer: info.get_issuer_common_name(),
+                },
+            ))
+        })
+        .collect();
+    MetricsResp {
+        requests: MetricsRequests {
+            accepted,
+            processing,
+            metrics: snapshot.requests,
+        },
+        upstreams: MetricsUpstreams {
+            healthy_status: new_upstream_provider().healthy_status(),
+            metrics: snapshot.upstreams,
+        },
+        connections: MetricsConnections {
+            fd_count: info.fd_count,
+            tcp_count: info.tcp_count,
+            tcp6_count: info.tcp6_count,
+            metrics: snapshot.connections,
+        },
+        certificates,
+        cache: snapshot.cache,
+        process: snapshot.process,
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 struct TomlJson {
     pub full: String,
@@ -250,6 +342,29 @@ struct AesResp {
     value: String,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+struct DrainParams {
+    upstream: String,
+    backend: String,
+    drained: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct CertificateReloadResp {
+    /// Domains whose certificates were updated
+    updated: Vec<String>,
+    /// Semicolon-separated list of parsing errors
+    errors: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct WebhookTestResp {
+    /// Http status returned by the webhook endpoint
+    status: u16,
+    /// Body returned by the webhook endpoint
+    body: String,
+}
+
 async fn get_request_body(session: &mut Session) -> pingora::Result<BytesMut> {
     let mut buf = BytesMut::with_capacity(4096);
     while let Some(value) = session.read_request_body().await? {
@@ -696,6 +811,62 @@ async fn handle_request_admin(
         .map_err(|e| pingap_core::new_internal_error(400, e))?;
         HttpResponse::try_from_json(&AesResp { value })
             .unwrap_or(HttpResponse::unknown_error("Json serde fail"))
+    } else if path == "/upstreams/backends" {
+        let provider = new_upstream_provider();
+        if method == Method::POST {
+            let buf = get_request_body(session).await?;
+            let params: DrainParams = serde_json::from_slice(buf.as_ref())
+                .map_err(|e| pingap_core::new_internal_error(400, e))?;
+            let Some(upstream) = provider.get(&params.upstream) else {
+                return Ok(Some(HttpResponse::bad_request(format!(
+                    "upstream {} not found",
+                    params.upstream
+                ))));
+            };
+            if !upstream.set_backend_drained(&params.backend, params.drained) {
+                return Ok(Some(HttpResponse::bad_request(format!(
+                    "backend {} not found",
+                    params.backend
+                ))));
+            }
+        }
+        HttpResponse::try_from_json(&provider.backends_status())
+            .unwrap_or(HttpResponse::unknown_error("Json serde fail"))
+    } else if path == "/webhooks/test" && method == Method::POST {
+        // use the saved config, so the webhook can be tested before restart
+        let current_config = plugin.load_config(true).await?;
+        match new_webhook_notification_sender(&current_config.basic)
+            .send_test_notification()
+            .await
+        {
+            Ok(resp) => HttpResponse::try_from_json(&WebhookTestResp {
+                status: resp.status,
+                body: resp.body,
+            })
+            .unwrap_or(HttpResponse::unknown_error("Json serde fail")),
+            Err(e) => {
+                error!(target: LOG_TARGET, error = e.to_string(), "Test webhook fail");
+                HttpResponse::bad_request(e.to_string())
+            },
+        }
+    } else if path == "/certificates/reload" && method == Method::POST {
+        // only the certificates are reloaded, other config is untouched
+        match reload_certificates(&plugin.manager).await {
+            Ok((updated, errors)) => {
+                HttpResponse::try_from_json(&CertificateReloadResp {
+                    updated,
+                    errors,
+                })
+                .unwrap_or(HttpResponse::unknown_error("Json serde fail"))
+            },
+            Err(e) => {
+                error!(target: LOG_TARGET, error = e.to_string(), "Reload certificates fail");
+                HttpResponse::bad_request(e.to_string())
+            },
+        }
+    } else if path == "/metrics" {
+        HttpResponse::try_from_json(&get_metrics_resp())
+            .unwrap_or(HttpResponse::unknown_error("Json serde fail"))
     } else if path == "/certificates" {
         let mut infos = HashMap::new();
         for (name, cert) in new_certificate_provider().list().iter() {
@@ -758,7 +929,7 @@ fn init() {
 
 #[cfg(test)]
 mod tests {
-    use super::{AdminAsset, AdminServe, EmbeddedStaticFile};
+    use super::{AdminAsset, AdminServe, EmbeddedStaticFile, get_metrics_resp};
     use crate::config_manager::try_init_config_manager;
     use pingap_config::PluginConf;
     use pingap_core::HttpResponse;
@@ -831,4 +1002,31 @@ mod tests {
             EmbeddedStaticFile(None, Duration::from_secs(60)).into();
         assert_eq!(404, resp.status.as_u16())
     }
+
+    #[test]
+    fn test_get_metrics_resp() {
+        let value = serde_json::to_value(get_metrics_resp()).unwrap();
+        let mut groups = value
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        groups.sort();
+        assert_eq!(
+            vec![
+                "cache",
+                "certificates",
+                "connections",
+                "process",
+                "requests",
+                "upstreams"
+            ],
+            groups
+        );
+        assert_eq!(true, value["requests"]["accepted"].is_u64());
+        assert_eq!(true, value["requests"]["metrics"].is_object());
+        assert_eq!(true, value["upstreams"]["healthy_status"].is_object());
+        assert_eq!(true, value["connections"]["tcp_count"].is_u64());
+    }
 }
@@ -13,7 +13,7 @@
 // limitations under the License.
 
 use super::{Plugin, get_hash_key, get_step_conf, get_str_conf};
-use crate::process::get_start_time;
+use crate::process::{get_coalesced_reload_count, get_start_time};
 use async_trait::async_trait;
 use bytes::Bytes;
 use ctor::ctor;
@@ -56,6 +56,9 @@ struct ServerStats {
     start_time: u64, // Unix timestamp when the server was started
     uptime: String, // Human-readable duration since server start (e.g., "2 days 3 hours")
 
+    // Config Reload
+    coalesced_reloads: u64, // Number of config reloads coalesced by debounce
+
     // Memory Statistics
     memory_mb: usize, // Current process memory usage in megabytes
     memory: String,   // Human-readable process memory usage (e.g., "1.2 GB")
@@ -182,6 +185,7 @@ impl Plugin for Stats {
             rustc_version: pingap_util::get_rustc_version(),
             start_time: get_start_time(),
             uptime: uptime.to_string(),
+            coalesced_reloads: get_coalesced_reload_count(),
             memory_mb: info.memory_mb,
             memory: info.memory,
             arch: info.arch,
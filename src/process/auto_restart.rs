@@ -21,6 +21,7 @@ use crate::upstreams::try_update_upstreams;
 use crate::webhook::{get_webhook_sender, send_notification};
 use arc_swap::ArcSwap;
 use async_trait::async_trait;
+use pingap_acme::AcmeStorage;
 use pingap_config::{
     CATEGORY_CERTIFICATE, CATEGORY_LOCATION, CATEGORY_PLUGIN,
     CATEGORY_UPSTREAM, ConfigManager, PingapConfig,
@@ -34,13 +35,72 @@ use pingap_logger::new_env_filter;
 use pingora::server::ShutdownWatch;
 use pingora::services::background::BackgroundService;
 use std::sync::Arc;
-use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::time::Duration;
-use tokio::time::interval;
+use tokio::time::{Instant, interval, sleep_until};
 use tracing::{debug, error, info};
 
 static LOG_TARGET: &str = "main::auto_restart";
 
+static COALESCED_RELOAD_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Returns the count of reloads coalesced by debounce since server start
+pub fn get_coalesced_reload_count() -> u64 {
+    COALESCED_RELOAD_COUNT.load(Ordering::Relaxed)
+}
+
+/// The max wait of pending reload is the multiple of debounce window
+const MAX_DEBOUNCE_WINDOWS: u32 = 10;
+
+/// ReloadDebouncer coalesces the reload triggers, the reload is due only
+/// when there is no new trigger within the window, so a burst of config
+/// changes results in a single reload which applies the last change.
+/// The pending reload is due at the max wait after the first trigger,
+/// so the continuous changes can't delay the reload forever.
+struct ReloadDebouncer {
+    window: Duration,
+    max_wait: Duration,
+    deadline: Option<Instant>,
+    max_deadline: Option<Instant>,
+}
+
+impl ReloadDebouncer {
+    fn new(window: Duration, max_wait: Duration) -> Self {
+        Self {
+            window,
+            max_wait,
+            deadline: None,
+            max_deadline: None,
+        }
+    }
+    /// Triggers a reload, the pending reload is coalesced and delayed
+    /// until the max wait
+    fn trigger(&mut self) {
+        let now = Instant::now();
+        if self.deadline.is_some() {
+            COALESCED_RELOAD_COUNT.fetch_add(1, Ordering::Relaxed);
+        }
+        let max_deadline =
+            *self.max_deadline.get_or_insert(now + self.max_wait);
+        self.deadline = Some((now + self.window).min(max_deadline));
+    }
+    /// Returns true if there is a pending reload
+    fn is_pending(&self) -> bool {
+        self.deadline.is_some()
+    }
+    /// Waits until the pending reload is due
+    async fn wait(&self) {
+        if let Some(deadline) = self.deadline {
+            sleep_until(deadline).await;
+        }
+    }
+    /// Takes the due reload, it's not pending anymore
+    fn take(&mut self) {
+        self.deadline = None;
+        self.max_deadline = None;
+    }
+}
+
 /// Compares configurations and handles updates through hot reload or full restart
 ///
 /// This function:
@@ -60,7 +120,10 @@ async fn diff_and_update_config(
     hot_reload_only: bool,
 ) -> Result<PingapConfig, Box<dyn std::error::Error>> {
     let new_toml_config = config_manager.load_all().await?;
-    let new_config = new_toml_config.to_pingap_config(true)?;
+    let mut new_config = new_toml_config.to_pingap_config(true)?;
+    AcmeStorage::resolve(config_manager.clone())
+        .merge_certificates(&mut new_config.certificates)
+        .await?;
     new_config.validate()?;
     let current_config: PingapConfig =
         config_manager.get_current_config().as_ref().clone();
@@ -149,6 +212,7 @@ async fn diff_and_update_config(
         if should_reload_upstream {
             match try_update_upstreams(
                 &new_config.upstreams,
+                new_config.basic.upstream_proxy.as_deref(),
                 get_webhook_sender(),
             )
             .await
@@ -426,6 +490,8 @@ pub struct ConfigObserverService {
     interval: Duration,
     /// If true, only perform hot reloads when changes detected
     only_hot_reload: bool,
+    /// The window to coalesce the changes detected by observer
+    debounce: Duration,
     delay: AtomicU32,
 }
 
@@ -436,6 +502,7 @@ pub fn new_observer_service(
     config_manager: Arc<ConfigManager>,
     log_reload_handle: LoggerReloadHandle,
     interval: Duration,
+    debounce: Duration,
     only_hot_reload: bool,
 ) -> ConfigObserverService {
     let current_log_level = config_manager
@@ -449,6 +516,7 @@ pub fn new_observer_service(
         config_manager,
         log_reload_handle,
         interval,
+        debounce,
         only_hot_reload,
         current_log_level: ArcSwap::from_pointee(current_log_level),
         delay: AtomicU32::new(MIN_DELAY),
@@ -472,6 +540,10 @@ impl BackgroundService for ConfigObserverService {
             "background service is running",
         );
         let mut period = interval(self.interval);
+        let mut debouncer = ReloadDebouncer::new(
+            self.debounce,
+            self.debounce * MAX_DEBOUNCE_WINDOWS,
+        );
 
         let mut observer = match self.config_manager.observe().await {
             Ok(observer) => observer,
@@ -518,6 +590,11 @@ impl BackgroundService for ConfigObserverService {
                         }
                     }
                 }
+                _ = debouncer.wait(), if debouncer.is_pending() => {
+                    debouncer.take();
+                    // only hot reload for observe updated
+                    run_diff_and_update_config(self.config_manager.clone(), true).await;
+                }
                 result = observer.watch() => {
                     let delay = self.delay.load(Ordering::Relaxed);
                     match result {
@@ -528,8 +605,8 @@ impl BackgroundService for ConfigObserverService {
                             if !updated {
                                 continue;
                             }
-                            // only hot reload for observe updated
-                            run_diff_and_update_config(self.config_manager.clone(), true).await;
+                            // the reload is applied after the debounce window
+                            debouncer.trigger();
                         },
                         Err(e) => {
                             error!(
@@ -613,3 +690,69 @@ impl BackgroundTask for AutoRestart {
         Ok(true)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pretty_assertions::assert_eq;
+    use tokio::sync::mpsc;
+
+    /// Sends the reload signals with the interval, returns the count of
+    /// reloads applied by the debouncer.
+    async fn run_debouncer(
+        mut debouncer: ReloadDebouncer,
+        signals: usize,
+        interval: Duration,
+    ) -> usize {
+        let (tx, mut rx) = mpsc::channel::<()>(10);
+        tokio::spawn(async move {
+            for _ in 0..signals {
+                tx.send(()).await.unwrap();
+                tokio::time::sleep(interval).await;
+            }
+        });
+
+        let mut applied = 0;
+        let mut closed = false;
+        while !closed || debouncer.is_pending() {
+            tokio::select! {
+                _ = debouncer.wait(), if debouncer.is_pending() => {
+                    debouncer.take();
+                    applied += 1;
+                }
+                result = rx.recv(), if !closed => {
+                    match result {
+                        Some(_) => debouncer.trigger(),
+                        None => closed = true,
+                    }
+                }
+            }
+        }
+        applied
+    }
+
+    #[tokio::test]
+    async fn test_reload_debouncer() {
+        // a burst of reload signals
+        let coalesced_count = get_coalesced_reload_count();
+        let debouncer = ReloadDebouncer::new(
+            Duration::from_millis(100),
+            Duration::from_secs(1),
+        );
+        let applied =
+            run_debouncer(debouncer, 5, Duration::from_millis(10)).await;
+        assert_eq!(1, applied);
+        assert_eq!(4, get_coalesced_reload_count() - coalesced_count);
+
+        // the continuous reload signals for about 500ms, the reload
+        // isn't delayed until the signals stop
+        let debouncer = ReloadDebouncer::new(
+            Duration::from_millis(100),
+            Duration::from_millis(200),
+        );
+        let applied =
+            run_debouncer(debouncer, 50, Duration::from_millis(10)).await;
+        assert_eq!(true, applied >= 2);
+        assert_eq!(true, applied < 50);
+    }
+}
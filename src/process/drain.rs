@@ -0,0 +1,64 @@
+// Copyright 2024-2025 Tree xie.
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+// http://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use async_trait::async_trait;
+use pingora::server::ShutdownWatch;
+use pingora::services::background::BackgroundService;
+
+static LOG_TARGET: &str = "main::process";
+
+/// DrainService marks the server as draining once the graceful shutdown
+/// (SIGTERM) or graceful upgrade(SIGQUIT) signal is received, the requests
+/// on the existing keep-alive connections are still handled, but the
+/// connections are closed after the response, so the draining converges
+/// before the grace period ends.
+pub struct DrainService {}
+
+pub fn new_drain_service() -> DrainService {
+    DrainService {}
+}
+
+#[async_trait]
+impl BackgroundService for DrainService {
+    #[cfg(unix)]
+    async fn start(&self, mut shutdown: ShutdownWatch) {
+        use tokio::signal::unix::{SignalKind, signal};
+        use tracing::{error, info};
+        let (mut terminate, mut quit) =
+            match (signal(SignalKind::terminate()), signal(SignalKind::quit()))
+            {
+                (Ok(terminate), Ok(quit)) => (terminate, quit),
+                (Err(e), _) | (_, Err(e)) => {
+                    error!(
+                        target: LOG_TARGET,
+                        error = %e,
+                        "listen drain signal fail"
+                    );
+                    return;
+                },
+            };
+        let signal = tokio::select! {
+            _ = shutdown.changed() => "shutdown",
+            _ = terminate.recv() => "terminate",
+            _ = quit.recv() => "quit",
+        };
+        pingap_core::set_draining(true);
+        info!(
+            target: LOG_TARGET,
+            signal, "server is draining, keep-alive connections are closed"
+        );
+    }
+    #[cfg(not(unix))]
+    async fn start(&self, _shutdown: ShutdownWatch) {}
+}
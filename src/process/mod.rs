@@ -1,5 +1,7 @@
 mod auto_restart;
 mod common;
+mod drain;
 
 pub use auto_restart::*;
 pub use common::*;
+pub use drain::*;
@@ -62,19 +62,38 @@ pub fn new_upstream_provider() -> Arc<dyn UpstreamProvider> {
     UPSTREAM_PROVIDER.clone()
 }
 
+/// Applies the default proxy to the upstreams without proxy
+fn with_default_proxy(
+    upstream_configs: &HashMap<String, UpstreamConf>,
+    default_proxy: Option<&str>,
+) -> HashMap<String, UpstreamConf> {
+    let mut upstream_configs = upstream_configs.clone();
+    let Some(default_proxy) = default_proxy.filter(|v| !v.is_empty()) else {
+        return upstream_configs;
+    };
+    for conf in upstream_configs.values_mut() {
+        if conf.proxy.as_ref().is_none_or(|v| v.is_empty()) {
+            conf.proxy = Some(default_proxy.to_string());
+        }
+    }
+    upstream_configs
+}
+
 /// Initialize the upstreams
 ///
 /// # Arguments
 /// * `upstream_configs` - The upstream configurations
+/// * `default_proxy` - The proxy of upstreams without proxy
 /// * `sender` - The notification sender
 ///
 /// # Returns
 pub fn try_init_upstreams(
     upstream_configs: &HashMap<String, UpstreamConf>,
+    default_proxy: Option<&str>,
     sender: Option<Arc<NotificationSender>>,
 ) -> Result<()> {
     let (upstreams, _) = new_ahash_upstreams(
-        upstream_configs,
+        &with_default_proxy(upstream_configs, default_proxy),
         UPSTREAM_PROVIDER.clone(),
         sender,
     )
@@ -88,10 +107,11 @@ pub fn try_init_upstreams(
 
 pub async fn try_update_upstreams(
     upstream_configs: &HashMap<String, UpstreamConf>,
+    default_proxy: Option<&str>,
     sender: Option<Arc<NotificationSender>>,
 ) -> Result<Vec<String>> {
     let (upstreams, updated_upstreams) = new_ahash_upstreams(
-        upstream_configs,
+        &with_default_proxy(upstream_configs, default_proxy),
         UPSTREAM_PROVIDER.clone(),
         sender,
     )
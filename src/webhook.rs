@@ -12,6 +12,7 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use pingap_config::BasicConf;
 use pingap_core::{NotificationData, NotificationSender};
 use pingap_webhook::WebhookNotificationSender;
 use std::sync::Arc;
@@ -20,14 +21,22 @@ use std::sync::OnceLock;
 static WEBHOOK_NOTIFICATION_SENDER: OnceLock<Arc<NotificationSender>> =
     OnceLock::new();
 
-pub fn init_webhook_notification_sender(
-    url: String,
-    category: String,
-    notifications: Vec<String>,
-) {
-    let _ = WEBHOOK_NOTIFICATION_SENDER.set(Arc::new(Box::new(
-        WebhookNotificationSender::new(url, category, notifications),
-    )));
+/// Creates the webhook notification sender of the basic config
+pub fn new_webhook_notification_sender(
+    conf: &BasicConf,
+) -> WebhookNotificationSender {
+    WebhookNotificationSender::new(
+        conf.webhook.clone().unwrap_or_default(),
+        conf.webhook_type.clone().unwrap_or_default(),
+        conf.webhook_notifications.clone().unwrap_or_default(),
+    )
+    .with_template(conf.webhook_template.clone())
+    .with_headers(conf.webhook_headers.clone().unwrap_or_default())
+}
+
+pub fn init_webhook_notification_sender(conf: &BasicConf) {
+    let _ = WEBHOOK_NOTIFICATION_SENDER
+        .set(Arc::new(Box::new(new_webhook_notification_sender(conf))));
 }
 
 pub fn get_webhook_sender() -> Option<Arc<NotificationSender>> {
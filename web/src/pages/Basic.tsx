@@ -72,6 +72,14 @@ export default function Basic() {
       span: 3,
       category: ExFormItemCategory.NUMBER,
     },
+    {
+      name: "upstream_proxy",
+      label: basicI18n("upstreamProxy"),
+      placeholder: basicI18n("upstreamProxyPlaceholder"),
+      defaultValue: basic.upstream_proxy,
+      span: 3,
+      category: ExFormItemCategory.TEXT,
+    },
     {
       name: "log_level",
       label: basicI18n("logLevel"),
@@ -152,6 +160,15 @@ export default function Basic() {
       span: 3,
       category: ExFormItemCategory.TEXT,
     },
+    {
+      name: "drain_connection_close",
+      label: basicI18n("drainConnectionClose"),
+      placeholder: "",
+      defaultValue: basic.drain_connection_close,
+      span: 3,
+      category: ExFormItemCategory.RADIOS,
+      options: newBooleanOptions(),
+    },
     {
       name: "auto_restart_check_interval",
       label: basicI18n("autoRestartCheckInterval"),
@@ -160,6 +177,30 @@ export default function Basic() {
       span: 3,
       category: ExFormItemCategory.TEXT,
     },
+    {
+      name: "auto_reload_debounce",
+      label: basicI18n("autoReloadDebounce"),
+      placeholder: basicI18n("autoReloadDebouncePlaceholder"),
+      defaultValue: basic.auto_reload_debounce,
+      span: 3,
+      category: ExFormItemCategory.TEXT,
+    },
+    {
+      name: "certificate_watch_interval",
+      label: basicI18n("certificateWatchInterval"),
+      placeholder: basicI18n("certificateWatchIntervalPlaceholder"),
+      defaultValue: basic.certificate_watch_interval,
+      span: 3,
+      category: ExFormItemCategory.TEXT,
+    },
+    {
+      name: "certificate_watch_debounce",
+      label: basicI18n("certificateWatchDebounce"),
+      placeholder: basicI18n("certificateWatchDebouncePlaceholder"),
+      defaultValue: basic.certificate_watch_debounce,
+      span: 3,
+      category: ExFormItemCategory.TEXT,
+    },
     {
       name: "pid_file",
       label: basicI18n("pidFile"),
@@ -221,6 +262,7 @@ export default function Basic() {
           "parse_certificate_fail",
           "service_discover_fail",
           "upstream_status",
+          "tls_verify_fail",
         ].sort(),
         true,
       ),
@@ -273,6 +315,9 @@ export default function Basic() {
     grace_period: newZodDuration().optional(),
     graceful_shutdown_timeout: newZodDuration().optional(),
     auto_restart_check_interval: newZodDuration().optional(),
+    auto_reload_debounce: newZodDuration().optional(),
+    certificate_watch_interval: newZodDuration().optional(),
+    certificate_watch_debounce: newZodDuration().optional(),
     cache_max_size: newZodBytes().optional(),
   });
   return (
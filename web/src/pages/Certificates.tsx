@@ -93,6 +93,26 @@ export default function Certificates() {
       rows: countLines(certificateConfig.tls_key || ""),
       nullAsEmpty: true,
     },
+    {
+      name: "tls_alt_cert",
+      label: certificateI18n("tlsAltCert"),
+      placeholder: certificateI18n("tlsAltCertPlaceholder"),
+      defaultValue: certificateConfig.tls_alt_cert,
+      span: 6,
+      category: ExFormItemCategory.TEXTAREA,
+      rows: countLines(certificateConfig.tls_alt_cert || ""),
+      nullAsEmpty: true,
+    },
+    {
+      name: "tls_alt_key",
+      label: certificateI18n("tlsAltKey"),
+      placeholder: certificateI18n("tlsAltKeyPlaceholder"),
+      defaultValue: certificateConfig.tls_alt_key,
+      span: 6,
+      category: ExFormItemCategory.TEXTAREA,
+      rows: countLines(certificateConfig.tls_alt_key || ""),
+      nullAsEmpty: true,
+    },
     {
       name: "domains",
       label: certificateI18n("domains"),
@@ -158,6 +158,15 @@ export default function Locations() {
       span: 3,
       category: ExFormItemCategory.TEXT,
     },
+    {
+      name: "retry_on_upstream_reset",
+      label: locationI18n("retryOnUpstreamReset"),
+      placeholder: "",
+      defaultValue: locationConfig.retry_on_upstream_reset,
+      span: 3,
+      category: ExFormItemCategory.RADIOS,
+      options: newBooleanOptions(),
+    },
     {
       name: "enable_reverse_proxy_headers",
       label: locationI18n("enableReverseProxyHeaders"),
@@ -167,6 +176,15 @@ export default function Locations() {
       category: ExFormItemCategory.RADIOS,
       options: newBooleanOptions(),
     },
+    {
+      name: "upstream_keepalive",
+      label: locationI18n("upstreamKeepalive"),
+      placeholder: "",
+      defaultValue: locationConfig.upstream_keepalive,
+      span: 3,
+      category: ExFormItemCategory.RADIOS,
+      options: newBooleanOptions(),
+    },
     {
       name: "includes",
       label: i18n("includes"),
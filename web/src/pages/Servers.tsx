@@ -113,6 +113,41 @@ export default function Servers() {
       category: ExFormItemCategory.RADIOS,
       options: newBooleanOptions(),
     },
+    {
+      name: "acme_fallback",
+      label: serverI18n("acmeFallback"),
+      placeholder: "",
+      defaultValue: serverConfig.acme_fallback,
+      span: 3,
+      category: ExFormItemCategory.RADIOS,
+      options: newStringOptions(["http", "redirect"], true, true),
+    },
+    {
+      name: "request_framing",
+      label: serverI18n("requestFraming"),
+      placeholder: "",
+      defaultValue: serverConfig.request_framing,
+      span: 3,
+      category: ExFormItemCategory.RADIOS,
+      options: newStringOptions(["strict", "lenient"], true, true),
+    },
+    {
+      name: "proxy_protocol",
+      label: serverI18n("proxyProtocol"),
+      placeholder: "",
+      defaultValue: serverConfig.proxy_protocol,
+      span: 3,
+      category: ExFormItemCategory.RADIOS,
+      options: newStringOptions(["off", "optional", "required"], true, true),
+    },
+    {
+      name: "proxy_protocol_trusted",
+      label: serverI18n("proxyProtocolTrusted"),
+      placeholder: serverI18n("proxyProtocolTrustedPlaceholder"),
+      defaultValue: serverConfig.proxy_protocol_trusted || [],
+      span: 6,
+      category: ExFormItemCategory.TEXTS,
+    },
     {
       name: "access_log",
       label: serverI18n("accessLog"),
@@ -121,6 +156,31 @@ export default function Servers() {
       span: 6,
       category: ExFormItemCategory.TEXT,
     },
+    {
+      name: "access_log_level",
+      label: serverI18n("accessLogLevel"),
+      placeholder: "",
+      defaultValue: serverConfig.access_log_level,
+      span: 3,
+      category: ExFormItemCategory.RADIOS,
+      options: [
+        {
+          label: "all",
+          option: "all",
+          value: "all",
+        },
+        {
+          label: "error",
+          option: "error",
+          value: "error",
+        },
+        {
+          label: "off",
+          option: "off",
+          value: "off",
+        },
+      ],
+    },
     {
       name: "enabled_h2",
       label: serverI18n("enabledH2"),
@@ -155,6 +215,46 @@ export default function Servers() {
       span: 3,
       category: ExFormItemCategory.TEXT,
     },
+    {
+      name: "downstream_idle_timeout",
+      label: serverI18n("downstreamIdleTimeout"),
+      placeholder: serverI18n("downstreamIdleTimeoutPlaceholder"),
+      defaultValue: serverConfig.downstream_idle_timeout,
+      span: 3,
+      category: ExFormItemCategory.TEXT,
+    },
+    {
+      name: "fair_queue_capacity",
+      label: serverI18n("fairQueueCapacity"),
+      placeholder: serverI18n("fairQueueCapacityPlaceholder"),
+      defaultValue: serverConfig.fair_queue_capacity,
+      span: 3,
+      category: ExFormItemCategory.NUMBER,
+    },
+    {
+      name: "fair_queue_key",
+      label: serverI18n("fairQueueKey"),
+      placeholder: serverI18n("fairQueueKeyPlaceholder"),
+      defaultValue: serverConfig.fair_queue_key,
+      span: 3,
+      category: ExFormItemCategory.TEXT,
+    },
+    {
+      name: "fair_queue_depth",
+      label: serverI18n("fairQueueDepth"),
+      placeholder: serverI18n("fairQueueDepthPlaceholder"),
+      defaultValue: serverConfig.fair_queue_depth,
+      span: 3,
+      category: ExFormItemCategory.NUMBER,
+    },
+    {
+      name: "fair_queue_timeout",
+      label: serverI18n("fairQueueTimeout"),
+      placeholder: serverI18n("fairQueueTimeoutPlaceholder"),
+      defaultValue: serverConfig.fair_queue_timeout,
+      span: 3,
+      category: ExFormItemCategory.TEXT,
+    },
     {
       name: "reuse_port",
       label: serverI18n("reusePort"),
@@ -207,6 +307,39 @@ export default function Servers() {
       category: ExFormItemCategory.RADIOS,
       options: newStringOptions(["tlsv1.1", "tlsv1.2", "tlsv1.3"], false),
     },
+    {
+      name: "tls_session_tickets",
+      label: serverI18n("tlsSessionTickets"),
+      placeholder: "",
+      defaultValue: serverConfig.tls_session_tickets,
+      span: 3,
+      category: ExFormItemCategory.RADIOS,
+      options: newBooleanOptions(),
+    },
+    {
+      name: "tls_session_cache_size",
+      label: serverI18n("tlsSessionCacheSize"),
+      placeholder: serverI18n("tlsSessionCacheSizePlaceholder"),
+      defaultValue: serverConfig.tls_session_cache_size,
+      span: 3,
+      category: ExFormItemCategory.NUMBER,
+    },
+    {
+      name: "tls_ticket_key_rotation",
+      label: serverI18n("tlsTicketKeyRotation"),
+      placeholder: serverI18n("tlsTicketKeyRotationPlaceholder"),
+      defaultValue: serverConfig.tls_ticket_key_rotation,
+      span: 3,
+      category: ExFormItemCategory.TEXT,
+    },
+    {
+      name: "tls_ticket_key_overlap",
+      label: serverI18n("tlsTicketKeyOverlap"),
+      placeholder: serverI18n("tlsTicketKeyOverlapPlaceholder"),
+      defaultValue: serverConfig.tls_ticket_key_overlap,
+      span: 3,
+      category: ExFormItemCategory.TEXT,
+    },
     {
       name: "tls_max_version",
       label: serverI18n("tlsMaxVersion"),
@@ -220,6 +220,14 @@ export default function Upstreams() {
         },
       ],
     },
+    {
+      name: "h2_max_streams",
+      label: upstreamI18n("h2MaxStreams"),
+      placeholder: upstreamI18n("h2MaxStreamsPlaceholder"),
+      defaultValue: upstreamConfig.h2_max_streams,
+      span: 2,
+      category: ExFormItemCategory.NUMBER,
+    },
     {
       name: "sni",
       label: upstreamI18n("sni"),
@@ -228,6 +236,14 @@ export default function Upstreams() {
       span: 2,
       category: ExFormItemCategory.TEXT,
     },
+    {
+      name: "proxy",
+      label: upstreamI18n("proxy"),
+      placeholder: upstreamI18n("proxyPlaceholder"),
+      defaultValue: upstreamConfig.proxy,
+      span: 2,
+      category: ExFormItemCategory.TEXT,
+    },
     {
       name: "verify_cert",
       label: upstreamI18n("verifyCert"),
@@ -237,6 +253,23 @@ export default function Upstreams() {
       category: ExFormItemCategory.RADIOS,
       options: newBooleanOptions(),
     },
+    {
+      name: "tls_verify_failure",
+      label: upstreamI18n("tlsVerifyFailure"),
+      placeholder: "",
+      defaultValue: upstreamConfig.tls_verify_failure,
+      span: 2,
+      category: ExFormItemCategory.RADIOS,
+      options: newStringOptions(["fail", "insecure", "fallback"], true, true),
+    },
+    {
+      name: "tls_verify_fallback_upstream",
+      label: upstreamI18n("tlsVerifyFallbackUpstream"),
+      placeholder: upstreamI18n("tlsVerifyFallbackUpstreamPlaceholder"),
+      defaultValue: upstreamConfig.tls_verify_fallback_upstream,
+      span: 2,
+      category: ExFormItemCategory.TEXT,
+    },
     {
       name: "ipv4_only",
       label: upstreamI18n("ipv4Only"),
@@ -320,6 +353,105 @@ export default function Upstreams() {
       span: 2,
       category: ExFormItemCategory.TEXT,
     },
+    {
+      name: "retry_budget_percent",
+      label: upstreamI18n("retryBudgetPercent"),
+      placeholder: upstreamI18n("retryBudgetPercentPlaceholder"),
+      defaultValue: upstreamConfig.retry_budget_percent,
+      span: 2,
+      category: ExFormItemCategory.NUMBER,
+    },
+    {
+      name: "retry_budget_min_retries",
+      label: upstreamI18n("retryBudgetMinRetries"),
+      placeholder: upstreamI18n("retryBudgetMinRetriesPlaceholder"),
+      defaultValue: upstreamConfig.retry_budget_min_retries,
+      span: 2,
+      category: ExFormItemCategory.NUMBER,
+    },
+    {
+      name: "retry_budget_window",
+      label: upstreamI18n("retryBudgetWindow"),
+      placeholder: upstreamI18n("retryBudgetWindowPlaceholder"),
+      defaultValue: upstreamConfig.retry_budget_window,
+      span: 2,
+      category: ExFormItemCategory.TEXT,
+    },
+    {
+      name: "failure_avoidance_window",
+      label: upstreamI18n("failureAvoidanceWindow"),
+      placeholder: upstreamI18n("failureAvoidanceWindowPlaceholder"),
+      defaultValue: upstreamConfig.failure_avoidance_window,
+      span: 2,
+      category: ExFormItemCategory.TEXT,
+    },
+    {
+      name: "zone",
+      label: upstreamI18n("zone"),
+      placeholder: upstreamI18n("zonePlaceholder"),
+      defaultValue: upstreamConfig.zone,
+      span: 2,
+      category: ExFormItemCategory.TEXT,
+    },
+    {
+      name: "zone_max_processing",
+      label: upstreamI18n("zoneMaxProcessing"),
+      placeholder: upstreamI18n("zoneMaxProcessingPlaceholder"),
+      defaultValue: upstreamConfig.zone_max_processing,
+      span: 2,
+      category: ExFormItemCategory.NUMBER,
+    },
+    {
+      name: "backend_zones",
+      label: upstreamI18n("backendZones"),
+      placeholder: upstreamI18n("backendZonesPlaceholder"),
+      defaultValue: upstreamConfig.backend_zones,
+      span: 6,
+      category: ExFormItemCategory.KV_LIST,
+      separator: " ",
+      cols: [3, 1],
+    },
+    {
+      name: "proxy_set_headers",
+      label: upstreamI18n("proxySetHeaders"),
+      placeholder: upstreamI18n("proxySetHeadersPlaceholder"),
+      defaultValue: upstreamConfig.proxy_set_headers,
+      span: 3,
+      category: ExFormItemCategory.KV_LIST,
+    },
+    {
+      name: "proxy_add_headers",
+      label: upstreamI18n("proxyAddHeaders"),
+      placeholder: upstreamI18n("proxyAddHeadersPlaceholder"),
+      defaultValue: upstreamConfig.proxy_add_headers,
+      span: 3,
+      category: ExFormItemCategory.KV_LIST,
+    },
+    {
+      name: "response_set_headers",
+      label: upstreamI18n("responseSetHeaders"),
+      placeholder: upstreamI18n("responseSetHeadersPlaceholder"),
+      defaultValue: upstreamConfig.response_set_headers,
+      span: 3,
+      category: ExFormItemCategory.KV_LIST,
+    },
+    {
+      name: "response_add_headers",
+      label: upstreamI18n("responseAddHeaders"),
+      placeholder: upstreamI18n("responseAddHeadersPlaceholder"),
+      defaultValue: upstreamConfig.response_add_headers,
+      span: 3,
+      category: ExFormItemCategory.KV_LIST,
+    },
+    {
+      name: "keepalive",
+      label: upstreamI18n("keepalive"),
+      placeholder: "",
+      defaultValue: upstreamConfig.keepalive,
+      span: 2,
+      category: ExFormItemCategory.RADIOS,
+      options: newBooleanOptions(),
+    },
     {
       name: "tcp_fast_open",
       label: upstreamI18n("tcpFastOpen"),
@@ -17,6 +17,7 @@ export interface Upstream {
   algo?: string;
   sni?: string;
   alpn?: string;
+  h2_max_streams?: number;
   health_check?: string;
   ipv4_only?: boolean;
   enable_tracer?: boolean;
@@ -27,19 +28,34 @@ export interface Upstream {
   circuit_break_min_requests_threshold?: number;
   circuit_break_half_open_consecutive_success_threshold?: number;
   circuit_break_open_duration?: string;
+  retry_budget_percent?: number;
+  retry_budget_min_retries?: number;
+  retry_budget_window?: string;
+  failure_avoidance_window?: string;
+  zone?: string;
+  backend_zones?: string[];
+  zone_max_processing?: number;
+  proxy_set_headers?: string[];
+  proxy_add_headers?: string[];
+  response_set_headers?: string[];
+  response_add_headers?: string[];
   backend_stats_interval?: string;
   connection_timeout?: string;
   total_connection_timeout?: string;
   read_timeout?: string;
   idle_timeout?: string;
+  keepalive?: boolean;
   write_timeout?: string;
   verify_cert?: boolean;
+  tls_verify_failure?: string;
+  tls_verify_fallback_upstream?: string;
   tcp_idle?: string;
   tcp_interval?: string;
   tcp_user_timeout?: string;
   tcp_probe_count?: number;
   tcp_recv_buf?: number;
   tcp_fast_open?: boolean;
+  proxy?: string;
   includes?: string[];
   remark?: string;
 }
@@ -54,6 +70,8 @@ export interface Location {
   max_retries?: number;
   max_retry_window?: string;
   enable_reverse_proxy_headers?: boolean;
+  upstream_keepalive?: boolean;
+  retry_on_upstream_reset?: boolean;
   rewrite?: string;
   client_max_body_size?: string;
   max_processing?: number;
@@ -98,6 +116,7 @@ export function getLocationWeight(location: Location) {
 export interface Server {
   addr: string;
   access_log?: string;
+  access_log_level?: string;
   locations?: string[];
   threads?: number;
   tls_cert?: string;
@@ -107,13 +126,26 @@ export interface Server {
   enabled_h2?: boolean;
   enable_server_timing?: boolean;
   global_certificates?: boolean;
+  acme_fallback?: string;
+  request_framing?: string;
+  proxy_protocol?: string;
+  proxy_protocol_trusted?: string[];
   downstream_read_timeout?: string;
   downstream_write_timeout?: string;
+  downstream_idle_timeout?: string;
+  fair_queue_capacity?: number;
+  fair_queue_key?: string;
+  fair_queue_depth?: number;
+  fair_queue_timeout?: string;
   reuse_port?: boolean;
   tls_cipher_list?: string;
   tls_ciphersuites?: string;
   tls_min_version?: string;
   tls_max_version?: string;
+  tls_session_tickets?: boolean;
+  tls_session_cache_size?: number;
+  tls_ticket_key_rotation?: string;
+  tls_ticket_key_overlap?: string;
   tcp_idle?: string;
   tcp_user_timeout?: string;
   tcp_interval?: string;
@@ -130,6 +162,8 @@ export interface Certificate {
   domains?: string;
   tls_cert?: string;
   tls_key?: string;
+  tls_alt_cert?: string;
+  tls_alt_key?: string;
   certificate_file?: string;
   acme?: string;
   is_default?: boolean;
@@ -160,6 +194,7 @@ interface Basic {
   listener_tasks_per_fd?: number;
   grace_period?: string;
   graceful_shutdown_timeout?: string;
+  drain_connection_close?: boolean;
   upstream_keepalive_pool_size?: number;
   log_buffered_size?: string;
   log_format_json?: boolean;
@@ -167,8 +202,12 @@ interface Basic {
   log_compress_level?: number;
   log_compress_days_ago?: number;
   log_compress_time_point_hour?: number;
+  upstream_proxy?: string;
   log_level?: string;
   auto_restart_check_interval?: string;
+  auto_reload_debounce?: string;
+  certificate_watch_interval?: string;
+  certificate_watch_debounce?: string;
   cache_max_size?: number;
   cache_directory?: string;
   sentry?: string;